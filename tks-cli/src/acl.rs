@@ -0,0 +1,157 @@
+//! Manage the access control lists telling which applications, by process executable, may read,
+//! write or delete the items of a collection, or of a single item. Collections without an ACL let
+//! all the applications in; the service asks the user the first time an application needs an
+//! access its ACL doesn't grant. Granting accesses, or lifting an ACL, asks the user too.
+
+use crate::dbus_client::{connect, resolve_collection, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, ValueEnum};
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::{Connection, Proxy};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Access {
+    /// Reading the secrets
+    Read,
+    /// Creating items, changing their secret, label or attributes
+    Write,
+    /// Deleting items
+    Delete,
+}
+
+impl Access {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct AclTarget {
+    /// Collection: an alias, a label or an object path
+    pub collection: String,
+    #[clap(long)]
+    /// Object path of an item of the collection, to use its own ACL instead
+    pub item: Option<String>,
+}
+
+impl AclTarget {
+    fn proxy<'a>(&self, conn: &'a Connection) -> Result<Proxy<'a, &'a Connection>> {
+        let path = match &self.item {
+            Some(item) => dbus::Path::new(item.clone()).map_err(|e| anyhow::anyhow!(e))?,
+            None => resolve_collection(conn, &self.collection)?,
+        };
+        Ok(conn.with_proxy(SERVICE_NAME, path, TIMEOUT))
+    }
+
+    fn name(&self) -> &str {
+        self.item.as_deref().unwrap_or(&self.collection)
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct AclShowCmd {
+    #[command(flatten)]
+    pub target: AclTarget,
+}
+
+#[derive(Parser, Debug)]
+pub struct AclRestrictCmd {
+    #[command(flatten)]
+    pub target: AclTarget,
+}
+
+#[derive(Parser, Debug)]
+pub struct AclGrantCmd {
+    #[command(flatten)]
+    pub target: AclTarget,
+    /// Process executable of the application, e.g. /usr/bin/seahorse; `flatpak:<app ID>` or
+    /// `snap:<snap name>` for the sandboxed ones
+    pub exe: String,
+    #[clap(long, value_enum, value_delimiter = ',')]
+    /// Accesses to grant or revoke, all of them by default
+    pub access: Vec<Access>,
+}
+
+fn accesses(access: &[Access]) -> Vec<&'static str> {
+    access.iter().map(Access::as_str).collect()
+}
+
+impl AclShowCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let proxy = self.target.proxy(&conn)?;
+        let restricted: bool = proxy
+            .get("io.linux_tks.Acl1", "Restricted")
+            .with_context(|| format!("Cannot get the ACL of '{}'", self.target.name()))?;
+        if !restricted {
+            println!("All the applications may use '{}'", self.target.name());
+            return Ok(());
+        }
+        if proxy.get("io.linux_tks.Acl1", "Inherited")? {
+            println!("{}", "The item uses the ACL of its collection".dimmed());
+        }
+        let (entries,): (Vec<(String, Vec<String>)>,) =
+            proxy.method_call("io.linux_tks.Acl1", "GetAcl", ())?;
+        if entries.is_empty() {
+            println!("No application granted yet, they'll be asked for");
+        }
+        for (exe, granted) in entries {
+            println!("{} {}", exe.bold(), granted.join(","));
+        }
+        Ok(())
+    }
+}
+
+impl AclRestrictCmd {
+    pub(crate) fn run(&self, restricted: bool) -> Result<()> {
+        let conn = connect()?;
+        self.target
+            .proxy(&conn)?
+            .method_call::<(), _, _, _>("io.linux_tks.Acl1", "SetRestricted", (restricted,))
+            .with_context(|| format!("Cannot change the ACL of '{}'", self.target.name()))?;
+        match restricted {
+            true => println!(
+                "Only the granted applications may use '{}'",
+                self.target.name()
+            ),
+            false => println!("All the applications may use '{}'", self.target.name()),
+        }
+        Ok(())
+    }
+}
+
+impl AclGrantCmd {
+    pub(crate) fn run(&self, grant: bool) -> Result<()> {
+        let conn = connect()?;
+        let proxy = self.target.proxy(&conn)?;
+        let access = accesses(&self.access);
+        if grant {
+            proxy
+                .method_call::<(), _, _, _>("io.linux_tks.Acl1", "Grant", (&self.exe, access))
+                .with_context(|| format!("Cannot grant {} access", self.exe))?;
+            println!("Granted {} access to '{}'", self.exe, self.target.name());
+            return Ok(());
+        }
+        let (revoked,): (bool,) = proxy
+            .method_call("io.linux_tks.Acl1", "Revoke", (&self.exe, access))
+            .with_context(|| format!("Cannot revoke the access of {}", self.exe))?;
+        match revoked {
+            true => println!(
+                "Revoked the access of {} to '{}'",
+                self.exe,
+                self.target.name()
+            ),
+            false => println!(
+                "{} had no such access to '{}'",
+                self.exe,
+                self.target.name()
+            ),
+        }
+        Ok(())
+    }
+}
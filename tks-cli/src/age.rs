@@ -0,0 +1,12 @@
+//! How long ago something happened, the way the commands listing changes show it.
+
+/// How long ago, e.g. 5m or 3d, the given seconds since the Unix epoch were
+pub(crate) fn age(time: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(time);
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
@@ -0,0 +1,139 @@
+//! Find the items duplicated by repeated imports, and help deleting them. The service compares the
+//! items, so the secrets never reach this process; the chosen items get deleted in a single batch.
+
+use crate::age::age;
+use crate::dbus_client::{connect, resolve_collection, service_proxy, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use console::Term;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Parser, Debug)]
+pub struct AuditDuplicatesCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection to audit: an alias, a label or an object path
+    pub collection: String,
+    #[clap(long)]
+    /// Only report items having the same secret too; the collection should be unlocked
+    pub secrets: bool,
+    #[clap(long)]
+    /// Keep the most recently modified item of each group without asking
+    pub yes: bool,
+    #[clap(long)]
+    /// Only list the duplicates, without deleting anything
+    pub dry_run: bool,
+}
+
+struct ItemInfo {
+    path: dbus::Path<'static>,
+    label: String,
+    folder: Option<String>,
+    modified: u64,
+}
+
+impl ItemInfo {
+    fn read(conn: &Connection, path: dbus::Path<'static>) -> Result<ItemInfo> {
+        let proxy = conn.with_proxy(SERVICE_NAME, &path, TIMEOUT);
+        let label: String = proxy.get("org.freedesktop.Secret.Item", "Label")?;
+        let modified: u64 = proxy.get("org.freedesktop.Secret.Item", "Modified")?;
+        let attributes: HashMap<String, String> =
+            proxy.get("org.freedesktop.Secret.Item", "Attributes")?;
+        Ok(ItemInfo {
+            path,
+            label,
+            folder: attributes.get("tks:path").cloned(),
+            modified,
+        })
+    }
+}
+
+impl AuditDuplicatesCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.collection)?;
+        let service = service_proxy(&conn);
+        let (groups,): (Vec<Vec<dbus::Path<'static>>>,) = service
+            .method_call(
+                "io.linux_tks.Service1",
+                "FindDuplicates",
+                (collection, self.secrets),
+            )
+            .with_context(|| "Cannot look for duplicates")?;
+        if groups.is_empty() {
+            println!("No duplicates found in '{}'", self.collection);
+            return Ok(());
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let term = Term::stdout();
+        let mut to_delete = Vec::new();
+        for (n, group) in groups.into_iter().enumerate() {
+            let items = group
+                .into_iter()
+                .map(|p| ItemInfo::read(&conn, p))
+                .collect::<Result<Vec<_>>>()?;
+            println!("{} {}", "Duplicates".bold(), n + 1);
+            for (i, item) in items.iter().enumerate() {
+                let folder = item.folder.as_deref().map(|f| format!(" [{}]", f));
+                println!(
+                    "  {}) {}{}, modified {} ago",
+                    i + 1,
+                    item.label.bold(),
+                    folder.unwrap_or_default(),
+                    age(item.modified, now)
+                );
+            }
+            if self.dry_run {
+                continue;
+            }
+            let keep = match self.yes {
+                true => Some(0),
+                false => loop {
+                    term.write_str(&format!(
+                        "Keep which item? [1-{}, a = keep all, q = quit] (1): ",
+                        items.len()
+                    ))?;
+                    match term.read_line()?.trim() {
+                        "" => break Some(0),
+                        "a" => break None,
+                        "q" => return Ok(()),
+                        answer => match answer.parse::<usize>() {
+                            Ok(k) if k >= 1 && k <= items.len() => break Some(k - 1),
+                            _ => println!("Please answer with a number, a or q"),
+                        },
+                    }
+                },
+            };
+            if let Some(keep) = keep {
+                to_delete.extend(
+                    items
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(i, _)| *i != keep)
+                        .map(|(_, item)| item.path),
+                );
+            }
+        }
+        if to_delete.is_empty() {
+            return Ok(());
+        }
+
+        if !self.yes {
+            term.write_str(&format!("Delete {} item(s)? [y/N]: ", to_delete.len()))?;
+            if !term.read_line()?.trim().eq_ignore_ascii_case("y") {
+                println!("Nothing deleted");
+                return Ok(());
+            }
+        }
+        let count = to_delete.len();
+        service
+            .method_call::<(), _, _, _>("io.linux_tks.Service1", "DeleteItems", (to_delete,))
+            .with_context(|| "Cannot delete the duplicates")?;
+        println!("{} duplicate(s) deleted", count.to_string().bold());
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Export the audit log of the service as JSON lines, e.g. to ship it into a SIEM. The records get
+//! written as the service recorded them, one per line, oldest first.
+
+use crate::dbus_client::{connect, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::debug;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Parser, Debug)]
+pub struct AuditExportCmd {
+    #[clap(long, default_value = "24h")]
+    /// Oldest records to export: an age such as 30m, 24h or 7d, or seconds since the Unix epoch
+    pub since: String,
+    #[clap(long, short)]
+    /// File to append the records to, instead of the standard output
+    pub output: Option<PathBuf>,
+}
+
+/// Seconds since the Unix epoch of the `--since` argument
+fn parse_since(since: &str, now: u64) -> Result<u64> {
+    let unit = match since.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => {
+            return since
+                .parse::<u64>()
+                .with_context(|| format!("Invalid --since '{}', use e.g. 24h or 7d", since))
+        }
+    };
+    let count = since[..since.len() - 1]
+        .parse::<u64>()
+        .with_context(|| format!("Invalid --since '{}', use e.g. 24h or 7d", since))?;
+    Ok(now.saturating_sub(count * unit))
+}
+
+impl AuditExportCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let since = parse_since(&self.since, now)?;
+
+        let conn = connect()?;
+        let (records,): (Vec<String>,) = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "ExportAuditLog", (since,))
+            .with_context(|| "Cannot export the audit log")?;
+        debug!("Exporting {} record(s) since {}", records.len(), since);
+        let mut out: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Cannot open '{}'", path.display()))?,
+            ),
+            None => Box::new(std::io::stdout().lock()),
+        };
+        for record in records {
+            writeln!(out, "{}", record)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+//! Save all the collections into a passphrase protected file, and restore them from it, e.g. when
+//! moving to another machine.
+
+use crate::dbus_client::{connect, service_proxy, SERVICE_NAME, SERVICE_PATH};
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use console::Term;
+use log::debug;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The export waits for the user to confirm it
+const CREATE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum RestoreMode {
+    /// Only add the backed up items missing from the existing collections
+    Merge,
+    /// Replace the items of the existing collections with the backed up ones
+    Replace,
+}
+
+impl RestoreMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RestoreMode::Merge => "merge",
+            RestoreMode::Replace => "replace",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct BackupCreateCmd {
+    /// File to write the backup to
+    pub file: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+pub struct BackupRestoreCmd {
+    /// Backup file made by `tks-cli backup create`
+    pub file: PathBuf,
+    #[clap(long, value_enum, default_value = "merge")]
+    /// What to do with the collections already in the storage
+    pub mode: RestoreMode,
+}
+
+fn read_passphrase(prompt: &str) -> Result<String> {
+    let term = Term::stderr();
+    term.write_str(prompt)?;
+    Ok(term.read_secure_line()?)
+}
+
+impl BackupCreateCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        if self.file.exists() {
+            anyhow::bail!("File '{}' already exists", self.file.display());
+        }
+        let passphrase = read_passphrase("Backup passphrase: ")?;
+        if passphrase.is_empty() {
+            anyhow::bail!("The backup passphrase cannot be empty");
+        }
+        if read_passphrase("Confirm passphrase: ")? != passphrase {
+            anyhow::bail!("Passphrases do not match");
+        }
+
+        let conn = connect()?;
+        let (archive,): (Vec<u8>,) = conn
+            .with_proxy(SERVICE_NAME, SERVICE_PATH, CREATE_TIMEOUT)
+            .method_call("io.linux_tks.Service1", "CreateBackup", (passphrase,))
+            .with_context(|| "Cannot create the backup")?;
+        debug!("Writing {} bytes to {}", archive.len(), self.file.display());
+        fs::write(&self.file, archive)
+            .with_context(|| format!("Cannot write '{}'", self.file.display()))?;
+        println!("Backup written to '{}'", self.file.display().to_string().bold());
+        Ok(())
+    }
+}
+
+impl BackupRestoreCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let archive = fs::read(&self.file)
+            .with_context(|| format!("Cannot read '{}'", self.file.display()))?;
+        let passphrase = read_passphrase("Backup passphrase: ")?;
+
+        let conn = connect()?;
+        let (created, added, removed, skipped): (u32, u32, u32, u32) = service_proxy(&conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "RestoreBackup",
+                (archive, passphrase, self.mode.as_str()),
+            )
+            .with_context(|| "Cannot restore the backup")?;
+        println!(
+            "{} collection(s) created, {} item(s) restored",
+            created.to_string().bold(),
+            added.to_string().bold()
+        );
+        if removed > 0 {
+            println!("{} item(s) not in the backup got deleted", removed.to_string().yellow());
+        }
+        if skipped > 0 {
+            println!("{} item(s) were already there", skipped);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! List the clients the user let use tks-service, by process executable, and revoke them so that
+//! they have to ask again. The service keeps them encrypted with the storage key, so it needs to
+//! be unlocked.
+
+use crate::dbus_client::{connect, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+#[derive(Parser, Debug)]
+pub struct ClientsListCmd {}
+
+#[derive(Parser, Debug)]
+pub struct ClientsRevokeCmd {
+    /// Process executable of the client, as listed by `tks-cli clients list`
+    pub exe: String,
+}
+
+impl ClientsListCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let (clients,): (Vec<(String, String, String)>,) = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "ListClients", ())
+            .with_context(|| "Cannot list the clients, is the storage unlocked?")?;
+        if clients.is_empty() {
+            println!("No client let in");
+            return Ok(());
+        }
+        for (exe, name, sha256) in clients {
+            println!("{} {} {}", name.bold(), exe, format!("sha256:{}", sha256).dimmed());
+        }
+        Ok(())
+    }
+}
+
+impl ClientsRevokeCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let (revoked,): (bool,) = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "RevokeClient", (&self.exe,))
+            .with_context(|| format!("Cannot revoke {}", self.exe))?;
+        match revoked {
+            true => println!("{} will have to ask to be let in again", self.exe),
+            false => println!("{} wasn't let in", self.exe),
+        }
+        Ok(())
+    }
+}
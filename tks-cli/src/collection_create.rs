@@ -0,0 +1,37 @@
+//! Create a collection. When a collection of the same name exists, the service asks the user
+//! whether to reuse it.
+
+use crate::dbus_client::{capabilities, connect_secret_service};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use log::debug;
+
+#[derive(Parser, Debug)]
+pub struct CollectionCreateCmd {
+    /// Label of the collection
+    pub label: String,
+    #[clap(long, default_value = "")]
+    /// Alias to give the collection, e.g. `default`
+    pub alias: String,
+}
+
+impl CollectionCreateCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let capabilities = capabilities()?;
+        anyhow::ensure!(
+            capabilities.create_collection,
+            "The {} storage backend keeps all the items in the default collection, it can't \
+             create other collections",
+            capabilities.backend
+        );
+        let ss = connect_secret_service().await?;
+        let collection = ss
+            .create_collection(&self.label, &self.alias)
+            .await
+            .with_context(|| format!("Cannot create collection '{}'", self.label))?;
+        debug!("Created {}", collection.collection_path.as_str());
+        println!("Created '{}'", self.label.bold());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+//! Show the latest operations on the items of a collection, to find out what an application
+//! changed recently. Only the labels are shown, never the secrets.
+
+use crate::age::age;
+use crate::dbus_client::{connect, resolve_collection, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Parser, Debug)]
+pub struct CollectionHistoryCmd {
+    /// Collection to show: an alias, a label or an object path
+    pub name: String,
+    #[clap(long, short)]
+    /// Show only the latest operations
+    pub count: Option<usize>,
+}
+
+impl CollectionHistoryCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.name)?;
+        let (entries,): (Vec<(u64, String, String, String)>,) = conn
+            .with_proxy(SERVICE_NAME, &collection, TIMEOUT)
+            .method_call("io.linux_tks.Collection1", "GetHistory", ())
+            .with_context(|| format!("Cannot get the history of '{}'", self.name))?;
+        if entries.is_empty() {
+            println!("No recent changes in '{}'", self.name);
+            return Ok(());
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let skipped = self.count.map_or(0, |count| entries.len().saturating_sub(count));
+        for (time, operation, label, client) in entries.into_iter().skip(skipped) {
+            let operation = match operation.as_str() {
+                "created" | "restored" => operation.green(),
+                "deleted" => operation.red(),
+                _ => operation.yellow(),
+            };
+            print!("{:>4} ago  {:<8}  {}", age(time, now), operation, label.bold());
+            if !client.is_empty() {
+                print!("  by {}", client);
+            }
+            println!();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+//! List the collections, with their lock state and item count.
+
+use crate::dbus_client::{connect, service_proxy, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+
+#[derive(Parser, Debug)]
+pub struct CollectionListCmd {}
+
+impl CollectionListCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let service = service_proxy(&conn);
+        let (default,): (dbus::Path<'static>,) = service
+            .method_call("org.freedesktop.Secret.Service", "ReadAlias", ("default",))
+            .with_context(|| "Cannot read the default alias")?;
+        let collections: Vec<dbus::Path<'static>> =
+            service.get("org.freedesktop.Secret.Service", "Collections")?;
+        for path in collections {
+            let proxy = conn.with_proxy(SERVICE_NAME, &path, TIMEOUT);
+            let label: String = proxy.get("org.freedesktop.Secret.Collection", "Label")?;
+            let locked: bool = proxy.get("org.freedesktop.Secret.Collection", "Locked")?;
+            let items: Vec<dbus::Path<'static>> =
+                proxy.get("org.freedesktop.Secret.Collection", "Items")?;
+            println!(
+                "{}{} ({} items, {})",
+                label.bold(),
+                if path == default { " [default]" } else { "" },
+                items.len(),
+                if locked { "locked".red() } else { "unlocked".green() }
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+//! Lock or unlock a collection. Unlocking prompts for the passphrase through the service.
+
+use crate::dbus_client::{connect_secret_service, find_collection};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+#[derive(Parser, Debug)]
+pub struct CollectionLockCmd {
+    #[clap(default_value = "default")]
+    /// Collection to act on: an alias or a label
+    pub collection: String,
+}
+
+impl CollectionLockCmd {
+    pub(crate) async fn run(&self, lock: bool) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let collection = find_collection(&ss, &self.collection).await?;
+        match lock {
+            true => collection.lock().await,
+            false => collection.unlock().await,
+        }
+        .with_context(|| {
+            format!("Cannot {} '{}'", if lock { "lock" } else { "unlock" }, self.collection)
+        })?;
+        println!(
+            "{} '{}'",
+            if lock { "Locked" } else { "Unlocked" },
+            self.collection.bold()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! Move all the items of a collection into another one, e.g. after importing secrets into a
+//! temporary collection.
+
+use crate::dbus_client::{connect, resolve_collection, service_proxy};
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use log::debug;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OnConflict {
+    /// Leave the item in the source collection
+    Skip,
+    /// Replace the destination item
+    Replace,
+    /// Keep both items in the destination collection
+    KeepBoth,
+}
+
+impl OnConflict {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OnConflict::Skip => "skip",
+            OnConflict::Replace => "replace",
+            OnConflict::KeepBoth => "keep-both",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct CollectionMergeCmd {
+    /// Collection to take the items from: an alias, a label or an object path
+    pub source: String,
+    /// Collection to move the items to: an alias, a label or an object path
+    pub destination: String,
+    #[clap(long, value_enum, default_value = "skip")]
+    /// What to do with source items having the same attributes as a destination item
+    pub on_conflict: OnConflict,
+    #[clap(long)]
+    /// Delete the source collection once all of its items got moved
+    pub delete_source: bool,
+}
+
+impl CollectionMergeCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let source = resolve_collection(&conn, &self.source)?;
+        let destination = resolve_collection(&conn, &self.destination)?;
+        debug!("Merging {} into {}", source, destination);
+
+        let (moved, skipped, deleted): (u32, u32, bool) = service_proxy(&conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "MergeCollections",
+                (
+                    source,
+                    destination,
+                    self.on_conflict.as_str(),
+                    self.delete_source,
+                ),
+            )
+            .with_context(|| "Cannot merge the collections")?;
+        println!(
+            "{} item(s) moved from '{}' to '{}'",
+            moved.to_string().bold(),
+            self.source,
+            self.destination
+        );
+        if skipped > 0 {
+            println!(
+                "{} item(s) left in '{}' because of conflicts",
+                skipped.to_string().yellow(),
+                self.source
+            );
+        }
+        if deleted {
+            println!("Collection '{}' deleted", self.source);
+        } else if self.delete_source {
+            println!(
+                "{}: collection '{}' was not deleted as it still holds items",
+                "WARNING".bold(),
+                self.source
+            );
+        }
+        Ok(())
+    }
+}
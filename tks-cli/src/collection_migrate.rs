@@ -0,0 +1,33 @@
+//! Move a collection to another storage backend, e.g. from a shared store into the personal one.
+
+use crate::dbus_client::{connect, resolve_collection, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::debug;
+
+#[derive(Parser, Debug)]
+pub struct CollectionMigrateCmd {
+    /// Collection to move: an alias, a label or an object path
+    pub name: String,
+    #[clap(long)]
+    /// Name of the backend in the service settings, "main" being the [storage] one
+    pub to: String,
+}
+
+impl CollectionMigrateCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.name)?;
+        debug!("Migrating {} to {}", collection, self.to);
+
+        service_proxy(&conn)
+            .method_call::<(), _, _, _>(
+                "io.linux_tks.Service1",
+                "MigrateCollection",
+                (collection, self.to.as_str()),
+            )
+            .with_context(|| "Cannot migrate the collection")?;
+        println!("Collection '{}' moved to backend '{}'", self.name, self.to);
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Helpers for the commands talking to tks-service over the session bus. The commands handling
+//! secrets, or which may need a prompt, go through the secret-service crate instead, which
+//! encrypts the secrets in transit and runs the prompts.
+
+use anyhow::{Context, Result};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::{Connection, Proxy};
+use log::debug;
+use secret_service::{Collection, EncryptionType, Item, SecretService};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+pub const SERVICE_NAME: &str = "org.freedesktop.secrets";
+pub const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+
+pub fn connect() -> Result<Connection> {
+    Connection::new_session().with_context(|| "Cannot connect to the session bus")
+}
+
+pub fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+    conn.with_proxy(SERVICE_NAME, SERVICE_PATH, TIMEOUT)
+}
+
+/// Finds the collection object path given either an object path, an alias or a label
+pub fn resolve_collection(conn: &Connection, name: &str) -> Result<dbus::Path<'static>> {
+    if name.starts_with('/') {
+        return dbus::Path::new(name.to_string()).map_err(|e| anyhow::anyhow!(e));
+    }
+    let service = service_proxy(conn);
+    let (path,): (dbus::Path<'static>,) = service
+        .method_call("org.freedesktop.Secret.Service", "ReadAlias", (name,))
+        .with_context(|| format!("Cannot read alias '{}'", name))?;
+    if &*path != "/" {
+        return Ok(path);
+    }
+    let collections: Vec<dbus::Path<'static>> =
+        service.get("org.freedesktop.Secret.Service", "Collections")?;
+    for path in collections {
+        let label: String = conn
+            .with_proxy(SERVICE_NAME, &path, TIMEOUT)
+            .get("org.freedesktop.Secret.Collection", "Label")?;
+        if label == name {
+            return Ok(path);
+        }
+    }
+    anyhow::bail!("No collection named '{}'", name)
+}
+
+/// What the storage backend of the service can do, see `io.linux_tks.Service1.GetCapabilities`
+pub struct Capabilities {
+    pub backend: String,
+    pub create_collection: bool,
+    pub multiple_collections: bool,
+    pub binary_secrets: bool,
+    pub max_secret_size: Option<u64>,
+}
+
+impl Capabilities {
+    /// Fails unless the backend can store the secret
+    pub fn check_secret(&self, secret: &[u8], binary: bool) -> Result<()> {
+        anyhow::ensure!(
+            !binary || self.binary_secrets,
+            "The {} storage backend only stores text secrets",
+            self.backend
+        );
+        if let Some(max) = self.max_secret_size {
+            anyhow::ensure!(
+                secret.len() as u64 <= max,
+                "The secret takes {} bytes, the {} storage backend stores at most {}",
+                secret.len(),
+                self.backend,
+                max
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Asks the service what its storage backend can do. Other Secret Service implementations don't
+/// have the TKS extensions, so they are taken as able to do everything.
+pub fn capabilities() -> Result<Capabilities> {
+    let conn = connect()?;
+    let result: Result<(String, bool, bool, bool, u64), dbus::Error> =
+        service_proxy(&conn).method_call("io.linux_tks.Service1", "GetCapabilities", ());
+    Ok(match result {
+        Ok((backend, create_collection, multiple_collections, binary_secrets, max_secret_size)) => {
+            Capabilities {
+                backend,
+                create_collection,
+                multiple_collections,
+                binary_secrets,
+                max_secret_size: Some(max_secret_size).filter(|max| *max > 0),
+            }
+        }
+        Err(e) => {
+            debug!("Cannot get the service capabilities: {}", e);
+            Capabilities {
+                backend: "unknown".to_string(),
+                create_collection: true,
+                multiple_collections: true,
+                binary_secrets: true,
+                max_secret_size: None,
+            }
+        }
+    })
+}
+
+/// An exclusive batch lease on a collection, see `io.linux_tks.Collection1.BeginBatch`: the other
+/// clients can't change the collection until it gets dropped
+pub struct Batch {
+    conn: Connection,
+    collection: dbus::Path<'static>,
+    renewed: Instant,
+}
+
+impl Batch {
+    /// Seconds the lease lasts unless renewed
+    const TIMEOUT: u32 = 300;
+
+    pub fn begin(collection: &str) -> Result<Batch> {
+        let batch = Batch {
+            conn: connect()?,
+            collection: dbus::Path::new(collection.to_string()).map_err(|e| anyhow::anyhow!(e))?,
+            renewed: Instant::now(),
+        };
+        batch.call("BeginBatch", (Batch::TIMEOUT,))?;
+        Ok(batch)
+    }
+
+    /// Renews the lease once half of it elapsed, so long batches keep it
+    pub fn keep_alive(&mut self) -> Result<()> {
+        if self.renewed.elapsed() >= Duration::from_secs((Batch::TIMEOUT / 2).into()) {
+            self.call("BeginBatch", (Batch::TIMEOUT,))?;
+            self.renewed = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn call<A: dbus::arg::AppendAll>(&self, method: &str, args: A) -> Result<()> {
+        self.conn
+            .with_proxy(SERVICE_NAME, &self.collection, TIMEOUT)
+            .method_call::<(), _, _, _>("io.linux_tks.Collection1", method, args)
+            .with_context(|| format!("Cannot {} on {}", method, self.collection))
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if let Err(e) = self.call("EndBatch", ()) {
+            debug!("{:#}", e);
+        }
+    }
+}
+
+pub async fn connect_secret_service() -> Result<SecretService<'static>> {
+    SecretService::connect(EncryptionType::Dh)
+        .await
+        .with_context(|| "Failed to connect to the secret service. Is the TKS service running?")
+}
+
+/// Finds a collection given either an alias or a label
+pub async fn find_collection<'a>(ss: &'a SecretService<'a>, name: &str) -> Result<Collection<'a>> {
+    if let Ok(collection) = ss.get_collection_by_alias(name).await {
+        return Ok(collection);
+    }
+    for collection in ss.get_all_collections().await? {
+        if collection.get_label().await? == name {
+            return Ok(collection);
+        }
+    }
+    anyhow::bail!("No collection named '{}'", name)
+}
+
+/// The single item matching all the search terms, unlocked. `name=value` terms match the item
+/// attributes, other terms match the label.
+pub async fn find_item<'a>(
+    ss: &'a SecretService<'a>,
+    collection: Option<&str>,
+    search: &[String],
+) -> Result<Item<'a>> {
+    let attributes: HashMap<&str, &str> =
+        search.iter().filter_map(|term| term.split_once('=')).collect();
+    let labels: Vec<&String> = search.iter().filter(|term| !term.contains('=')).collect();
+    let result = ss.search_items(attributes).await?;
+    let mut candidates: Vec<Item> = result.unlocked.into_iter().chain(result.locked).collect();
+    if let Some(name) = collection {
+        let paths: Vec<_> = find_collection(ss, name)
+            .await?
+            .get_all_items()
+            .await?
+            .into_iter()
+            .map(|i| i.item_path)
+            .collect();
+        candidates.retain(|i| paths.contains(&i.item_path));
+    }
+    let mut found = Vec::new();
+    for item in candidates {
+        let label = item.get_label().await?;
+        if labels.iter().all(|l| **l == label) {
+            found.push(item);
+        }
+    }
+    let item = match found.len() {
+        0 => anyhow::bail!("No item matching {:?}", search),
+        1 => found.pop().unwrap(),
+        n => anyhow::bail!("{} items match {:?}, add terms to pick one", n, search),
+    };
+    item.ensure_unlocked()
+        .await
+        .with_context(|| "Cannot unlock the item")?;
+    Ok(item)
+}
@@ -0,0 +1,97 @@
+//! The desktop clipboard, typing and notifications. These go through the usual command line
+//! tools: `wl-copy`/`wl-paste` and `wtype` on Wayland, `xclip` and `xdotool` on X11, and
+//! `notify-send`. The clipboard tools keep serving the clipboard once tks-cli has exited.
+
+use anyhow::{Context, Result};
+use log::debug;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+pub enum Desktop {
+    Wayland,
+    X11,
+}
+
+impl Desktop {
+    /// The graphical session tks-cli runs in
+    pub fn detect() -> Result<Desktop> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Ok(Desktop::Wayland)
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Ok(Desktop::X11)
+        } else {
+            anyhow::bail!("No graphical session, neither WAYLAND_DISPLAY nor DISPLAY is set")
+        }
+    }
+
+    pub fn copy(&self, data: &[u8]) -> Result<()> {
+        match self {
+            Desktop::Wayland => feed("wl-copy", &[], data),
+            Desktop::X11 => feed("xclip", &["-selection", "clipboard"], data),
+        }
+    }
+
+    pub fn paste(&self) -> Result<Vec<u8>> {
+        match self {
+            Desktop::Wayland => read("wl-paste", &["--no-newline"]),
+            Desktop::X11 => read("xclip", &["-selection", "clipboard", "-o"]),
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        match self {
+            Desktop::Wayland => feed("wl-copy", &["--clear"], &[]),
+            Desktop::X11 => feed("xclip", &["-selection", "clipboard"], &[]),
+        }
+    }
+
+    /// Types the text into the focused window, as if it came from the keyboard
+    pub fn type_text(&self, text: &[u8]) -> Result<()> {
+        match self {
+            Desktop::Wayland => feed("wtype", &["-"], text),
+            Desktop::X11 => feed("xdotool", &["type", "--clearmodifiers", "--file", "-"], text),
+        }
+    }
+}
+
+/// Shows a desktop notification. The notification daemon may be missing, so failures only get
+/// logged.
+pub fn notify(summary: &str, body: &str) {
+    let status = Command::new("notify-send")
+        .args(["--app-name=tks", "--icon=dialog-password", summary, body])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(s) if s.success() => {}
+        Ok(s) => debug!("notify-send failed: {}", s),
+        Err(e) => debug!("Cannot run notify-send: {}", e),
+    }
+}
+
+/// Runs `program` with `data` on its standard input. The clipboard tools fork a process serving
+/// the clipboard, which keeps the standard output open, so that one doesn't get read.
+fn feed(program: &str, args: &[&str], data: &[u8]) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Cannot run {}, is it installed?", program))?;
+    child.stdin.take().unwrap().write_all(data)?;
+    let status = child.wait()?;
+    anyhow::ensure!(status.success(), "{} failed: {}", program, status);
+    Ok(())
+}
+
+fn read(program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| format!("Cannot run {}, is it installed?", program))?;
+    anyhow::ensure!(output.status.success(), "{} failed: {}", program, output.status);
+    Ok(output.stdout)
+}
@@ -0,0 +1,195 @@
+//! The docker-credential-helpers protocol, so that Docker, Podman and the other OCI tools keep
+//! their registry credentials in TKS. Each action reads its input from the standard input and
+//! writes its answer to the standard output: `store` takes a `{"ServerURL", "Username", "Secret"}`
+//! JSON object, `get` and `erase` a server URL, `get` answers the same JSON object and `list` a
+//! `{server URL: username}` one. The errors get written to the standard output too, with a
+//! non-zero exit status, as the tools expect.
+//!
+//! The credentials are items having the attributes of docker-credential-secretservice, so the ones
+//! it stored keep working. Docker looks for a `docker-credential-<credsStore>` executable: a
+//! `docker-credential-tks` symlink to tks-cli runs this command, `"credsStore": "tks"` in
+//! `~/.docker/config.json` then picks it.
+
+use crate::dbus_client::{connect_secret_service, find_collection};
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use secret_service::{Collection, Item};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+
+/// Executable name prefix of the credential helpers, see [helper_args]
+const HELPER_PREFIX: &str = "docker-credential-";
+
+/// The schema docker-credential-secretservice stores the credentials with
+const SCHEMA: &str = "io.docker.Credentials";
+
+/// The label attribute of docker-credential-secretservice, grouping the credentials
+const CREDENTIALS_LABEL: &str = "Docker Credentials";
+
+/// What the tools look for in the output of `get` and `erase`, to tell missing credentials apart
+const NOT_FOUND: &str = "credentials not found in native keychain";
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DockerCredentialAction {
+    /// Store the credentials read as JSON
+    Store,
+    /// Print the credentials of the server URL read
+    Get,
+    /// Delete the credentials of the server URL read
+    Erase,
+    /// Print the usernames, by server URL
+    List,
+}
+
+#[derive(Parser, Debug)]
+pub struct DockerCredentialCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection keeping the credentials: an alias or a label
+    pub collection: String,
+    pub action: DockerCredentialAction,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Credentials {
+    #[serde(rename = "ServerURL")]
+    server_url: String,
+    username: String,
+    secret: String,
+}
+
+/// The arguments of tks-cli started through a `docker-credential-<name>` symlink, e.g. `get`
+/// becoming `docker-credential get`; none when started as tks-cli
+pub fn helper_args() -> Option<Vec<String>> {
+    let mut args = std::env::args();
+    let program = args.next()?;
+    let name = std::path::Path::new(&program)
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    if !name.starts_with(HELPER_PREFIX) {
+        return None;
+    }
+    Some(
+        [name, "docker-credential".to_string()]
+            .into_iter()
+            .chain(args)
+            .collect(),
+    )
+}
+
+impl DockerCredentialCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        if let Err(e) = self.answer().await {
+            log::debug!("{:?}", e);
+            println!("{:#}", e);
+            std::process::exit(1);
+        }
+        Ok(())
+    }
+
+    async fn answer(&self) -> Result<()> {
+        let mut input = String::new();
+        if !matches!(self.action, DockerCredentialAction::List) {
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .with_context(|| "Cannot read the standard input")?;
+        }
+        let ss = connect_secret_service().await?;
+        let collection = find_collection(&ss, &self.collection).await?;
+        collection
+            .ensure_unlocked()
+            .await
+            .with_context(|| format!("Cannot unlock '{}'", self.collection))?;
+        let mut out = std::io::stdout().lock();
+        match self.action {
+            DockerCredentialAction::Store => {
+                let credentials: Credentials = serde_json::from_str(&input)
+                    .with_context(|| "Invalid credentials, a JSON object is expected")?;
+                anyhow::ensure!(
+                    !credentials.server_url.is_empty(),
+                    "no credentials server URL"
+                );
+                anyhow::ensure!(!credentials.username.is_empty(), "no credentials username");
+                let mut attributes = attributes(Some(&credentials.server_url));
+                attributes.insert("username", &credentials.username);
+                collection
+                    .create_item(
+                        &credentials.server_url,
+                        attributes,
+                        credentials.secret.as_bytes(),
+                        true,
+                        "text/plain",
+                    )
+                    .await
+                    .with_context(|| "Cannot store the credentials")?;
+            }
+            DockerCredentialAction::Get => {
+                let server_url = input.trim();
+                let item = find(&collection, server_url).await?;
+                let credentials = Credentials {
+                    server_url: server_url.to_string(),
+                    username: username(&item).await?,
+                    secret: String::from_utf8(item.get_secret().await?)
+                        .with_context(|| "The secret is not text")?,
+                };
+                serde_json::to_writer(&mut out, &credentials)?;
+                writeln!(out)?;
+            }
+            DockerCredentialAction::Erase => {
+                find(&collection, input.trim())
+                    .await?
+                    .delete()
+                    .await
+                    .with_context(|| "Cannot delete the credentials")?;
+            }
+            DockerCredentialAction::List => {
+                let mut list = BTreeMap::new();
+                for item in collection.search_items(attributes(None)).await? {
+                    let attributes = item.get_attributes().await?;
+                    if let Some(server) = attributes.get("server") {
+                        list.insert(server.clone(), username(&item).await?);
+                    }
+                }
+                serde_json::to_writer(&mut out, &list)?;
+                writeln!(out)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// The attributes of the credentials, of those of a server when given
+fn attributes(server_url: Option<&str>) -> HashMap<&str, &str> {
+    let mut attributes = HashMap::from([
+        ("xdg:schema", SCHEMA),
+        ("label", CREDENTIALS_LABEL),
+        ("docker_cli", "1"),
+    ]);
+    if let Some(server_url) = server_url {
+        attributes.insert("server", server_url);
+    }
+    attributes
+}
+
+async fn find<'a>(collection: &'a Collection<'a>, server_url: &str) -> Result<Item<'a>> {
+    anyhow::ensure!(!server_url.is_empty(), "no credentials server URL");
+    let mut items = collection
+        .search_items(attributes(Some(server_url)))
+        .await?;
+    let item = items.pop().with_context(|| NOT_FOUND)?;
+    item.ensure_unlocked()
+        .await
+        .with_context(|| "Cannot unlock the credentials")?;
+    Ok(item)
+}
+
+async fn username(item: &Item<'_>) -> Result<String> {
+    Ok(item
+        .get_attributes()
+        .await?
+        .remove("username")
+        .unwrap_or_default())
+}
@@ -0,0 +1,129 @@
+//! Export the items of a collection as a KWalletManager XML file, the counterpart of
+//! `import kwallet`: the KWallet entries imported into TKS get back their folder, name and type,
+//! and the map entries their keys and values.
+//!
+//! The file holds the secrets in clear text, so it gets created readable by its owner only.
+
+use crate::dbus_client::{connect_secret_service, find_collection};
+use crate::import_kwallet::MAP_CONTENT_TYPE;
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+/// The folder of the items which don't come from KWallet, and have no `tks:path` attribute
+const DEFAULT_FOLDER: &str = "Passwords";
+
+#[derive(Parser, Debug)]
+pub struct ExportKwalletCmd {
+    /// File to write
+    pub xml_file: PathBuf,
+    #[clap(long, default_value = "default")]
+    /// Collection to export: an alias or a label
+    pub collection: String,
+    #[clap(long, default_value = "kdewallet")]
+    /// Name of the wallet in the file
+    pub wallet: String,
+    #[clap(long)]
+    /// Overwrite the file when it exists
+    pub force: bool,
+}
+
+/// A wallet entry, ready to be written
+enum WalletEntry {
+    Password(String),
+    Map(BTreeMap<String, String>),
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+impl ExportKwalletCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let collection = find_collection(&ss, &self.collection).await?;
+        if collection.is_locked().await? {
+            collection
+                .unlock()
+                .await
+                .with_context(|| "Failed to unlock collection")?;
+        }
+
+        let mut folders: BTreeMap<String, Vec<(String, WalletEntry)>> = BTreeMap::new();
+        for item in collection.get_all_items().await? {
+            let label = item.get_label().await?;
+            let attributes = item.get_attributes().await?;
+            let folder = attributes
+                .get("tks:kwallet-folder")
+                .or(attributes.get("tks:path"))
+                .map_or(DEFAULT_FOLDER, |f| f.as_str());
+            let secret = item.get_secret().await?;
+            let is_map = attributes.get("tks:kwallet-entry-type").map(|t| t.as_str())
+                == Some("map")
+                || item.get_secret_content_type().await? == MAP_CONTENT_TYPE;
+            let entry = match is_map {
+                true => serde_json::from_slice(&secret).map(WalletEntry::Map).ok(),
+                false => String::from_utf8(secret).map(WalletEntry::Password).ok(),
+            };
+            match entry {
+                Some(entry) => folders
+                    .entry(folder.to_string())
+                    .or_default()
+                    .push((label, entry)),
+                None => warn!(
+                    "  Skipping '{}/{}': KWallet can't hold its secret",
+                    folder, label
+                ),
+            }
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).mode(0o600);
+        match self.force {
+            true => options.create(true).truncate(true),
+            false => options.create_new(true),
+        };
+        let mut out = options
+            .open(&self.xml_file)
+            .with_context(|| format!("Cannot create '{}'", self.xml_file.display()))?;
+        writeln!(out, "<wallet name=\"{}\">", escape(&self.wallet))?;
+        for (folder, entries) in &folders {
+            writeln!(out, "<folder name=\"{}\">", escape(folder))?;
+            for (name, entry) in entries {
+                match entry {
+                    WalletEntry::Password(password) => writeln!(
+                        out,
+                        "<password name=\"{}\">{}</password>",
+                        escape(name),
+                        escape(password)
+                    )?,
+                    WalletEntry::Map(map) => {
+                        writeln!(out, "<map name=\"{}\">", escape(name))?;
+                        for (key, value) in map {
+                            writeln!(
+                                out,
+                                "<mapentry name=\"{}\">{}</mapentry>",
+                                escape(key),
+                                escape(value)
+                            )?;
+                        }
+                        writeln!(out, "</map>")?;
+                    }
+                }
+                info!("  '{}/{}' exported", folder, name);
+            }
+            writeln!(out, "</folder>")?;
+        }
+        writeln!(out, "</wallet>")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,701 @@
+//! Mount the collections as a FUSE filesystem, for the tools which only read files: a directory
+//! per collection, holding a file per item, named after their labels. The locked collections are
+//! empty directories until they get unlocked, e.g. with `tks-cli collection unlock`. The
+//! filesystem is read-only unless `--read-write` is given: writing a file then replaces the secret
+//! of its item once closed, creating a file creates an item and removing one deletes its item.
+//!
+//! The secrets get read through the service when a file is opened, so that the usual prompts and
+//! ACLs apply; the files report a size of 0 until then, and get read directly rather than through
+//! the page cache. Only the user mounting the filesystem may access it. The kernel protocol gets
+//! spoken over `/dev/fuse`, which `fusermount3` opens and mounts on behalf of the user.
+
+use crate::dbus_client::connect_secret_service;
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::{debug, error, trace};
+use secret_service::{Error as SsError, SecretService};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_UNLINK: u32 = 10;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_CREATE: u32 = 35;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+/// The setattr fields given, see `fuse_setattr_in`
+const FATTR_SIZE: u32 = 1 << 3;
+const FATTR_FH: u32 = 1 << 6;
+/// Reads and writes go to the filesystem, rather than through the page cache
+const FOPEN_DIRECT_IO: u32 = 1;
+
+const IN_HEADER_SIZE: usize = 40;
+const MAX_WRITE: usize = 128 * 1024;
+const ROOT_INODE: u64 = 1;
+/// How long the kernel may cache the names and attributes, in seconds
+const TTL: u64 = 1;
+
+#[derive(Parser, Debug)]
+pub struct FuseCmd {
+    #[clap(long)]
+    /// Let the files get written, created and removed
+    pub read_write: bool,
+    /// Directory to mount the collections on
+    pub mountpoint: PathBuf,
+}
+
+impl FuseCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let device = Arc::new(mount(&self.mountpoint, self.read_write)?);
+        println!(
+            "Mounted the collections on {}, press Ctrl-C to unmount",
+            self.mountpoint.display()
+        );
+        let mut fs = Filesystem::new(&ss, self.read_write);
+        let served = tokio::select! {
+            served = fs.serve(device) => served,
+            _ = tokio::signal::ctrl_c() => Ok(()),
+        };
+        fs.forget_secrets();
+        unmount(&self.mountpoint);
+        served
+    }
+}
+
+/// Mounts the filesystem through fusermount3, which sends back the opened `/dev/fuse`
+fn mount(mountpoint: &Path, read_write: bool) -> Result<File> {
+    let (socket, child_socket) = UnixStream::pair()?;
+    // fusermount3 gets the socket as its _FUSE_COMMFD
+    if unsafe { libc::fcntl(child_socket.as_raw_fd(), libc::F_SETFD, 0) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let options = format!(
+        "{},nosuid,nodev,default_permissions,fsname=tks,subtype=tks",
+        if read_write { "rw" } else { "ro" }
+    );
+    let status = Command::new("fusermount3")
+        .env("_FUSE_COMMFD", child_socket.as_raw_fd().to_string())
+        .args(["-o", &options, "--"])
+        .arg(mountpoint)
+        .status()
+        .with_context(|| "Cannot run fusermount3, is fuse3 installed?")?;
+    drop(child_socket);
+    anyhow::ensure!(
+        status.success(),
+        "fusermount3 could not mount {}",
+        mountpoint.display()
+    );
+    Ok(File::from(receive_fd(&socket)?))
+}
+
+fn receive_fd(socket: &UnixStream) -> Result<OwnedFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<libc::c_int>() as u32) } as usize;
+    let mut control = vec![0u8; space];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    anyhow::ensure!(
+        !cmsg.is_null() && unsafe { (*cmsg).cmsg_type } == libc::SCM_RIGHTS,
+        "fusermount3 did not send the FUSE device"
+    );
+    let fd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int) };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn unmount(mountpoint: &Path) {
+    match Command::new("fusermount3")
+        .arg("-u")
+        .arg(mountpoint)
+        .status()
+    {
+        Ok(status) if status.success() => println!("Unmounted {}", mountpoint.display()),
+        Ok(_) => error!("fusermount3 could not unmount {}", mountpoint.display()),
+        Err(e) => error!("Cannot run fusermount3: {}", e),
+    }
+}
+
+/// A request of the kernel, after its header
+struct Request<'a> {
+    opcode: u32,
+    unique: u64,
+    inode: u64,
+    body: &'a [u8],
+}
+
+impl Request<'_> {
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_ne_bytes(self.body[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_ne_bytes(self.body[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The NUL terminated name starting at the offset
+    fn name_at(&self, offset: usize) -> String {
+        let name = &self.body[offset..];
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..end]).into_owned()
+    }
+}
+
+/// The answer to a request, either data or an errno
+type Reply = std::result::Result<Vec<u8>, i32>;
+
+/// Appends the fields of the reply structures, in native byte order
+trait Fields {
+    fn u32(&mut self, value: u32) -> &mut Self;
+    fn u64(&mut self, value: u64) -> &mut Self;
+}
+
+impl Fields for Vec<u8> {
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Root,
+    Collection { path: String },
+    Item { collection: String, path: String },
+}
+
+/// An opened file, holding the secret of its item
+struct Handle {
+    inode: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+struct Filesystem<'a> {
+    ss: &'a SecretService<'a>,
+    read_write: bool,
+    uid: u32,
+    gid: u32,
+    nodes: HashMap<u64, Node>,
+    /// The inodes of the collections and items, by object path
+    inodes: HashMap<String, u64>,
+    handles: HashMap<u64, Handle>,
+    last_handle: u64,
+}
+
+fn errno(e: SsError) -> i32 {
+    debug!("Secret Service call failed: {}", e);
+    match e {
+        SsError::Locked | SsError::Prompt => libc::EACCES,
+        SsError::NoResult => libc::ENOENT,
+        _ => libc::EIO,
+    }
+}
+
+/// A file name for a label: no slashes, and not empty
+fn file_name(label: &str, path: &str) -> String {
+    let name = label.replace(['/', '\0'], "_");
+    match name.as_str() {
+        "" | "." | ".." => path.rsplit('/').next().unwrap_or("_").to_string(),
+        _ => name,
+    }
+}
+
+impl<'a> Filesystem<'a> {
+    fn new(ss: &'a SecretService<'a>, read_write: bool) -> Filesystem<'a> {
+        Filesystem {
+            ss,
+            read_write,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            nodes: HashMap::from([(ROOT_INODE, Node::Root)]),
+            inodes: HashMap::new(),
+            handles: HashMap::new(),
+            last_handle: 0,
+        }
+    }
+
+    /// Answers the kernel requests until the filesystem gets unmounted
+    async fn serve(&mut self, device: Arc<File>) -> Result<()> {
+        let mut buffer = vec![0u8; MAX_WRITE + 4096];
+        loop {
+            let reader = device.clone();
+            let (read, returned) = tokio::task::spawn_blocking(move || {
+                let read = (&*reader).read(&mut buffer);
+                (read, buffer)
+            })
+            .await?;
+            buffer = returned;
+            let length = match read {
+                Ok(length) => length,
+                Err(e) if e.raw_os_error() == Some(libc::ENODEV) => return Ok(()),
+                // an interrupted request, or a signal
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT | libc::EINTR)) => continue,
+                Err(e) => return Err(e).with_context(|| "Cannot read the FUSE requests"),
+            };
+            anyhow::ensure!(length >= IN_HEADER_SIZE, "Truncated FUSE request");
+            let header = Request {
+                opcode: 0,
+                unique: 0,
+                inode: 0,
+                body: &buffer[..IN_HEADER_SIZE],
+            };
+            let request = Request {
+                opcode: header.u32_at(4),
+                unique: header.u64_at(8),
+                inode: header.u64_at(16),
+                body: &buffer[IN_HEADER_SIZE..length],
+            };
+            trace!("FUSE request {} on {}", request.opcode, request.inode);
+            let reply = match request.opcode {
+                FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => continue,
+                FUSE_DESTROY => return Ok(()),
+                _ => self.answer(&request).await,
+            };
+            let mut out = Vec::with_capacity(16);
+            let (error, data) = match reply {
+                Ok(data) => (0, data),
+                Err(errno) => (-errno, Vec::new()),
+            };
+            out.u32((16 + data.len()) as u32)
+                .u32(error as u32)
+                .u64(request.unique);
+            out.extend_from_slice(&data);
+            if let Err(e) = (&*device).write(&out) {
+                // the request got interrupted meanwhile
+                debug!("Cannot answer FUSE request {}: {}", request.opcode, e);
+            }
+        }
+    }
+
+    async fn answer(&mut self, request: &Request<'_>) -> Reply {
+        match request.opcode {
+            FUSE_INIT => Ok(init(request)),
+            FUSE_LOOKUP => {
+                let inode = self.lookup(request.inode, &request.name_at(0)).await?;
+                self.entry(inode)
+            }
+            FUSE_GETATTR => self.attr_out(request.inode),
+            FUSE_SETATTR => self.setattr(request).await,
+            FUSE_OPENDIR => Ok(open_out(0, 0)),
+            FUSE_RELEASEDIR | FUSE_ACCESS => Ok(Vec::new()),
+            FUSE_READDIR => self.readdir(request).await,
+            FUSE_OPEN => self.open(request.inode, request.u32_at(0)).await,
+            FUSE_READ => {
+                let handle = self.handles.get(&request.u64_at(0)).ok_or(libc::EBADF)?;
+                let offset = (request.u64_at(8) as usize).min(handle.data.len());
+                let end = (offset + request.u32_at(16) as usize).min(handle.data.len());
+                Ok(handle.data[offset..end].to_vec())
+            }
+            FUSE_WRITE => {
+                let handle = self
+                    .handles
+                    .get_mut(&request.u64_at(0))
+                    .ok_or(libc::EBADF)?;
+                let offset = request.u64_at(8) as usize;
+                let data = &request.body[40..40 + request.u32_at(16) as usize];
+                if handle.data.len() < offset + data.len() {
+                    handle.data.resize(offset + data.len(), 0);
+                }
+                handle.data[offset..offset + data.len()].copy_from_slice(data);
+                handle.dirty = true;
+                let mut out = Vec::new();
+                out.u32(data.len() as u32).u32(0);
+                Ok(out)
+            }
+            FUSE_FLUSH => self.flush(request.u64_at(0)).await.map(|_| Vec::new()),
+            FUSE_RELEASE => {
+                let flushed = self.flush(request.u64_at(0)).await;
+                if let Some(mut handle) = self.handles.remove(&request.u64_at(0)) {
+                    handle.data.fill(0);
+                }
+                flushed.map(|_| Vec::new())
+            }
+            FUSE_CREATE => self.create(request).await,
+            FUSE_UNLINK => self.unlink(request.inode, &request.name_at(0)).await,
+            FUSE_STATFS => {
+                let mut out = Vec::new();
+                (0..5).for_each(|_| {
+                    out.u64(0);
+                });
+                out.u32(4096).u32(255).u32(4096).u32(0);
+                (0..6).for_each(|_| {
+                    out.u32(0);
+                });
+                Ok(out)
+            }
+            _ => Err(libc::ENOSYS),
+        }
+    }
+
+    fn inode_of(&mut self, path: &str, node: Node) -> u64 {
+        if let Some(inode) = self.inodes.get(path) {
+            return *inode;
+        }
+        let inode = ROOT_INODE + 1 + self.inodes.len() as u64;
+        self.inodes.insert(path.to_string(), inode);
+        self.nodes.insert(inode, node);
+        inode
+    }
+
+    /// The entries of a directory, by name; the locked collections have none
+    async fn children(&mut self, inode: u64) -> std::result::Result<BTreeMap<String, u64>, i32> {
+        let mut named: Vec<(String, String, Node)> = Vec::new();
+        match self.nodes.get(&inode).ok_or(libc::ENOENT)?.clone() {
+            Node::Root => {
+                for collection in self.ss.get_all_collections().await.map_err(errno)? {
+                    let path = collection.collection_path.to_string();
+                    let label = collection.get_label().await.map_err(errno)?;
+                    named.push((
+                        file_name(&label, &path),
+                        path.clone(),
+                        Node::Collection { path },
+                    ));
+                }
+            }
+            Node::Collection { path } => {
+                let collection = self.collection(&path).await?;
+                if !collection.is_locked().await.map_err(errno)? {
+                    for item in collection.get_all_items().await.map_err(errno)? {
+                        let item_path = item.item_path.to_string();
+                        let label = item.get_label().await.map_err(errno)?;
+                        let node = Node::Item {
+                            collection: path.clone(),
+                            path: item_path.clone(),
+                        };
+                        named.push((file_name(&label, &item_path), item_path, node));
+                    }
+                }
+            }
+            Node::Item { .. } => return Err(libc::ENOTDIR),
+        }
+        // the same labels get numbered, in a stable order
+        named.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut children = BTreeMap::new();
+        for (name, path, node) in named {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while children.contains_key(&unique) {
+                n += 1;
+                unique = format!("{} ({})", name, n);
+            }
+            let inode = self.inode_of(&path, node);
+            children.insert(unique, inode);
+        }
+        Ok(children)
+    }
+
+    async fn lookup(&mut self, parent: u64, name: &str) -> std::result::Result<u64, i32> {
+        self.children(parent)
+            .await?
+            .remove(name)
+            .ok_or(libc::ENOENT)
+    }
+
+    async fn collection(
+        &self,
+        path: &str,
+    ) -> std::result::Result<secret_service::Collection<'a>, i32> {
+        for collection in self.ss.get_all_collections().await.map_err(errno)? {
+            if collection.collection_path.as_str() == path {
+                return Ok(collection);
+            }
+        }
+        Err(libc::ENOENT)
+    }
+
+    fn item_paths(&self, inode: u64) -> std::result::Result<(String, String), i32> {
+        match self.nodes.get(&inode) {
+            Some(Node::Item { collection, path }) => Ok((collection.clone(), path.clone())),
+            Some(_) => Err(libc::EISDIR),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    async fn read_secret(&self, inode: u64) -> std::result::Result<Vec<u8>, i32> {
+        let (collection, path) = self.item_paths(inode)?;
+        let collection = self.collection(&collection).await?;
+        for item in collection.get_all_items().await.map_err(errno)? {
+            if item.item_path.as_str() == path {
+                item.ensure_unlocked().await.map_err(errno)?;
+                return item.get_secret().await.map_err(errno);
+            }
+        }
+        Err(libc::ENOENT)
+    }
+
+    /// Replaces the secret of the item, keeping its content type
+    async fn write_secret(&self, inode: u64, secret: &[u8]) -> std::result::Result<(), i32> {
+        let (collection, path) = self.item_paths(inode)?;
+        let collection = self.collection(&collection).await?;
+        for item in collection.get_all_items().await.map_err(errno)? {
+            if item.item_path.as_str() == path {
+                let content_type = item.get_secret_content_type().await.map_err(errno)?;
+                return item.set_secret(secret, &content_type).await.map_err(errno);
+            }
+        }
+        Err(libc::ENOENT)
+    }
+
+    async fn flush(&mut self, fh: u64) -> std::result::Result<(), i32> {
+        let Some(handle) = self.handles.get(&fh) else {
+            return Ok(());
+        };
+        if !handle.dirty {
+            return Ok(());
+        }
+        self.write_secret(handle.inode, &handle.data).await?;
+        if let Some(handle) = self.handles.get_mut(&fh) {
+            handle.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn add_handle(&mut self, inode: u64, data: Vec<u8>, dirty: bool) -> u64 {
+        self.last_handle += 1;
+        self.handles
+            .insert(self.last_handle, Handle { inode, data, dirty });
+        self.last_handle
+    }
+
+    async fn open(&mut self, inode: u64, flags: u32) -> Reply {
+        let writing = flags as i32 & libc::O_ACCMODE != libc::O_RDONLY;
+        if writing && !self.read_write {
+            return Err(libc::EROFS);
+        }
+        let truncate = writing && flags as i32 & libc::O_TRUNC != 0;
+        let data = match truncate {
+            true => {
+                self.item_paths(inode)?;
+                Vec::new()
+            }
+            false => self.read_secret(inode).await?,
+        };
+        let fh = self.add_handle(inode, data, truncate);
+        Ok(open_out(fh, FOPEN_DIRECT_IO))
+    }
+
+    async fn create(&mut self, request: &Request<'_>) -> Reply {
+        if !self.read_write {
+            return Err(libc::EROFS);
+        }
+        let name = request.name_at(16);
+        if self.children(request.inode).await?.contains_key(&name) {
+            return Err(libc::EEXIST);
+        }
+        let Some(Node::Collection { path }) = self.nodes.get(&request.inode).cloned() else {
+            return Err(libc::EACCES);
+        };
+        let collection = self.collection(&path).await?;
+        let item = collection
+            .create_item(&name, HashMap::new(), b"", false, "text/plain")
+            .await
+            .map_err(errno)?;
+        let item_path = item.item_path.to_string();
+        let node = Node::Item {
+            collection: path,
+            path: item_path.clone(),
+        };
+        let inode = self.inode_of(&item_path, node);
+        let fh = self.add_handle(inode, Vec::new(), false);
+        let mut out = self.entry(inode)?;
+        out.extend_from_slice(&open_out(fh, FOPEN_DIRECT_IO));
+        Ok(out)
+    }
+
+    async fn unlink(&mut self, parent: u64, name: &str) -> Reply {
+        if !self.read_write {
+            return Err(libc::EROFS);
+        }
+        let inode = self.lookup(parent, name).await?;
+        let (collection, path) = self.item_paths(inode)?;
+        let collection = self.collection(&collection).await?;
+        for item in collection.get_all_items().await.map_err(errno)? {
+            if item.item_path.as_str() == path {
+                item.delete().await.map_err(errno)?;
+                return Ok(Vec::new());
+            }
+        }
+        Err(libc::ENOENT)
+    }
+
+    /// Only truncating is supported, the other changes get ignored
+    async fn setattr(&mut self, request: &Request<'_>) -> Reply {
+        let valid = request.u32_at(0);
+        if valid & FATTR_SIZE != 0 {
+            if !self.read_write {
+                return Err(libc::EROFS);
+            }
+            let size = request.u64_at(16) as usize;
+            let open = match valid & FATTR_FH != 0 {
+                true => self.handles.get_mut(&request.u64_at(8)),
+                false => None,
+            };
+            match open {
+                Some(handle) => {
+                    handle.data.resize(size, 0);
+                    handle.dirty = true;
+                }
+                None => {
+                    let mut secret = self.read_secret(request.inode).await?;
+                    secret.resize(size, 0);
+                    self.write_secret(request.inode, &secret).await?;
+                    secret.fill(0);
+                }
+            }
+        }
+        self.attr_out(request.inode)
+    }
+
+    async fn readdir(&mut self, request: &Request<'_>) -> Reply {
+        let offset = request.u64_at(8) as usize;
+        let size = request.u32_at(16) as usize;
+        let mut entries = vec![
+            (".".to_string(), request.inode),
+            ("..".to_string(), ROOT_INODE),
+        ];
+        entries.extend(self.children(request.inode).await?);
+        let mut out = Vec::new();
+        for (index, (name, inode)) in entries.iter().enumerate().skip(offset) {
+            let mut dirent = Vec::new();
+            let kind = match self.nodes.get(inode) {
+                Some(Node::Item { .. }) => libc::DT_REG,
+                _ => libc::DT_DIR,
+            };
+            dirent
+                .u64(*inode)
+                .u64(index as u64 + 1)
+                .u32(name.len() as u32)
+                .u32(kind as u32);
+            dirent.extend_from_slice(name.as_bytes());
+            dirent.resize(dirent.len().next_multiple_of(8), 0);
+            if out.len() + dirent.len() > size {
+                break;
+            }
+            out.extend_from_slice(&dirent);
+        }
+        Ok(out)
+    }
+
+    /// `fuse_attr`
+    fn attr(&self, inode: u64) -> std::result::Result<Vec<u8>, i32> {
+        let node = self.nodes.get(&inode).ok_or(libc::ENOENT)?;
+        let (mode, size) = match node {
+            Node::Item { .. } => {
+                let size = self
+                    .handles
+                    .values()
+                    .find(|h| h.inode == inode)
+                    .map_or(0, |h| h.data.len() as u64);
+                (
+                    libc::S_IFREG | if self.read_write { 0o600 } else { 0o400 },
+                    size,
+                )
+            }
+            _ => (
+                libc::S_IFDIR | if self.read_write { 0o700 } else { 0o500 },
+                0,
+            ),
+        };
+        let mut out = Vec::new();
+        out.u64(inode).u64(size).u64(size.div_ceil(512));
+        // access, modification and change times
+        (0..3).for_each(|_| {
+            out.u64(0);
+        });
+        (0..3).for_each(|_| {
+            out.u32(0);
+        });
+        out.u32(mode)
+            .u32(1)
+            .u32(self.uid)
+            .u32(self.gid)
+            .u32(0)
+            .u32(4096)
+            .u32(0);
+        Ok(out)
+    }
+
+    /// `fuse_attr_out`
+    fn attr_out(&self, inode: u64) -> Reply {
+        let mut out = Vec::new();
+        out.u64(TTL).u32(0).u32(0);
+        out.extend_from_slice(&self.attr(inode)?);
+        Ok(out)
+    }
+
+    /// `fuse_entry_out`
+    fn entry(&self, inode: u64) -> Reply {
+        let mut out = Vec::new();
+        out.u64(inode).u64(0).u64(TTL).u64(TTL).u32(0).u32(0);
+        out.extend_from_slice(&self.attr(inode)?);
+        Ok(out)
+    }
+
+    /// Wipes the secrets of the files left open
+    fn forget_secrets(&mut self) {
+        self.handles.values_mut().for_each(|h| h.data.fill(0));
+        self.handles.clear();
+    }
+}
+
+/// `fuse_init_out`, for the protocol 7.31
+fn init(request: &Request<'_>) -> Vec<u8> {
+    debug!("FUSE protocol {}.{}", request.u32_at(0), request.u32_at(4));
+    let mut out = Vec::new();
+    out.u32(7).u32(31).u32(request.u32_at(8)).u32(0);
+    // max_background and congestion_threshold
+    out.extend_from_slice(&16u16.to_ne_bytes());
+    out.extend_from_slice(&12u16.to_ne_bytes());
+    out.u32(MAX_WRITE as u32).u32(1);
+    // max_pages and map_alignment
+    out.extend_from_slice(&((MAX_WRITE / 4096) as u16).to_ne_bytes());
+    out.extend_from_slice(&0u16.to_ne_bytes());
+    (0..8).for_each(|_| {
+        out.u32(0);
+    });
+    out
+}
+
+/// `fuse_open_out`
+fn open_out(fh: u64, flags: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.u64(fh).u32(flags).u32(0);
+    out
+}
@@ -0,0 +1,193 @@
+//! Import the unencrypted JSON export of Bitwarden, see `Tools > Export vault` in its clients
+//!
+//! The logins keep their password as secret, the secure notes their notes and the cards their
+//! number; the identities hold no secret and get skipped. The hidden custom fields, the TOTP
+//! seeds and the card codes become items of their own.
+
+use crate::importer::{Entry, ImportTarget};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ImportBitwardenCmd {
+    /// Path to the JSON file exported by Bitwarden
+    pub json_file: PathBuf,
+
+    #[clap(flatten)]
+    pub target: ImportTarget,
+}
+
+const LOGIN: u32 = 1;
+const SECURE_NOTE: u32 = 2;
+const CARD: u32 = 3;
+
+const TEXT_FIELD: u32 = 0;
+const HIDDEN_FIELD: u32 = 1;
+const BOOLEAN_FIELD: u32 = 2;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    collections: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<Cipher>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Cipher {
+    #[serde(rename = "type")]
+    kind: u32,
+    name: String,
+    folder_id: Option<String>,
+    #[serde(default)]
+    collection_ids: Option<Vec<String>>,
+    notes: Option<String>,
+    #[serde(default)]
+    fields: Vec<Field>,
+    login: Option<Login>,
+    card: Option<Card>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<String>,
+    #[serde(rename = "type")]
+    kind: u32,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    #[serde(default)]
+    uris: Option<Vec<Uri>>,
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Card {
+    cardholder_name: Option<String>,
+    brand: Option<String>,
+    number: Option<String>,
+    exp_month: Option<String>,
+    exp_year: Option<String>,
+    code: Option<String>,
+}
+
+impl ImportBitwardenCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        info!(
+            "Importing Bitwarden data from file: {}",
+            self.json_file.display()
+        );
+        let json = fs::read_to_string(&self.json_file)
+            .with_context(|| format!("Error reading file '{}'", self.json_file.display()))?;
+        let export: Export = serde_json::from_str(&json)
+            .with_context(|| format!("Malformed file '{}'", self.json_file.display()))?;
+        if export.encrypted {
+            bail!("The export is encrypted, export the vault as unencrypted JSON instead");
+        }
+        let folders: HashMap<&str, &str> = export
+            .folders
+            .iter()
+            .chain(&export.collections)
+            .map(|f| (f.id.as_str(), f.name.as_str()))
+            .collect();
+        let mut entries = Vec::new();
+        for cipher in &export.items {
+            match entry(cipher, &folders) {
+                Some(entry) => entries.push(entry),
+                None => warn!("  Skipping '{}': it holds no secret", cipher.name),
+            }
+        }
+        self.target.import("com.bitwarden", entries).await
+    }
+}
+
+fn entry(cipher: &Cipher, folders: &HashMap<&str, &str>) -> Option<Entry> {
+    let notes = cipher.notes.clone().unwrap_or_default();
+    let mut entry = Entry {
+        label: cipher.name.clone(),
+        ..Default::default()
+    };
+    // organization items have collections instead of folders, Bitwarden nests them by name
+    let folder = cipher.folder_id.as_deref().or_else(|| {
+        cipher
+            .collection_ids
+            .as_ref()
+            .and_then(|ids| ids.first())
+            .map(|id| id.as_str())
+    });
+    if let Some(name) = folder.and_then(|id| folders.get(id)) {
+        entry.folder = name.split('/').map(String::from).collect();
+    }
+    match cipher.kind {
+        LOGIN => {
+            let login = cipher.login.as_ref()?;
+            entry.secret = login.password.clone().unwrap_or_default();
+            entry.attribute("username", login.username.as_deref().unwrap_or_default());
+            let uri = login.uris.iter().flatten().find_map(|u| u.uri.as_deref());
+            entry.attribute("url", uri.unwrap_or_default());
+            entry.attribute("notes", &notes);
+            if let Some(totp) = login.totp.as_ref().filter(|t| !t.is_empty()) {
+                entry.hidden_fields.push(("totp".to_string(), totp.clone()));
+            }
+        }
+        SECURE_NOTE => entry.secret = notes,
+        CARD => {
+            let card = cipher.card.as_ref()?;
+            entry.secret = card.number.clone().unwrap_or_default();
+            entry.attribute(
+                "cardholder",
+                card.cardholder_name.as_deref().unwrap_or_default(),
+            );
+            entry.attribute("brand", card.brand.as_deref().unwrap_or_default());
+            let expiry = match (&card.exp_month, &card.exp_year) {
+                (Some(month), Some(year)) => format!("{}/{}", month, year),
+                _ => String::new(),
+            };
+            entry.attribute("expiry", &expiry);
+            entry.attribute("notes", &notes);
+            if let Some(code) = card.code.as_ref().filter(|c| !c.is_empty()) {
+                entry.hidden_fields.push(("code".to_string(), code.clone()));
+            }
+        }
+        _ => return None,
+    }
+    for field in &cipher.fields {
+        let (Some(name), Some(value)) = (&field.name, &field.value) else {
+            continue;
+        };
+        match field.kind {
+            TEXT_FIELD | BOOLEAN_FIELD => entry.attribute(name, value),
+            HIDDEN_FIELD => entry.hidden_fields.push((name.clone(), value.clone())),
+            // the linked fields only point to the other fields
+            _ => {}
+        }
+    }
+    Some(entry)
+}
@@ -0,0 +1,563 @@
+//! Import the logins saved by the web browsers into a Secret Service collection
+//!
+//! Chromium and Chrome keep their logins in the `Login Data` SQLite database of the profile, the
+//! passwords being encrypted with a key derived from their `Safe Storage` Secret Service item, or
+//! from a built-in password when they ran without a Secret Service. Firefox keeps them in
+//! `logins.json`, encrypted with a key found in `key4.db`, itself encrypted with the primary
+//! password.
+//!
+//! The databases are opened read-only, so the browsers may keep running during the import.
+
+use crate::dbus_client::{capabilities, connect_secret_service, find_collection, Batch};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::{Parser, ValueEnum};
+use console::Term;
+use log::{debug, info, warn};
+use openssl::base64;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::symm::{decrypt, Cipher};
+use rusqlite::{Connection, OpenFlags};
+use secret_service::SecretService;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Browser {
+    Chromium,
+    Chrome,
+    Firefox,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportBrowserCmd {
+    /// Browser that saved the logins
+    #[clap(long, value_enum)]
+    pub from: Browser,
+
+    /// Profile directory of the browser, e.g. `~/.config/chromium/Default`; the default profile
+    /// by default
+    #[clap(long)]
+    pub profile: Option<PathBuf>,
+
+    /// Collection receiving the logins: an alias or a label
+    #[clap(long, default_value = "default")]
+    pub collection: String,
+
+    /// Replaces the items already having the same attributes, e.g. when importing again
+    #[clap(long, short = 'r')]
+    pub replace_existing_items: bool,
+}
+
+/// A login ready to become an item
+struct Login {
+    label: String,
+    attributes: Vec<(&'static str, String)>,
+    password: Vec<u8>,
+}
+
+impl ImportBrowserCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let profile = match &self.profile {
+            Some(profile) => profile.clone(),
+            None => self.from.default_profile()?,
+        };
+        info!(
+            "Importing the {:?} logins of {}",
+            self.from,
+            profile.display()
+        );
+        let ss = connect_secret_service().await?;
+        let (logins, failures) = match self.from {
+            Browser::Firefox => firefox_logins(&profile)?,
+            browser => chromium_logins(&ss, browser, &profile).await?,
+        };
+
+        let capabilities = capabilities()?;
+        let collection = find_collection(&ss, &self.collection).await?;
+        if collection.is_locked().await? {
+            collection
+                .unlock()
+                .await
+                .with_context(|| "Failed to unlock collection")?;
+        }
+        // keeps the other clients from writing to the collection meanwhile
+        let mut batch = match Batch::begin(collection.collection_path.as_str()) {
+            Ok(batch) => Some(batch),
+            Err(e) => {
+                warn!(
+                    "  other clients may change the collection during the import: {:#}",
+                    e
+                );
+                None
+            }
+        };
+
+        let mut imported = 0;
+        for login in &logins {
+            if let Err(e) = capabilities.check_secret(&login.password, false) {
+                warn!("  Skipping '{}': {}", login.label, e);
+                continue;
+            }
+            if let Some(batch) = batch.as_mut() {
+                batch.keep_alive()?;
+            }
+            let properties: HashMap<&str, &str> = login
+                .attributes
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect();
+            let item = collection
+                .create_item(
+                    &login.label,
+                    properties,
+                    &login.password,
+                    self.replace_existing_items,
+                    "text/plain",
+                )
+                .await
+                .with_context(|| format!("Failed to create item '{}'", login.label))?;
+            info!("  '{}' -> '{}'", login.label, item.item_path.as_str());
+            imported += 1;
+        }
+        info!(
+            "Imported {} of {} logins",
+            imported,
+            logins.len() + failures
+        );
+        Ok(())
+    }
+}
+
+impl Browser {
+    fn default_profile(&self) -> Result<PathBuf> {
+        let home = PathBuf::from(env::var("HOME").with_context(|| "HOME is not set")?);
+        match self {
+            Browser::Chromium => Ok(home.join(".config/chromium/Default")),
+            Browser::Chrome => Ok(home.join(".config/google-chrome/Default")),
+            Browser::Firefox => {
+                let mut profiles = Vec::new();
+                for dir in [
+                    home.join(".mozilla/firefox"),
+                    home.join(".config/mozilla/firefox"),
+                ] {
+                    let Ok(entries) = fs::read_dir(&dir) else {
+                        continue;
+                    };
+                    profiles.extend(
+                        entries
+                            .filter_map(|e| e.ok())
+                            .map(|e| e.path())
+                            .filter(|p| p.join("logins.json").is_file()),
+                    );
+                }
+                match profiles.as_slice() {
+                    [profile] => Ok(profile.clone()),
+                    [] => bail!("No Firefox profile having logins found, use --profile"),
+                    _ => bail!(
+                        "Several Firefox profiles have logins, choose one with --profile: {}",
+                        profiles
+                            .iter()
+                            .map(|p| p.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }
+            }
+        }
+    }
+
+    /// The `application` attribute of the browser's items
+    fn application(&self) -> &'static str {
+        match self {
+            Browser::Chromium => "chromium",
+            Browser::Chrome => "chrome",
+            Browser::Firefox => "firefox",
+        }
+    }
+}
+
+/// Opens a browser's database read-only, without waiting for the browser's lock
+fn open_database(path: &Path) -> Result<Connection> {
+    let path = path.to_string_lossy();
+    let uri = format!(
+        "file:{}?immutable=1",
+        path.replace('%', "%25")
+            .replace('?', "%3f")
+            .replace('#', "%23")
+    );
+    Connection::open_with_flags(
+        uri,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+    )
+    .with_context(|| format!("Cannot open '{}'", path))
+}
+
+/// The host part of an URL, naming the items
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split(['/', '?', '#']).next().unwrap_or(rest)
+}
+
+fn label(url: &str, username: &str) -> String {
+    match username {
+        "" => host(url).to_string(),
+        username => format!("{} ({})", host(url), username),
+    }
+}
+
+/// Derives a Chromium encryption key from a password
+fn chromium_key(password: &[u8]) -> Result<[u8; 16]> {
+    let mut key = [0; 16];
+    pbkdf2_hmac(password, b"saltysalt", 1, MessageDigest::sha1(), &mut key)?;
+    Ok(key)
+}
+
+/// The password of the browser's `Safe Storage` item, when it used a Secret Service
+async fn safe_storage_password(
+    ss: &SecretService<'_>,
+    browser: Browser,
+) -> Result<Option<Vec<u8>>> {
+    for schema in [
+        "chrome_libsecret_os_crypt_password_v2",
+        "chrome_libsecret_os_crypt_password",
+    ] {
+        let attributes = HashMap::from([
+            ("xdg:schema", schema),
+            ("application", browser.application()),
+        ]);
+        let result = ss.search_items(attributes).await?;
+        if let Some(item) = result.unlocked.into_iter().chain(result.locked).next() {
+            item.unlock().await?;
+            return Ok(Some(item.get_secret().await?));
+        }
+    }
+    Ok(None)
+}
+
+async fn chromium_logins(
+    ss: &SecretService<'_>,
+    browser: Browser,
+    profile: &Path,
+) -> Result<(Vec<Login>, usize)> {
+    // the v10 passwords got encrypted with a built-in password, the v11 ones with the password of
+    // the Safe Storage item
+    let v10_key = chromium_key(b"peanuts")?;
+    let v11_key = match safe_storage_password(ss, browser).await? {
+        Some(password) => Some(chromium_key(&password)?),
+        None => {
+            warn!("  no Safe Storage item found, only the v10 passwords can be decrypted");
+            None
+        }
+    };
+    let db = open_database(&profile.join("Login Data"))?;
+    let mut statement = db.prepare(
+        "SELECT origin_url, action_url, username_element, username_value, password_element, \
+         password_value, signon_realm, date_created FROM logins WHERE blacklisted_by_user = 0",
+    )?;
+    let rows = statement.query_map([], |row| {
+        Ok((
+            [
+                row.get::<_, String>(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ],
+            row.get::<_, Vec<u8>>(5)?,
+            row.get::<_, String>(6)?,
+            row.get::<_, i64>(7)?,
+        ))
+    })?;
+
+    let mut logins = Vec::new();
+    let mut failures = 0;
+    for row in rows {
+        let (fields, encrypted, signon_realm, date_created) = row?;
+        let [origin_url, action_url, username_element, username_value, password_element] = fields;
+        let label = label(&origin_url, &username_value);
+        let key = match encrypted.get(..3) {
+            Some(b"v10") => Some(&v10_key),
+            Some(b"v11") => v11_key.as_ref(),
+            _ => None,
+        };
+        let password = match key {
+            Some(key) => decrypt(
+                Cipher::aes_128_cbc(),
+                key,
+                Some(&[b' '; 16]),
+                &encrypted[3..],
+            ),
+            None => {
+                warn!("  Skipping '{}': cannot decrypt its password", label);
+                failures += 1;
+                continue;
+            }
+        };
+        let password = match password {
+            Ok(password) if !password.is_empty() => password,
+            Ok(_) => {
+                debug!("  Skipping '{}': it has no password", label);
+                failures += 1;
+                continue;
+            }
+            Err(e) => {
+                warn!("  Skipping '{}': cannot decrypt its password: {}", label, e);
+                failures += 1;
+                continue;
+            }
+        };
+        // the attributes of Chromium's libsecret password store, so the browser can find the
+        // logins again
+        let attributes = vec![
+            ("xdg:schema", "chrome_libsecret_password_schema".to_string()),
+            ("xdg:creator", format!("org.{}", browser.application())),
+            ("tks:path", format!("{:?}", browser)),
+            ("application", browser.application().to_string()),
+            ("origin_url", origin_url),
+            ("action_url", action_url),
+            ("username_element", username_element),
+            ("username_value", username_value),
+            ("password_element", password_element),
+            ("submit_element", String::new()),
+            ("signon_realm", signon_realm),
+            ("date_created", date_created.to_string()),
+            ("blacklisted_by_user", "0".to_string()),
+            ("scheme", "0".to_string()),
+        ];
+        logins.push(Login {
+            label,
+            attributes,
+            password,
+        });
+    }
+    Ok((logins, failures))
+}
+
+const SEQUENCE: u8 = 0x30;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const INTEGER: u8 = 0x02;
+
+/// 1.2.840.113549.1.5.13
+const PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+/// 1.2.840.113549.1.5.12
+const PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+/// 2.16.840.1.101.3.4.1.42
+const AES_256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+/// 1.2.840.113549.3.7
+const DES_EDE3_CBC: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x03, 0x07];
+
+/// The `CKA_ID` of the key encrypting the Firefox logins
+const LOGINS_KEY_ID: &[u8] = &[
+    0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+];
+
+/// Reads the DER encoded structures of NSS
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    /// Reads the value of the next element, which must have the given tag
+    fn read(&mut self, tag: u8) -> Result<&'a [u8]> {
+        let [found, length, rest @ ..] = self.0 else {
+            bail!("Truncated DER data");
+        };
+        ensure!(
+            *found == tag,
+            "Expected the DER tag {:#x}, found {:#x}",
+            tag,
+            found
+        );
+        let (length, rest) = match *length as usize {
+            length if length < 0x80 => (length, rest),
+            length => {
+                let bytes = length & 0x7f;
+                ensure!(bytes <= 4 && rest.len() >= bytes, "Malformed DER length");
+                let (length, rest) = rest.split_at(bytes);
+                (length.iter().fold(0, |l, b| l << 8 | *b as usize), rest)
+            }
+        };
+        ensure!(rest.len() >= length, "Truncated DER data");
+        let (value, rest) = rest.split_at(length);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    fn sequence(&mut self) -> Result<Der<'a>> {
+        Ok(Der(self.read(SEQUENCE)?))
+    }
+
+    fn integer(&mut self) -> Result<usize> {
+        let value = self.read(INTEGER)?;
+        ensure!(value.len() <= 4, "DER integer too large");
+        Ok(value.iter().fold(0, |i, b| i << 8 | *b as usize))
+    }
+}
+
+/// Decrypts the PBES2 encrypted data of `key4.db`
+fn decrypt_pbes2(data: &[u8], global_salt: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let mut der = Der(data).sequence()?;
+    let mut algorithm = der.sequence()?;
+    ensure!(
+        algorithm.read(OBJECT_IDENTIFIER)? == PBES2,
+        "Unsupported key4.db encryption, open the profile with a recent Firefox first"
+    );
+    let mut parameters = algorithm.sequence()?;
+    let mut kdf = parameters.sequence()?;
+    ensure!(
+        kdf.read(OBJECT_IDENTIFIER)? == PBKDF2,
+        "Unsupported key4.db key derivation"
+    );
+    // the pseudo-random function which follows is always HMAC-SHA256
+    let mut kdf = kdf.sequence()?;
+    let salt = kdf.read(OCTET_STRING)?;
+    let iterations = kdf.integer()?;
+    let mut key = vec![0; kdf.integer()?];
+    let mut cipher = parameters.sequence()?;
+    ensure!(
+        cipher.read(OBJECT_IDENTIFIER)? == AES_256_CBC,
+        "Unsupported key4.db cipher"
+    );
+    let iv = cipher.read(OCTET_STRING)?;
+    let encrypted = der.read(OCTET_STRING)?;
+
+    let password = hash(MessageDigest::sha1(), &[global_salt, password].concat())?;
+    pbkdf2_hmac(
+        &password,
+        salt,
+        iterations,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    // NSS drops the header of the IV's OCTET STRING
+    let iv = match iv.len() {
+        14 => [&[OCTET_STRING, 14], iv].concat(),
+        _ => iv.to_vec(),
+    };
+    Ok(decrypt(Cipher::aes_256_cbc(), &key, Some(&iv), encrypted)?)
+}
+
+/// The key encrypting the Firefox logins, asking for the primary password when one is set
+fn firefox_key(profile: &Path) -> Result<Vec<u8>> {
+    let db = open_database(&profile.join("key4.db"))?;
+    let (global_salt, check): (Vec<u8>, Vec<u8>) = db
+        .query_row(
+            "SELECT item1, item2 FROM metaData WHERE id = 'password'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .with_context(|| "No password check in key4.db")?;
+    let checks = |password: &[u8]| {
+        decrypt_pbes2(&check, &global_salt, password)
+            .map(|check| check.starts_with(b"password-check"))
+    };
+    let mut password = String::new();
+    if !checks(b"").unwrap_or(false) {
+        let term = Term::stderr();
+        term.write_str("Firefox primary password: ")?;
+        password = term.read_secure_line()?;
+        ensure!(
+            checks(password.as_bytes()).unwrap_or(false),
+            "Wrong Firefox primary password"
+        );
+    }
+
+    let mut statement = db.prepare("SELECT a11, a102 FROM nssPrivate")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        if row.get::<_, Vec<u8>>(1)? == LOGINS_KEY_ID {
+            return decrypt_pbes2(
+                &row.get::<_, Vec<u8>>(0)?,
+                &global_salt,
+                password.as_bytes(),
+            );
+        }
+    }
+    bail!("No logins key in key4.db")
+}
+
+/// Decrypts a base64 encoded field of `logins.json`
+fn decrypt_login_field(key: &[u8], field: &str) -> Result<Vec<u8>> {
+    let data = base64::decode_block(field)?;
+    let mut der = Der(&data).sequence()?;
+    der.read(OCTET_STRING)?;
+    let mut algorithm = der.sequence()?;
+    let oid = algorithm.read(OBJECT_IDENTIFIER)?;
+    let iv = algorithm.read(OCTET_STRING)?;
+    let encrypted = der.read(OCTET_STRING)?;
+    let (cipher, key) = match oid {
+        DES_EDE3_CBC => (Cipher::des_ede3_cbc(), key.get(..24)),
+        AES_256_CBC => (Cipher::aes_256_cbc(), key.get(..32)),
+        _ => bail!("Unsupported login cipher"),
+    };
+    let key = key.ok_or_else(|| anyhow!("The logins key is too short"))?;
+    Ok(decrypt(cipher, key, Some(iv), encrypted)?)
+}
+
+#[derive(Deserialize)]
+struct FirefoxLogins {
+    logins: Vec<FirefoxLogin>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirefoxLogin {
+    hostname: String,
+    http_realm: Option<String>,
+    #[serde(rename = "formSubmitURL")]
+    form_submit_url: Option<String>,
+    username_field: String,
+    password_field: String,
+    encrypted_username: String,
+    encrypted_password: String,
+}
+
+fn firefox_logins(profile: &Path) -> Result<(Vec<Login>, usize)> {
+    let path = profile.join("logins.json");
+    let json = fs::read_to_string(&path)
+        .with_context(|| format!("Error reading file '{}'", path.display()))?;
+    let saved: FirefoxLogins = serde_json::from_str(&json)
+        .with_context(|| format!("Malformed file '{}'", path.display()))?;
+    let key = firefox_key(profile)?;
+
+    let mut logins = Vec::new();
+    let mut failures = 0;
+    for login in saved.logins {
+        let decrypted = decrypt_login_field(&key, &login.encrypted_username).and_then(|username| {
+            let password = decrypt_login_field(&key, &login.encrypted_password)?;
+            Ok((String::from_utf8(username)?, password))
+        });
+        let (username, password) = match decrypted {
+            Ok(decrypted) => decrypted,
+            Err(e) => {
+                warn!("  Skipping a login of {}: {:#}", login.hostname, e);
+                failures += 1;
+                continue;
+            }
+        };
+        let mut attributes = vec![
+            ("xdg:schema", "io.linux_tks.FirefoxLogin".to_string()),
+            ("xdg:creator", "org.mozilla.firefox".to_string()),
+            ("tks:path", "Firefox".to_string()),
+            ("url", login.hostname.clone()),
+            ("username", username.clone()),
+            ("username-field", login.username_field),
+            ("password-field", login.password_field),
+        ];
+        if let Some(url) = login.form_submit_url.filter(|u| !u.is_empty()) {
+            attributes.push(("form-submit-url", url));
+        }
+        if let Some(realm) = login.http_realm {
+            attributes.push(("http-realm", realm));
+        }
+        logins.push(Login {
+            label: label(&login.hostname, &username),
+            attributes,
+            password,
+        });
+    }
+    Ok((logins, failures))
+}
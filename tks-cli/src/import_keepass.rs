@@ -0,0 +1,536 @@
+//! Import a KeePass database, as saved by KeePass 2 or KeePassXC: the KDBX 3.1 and 4 formats,
+//! encrypted with AES-256 or ChaCha20, their key derived with AES-KDF or Argon2.
+//!
+//! The groups become the folders of the entries, leaving out the recycle bin and the history of
+//! the entries. The entries keep their password as secret, or their notes when they have none.
+//! Their protected custom strings, like the KeePassXC one-time password seeds, become items of
+//! their own, while the other ones become attributes.
+
+use crate::importer::{Entry, ImportTarget};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::Parser;
+use console::Term;
+use flate2::read::GzDecoder;
+use log::info;
+use openssl::base64;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt, Cipher, Crypter, Mode};
+use roxmltree::{Document, Node, NodeId};
+use salsa20::cipher::{KeyIvInit, StreamCipher};
+use salsa20::Salsa20;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+pub struct ImportKeepassCmd {
+    /// Path to the KeePass database
+    pub kdbx_file: PathBuf,
+
+    /// Key file protecting the database, along with its password
+    #[clap(long)]
+    pub key_file: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub target: ImportTarget,
+}
+
+const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+
+const AES256_CIPHER: [u8; 16] = uuid(0x31c1f2e6bf714350be5805216afc5aff);
+const CHACHA20_CIPHER: [u8; 16] = uuid(0xd6038a2b8b6f4cb5a524339a31dbb59a);
+
+const AES_KDF: [u8; 16] = uuid(0xc9d9f39a628a4460bf740d08c18a4fea);
+const AES_KDF_KDBX4: [u8; 16] = uuid(0x7c02bb8279a74ac0927d114a00648238);
+const ARGON2D_KDF: [u8; 16] = uuid(0xef636ddf8c29444b91f7a9a403e30a0c);
+const ARGON2ID_KDF: [u8; 16] = uuid(0x9e298b1956db4773b23dfc3ec6f0a1e6);
+
+// the outer header fields
+const END_OF_HEADER: u8 = 0;
+const CIPHER_ID: u8 = 2;
+const COMPRESSION_FLAGS: u8 = 3;
+const MASTER_SEED: u8 = 4;
+const TRANSFORM_SEED: u8 = 5;
+const TRANSFORM_ROUNDS: u8 = 6;
+const ENCRYPTION_IV: u8 = 7;
+const PROTECTED_STREAM_KEY: u8 = 8;
+const STREAM_START_BYTES: u8 = 9;
+const INNER_RANDOM_STREAM_ID: u8 = 10;
+const KDF_PARAMETERS: u8 = 11;
+
+// the inner header fields of KDBX 4
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+
+const SALSA20_STREAM: u32 = 2;
+const CHACHA20_STREAM: u32 = 3;
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+const fn uuid(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+impl ImportKeepassCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        info!(
+            "Importing KeePass data from file: {}",
+            self.kdbx_file.display()
+        );
+        let data = fs::read(&self.kdbx_file)
+            .with_context(|| format!("Error reading file '{}'", self.kdbx_file.display()))?;
+        let term = Term::stderr();
+        term.write_str("KeePass database password: ")?;
+        let password = term.read_secure_line()?;
+        let key = composite_key(&password, self.key_file.as_deref())?;
+        let (xml, stream) = open_database(&data, &key)?;
+        let entries = entries(&xml, stream)?;
+        self.target.import("org.keepassxc.KeePassXC", entries).await
+    }
+}
+
+/// Reads the little-endian values of the database
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        ensure!(self.0.len() >= length, "Truncated KeePass database");
+        let (value, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+/// A little-endian integer of any length up to 8 bytes
+fn integer(value: &[u8]) -> u64 {
+    value.iter().rev().fold(0, |i, b| i << 8 | *b as u64)
+}
+
+fn sha256(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(hash(MessageDigest::sha256(), data)?.to_vec())
+}
+
+fn sha512(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(hash(MessageDigest::sha512(), data)?.to_vec())
+}
+
+fn hex(text: &str) -> Result<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            ensure!(pair.len() == 2, "Malformed hexadecimal data");
+            Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?)
+        })
+        .collect()
+}
+
+/// The key of the database, made of its password and key file
+fn composite_key(password: &str, key_file: Option<&Path>) -> Result<Vec<u8>> {
+    let mut key = Vec::new();
+    if !password.is_empty() || key_file.is_none() {
+        key.extend(sha256(password.as_bytes())?);
+    }
+    if let Some(path) = key_file {
+        let data =
+            fs::read(path).with_context(|| format!("Error reading file '{}'", path.display()))?;
+        key.extend(key_file_hash(&data)?);
+    }
+    sha256(&key)
+}
+
+fn key_file_hash(data: &[u8]) -> Result<Vec<u8>> {
+    let xml = std::str::from_utf8(data).ok();
+    if let Some(doc) = xml.and_then(|xml| Document::parse(xml).ok()) {
+        let text = |name| {
+            doc.descendants()
+                .find(|n| n.has_tag_name(name))
+                .and_then(|n| n.text())
+        };
+        if let Some(key) = text("Data") {
+            let key: String = key.split_whitespace().collect();
+            return match text("Version") {
+                Some(version) if version.starts_with("2.") => hex(&key),
+                _ => Ok(base64::decode_block(&key)?),
+            };
+        }
+    }
+    match data.len() {
+        32 => Ok(data.to_vec()),
+        64 => match xml.map(hex) {
+            Some(Ok(key)) => Ok(key),
+            _ => sha256(data),
+        },
+        _ => sha256(data),
+    }
+}
+
+struct Header {
+    major_version: u16,
+    fields: HashMap<u8, Vec<u8>>,
+    /// How many bytes the header takes
+    length: usize,
+}
+
+impl Header {
+    fn read(data: &[u8]) -> Result<Header> {
+        let mut reader = Reader(data);
+        ensure!(reader.take(8)? == SIGNATURE, "Not a KeePass 2 database");
+        reader.u16()?;
+        let major_version = reader.u16()?;
+        ensure!(
+            major_version == 3 || major_version == 4,
+            "Unsupported KDBX version {}",
+            major_version
+        );
+        let mut fields = HashMap::new();
+        loop {
+            let id = reader.u8()?;
+            let length = match major_version {
+                3 => reader.u16()? as usize,
+                _ => reader.u32()? as usize,
+            };
+            let value = reader.take(length)?;
+            if id == END_OF_HEADER {
+                break;
+            }
+            fields.insert(id, value.to_vec());
+        }
+        Ok(Header {
+            major_version,
+            fields,
+            length: data.len() - reader.0.len(),
+        })
+    }
+
+    fn field(&self, id: u8) -> Result<&[u8]> {
+        self.fields
+            .get(&id)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("The KeePass database lacks the header field {}", id))
+    }
+
+    /// The parameters of the key derivation function
+    fn kdf_parameters(&self) -> Result<HashMap<String, Vec<u8>>> {
+        if self.major_version == 3 {
+            return Ok(HashMap::from([
+                ("$UUID".to_string(), AES_KDF.to_vec()),
+                ("S".to_string(), self.field(TRANSFORM_SEED)?.to_vec()),
+                ("R".to_string(), self.field(TRANSFORM_ROUNDS)?.to_vec()),
+            ]));
+        }
+        // a VariantDictionary: a version, then typed entries up to a null type
+        let mut reader = Reader(self.field(KDF_PARAMETERS)?);
+        reader.u16()?;
+        let mut parameters = HashMap::new();
+        while reader.u8()? != 0 {
+            let length = reader.u32()? as usize;
+            let name = String::from_utf8_lossy(reader.take(length)?).into_owned();
+            let length = reader.u32()? as usize;
+            parameters.insert(name, reader.take(length)?.to_vec());
+        }
+        Ok(parameters)
+    }
+
+    /// Derives the key from the composite key
+    fn transform_key(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let parameters = self.kdf_parameters()?;
+        let parameter = |name: &str| {
+            parameters
+                .get(name)
+                .map(Vec::as_slice)
+                .ok_or_else(|| anyhow!("The KeePass database lacks the KDF parameter {}", name))
+        };
+        let kdf: [u8; 16] = parameter("$UUID")?.try_into()?;
+        match kdf {
+            AES_KDF | AES_KDF_KDBX4 => {
+                let mut crypter =
+                    Crypter::new(Cipher::aes_256_ecb(), Mode::Encrypt, parameter("S")?, None)?;
+                crypter.pad(false);
+                let mut key = key.to_vec();
+                let mut encrypted = vec![0; key.len() + 16];
+                for _ in 0..integer(parameter("R")?) {
+                    crypter.update(&key, &mut encrypted)?;
+                    let length = key.len();
+                    key.copy_from_slice(&encrypted[..length]);
+                }
+                sha256(&key)
+            }
+            ARGON2D_KDF | ARGON2ID_KDF => {
+                let config = argon2::Config {
+                    variant: match kdf {
+                        ARGON2D_KDF => argon2::Variant::Argon2d,
+                        _ => argon2::Variant::Argon2id,
+                    },
+                    version: argon2::Version::from_u32(integer(parameter("V")?) as u32)?,
+                    mem_cost: (integer(parameter("M")?) / 1024) as u32,
+                    time_cost: integer(parameter("I")?) as u32,
+                    lanes: integer(parameter("P")?) as u32,
+                    secret: parameters.get("K").map_or(&[], Vec::as_slice),
+                    ad: parameters.get("A").map_or(&[], Vec::as_slice),
+                    hash_length: 32,
+                };
+                Ok(argon2::hash_raw(key, parameter("S")?, &config)?)
+            }
+            _ => bail!("Unsupported key derivation function"),
+        }
+    }
+
+    /// Decrypts the payload of the database
+    fn decrypt(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let iv = self.field(ENCRYPTION_IV)?;
+        let (cipher, iv) = match self.field(CIPHER_ID)? {
+            id if id == AES256_CIPHER => (Cipher::aes_256_cbc(), iv.to_vec()),
+            // OpenSSL takes a 32 bits block counter before the nonce
+            id if id == CHACHA20_CIPHER => (Cipher::chacha20(), [&[0; 4], iv].concat()),
+            _ => bail!("Unsupported cipher, only AES-256 and ChaCha20 are"),
+        };
+        decrypt(cipher, key, Some(&iv), data).map_err(|_| anyhow!("Wrong password or key file"))
+    }
+}
+
+/// The protected values of the XML document are encrypted with a stream cipher, in the order
+/// they appear in the document
+enum InnerStream {
+    Salsa20(Box<Salsa20>),
+    ChaCha20(Crypter),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<InnerStream> {
+        match id {
+            SALSA20_STREAM => {
+                let key = sha256(key)?;
+                let cipher = Salsa20::new(key.as_slice().into(), &SALSA20_NONCE.into());
+                Ok(InnerStream::Salsa20(Box::new(cipher)))
+            }
+            CHACHA20_STREAM => {
+                let key = sha512(key)?;
+                let iv = [&[0; 4], &key[32..44]].concat();
+                let crypter =
+                    Crypter::new(Cipher::chacha20(), Mode::Decrypt, &key[..32], Some(&iv))?;
+                Ok(InnerStream::ChaCha20(crypter))
+            }
+            _ => bail!("Unsupported protected values encryption {}", id),
+        }
+    }
+
+    fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            InnerStream::Salsa20(cipher) => {
+                let mut data = data.to_vec();
+                cipher.apply_keystream(&mut data);
+                Ok(data)
+            }
+            InnerStream::ChaCha20(crypter) => {
+                let mut decrypted = vec![0; data.len() + 1];
+                let length = crypter.update(data, &mut decrypted)?;
+                decrypted.truncate(length);
+                Ok(decrypted)
+            }
+        }
+    }
+}
+
+/// The HMAC-SHA256 of a block of KDBX 4 databases, the header being the block `u64::MAX`
+fn block_hmac(key: &[u8], index: u64, data: &[&[u8]]) -> Result<Vec<u8>> {
+    let key = sha512(&[&index.to_le_bytes(), key].concat())?;
+    let key = PKey::hmac(&key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    for data in data {
+        signer.update(data)?;
+    }
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Decrypts a database, giving its XML document and the stream decrypting its protected values
+fn open_database(data: &[u8], key: &[u8]) -> Result<(String, InnerStream)> {
+    let header = Header::read(data)?;
+    let transformed = header.transform_key(key)?;
+    let master_seed = header.field(MASTER_SEED)?;
+    let key = sha256(&[master_seed, &transformed].concat())?;
+    let mut reader = Reader(&data[header.length..]);
+
+    let payload = if header.major_version == 3 {
+        let decrypted = header.decrypt(&key, reader.0)?;
+        let start = header.field(STREAM_START_BYTES)?;
+        ensure!(decrypted.starts_with(start), "Wrong password or key file");
+        // the hashed blocks: an index, the SHA-256 of the data, its length then the data
+        let mut reader = Reader(&decrypted[start.len()..]);
+        let mut payload = Vec::new();
+        loop {
+            reader.u32()?;
+            let hash = reader.take(32)?;
+            let length = reader.u32()? as usize;
+            if length == 0 {
+                break;
+            }
+            let block = reader.take(length)?;
+            ensure!(sha256(block)? == hash, "Corrupted KeePass database");
+            payload.extend_from_slice(block);
+        }
+        payload
+    } else {
+        let header_data = &data[..header.length];
+        ensure!(
+            sha256(header_data)? == reader.take(32)?,
+            "Corrupted KeePass database header"
+        );
+        let hmac_key = sha512(&[master_seed, &transformed, &[1]].concat())?;
+        ensure!(
+            block_hmac(&hmac_key, u64::MAX, &[header_data])? == reader.take(32)?,
+            "Wrong password or key file"
+        );
+        // the HMAC blocks: the HMAC of the block, the length of the data then the data
+        let mut encrypted = Vec::new();
+        for index in 0.. {
+            let hmac = reader.take(32)?;
+            let length = reader.take(4)?;
+            let block = reader.take(integer(length) as usize)?;
+            ensure!(
+                block_hmac(&hmac_key, index, &[&index.to_le_bytes(), length, block])? == hmac,
+                "Corrupted KeePass database"
+            );
+            if block.is_empty() {
+                break;
+            }
+            encrypted.extend_from_slice(block);
+        }
+        header.decrypt(&key, &encrypted)?
+    };
+
+    let payload = match integer(header.field(COMPRESSION_FLAGS)?) {
+        0 => payload,
+        _ => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(payload.as_slice())
+                .read_to_end(&mut decompressed)
+                .with_context(|| "Corrupted KeePass database")?;
+            decompressed
+        }
+    };
+
+    let (stream, xml) = if header.major_version == 3 {
+        let id = integer(header.field(INNER_RANDOM_STREAM_ID)?) as u32;
+        let stream = InnerStream::new(id, header.field(PROTECTED_STREAM_KEY)?)?;
+        (stream, payload.as_slice())
+    } else {
+        let mut reader = Reader(&payload);
+        let (mut id, mut key) = (0, &[][..]);
+        loop {
+            let field = reader.u8()?;
+            let length = reader.u32()? as usize;
+            let value = reader.take(length)?;
+            match field {
+                END_OF_HEADER => break,
+                INNER_STREAM_ID => id = integer(value) as u32,
+                INNER_STREAM_KEY => key = value,
+                // the attachments
+                _ => {}
+            }
+        }
+        (InnerStream::new(id, key)?, reader.0)
+    };
+    Ok((String::from_utf8_lossy(xml).into_owned(), stream))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+}
+
+/// The entries of the XML document
+fn entries(xml: &str, mut stream: InnerStream) -> Result<Vec<Entry>> {
+    let doc = Document::parse(xml).with_context(|| "Malformed KeePass database")?;
+    let mut protected = HashMap::new();
+    for value in doc.descendants().filter(|n| n.has_tag_name("Value")) {
+        if value.attribute("Protected") == Some("True") {
+            let encrypted = base64::decode_block(value.text().unwrap_or_default())?;
+            let decrypted = stream.decrypt(&encrypted)?;
+            protected.insert(value.id(), String::from_utf8_lossy(&decrypted).into_owned());
+        }
+    }
+    let recycle_bin = doc
+        .descendants()
+        .find(|n| n.has_tag_name("Meta"))
+        .and_then(|meta| child_text(meta, "RecycleBinUUID"));
+    let root = doc
+        .descendants()
+        .find(|n| n.has_tag_name("Root"))
+        .and_then(|root| root.children().find(|n| n.has_tag_name("Group")))
+        .ok_or_else(|| anyhow!("The KeePass database has no root group"))?;
+
+    let mut entries = Vec::new();
+    add_entries(root, &mut Vec::new(), recycle_bin, &protected, &mut entries);
+    Ok(entries)
+}
+
+/// Adds the entries of a group and of its subgroups, except the recycle bin
+fn add_entries(
+    group: Node,
+    folder: &mut Vec<String>,
+    recycle_bin: Option<&str>,
+    protected: &HashMap<NodeId, String>,
+    entries: &mut Vec<Entry>,
+) {
+    for child in group.children() {
+        if child.has_tag_name("Entry") {
+            entries.push(entry(child, folder, protected));
+        } else if child.has_tag_name("Group") && child_text(child, "UUID") != recycle_bin {
+            folder.push(child_text(child, "Name").unwrap_or_default().to_string());
+            add_entries(child, folder, recycle_bin, protected, entries);
+            folder.pop();
+        }
+    }
+}
+
+fn entry(node: Node, folder: &[String], protected: &HashMap<NodeId, String>) -> Entry {
+    let mut entry = Entry {
+        folder: folder.to_vec(),
+        ..Default::default()
+    };
+    let mut notes = String::new();
+    for string in node.children().filter(|n| n.has_tag_name("String")) {
+        let Some(key) = child_text(string, "Key") else {
+            continue;
+        };
+        let Some(value) = string.children().find(|n| n.has_tag_name("Value")) else {
+            continue;
+        };
+        let (text, is_protected) = match protected.get(&value.id()) {
+            Some(text) => (text.clone(), true),
+            None => (value.text().unwrap_or_default().to_string(), false),
+        };
+        match key {
+            "Title" => entry.label = text,
+            "Password" => entry.secret = text,
+            "UserName" => entry.attribute("username", &text),
+            "URL" => entry.attribute("url", &text),
+            "Notes" => notes = text,
+            _ if is_protected => entry.hidden_fields.push((key.to_string(), text)),
+            _ => entry.attribute(key, &text),
+        }
+    }
+    if entry.label.is_empty() {
+        entry.label = "Untitled".to_string();
+    }
+    match entry.secret.is_empty() {
+        true => entry.secret = notes,
+        false => entry.attribute("notes", &notes),
+    }
+    entry
+}
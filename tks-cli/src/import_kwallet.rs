@@ -1,24 +1,42 @@
 //! Import KWallet data into Secret Service collections
 //! NOTE: should this tool become a KWallet to Secret Service conversion tool on its own?
 //!
-//! This uses an XML file previously created by the KWalletManager's `export to XML` function.
+//! This uses either an XML file previously created by the KWalletManager's `export to XML`
+//! function, or, with `--live`, the wallet read from the running KWallet daemon over DBus.
 
-use anyhow::{anyhow, Context, Result};
+use crate::dbus_client::{capabilities, connect, Batch};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use clap::Parser;
+use dbus::blocking::{Connection, Proxy};
 use log::{debug, info, warn};
-use roxmltree::NodeType;
 use roxmltree::NodeType::Element;
 use secret_service::{Collection, EncryptionType, SecretService};
-use std::collections::HashMap;
-use std::error::Error;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::process::Command;
+use std::time::Duration;
+
+const KWALLET_INTERFACE: &str = "org.kde.KWallet";
+const APP_ID: &str = "tks-cli";
+/// Opening a wallet may wait for the user to type its password
+const OPEN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The KWallet entry types, see `KWallet::Wallet::EntryType`
+const PASSWORD_ENTRY: i32 = 1;
+const MAP_ENTRY: i32 = 3;
+
+/// The content type of the map entries, whose secret is the JSON object of the map
+pub(crate) const MAP_CONTENT_TYPE: &str = "application/x-kde-wallet-map";
+/// The map entries have an attribute named after each of their keys, with an empty value, so that
+/// the maps having a given key can be searched
+pub(crate) const MAP_KEY_ATTRIBUTE: &str = "tks:kwallet-map-key:";
 
 #[derive(Parser, Debug)]
 #[clap(verbatim_doc_comment)]
 pub struct ImportKwalletCmd {
-    #[clap(verbatim_doc_comment)]
+    #[clap(required_unless_present = "live", verbatim_doc_comment)]
     /// Path to the KWalletManager's exported file
-    pub xml_file: String,
+    pub xml_file: Option<String>,
 
     #[clap(long, short = 'd', default_value = "true", verbatim_doc_comment)]
     /// Imports all the wallet's contents into the `default` collection
@@ -32,27 +50,94 @@ pub struct ImportKwalletCmd {
     /// This is useful when re-attempting a in the middle stopped import and we need to avoid
     /// duplicate errors
     pub replace_existing_items: bool,
+
+    #[clap(long, conflicts_with = "xml_file", verbatim_doc_comment)]
+    /// Reads the wallet from the running KWallet daemon instead of an exported file; KWallet may
+    /// ask for the wallet's password
+    pub live: bool,
+
+    #[clap(long, requires = "live", verbatim_doc_comment)]
+    /// Wallet to read with `--live`, the local wallet by default
+    pub wallet: Option<String>,
+
+    #[clap(long, verbatim_doc_comment)]
+    /// Turns off the Secret Service interface of KWallet once the import succeeded, so that it
+    /// leaves the `org.freedesktop.secrets` name to TKS from the next session on
+    pub disable_kwallet_secret_service: bool,
+}
+
+/// A wallet entry to import
+struct WalletEntry {
+    folder: String,
+    name: String,
+    /// `password` or `map`
+    entry_type: &'static str,
+    secret: String,
+    content_type: &'static str,
+    /// The keys of the map entries
+    keys: Vec<String>,
+}
+
+impl WalletEntry {
+    fn password(folder: &str, name: &str, password: String) -> WalletEntry {
+        WalletEntry {
+            folder: folder.to_string(),
+            name: name.to_string(),
+            entry_type: "password",
+            secret: password,
+            content_type: "text/plain",
+            keys: Vec::new(),
+        }
+    }
+
+    fn map(folder: &str, name: &str, map: &BTreeMap<String, String>) -> Result<WalletEntry> {
+        Ok(WalletEntry {
+            folder: folder.to_string(),
+            name: name.to_string(),
+            entry_type: "map",
+            secret: serde_json::to_string(map)?,
+            content_type: MAP_CONTENT_TYPE,
+            keys: map
+                .keys()
+                .map(|key| format!("{}{}", MAP_KEY_ATTRIBUTE, key))
+                .collect(),
+        })
+    }
 }
 
 impl ImportKwalletCmd {
     pub(crate) async fn run(&self) -> Result<()> {
-        info!("Importing kwallet data from file: {}", self.xml_file);
+        let entries = match &self.xml_file {
+            Some(xml_file) => {
+                info!("Importing kwallet data from file: {}", xml_file);
+                let xml_string = fs::read_to_string(xml_file)
+                    .with_context(|| format!("Error reading file '{}'", xml_file))?;
+                xml_entries(&xml_string)?
+            }
+            None => {
+                info!("Importing kwallet data from the KWallet daemon");
+                live_entries(self.wallet.as_deref())?
+            }
+        };
         if self.to_default_collection {
             info!("  target the default collection");
-        } else {
-            if let Some(collection) = self.collection_name.as_ref() {
-                info!("  target the collection: {}", collection);
-            }
+        } else if let Some(collection) = self.collection_name.as_ref() {
+            info!("  target the collection: {}", collection);
         }
-        let xml_string = fs::read_to_string(&self.xml_file)
-            .with_context(|| format!("Error reading file '{}'", self.xml_file))?;
 
+        let capabilities = capabilities()?;
         let ss = SecretService::connect(EncryptionType::Dh)
             .await
-            .unwrap_or_else(|err| {
-                panic!("  Failed to connect to secret service. Is the TKS service running?");
-            });
-        let collection = if self.to_default_collection {
+            .with_context(|| "Failed to connect to secret service. Is the TKS service running?")?;
+        let to_default_collection = self.to_default_collection
+            || !capabilities.multiple_collections;
+        if !self.to_default_collection && to_default_collection {
+            warn!(
+                "  the {} storage backend only has the default collection, importing into it",
+                capabilities.backend
+            );
+        }
+        let collection = if to_default_collection {
             ss.get_default_collection()
                 .await
                 .with_context(|| "Failed to get default collection")?
@@ -73,7 +158,23 @@ impl ImportKwalletCmd {
                     false => continue,
                 }
             }
-            coll.ok_or_else(|| anyhow!("No collection named '{}' found", collection_name))?
+            match coll {
+                Some(c) => c,
+                None if capabilities.create_collection => {
+                    info!("  creating the collection: {}", collection_name);
+                    ss.create_collection(collection_name, "")
+                        .await
+                        .with_context(|| format!("Failed to create '{}'", collection_name))?
+                }
+                None => {
+                    return Err(anyhow!(
+                        "No collection named '{}' found, and the {} storage backend can't create \
+                         collections; import into the default collection instead",
+                        collection_name,
+                        capabilities.backend
+                    ))
+                }
+            }
         };
 
         if collection
@@ -87,74 +188,237 @@ impl ImportKwalletCmd {
                 .with_context(|| "Failed to unlock collection")?;
         }
 
-        let xml = roxmltree::Document::parse(&xml_string).expect("Import failed");
-        if let Some(wallet) = xml.descendants().find(|n| n.tag_name().name() == "wallet") {
-            for f in wallet.children().filter(|n| n.node_type() == Element) {
-                let current_folder = f
-                    .attribute("name")
-                    .ok_or_else(|| anyhow!("Missing name in wallet attribute"))?;
-                info!("  processing folder '{}'", current_folder);
-                for e in f.children().filter(|n| n.node_type() == Element) {
-                    debug!("  entry: {:?}", e);
-
-                    let label = e.attribute("name").ok_or_else(|| anyhow!("Missing name"))?;
-                    let item_type = e.tag_name().name();
-                    match item_type {
-                        "map" => {
-                            // NOTE: at the time of writing this importer, it is not clear for me
-                            // how maps should be represented into the Secret Service in such a way
-                            // the client applications seamlessly find the same settings in SS
-                            // instead of KWallet
-                            info!("    Ignoring map entry {}/{}", current_folder, label);
-                        }
-                        "password" => {
-                            let mut properties = HashMap::new();
-                            properties.insert("tks:kwallet-folder", current_folder);
-                            properties.insert("tks:kwallet-entry-type", item_type);
-                            properties.insert("xdg:schema", "org.freedesktop.Secret.Generic");
-                            properties.insert("xdg:creator", "org.kde.KWallet");
-                            if let Some(secret_text) = e.text() {
-                                let secret: &[u8] = secret_text.as_bytes();
-                                // existing items will be updated in the secret service
-                                let p = collection
-                                    .create_item(
-                                        label,
-                                        properties,
-                                        secret,
-                                        self.replace_existing_items,
-                                        "text/plain",
-                                    )
-                                    .await
-                                    .with_context(|| {
-                                        format!("Failed to create item '{}'", label)
-                                    })?;
-                                match p.item_path.to_string() == "/" {
-                                    true => {
-                                        warn!("The Secret Service (maybe TKS) returned a prompt instead of creating item {}", label);
-                                    }
-                                    false => {
-                                        info!(
-                                            "  '{}/{}' -> '{}'",
-                                            current_folder,
-                                            label,
-                                            p.item_path.to_string()
-                                        );
-                                    }
-                                }
-                            } else {
-                                info!(
-                                    "  '{}/{}' -> 'None' (as it was empty)",
-                                    current_folder, label
-                                );
-                            }
-                        }
-                        _ => {}
-                    }
+        // keeps the other clients from writing to the collection meanwhile
+        let mut batch = match Batch::begin(collection.collection_path.as_str()) {
+            Ok(batch) => Some(batch),
+            Err(e) => {
+                warn!("  other clients may change the collection during the import: {:#}", e);
+                None
+            }
+        };
+
+        for entry in &entries {
+            let (folder, label) = (entry.folder.as_str(), entry.name.as_str());
+            if entry.secret.is_empty() {
+                info!("  '{}/{}' -> 'None' (as it was empty)", folder, label);
+                continue;
+            }
+            let mut properties = HashMap::new();
+            properties.insert("tks:kwallet-folder", folder);
+            properties.insert("tks:path", folder);
+            properties.insert("tks:kwallet-entry-type", entry.entry_type);
+            properties.insert("xdg:schema", "org.freedesktop.Secret.Generic");
+            properties.insert("xdg:creator", "org.kde.KWallet");
+            for key in &entry.keys {
+                properties.insert(key, "");
+            }
+            let secret: &[u8] = entry.secret.as_bytes();
+            if let Err(e) = capabilities.check_secret(secret, false) {
+                warn!("  Skipping '{}/{}': {}", folder, label, e);
+                continue;
+            }
+            if let Some(batch) = batch.as_mut() {
+                batch.keep_alive()?;
+            }
+            // existing items will be updated in the secret service
+            let p = collection
+                .create_item(
+                    label,
+                    properties,
+                    secret,
+                    self.replace_existing_items,
+                    entry.content_type,
+                )
+                .await
+                .with_context(|| format!("Failed to create item '{}'", label))?;
+            match p.item_path.to_string() == "/" {
+                true => {
+                    warn!(
+                        "The Secret Service (maybe TKS) returned a prompt instead of creating \
+                         item {}",
+                        label
+                    );
+                }
+                false => {
+                    info!("  '{}/{}' -> '{}'", folder, label, p.item_path);
                 }
             }
-        } else {
-            panic!("XML file does not contain a wallet root element");
+        }
+        drop(batch);
+
+        if self.disable_kwallet_secret_service {
+            disable_kwallet_secret_service()?;
         }
         Ok(())
     }
 }
+
+/// The entries of a KWalletManager's exported file
+fn xml_entries(xml_string: &str) -> Result<Vec<WalletEntry>> {
+    let xml = roxmltree::Document::parse(xml_string).with_context(|| "Import failed")?;
+    let wallet = xml
+        .descendants()
+        .find(|n| n.tag_name().name() == "wallet")
+        .ok_or_else(|| anyhow!("XML file does not contain a wallet root element"))?;
+    let mut entries = Vec::new();
+    for f in wallet.children().filter(|n| n.node_type() == Element) {
+        let current_folder = f
+            .attribute("name")
+            .ok_or_else(|| anyhow!("Missing name in wallet attribute"))?;
+        info!("  processing folder '{}'", current_folder);
+        for e in f.children().filter(|n| n.node_type() == Element) {
+            debug!("  entry: {:?}", e);
+
+            let label = e.attribute("name").ok_or_else(|| anyhow!("Missing name"))?;
+            match e.tag_name().name() {
+                "map" => {
+                    let map = e
+                        .children()
+                        .filter(|n| n.tag_name().name() == "mapentry")
+                        .filter_map(|n| {
+                            let value = n.text().unwrap_or_default().to_string();
+                            n.attribute("name").map(|key| (key.to_string(), value))
+                        })
+                        .collect();
+                    entries.push(WalletEntry::map(current_folder, label, &map)?);
+                }
+                "password" => {
+                    let password = e.text().unwrap_or_default().to_string();
+                    entries.push(WalletEntry::password(current_folder, label, password));
+                }
+                item_type => info!("    Ignoring {} entry {}/{}", item_type, current_folder, label),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// The entries of a wallet, read from the running KWallet daemon
+fn live_entries(wallet: Option<&str>) -> Result<Vec<WalletEntry>> {
+    let conn = connect()?;
+    let mut daemon = None;
+    for name in ["kwalletd6", "kwalletd5"] {
+        let proxy = conn.with_proxy(
+            format!("org.kde.{}", name),
+            format!("/modules/{}", name),
+            OPEN_TIMEOUT,
+        );
+        match proxy.method_call(KWALLET_INTERFACE, "localWallet", ()) {
+            Ok((local,)) => {
+                daemon = Some((proxy, local));
+                break;
+            }
+            Err(e) => debug!("No {}: {}", name, e),
+        }
+    }
+    let (proxy, local): (_, String) =
+        daemon.ok_or_else(|| anyhow!("No KWallet daemon found on the session bus"))?;
+    let wallet = wallet.unwrap_or(&local);
+    info!("  opening the wallet '{}'", wallet);
+    let (handle,): (i32,) = proxy
+        .method_call(KWALLET_INTERFACE, "open", (wallet, 0i64, APP_ID))
+        .with_context(|| format!("Cannot open the wallet '{}'", wallet))?;
+    ensure!(handle >= 0, "KWallet didn't open the wallet '{}'", wallet);
+    let entries = wallet_entries(&proxy, handle);
+    let closed: Result<(i32,), _> =
+        proxy.method_call(KWALLET_INTERFACE, "close", (handle, false, APP_ID));
+    if let Err(e) = closed {
+        debug!("Cannot close the wallet: {}", e);
+    }
+    entries
+}
+
+fn wallet_entries(proxy: &Proxy<&Connection>, handle: i32) -> Result<Vec<WalletEntry>> {
+    let mut entries = Vec::new();
+    let (folders,): (Vec<String>,) =
+        proxy.method_call(KWALLET_INTERFACE, "folderList", (handle, APP_ID))?;
+    for folder in &folders {
+        info!("  processing folder '{}'", folder);
+        let (names,): (Vec<String>,) =
+            proxy.method_call(KWALLET_INTERFACE, "entryList", (handle, folder, APP_ID))?;
+        for name in &names {
+            let arguments = (handle, folder, name, APP_ID);
+            let (entry_type,): (i32,) =
+                proxy.method_call(KWALLET_INTERFACE, "entryType", arguments)?;
+            match entry_type {
+                PASSWORD_ENTRY => {
+                    let (password,): (String,) =
+                        proxy.method_call(KWALLET_INTERFACE, "readPassword", arguments)?;
+                    entries.push(WalletEntry::password(folder, name, password));
+                }
+                MAP_ENTRY => {
+                    let (data,): (Vec<u8>,) =
+                        proxy.method_call(KWALLET_INTERFACE, "readMap", arguments)?;
+                    let map = read_qmap(&data)
+                        .with_context(|| format!("Cannot read the map {}/{}", folder, name))?;
+                    entries.push(WalletEntry::map(folder, name, &map)?);
+                }
+                _ => info!("    Ignoring binary entry {}/{}", folder, name),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn read_u32(data: &mut &[u8]) -> Result<u32> {
+    ensure!(data.len() >= 4, "Truncated data");
+    let (value, rest) = data.split_at(4);
+    *data = rest;
+    Ok(u32::from_be_bytes(value.try_into()?))
+}
+
+/// Reads a `QString` serialized by `QDataStream`: its length in bytes, then its UTF-16 text
+fn read_qstring(data: &mut &[u8]) -> Result<String> {
+    let length = read_u32(data)?;
+    // a null string
+    if length == u32::MAX {
+        return Ok(String::new());
+    }
+    let length = length as usize;
+    ensure!(data.len() >= length, "Truncated data");
+    let (text, rest) = data.split_at(length);
+    *data = rest;
+    let units = text.chunks_exact(2);
+    ensure!(units.remainder().is_empty(), "Malformed string");
+    Ok(String::from_utf16(
+        &units
+            .map(|u| u16::from_be_bytes([u[0], u[1]]))
+            .collect::<Vec<_>>(),
+    )?)
+}
+
+/// Reads a `QMap<QString, QString>` serialized by `QDataStream`, as KWallet stores its maps
+fn read_qmap(mut data: &[u8]) -> Result<BTreeMap<String, String>> {
+    let count = read_u32(&mut data)?;
+    let mut map = BTreeMap::new();
+    for _ in 0..count {
+        let key = read_qstring(&mut data)?;
+        map.insert(key, read_qstring(&mut data)?);
+    }
+    Ok(map)
+}
+
+/// Sets `apiEnabled=false` in the `org.freedesktop.secrets` group of `kwalletrc`
+fn disable_kwallet_secret_service() -> Result<()> {
+    for tool in ["kwriteconfig6", "kwriteconfig5"] {
+        let status = Command::new(tool)
+            .args(["--file", "kwalletrc", "--group", "org.freedesktop.secrets"])
+            .args(["--key", "apiEnabled", "--type", "bool", "false"])
+            .status();
+        match status {
+            Ok(status) if status.success() => {
+                info!(
+                    "Disabled the Secret Service interface of KWallet, it takes effect once \
+                     kwalletd restarts, e.g. at the next login"
+                );
+                return Ok(());
+            }
+            Ok(status) => bail!("{} failed: {}", tool, status),
+            Err(e) => debug!("Cannot run {}: {}", tool, e),
+        }
+    }
+    bail!(
+        "Neither kwriteconfig6 nor kwriteconfig5 found, set `apiEnabled=false` in the \
+         `[org.freedesktop.secrets]` group of ~/.config/kwalletrc instead"
+    )
+}
@@ -0,0 +1,176 @@
+//! Import the `.1pux` export of 1Password, see `File > Export` in its desktop application
+//!
+//! The export is a zip archive whose `export.data` file lists the vaults of the accounts, which
+//! become the folders of the entries. The items keep their password as secret, or their notes
+//! when they have none, e.g. the secure notes. The concealed fields of their sections, like the
+//! one-time password seeds or the card numbers, become items of their own, while the others
+//! become attributes.
+
+use crate::importer::{Entry, ImportTarget};
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ImportOnePasswordCmd {
+    /// Path to the `.1pux` file exported by 1Password
+    pub export_file: PathBuf,
+
+    #[clap(flatten)]
+    pub target: ImportTarget,
+}
+
+/// The kinds of field values holding secrets
+const CONCEALED_VALUES: [&str; 3] = ["concealed", "totp", "creditCardNumber"];
+
+#[derive(Deserialize)]
+struct Export {
+    accounts: Vec<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    vaults: Vec<Vault>,
+}
+
+#[derive(Deserialize)]
+struct Vault {
+    attrs: VaultAttributes,
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct VaultAttributes {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    overview: Overview,
+    details: Details,
+}
+
+#[derive(Deserialize)]
+struct Overview {
+    title: String,
+    #[serde(default)]
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Details {
+    #[serde(default)]
+    login_fields: Vec<LoginField>,
+    #[serde(default)]
+    notes_plain: Option<String>,
+    #[serde(default)]
+    sections: Vec<Section>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginField {
+    #[serde(default)]
+    value: String,
+    #[serde(default)]
+    designation: String,
+}
+
+#[derive(Deserialize)]
+struct Section {
+    #[serde(default)]
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    id: String,
+    /// An object having the kind of the value as only key
+    value: Value,
+}
+
+impl ImportOnePasswordCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        info!(
+            "Importing 1Password data from file: {}",
+            self.export_file.display()
+        );
+        let file = File::open(&self.export_file)
+            .with_context(|| format!("Error reading file '{}'", self.export_file.display()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("'{}' isn't a 1pux file", self.export_file.display()))?;
+        let mut json = String::new();
+        archive
+            .by_name("export.data")
+            .with_context(|| "No export.data in the 1pux file")?
+            .read_to_string(&mut json)?;
+        let export: Export =
+            serde_json::from_str(&json).with_context(|| "Malformed export.data")?;
+
+        let mut entries = Vec::new();
+        for vault in export.accounts.into_iter().flat_map(|a| a.vaults) {
+            for item in vault.items {
+                entries.push(entry(&vault.attrs.name, item));
+            }
+        }
+        self.target.import("com.1password", entries).await
+    }
+}
+
+fn entry(vault: &str, item: Item) -> Entry {
+    let details = item.details;
+    let mut entry = Entry {
+        folder: vec![vault.to_string()],
+        label: item.overview.title,
+        ..Default::default()
+    };
+    let login_field = |designation: &str| {
+        details
+            .login_fields
+            .iter()
+            .find(|f| f.designation == designation)
+            .map(|f| f.value.clone())
+    };
+    let notes = details.notes_plain.clone().unwrap_or_default();
+    let password = login_field("password").or(details.password.clone());
+    match password.filter(|p| !p.is_empty()) {
+        Some(password) => {
+            entry.secret = password;
+            entry.attribute("notes", &notes);
+        }
+        None => entry.secret = notes,
+    }
+    entry.attribute("username", &login_field("username").unwrap_or_default());
+    entry.attribute("url", &item.overview.url);
+
+    for field in details.sections.iter().flat_map(|s| &s.fields) {
+        let name = match field.title.as_str() {
+            "" => &field.id,
+            title => title,
+        };
+        let Some((kind, value)) = field.value.as_object().and_then(|v| v.iter().next()) else {
+            continue;
+        };
+        // the other values, like the dates or the addresses, aren't strings
+        let Some(value) = value.as_str().filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        match CONCEALED_VALUES.contains(&kind.as_str()) {
+            true => entry
+                .hidden_fields
+                .push((name.to_string(), value.to_string())),
+            false => entry.attribute(name, value),
+        }
+    }
+    entry
+}
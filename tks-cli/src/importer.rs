@@ -0,0 +1,169 @@
+//! The common part of the importers of the password managers' exports: the entries become items
+//! of the target collection, their folders going into the `tks:path` attribute, or choosing the
+//! collection when asked.
+
+use crate::dbus_client::{
+    capabilities, connect_secret_service, find_collection, Batch, Capabilities,
+};
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use log::{debug, info, warn};
+use secret_service::{Collection, SecretService};
+use std::collections::{BTreeMap, HashMap};
+
+/// An entry of a password manager, ready to become an item
+#[derive(Debug, Default)]
+pub(crate) struct Entry {
+    /// Names of the folders holding the entry, outermost first
+    pub folder: Vec<String>,
+    pub label: String,
+    pub secret: String,
+    pub attributes: Vec<(String, String)>,
+    /// The custom fields holding secrets: each one becomes an item of its own, the attributes
+    /// being readable without unlocking the collection
+    pub hidden_fields: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportTarget {
+    /// Collection receiving the entries: an alias or a label
+    #[clap(long, default_value = "default")]
+    pub collection: String,
+
+    /// Imports the entries of each top-level folder into the collection having its name, which
+    /// gets created when missing
+    #[clap(long)]
+    pub folders_as_collections: bool,
+
+    /// Replaces the items already having the same attributes, e.g. when importing again
+    #[clap(long, short = 'r')]
+    pub replace_existing_items: bool,
+}
+
+impl Entry {
+    /// Sets an attribute unless its value is empty
+    pub fn attribute(&mut self, name: &str, value: &str) {
+        if !value.is_empty() {
+            self.attributes.push((name.to_string(), value.to_string()));
+        }
+    }
+}
+
+impl ImportTarget {
+    /// Creates the items of the entries, `creator` going into their `xdg:creator` attribute
+    pub(crate) async fn import(&self, creator: &str, entries: Vec<Entry>) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let capabilities = capabilities()?;
+        let count = entries.len();
+        let mut collections: BTreeMap<String, Vec<(String, Entry)>> = BTreeMap::new();
+        for mut entry in entries {
+            let collection = match self.folders_as_collections && !entry.folder.is_empty() {
+                true => entry.folder.remove(0),
+                false => self.collection.clone(),
+            };
+            let path = entry.folder.join("/");
+            collections
+                .entry(collection)
+                .or_default()
+                .push((path, entry));
+        }
+
+        let mut created = 0;
+        for (name, entries) in collections {
+            let collection = self.collection(&ss, &capabilities, &name).await?;
+            // keeps the other clients from writing to the collection meanwhile
+            let mut batch = match Batch::begin(collection.collection_path.as_str()) {
+                Ok(batch) => Some(batch),
+                Err(e) => {
+                    warn!(
+                        "  other clients may change '{}' during the import: {:#}",
+                        name, e
+                    );
+                    None
+                }
+            };
+            for (path, entry) in entries {
+                let mut attributes: Vec<(&str, &str)> = entry
+                    .attributes
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect();
+                attributes.push(("xdg:schema", "org.freedesktop.Secret.Generic"));
+                attributes.push(("xdg:creator", creator));
+                if !path.is_empty() {
+                    attributes.push(("tks:path", &path));
+                }
+                let mut items = vec![(entry.label.clone(), None, entry.secret.as_str())];
+                items.extend(entry.hidden_fields.iter().map(|(field, secret)| {
+                    let label = format!("{} / {}", entry.label, field);
+                    (label, Some(field.as_str()), secret.as_str())
+                }));
+                for (label, field, secret) in items {
+                    if secret.is_empty() {
+                        debug!("  Skipping '{}': it has no secret", label);
+                        continue;
+                    }
+                    if let Err(e) = capabilities.check_secret(secret.as_bytes(), false) {
+                        warn!("  Skipping '{}': {}", label, e);
+                        continue;
+                    }
+                    let mut properties: HashMap<&str, &str> = attributes.iter().copied().collect();
+                    if let Some(field) = field {
+                        properties.insert("field", field);
+                    }
+                    if let Some(batch) = batch.as_mut() {
+                        batch.keep_alive()?;
+                    }
+                    let item = collection
+                        .create_item(
+                            &label,
+                            properties,
+                            secret.as_bytes(),
+                            self.replace_existing_items,
+                            "text/plain",
+                        )
+                        .await
+                        .with_context(|| format!("Failed to create item '{}'", label))?;
+                    info!("  '{}/{}' -> '{}'", name, label, item.item_path.as_str());
+                    created += 1;
+                }
+            }
+        }
+        info!("Created {} items from {} entries", created, count);
+        Ok(())
+    }
+
+    /// The collection having the given name, unlocked; the folders' ones get created when missing
+    async fn collection<'a>(
+        &self,
+        ss: &'a SecretService<'a>,
+        capabilities: &Capabilities,
+        name: &str,
+    ) -> Result<Collection<'a>> {
+        let collection = match find_collection(ss, name).await {
+            Ok(collection) => collection,
+            Err(_) if self.folders_as_collections && name != self.collection => {
+                if !capabilities.create_collection {
+                    return Err(anyhow!(
+                        "The {} storage backend can't create the collection '{}', import without \
+                         --folders-as-collections instead",
+                        capabilities.backend,
+                        name
+                    ));
+                }
+                info!("  creating the collection: {}", name);
+                ss.create_collection(name, "")
+                    .await
+                    .with_context(|| format!("Failed to create '{}'", name))?
+            }
+            Err(e) => return Err(e),
+        };
+        if collection.is_locked().await? {
+            collection
+                .unlock()
+                .await
+                .with_context(|| format!("Failed to unlock '{}'", name))?;
+        }
+        Ok(collection)
+    }
+}
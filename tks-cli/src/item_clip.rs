@@ -0,0 +1,107 @@
+//! Put the secret of an item on the clipboard, e.g. from dmenu or rofi scripts. The clipboard gets
+//! cleared after a while by a `tks-cli item clear-clipboard` process running in the background,
+//! which gets the secret on its standard input, so that it only clears the clipboard if it still
+//! holds the secret.
+
+use crate::desktop::{notify, Desktop};
+use crate::dbus_client::{connect_secret_service, find_item};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+pub struct ItemClipCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(long, default_value_t = 45)]
+    /// Clear the clipboard after this many seconds; 0 leaves the secret on the clipboard
+    pub clear_after: u64,
+    #[clap(long)]
+    /// Don't show a desktop notification
+    pub no_notify: bool,
+    #[clap(required = true)]
+    /// Item to clip: `name=value` terms match the item attributes, other terms match the label;
+    /// exactly one item should match all the terms
+    pub search: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ClipboardClearCmd {
+    #[clap(long)]
+    /// Seconds to wait before clearing the clipboard
+    pub after: u64,
+}
+
+impl ItemClipCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let desktop = Desktop::detect()?;
+        let ss = connect_secret_service().await?;
+        let item = find_item(&ss, self.collection.as_deref(), &self.search).await?;
+        let label = item.get_label().await?;
+        let secret = item.get_secret().await?;
+        let message = copy_secret(&desktop, &label, &secret, self.clear_after, !self.no_notify)?;
+        println!("{}", message.bold());
+        Ok(())
+    }
+}
+
+/// Puts the secret on the clipboard, and starts the process clearing it after `clear_after`
+/// seconds unless that's 0. Returns the message given in the notification.
+pub(crate) fn copy_secret(
+    desktop: &Desktop,
+    label: &str,
+    secret: &[u8],
+    clear_after: u64,
+    notify_user: bool,
+) -> Result<String> {
+    anyhow::ensure!(
+        std::str::from_utf8(secret).is_ok(),
+        "The secret of '{}' is not text, it can't go on the clipboard",
+        label
+    );
+    desktop.copy(secret)?;
+    let message = match clear_after {
+        0 => format!("Copied '{}' to the clipboard", label),
+        n => {
+            spawn_clearer(n, secret)?;
+            format!("Copied '{}' to the clipboard, clearing it in {} seconds", label, n)
+        }
+    };
+    if notify_user {
+        notify("Secret copied", &message);
+    }
+    Ok(message)
+}
+
+/// Starts `tks-cli item clear-clipboard` in its own process group, so that it survives the
+/// terminal or the script which ran tks-cli
+fn spawn_clearer(after: u64, secret: &[u8]) -> Result<()> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(["item", "clear-clipboard", "--after", &after.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .with_context(|| "Cannot start the process clearing the clipboard")?;
+    child.stdin.take().unwrap().write_all(secret)?;
+    Ok(())
+}
+
+impl ClipboardClearCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let mut secret = Vec::new();
+        std::io::stdin().read_to_end(&mut secret)?;
+        std::thread::sleep(Duration::from_secs(self.after));
+        let desktop = Desktop::detect()?;
+        if desktop.paste().is_ok_and(|content| content == secret) {
+            desktop.clear()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Create an item from the standard input, e.g. for scripts. The input is taken as text unless
+//! `--binary` is given, see `tks-cli item set`. Short-lived secrets, such as tokens, may be given
+//! an expiry, after which the service deletes them.
+
+use crate::dbus_client::{capabilities, connect_secret_service, find_collection};
+use crate::item_set::{read_secret, warn_secret_terms, BINARY_CONTENT_TYPE, TEXT_CONTENT_TYPE};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use log::debug;
+use std::collections::HashMap;
+use std::time::Duration;
+use tks_service::storage::expiry::{self, EXPIRES_AT_ATTRIBUTE};
+
+#[derive(Parser, Debug)]
+pub struct ItemCreateCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection to create the item in: an alias or a label
+    pub collection: String,
+    #[clap(long)]
+    /// Label of the item
+    pub label: String,
+    #[clap(long)]
+    /// Store the standard input as raw bytes
+    pub binary: bool,
+    #[clap(long)]
+    /// Content type of the secret, by default text/plain, or application/octet-stream with
+    /// --binary
+    pub content_type: Option<String>,
+    #[clap(long)]
+    /// Replace the item having the same attributes, if any, instead of failing
+    pub replace: bool,
+    #[clap(long, value_parser = parse_duration)]
+    /// Let the service delete the item after this long, e.g. 90m, 12h or 7d
+    pub expires_in: Option<Duration>,
+    /// Attributes of the item, as `name=value` terms
+    pub attributes: Vec<String>,
+}
+
+impl ItemCreateCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        warn_secret_terms(&self.attributes);
+        let mut attributes = self
+            .attributes
+            .iter()
+            .map(|term| {
+                term.split_once('=')
+                    .with_context(|| format!("Invalid attribute '{}', use name=value", term))
+            })
+            .collect::<Result<HashMap<&str, &str>>>()?;
+        let expires_at = self
+            .expires_in
+            .map(|expires_in| (expiry::now() + expires_in.as_secs()).to_string());
+        if let Some(expires_at) = &expires_at {
+            attributes.insert(EXPIRES_AT_ATTRIBUTE, expires_at);
+        }
+        let content_type = match (&self.content_type, self.binary) {
+            (Some(content_type), _) => content_type.as_str(),
+            (None, true) => BINARY_CONTENT_TYPE,
+            (None, false) => TEXT_CONTENT_TYPE,
+        };
+        let secret = read_secret(self.binary)?;
+        capabilities()?.check_secret(&secret, self.binary)?;
+
+        let ss = connect_secret_service().await?;
+        let collection = find_collection(&ss, &self.collection).await?;
+        collection
+            .ensure_unlocked()
+            .await
+            .with_context(|| format!("Cannot unlock '{}'", self.collection))?;
+        let item = collection
+            .create_item(&self.label, attributes, &secret, self.replace, content_type)
+            .await
+            .with_context(|| format!("Cannot create item '{}'", self.label))?;
+        debug!("Created {}", item.item_path.as_str());
+        println!("Created '{}' in '{}'", self.label.bold(), self.collection);
+        Ok(())
+    }
+}
+
+/// A number of seconds, or of minutes, hours, days or weeks followed by m, h, d or w
+pub(crate) fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let (count, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(format!("unknown unit '{}', use s, m, h, d or w", unit)),
+    };
+    let count: u64 = count.parse().map_err(|_| format!("'{}' is not a duration", value))?;
+    count
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("'{}' is too long", value))
+}
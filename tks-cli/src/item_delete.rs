@@ -0,0 +1,30 @@
+//! Delete an item, e.g. for scripts.
+
+use crate::dbus_client::{connect_secret_service, find_item};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+#[derive(Parser, Debug)]
+pub struct ItemDeleteCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(required = true)]
+    /// Item to delete: `name=value` terms match the item attributes, other terms match the label;
+    /// exactly one item should match all the terms
+    pub search: Vec<String>,
+}
+
+impl ItemDeleteCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let item = find_item(&ss, self.collection.as_deref(), &self.search).await?;
+        let label = item.get_label().await?;
+        item.delete()
+            .await
+            .with_context(|| format!("Cannot delete item '{}'", label))?;
+        println!("Deleted '{}'", label.bold());
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Print the secret of an item, e.g. for scripts piping it into other tools. With `--binary`, the
+//! secret gets written exactly as stored: no newline gets added, and binary secrets, such as SSH
+//! keys or the KWallet binary entries, go through untouched. Those don't get written to a
+//! terminal unless `--force` is given.
+
+use crate::dbus_client::{connect_secret_service, find_item};
+use crate::item_set::warn_secret_terms;
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::debug;
+use std::io::{IsTerminal, Write};
+
+#[derive(Parser, Debug)]
+pub struct ItemGetCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(long)]
+    /// Write the secret as raw bytes, without a trailing newline, whatever its content type
+    pub binary: bool,
+    #[clap(long, requires = "binary")]
+    /// Write the raw bytes even when the standard output is a terminal
+    pub force: bool,
+    #[clap(required = true)]
+    /// Item to print: `name=value` terms match the item attributes, other terms match the label;
+    /// exactly one item should match all the terms
+    pub search: Vec<String>,
+}
+
+impl ItemGetCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        warn_secret_terms(&self.search);
+        let stdout = std::io::stdout();
+        anyhow::ensure!(
+            !self.binary || self.force || !stdout.is_terminal(),
+            "Refusing to write the raw secret to a terminal; redirect the output, or add --force"
+        );
+        let ss = connect_secret_service().await?;
+        let item = find_item(&ss, self.collection.as_deref(), &self.search).await?;
+        let secret = item.get_secret().await?;
+        debug!("Content type: {}", item.get_secret_content_type().await?);
+        let mut out = stdout.lock();
+        match self.binary {
+            true => out.write_all(&secret)?,
+            false => {
+                let text = String::from_utf8(secret)
+                    .with_context(|| "The secret is not text, use --binary to get it")?;
+                writeln!(out, "{}", text)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
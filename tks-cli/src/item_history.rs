@@ -0,0 +1,84 @@
+//! Show the previous secrets of an item, and get one of them back, e.g. after overwriting a
+//! password by mistake. The service keeps a few of them per item, see the
+//! `tks:secret-history-depth` collection property; only their size gets shown, never the secrets.
+
+use crate::age::age;
+use crate::dbus_client::{connect, connect_secret_service, find_item, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ITEM_INTERFACE: &str = "io.linux_tks.Item1";
+
+#[derive(Parser, Debug)]
+pub struct ItemHistoryCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(required = true)]
+    /// Item to show: `name=value` terms match the item attributes, other terms match the label;
+    /// exactly one item should match all the terms
+    pub search: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ItemRollbackCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(long, default_value_t = 0)]
+    /// Version to get back, as numbered by `tks-cli item history`; the latest one by default
+    pub version: u32,
+    #[clap(required = true)]
+    /// Item to roll back: `name=value` terms match the item attributes, other terms match the
+    /// label; exactly one item should match all the terms
+    pub search: Vec<String>,
+}
+
+impl ItemHistoryCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let item = find_item(&ss, self.collection.as_deref(), &self.search).await?;
+        let label = item.get_label().await?;
+        let conn = connect()?;
+        let (versions,): (Vec<(u64, String, u64)>,) = conn
+            .with_proxy(SERVICE_NAME, item.item_path.as_str(), TIMEOUT)
+            .method_call(ITEM_INTERFACE, "GetVersions", ())
+            .with_context(|| format!("Cannot get the previous secrets of '{}'", label))?;
+        if versions.is_empty() {
+            println!("No previous secrets of '{}'", label);
+            return Ok(());
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        for (index, (replaced, content_type, size)) in versions.into_iter().enumerate() {
+            println!(
+                "{:>3}  replaced {:>4} ago  {} bytes ({})",
+                index.to_string().bold(),
+                age(replaced, now),
+                size,
+                content_type
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ItemRollbackCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let item = find_item(&ss, self.collection.as_deref(), &self.search).await?;
+        let label = item.get_label().await?;
+        let conn = connect()?;
+        conn.with_proxy(SERVICE_NAME, item.item_path.as_str(), TIMEOUT)
+            .method_call::<(), _, _, _>(ITEM_INTERFACE, "Rollback", (self.version,))
+            .with_context(|| format!("Cannot roll '{}' back", label))?;
+        println!(
+            "Got version {} of '{}' back; rolling back to version 0 undoes it",
+            self.version,
+            label.bold()
+        );
+        Ok(())
+    }
+}
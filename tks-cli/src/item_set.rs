@@ -0,0 +1,146 @@
+//! Replace the secret of an item, without it showing on the terminal. The secret gets prompted
+//! for without echo, or read from the standard input when it isn't a terminal. With `--binary`,
+//! the input is taken as raw bytes, e.g. an SSH key; otherwise, it is text and its trailing
+//! newline gets dropped. A secret given on the command line with `--value` works too, but ends up
+//! in the shell history, which gets warned about.
+
+use crate::dbus_client::{capabilities, connect_secret_service, find_item};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use console::Term;
+use secret_service::Item;
+use std::io::{IsTerminal, Read};
+
+pub(crate) const BINARY_CONTENT_TYPE: &str = "application/octet-stream";
+pub(crate) const TEXT_CONTENT_TYPE: &str = "text/plain";
+
+/// Attribute names hinting that the attribute value is a secret
+const SECRET_NAMES: [&str; 4] = ["password", "passphrase", "secret", "token"];
+
+/// Reads a secret from the standard input; text secrets lose their trailing newline
+pub(crate) fn read_secret(binary: bool) -> Result<Vec<u8>> {
+    let mut secret = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut secret)
+        .with_context(|| "Cannot read the secret from the standard input")?;
+    if !binary {
+        std::str::from_utf8(&secret)
+            .with_context(|| "The input is not text, use --binary to store it")?;
+        secret = text_secret(secret)?;
+    }
+    Ok(secret)
+}
+
+/// Checks the secret is text, and drops its trailing newline
+pub(crate) fn text_secret(mut secret: Vec<u8>) -> Result<Vec<u8>> {
+    std::str::from_utf8(&secret).with_context(|| "The input is not text")?;
+    if secret.ends_with(b"\n") {
+        secret.pop();
+    }
+    if secret.ends_with(b"\r") {
+        secret.pop();
+    }
+    Ok(secret)
+}
+
+/// The content type to store a secret with: the given one, otherwise the item's own, unless the
+/// secret switches between text and binary
+pub(crate) async fn content_type(
+    item: &Item<'_>,
+    given: Option<&str>,
+    binary: bool,
+) -> Result<String> {
+    if let Some(content_type) = given {
+        return Ok(content_type.to_string());
+    }
+    let current = item.get_secret_content_type().await?;
+    Ok(match (binary, current.starts_with("text/")) {
+        (true, true) => BINARY_CONTENT_TYPE.to_string(),
+        (false, false) => TEXT_CONTENT_TYPE.to_string(),
+        _ => current,
+    })
+}
+
+#[derive(Parser, Debug)]
+pub struct ItemSetCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(long, conflicts_with = "value")]
+    /// Store the standard input as raw bytes
+    pub binary: bool,
+    #[clap(long)]
+    /// The secret; it ends up in the shell history, prefer the prompt or the standard input
+    pub value: Option<String>,
+    #[clap(long)]
+    /// Content type of the secret; the item keeps its own by default, unless it switches between
+    /// text and binary
+    pub content_type: Option<String>,
+    #[clap(required = true)]
+    /// Item to update: `name=value` terms match the item attributes, other terms match the label;
+    /// exactly one item should match all the terms
+    pub search: Vec<String>,
+}
+
+impl ItemSetCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        warn_secret_terms(&self.search);
+        let secret = self.read_secret()?;
+        capabilities()?.check_secret(&secret, self.binary)?;
+        let ss = connect_secret_service().await?;
+        let item = find_item(&ss, self.collection.as_deref(), &self.search).await?;
+        let content_type = content_type(&item, self.content_type.as_deref(), self.binary).await?;
+        item.set_secret(&secret, &content_type)
+            .await
+            .with_context(|| "Cannot store the secret")?;
+        println!(
+            "Stored {} bytes ({}) into '{}'",
+            secret.len(),
+            content_type,
+            item.get_label().await?.bold()
+        );
+        Ok(())
+    }
+
+    fn read_secret(&self) -> Result<Vec<u8>> {
+        if let Some(value) = &self.value {
+            warn_secret_argument("--value");
+            return Ok(value.clone().into_bytes());
+        }
+        if !std::io::stdin().is_terminal() {
+            return read_secret(self.binary);
+        }
+        anyhow::ensure!(
+            !self.binary,
+            "Binary secrets can't be typed; give them on the standard input"
+        );
+        let term = Term::stderr();
+        term.write_str("Secret: ")?;
+        let secret = term.read_secure_line()?;
+        term.write_str("Confirm secret: ")?;
+        anyhow::ensure!(term.read_secure_line()? == secret, "The secrets do not match");
+        Ok(secret.into_bytes())
+    }
+}
+
+/// Secrets given as arguments stay in the shell history, and other users may see them in the
+/// process list
+fn warn_secret_argument(option: &str) {
+    eprintln!(
+        "{}: the secret given with {} ends up in the shell history and shows in the process \
+         list; prefer the prompt or the standard input",
+        "WARNING".bold(),
+        option
+    );
+}
+
+/// Warns about the `name=value` terms which look like they carry a secret, e.g. `password=...`
+pub(crate) fn warn_secret_terms(terms: &[String]) {
+    for (name, _) in terms.iter().filter_map(|term| term.split_once('=')) {
+        let name = name.to_lowercase();
+        if SECRET_NAMES.iter().any(|s| name.contains(s)) {
+            warn_secret_argument(&format!("the '{}' attribute", name));
+        }
+    }
+}
@@ -1,4 +1,49 @@
+mod acl;
+mod age;
+mod audit_duplicates;
+mod audit_export;
+mod backup;
+mod clients;
+mod collection_create;
+mod collection_history;
+mod collection_list;
+mod collection_lock;
+mod collection_merge;
+mod collection_migrate;
+mod dbus_client;
+mod desktop;
+mod docker_credential;
+mod export_kwallet;
+mod fuse;
+mod import_bitwarden;
+mod import_browser;
+mod import_keepass;
 mod import_kwallet;
+mod import_onepassword;
+mod importer;
+mod item_clip;
+mod item_create;
+mod item_delete;
+mod item_get;
+mod item_history;
+mod item_set;
+mod menu;
+mod oauth;
+mod provision;
+mod review;
+mod run;
+mod search;
+mod secret_list;
+mod secret_move;
+mod service_change_password;
+mod service_check_config;
+mod service_diagnostics;
+mod service_fsck;
+mod service_forget;
+mod service_reload_config;
+mod service_test_prompt;
+mod service_unlock;
+mod trash;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
@@ -9,7 +54,48 @@ use std::io::Read;
 use std::{io, process::exit};
 use yubikey::{Context, Key, Serial, YubiKey};
 use yubikey::piv::SlotId;
+use acl::{AclGrantCmd, AclRestrictCmd, AclShowCmd};
+use audit_duplicates::AuditDuplicatesCmd;
+use audit_export::AuditExportCmd;
+use backup::{BackupCreateCmd, BackupRestoreCmd};
+use clients::{ClientsListCmd, ClientsRevokeCmd};
+use collection_create::CollectionCreateCmd;
+use collection_history::CollectionHistoryCmd;
+use collection_list::CollectionListCmd;
+use collection_lock::CollectionLockCmd;
+use collection_merge::CollectionMergeCmd;
+use collection_migrate::CollectionMigrateCmd;
+use docker_credential::DockerCredentialCmd;
+use export_kwallet::ExportKwalletCmd;
+use fuse::FuseCmd;
+use import_bitwarden::ImportBitwardenCmd;
+use import_browser::ImportBrowserCmd;
+use import_keepass::ImportKeepassCmd;
 use import_kwallet::ImportKwalletCmd;
+use import_onepassword::ImportOnePasswordCmd;
+use item_clip::{ClipboardClearCmd, ItemClipCmd};
+use item_create::ItemCreateCmd;
+use item_delete::ItemDeleteCmd;
+use item_get::ItemGetCmd;
+use item_history::{ItemHistoryCmd, ItemRollbackCmd};
+use item_set::ItemSetCmd;
+use menu::MenuCmd;
+use oauth::{OauthGetCmd, OauthListCmd, OauthStoreCmd};
+use provision::ProvisionCmd;
+use review::ReviewCmd;
+use run::RunCmd;
+use search::SearchCmd;
+use secret_list::SecretListCmd;
+use secret_move::SecretMoveCmd;
+use service_change_password::ServiceChangePasswordCmd;
+use service_check_config::ServiceCheckConfigCmd;
+use service_diagnostics::ServiceDiagnosticsCmd;
+use service_fsck::ServiceFsckCmd;
+use service_forget::ServiceForgetCmd;
+use service_reload_config::ServiceReloadConfigCmd;
+use service_test_prompt::ServiceTestPromptCmd;
+use service_unlock::ServiceUnlockCmd;
+use trash::{TrashListCmd, TrashPurgeCmd, TrashRestoreCmd};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -42,6 +128,138 @@ struct ServiceStatusCmd {}
 enum ServiceCmd {
     /// Display information about the service
     Status(ServiceStatusCmd),
+    /// Display each prompt type through the service's prompt backend, to debug the pinentry setup,
+    /// once tks-cli got let in
+    TestPrompt(ServiceTestPromptCmd),
+    /// Validate the service configuration, without starting the service
+    CheckConfig(ServiceCheckConfigCmd),
+    /// Show how many objects, sessions and prompts the service holds, and its memory usage
+    Diagnostics(ServiceDiagnosticsCmd),
+    /// Make the running service apply the changes of its configuration file
+    ReloadConfig(ServiceReloadConfigCmd),
+    /// Change the unlock password, encrypting the storage again
+    ChangePassword(ServiceChangePasswordCmd),
+    /// Check the storage files, reporting the damaged ones and the items without their secret
+    Fsck(ServiceFsckCmd),
+    /// Answer the password dialogs of a service configured with the remote prompt backend, e.g.
+    /// over SSH, unlocking the collection when none is pending
+    Unlock(ServiceUnlockCmd),
+    /// Lock the collections and forget the cached keys, so that unlocking them asks their
+    /// password again
+    Forget(ServiceForgetCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum CollectionCmd {
+    /// Create a collection
+    Create(CollectionCreateCmd),
+    /// List the collections
+    List(CollectionListCmd),
+    /// Lock a collection
+    Lock(CollectionLockCmd),
+    /// Unlock a collection, prompting for its passphrase
+    Unlock(CollectionLockCmd),
+    /// Move all the items of a collection into another one, e.g. after importing into a temporary
+    /// collection
+    Merge(CollectionMergeCmd),
+    /// Move a collection to another storage backend of the service
+    Migrate(CollectionMigrateCmd),
+    /// Show what got recently created, modified or deleted in a collection
+    History(CollectionHistoryCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum SecretCmd {
+    /// List the items of a collection
+    List(SecretListCmd),
+    /// Move items to another collection
+    Move(SecretMoveCmd),
+    /// Copy items to another collection
+    Copy(SecretMoveCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum ItemCmd {
+    /// Create an item holding the standard input
+    Create(ItemCreateCmd),
+    /// Print the secret of an item; with --binary, exactly as stored, e.g. for pipes
+    Get(ItemGetCmd),
+    /// Replace the secret of an item, prompting for it without echo unless given on the standard
+    /// input
+    Set(ItemSetCmd),
+    /// Delete an item
+    Delete(ItemDeleteCmd),
+    /// List the items of a collection
+    List(SecretListCmd),
+    /// Put the secret of an item on the clipboard, and clear it after a while
+    Clip(ItemClipCmd),
+    /// Show the previous secrets of an item
+    History(ItemHistoryCmd),
+    /// Get a previous secret of an item back
+    Rollback(ItemRollbackCmd),
+    /// Clear the clipboard if it still holds the secret given on the standard input; used by `clip`
+    #[command(hide = true)]
+    ClearClipboard(ClipboardClearCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupCmd {
+    /// Write all the collections, with their items and secrets, to a passphrase protected file
+    Create(BackupCreateCmd),
+    /// Restore the collections from a backup file
+    Restore(BackupRestoreCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum ClientsCmd {
+    /// List the applications let use the service, by process executable
+    List(ClientsListCmd),
+    /// Make an application ask to be let in again
+    Revoke(ClientsRevokeCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum TrashCmd {
+    /// List the deleted items kept in the trash
+    List(TrashListCmd),
+    /// Move a deleted item back to its collection
+    Restore(TrashRestoreCmd),
+    /// Destroy deleted items for good
+    Purge(TrashPurgeCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum AclCmd {
+    /// Show which applications may use a collection or an item
+    Show(AclShowCmd),
+    /// Let only the granted applications use a collection or an item; the others get asked for
+    Restrict(AclRestrictCmd),
+    /// Let all the applications use a collection again, or an item use the ACL of its collection
+    Unrestrict(AclRestrictCmd),
+    /// Let an application read, write or delete the items
+    Grant(AclGrantCmd),
+    /// Take accesses back from an application
+    Revoke(AclGrantCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditCmd {
+    /// Find the items having the same attributes, e.g. after repeated imports, and delete the
+    /// unwanted ones
+    Duplicates(AuditDuplicatesCmd),
+    /// Write the records of the service's audit log as JSON lines, e.g. for a SIEM
+    Export(AuditExportCmd),
+}
+
+#[derive(Subcommand, Debug)]
+enum OauthCmd {
+    /// Keep the token read from the standard input, as the token endpoint answered it, replacing
+    /// the previous one of the same issuer, account and scopes
+    Store(OauthStoreCmd),
+    /// Print the access token still valid for a while, expiring last
+    Get(OauthGetCmd),
+    /// List the tokens, with their scopes and expiry
+    List(OauthListCmd),
 }
 
 #[derive(Parser, Debug)]
@@ -58,11 +276,17 @@ enum ImportCmd {
     /// folder is the `Passwords` folder. Another default folder name is `FormData`. Then, we can have
     /// any other arbitrary folders at the top of the wallet. The name of the original folder is
     /// being put into a special attribute attached to each item. This attributes name is
-    /// `tks:kwallet-folder`.
+    /// `tks:kwallet-folder`. The folder also goes into the `tks:path` attribute, so that
+    /// `tks-cli secret list --tree` shows the wallet's folders.
+    ///
+    /// The KWallet Map entries become items having the JSON object of the map as secret, with the
+    /// `application/x-kde-wallet-map` content type, and a `tks:kwallet-map-key:<key>` attribute
+    /// with an empty value for each of their keys. The binary entries are ignored. See
+    /// `tks-cli export kwallet` to get them back into KWallet.
     ///
-    /// NOTE: Currently, there is no known mapping between KWallet Map entries and Secret Service
-    /// items. For this reason, this tool ignores the Map entries. Same applies to FormData. If you
-    /// happen to know how to map these from KWallet to Secret Service, then please issue a Pull Request.
+    /// With `--live`, the wallet gets read from the running KWallet daemon (`org.kde.kwalletd6`,
+    /// or `org.kde.kwalletd5`) instead of an exported file, and `--disable-kwallet-secret-service`
+    /// then turns off KWallet's own Secret Service interface, completing the move to TKS.
     ///
     /// KWallet entry type can be passwords, maps, binary data or unknown. We use the attribute
     /// `tks:kwallet-entry-type` to store the initial item type.
@@ -71,12 +295,55 @@ enum ImportCmd {
     /// `xdg:schema`:`org.freedesktop.Secret.Generic'
     /// `xdg:creator`:`org.kde.KWallet`
     Kwallet(ImportKwalletCmd),
+    /// This command imports the logins saved by Chromium, Chrome or Firefox
+    ///
+    /// The passwords get decrypted the way the browser does: Chromium and Chrome use their `Safe
+    /// Storage` Secret Service item, Firefox its primary password, asked when one is set.
+    ///
+    /// The Chromium and Chrome logins get the attributes of the browser's libsecret password store,
+    /// `xdg:schema`:`chrome_libsecret_password_schema` and `origin_url`, `signon_realm`,
+    /// `username_value`... The Firefox logins get the `org.freedesktop.Secret.Generic` schema and
+    /// the `url`, `username` attributes. The `tks:path` attribute names the browser.
+    Browser(ImportBrowserCmd),
+    /// This command imports the unencrypted JSON export of Bitwarden
+    ///
+    /// The folders go into the `tks:path` attribute of the items, see `--folders-as-collections`
+    /// to import them into collections instead. The usernames, URLs, notes and the text custom
+    /// fields become attributes, while the hidden custom fields and the TOTP seeds become items
+    /// of their own, labelled `<entry> / <field>` and having a `field` attribute.
+    Bitwarden(ImportBitwardenCmd),
+    /// This command imports a KeePass 2 or KeePassXC database, asking for its password
+    ///
+    /// The groups go into the `tks:path` attribute of the items, see `--folders-as-collections`
+    /// to import the top-level ones into collections instead. The usernames, URLs, notes and the
+    /// custom strings become attributes, while the protected custom strings become items of their
+    /// own, labelled `<entry> / <string>` and having a `field` attribute.
+    Keepass(ImportKeepassCmd),
+    /// This command imports the `.1pux` export of 1Password
+    ///
+    /// The vaults go into the `tks:path` attribute of the items, see `--folders-as-collections` to
+    /// import them into collections instead. The usernames, URLs, notes and the text fields
+    /// become attributes, while the concealed fields become items of their own, labelled
+    /// `<entry> / <field>` and having a `field` attribute.
+    #[clap(name = "1password")]
+    OnePassword(ImportOnePasswordCmd),
     /// Import from GNOME Keyring
     Gnome(ImportGnomeCmd),
     /// Import from PASS
     Pass(ImportPassCmd),
 }
 
+#[derive(Subcommand, Debug)]
+enum ExportCmd {
+    /// Write the items of a collection as a KWalletManager XML file, to import with its
+    /// "import from XML" feature
+    ///
+    /// The items imported from KWallet get back their folder and entry type; the Map entries get
+    /// back their keys and values. The other items become passwords, in the folder named by their
+    /// `tks:path` attribute, or in the `Passwords` folder.
+    Kwallet(ExportKwalletCmd),
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Yuibkey-related commands
@@ -94,18 +361,115 @@ enum Commands {
         #[command(subcommand)]
         import_cmd: ImportCmd,
     },
+    /// Export operations
+    Export {
+        #[command(subcommand)]
+        export_cmd: ExportCmd,
+    },
+    /// Collection-related commands
+    Collection {
+        #[command(subcommand)]
+        collection_cmd: CollectionCmd,
+    },
+    /// Item-related commands
+    Secret {
+        #[command(subcommand)]
+        secret_cmd: SecretCmd,
+    },
+    /// Create, read and delete items, e.g. from scripts
+    Item {
+        #[command(subcommand)]
+        item_cmd: ItemCmd,
+    },
+    /// Backup and restore
+    Backup {
+        #[command(subcommand)]
+        backup_cmd: BackupCmd,
+    },
+    /// Applications trusted with the secrets
+    Clients {
+        #[command(subcommand)]
+        clients_cmd: ClientsCmd,
+    },
+    /// Which applications may read, write or delete the items
+    Acl {
+        #[command(subcommand)]
+        acl_cmd: AclCmd,
+    },
+    /// Deleted items, kept a while before getting destroyed
+    Trash {
+        #[command(subcommand)]
+        trash_cmd: TrashCmd,
+    },
+    /// Storage health checks
+    Audit {
+        #[command(subcommand)]
+        audit_cmd: AuditCmd,
+    },
+    /// OAuth2 tokens shared by the mail clients and the command line tools
+    Oauth {
+        #[command(subcommand)]
+        oauth_cmd: OauthCmd,
+    },
+    /// List the items for rofi or dmenu, then type or copy the secret of the one picked
+    Menu(MenuCmd),
+    /// Make the collections and items match a provisioning file, e.g. from Nix or home-manager
+    Provision(ProvisionCmd),
+    /// Check the setup for security weaknesses, and tell how to fix them
+    Review(ReviewCmd),
+    /// Keep the Docker and Podman registry credentials, as a docker-credential-helpers helper
+    ///
+    /// The action reads its input from the standard input and answers on the standard output, as
+    /// Docker expects. Symlink tks-cli as `docker-credential-tks`, somewhere in the PATH, then set
+    /// `"credsStore": "tks"` in `~/.docker/config.json`. The credentials stored by
+    /// docker-credential-secretservice are found too.
+    DockerCredential(DockerCredentialCmd),
+    /// Run a command with secrets as environment variables, e.g.
+    /// `tks-cli run --env DB_PASSWORD=attr:service=postgres -- psql`
+    Run(RunCmd),
+    /// Find the items whose label or attribute values contain some text, ignoring case
+    ///
+    /// The matches get listed with their collection and object path, the locked ones dimmed;
+    /// `--json` prints them with their attributes instead, for scripts. The secrets are not read,
+    /// so nothing gets unlocked.
+    Search(SearchCmd),
+    /// Mount the collections as a filesystem, a directory per collection and a file per item
+    ///
+    /// The locked collections are empty directories until they get unlocked. The filesystem is
+    /// read-only, unless `--read-write` is given. Needs fusermount3, from fuse3.
+    Fuse(FuseCmd),
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = match docker_credential::helper_args() {
+        Some(helper_args) => Args::parse_from(helper_args),
+        None => Args::parse(),
+    };
 
     pretty_env_logger::formatted_builder().filter_level(args.verbosity.into()).init();
 
     match args.cmd {
         Commands::Yk { yk_cmd } => yk_cmd.run(),
-        Commands::Service { service_cmd } => service_cmd.run(),
+        Commands::Service { service_cmd } => service_cmd.run()?,
         Commands::Import { import_cmd } => import_cmd.run().await?,
+        Commands::Export { export_cmd } => export_cmd.run().await?,
+        Commands::Collection { collection_cmd } => collection_cmd.run().await?,
+        Commands::Secret { secret_cmd } => secret_cmd.run().await?,
+        Commands::Item { item_cmd } => item_cmd.run().await?,
+        Commands::Backup { backup_cmd } => backup_cmd.run()?,
+        Commands::Clients { clients_cmd } => clients_cmd.run()?,
+        Commands::Acl { acl_cmd } => acl_cmd.run()?,
+        Commands::Trash { trash_cmd } => trash_cmd.run()?,
+        Commands::Audit { audit_cmd } => audit_cmd.run()?,
+        Commands::Oauth { oauth_cmd } => oauth_cmd.run().await?,
+        Commands::Menu(cmd) => cmd.run().await?,
+        Commands::Provision(cmd) => cmd.run().await?,
+        Commands::Review(cmd) => cmd.run()?,
+        Commands::DockerCredential(cmd) => cmd.run().await?,
+        Commands::Run(cmd) => cmd.run().await?,
+        Commands::Search(cmd) => cmd.run()?,
+        Commands::Fuse(cmd) => cmd.run().await?,
     }
     Ok(())
 }
@@ -222,10 +586,19 @@ impl YkListCmd {
     }
 }
 impl ServiceCmd {
-    fn run(&self) {
+    fn run(&self) -> Result<()> {
         match self {
             ServiceCmd::Status(cmd) => cmd.run(),
+            ServiceCmd::TestPrompt(cmd) => cmd.run()?,
+            ServiceCmd::CheckConfig(cmd) => cmd.run()?,
+            ServiceCmd::Diagnostics(cmd) => cmd.run()?,
+            ServiceCmd::ReloadConfig(cmd) => cmd.run()?,
+            ServiceCmd::ChangePassword(cmd) => cmd.run()?,
+            ServiceCmd::Fsck(cmd) => cmd.run()?,
+            ServiceCmd::Unlock(cmd) => cmd.run()?,
+            ServiceCmd::Forget(cmd) => cmd.run()?,
         }
+        Ok(())
     }
 }
 impl ServiceStatusCmd {
@@ -233,15 +606,118 @@ impl ServiceStatusCmd {
         println!("Not yet implemented.");
     }
 }
+impl CollectionCmd {
+    async fn run(&self) -> Result<()> {
+        match self {
+            CollectionCmd::Create(cmd) => cmd.run().await,
+            CollectionCmd::List(cmd) => cmd.run(),
+            CollectionCmd::Lock(cmd) => cmd.run(true).await,
+            CollectionCmd::Unlock(cmd) => cmd.run(false).await,
+            CollectionCmd::Merge(cmd) => cmd.run(),
+            CollectionCmd::Migrate(cmd) => cmd.run(),
+            CollectionCmd::History(cmd) => cmd.run(),
+        }
+    }
+}
+impl ItemCmd {
+    async fn run(&self) -> Result<()> {
+        match self {
+            ItemCmd::Create(cmd) => cmd.run().await,
+            ItemCmd::Get(cmd) => cmd.run().await,
+            ItemCmd::Set(cmd) => cmd.run().await,
+            ItemCmd::Delete(cmd) => cmd.run().await,
+            ItemCmd::List(cmd) => cmd.run(),
+            ItemCmd::Clip(cmd) => cmd.run().await,
+            ItemCmd::History(cmd) => cmd.run().await,
+            ItemCmd::Rollback(cmd) => cmd.run().await,
+            ItemCmd::ClearClipboard(cmd) => cmd.run(),
+        }
+    }
+}
+impl SecretCmd {
+    async fn run(&self) -> Result<()> {
+        match self {
+            SecretCmd::List(cmd) => cmd.run(),
+            SecretCmd::Move(cmd) => cmd.run(false),
+            SecretCmd::Copy(cmd) => cmd.run(true),
+        }
+    }
+}
+impl BackupCmd {
+    fn run(&self) -> Result<()> {
+        match self {
+            BackupCmd::Create(cmd) => cmd.run(),
+            BackupCmd::Restore(cmd) => cmd.run(),
+        }
+    }
+}
+impl ClientsCmd {
+    fn run(&self) -> Result<()> {
+        match self {
+            ClientsCmd::List(cmd) => cmd.run(),
+            ClientsCmd::Revoke(cmd) => cmd.run(),
+        }
+    }
+}
+impl AclCmd {
+    fn run(&self) -> Result<()> {
+        match self {
+            AclCmd::Show(cmd) => cmd.run(),
+            AclCmd::Restrict(cmd) => cmd.run(true),
+            AclCmd::Unrestrict(cmd) => cmd.run(false),
+            AclCmd::Grant(cmd) => cmd.run(true),
+            AclCmd::Revoke(cmd) => cmd.run(false),
+        }
+    }
+}
+impl TrashCmd {
+    fn run(&self) -> Result<()> {
+        match self {
+            TrashCmd::List(cmd) => cmd.run(),
+            TrashCmd::Restore(cmd) => cmd.run(),
+            TrashCmd::Purge(cmd) => cmd.run(),
+        }
+    }
+}
+impl OauthCmd {
+    async fn run(&self) -> Result<()> {
+        match self {
+            OauthCmd::Store(cmd) => cmd.run().await,
+            OauthCmd::Get(cmd) => cmd.run().await,
+            OauthCmd::List(cmd) => cmd.run().await,
+        }
+    }
+}
+impl AuditCmd {
+    fn run(&self) -> Result<()> {
+        match self {
+            AuditCmd::Duplicates(cmd) => cmd.run(),
+            AuditCmd::Export(cmd) => cmd.run(),
+        }
+    }
+}
+
 impl ImportCmd {
     async fn run(&self) -> Result<()> {
         match self {
             ImportCmd::Kwallet(cmd) => cmd.run().await,
+            ImportCmd::Browser(cmd) => cmd.run().await,
+            ImportCmd::Bitwarden(cmd) => cmd.run().await,
+            ImportCmd::Keepass(cmd) => cmd.run().await,
+            ImportCmd::OnePassword(cmd) => cmd.run().await,
             ImportCmd::Gnome(cmd) => cmd.run().await,
             ImportCmd::Pass(cmd) => cmd.run().await,
         }
     }
 }
+
+impl ExportCmd {
+    async fn run(&self) -> Result<()> {
+        match self {
+            ExportCmd::Kwallet(cmd) => cmd.run().await,
+        }
+    }
+}
 impl ImportGnomeCmd {
     async fn run(&self) -> Result<()> {
         todo!()
@@ -0,0 +1,129 @@
+//! Pick an item from rofi or dmenu. Without `--type` or `--copy`, the items get listed, one per
+//! line; with them, the line picked gets read from the standard input, and the secret of its item
+//! gets typed into the focused window or put on the clipboard:
+//!
+//! ```sh
+//! tks-cli menu | rofi -dmenu | tks-cli menu --copy
+//! ```
+//!
+//! rofi may answer the index of the line picked instead, which `--index` reads:
+//!
+//! ```sh
+//! tks-cli menu | rofi -dmenu -format i | tks-cli menu --copy --index
+//! ```
+
+use crate::dbus_client::{connect_secret_service, find_collection};
+use crate::desktop::Desktop;
+use crate::item_clip::copy_secret;
+use anyhow::{Context, Result};
+use clap::Parser;
+use secret_service::{Collection, Item};
+use std::collections::HashSet;
+use std::io::Write;
+
+#[derive(Parser, Debug)]
+pub struct MenuCmd {
+    #[clap(long)]
+    /// Only list the items of this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(long = "type", conflicts_with = "copy")]
+    /// Type the secret of the item picked on the standard input into the focused window
+    pub type_secret: bool,
+    #[clap(long)]
+    /// Put the secret of the item picked on the standard input on the clipboard
+    pub copy: bool,
+    #[clap(long)]
+    /// The standard input gives the index of the line picked, counting from 0, rather than the
+    /// line itself
+    pub index: bool,
+    #[clap(long, default_value_t = 45)]
+    /// With --copy, clear the clipboard after this many seconds; 0 leaves the secret on it
+    pub clear_after: u64,
+}
+
+impl MenuCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let collections = match &self.collection {
+            Some(name) => vec![find_collection(&ss, name).await?],
+            None => ss.get_all_collections().await?,
+        };
+        let entries = self.entries(&collections).await?;
+        if !self.type_secret && !self.copy {
+            let mut out = std::io::stdout().lock();
+            for (line, _) in &entries {
+                writeln!(out, "{}", line)?;
+            }
+            return Ok(());
+        }
+
+        let mut picked = String::new();
+        std::io::stdin().read_line(&mut picked)?;
+        let picked = picked.trim_end_matches(['\n', '\r']);
+        if picked.is_empty() {
+            // the menu got dismissed
+            return Ok(());
+        }
+        let position = match self.index {
+            true => picked.parse::<usize>().ok(),
+            false => entries.iter().position(|(line, _)| line == picked),
+        };
+        let (_, item) = position
+            .and_then(|position| entries.into_iter().nth(position))
+            .with_context(|| format!("No item '{}'", picked))?;
+        item.ensure_unlocked()
+            .await
+            .with_context(|| "Cannot unlock the item")?;
+        let label = item.get_label().await?;
+        let secret = item.get_secret().await?;
+        let desktop = Desktop::detect()?;
+        match self.copy {
+            true => {
+                copy_secret(&desktop, &label, &secret, self.clear_after, true)?;
+            }
+            false => {
+                anyhow::ensure!(
+                    std::str::from_utf8(&secret).is_ok(),
+                    "The secret of '{}' is not text, it can't be typed",
+                    label
+                );
+                desktop.type_text(&secret)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// One line per item: its label, prefixed by the label of its collection unless
+    /// `--collection` is given, the control characters, e.g. newlines, replaced by spaces. Items
+    /// having the same line get a ` (n)` suffix, so that each line picks a single item, even when
+    /// a label already ends that way.
+    async fn entries<'a>(
+        &self,
+        collections: &'a [Collection<'a>],
+    ) -> Result<Vec<(String, Item<'a>)>> {
+        let mut entries = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        for collection in collections {
+            let prefix = match self.collection {
+                Some(_) => String::new(),
+                None => format!("{}/", collection.get_label().await?),
+            };
+            for item in collection.get_all_items().await? {
+                let line: String = format!("{}{}", prefix, item.get_label().await?)
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .collect();
+                let line = (1..)
+                    .map(|n| match n {
+                        1 => line.clone(),
+                        n => format!("{} ({})", line, n),
+                    })
+                    .find(|line| !seen.contains(line))
+                    .unwrap_or_default();
+                seen.insert(line.clone());
+                entries.push((line, item));
+            }
+        }
+        Ok(entries)
+    }
+}
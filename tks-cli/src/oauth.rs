@@ -0,0 +1,276 @@
+//! Keep OAuth2 tokens for the mail clients and the command line tools sharing them, see
+//! `tks_service::storage::oauth`. `store` reads the JSON answer of the token endpoint, as a tool
+//! got it after the authorization or a refresh; `get` prints the access token of the token still
+//! valid for a while, expiring last, so scripts don't walk the user through the authorization
+//! again, e.g. `curl -H "Authorization: Bearer $(tks-cli oauth get --issuer ...)"`.
+//!
+//! The answers to a refresh often leave the refresh token out: the one of the replaced token
+//! then carries over.
+
+use crate::dbus_client::{connect, connect_secret_service, find_collection, service_proxy};
+use crate::item_create::parse_duration;
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use log::debug;
+use secret_service::{Item, SecretService};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::Duration;
+use tks_service::storage::expiry;
+use tks_service::storage::oauth::{
+    TokenMetadata, ACCOUNT_ATTRIBUTE, ISSUER_ATTRIBUTE, SCHEMA, SCOPES_ATTRIBUTE,
+};
+
+const OAUTH_INTERFACE: &str = "io.linux_tks.OAuth1";
+
+/// Content type of the tokens, as the token endpoint answered them
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+#[derive(Parser, Debug)]
+pub struct OauthStoreCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection to keep the token in: an alias or a label
+    pub collection: String,
+    #[clap(long)]
+    /// Issuer of the token, e.g. https://accounts.google.com
+    pub issuer: String,
+    #[clap(long)]
+    /// User the token was granted for, e.g. their email address
+    pub account: String,
+    #[clap(long = "scope")]
+    /// Granted scope, may be repeated; by default the `scope` of the answer
+    pub scopes: Vec<String>,
+    #[clap(long, value_parser = parse_duration)]
+    /// Lifetime of the access token, when the answer has no `expires_in`, e.g. 1h
+    pub expires_in: Option<Duration>,
+    #[clap(long)]
+    /// Token endpoint to refresh the token with, kept for the tools sharing it
+    pub token_endpoint: Option<String>,
+    #[clap(long)]
+    /// OAuth2 client the token was granted to, kept for the tools sharing it
+    pub client_id: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct OauthGetCmd {
+    #[clap(long)]
+    /// Issuer of the token; any by default
+    pub issuer: Option<String>,
+    #[clap(long)]
+    /// User the token was granted for; any by default
+    pub account: Option<String>,
+    #[clap(long = "scope")]
+    /// Scope the token should grant, may be repeated
+    pub scopes: Vec<String>,
+    #[clap(long, value_parser = parse_duration, default_value = "60")]
+    /// How long the access token should still be valid, e.g. 5m
+    pub min_validity: Duration,
+    #[clap(long)]
+    /// Print the whole JSON token, with its refresh token, rather than the access token
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct OauthListCmd {}
+
+/// The attributes finding the tokens; searching by scopes only finds the same grant
+fn search_attributes<'a>(
+    issuer: Option<&'a str>,
+    account: Option<&'a str>,
+    scopes: Option<&'a str>,
+) -> HashMap<&'a str, &'a str> {
+    let mut attributes = HashMap::from([("xdg:schema", SCHEMA)]);
+    let pairs = [
+        (ISSUER_ATTRIBUTE, issuer),
+        (ACCOUNT_ATTRIBUTE, account),
+        (SCOPES_ATTRIBUTE, scopes),
+    ];
+    for (name, value) in pairs {
+        if let Some(value) = value {
+            attributes.insert(name, value);
+        }
+    }
+    attributes
+}
+
+/// The JSON object of a token
+fn parse_token(secret: &[u8]) -> Result<Map<String, Value>> {
+    match serde_json::from_slice(secret).with_context(|| "The token is not JSON")? {
+        Value::Object(token) => Ok(token),
+        _ => anyhow::bail!("The token is not a JSON object"),
+    }
+}
+
+/// The refresh token of the token being replaced, if any
+async fn previous_refresh_token(ss: &SecretService<'_>, metadata: &TokenMetadata) -> Option<Value> {
+    let attributes = metadata.attributes();
+    let search = search_attributes(
+        Some(&metadata.issuer),
+        Some(&metadata.account),
+        attributes.get(SCOPES_ATTRIBUTE).map(String::as_str),
+    );
+    let result = ss.search_items(search).await.ok()?;
+    let item = result.unlocked.into_iter().next()?;
+    let token = parse_token(&item.get_secret().await.ok()?).ok()?;
+    token.get("refresh_token").cloned()
+}
+
+impl OauthStoreCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let mut input = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut input)
+            .with_context(|| "Cannot read the token from the standard input")?;
+        let mut token = parse_token(&input)?;
+        if !token.contains_key("access_token") {
+            anyhow::bail!("The token has no access_token");
+        }
+        let scopes = match (self.scopes.is_empty(), token.get("scope")) {
+            (false, _) => self.scopes.iter().cloned().collect(),
+            (true, Some(Value::String(scope))) => {
+                scope.split_whitespace().map(String::from).collect()
+            }
+            (true, _) => anyhow::bail!("The token has no scope, give them with --scope"),
+        };
+        let expires_in = match (
+            token.get("expires_in").and_then(Value::as_u64),
+            self.expires_in,
+        ) {
+            (Some(expires_in), _) => expires_in,
+            (None, Some(expires_in)) => expires_in.as_secs(),
+            (None, None) => anyhow::bail!("The token has no expires_in, give it with --expires-in"),
+        };
+        let mut metadata = TokenMetadata {
+            issuer: self.issuer.clone(),
+            account: self.account.clone(),
+            scopes,
+            expires_at: expiry::now() + expires_in,
+            refreshable: token.contains_key("refresh_token"),
+            token_endpoint: self.token_endpoint.clone(),
+            client_id: self.client_id.clone(),
+        };
+
+        let ss = connect_secret_service().await?;
+        let collection = find_collection(&ss, &self.collection).await?;
+        collection
+            .ensure_unlocked()
+            .await
+            .with_context(|| format!("Cannot unlock '{}'", self.collection))?;
+        if !metadata.refreshable {
+            if let Some(refresh_token) = previous_refresh_token(&ss, &metadata).await {
+                debug!("Keeping the refresh token of the previous token");
+                token.insert("refresh_token".to_string(), refresh_token);
+                metadata.refreshable = true;
+            }
+        }
+        let secret = serde_json::to_vec(&token)?;
+        let attributes = metadata.attributes();
+        let attributes = attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let item = collection
+            .create_item(
+                &metadata.label(),
+                attributes,
+                &secret,
+                true,
+                JSON_CONTENT_TYPE,
+            )
+            .await
+            .with_context(|| "Cannot store the token")?;
+        debug!("Stored {}", item.item_path.as_str());
+        println!(
+            "Stored the token of {} at {}, valid for {}s",
+            metadata.account.bold(),
+            metadata.issuer,
+            expires_in
+        );
+        Ok(())
+    }
+}
+
+impl OauthGetCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let (unlocked, locked): (Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>) =
+            service_proxy(&conn)
+                .method_call(
+                    OAUTH_INTERFACE,
+                    "FindTokens",
+                    (
+                        self.issuer.as_deref().unwrap_or_default(),
+                        self.account.as_deref().unwrap_or_default(),
+                        &self.scopes,
+                        self.min_validity.as_secs(),
+                    ),
+                )
+                .with_context(|| "Cannot look the tokens up")?;
+        // the unlocked ones first, rather than prompting
+        let Some(path) = unlocked.into_iter().chain(locked).next() else {
+            anyhow::bail!("No token valid for {}s", self.min_validity.as_secs());
+        };
+        debug!("Found {}", path);
+
+        let ss = connect_secret_service().await?;
+        let search = search_attributes(self.issuer.as_deref(), self.account.as_deref(), None);
+        let result = ss.search_items(search).await?;
+        let item: Item = result
+            .unlocked
+            .into_iter()
+            .chain(result.locked)
+            .find(|i| i.item_path.as_str() == &*path)
+            .with_context(|| format!("Cannot find {}", path))?;
+        item.ensure_unlocked()
+            .await
+            .with_context(|| "Cannot unlock the token")?;
+        let token = parse_token(&item.get_secret().await?)?;
+        match self.json {
+            true => println!("{}", serde_json::to_string(&token)?),
+            false => match token.get("access_token") {
+                Some(Value::String(access_token)) => println!("{}", access_token),
+                _ => anyhow::bail!("The token has no access_token"),
+            },
+        }
+        Ok(())
+    }
+}
+
+impl OauthListCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let ss = connect_secret_service().await?;
+        let result = ss.search_items(search_attributes(None, None, None)).await?;
+        let mut tokens = Vec::new();
+        for item in result.unlocked.iter().chain(result.locked.iter()) {
+            if let Some(token) = TokenMetadata::from_attributes(&item.get_attributes().await?) {
+                tokens.push(token);
+            }
+        }
+        if tokens.is_empty() {
+            println!("No token stored");
+            return Ok(());
+        }
+        tokens.sort_by(|a, b| (&a.issuer, &a.account).cmp(&(&b.issuer, &b.account)));
+        let now = expiry::now();
+        for token in tokens {
+            let validity = match token.expires_at.checked_sub(now) {
+                Some(left) if left > 0 => format!("valid for {}s", left),
+                _ => "expired".red().to_string(),
+            };
+            let refreshable = match token.refreshable {
+                true => ", refreshable",
+                false => "",
+            };
+            println!(
+                "{} at {}: {} {}",
+                token.account.bold(),
+                token.issuer,
+                token.scopes.into_iter().collect::<Vec<_>>().join(" "),
+                format!("({}{})", validity, refreshable).dimmed()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+//! Make the collections and items match a provisioning file, e.g. one generated by Nix or
+//! home-manager. The items it creates get the `tks:managed-by` attribute, set to the manager of
+//! the file; these items get updated to match the file, or deleted once they leave it, while the
+//! other items never get touched. Running it again without changing the file changes nothing.
+//!
+//! ```toml
+//! # tells apart the items of several provisioning files, defaults to "tks-cli"
+//! manager = "home-manager"
+//!
+//! [[items]]
+//! # an alias or a label, created when missing; defaults to "default"
+//! collection = "work"
+//! label = "VPN"
+//! attributes = { service = "vpn", user = "me" }
+//! # relative to the provisioning file; without it, the secret gets prompted for upon creation
+//! secret_file = "/run/secrets/vpn"
+//! ```
+//!
+//! Items are told apart by their collection and attributes. Text secrets lose the trailing newline
+//! of their file; `binary = true` stores the file as it is.
+
+use crate::dbus_client::{capabilities, connect_secret_service, find_collection};
+use crate::item_set::{text_secret, BINARY_CONTENT_TYPE, TEXT_CONTENT_TYPE};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use console::Term;
+use log::debug;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+/// The item attribute telling which provisioning file manages the item
+const MANAGED_BY_ATTRIBUTE: &str = "tks:managed-by";
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Provisioning {
+    #[serde(default = "Provisioning::default_manager")]
+    manager: String,
+    #[serde(default)]
+    items: Vec<ProvisionedItem>,
+}
+
+impl Provisioning {
+    fn default_manager() -> String {
+        "tks-cli".to_string()
+    }
+
+    fn check(&self) -> Result<()> {
+        anyhow::ensure!(!self.manager.is_empty(), "The manager cannot be empty");
+        let mut seen = HashSet::new();
+        for item in &self.items {
+            anyhow::ensure!(
+                !item.attributes.contains_key(MANAGED_BY_ATTRIBUTE),
+                "Item '{}' sets {}, which gets set to the manager",
+                item.label,
+                MANAGED_BY_ATTRIBUTE
+            );
+            let mut attributes: Vec<_> = item.attributes.iter().collect();
+            attributes.sort();
+            anyhow::ensure!(
+                seen.insert((&item.collection, attributes)),
+                "Item '{}' has the same attributes as another item of '{}'",
+                item.label,
+                item.collection
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ProvisionedItem {
+    #[serde(default = "ProvisionedItem::default_collection")]
+    collection: String,
+    label: String,
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+    secret_file: Option<PathBuf>,
+    #[serde(default)]
+    binary: bool,
+    content_type: Option<String>,
+}
+
+impl ProvisionedItem {
+    fn default_collection() -> String {
+        "default".to_string()
+    }
+
+    /// The attributes of the item in the storage
+    fn attributes<'a>(&'a self, manager: &'a str) -> HashMap<&'a str, &'a str> {
+        self.attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .chain([(MANAGED_BY_ATTRIBUTE, manager)])
+            .collect()
+    }
+
+    fn content_type(&self) -> &str {
+        match (&self.content_type, self.binary) {
+            (Some(content_type), _) => content_type,
+            (None, true) => BINARY_CONTENT_TYPE,
+            (None, false) => TEXT_CONTENT_TYPE,
+        }
+    }
+
+    /// Reads the secret file, if any, relative to the directory of the provisioning file
+    fn read_secret(&self, base: &Path) -> Result<Option<Vec<u8>>> {
+        let Some(file) = &self.secret_file else {
+            return Ok(None);
+        };
+        let path = base.join(file);
+        let secret = fs::read(&path)
+            .with_context(|| format!("Cannot read the secret of '{}'", self.label))?;
+        match self.binary {
+            true => Ok(Some(secret)),
+            false => Ok(Some(text_secret(secret).with_context(|| {
+                format!("{} is not text, set binary = true to store it", path.display())
+            })?)),
+        }
+    }
+
+    fn prompt_secret(&self) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            !self.binary,
+            "Item '{}' is binary, give its secret_file instead",
+            self.label
+        );
+        anyhow::ensure!(
+            std::io::stdin().is_terminal(),
+            "Item '{}' has no secret_file, and the standard input is not a terminal to prompt \
+             for its secret",
+            self.label
+        );
+        let term = Term::stderr();
+        term.write_str(&format!("Secret of '{}': ", self.label))?;
+        let secret = term.read_secure_line()?;
+        term.write_str("Confirm secret: ")?;
+        anyhow::ensure!(term.read_secure_line()? == secret, "The secrets do not match");
+        Ok(secret.into_bytes())
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ProvisionCmd {
+    #[clap(long)]
+    /// Provisioning file, listing the items to create, update or keep
+    pub file: PathBuf,
+    #[clap(long)]
+    /// Only tell what would change
+    pub dry_run: bool,
+}
+
+impl ProvisionCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let contents = fs::read_to_string(&self.file)
+            .with_context(|| format!("Cannot read '{}'", self.file.display()))?;
+        let provisioning: Provisioning = toml::from_str(&contents)
+            .with_context(|| format!("Invalid provisioning file '{}'", self.file.display()))?;
+        provisioning.check()?;
+        let base = self.file.parent().unwrap_or(Path::new("."));
+        let capabilities = capabilities()?;
+        let ss = connect_secret_service().await?;
+        let verb = |done: &'static str, planned: &'static str| match self.dry_run {
+            true => planned,
+            false => done,
+        };
+
+        // paths of the managed items still in the file
+        let mut kept = HashSet::new();
+        // collections missing from the storage, in a dry run
+        let mut planned = HashSet::new();
+        let mut changes = 0;
+        for provisioned in &provisioning.items {
+            let secret = provisioned.read_secret(base)?;
+            if let Some(secret) = &secret {
+                capabilities.check_secret(secret, provisioned.binary)?;
+            }
+            let collection = match find_collection(&ss, &provisioned.collection).await {
+                Ok(collection) => Some(collection),
+                Err(e) => {
+                    debug!("{}", e);
+                    anyhow::ensure!(
+                        capabilities.create_collection,
+                        "No collection named '{}', and the {} storage backend can't create \
+                         collections",
+                        provisioned.collection,
+                        capabilities.backend
+                    );
+                    if self.dry_run {
+                        // the next items of the collection get planned without it too
+                        if planned.insert(provisioned.collection.as_str()) {
+                            println!("Would create collection '{}'", provisioned.collection.bold());
+                            changes += 1;
+                        }
+                        None
+                    } else {
+                        let collection = ss
+                            .create_collection(&provisioned.collection, "")
+                            .await
+                            .with_context(|| {
+                                format!("Cannot create '{}'", provisioned.collection)
+                            })?;
+                        println!("Created collection '{}'", provisioned.collection.bold());
+                        changes += 1;
+                        Some(collection)
+                    }
+                }
+            };
+
+            let attributes = provisioned.attributes(&provisioning.manager);
+            let mut existing = Vec::new();
+            if let Some(collection) = &collection {
+                collection
+                    .ensure_unlocked()
+                    .await
+                    .with_context(|| format!("Cannot unlock '{}'", provisioned.collection))?;
+                for item in collection.search_items(attributes.clone()).await? {
+                    let item_attributes = item.get_attributes().await?;
+                    let same = item_attributes.len() == attributes.len()
+                        && attributes
+                            .iter()
+                            .all(|(k, v)| item_attributes.get(*k).map(String::as_str) == Some(*v));
+                    if same {
+                        existing.push(item);
+                    }
+                }
+            }
+            anyhow::ensure!(
+                existing.len() <= 1,
+                "{} items of '{}' match '{}', delete all but one",
+                existing.len(),
+                provisioned.collection,
+                provisioned.label
+            );
+
+            let content_type = provisioned.content_type();
+            match (existing.pop(), &collection) {
+                (Some(item), _) => {
+                    kept.insert(item.item_path.to_string());
+                    if item.get_label().await? != provisioned.label {
+                        println!(
+                            "{} the label of '{}'",
+                            verb("Updated", "Would update"),
+                            provisioned.label.bold()
+                        );
+                        changes += 1;
+                        if !self.dry_run {
+                            item.set_label(&provisioned.label).await?;
+                        }
+                    }
+                    let Some(secret) = &secret else {
+                        continue;
+                    };
+                    item.ensure_unlocked()
+                        .await
+                        .with_context(|| format!("Cannot unlock '{}'", provisioned.label))?;
+                    if item.get_secret().await? != *secret
+                        || item.get_secret_content_type().await? != content_type
+                    {
+                        println!(
+                            "{} the secret of '{}'",
+                            verb("Updated", "Would update"),
+                            provisioned.label.bold()
+                        );
+                        changes += 1;
+                        if !self.dry_run {
+                            item.set_secret(secret, content_type).await?;
+                        }
+                    }
+                }
+                (None, Some(collection)) if !self.dry_run => {
+                    let secret = match secret {
+                        Some(secret) => secret,
+                        None => provisioned.prompt_secret()?,
+                    };
+                    let item = collection
+                        .create_item(&provisioned.label, attributes, &secret, false, content_type)
+                        .await
+                        .with_context(|| format!("Cannot create item '{}'", provisioned.label))?;
+                    kept.insert(item.item_path.to_string());
+                    println!(
+                        "Created '{}' in '{}'",
+                        provisioned.label.bold(),
+                        provisioned.collection
+                    );
+                    changes += 1;
+                }
+                (None, _) => {
+                    println!(
+                        "Would create '{}' in '{}'",
+                        provisioned.label.bold(),
+                        provisioned.collection
+                    );
+                    changes += 1;
+                }
+            }
+        }
+
+        let managed = HashMap::from([(MANAGED_BY_ATTRIBUTE, provisioning.manager.as_str())]);
+        let result = ss.search_items(managed).await?;
+        for item in result.unlocked.iter().chain(result.locked.iter()) {
+            if kept.contains(&item.item_path.to_string()) {
+                continue;
+            }
+            let label = item.get_label().await?;
+            println!("{} '{}'", verb("Deleted", "Would delete"), label.bold());
+            changes += 1;
+            if !self.dry_run {
+                item.delete()
+                    .await
+                    .with_context(|| format!("Cannot delete item '{}'", label))?;
+            }
+        }
+
+        if changes == 0 {
+            println!("Nothing to change");
+        }
+        Ok(())
+    }
+}
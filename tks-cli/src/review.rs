@@ -0,0 +1,300 @@
+//! Review the setup of tks-service for weaknesses: weak key derivation, session keys which never
+//! expire, storage files other users may read, collections staying unlocked, clients remembered as
+//! allowed to read sensitive items through plain sessions, and missing or old backups. The
+//! findings get printed most important first, each with the command or setting fixing it.
+//!
+//! The review reads the configuration file and the state files of the service, asks the running
+//! service about its collections, and reads the headers of the backups in the given directory;
+//! it never reads any secret.
+
+use crate::dbus_client::{connect, service_proxy, SERVICE_NAME, TIMEOUT};
+use anyhow::Result;
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tks_service::settings::{self, Settings, StorageMount};
+use tks_service::storage::backup::{self, BackupHeader};
+use tks_service::storage::KDF_ITERATIONS;
+use tks_service::tks_dbus::plain_transfers::{self, PlainTransferChoices};
+
+#[derive(Parser, Debug)]
+pub struct ReviewCmd {
+    #[clap(long)]
+    /// Configuration file to review instead of the one the service reads
+    pub config: Option<String>,
+    #[clap(long)]
+    /// Directory holding the backups made by `tks-cli backup create`
+    pub backups: Option<PathBuf>,
+    #[clap(long, default_value = "30")]
+    /// Days after which the latest backup is too old
+    pub backup_age: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    High,
+    Medium,
+    Low,
+}
+
+struct Finding {
+    priority: Priority,
+    problem: String,
+    fix: String,
+}
+
+impl Finding {
+    fn new(priority: Priority, problem: String, fix: String) -> Finding {
+        Finding {
+            priority,
+            problem,
+            fix,
+        }
+    }
+}
+
+impl ReviewCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let config_path = match &self.config {
+            Some(path) => path.clone(),
+            None => Settings::config_path().map_err(|e| anyhow::anyhow!("{}", e))?,
+        };
+        let mut findings = Vec::new();
+        match Settings::check(&config_path) {
+            Ok(settings) => {
+                findings.extend(review_storage(&settings, &config_path));
+                findings.extend(review_sessions(&settings, &config_path));
+                findings.extend(review_unlocked_collections(&settings, &config_path));
+            }
+            Err(problems) => findings.push(Finding::new(
+                Priority::High,
+                format!("The configuration {} has {} problem(s)", config_path, problems.len()),
+                format!("tks-cli service check-config --config {}", config_path),
+            )),
+        }
+        findings.extend(review_plain_transfers());
+        if let Some(dir) = &self.backups {
+            findings.extend(review_backups(dir, Duration::from_secs(self.backup_age * 86400)));
+        }
+
+        if findings.is_empty() {
+            println!("{}", "No issue found".green());
+            return Ok(());
+        }
+        findings.sort_by_key(|f| f.priority);
+        for finding in &findings {
+            let priority = match finding.priority {
+                Priority::High => "HIGH  ".red().bold(),
+                Priority::Medium => "MEDIUM".yellow().bold(),
+                Priority::Low => "LOW   ".bold(),
+            };
+            println!("{} {}", priority, finding.problem);
+            println!("       {} {}", "fix:".dimmed(), finding.fix);
+        }
+        println!("{} recommendation(s)", findings.len());
+        Ok(())
+    }
+}
+
+/// The tks_gcm backends, by mount name, with their settings
+fn tks_gcm_backends(settings: &Settings) -> Vec<(String, settings::Storage)> {
+    let main = (StorageMount::MAIN.to_string(), settings.storage.clone());
+    let mounts = settings
+        .storage
+        .mounts
+        .iter()
+        .map(|m| (m.name.clone(), m.settings(&settings.storage)));
+    std::iter::once(main)
+        .chain(mounts)
+        .filter(|(_, storage)| storage.kind == "tks_gcm")
+        .collect()
+}
+
+fn review_storage(settings: &Settings, config_path: &str) -> Vec<Finding> {
+    let backends = tks_gcm_backends(settings);
+    let mut findings = Vec::new();
+    if !backends.is_empty() && KDF_ITERATIONS < backup::KDF_ITERATIONS {
+        findings.push(Finding::new(
+            Priority::Medium,
+            format!(
+                "The tks_gcm storage key gets derived with {} PBKDF2 iterations, so short \
+                 passwords get guessed quickly from a copy of the storage files",
+                KDF_ITERATIONS
+            ),
+            "unlock with a long passphrase, or with a key file set in storage.keyfiles".to_string(),
+        ));
+    }
+    for (name, storage) in backends {
+        let Ok(path) = storage.resolved_path() else {
+            continue;
+        };
+        // the metadata, i.e. the labels, attributes and history, isn't encrypted
+        let mode = match fs::metadata(&path) {
+            Ok(metadata) => metadata.permissions().mode(),
+            Err(_) => continue,
+        };
+        if mode & 0o077 != 0 {
+            findings.push(Finding::new(
+                Priority::High,
+                format!(
+                    "Other users may read the unencrypted labels and attributes of the '{}' \
+                     storage in {}",
+                    name,
+                    path.display()
+                ),
+                format!("chmod -R go-rwx {}", path.display()),
+            ));
+        }
+        if storage.per_item_files && !storage.pad_item_files {
+            findings.push(Finding::new(
+                Priority::Low,
+                format!("The item files of the '{}' storage tell the length of their secret", name),
+                format!("set pad_item_files = true for the '{}' storage in {}", name, config_path),
+            ));
+        }
+    }
+    findings
+}
+
+fn review_sessions(settings: &Settings, config_path: &str) -> Vec<Finding> {
+    match (settings.session.max_age, settings.session.max_uses) {
+        (None, None) => vec![Finding::new(
+            Priority::Low,
+            "The transport keys of the encrypted sessions never expire".to_string(),
+            format!("set max_age = 3600 in the [session] section of {}", config_path),
+        )],
+        _ => Vec::new(),
+    }
+}
+
+fn review_unlocked_collections(settings: &Settings, config_path: &str) -> Vec<Finding> {
+    let auto_lock = &settings.auto_lock;
+    if auto_lock.lock_after_idle_minutes.is_some() || !auto_lock.lock_on.is_empty() {
+        return Vec::new();
+    }
+    let unlocked = match unlocked_collections() {
+        Ok(unlocked) => unlocked,
+        Err(e) => {
+            return vec![Finding::new(
+                Priority::Low,
+                format!("Cannot list the collections, they didn't get reviewed: {}", e),
+                "start tks-service, then review again".to_string(),
+            )]
+        }
+    };
+    if unlocked.is_empty() {
+        return Vec::new();
+    }
+    vec![Finding::new(
+        Priority::Medium,
+        format!(
+            "Collections {} stay unlocked until locked by hand, nothing locks them automatically",
+            unlocked.join(", ")
+        ),
+        format!(
+            "set lock_after_idle_minutes = 15 in the [auto_lock] section of {}, and run tks-cli \
+             collection lock <collection> meanwhile",
+            config_path
+        ),
+    )]
+}
+
+/// Labels of the unlocked collections
+fn unlocked_collections() -> Result<Vec<String>> {
+    let conn = connect()?;
+    let collections: Vec<dbus::Path<'static>> =
+        service_proxy(&conn).get("org.freedesktop.Secret.Service", "Collections")?;
+    let mut unlocked = Vec::new();
+    for path in collections {
+        let proxy = conn.with_proxy(SERVICE_NAME, &path, TIMEOUT);
+        let locked: bool = proxy.get("org.freedesktop.Secret.Collection", "Locked")?;
+        if !locked {
+            unlocked.push(proxy.get("org.freedesktop.Secret.Collection", "Label")?);
+        }
+    }
+    Ok(unlocked)
+}
+
+fn review_plain_transfers() -> Vec<Finding> {
+    let Ok(path) = plain_transfers::choices_path() else {
+        return Vec::new();
+    };
+    let Ok(choices) = PlainTransferChoices::load(&path) else {
+        return Vec::new();
+    };
+    let allowed = choices.allowed_clients();
+    let (installed, gone): (Vec<&str>, Vec<&str>) =
+        allowed.into_iter().partition(|exe_path| Path::new(exe_path).exists());
+    let mut findings = Vec::new();
+    if !gone.is_empty() {
+        findings.push(Finding::new(
+            Priority::Medium,
+            format!(
+                "Executables no longer installed, {}, may still read sensitive items through \
+                 plain sessions; another program installed there would too",
+                gone.join(", ")
+            ),
+            format!("remove their entries from {}", path.display()),
+        ));
+    }
+    if !installed.is_empty() {
+        findings.push(Finding::new(
+            Priority::Low,
+            format!(
+                "{} may read sensitive items through plain sessions without asking",
+                installed.join(", ")
+            ),
+            format!("remove their entries from {} to get asked again", path.display()),
+        ));
+    }
+    findings
+}
+
+fn review_backups(dir: &Path, max_age: Duration) -> Vec<Finding> {
+    let create = format!("tks-cli backup create {}", dir.join("tks.backup").display());
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let Ok(archive) = fs::read(&path) else {
+            continue;
+        };
+        let Ok(header) = BackupHeader::read(&archive) else {
+            continue;
+        };
+        let modified = entry.metadata().and_then(|m| m.modified()).ok();
+        backups.push((path, header, modified));
+    }
+    let Some(latest) = backups.iter().filter_map(|(_, _, modified)| *modified).max() else {
+        return vec![Finding::new(
+            Priority::High,
+            format!("No backup found in {}", dir.display()),
+            create,
+        )];
+    };
+    let mut findings = Vec::new();
+    let age = SystemTime::now().duration_since(latest).unwrap_or_default();
+    if age > max_age {
+        findings.push(Finding::new(
+            Priority::Medium,
+            format!("The latest backup in {} is {} days old", dir.display(), age.as_secs() / 86400),
+            create.clone(),
+        ));
+    }
+    let weak: Vec<String> = backups
+        .iter()
+        .filter(|(_, header, _)| header.iterations < backup::KDF_ITERATIONS)
+        .map(|(path, _, _)| path.display().to_string())
+        .collect();
+    if !weak.is_empty() {
+        findings.push(Finding::new(
+            Priority::Low,
+            format!("Backups {} use a weaker key derivation than the new ones", weak.join(", ")),
+            format!("{}, then delete them", create),
+        ));
+    }
+    findings
+}
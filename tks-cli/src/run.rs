@@ -0,0 +1,85 @@
+//! Run a command with secrets in its environment, e.g. a script needing a database password,
+//! instead of keeping them in a plaintext `.env` file. The secrets get read from the service, then
+//! tks-cli replaces itself with the command: they never get written to disk, nor show in the
+//! command line of the process.
+
+use crate::dbus_client::{connect_secret_service, find_item};
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::debug;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+#[derive(Parser, Debug)]
+pub struct RunCmd {
+    #[clap(long)]
+    /// Only look in this collection: an alias or a label
+    pub collection: Option<String>,
+    #[clap(long = "env", value_name = "NAME=SPEC", required = true)]
+    /// Environment variable to set to the secret of an item: `attr:name=value[,name=value...]`
+    /// matches the item attributes, `label:text` its label; exactly one item should match
+    pub envs: Vec<String>,
+    #[clap(required = true, last = true)]
+    /// The command to run, and its arguments
+    pub command: Vec<String>,
+}
+
+impl RunCmd {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let envs = self
+            .envs
+            .iter()
+            .map(|env| parse_env(env))
+            .collect::<Result<Vec<_>>>()?;
+        let ss = connect_secret_service().await?;
+        let mut command = Command::new(&self.command[0]);
+        command.args(&self.command[1..]);
+        for (name, search) in envs {
+            let item = find_item(&ss, self.collection.as_deref(), &search)
+                .await
+                .with_context(|| format!("Cannot find the secret of {}", name))?;
+            let secret = item.get_secret().await?;
+            anyhow::ensure!(
+                !secret.contains(&0),
+                "The secret of {} holds a NUL byte, it cannot go into the environment",
+                name
+            );
+            debug!("Setting {} from '{}'", name, item.get_label().await?);
+            command.env(name, OsString::from_vec(secret));
+        }
+        // only returns on failure
+        let e = command.exec();
+        Err(e).with_context(|| format!("Cannot run '{}'", self.command[0]))
+    }
+}
+
+/// The variable name, and the search terms of [find_item], of a `NAME=SPEC` option
+fn parse_env(env: &str) -> Result<(&str, Vec<String>)> {
+    let (name, spec) = env
+        .split_once('=')
+        .with_context(|| format!("Invalid --env '{}', use NAME=SPEC", env))?;
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains('\0'),
+        "Invalid variable name in --env '{}'",
+        env
+    );
+    let search = match spec.split_once(':') {
+        Some(("attr", terms)) => {
+            let terms: Vec<String> = terms.split(',').map(String::from).collect();
+            anyhow::ensure!(
+                terms.iter().all(|t| t.contains('=')),
+                "Invalid --env '{}', attr: takes name=value terms",
+                env
+            );
+            terms
+        }
+        Some(("label", label)) if !label.contains('=') => vec![label.to_string()],
+        _ => anyhow::bail!(
+            "Invalid --env '{}', the secret is either attr:name=value or label:text",
+            env
+        ),
+    };
+    Ok((name, search))
+}
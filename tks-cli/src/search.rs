@@ -0,0 +1,160 @@
+//! Find the items by what one remembers of them: the text is looked for in the labels and in the
+//! values of all the attributes, ignoring case, through `io.linux_tks.Search1.SearchText`. Other
+//! Secret Service implementations lack this extension, their items then get listed with
+//! SearchItems and matched here, without the regular expressions. The secrets are never read.
+
+use crate::dbus_client::{connect, service_proxy, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use log::debug;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+type ItemPaths = Vec<dbus::Path<'static>>;
+
+/// What the services lacking SearchText answer
+const UNKNOWN_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.UnknownInterface",
+    "org.freedesktop.DBus.Error.UnknownMethod",
+];
+
+#[derive(Parser, Debug)]
+pub struct SearchCmd {
+    /// Text to look for in the labels and the attribute values, ignoring case
+    pub query: String,
+    #[clap(long)]
+    /// The query is a regular expression
+    pub regex: bool,
+    #[clap(long)]
+    /// Print the matches as a JSON array, with the attributes of the items
+    pub json: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Match {
+    path: String,
+    label: String,
+    collection: String,
+    collection_path: String,
+    locked: bool,
+    attributes: BTreeMap<String, String>,
+}
+
+impl SearchCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let (unlocked, locked, filter) = self.search(&conn)?;
+        let mut collections = HashMap::new();
+        let mut matches = Vec::new();
+        for (path, locked) in unlocked
+            .into_iter()
+            .map(|p| (p, false))
+            .chain(locked.into_iter().map(|p| (p, true)))
+        {
+            let proxy = conn.with_proxy(SERVICE_NAME, &path, TIMEOUT);
+            let label: String = proxy.get("org.freedesktop.Secret.Item", "Label")?;
+            let attributes: HashMap<String, String> =
+                proxy.get("org.freedesktop.Secret.Item", "Attributes")?;
+            if filter && !self.matches(&label, &attributes) {
+                continue;
+            }
+            let collection_path = path.rsplit_once('/').map_or("", |(c, _)| c).to_string();
+            if !collections.contains_key(&collection_path) {
+                let label: String = conn
+                    .with_proxy(SERVICE_NAME, collection_path.as_str(), TIMEOUT)
+                    .get("org.freedesktop.Secret.Collection", "Label")
+                    .unwrap_or_default();
+                collections.insert(collection_path.clone(), label);
+            }
+            matches.push(Match {
+                path: path.to_string(),
+                label,
+                collection: collections[&collection_path].clone(),
+                collection_path,
+                locked,
+                attributes: attributes.into_iter().collect(),
+            });
+        }
+        matches.sort_by(|a, b| (&a.collection, &a.label).cmp(&(&b.collection, &b.label)));
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&matches)?);
+            return Ok(());
+        }
+        if matches.is_empty() {
+            println!("No item matches '{}'", self.query);
+            return Ok(());
+        }
+        print_table(&matches);
+        Ok(())
+    }
+
+    /// The unlocked and locked items found by the service, through SearchText when it has it;
+    /// otherwise all the items, which then need to be filtered
+    fn search(&self, conn: &Connection) -> Result<(ItemPaths, ItemPaths, bool)> {
+        let service = service_proxy(conn);
+        let found: Result<(ItemPaths, ItemPaths), dbus::Error> = service.method_call(
+            "io.linux_tks.Search1",
+            "SearchText",
+            (self.query.as_str(), self.regex),
+        );
+        match found {
+            Ok((unlocked, locked)) => Ok((unlocked, locked, false)),
+            Err(e) if UNKNOWN_ERRORS.contains(&e.name().unwrap_or_default()) => {
+                debug!("The service has no SearchText: {}", e);
+                anyhow::ensure!(!self.regex, "The service can't search regular expressions");
+                // no attributes match all the items
+                let all: HashMap<&str, &str> = HashMap::new();
+                let (unlocked, locked): (ItemPaths, ItemPaths) = service
+                    .method_call("org.freedesktop.Secret.Service", "SearchItems", (all,))
+                    .with_context(|| "Cannot list the items")?;
+                Ok((unlocked, locked, true))
+            }
+            Err(e) => Err(e).with_context(|| format!("Cannot search '{}'", self.query)),
+        }
+    }
+
+    /// Whether the label or an attribute value contains the query, as SearchText would tell
+    fn matches(&self, label: &str, attributes: &HashMap<String, String>) -> bool {
+        let query = self.query.to_lowercase();
+        std::iter::once(label)
+            .chain(attributes.values().map(String::as_str))
+            .any(|text| text.to_lowercase().contains(&query))
+    }
+}
+
+fn print_table(matches: &[Match]) {
+    let width = |column: fn(&Match) -> &str, title: &str| {
+        let widest = matches.iter().map(|m| column(m).chars().count()).max();
+        widest.unwrap_or(0).max(title.len())
+    };
+    let collection_width = width(|m| &m.collection, "COLLECTION");
+    let label_width = width(|m| &m.label, "LABEL");
+    println!(
+        "{}",
+        format!(
+            "{:<c$}  {:<l$}  PATH",
+            "COLLECTION",
+            "LABEL",
+            c = collection_width,
+            l = label_width
+        )
+        .bold()
+    );
+    for m in matches {
+        let label = format!("{:<l$}", m.label, l = label_width);
+        println!(
+            "{:<c$}  {}  {}",
+            m.collection,
+            if m.locked {
+                label.dimmed()
+            } else {
+                label.normal()
+            },
+            m.path,
+            c = collection_width
+        );
+    }
+}
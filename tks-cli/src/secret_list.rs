@@ -0,0 +1,81 @@
+//! List the items of a collection, either flat or as the folder tree given by their `tks:path`
+//! attribute.
+
+use crate::dbus_client::{connect, resolve_collection, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::collections::HashMap;
+
+const PATH_ATTRIBUTE: &str = "tks:path";
+
+#[derive(Parser, Debug)]
+pub struct SecretListCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection to list: an alias, a label or an object path
+    pub collection: String,
+    #[clap(long)]
+    /// Show the items organized in folders, after their `tks:path` attribute
+    pub tree: bool,
+}
+
+impl SecretListCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.collection)?;
+        if self.tree {
+            println!("{}", self.collection.bold());
+            return print_folder(&conn, &collection, "", "");
+        }
+        let items: Vec<dbus::Path<'static>> = conn
+            .with_proxy(SERVICE_NAME, &collection, TIMEOUT)
+            .get("org.freedesktop.Secret.Collection", "Items")?;
+        for item in items {
+            let proxy = conn.with_proxy(SERVICE_NAME, &item, TIMEOUT);
+            let label: String = proxy.get("org.freedesktop.Secret.Item", "Label")?;
+            let attributes: HashMap<String, String> =
+                proxy.get("org.freedesktop.Secret.Item", "Attributes")?;
+            match attributes.get(PATH_ATTRIBUTE).map(|p| p.trim_matches('/')) {
+                Some(folder) if !folder.is_empty() => println!("{}/{}", folder, label),
+                _ => println!("{}", label),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints the subfolders and the items of a folder, each line starting with `indent`
+fn print_folder(
+    conn: &Connection,
+    collection: &dbus::Path<'static>,
+    folder: &str,
+    indent: &str,
+) -> Result<()> {
+    let (subfolders, items): (Vec<String>, Vec<dbus::Path<'static>>) = conn
+        .with_proxy(SERVICE_NAME, collection, TIMEOUT)
+        .method_call("io.linux_tks.Collection1", "ListFolder", (folder,))
+        .with_context(|| format!("Cannot list folder '{}'", folder))?;
+    let mut labels = Vec::new();
+    for item in items {
+        let label: String = conn
+            .with_proxy(SERVICE_NAME, &item, TIMEOUT)
+            .get("org.freedesktop.Secret.Item", "Label")?;
+        labels.push(label);
+    }
+    labels.sort();
+    let count = subfolders.len() + labels.len();
+    for (i, subfolder) in subfolders.iter().enumerate() {
+        let last = i + 1 == count;
+        let name = subfolder.rsplit('/').next().unwrap_or(subfolder);
+        println!("{}{}{}/", indent, if last { "└── " } else { "├── " }, name.blue().bold());
+        let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+        print_folder(conn, collection, subfolder, &indent)?;
+    }
+    for (i, label) in labels.iter().enumerate() {
+        let last = subfolders.len() + i + 1 == count;
+        println!("{}{}{}", indent, if last { "└── " } else { "├── " }, label);
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! Move or copy items between collections. The service re-homes the items itself, so the secrets
+//! never reach this process.
+
+use crate::dbus_client::{connect, resolve_collection, service_proxy, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use log::debug;
+use std::collections::HashMap;
+
+#[derive(Parser, Debug)]
+pub struct SecretMoveCmd {
+    #[clap(long)]
+    /// Collection holding the items: an alias, a label or an object path
+    pub from: String,
+    #[clap(long)]
+    /// Collection receiving the items: an alias, a label or an object path
+    pub to: String,
+    #[clap(required = true)]
+    /// Items to act on: `name=value` terms match the item attributes, other terms match the label;
+    /// items should match all the terms
+    pub search: Vec<String>,
+}
+
+impl SecretMoveCmd {
+    pub(crate) fn run(&self, copy: bool) -> Result<()> {
+        let conn = connect()?;
+        let from = resolve_collection(&conn, &self.from)?;
+        let to = resolve_collection(&conn, &self.to)?;
+        let items = self.find_items(&conn, &from)?;
+        if items.is_empty() {
+            anyhow::bail!("No item matching {:?} in '{}'", self.search, self.from);
+        }
+        let service = service_proxy(&conn);
+        for (item, label) in items {
+            debug!("{} {} to {}", if copy { "Copying" } else { "Moving" }, item, to);
+            let (result,): (dbus::Path<'static>,) = service
+                .method_call("io.linux_tks.Service1", "MoveItem", (item, to.clone(), copy))
+                .with_context(|| format!("Cannot move item '{}'", label))?;
+            debug!("Item is now {}", result);
+            println!(
+                "{} '{}' to '{}'",
+                if copy { "Copied" } else { "Moved" },
+                label.bold(),
+                self.to
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the paths and labels of the items matching all the search terms
+    fn find_items(
+        &self,
+        conn: &Connection,
+        collection: &dbus::Path<'static>,
+    ) -> Result<Vec<(dbus::Path<'static>, String)>> {
+        let items: Vec<dbus::Path<'static>> = conn
+            .with_proxy(SERVICE_NAME, collection, TIMEOUT)
+            .get("org.freedesktop.Secret.Collection", "Items")?;
+        let mut found = Vec::new();
+        for item in items {
+            let proxy = conn.with_proxy(SERVICE_NAME, &item, TIMEOUT);
+            let label: String = proxy.get("org.freedesktop.Secret.Item", "Label")?;
+            let attributes: HashMap<String, String> =
+                proxy.get("org.freedesktop.Secret.Item", "Attributes")?;
+            let matches = self.search.iter().all(|term| match term.split_once('=') {
+                Some((name, value)) => attributes.get(name).is_some_and(|v| v == value),
+                None => label == *term,
+            });
+            if matches {
+                found.push((item, label));
+            }
+        }
+        Ok(found)
+    }
+}
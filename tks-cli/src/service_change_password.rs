@@ -0,0 +1,54 @@
+//! Change the unlock password of the storage, or the one of a collection having its own, see
+//! `storage.per_collection_keys`. The service encrypts the storage files again with the new
+//! password; when it fails to, the old password keeps working.
+
+use crate::dbus_client::{connect, resolve_collection, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use console::Term;
+use log::debug;
+
+#[derive(Parser, Debug)]
+pub struct ServiceChangePasswordCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection whose password changes, along with the collections sharing it: an alias, a
+    /// label or an object path
+    pub collection: String,
+}
+
+pub(crate) fn read_password(prompt: &str) -> Result<String> {
+    let term = Term::stderr();
+    term.write_str(prompt)?;
+    Ok(term.read_secure_line()?)
+}
+
+impl ServiceChangePasswordCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.collection)?;
+        let old_password = read_password("Current password: ")?;
+        let new_password = read_password("New password: ")?;
+        if new_password.is_empty() {
+            anyhow::bail!("The new password cannot be empty");
+        }
+        if read_password("Confirm new password: ")? != new_password {
+            anyhow::bail!("Passwords do not match");
+        }
+
+        debug!("Changing the password of {}", collection);
+        let (collections,): (Vec<dbus::Path<'static>>,) = service_proxy(&conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "ChangePassword",
+                (collection, old_password, new_password),
+            )
+            .with_context(|| "The password was not changed")?;
+        println!(
+            "{}, it unlocks {} collection(s)",
+            "Password changed".green(),
+            collections.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+//! Validate the tks-service configuration without starting the daemon, e.g. after editing it.
+
+use anyhow::Result;
+use clap::Parser;
+use colored::Colorize;
+use tks_service::settings::Settings;
+
+#[derive(Parser, Debug)]
+pub struct ServiceCheckConfigCmd {
+    #[clap(long)]
+    /// Configuration file to check instead of the one the service would read
+    pub config: Option<String>,
+}
+
+impl ServiceCheckConfigCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let config_path = match &self.config {
+            Some(path) => path.clone(),
+            None => Settings::config_path().map_err(|e| anyhow::anyhow!("{}", e))?,
+        };
+        match Settings::check(&config_path) {
+            Ok(_) => {
+                println!("{}: {}", config_path, "OK".green());
+                Ok(())
+            }
+            Err(problems) => {
+                println!("{}: {} problem(s) found", config_path, problems.len());
+                for problem in &problems {
+                    println!("{} {}", "ERROR".red().bold(), problem);
+                }
+                anyhow::bail!("Invalid configuration")
+            }
+        }
+    }
+}
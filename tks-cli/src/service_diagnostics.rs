@@ -0,0 +1,25 @@
+//! Show the diagnostics counters of tks-service, to watch for leaks: the DBus objects, sessions
+//! and prompts it holds on to, and its memory usage. The counters get printed one per line,
+//! `name: value`, sorted by name, the memory being in bytes.
+
+use crate::dbus_client::{connect, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+pub struct ServiceDiagnosticsCmd {}
+
+impl ServiceDiagnosticsCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let (counters,): (BTreeMap<String, u64>,) = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "GetDiagnostics", ())
+            .with_context(|| "Cannot get the service diagnostics")?;
+        let width = counters.keys().map(String::len).max().unwrap_or(0);
+        for (name, value) in counters {
+            println!("{:width$} {}", format!("{}:", name), value, width = width + 1);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+//! Make tks-service lock the collections and forget the keys which lately unlocked them, see the
+//! `prompt.cache_ttl` setting, so that unlocking them asks their password again, e.g. before
+//! leaving the computer.
+
+use crate::dbus_client::{connect, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+#[derive(Parser, Debug)]
+pub struct ServiceForgetCmd {}
+
+impl ServiceForgetCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let () = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "ForgetKeys", ())
+            .with_context(|| "The cached keys were not forgotten")?;
+        println!("{}", "Cached keys forgotten".green());
+        Ok(())
+    }
+}
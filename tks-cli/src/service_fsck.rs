@@ -0,0 +1,44 @@
+//! Check the storage files of the running service: the collection metadata, and the secrets of
+//! the unlocked collections, which otherwise only fail when unlocking. `--repair` moves the
+//! damaged files to the quarantine directory of the storage and drops the items having lost
+//! their secret.
+
+use crate::dbus_client::{connect, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use log::debug;
+
+#[derive(Parser, Debug)]
+pub struct ServiceFsckCmd {
+    #[clap(long)]
+    /// Quarantine the damaged files and drop the items having lost their secret
+    pub repair: bool,
+}
+
+impl ServiceFsckCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        debug!("Checking the storage (repair: {})", self.repair);
+        let (findings,): (Vec<(String, String, String, bool)>,) = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "CheckStorage", (self.repair,))
+            .with_context(|| "Cannot check the storage")?;
+        let mut remaining = 0;
+        for (kind, subject, detail, repaired) in &findings {
+            let status = if kind == "skipped" {
+                "SKIPPED".yellow().bold()
+            } else if *repaired {
+                "REPAIRED".green().bold()
+            } else {
+                remaining += 1;
+                "ERROR".red().bold()
+            };
+            println!("{} {} {}: {}", status, kind, subject, detail);
+        }
+        if remaining > 0 {
+            anyhow::bail!("{} problem(s) found", remaining);
+        }
+        println!("Storage: {}", "OK".green());
+        Ok(())
+    }
+}
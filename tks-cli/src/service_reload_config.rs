@@ -0,0 +1,29 @@
+//! Make tks-service apply the changes of its configuration file right away. The service also
+//! does it by itself when the file gets saved; this tells about the problems which kept the
+//! changes from applying.
+
+use crate::dbus_client::{connect, service_proxy};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+#[derive(Parser, Debug)]
+pub struct ServiceReloadConfigCmd {}
+
+impl ServiceReloadConfigCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let (restart_needed,): (Vec<String>,) = service_proxy(&conn)
+            .method_call("io.linux_tks.Service1", "ReloadConfig", ())
+            .with_context(|| "The configuration was not reloaded")?;
+        println!("{}", "Configuration reloaded".green());
+        for setting in restart_needed {
+            println!(
+                "{}: {} only changes after restarting the service",
+                "WARNING".bold(),
+                setting
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Ask tks-service to display dummy prompts, so broken pinentry setups get noticed before a real
+//! unlock needs them.
+
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use dbus::blocking::Connection;
+use log::debug;
+use std::time::Duration;
+
+/// The prompt dialogs wait for the user, so give them plenty of time
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PromptKind {
+    Message,
+    Confirmation,
+    Passphrase,
+}
+
+impl PromptKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PromptKind::Message => "message",
+            PromptKind::Confirmation => "confirmation",
+            PromptKind::Passphrase => "passphrase",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ServiceTestPromptCmd {
+    #[clap(long, short = 'k', value_enum)]
+    /// Only test this prompt kind; all kinds get tested by default
+    pub kind: Option<PromptKind>,
+}
+
+impl ServiceTestPromptCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = Connection::new_session().with_context(|| "Cannot connect to the session bus")?;
+        let proxy = conn.with_proxy(
+            "org.freedesktop.secrets",
+            "/org/freedesktop/secrets",
+            PROMPT_TIMEOUT,
+        );
+        let kinds = match self.kind {
+            Some(kind) => vec![kind],
+            None => vec![
+                PromptKind::Message,
+                PromptKind::Confirmation,
+                PromptKind::Passphrase,
+            ],
+        };
+        let mut failures = 0;
+        for kind in kinds {
+            println!("Testing {} prompt...", kind.as_str().bold());
+            let result: Result<(bool,), dbus::Error> =
+                proxy.method_call("io.linux_tks.Service1", "TestPrompt", (kind.as_str(),));
+            match result {
+                Ok((false,)) => println!("  {}", "OK".green()),
+                Ok((true,)) => println!("  {}", "OK (dismissed by the user)".green()),
+                Err(e) => {
+                    debug!("TestPrompt error: {:?}", e);
+                    failures += 1;
+                    println!(
+                        "  {}: {}",
+                        "FAILED".red(),
+                        e.message().unwrap_or("unknown error")
+                    );
+                }
+            }
+        }
+        if failures > 0 {
+            anyhow::bail!("{} prompt(s) could not be displayed", failures);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Answer the password dialogs of the service from a terminal. On a server without any display,
+//! reached over SSH for instance, the service waits for this command instead of showing pinentry
+//! once its `prompt.backend` setting is `remote`. Without any dialog pending, the collection gets
+//! unlocked first, which brings up its dialog.
+
+use crate::dbus_client::{connect, resolve_collection, service_proxy, SERVICE_NAME, TIMEOUT};
+use crate::service_change_password::read_password;
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use log::debug;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the service may take to bring up the dialog, or to unlock once answered
+const DIALOG_DELAY: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The id, description and prompt of each pending dialog, and whether it asks the password twice
+type PendingPassphrases = Vec<(u32, String, String, bool)>;
+
+#[derive(Parser, Debug)]
+pub struct ServiceUnlockCmd {
+    #[clap(long, default_value = "default")]
+    /// Collection to unlock when no dialog is pending: an alias, a label or an object path
+    pub collection: String,
+}
+
+impl ServiceUnlockCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let mut pending = pending_passphrases(&conn)?;
+        let mut unlocking = None;
+        if pending.is_empty() {
+            let collection = resolve_collection(&conn, &self.collection)?;
+            if !start_unlock(&conn, &collection)? {
+                println!("'{}' is already unlocked", self.collection.bold());
+                return Ok(());
+            }
+            pending = wait_for(DIALOG_DELAY, || {
+                let pending = pending_passphrases(&conn)?;
+                Ok((!pending.is_empty()).then_some(pending))
+            })?
+            .with_context(|| {
+                "The service shows no password dialog, its prompt.backend should be remote"
+            })?;
+            unlocking = Some(collection);
+        }
+        for (id, description, prompt, confirmation) in pending {
+            answer(&conn, id, &description, &prompt, confirmation)?;
+        }
+        let Some(collection) = unlocking else {
+            return Ok(());
+        };
+        let proxy = conn.with_proxy(SERVICE_NAME, &collection, TIMEOUT);
+        wait_for(DIALOG_DELAY, || {
+            let locked: bool = proxy.get("org.freedesktop.Secret.Collection", "Locked")?;
+            Ok((!locked).then_some(()))
+        })?
+        .with_context(|| format!("'{}' is still locked, wrong password?", self.collection))?;
+        println!("Unlocked '{}'", self.collection.bold());
+        Ok(())
+    }
+}
+
+fn pending_passphrases(conn: &Connection) -> Result<PendingPassphrases> {
+    let (pending,): (PendingPassphrases,) = service_proxy(conn)
+        .method_call("io.linux_tks.Service1", "PendingPassphrases", ())
+        .with_context(|| "Cannot list the password dialogs of the service")?;
+    Ok(pending)
+}
+
+/// Unlocks the collection through the Secret Service API, the service then asking its password;
+/// false when the collection was not locked
+fn start_unlock(conn: &Connection, collection: &dbus::Path<'static>) -> Result<bool> {
+    let (_, prompt): (Vec<dbus::Path<'static>>, dbus::Path<'static>) = service_proxy(conn)
+        .method_call(
+            "org.freedesktop.Secret.Service",
+            "Unlock",
+            (vec![collection.clone()],),
+        )
+        .with_context(|| format!("Cannot unlock {}", collection))?;
+    if &*prompt == "/" {
+        return Ok(false);
+    }
+    debug!("Running the unlock prompt {}", prompt);
+    conn.with_proxy(SERVICE_NAME, &prompt, TIMEOUT)
+        .method_call::<(), _, _, _>("org.freedesktop.Secret.Prompt", "Prompt", ("",))
+        .with_context(|| "Cannot run the unlock prompt")?;
+    Ok(true)
+}
+
+/// Polls until `check` gives something, for at most `delay`
+fn wait_for<T>(delay: Duration, mut check: impl FnMut() -> Result<Option<T>>) -> Result<Option<T>> {
+    let deadline = Instant::now() + delay;
+    loop {
+        if let Some(found) = check()? {
+            return Ok(Some(found));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn answer(
+    conn: &Connection,
+    id: u32,
+    description: &str,
+    prompt: &str,
+    confirmation: bool,
+) -> Result<()> {
+    println!("{}", description);
+    loop {
+        let password = read_password(&format!("{}: ", prompt))?;
+        if confirmation && read_password("Confirm password: ")? != password {
+            eprintln!("{}", "Passwords do not match".red());
+            continue;
+        }
+        let empty = password.is_empty();
+        let answered: Result<(), dbus::Error> = service_proxy(conn).method_call(
+            "io.linux_tks.Service1",
+            "AnswerPassphrase",
+            (id, false, password),
+        );
+        match answered {
+            Ok(()) => return Ok(()),
+            // the dialog stays pending
+            Err(e) if empty => {
+                debug!("AnswerPassphrase error: {:?}", e);
+                eprintln!("{}", "The password is required".red());
+            }
+            Err(e) => return Err(e).with_context(|| "The service refused the answer"),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! The items deleted by the clients stay a while in the trash of their collection, see the
+//! `tks:trash-retention-days` collection property, so they can be restored after an accidental
+//! deletion. Only the labels of the trashed items are shown, never their secrets.
+
+use crate::age::age;
+use crate::dbus_client::{connect, resolve_collection, service_proxy, SERVICE_NAME, TIMEOUT};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::{Connection, Proxy};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const COLLECTION_INTERFACE: &str = "io.linux_tks.Collection1";
+
+#[derive(Parser, Debug)]
+pub struct TrashListCmd {
+    /// Collection to show: an alias, a label or an object path; all of them by default
+    pub collection: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct TrashRestoreCmd {
+    /// Collection of the item: an alias, a label or an object path
+    pub collection: String,
+    /// Trashed item: its uuid, or its label when no other trashed item has it
+    pub item: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct TrashPurgeCmd {
+    /// Collection to purge: an alias, a label or an object path
+    pub collection: String,
+    /// Trashed items to destroy, by uuid or label; all of them by default
+    pub items: Vec<String>,
+}
+
+/// The uuid, label and deletion time of the trashed items of a collection
+fn trashed(proxy: &Proxy<&Connection>) -> Result<Vec<(String, String, u64)>> {
+    let (items,): (Vec<(String, String, u64)>,) = proxy
+        .method_call(COLLECTION_INTERFACE, "ListTrash", ())
+        .with_context(|| "Cannot list the trash")?;
+    Ok(items)
+}
+
+/// The uuid of the single trashed item having this uuid or label
+fn find<'a>(trashed: &'a [(String, String, u64)], item: &str) -> Result<&'a str> {
+    if let Some((uuid, _, _)) = trashed.iter().find(|(uuid, _, _)| uuid == item) {
+        return Ok(uuid);
+    }
+    let found: Vec<_> = trashed
+        .iter()
+        .filter(|(_, label, _)| label == item)
+        .collect();
+    match found.as_slice() {
+        [] => anyhow::bail!("No trashed item '{}'", item),
+        [(uuid, _, _)] => Ok(uuid),
+        _ => anyhow::bail!(
+            "{} trashed items are labeled '{}', give a uuid",
+            found.len(),
+            item
+        ),
+    }
+}
+
+impl TrashListCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collections = match &self.collection {
+            Some(name) => vec![resolve_collection(&conn, name)?],
+            None => service_proxy(&conn).get("org.freedesktop.Secret.Service", "Collections")?,
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut empty = true;
+        for path in collections {
+            let proxy = conn.with_proxy(SERVICE_NAME, &path, TIMEOUT);
+            let items = trashed(&proxy)?;
+            if items.is_empty() {
+                continue;
+            }
+            empty = false;
+            let label: String = proxy.get("org.freedesktop.Secret.Collection", "Label")?;
+            println!("{}", label.bold());
+            for (uuid, label, deleted) in items {
+                println!("{:>6} ago  {}  {}", age(deleted, now), label, uuid.dimmed());
+            }
+        }
+        if empty {
+            println!("The trash is empty");
+        }
+        Ok(())
+    }
+}
+
+impl TrashRestoreCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.collection)?;
+        let proxy = conn.with_proxy(SERVICE_NAME, collection, TIMEOUT);
+        let trashed = trashed(&proxy)?;
+        let uuid = find(&trashed, &self.item)?;
+        let (_,): (dbus::Path<'static>,) = proxy
+            .method_call(COLLECTION_INTERFACE, "RestoreItem", (uuid,))
+            .with_context(|| format!("Cannot restore '{}'", self.item))?;
+        println!("Restored '{}' into '{}'", self.item.bold(), self.collection);
+        Ok(())
+    }
+}
+
+impl TrashPurgeCmd {
+    pub(crate) fn run(&self) -> Result<()> {
+        let conn = connect()?;
+        let collection = resolve_collection(&conn, &self.collection)?;
+        let proxy = conn.with_proxy(SERVICE_NAME, collection, TIMEOUT);
+        let trashed = trashed(&proxy)?;
+        let uuids = self
+            .items
+            .iter()
+            .map(|item| find(&trashed, item))
+            .collect::<Result<Vec<_>>>()?;
+        let (purged,): (u32,) = proxy
+            .method_call(COLLECTION_INTERFACE, "PurgeTrash", (uuids,))
+            .with_context(|| format!("Cannot purge the trash of '{}'", self.collection))?;
+        println!(
+            "Destroyed {} trashed item(s) of '{}'",
+            purged, self.collection
+        );
+        Ok(())
+    }
+}
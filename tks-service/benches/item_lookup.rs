@@ -0,0 +1,81 @@
+// Resolves the item paths the way GetSecrets does for each item it gets asked for, with 10k items
+// in a storage opened in a temporary directory. Run with `cargo bench --bench item_lookup`; it
+// doesn't need a DBus session bus.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use secrecy::SecretString;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use tks_service::settings;
+use tks_service::storage::Storage;
+use tks_service::tks_dbus::item_impl::ItemImpl;
+use tks_service::tks_dbus::session_impl::Session;
+use uuid::Uuid;
+
+const ITEMS: usize = 10_000;
+const SENDER: &str = ":1.42";
+
+/// The paths of the items of a storage holding [ITEMS] items
+fn item_paths() -> Vec<dbus::Path<'static>> {
+    let mut path = env::temp_dir();
+    path.push(format!("tks-item-lookup-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    let mut storage = Storage::open(settings::Storage {
+        path: Some(path.to_string_lossy().into()),
+        kind: "tks_gcm".to_string(),
+        keyfiles: HashMap::new(),
+        flush_delay: 0,
+        per_item_files: false,
+        pad_item_files: false,
+        min_free_space: 0,
+        per_collection_keys: false,
+        mounts: Vec::new(),
+    })
+    .expect("storage should open");
+    storage
+        .unlock_with_password(SecretString::new("item-lookup".into()))
+        .expect("storage should unlock");
+    let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+    let session = Session::new(0, "plain".to_string(), SENDER.to_string());
+    storage
+        .modify_collection(&default, |c| {
+            for i in 0..ITEMS {
+                let label = format!("item {}", i);
+                let attributes = HashMap::from([("index".to_string(), i.to_string())]);
+                let secret = (&session, vec![], vec![0; 16], "text/plain".to_string());
+                c.create_item(&label, attributes, secret, false, SENDER.to_string())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    let paths = storage
+        .with_collection(&default, |c| {
+            Ok(c.items.iter().map(|i| ItemImpl::from(i).into()).collect())
+        })
+        .unwrap();
+    let _ = fs::remove_dir_all(&path);
+    paths
+}
+
+fn item_lookup(c: &mut Criterion) {
+    // registering the item handles spawns the registration of their objects, which never runs
+    // as nothing drives this runtime: there is no DBus connection to register them on
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let _guard = runtime.enter();
+    let paths = item_paths();
+
+    c.bench_function("GetSecrets item lookup, 10k items", |b| {
+        b.iter(|| {
+            for path in &paths {
+                black_box(ItemImpl::from(path));
+            }
+        })
+    });
+    c.bench_function("single item lookup, 10k items", |b| {
+        b.iter(|| ItemImpl::from(black_box(&paths[ITEMS / 2])))
+    });
+}
+
+criterion_group!(benches, item_lookup);
+criterion_main!(benches);
@@ -0,0 +1,290 @@
+//! When `audit.enabled` is configured, each client operation touching secrets, items or
+//! collections gets appended to the audit log, one JSON record per line. The records are meant to
+//! be shipped into a SIEM, e.g. by `tks-cli audit export`, so their field names are stable: new
+//! fields may get added, but existing ones never get renamed nor change meaning without bumping
+//! [RECORD_VERSION]. The records never hold secrets, labels nor attributes, only uuids.
+
+use crate::settings::SETTINGS;
+use crate::tks_dbus;
+use crate::tks_error::TksError;
+use lazy_static::lazy_static;
+use log::{debug, error, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sysinfo::{Pid, ProcessRefreshKind, RefreshKind};
+use uuid::Uuid;
+
+/// Version of the record format, see the module documentation
+pub const RECORD_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    #[serde(rename = "secret.read")]
+    SecretRead,
+    #[serde(rename = "secret.write")]
+    SecretWrite,
+    #[serde(rename = "item.create")]
+    ItemCreate,
+    #[serde(rename = "item.delete")]
+    ItemDelete,
+    #[serde(rename = "collection.create")]
+    CollectionCreate,
+    #[serde(rename = "collection.lock")]
+    CollectionLock,
+    #[serde(rename = "collection.unlock")]
+    CollectionUnlock,
+    #[serde(rename = "backup.create")]
+    BackupCreate,
+    #[serde(rename = "backup.restore")]
+    BackupRestore,
+    #[serde(rename = "password.change")]
+    PasswordChange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// The client got a prompt, the operation happens once the user accepts it
+    Prompted,
+}
+
+impl<T, E> From<&Result<T, E>> for Outcome {
+    fn from(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err(_) => Outcome::Failure,
+        }
+    }
+}
+
+/// The process calling the service; the fields are unset when they could not be found out
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditClient {
+    /// Unique bus name of the caller, e.g. `:1.42`
+    pub bus_name: Option<String>,
+    pub pid: Option<u32>,
+    /// Executable path of the caller
+    pub exe: Option<String>,
+    /// Application the REST API token of the caller belongs to, see [crate::rest_api], or the
+    /// one a portal asked for, see [crate::tks_dbus::portal_impl]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub version: u32,
+    /// RFC 3339, in UTC, e.g. `2024-07-14T09:30:00Z`
+    pub timestamp: String,
+    /// Same as timestamp, in seconds since the Unix epoch
+    pub time: u64,
+    pub event: AuditEvent,
+    pub outcome: Outcome,
+    pub client: AuditClient,
+    /// Uuids of the items, or collections, the operation applied to
+    pub objects: Vec<Uuid>,
+}
+
+impl AuditRecord {
+    pub fn new(
+        time: SystemTime,
+        event: AuditEvent,
+        outcome: Outcome,
+        client: AuditClient,
+        objects: Vec<Uuid>,
+    ) -> AuditRecord {
+        let time = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+        AuditRecord {
+            version: RECORD_VERSION,
+            timestamp: rfc3339(time),
+            time,
+            event,
+            outcome,
+            client,
+            objects,
+        }
+    }
+}
+
+lazy_static! {
+    /// Unique bus name of the client whose call is being handled
+    static ref CALLER: Mutex<Option<String>> = Mutex::new(None);
+    /// Clients already looked up, by unique bus name
+    static ref CLIENTS: Mutex<HashMap<String, AuditClient>> = Mutex::new(HashMap::new());
+}
+
+/// Notes the client whose call is about to be handled, or none once it got handled
+pub fn set_caller(bus_name: Option<String>) {
+    *CALLER.lock().unwrap() = bus_name;
+}
+
+/// Unique bus name of the client whose call is being handled
+pub fn caller_bus_name() -> Option<String> {
+    CALLER.lock().unwrap().clone()
+}
+
+/// Drops what was looked up about a client which left the bus
+pub fn forget_client(bus_name: &str) {
+    CLIENTS.lock().unwrap().remove(bus_name);
+}
+
+/// Appends a record of an operation of the current caller to the audit log, if enabled. Failing
+/// to write the record doesn't fail the operation.
+pub fn record(event: AuditEvent, outcome: Outcome, objects: Vec<Uuid>) {
+    if audit_path().is_some() {
+        record_for(current_client(), event, outcome, objects);
+    }
+}
+
+/// Same as [record], for a client calling through another way than the bus
+pub fn record_for(client: AuditClient, event: AuditEvent, outcome: Outcome, objects: Vec<Uuid>) {
+    let Some(path) = audit_path() else {
+        return;
+    };
+    let record = AuditRecord::new(SystemTime::now(), event, outcome, client, objects);
+    if let Err(e) = append(Path::new(&path), &record) {
+        error!("Cannot write the audit record {:?}: {}", record, e);
+    }
+}
+
+/// The audit log, unless auditing is disabled
+fn audit_path() -> Option<String> {
+    let settings = SETTINGS.lock().unwrap();
+    if !settings.audit.enabled {
+        return None;
+    }
+    match settings.audit_path() {
+        Ok(path) => Some(path),
+        Err(e) => {
+            error!("Cannot find the audit log: {}", e);
+            None
+        }
+    }
+}
+
+/// Appends a record to the given audit log, which gets created readable by its owner only
+pub fn append(path: &Path, record: &AuditRecord) -> Result<(), TksError> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?;
+    // a single write keeps the lines whole should another writer append too
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// The lines of the given audit log recorded at, or after, `since` seconds after the Unix epoch.
+/// The lines are returned unchanged, so they may get exported as they were recorded; damaged
+/// lines get skipped.
+pub fn read_since(path: &Path, since: u64) -> Result<Vec<String>, TksError> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str::<AuditRecord>(&line) {
+            Ok(record) if record.time >= since => lines.push(line),
+            Ok(_) => {}
+            Err(e) => warn!("Skipping damaged audit record: {}", e),
+        }
+    }
+    Ok(lines)
+}
+
+/// The client whose call is being handled; its fields are unset for the service's own operations
+pub fn caller() -> AuditClient {
+    current_client()
+}
+
+/// Executable path of the client whose call is being handled, if it could be looked up
+pub fn caller_exe() -> Option<String> {
+    current_client().exe
+}
+
+fn current_client() -> AuditClient {
+    let bus_name = match CALLER.lock().unwrap().clone() {
+        Some(bus_name) => bus_name,
+        // e.g. the automatic locking
+        None => return AuditClient::default(),
+    };
+    if let Some(client) = CLIENTS.lock().unwrap().get(&bus_name) {
+        return client.clone();
+    }
+    let pid = match caller_pid(&bus_name) {
+        Ok(pid) => Some(pid),
+        Err(e) => {
+            debug!("Cannot get the process of {}: {}", bus_name, e);
+            None
+        }
+    };
+    let exe = pid.and_then(|pid| {
+        let s = sysinfo::System::new_with_specifics(
+            RefreshKind::new().with_processes(ProcessRefreshKind::everything()),
+        );
+        s.process(Pid::from_u32(pid))
+            .and_then(|p| p.exe())
+            .map(|exe| exe.to_string_lossy().into())
+    });
+    let client = AuditClient {
+        bus_name: Some(bus_name.clone()),
+        pid,
+        exe,
+        application: None,
+    };
+    CLIENTS.lock().unwrap().insert(bus_name, client.clone());
+    client
+}
+
+fn caller_pid(bus_name: &str) -> Result<u32, TksError> {
+    let conn = tks_dbus::blocking_connection()?;
+    let proxy = conn.with_proxy(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        Duration::from_secs(5),
+    );
+    let (pid,): (u32,) = proxy.method_call(
+        "org.freedesktop.DBus",
+        "GetConnectionUnixProcessID",
+        (bus_name,),
+    )?;
+    Ok(pid)
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp
+fn rfc3339(time: u64) -> String {
+    let days = (time / 86400) as i64;
+    let seconds = time % 86400;
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
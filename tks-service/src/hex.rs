@@ -0,0 +1,6 @@
+//! Hex encoding of the digests, tokens and checksums the service shows or stores as text
+
+/// Lowercase hex encoding of the bytes, two digits each
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -6,4 +6,8 @@ extern crate core;
 pub mod tks_error;
 pub mod settings;
 pub mod storage;
-pub mod tks_dbus;
\ No newline at end of file
+pub mod tks_dbus;
+pub mod audit;
+pub mod hex;
+#[cfg(feature = "rest-api")]
+pub mod rest_api;
\ No newline at end of file
@@ -5,12 +5,51 @@
 extern crate log;
 extern crate pretty_env_logger;
 
-use std::future;
+use log::{error, info};
+use std::process::exit;
+use tks_service::settings::Settings;
+use tks_service::storage::STORAGE;
+use tokio::signal::unix::{signal, SignalKind};
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
+    check_config();
     tks_service::tks_dbus::start_server().await;
-    future::pending::<()>().await;
-    unreachable!();
+    #[cfg(feature = "rest-api")]
+    start_rest_api();
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
+    }
+    info!("Shutting down");
+    // write the changes still waiting for the flush delay
+    if let Err(e) = STORAGE.write().unwrap().flush() {
+        error!("Cannot flush the storage: {}", e);
+    }
+}
+
+#[cfg(feature = "rest-api")]
+fn start_rest_api() {
+    let rest_api = tks_service::settings::SETTINGS.lock().unwrap().rest_api.clone();
+    if rest_api.enabled {
+        tks_service::rest_api::start(rest_api.port);
+    }
+}
+
+/// Reports all the configuration problems at once, rather than panicking upon the first one
+fn check_config() {
+    let config_path = Settings::config_path().unwrap_or_else(|e| {
+        eprintln!("Cannot locate the configuration file: {}", e);
+        exit(1);
+    });
+    if let Err(problems) = Settings::check(&config_path) {
+        eprintln!("Invalid configuration in {}:", config_path);
+        for problem in problems {
+            eprintln!("{}", problem);
+        }
+        exit(1);
+    }
 }
@@ -0,0 +1,618 @@
+//! A REST API on localhost, for the clients which can't reach the session bus, e.g. Electron
+//! applications or scripts running inside a sandbox. It mirrors the Secret Service operations:
+//! listing the collections and their items, searching the items by attributes, reading and writing
+//! their secrets. The service only serves it when built with the `rest-api` feature and once
+//! `rest_api.enabled` is set, on 127.0.0.1 only.
+//!
+//! Each call carries an `Authorization: Bearer <token>` header. An application gets its token by
+//! calling `POST /v1/tokens` with its name, which asks the user whether to let it in, see
+//! [tokens]. The token lets it use the collections the way the D-Bus clients do, except that the
+//! collections and items having an ACL stay out of its reach, their ACL listing executables, as do
+//! the collections only visible to the enrolled clients. The locked collections need to get
+//! unlocked through the Secret Service first. The requests coming from a web page, having an
+//! `Origin` header or another host than localhost, get turned down.
+//!
+//! The calls, the `item` objects being described below:
+//! - `POST /v1/tokens`, sending `{"application"}`, answers `{"token"}`
+//! - `DELETE /v1/tokens` revokes the token of the call
+//! - `GET /v1/collections` answers `[{"id", "label", "aliases", "locked"}]`
+//! - `GET /v1/collections/{collection}/items` answers `[item]`
+//! - `POST /v1/collections/{collection}/items`, sending
+//!   `{"label", "attributes", "secret", "content_type", "replace"}`, answers `item`
+//! - `POST /v1/search`, sending `{"attributes"}`, answers `[item]`
+//! - `GET /v1/collections/{collection}/items/{item}/secret` answers the secret, as its content
+//!   type
+//! - `PUT /v1/collections/{collection}/items/{item}/secret`, sending the secret as its content
+//!   type, replaces it
+//!
+//! The collections are given by uuid or alias, e.g. `default`, and the items by uuid. The items
+//! are `{"id", "collection", "label", "attributes", "created", "modified"}` objects. The errors
+//! are `{"error"}` objects, with the matching HTTP status.
+
+pub mod tokens;
+
+use crate::audit;
+use crate::audit::{AuditClient, AuditEvent};
+use crate::storage::collection::{Collection, Item, ItemId};
+use crate::storage::STORAGE;
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::{prompter, visibility};
+use crate::tks_error::TksError;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use lazy_static::lazy_static;
+use log::{debug, error, info, trace, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::Ipv4Addr;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+/// Largest request body accepted, secrets included
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Longest application name accepted, as shown to the user
+const MAX_APPLICATION: usize = 64;
+
+const DEFAULT_CONTENT_TYPE: &str = "text/plain";
+
+/// The calls of the API, see the module documentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    MintToken,
+    RevokeToken,
+    Collections,
+    Items(String),
+    CreateItem(String),
+    Search,
+    GetSecret(String, String),
+    SetSecret(String, String),
+}
+
+impl Route {
+    pub fn parse(method: &str, path: &str) -> Option<Route> {
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(1).collect();
+        let route = match (method, segments.as_slice()) {
+            ("POST", ["v1", "tokens"]) => Route::MintToken,
+            ("DELETE", ["v1", "tokens"]) => Route::RevokeToken,
+            ("GET", ["v1", "collections"]) => Route::Collections,
+            ("GET", ["v1", "collections", c, "items"]) => Route::Items(c.to_string()),
+            ("POST", ["v1", "collections", c, "items"]) => Route::CreateItem(c.to_string()),
+            ("POST", ["v1", "search"]) => Route::Search,
+            ("GET", ["v1", "collections", c, "items", i, "secret"]) => {
+                Route::GetSecret(c.to_string(), i.to_string())
+            }
+            ("PUT", ["v1", "collections", c, "items", i, "secret"]) => {
+                Route::SetSecret(c.to_string(), i.to_string())
+            }
+            _ => return None,
+        };
+        match segments.iter().any(|s| s.is_empty()) {
+            true => None,
+            false => Some(route),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(e: impl std::fmt::Display) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e))
+    }
+
+    fn locked() -> ApiError {
+        ApiError::new(
+            StatusCode::LOCKED,
+            "Locked, unlock it through the Secret Service first",
+        )
+    }
+
+    fn response(&self) -> Response<Full<Bytes>> {
+        debug!("REST API call failed: {} {}", self.status, self.message);
+        json(
+            self.status,
+            &ErrorAnswer {
+                error: &self.message,
+            },
+        )
+    }
+}
+
+impl From<TksError> for ApiError {
+    fn from(e: TksError) -> Self {
+        let status = match &e {
+            TksError::NotFound(_) | TksError::ItemNotFound => StatusCode::NOT_FOUND,
+            TksError::IOError(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                StatusCode::NOT_FOUND
+            }
+            TksError::PermissionDenied | TksError::NotSupported(_) => StatusCode::FORBIDDEN,
+            TksError::ParameterError => StatusCode::BAD_REQUEST,
+            TksError::Duplicate | TksError::BatchInProgress => StatusCode::CONFLICT,
+            TksError::StorageFull(_) => StatusCode::INSUFFICIENT_STORAGE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError::new(status, e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorAnswer<'a> {
+    error: &'a str,
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    application: String,
+}
+
+#[derive(Serialize)]
+struct TokenAnswer {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct CollectionAnswer {
+    id: Uuid,
+    label: String,
+    aliases: Vec<String>,
+    locked: bool,
+}
+
+#[derive(Serialize)]
+struct ItemAnswer {
+    id: Uuid,
+    collection: Uuid,
+    label: String,
+    attributes: HashMap<String, String>,
+    created: u64,
+    modified: u64,
+}
+
+impl From<&Item> for ItemAnswer {
+    fn from(item: &Item) -> Self {
+        ItemAnswer {
+            id: item.id.uuid,
+            collection: item.id.collection_uuid,
+            label: item.label.clone(),
+            attributes: item.attributes.clone(),
+            created: item.created,
+            modified: item.modified,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateItemRequest {
+    label: String,
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+    secret: String,
+    #[serde(default)]
+    content_type: Option<String>,
+    /// Replace the secret of the item having the same attributes, if any
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    attributes: HashMap<String, String>,
+}
+
+lazy_static! {
+    /// Held while the user gets asked about a token, so the clients can't flood them with dialogs
+    static ref ASKING: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Serves the API on the port, from a task of its own
+pub fn start(port: u16) {
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Cannot serve the REST API on port {}: {}", port, e);
+                return;
+            }
+        };
+        info!("Serving the REST API on http://127.0.0.1:{}", port);
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Cannot accept a REST API connection: {}", e);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                let served = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service_fn(answer))
+                    .await;
+                if let Err(e) = served {
+                    debug!("REST API connection failed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+async fn answer(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    trace!(
+        "REST API call {} {}",
+        request.method(),
+        request.uri().path()
+    );
+    Ok(handle(request).await.unwrap_or_else(|e| e.response()))
+}
+
+fn json(status: StatusCode, value: &impl serde::Serialize) -> Response<Full<Bytes>> {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    respond(status, "application/json", body)
+}
+
+fn respond(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(Full::new(Bytes::from(body)))
+        .unwrap_or_default()
+}
+
+/// Turns down the requests a web page sends to localhost, including through DNS rebinding
+fn check_origin(headers: &HeaderMap) -> Result<(), ApiError> {
+    let host = headers
+        .get(HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    if headers.contains_key(ORIGIN) || !matches!(host, "127.0.0.1" | "localhost") {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "Only the local applications may call the API, not web pages",
+        ));
+    }
+    Ok(())
+}
+
+/// The token of the call, and the application it belongs to
+fn authenticate(headers: &HeaderMap) -> Result<(String, String), ApiError> {
+    let unauthorized = || ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid token");
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?
+        .trim()
+        .to_string();
+    let application = tokens::with_tokens(|tokens| tokens.verify(&token).map(String::from))
+        .map_err(|_| ApiError::locked())?
+        .ok_or_else(unauthorized)?;
+    Ok((token, application))
+}
+
+/// Runs `f` on a blocking thread, as it waits for the storage lock, which may be held a while,
+/// e.g. while a collection gets saved; a panic of `f` answers 500
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+async fn handle(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, ApiError> {
+    let (parts, body) = request.into_parts();
+    check_origin(&parts.headers)?;
+    let route = Route::parse(parts.method.as_str(), parts.uri.path())
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "No such call"))?;
+    let body = Limited::new(body, MAX_BODY)
+        .collect()
+        .await
+        .map_err(|e| ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?
+        .to_bytes();
+    if route == Route::MintToken {
+        return mint_token(&body).await;
+    }
+    blocking(move || serve(route, &parts.headers, &body)).await
+}
+
+/// Answers the calls but [Route::MintToken], using the storage
+fn serve(
+    route: Route,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Result<Response<Full<Bytes>>, ApiError> {
+    let (token, application) = authenticate(headers)?;
+    let client = AuditClient {
+        application: Some(application),
+        ..AuditClient::default()
+    };
+    match route {
+        Route::MintToken => unreachable!(),
+        Route::RevokeToken => {
+            tokens::revoke(&token)?;
+            Ok(respond(StatusCode::NO_CONTENT, "text/plain", Vec::new()))
+        }
+        Route::Collections => {
+            let hidden = visibility::hidden_from(None);
+            let storage = STORAGE.read().map_err(TksError::from)?;
+            let collections: Vec<CollectionAnswer> = storage
+                .collections
+                .iter()
+                .filter(|c| reachable(c, &hidden))
+                .map(|c| CollectionAnswer {
+                    id: c.uuid,
+                    label: c.label().to_string(),
+                    aliases: c.aliases.clone().unwrap_or_default(),
+                    locked: c.locked,
+                })
+                .collect();
+            Ok(json(StatusCode::OK, &collections))
+        }
+        Route::Items(collection) => {
+            let uuid = reachable_collection(&collection)?;
+            let storage = STORAGE.read().map_err(TksError::from)?;
+            let items: Vec<ItemAnswer> = storage.with_collection(&uuid, |c| {
+                Ok(c.items
+                    .iter()
+                    .filter(|i| i.acl.is_none())
+                    .map(ItemAnswer::from)
+                    .collect())
+            })?;
+            Ok(json(StatusCode::OK, &items))
+        }
+        Route::Search => {
+            let search: SearchRequest =
+                serde_json::from_slice(body).map_err(ApiError::bad_request)?;
+            let hidden = visibility::hidden_from(None);
+            let storage = STORAGE.read().map_err(TksError::from)?;
+            let reachable: HashSet<Uuid> = storage
+                .collections
+                .iter()
+                .filter(|c| reachable(c, &hidden))
+                .map(|c| c.uuid)
+                .collect();
+            let items: Vec<ItemAnswer> = storage
+                .search_items(&search.attributes)
+                .into_iter()
+                .filter(|i| i.acl.is_none() && reachable.contains(&i.id.collection_uuid))
+                .map(ItemAnswer::from)
+                .collect();
+            Ok(json(StatusCode::OK, &items))
+        }
+        Route::GetSecret(collection, item) => {
+            let item_id = reachable_item(&collection, &item)?;
+            let result = STORAGE.read().map_err(TksError::from)?.with_item(
+                &item_id.collection_uuid,
+                &item_id.uuid,
+                |item| {
+                    let data = item.data.as_ref().ok_or(TksError::PermissionDenied)?;
+                    Ok((data.secret().to_vec(), data.content_type.clone()))
+                },
+            );
+            audit::record_for(
+                client,
+                AuditEvent::SecretRead,
+                (&result).into(),
+                vec![item_id.uuid],
+            );
+            let (secret, content_type) = result?;
+            let content_type = match content_type.is_empty() {
+                true => DEFAULT_CONTENT_TYPE.to_string(),
+                false => content_type,
+            };
+            Ok(respond(StatusCode::OK, &content_type, secret))
+        }
+        Route::SetSecret(collection, item) => {
+            let item_id = reachable_item(&collection, &item)?;
+            let content_type = headers
+                .get(CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or(DEFAULT_CONTENT_TYPE)
+                .to_string();
+            let result = STORAGE.write().map_err(TksError::from)?.modify_item(
+                &item_id.collection_uuid,
+                &item_id.uuid,
+                |item| {
+                    let data = item.data.as_mut().ok_or(TksError::PermissionDenied)?;
+                    data.replace(body.to_vec().into(), content_type);
+                    Ok(())
+                },
+            );
+            audit::record_for(
+                client,
+                AuditEvent::SecretWrite,
+                (&result).into(),
+                vec![item_id.uuid],
+            );
+            result?;
+            ItemImpl::emit_properties_changed(item_id.clone(), &["Type", "Modified"]);
+            CollectionImpl::emit_sequence_changed(item_id.collection_uuid);
+            Ok(respond(StatusCode::NO_CONTENT, "text/plain", Vec::new()))
+        }
+        Route::CreateItem(collection) => {
+            let request: CreateItemRequest =
+                serde_json::from_slice(body).map_err(ApiError::bad_request)?;
+            create_item(client, &collection, request)
+        }
+    }
+}
+
+async fn mint_token(body: &[u8]) -> Result<Response<Full<Bytes>>, ApiError> {
+    let request: TokenRequest = serde_json::from_slice(body).map_err(ApiError::bad_request)?;
+    let application = request.application.trim().to_string();
+    if application.is_empty()
+        || application.chars().count() > MAX_APPLICATION
+        || application.chars().any(char::is_control)
+    {
+        return Err(ApiError::bad_request(format!(
+            "the application name needs 1 to {} printable characters",
+            MAX_APPLICATION
+        )));
+    }
+    let _asking = ASKING.try_lock().map_err(|_| {
+        ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "The user is being asked about another application, try again later",
+        )
+    })?;
+    // the token gets saved with the storage key
+    blocking(|| tokens::with_tokens(|_| ()).map_err(|_| ApiError::locked())).await?;
+    let message = format!(
+        "'{}' asks to use your secrets through the REST API. Allow it?",
+        application
+    );
+    let allowed =
+        blocking(move || Ok(prompter::current().confirm("Allow", "Deny", &message)?)).await?;
+    if !allowed {
+        debug!(
+            "The user turned down the REST API token of '{}'",
+            application
+        );
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "The user denied the access",
+        ));
+    }
+    info!("Minting a REST API token for '{}'", application);
+    let token = blocking(move || Ok(tokens::mint(&application)?)).await?;
+    Ok(json(StatusCode::CREATED, &TokenAnswer { token }))
+}
+
+fn create_item(
+    client: AuditClient,
+    collection: &str,
+    request: CreateItemRequest,
+) -> Result<Response<Full<Bytes>>, ApiError> {
+    let (uuid, existing) = {
+        let uuid = reachable_collection(collection)?;
+        let storage = STORAGE.read().map_err(TksError::from)?;
+        let existing = storage.with_collection(&uuid, |c| {
+            if c.locked {
+                return Err(TksError::PermissionDenied);
+            }
+            Ok(c.items
+                .iter()
+                .find(|i| request.replace && i.attributes == request.attributes)
+                .map(|i| (i.id.clone(), i.acl.is_none())))
+        });
+        (uuid, existing.map_err(|_| ApiError::locked())?)
+    };
+    let content_type = request
+        .content_type
+        .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string());
+    let secret = request.secret.into_bytes();
+    let item_id = match existing {
+        Some((_, false)) => {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "The item to replace has an ACL",
+            ))
+        }
+        Some((item_id, true)) => {
+            let result = STORAGE
+                .write()
+                .map_err(TksError::from)?
+                .modify_item(&uuid, &item_id.uuid, |item| {
+                    item.label = request.label;
+                    let data = item.data.as_mut().ok_or(TksError::PermissionDenied)?;
+                    data.replace(secret.into(), content_type);
+                    Ok(())
+                });
+            audit::record_for(
+                client,
+                AuditEvent::SecretWrite,
+                (&result).into(),
+                vec![item_id.uuid],
+            );
+            result?;
+            ItemImpl::emit_properties_changed(item_id.clone(), &["Label", "Type", "Modified"]);
+            CollectionImpl::emit_sequence_changed(uuid);
+            item_id
+        }
+        None => {
+            let result = STORAGE.write().map_err(TksError::from)?.modify_collection(&uuid, |c| {
+                c.add_item(&request.label, request.attributes, secret, content_type)
+            });
+            let uuids = result.as_ref().map_or(vec![uuid], |id| vec![id.uuid]);
+            audit::record_for(client, AuditEvent::ItemCreate, (&result).into(), uuids);
+            let item_id = result?;
+            ItemImpl::register(&item_id);
+            CollectionImpl::emit_properties_changed(uuid, &["Items"]);
+            CollectionImpl::emit_sequence_changed(uuid);
+            item_id
+        }
+    };
+    let item = STORAGE
+        .read()
+        .map_err(TksError::from)?
+        .with_item(&uuid, &item_id.uuid, |item| Ok(ItemAnswer::from(item)))?;
+    Ok(json(StatusCode::CREATED, &item))
+}
+
+/// Whether the REST clients may use the collection, see the module documentation
+fn reachable(collection: &Collection, hidden: &HashSet<Uuid>) -> bool {
+    collection.acl.is_none() && !hidden.contains(&collection.uuid)
+}
+
+/// The collection having this uuid or alias, unless it's out of reach
+fn reachable_collection(collection: &str) -> Result<Uuid, ApiError> {
+    // looks the storage up too, so it can't be held meanwhile
+    let hidden = visibility::hidden_from(None);
+    let storage = STORAGE.read().map_err(TksError::from)?;
+    let uuid = match Uuid::parse_str(collection) {
+        Ok(uuid) => uuid,
+        Err(_) => Uuid::parse_str(&storage.read_alias(collection)?)
+            .map_err(|_| TksError::NotFound(None))?,
+    };
+    match storage.collections.iter().find(|c| c.uuid == uuid) {
+        Some(c) if reachable(c, &hidden) => Ok(uuid),
+        Some(_) => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "The collection is out of reach of the REST API",
+        )),
+        None => Err(TksError::NotFound(None).into()),
+    }
+}
+
+/// The item of the collection, unless it's out of reach or locked
+fn reachable_item(collection: &str, item: &str) -> Result<ItemId, ApiError> {
+    let uuid = reachable_collection(collection)?;
+    let storage = STORAGE.read().map_err(TksError::from)?;
+    let item = Uuid::parse_str(item).map_err(|_| TksError::NotFound(None))?;
+    storage
+        .with_item(&uuid, &item, |item| {
+            Ok((item.id.clone(), item.acl.is_none(), item.data.is_some()))
+        })
+        .map_err(ApiError::from)
+        .and_then(
+            |(item_id, reachable, unlocked)| match (reachable, unlocked) {
+                (false, _) => Err(ApiError::new(
+                    StatusCode::FORBIDDEN,
+                    "The item is out of reach of the REST API",
+                )),
+                (true, false) => Err(ApiError::locked()),
+                (true, true) => Ok(item_id),
+            },
+        )
+}
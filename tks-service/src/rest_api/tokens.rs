@@ -0,0 +1,136 @@
+//! The bearer tokens of the REST API, one per application the user let in. Only their SHA-256 gets
+//! saved, encrypted with the key of the `[storage]` backend like the known clients, so reading the
+//! storage doesn't give them away; the tokens themselves are only known to their applications.
+
+use crate::hex;
+use crate::storage::{Storage, STORAGE};
+use crate::tks_error::TksError;
+use lazy_static::lazy_static;
+use log::debug;
+use openssl::sha;
+use rand::RngCore;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the private file of the storage holding the tokens
+const TOKENS_FILE: &str = "rest-api-tokens";
+
+/// Random bytes of a token, hex encoded in the token itself
+const TOKEN_SIZE: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Name the application gave when asking for its token, as shown to the user
+    pub application: String,
+    /// Hex encoded SHA-256 of the token
+    pub token_sha256: String,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ApiTokens {
+    tokens: Vec<ApiToken>,
+}
+
+fn sha256_hex(token: &str) -> String {
+    hex::encode(&sha::sha256(token.as_bytes()))
+}
+
+impl ApiTokens {
+    /// Reads the tokens back; `None` while the storage is locked. Backends without private files
+    /// don't remember the tokens across restarts.
+    pub fn load(storage: &Storage) -> Result<Option<ApiTokens>, TksError> {
+        let tokens = match storage.read_private_file(TOKENS_FILE) {
+            Ok(Some(data)) => serde_json::from_slice(&data)?,
+            Ok(None) | Err(TksError::NotSupported(_)) => Vec::new(),
+            Err(TksError::PermissionDenied) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(ApiTokens { tokens }))
+    }
+
+    pub fn save(&self, storage: &Storage) -> Result<(), TksError> {
+        match storage.write_private_file(TOKENS_FILE, &serde_json::to_vec(&self.tokens)?) {
+            Err(TksError::NotSupported(what)) => {
+                debug!(
+                    "The REST API tokens won't survive a restart, {} aren't supported",
+                    what
+                );
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Gives the application a new token, replacing the one it had
+    pub fn mint(&mut self, application: &str) -> String {
+        let mut random = [0u8; TOKEN_SIZE];
+        rand::thread_rng().fill_bytes(&mut random);
+        let token = hex::encode(&random);
+        self.tokens.retain(|t| t.application != application);
+        self.tokens.push(ApiToken {
+            application: application.to_string(),
+            token_sha256: sha256_hex(&token),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        });
+        token
+    }
+
+    /// The application the token belongs to, if it's a valid one
+    pub fn verify(&self, token: &str) -> Option<&str> {
+        let hash = sha256_hex(token);
+        self.tokens
+            .iter()
+            .find(|t| openssl::memcmp::eq(t.token_sha256.as_bytes(), hash.as_bytes()))
+            .map(|t| t.application.as_str())
+    }
+
+    /// Returns whether the token was a valid one
+    pub fn revoke(&mut self, token: &str) -> bool {
+        let hash = sha256_hex(token);
+        let count = self.tokens.len();
+        self.tokens.retain(|t| t.token_sha256 != hash);
+        self.tokens.len() != count
+    }
+
+    pub fn list(&self) -> &[ApiToken] {
+        &self.tokens
+    }
+}
+
+lazy_static! {
+    /// The tokens, read once the storage got unlocked
+    static ref TOKENS: Mutex<Option<ApiTokens>> = Mutex::new(None);
+}
+
+/// Runs `f` over the tokens, reading them first if needed; fails with
+/// [TksError::PermissionDenied] while the storage is locked
+pub(crate) fn with_tokens<T>(f: impl FnOnce(&mut ApiTokens) -> T) -> Result<T, TksError> {
+    let mut tokens = TOKENS.lock().map_err(|_| TksError::LockingError)?;
+    if tokens.is_none() {
+        *tokens = ApiTokens::load(&*STORAGE.read()?)?;
+    }
+    let tokens = tokens.as_mut().ok_or(TksError::PermissionDenied)?;
+    Ok(f(tokens))
+}
+
+/// Gives the application a new token, and saves it
+pub(crate) fn mint(application: &str) -> Result<String, TksError> {
+    with_tokens(|tokens| {
+        let token = tokens.mint(application);
+        tokens.save(&*STORAGE.read()?)?;
+        Ok(token)
+    })?
+}
+
+/// Revokes the token, and saves the remaining ones
+pub(crate) fn revoke(token: &str) -> Result<(), TksError> {
+    with_tokens(|tokens| match tokens.revoke(token) {
+        true => tokens.save(&*STORAGE.read()?),
+        false => Err(TksError::PermissionDenied),
+    })?
+}
@@ -3,22 +3,480 @@ use config::{Config, Environment, File};
 use lazy_static::lazy_static;
 use log::debug;
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+pub mod reload;
+mod validation;
+pub use validation::ConfigProblem;
+
 #[derive(Debug, Clone, Deserialize)]
 #[allow(unused)]
 pub struct Storage {
     pub path: Option<String>,
     /// see [StorageBackendType]
     pub kind: String,
+    /// Key files that may be used instead of the password, by collection name
+    #[serde(default)]
+    pub keyfiles: HashMap<String, String>,
+    /// Milliseconds to wait for further changes before writing a collection; 0 writes right away
+    #[serde(default)]
+    pub flush_delay: u64,
+    /// Store each item's secret in its own file instead of a single file per collection
+    #[serde(default)]
+    pub per_item_files: bool,
+    /// Pad the item files to bucketed sizes, so they don't reveal the length of their secret
+    #[serde(default)]
+    pub pad_item_files: bool,
+    /// Megabytes to keep free on the storage filesystem, saves fail below that; 0 disables it
+    #[serde(default)]
+    pub min_free_space: u64,
+    /// Give each new collection, but the default one, a password of its own
+    #[serde(default)]
+    pub per_collection_keys: bool,
+    /// Further backends contributing their collections, see [crate::storage::Storage::open]
+    #[serde(default)]
+    pub mounts: Vec<StorageMount>,
+}
+
+/// A storage backend mounted next to the `[storage]` one
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[allow(unused)]
+pub struct StorageMount {
+    /// Names the backend, e.g. for migrating collections to it; the `[storage]` one is
+    /// [StorageMount::MAIN]
+    pub name: String,
+    /// see [StorageBackendType]
+    pub kind: String,
+    pub path: Option<String>,
+    /// Refuse any change to the collections of this backend
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub keyfiles: HashMap<String, String>,
+    #[serde(default)]
+    pub per_item_files: bool,
+    #[serde(default)]
+    pub pad_item_files: bool,
+    /// The directory gets synced with the other machines of the user, e.g. by Syncthing or
+    /// Nextcloud, see [crate::storage::sync]
+    #[serde(default)]
+    pub sync: bool,
+}
+
+impl Storage {
+    /// Directory holding the files of a tks_gcm backend: the configured one, or the storage one
+    /// in the XDG data directory
+    pub fn resolved_path(&self) -> Result<PathBuf, TksError> {
+        match &self.path {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Ok(xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?
+                .get_data_home()
+                .join("storage")),
+        }
+    }
+}
+
+/// A tks_gcm storage in the XDG data directory, writing right away
+impl Default for Storage {
+    fn default() -> Self {
+        Storage {
+            path: None,
+            kind: "tks_gcm".to_string(),
+            keyfiles: HashMap::new(),
+            flush_delay: 0,
+            per_item_files: false,
+            pad_item_files: false,
+            min_free_space: 0,
+            per_collection_keys: false,
+            mounts: Vec::new(),
+        }
+    }
+}
+
+impl StorageMount {
+    /// Name of the `[storage]` backend
+    pub const MAIN: &'static str = "main";
+    /// Name of the backend keeping the session collection in memory
+    pub const SESSION: &'static str = "session";
+
+    /// The settings opening the backend; the write-back and free space ones are the `[storage]`
+    /// ones
+    pub fn settings(&self, storage: &Storage) -> Storage {
+        Storage {
+            path: self.path.clone(),
+            kind: self.kind.clone(),
+            keyfiles: self.keyfiles.clone(),
+            per_item_files: self.per_item_files,
+            pad_item_files: self.pad_item_files,
+            mounts: Vec::new(),
+            ..storage.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(unused)]
+pub struct Session {
+    /// Seconds after which encrypted sessions must be opened again
+    pub max_age: Option<u64>,
+    /// Number of secrets transferred after which encrypted sessions must be opened again
+    pub max_uses: Option<u64>,
+}
+
+/// How the prompts show their dialogs, see [crate::tks_dbus::prompter]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PromptBackend {
+    /// The prompter registered by the desktop environment, if any, otherwise pinentry, or polkit
+    /// on the system bus
+    #[default]
+    Auto,
+    Pinentry,
+    /// PolicyKit authorizes the requests, on the system bus only; the passwords can't be asked,
+    /// the collections unlock with their key files
+    Polkit,
+    /// `tks-cli service unlock` answers the passphrase dialogs, e.g. over SSH; the confirmations
+    /// get declined. Only used when configured
+    Remote,
+    /// The answers programmed by the tests answer the dialogs, in test mode only, see
+    /// [crate::tks_dbus::prompter::ScriptedPrompter]
+    Scripted,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(unused)]
+pub struct Prompt {
+    /// Seconds after which the prompts not invoked by their client get dismissed; 0 disables it
+    #[serde(default = "Prompt::default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub backend: PromptBackend,
+    /// Seconds during which the key derived from the password which unlocked a collection
+    /// unlocks it again without asking, see [crate::storage::key_cache]; 0 disables the cache
+    #[serde(default)]
+    pub cache_ttl: u64,
+    #[serde(default)]
+    pub pinentry: Pinentry,
+}
+
+impl Prompt {
+    fn default_timeout() -> u64 {
+        300
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Prompt {
+            timeout: Prompt::default_timeout(),
+            backend: PromptBackend::default(),
+            cache_ttl: 0,
+            pinentry: Pinentry::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Pinentry {
+    /// The pinentry program, by name or by path; [Pinentry::AUTO] picks the flavor of the desktop
+    /// environment, see [crate::tks_dbus::prompter::pinentry_program]
+    #[serde(default = "Pinentry::default_program")]
+    pub program: String,
+    /// Title of the dialogs, instead of the one of pinentry
+    pub title: Option<String>,
+}
+
+impl Pinentry {
+    pub const AUTO: &'static str = "auto";
+
+    fn default_program() -> String {
+        Pinentry::AUTO.to_string()
+    }
+}
+
+impl Default for Pinentry {
+    fn default() -> Self {
+        Pinentry {
+            program: Pinentry::default_program(),
+            title: None,
+        }
+    }
+}
+
+/// Desktop session events locking all the collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockTrigger {
+    /// The screensaver got activated
+    Screensaver,
+    /// logind locked the session
+    SessionLock,
+    /// logind flagged the session idle
+    Idle,
+    /// The system is about to suspend or hibernate
+    Sleep,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(unused)]
+pub struct AutoLock {
+    /// Minutes without any client call after which all the collections get locked
+    pub lock_after_idle_minutes: Option<u64>,
+    #[serde(default)]
+    pub lock_on: Vec<LockTrigger>,
+}
+
+/// Responses adjusted for the clients expecting them, see [crate::tks_dbus::quirks]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quirk {
+    /// The Label property of the collections having an alias is that alias
+    LabelIsAlias,
+    /// ReadAlias returns the `/org/freedesktop/secrets/aliases/...` path of the collection,
+    /// instead of its canonical one
+    AliasPath,
+    /// Creating an item also sends the CollectionChanged signal of its collection
+    CollectionChangedOnNewItem,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(unused)]
+pub struct Clients {
+    /// Seconds a client gets turned down after the user denied it access, doubling upon each
+    /// further denial; 0 prompts again upon each call
+    #[serde(default = "Clients::default_denial_timeout")]
+    pub denial_timeout: u64,
+    /// Apply the quirks of the clients known to need them
+    #[serde(default = "Clients::default_builtin_quirks")]
+    pub builtin_quirks: bool,
+    /// Quirks of the clients, by process executable file name or path; these replace the
+    /// built-in ones
+    #[serde(default)]
+    pub quirks: HashMap<String, Vec<Quirk>>,
+}
+
+impl Clients {
+    fn default_denial_timeout() -> u64 {
+        60
+    }
+
+    fn default_builtin_quirks() -> bool {
+        true
+    }
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Clients {
+            denial_timeout: Clients::default_denial_timeout(),
+            builtin_quirks: Clients::default_builtin_quirks(),
+            quirks: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(unused)]
+pub struct Audit {
+    /// Append a record of each client operation to the audit log, see [crate::audit]
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to `audit.jsonl` in the XDG state directory
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(unused)]
+pub struct Kwallet {
+    /// Serve the legacy org.kde.KWallet interface over the collections, see
+    /// [crate::tks_dbus::kwallet_impl]
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(unused)]
+pub struct GnomeKeyring {
+    /// Take the org.gnome.keyring name of gnome-keyring-daemon, see
+    /// [crate::tks_dbus::gnome_keyring_impl]
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(unused)]
+pub struct Portal {
+    /// Serve the backend of the Secret portal of xdg-desktop-portal, see
+    /// [crate::tks_dbus::portal_impl]
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(unused)]
+pub struct RestApi {
+    /// Serve the REST API on localhost, see [crate::rest_api]; needs the `rest-api` feature
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "RestApi::default_port")]
+    pub port: u16,
+}
+
+impl RestApi {
+    fn default_port() -> u16 {
+        7741
+    }
+}
+
+impl Default for RestApi {
+    fn default() -> Self {
+        RestApi {
+            enabled: false,
+            port: RestApi::default_port(),
+        }
+    }
+}
+
+/// The message bus the service is reachable on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BusKind {
+    #[default]
+    Session,
+    /// For the hosts having no desktop session, see `io.linux-tks.conf`
+    System,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(unused)]
+pub struct Bus {
+    #[serde(default)]
+    pub kind: BusKind,
+    /// The well-known name to request
+    #[serde(default = "Bus::default_name")]
+    pub name: String,
+}
+
+impl Bus {
+    fn default_name() -> String {
+        "org.freedesktop.secrets".to_string()
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus {
+            kind: BusKind::default(),
+            name: Bus::default_name(),
+        }
+    }
+}
+
+/// The type of the values of a schema attribute, as libsecret's SecretSchemaAttributeType
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttributeType {
+    String,
+    /// A decimal number, e.g. `8080`
+    Integer,
+    /// `true` or `false`
+    Boolean,
+}
+
+/// The attributes the items having `xdg:schema` set to the schema name may have, see
+/// [crate::storage::item_schemas]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ItemSchema {
+    pub name: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, AttributeType>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 #[allow(unused)]
 pub struct Settings {
     pub storage: Storage,
+    #[serde(default)]
+    pub session: Session,
+    #[serde(default)]
+    pub auto_lock: AutoLock,
+    #[serde(default)]
+    pub prompt: Prompt,
+    #[serde(default)]
+    pub clients: Clients,
+    #[serde(default)]
+    pub audit: Audit,
+    #[serde(default)]
+    pub kwallet: Kwallet,
+    #[serde(default)]
+    pub gnome_keyring: GnomeKeyring,
+    #[serde(default)]
+    pub portal: Portal,
+    #[serde(default)]
+    pub rest_api: RestApi,
+    #[serde(default)]
+    pub bus: Bus,
+    /// Schemas validating the item attributes, next to the built-in ones
+    #[serde(default)]
+    pub schemas: Vec<ItemSchema>,
+}
+
+/// How the service was started, from the `TKS_RUN_MODE` environment variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    Normal,
+    /// Set to `test`: the test suite, or someone experimenting, runs the service. The storage
+    /// path and bus name then come from `TKS_TEST_*` environment variables, so that the real
+    /// store never gets touched; the mounted backends get left out.
+    Test,
+}
+
+impl RunMode {
+    /// Configuration file used in test mode
+    pub const TEST_CONFIG_PATH: &'static str = "TKS_TEST_CONFIG_PATH";
+    /// Storage directory used in test mode, defaults to a per-process temporary directory
+    pub const TEST_STORAGE_PATH: &'static str = "TKS_TEST_STORAGE_PATH";
+    /// Bus name to request in test mode, defaults to `org.freedesktop.secrets`
+    pub const TEST_BUS_NAME: &'static str = "TKS_TEST_BUS_NAME";
+    /// Answers of the dialogs in test mode, one per line; setting it, even empty, selects the
+    /// scripted prompter, see [crate::tks_dbus::prompter::ScriptedPrompter]
+    pub const TEST_PROMPT_SCRIPT: &'static str = "TKS_TEST_PROMPT_SCRIPT";
+
+    pub fn current() -> RunMode {
+        match env::var("TKS_RUN_MODE") {
+            Ok(mode) if mode == "test" => RunMode::Test,
+            _ => RunMode::Normal,
+        }
+    }
+
+    /// The storage directory test mode uses instead of the configured one
+    pub fn test_storage_path() -> String {
+        env::var(RunMode::TEST_STORAGE_PATH).unwrap_or_else(|_| {
+            let mut path = env::temp_dir();
+            path.push(format!("tks-service-test-{}", std::process::id()));
+            path.to_string_lossy().into()
+        })
+    }
+
+    /// The bus name to request in test mode, if overridden
+    pub fn test_bus_name() -> Option<String> {
+        match RunMode::current() {
+            RunMode::Test => env::var(RunMode::TEST_BUS_NAME).ok(),
+            RunMode::Normal => None,
+        }
+    }
+
+    /// The answers of the dialogs in test mode, if scripted
+    pub fn test_prompt_script() -> Option<String> {
+        match RunMode::current() {
+            RunMode::Test => env::var(RunMode::TEST_PROMPT_SCRIPT).ok(),
+            RunMode::Normal => None,
+        }
+    }
 }
 
 lazy_static! {
@@ -30,45 +488,113 @@ lazy_static! {
 impl Settings {
     pub const XDG_DIR_NAME: &'static str = "io.linux-tks";
     pub fn new() -> Result<Self, TksError> {
-        // let run_mode = env::var("TKS_RUN_MODE").unwrap_or_else(|_| "development".into());
+        Settings::check(&Settings::config_path()?).map_err(|problems| {
+            TksError::ConfigurationError(
+                problems
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        })
+    }
 
-        let xdg_dirs = xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?;
-        let config_path = match env::var("TKS_SERVICE_CONFIG_PATH") {
+    /// The configuration file the service reads
+    pub fn config_path() -> Result<String, TksError> {
+        if RunMode::current() == RunMode::Test {
+            if let Ok(path) = env::var(RunMode::TEST_CONFIG_PATH) {
+                debug!("Test mode, {} set to {}.", RunMode::TEST_CONFIG_PATH, path);
+                return Ok(path);
+            }
+        }
+        match env::var("TKS_SERVICE_CONFIG_PATH") {
             Ok(path) => {
                 debug!("TKS_SERVICE_CONFIG_PATH set to {}.", path);
-                path
+                Ok(path)
             }
-            Err(_) => xdg_dirs
-                .place_config_file("service.toml")
-                .expect("Failed to place config file.")
+            Err(_) => Ok(xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?
+                .place_config_file("service.toml")?
                 .to_string_lossy()
-                .into(),
-        };
+                .into()),
+        }
+    }
+
+    /// The file the audit records get appended to
+    pub fn audit_path(&self) -> Result<String, TksError> {
+        match &self.audit.path {
+            Some(path) => Ok(path.clone()),
+            None => Ok(xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?
+                .place_state_file("audit.jsonl")?
+                .to_string_lossy()
+                .into()),
+        }
+    }
+
+    /// Reads and validates the configuration file, listing all the problems found
+    pub fn check(config_path: &str) -> Result<Settings, Vec<ConfigProblem>> {
+        let settings = Settings::read(config_path).map_err(|p| vec![p])?;
+        let problems = settings.validate();
+        if problems.is_empty() {
+            Ok(settings)
+        } else {
+            Err(problems)
+        }
+    }
+
+    fn read(config_path: &str) -> Result<Settings, ConfigProblem> {
         let s = Config::builder()
-            .add_source(File::with_name(&config_path))
+            .add_source(File::with_name(config_path))
             .add_source(File::with_name("local").required(false))
             .add_source(Environment::with_prefix("tks"))
-            .set_default("storage.backend", "fscrypt")?
+            .set_default("storage.backend", "fscrypt")
             // .set_default("storage.path",
             //              xdg_dirs.create_data_directory("storage")?
             //                  .to_str())?
-            .build()?;
+            .and_then(|b| b.build())
+            .map_err(|e| ConfigProblem::from_config_error(config_path, e))?;
 
         debug!("configuration: {:?}", s);
 
-        s.try_deserialize()
-            .and_then(|s| {
-                let mut settings: Settings = s;
-                if !settings.storage.path.is_none() {
-                    settings.storage.path = Some(
-                        shellexpand::full(&settings.storage.path.unwrap())
-                            .expect("Failed to expand storage path.")
-                            .into_owned()
-                            .into(),
-                    );
-                }
-                Ok(settings)
-            })
-            .map_err(|e| TksError::ConfigurationError(e.to_string()))
+        let mut settings: Settings = s
+            .try_deserialize()
+            .map_err(|e| ConfigProblem::from_config_error(config_path, e))?;
+        for (name, path) in settings.storage.keyfiles.iter_mut() {
+            *path = expand_path(&format!("storage.keyfiles.{}", name), path)?;
+        }
+        if let Some(path) = &settings.storage.path {
+            settings.storage.path = Some(expand_path("storage.path", path)?);
+        }
+        for (i, mount) in settings.storage.mounts.iter_mut().enumerate() {
+            if let Some(path) = &mount.path {
+                mount.path = Some(expand_path(&format!("storage.mounts[{}].path", i), path)?);
+            }
+            for (name, path) in mount.keyfiles.iter_mut() {
+                *path = expand_path(&format!("storage.mounts[{}].keyfiles.{}", i, name), path)?;
+            }
+        }
+        settings.audit.path = match &settings.audit.path {
+            Some(path) => Some(expand_path("audit.path", path)?),
+            None => None,
+        };
+        if RunMode::current() == RunMode::Test {
+            let path = RunMode::test_storage_path();
+            debug!("Test mode, storing into {} instead of {:?}", path, settings.storage.path);
+            settings.audit.path = Some(format!("{}/audit.jsonl", path));
+            settings.storage.path = Some(path);
+            settings.storage.mounts.clear();
+        }
+        Ok(settings)
     }
 }
+
+fn expand_path(setting: &str, path: &str) -> Result<String, ConfigProblem> {
+    shellexpand::full(path)
+        .map(|p| p.into_owned())
+        .map_err(|e| {
+            ConfigProblem::new(
+                setting,
+                format!("cannot expand '{}': {}", path, e),
+                "define the environment variable or use an absolute path",
+            )
+        })
+}
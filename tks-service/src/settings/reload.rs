@@ -0,0 +1,170 @@
+//! The configuration file gets read again once it changes, or upon the ReloadConfig call of
+//! `io.linux_tks.Service1`, so that the settings apply without restarting the service. The
+//! storage got opened with the `[storage]` settings, so these can't change at runtime: such a
+//! configuration gets refused as a whole, the running settings staying in effect, as for a change
+//! of `bus.kind`, the bus the service and its prompts connect to. The watched desktop events,
+//! `auto_lock.lock_on`, and the bus name only change after a restart.
+
+use crate::settings::{ConfigProblem, Settings, SETTINGS};
+use crate::tks_dbus::quirks;
+use crate::tks_error::TksError;
+use log::{debug, error, info, warn};
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Size of `struct inotify_event`, without its name
+const EVENT_HEADER_SIZE: usize = 16;
+
+/// Compares the settings read again to the running ones. Returns the problems of the settings
+/// which can't change at runtime, and the changed settings which only apply after a restart.
+pub fn compare(current: &Settings, new: &Settings) -> (Vec<ConfigProblem>, Vec<&'static str>) {
+    let (c, n) = (&current.storage, &new.storage);
+    let fixed = [
+        ("bus.kind", current.bus.kind != new.bus.kind),
+        ("storage.kind", c.kind != n.kind),
+        ("storage.path", c.path != n.path),
+        ("storage.keyfiles", c.keyfiles != n.keyfiles),
+        ("storage.flush_delay", c.flush_delay != n.flush_delay),
+        ("storage.per_item_files", c.per_item_files != n.per_item_files),
+        ("storage.pad_item_files", c.pad_item_files != n.pad_item_files),
+        ("storage.min_free_space", c.min_free_space != n.min_free_space),
+        ("storage.per_collection_keys", c.per_collection_keys != n.per_collection_keys),
+        ("storage.mounts", c.mounts != n.mounts),
+    ];
+    let problems = fixed
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(setting, _)| {
+            ConfigProblem::new(
+                setting,
+                "cannot change while the service runs",
+                "revert it, or restart the service to apply the new configuration",
+            )
+        })
+        .collect();
+    let mut restart_needed = Vec::new();
+    if current.auto_lock.lock_on != new.auto_lock.lock_on {
+        restart_needed.push("auto_lock.lock_on");
+    }
+    if current.kwallet.enabled != new.kwallet.enabled {
+        restart_needed.push("kwallet.enabled");
+    }
+    if current.gnome_keyring.enabled != new.gnome_keyring.enabled {
+        restart_needed.push("gnome_keyring.enabled");
+    }
+    if current.portal.enabled != new.portal.enabled {
+        restart_needed.push("portal.enabled");
+    }
+    if current.rest_api.enabled != new.rest_api.enabled {
+        restart_needed.push("rest_api.enabled");
+    }
+    if current.rest_api.port != new.rest_api.port {
+        restart_needed.push("rest_api.port");
+    }
+    if current.bus.name != new.bus.name {
+        restart_needed.push("bus.name");
+    }
+    (problems, restart_needed)
+}
+
+/// Reads the configuration file again and applies it, unless it has problems. Returns the changed
+/// settings which only apply after a restart.
+pub fn reload() -> Result<Vec<&'static str>, TksError> {
+    let config_path = Settings::config_path()?;
+    let new = Settings::check(&config_path).map_err(configuration_error)?;
+    let mut settings = SETTINGS.lock().unwrap();
+    let (problems, restart_needed) = compare(&settings, &new);
+    if !problems.is_empty() {
+        return Err(configuration_error(problems));
+    }
+    *settings = new;
+    drop(settings);
+    // the quirks got looked up with the previous settings
+    quirks::forget_all();
+    info!("Reloaded the configuration from {}", config_path);
+    if !restart_needed.is_empty() {
+        warn!("Restart the service to apply {}", restart_needed.join(", "));
+    }
+    Ok(restart_needed)
+}
+
+fn configuration_error(problems: Vec<ConfigProblem>) -> TksError {
+    TksError::ConfigurationError(
+        problems
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// Reloads the configuration each time its file gets written, or replaced as editors do. Its
+/// directory gets watched with inotify, from a thread of its own.
+pub fn start_watching() -> Result<(), TksError> {
+    let config_path = Settings::config_path()?;
+    let path = Path::new(&config_path);
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(TksError::ConfigurationError(format!(
+            "Cannot watch {}, it is not a file path",
+            config_path
+        )));
+    };
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let c_dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| TksError::ConfigurationError(format!("Invalid path {}", config_path)))?;
+    // SAFETY: plain system calls; the descriptor gets owned by the File below
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let events = unsafe { File::from_raw_fd(fd) };
+    let wd = unsafe {
+        libc::inotify_add_watch(fd, c_dir.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO)
+    };
+    if wd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    debug!("Watching {} for changes", config_path);
+    let name = name.to_os_string();
+    std::thread::Builder::new()
+        .name("config-watcher".to_string())
+        .spawn(move || watch(events, name))?;
+    Ok(())
+}
+
+fn watch(mut events: File, name: OsString) {
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = match events.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) => {
+                error!("Cannot watch the configuration file anymore: {}", e);
+                return;
+            }
+        };
+        let mut changed = false;
+        let mut offset = 0;
+        while offset + EVENT_HEADER_SIZE <= n {
+            let len = u32::from_ne_bytes(buffer[offset + 12..offset + 16].try_into().unwrap());
+            let start = offset + EVENT_HEADER_SIZE;
+            let end = (start + len as usize).min(n);
+            // the name gets padded with NULs
+            let event_name = buffer[start..end].split(|b| *b == 0).next().unwrap_or_default();
+            changed |= event_name == name.as_bytes();
+            offset = end;
+        }
+        if !changed {
+            continue;
+        }
+        if let Err(e) = reload() {
+            error!("Keeping the running configuration: {}", e);
+        }
+    }
+}
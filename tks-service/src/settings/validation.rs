@@ -0,0 +1,348 @@
+//! Checks the configuration before the service relies on it, so that all the mistakes get reported
+//! at once, each with a hint about fixing it, instead of a panic upon first use.
+
+use crate::settings::{
+    BusKind, LockTrigger, Pinentry, PromptBackend, RunMode, Settings, StorageMount,
+};
+use crate::storage::item_schemas;
+use crate::tks_dbus::prompter;
+use config::ConfigError;
+use std::env;
+use std::fmt;
+use std::path::Path;
+
+/// The storage backends known to [crate::storage::Storage::open]
+pub const STORAGE_KINDS: &[&str] = &["tks_gcm", "password-store"];
+
+/// Longer delays would keep the changes unsaved for too long
+const MAX_FLUSH_DELAY: u64 = 60 * 60 * 1000;
+
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    /// The offending setting, e.g. `storage.kind`, or the configuration file itself
+    pub setting: String,
+    pub problem: String,
+    /// What to change to fix it
+    pub hint: String,
+}
+
+impl ConfigProblem {
+    pub(crate) fn new(
+        setting: &str,
+        problem: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> ConfigProblem {
+        ConfigProblem {
+            setting: setting.to_string(),
+            problem: problem.into(),
+            hint: hint.into(),
+        }
+    }
+
+    pub(crate) fn from_config_error(config_path: &str, e: ConfigError) -> ConfigProblem {
+        match e {
+            ConfigError::FileParse { uri, cause } => ConfigProblem::new(
+                &uri.unwrap_or(config_path.to_string()),
+                cause.to_string(),
+                "fix the TOML syntax of the file",
+            ),
+            ConfigError::Type {
+                unexpected,
+                expected,
+                key,
+                ..
+            } => ConfigProblem::new(
+                &key.unwrap_or(config_path.to_string()),
+                format!("invalid value {}", unexpected),
+                format!("use {} instead", expected),
+            ),
+            ConfigError::NotFound(key) => ConfigProblem::new(
+                &key,
+                "missing setting",
+                "add it to the configuration file, see config/service.toml for an example",
+            ),
+            e => ConfigProblem::new(
+                config_path,
+                e.to_string(),
+                "check the configuration file exists and is complete, see config/service.toml \
+                 for an example",
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}\n  hint: {}", self.setting, self.problem, self.hint)
+    }
+}
+
+impl Settings {
+    /// Lists the problems of the settings read from the configuration file
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let storage = &self.storage;
+        if !STORAGE_KINDS.contains(&storage.kind.as_str()) {
+            problems.push(ConfigProblem::new(
+                "storage.kind",
+                format!("unknown storage backend '{}'", storage.kind),
+                format!("use one of: {}", STORAGE_KINDS.join(", ")),
+            ));
+        }
+        match &storage.path {
+            None if storage.kind == "password-store" => problems.push(ConfigProblem::new(
+                "storage.path",
+                "the password-store backend needs the store's location",
+                "set it to the pass store, usually \"$HOME/.password-store\"",
+            )),
+            Some(path) if Path::new(path).exists() && !Path::new(path).is_dir() => {
+                problems.push(ConfigProblem::new(
+                    "storage.path",
+                    format!("'{}' is not a directory", path),
+                    "point it to a directory, it gets created when missing",
+                ))
+            }
+            _ => {}
+        }
+        for (name, path) in &storage.keyfiles {
+            if !Path::new(path).is_absolute() {
+                problems.push(ConfigProblem::new(
+                    &format!("storage.keyfiles.{}", name),
+                    format!("'{}' is a relative path", path),
+                    "use an absolute path, e.g. starting with $HOME",
+                ));
+            }
+        }
+        if storage.pad_item_files && !storage.per_item_files {
+            problems.push(ConfigProblem::new(
+                "storage.pad_item_files",
+                "only the item files get padded, and per_item_files is off",
+                "also set per_item_files = true, or remove the setting",
+            ));
+        }
+        if storage.per_collection_keys && storage.kind != "tks_gcm" {
+            problems.push(ConfigProblem::new(
+                "storage.per_collection_keys",
+                format!("the {} backend has a single key", storage.kind),
+                "remove the setting, only the tks_gcm backend gives collections their own key",
+            ));
+        }
+        if storage.flush_delay > MAX_FLUSH_DELAY {
+            problems.push(ConfigProblem::new(
+                "storage.flush_delay",
+                format!(
+                    "{} milliseconds would keep the changes unsaved for too long",
+                    storage.flush_delay
+                ),
+                format!(
+                    "use at most {} milliseconds; the value is in milliseconds, not seconds",
+                    MAX_FLUSH_DELAY
+                ),
+            ));
+        }
+        for (i, mount) in storage.mounts.iter().enumerate() {
+            let setting = |name: &str| format!("storage.mounts[{}].{}", i, name);
+            let named = |name: &str| {
+                name == StorageMount::MAIN
+                    || name == StorageMount::SESSION
+                    || storage.mounts[..i].iter().any(|m| m.name == name)
+            };
+            if mount.name.is_empty() || named(&mount.name) {
+                problems.push(ConfigProblem::new(
+                    &setting("name"),
+                    format!("'{}' does not tell the backend apart", mount.name),
+                    format!(
+                        "give each backend a name of its own, '{}' being the [storage] one and \
+                         '{}' the one of the session collection",
+                        StorageMount::MAIN,
+                        StorageMount::SESSION
+                    ),
+                ));
+            }
+            if !STORAGE_KINDS.contains(&mount.kind.as_str()) {
+                problems.push(ConfigProblem::new(
+                    &setting("kind"),
+                    format!("unknown storage backend '{}'", mount.kind),
+                    format!("use one of: {}", STORAGE_KINDS.join(", ")),
+                ));
+            }
+            let taken = |path: &String| {
+                storage.path.as_ref() == Some(path)
+                    || storage.mounts[..i].iter().any(|m| m.path.as_ref() == Some(path))
+            };
+            match &mount.path {
+                None => problems.push(ConfigProblem::new(
+                    &setting("path"),
+                    "mounted backends have no default location",
+                    "set it to the directory of the backend",
+                )),
+                Some(path) if taken(path) => problems.push(ConfigProblem::new(
+                    &setting("path"),
+                    format!("'{}' is already used by another backend", path),
+                    "give each backend a directory of its own",
+                )),
+                Some(path) if Path::new(path).exists() && !Path::new(path).is_dir() => {
+                    problems.push(ConfigProblem::new(
+                        &setting("path"),
+                        format!("'{}' is not a directory", path),
+                        "point it to a directory, it gets created when missing",
+                    ))
+                }
+                _ => {}
+            }
+            if mount.read_only && mount.per_item_files {
+                problems.push(ConfigProblem::new(
+                    &setting("per_item_files"),
+                    "unlocking would migrate the items of the read-only backend to item files",
+                    "remove the setting, the item files get read regardless",
+                ));
+            }
+            if mount.pad_item_files && !mount.per_item_files {
+                problems.push(ConfigProblem::new(
+                    &setting("pad_item_files"),
+                    "only the item files get padded, and per_item_files is off",
+                    "also set per_item_files = true, or remove the setting",
+                ));
+            }
+            if mount.sync && (mount.kind != "tks_gcm" || !mount.per_item_files) {
+                problems.push(ConfigProblem::new(
+                    &setting("sync"),
+                    "only the item files of the tks_gcm backend merge item by item",
+                    "set kind = \"tks_gcm\" and per_item_files = true, or remove the setting",
+                ));
+            }
+        }
+        let password_stores = std::iter::once(&storage.kind)
+            .chain(storage.mounts.iter().map(|m| &m.kind))
+            .filter(|kind| *kind == "password-store")
+            .count();
+        if password_stores > 1 {
+            problems.push(ConfigProblem::new(
+                "storage.mounts",
+                "the password-store backends would share their metadata",
+                "use a single password-store backend",
+            ));
+        }
+        if self.session.max_age == Some(0) {
+            problems.push(ConfigProblem::new(
+                "session.max_age",
+                "sessions would expire right away",
+                "use a number of seconds, or remove the setting to disable the limit",
+            ));
+        }
+        if self.session.max_uses == Some(0) {
+            problems.push(ConfigProblem::new(
+                "session.max_uses",
+                "sessions could not transfer any secret",
+                "use a positive number, or remove the setting to disable the limit",
+            ));
+        }
+        if self.auto_lock.lock_after_idle_minutes == Some(0) {
+            problems.push(ConfigProblem::new(
+                "auto_lock.lock_after_idle_minutes",
+                "collections would get locked right after being unlocked",
+                "use a number of minutes, or remove the setting to disable automatic locking",
+            ));
+        }
+        if self.rest_api.enabled && !cfg!(feature = "rest-api") {
+            problems.push(ConfigProblem::new(
+                "rest_api.enabled",
+                "the service got built without the REST API",
+                "build it with the rest-api feature, or remove the setting",
+            ));
+        }
+        if self.rest_api.enabled && self.rest_api.port == 0 {
+            problems.push(ConfigProblem::new(
+                "rest_api.port",
+                "the REST API would listen on a random port",
+                "use a free port, or remove the setting to use the default one",
+            ));
+        }
+        if dbus::strings::BusName::new(self.bus.name.as_str()).is_err() {
+            problems.push(ConfigProblem::new(
+                "bus.name",
+                format!("'{}' is not a valid bus name", self.bus.name),
+                "use a well-known name such as org.freedesktop.secrets",
+            ));
+        }
+        if self.bus.kind == BusKind::System {
+            let desktop = [
+                ("kwallet.enabled", self.kwallet.enabled),
+                ("gnome_keyring.enabled", self.gnome_keyring.enabled),
+                ("portal.enabled", self.portal.enabled),
+            ];
+            for (setting, _) in desktop.into_iter().filter(|(_, enabled)| *enabled) {
+                problems.push(ConfigProblem::new(
+                    setting,
+                    "the desktop clients of this interface are on the session bus",
+                    "remove the setting, or serve on the session bus",
+                ));
+            }
+            let session_triggers: Vec<_> = self
+                .auto_lock
+                .lock_on
+                .iter()
+                .filter(|t| **t != LockTrigger::Sleep)
+                .collect();
+            if !session_triggers.is_empty() {
+                problems.push(ConfigProblem::new(
+                    "auto_lock.lock_on",
+                    format!("{:?} need a desktop session", session_triggers),
+                    "keep only \"sleep\" on the system bus",
+                ));
+            }
+        }
+        let builtin = item_schemas::builtin();
+        for (i, schema) in self.schemas.iter().enumerate() {
+            let setting = |name: &str| format!("schemas[{}].{}", i, name);
+            let named = |name: &str| {
+                builtin.iter().any(|s| s.name == name)
+                    || self.schemas[..i].iter().any(|s| s.name == name)
+            };
+            if schema.name.is_empty() || named(&schema.name) {
+                problems.push(ConfigProblem::new(
+                    &setting("name"),
+                    format!("'{}' is already the name of another schema", schema.name),
+                    "give the schema a name of its own, io.linux_tks.Service1.ListSchemas \
+                     lists the built-in ones",
+                ));
+            }
+            let mut attributes: Vec<_> = schema.attributes.keys().collect();
+            attributes.sort();
+            for attribute in attributes {
+                if attribute.is_empty() || item_schemas::is_meta(attribute) {
+                    problems.push(ConfigProblem::new(
+                        &setting("attributes"),
+                        format!("'{}' is not an attribute of the items", attribute),
+                        "remove it, the xdg: and tks: attributes belong to all the schemas",
+                    ));
+                }
+            }
+        }
+        if self.prompt.backend == PromptBackend::Polkit && self.bus.kind != BusKind::System {
+            problems.push(ConfigProblem::new(
+                "prompt.backend",
+                "polkit only identifies the clients of the system bus",
+                "set bus.kind = \"system\", or use another backend",
+            ));
+        }
+        if self.prompt.backend == PromptBackend::Scripted && RunMode::current() != RunMode::Test {
+            problems.push(ConfigProblem::new(
+                "prompt.backend",
+                "scripted prompts would answer the dialogs instead of the user",
+                "use another backend, scripted is for TKS_RUN_MODE=test",
+            ));
+        }
+        let pinentry = &self.prompt.pinentry.program;
+        if pinentry != Pinentry::AUTO
+            && prompter::pinentry_program(pinentry, None, env::var_os("PATH").as_deref()).is_none()
+        {
+            problems.push(ConfigProblem::new(
+                "prompt.pinentry.program",
+                format!("there is no pinentry program '{}'", pinentry),
+                "install it, give its path, or use \"auto\" to pick the flavor of the desktop",
+            ));
+        }
+        problems
+    }
+}
@@ -0,0 +1,134 @@
+//! Access control lists restrict which client applications, by process executable, may read,
+//! write or delete the items of a collection, as KWallet does. Collections have no ACL by default,
+//! letting all the clients in; once restricted, a client not granted an access yet gets the user
+//! asked the first time it needs it, see [crate::tks_dbus::acl]. Items may get an ACL of their own,
+//! which then replaces the one of their collection.
+//!
+//! The ACLs are saved with the metadata, so they're known while the collection is locked.
+
+use crate::storage::collection::Collection;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// Reading the secrets
+    Read,
+    /// Creating items, changing their secret, label or attributes
+    Write,
+    /// Deleting items, or the collection
+    Delete,
+}
+
+impl Access {
+    pub const ALL: [Access; 3] = [Access::Read, Access::Write, Access::Delete];
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Delete => "delete",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Access {
+    type Err = TksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Access::ALL
+            .into_iter()
+            .find(|access| access.to_string() == s)
+            .ok_or(TksError::ParameterError)
+    }
+}
+
+/// The accesses granted to the clients, by process executable
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    grants: BTreeMap<String, BTreeSet<Access>>,
+}
+
+impl Acl {
+    pub fn allows(&self, exe_path: &str, access: Access) -> bool {
+        self.grants
+            .get(exe_path)
+            .is_some_and(|granted| granted.contains(&access))
+    }
+
+    pub fn grant(&mut self, exe_path: &str, access: Access) {
+        self.grants
+            .entry(exe_path.to_string())
+            .or_default()
+            .insert(access);
+    }
+
+    /// Takes the given accesses back from the client, all of them when empty; returns false if it
+    /// had none of them
+    pub fn revoke(&mut self, exe_path: &str, accesses: &[Access]) -> bool {
+        let Some(granted) = self.grants.get_mut(exe_path) else {
+            return false;
+        };
+        let mut revoked = accesses.is_empty() && !granted.is_empty();
+        for access in accesses {
+            revoked |= granted.remove(access);
+        }
+        if accesses.is_empty() || granted.is_empty() {
+            self.grants.remove(exe_path);
+        }
+        revoked
+    }
+
+    /// The clients, sorted by executable, with their accesses
+    pub fn entries(&self) -> Vec<(String, Vec<Access>)> {
+        self.grants
+            .iter()
+            .map(|(exe_path, granted)| (exe_path.clone(), granted.iter().copied().collect()))
+            .collect()
+    }
+}
+
+impl Collection {
+    /// The ACL applying to the item, or to the collection itself without one; `None` lets all the
+    /// clients in
+    pub fn acl_of(&self, item: Option<&Uuid>) -> Result<Option<&Acl>, TksError> {
+        if let Some(item) = item {
+            if let Some(acl) = self.get_item(item)?.acl.as_ref() {
+                return Ok(Some(acl));
+            }
+        }
+        Ok(self.acl.as_ref())
+    }
+}
+
+impl Storage {
+    /// The ACL applying to the collection, or to one of its items, see [Collection::acl_of]
+    pub fn acl_of(&self, uuid: &Uuid, item: Option<&Uuid>) -> Result<Option<Acl>, TksError> {
+        self.with_collection(uuid, |c| Ok(c.acl_of(item)?.cloned()))
+    }
+
+    /// Changes the own ACL of the collection, or of one of its items, then saves the collection
+    pub fn modify_acl<F, T>(
+        &mut self,
+        uuid: &Uuid,
+        item: Option<&Uuid>,
+        f: F,
+    ) -> Result<T, TksError>
+    where
+        F: FnOnce(&mut Option<Acl>) -> T,
+    {
+        self.modify_collection(uuid, |c| match item {
+            Some(item) => Ok(f(&mut c.get_item_mut(item)?.acl)),
+            None => Ok(f(&mut c.acl)),
+        })
+    }
+}
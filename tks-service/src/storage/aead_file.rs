@@ -0,0 +1,63 @@
+//! The files the tks_gcm backend encrypts: the version of their format, see
+//! [crate::storage::schema], the IV, the GCM tag, then the ciphertext. The additional
+//! authenticated data ties the file to what it holds, e.g. the name of its collection, so that the
+//! files can't be swapped. Files too short to hold the IV and the tag get refused as corrupted.
+
+use crate::tks_error::TksError;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+/// Format of the encrypted files; the older formats keep their arm in [open]
+pub const FORMAT_VERSION: u8 = 1;
+
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Encrypts the data in the current format, with a new random IV
+pub fn seal(cipher: Cipher, key: &[u8], aad: &str, data: &[u8]) -> Result<Vec<u8>, TksError> {
+    let mut tag = [0u8; TAG_LEN];
+    let mut iv = [0u8; IV_LEN];
+    rand_bytes(&mut iv)?;
+    let ciphertext = encrypt_aead(cipher, key, Some(&iv), aad.as_bytes(), data, &mut tag)?;
+    let mut encrypted = Vec::with_capacity(1 + IV_LEN + TAG_LEN + ciphertext.len());
+    encrypted.push(FORMAT_VERSION);
+    encrypted.extend_from_slice(&iv);
+    encrypted.extend_from_slice(&tag);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+/// Decrypts a file written by [seal], by this or an older version of the service
+pub fn open(cipher: Cipher, key: &[u8], aad: &str, encrypted: &[u8]) -> Result<Vec<u8>, TksError> {
+    let corrupted = || TksError::SerializationError("Corrupted file".to_string());
+    let (version, rest) = encrypted.split_first().ok_or_else(corrupted)?;
+    let (iv, tag, ciphertext) = match version {
+        1 => {
+            if rest.len() < IV_LEN + TAG_LEN {
+                return Err(corrupted());
+            }
+            let (iv, rest) = rest.split_at(IV_LEN);
+            let (tag, ciphertext) = rest.split_at(TAG_LEN);
+            (iv, tag, ciphertext)
+        }
+        v if *v > FORMAT_VERSION => {
+            return Err(TksError::UnsupportedVersion(format!(
+                "encrypted file format {}, this tks-service reads up to format {}",
+                v, FORMAT_VERSION
+            )))
+        }
+        _ => {
+            return Err(TksError::SerializationError(
+                "Unknown file version".to_string(),
+            ))
+        }
+    };
+    Ok(decrypt_aead(
+        cipher,
+        key,
+        Some(iv),
+        aad.as_bytes(),
+        ciphertext,
+        tag,
+    )?)
+}
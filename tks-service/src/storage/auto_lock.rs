@@ -0,0 +1,76 @@
+//! When `auto_lock.lock_after_idle_minutes` is configured, a background task locks all the
+//! collections once no client called the service for that long, e.g. after the user walked away
+//! from their laptop. Locking drops, and zeroes out, the decrypted secrets.
+
+use crate::settings::SETTINGS;
+use crate::storage::{Storage, STORAGE};
+use crate::tks_error::TksError;
+use lazy_static::lazy_static;
+use log::{debug, error, info};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often the idle time gets checked, at most
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref LAST_ACTIVITY: Mutex<Instant> = Mutex::new(Instant::now());
+}
+
+/// Notes that a client called the service, which postpones the automatic locking
+pub fn record_activity() {
+    *LAST_ACTIVITY.lock().unwrap() = Instant::now();
+}
+
+/// Time elapsed since the last client call
+pub fn idle_time() -> Duration {
+    LAST_ACTIVITY.lock().unwrap().elapsed()
+}
+
+impl Storage {
+    /// Locks all the collections having secrets in memory, returning them; the session collection
+    /// never gets locked, see [crate::storage::memory]
+    pub fn lock_all(&mut self) -> Result<Vec<Uuid>, TksError> {
+        let unlocked: Vec<Uuid> = self
+            .collections
+            .iter()
+            .filter(|c| !c.locked || c.items.iter().any(|i| i.data.is_some()))
+            .map(|c| c.uuid)
+            .filter(|uuid| !self.is_in_memory(uuid))
+            .collect();
+        for uuid in unlocked.iter() {
+            self.lock_collection(uuid)?;
+        }
+        Ok(unlocked)
+    }
+
+    /// Starts the task locking the collections after the configured inactivity. The setting gets
+    /// read upon each check, so that reloading the configuration applies it.
+    pub fn start_auto_lock() {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let minutes = SETTINGS.lock().unwrap().auto_lock.lock_after_idle_minutes;
+                let timeout = match minutes {
+                    Some(minutes) if minutes > 0 => Duration::from_secs(minutes * 60),
+                    _ => continue,
+                };
+                if idle_time() < timeout {
+                    continue;
+                }
+                match STORAGE.write().unwrap().lock_all() {
+                    Ok(locked) if locked.is_empty() => {}
+                    Ok(locked) => {
+                        info!("Idle for {:?}, locked {} collection(s)", timeout, locked.len())
+                    }
+                    // the collections that failed to save stay unlocked, so no change gets lost;
+                    // they get retried on the next tick
+                    Err(e) => error!("Cannot lock the collections after inactivity: {}", e),
+                }
+                debug!("Auto-lock check done");
+            }
+        });
+    }
+}
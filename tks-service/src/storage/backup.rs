@@ -0,0 +1,386 @@
+//! Backups hold all the collections, with their items, attributes and secrets, in a single file
+//! encrypted with a passphrase. The file starts with a clear-text header telling the format
+//! version, so restoring refuses the backups made by a newer, incompatible tks-service before even
+//! asking for the passphrase:
+//!
+//! `TKS-BACKUP` | version (u16) | key derivation iterations (u32) | salt | iv | data | tag
+//!
+//! The data is the JSON serialized [BackupContents], encrypted with AES/GCM using a key derived
+//! from the passphrase with PBKDF2. The whole header is authenticated as well.
+
+use crate::storage::collection::{Item, ItemData, ItemId};
+use crate::storage::merge::ItemsSnapshot;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{debug, error, trace};
+use openssl::hash::MessageDigest;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use secrecy::{ExposeSecret, SecretString};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const MAGIC: &[u8] = b"TKS-BACKUP";
+/// Bumped upon incompatible changes of [BackupContents]
+pub const BACKUP_VERSION: u16 = 1;
+/// PBKDF2 iterations deriving the key of the new backups
+pub const KDF_ITERATIONS: u32 = 210_000;
+const SALT_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4 + SALT_LEN + IV_LEN;
+
+/// The clear-text header of a backup
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackupHeader {
+    pub version: u16,
+    /// PBKDF2 iterations deriving the key from the passphrase
+    pub iterations: u32,
+}
+
+impl BackupHeader {
+    /// Reads the header of a backup, without the passphrase
+    pub fn read(archive: &[u8]) -> Result<BackupHeader, TksError> {
+        if archive.len() < HEADER_LEN + TAG_LEN || !archive.starts_with(MAGIC) {
+            return Err(TksError::BackupError("not a tks backup".to_string()));
+        }
+        let version = u16::from_be_bytes([archive[MAGIC.len()], archive[MAGIC.len() + 1]]);
+        let mut iterations = [0u8; 4];
+        iterations.copy_from_slice(&archive[MAGIC.len() + 2..MAGIC.len() + 6]);
+        Ok(BackupHeader {
+            version,
+            iterations: u32::from_be_bytes(iterations),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupContents {
+    created: u64,
+    collections: Vec<BackupCollection>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupCollection {
+    name: String,
+    label: Option<String>,
+    properties: HashMap<String, String>,
+    aliases: Vec<String>,
+    items: Vec<BackupItem>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupItem {
+    uuid: Uuid,
+    label: String,
+    created: u64,
+    modified: u64,
+    attributes: HashMap<String, String>,
+    secret: Vec<u8>,
+    content_type: String,
+}
+
+impl BackupItem {
+    fn into_item(self, collection_uuid: Uuid) -> Item {
+        Item {
+            label: self.label,
+            created: self.created,
+            modified: self.modified,
+            attributes: self.attributes,
+            id: ItemId {
+                uuid: self.uuid,
+                collection_uuid,
+            },
+            data: Some(ItemData::new(self.uuid, self.secret, self.content_type)),
+            locked: false,
+            acl: None,
+        }
+    }
+}
+
+/// What to do with the collections already in the storage when restoring a backup; collections
+/// are matched by name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestoreMode {
+    /// Only add the backed up items missing from the collection, i.e. the ones having neither the
+    /// same uuid nor the same attributes as an item of the collection
+    Merge,
+    /// Replace the collection's label, properties and items with the backed up ones
+    Replace,
+}
+
+impl FromStr for RestoreMode {
+    type Err = TksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(RestoreMode::Merge),
+            "replace" => Ok(RestoreMode::Replace),
+            _ => Err(TksError::ParameterError),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreOutcome {
+    /// The collections missing from the storage, created from the backup
+    pub created: Vec<Uuid>,
+    pub added: Vec<ItemId>,
+    /// The items deleted because they were not in the backed up collection
+    pub removed: Vec<ItemId>,
+    /// Number of backed up items already in the storage
+    pub skipped: usize,
+}
+
+impl Storage {
+    /// Encrypts all the collections into a backup. All the collections should be unlocked.
+    pub fn create_backup(&self, passphrase: &SecretString) -> Result<Vec<u8>, TksError> {
+        trace!("create_backup");
+        self.check_all_unlocked()?;
+        let contents = BackupContents {
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            // the session collection never gets written anywhere
+            collections: self
+                .collections
+                .iter()
+                .filter(|c| !self.is_in_memory(&c.uuid))
+                .map(|c| BackupCollection {
+                    name: c.name.clone(),
+                    label: c.label.clone(),
+                    properties: c.properties.clone(),
+                    aliases: c.aliases.clone().unwrap_or_default(),
+                    items: c
+                        .items
+                        .iter()
+                        .filter_map(|i| {
+                            i.data.as_ref().map(|data| BackupItem {
+                                uuid: i.id.uuid,
+                                label: i.label.clone(),
+                                created: i.created,
+                                modified: i.modified,
+                                attributes: i.attributes.clone(),
+                                secret: data.secret().to_vec(),
+                                content_type: data.content_type.clone(),
+                            })
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        encrypt(passphrase, &serde_json::to_vec(&contents)?)
+    }
+
+    /// Restores a backup made by [Storage::create_backup]. All the collections should be unlocked.
+    /// When saving any collection fails, the storage is left as it was.
+    pub fn restore_backup(
+        &mut self,
+        archive: &[u8],
+        passphrase: &SecretString,
+        mode: RestoreMode,
+    ) -> Result<RestoreOutcome, TksError> {
+        trace!("restore_backup ({:?})", mode);
+        let contents: BackupContents = serde_json::from_slice(&decrypt(passphrase, archive)?)?;
+        debug!(
+            "Restoring {} collections backed up at {}",
+            contents.collections.len(),
+            contents.created
+        );
+        self.check_all_unlocked()?;
+
+        let mut outcome = RestoreOutcome::default();
+        let mut snapshots = Vec::new();
+        let mut touched = Vec::new();
+        for backup in contents.collections {
+            let uuid = match self.collections.iter().find(|c| c.name == backup.name) {
+                Some(c) => c.uuid,
+                None => match self.create_restored_collection(&backup) {
+                    Ok(uuid) => {
+                        outcome.created.push(uuid);
+                        uuid
+                    }
+                    Err(e) => {
+                        // nothing got saved yet
+                        self.rollback_restore(&snapshots, &[], &outcome.created);
+                        return Err(e);
+                    }
+                },
+            };
+            // item uuids are unique across the collections
+            let taken: Vec<Uuid> = self
+                .collections
+                .iter()
+                .filter(|c| c.uuid != uuid)
+                .flat_map(|c| c.items.iter().map(|i| i.id.uuid))
+                .collect();
+            let collection = self
+                .collections
+                .iter_mut()
+                .find(|c| c.uuid == uuid)
+                .ok_or(TksError::NotFound(None))?;
+            snapshots.push(ItemsSnapshot::new(collection));
+            let items = backup.items.into_iter().map(|i| {
+                let mut item = i.into_item(uuid);
+                if taken.contains(&item.id.uuid) {
+                    let uuid = Uuid::new_v4();
+                    item.id.uuid = uuid;
+                    if let Some(data) = item.data.as_mut() {
+                        data.uuid = uuid;
+                    }
+                }
+                item
+            });
+            match mode {
+                RestoreMode::Merge => {
+                    for item in items {
+                        if collection.items.iter().any(|i| {
+                            i.id.uuid == item.id.uuid || i.attributes == item.attributes
+                        }) {
+                            outcome.skipped += 1;
+                            continue;
+                        }
+                        outcome.added.push(item.id.clone());
+                        collection.items.push(item);
+                    }
+                }
+                RestoreMode::Replace => {
+                    let items: Vec<Item> = items.collect();
+                    let previous = std::mem::replace(&mut collection.items, items);
+                    let kept = |uuid: &Uuid, items: &[Item]| items.iter().any(|i| i.id.uuid == *uuid);
+                    outcome.added.extend(
+                        collection
+                            .items
+                            .iter()
+                            .filter(|i| !kept(&i.id.uuid, &previous))
+                            .map(|i| i.id.clone()),
+                    );
+                    outcome.removed.extend(
+                        previous
+                            .into_iter()
+                            .filter(|p| !kept(&p.id.uuid, &collection.items))
+                            .map(|p| p.id),
+                    );
+                    collection.label = backup.label;
+                    collection.properties = backup.properties;
+                }
+            }
+            touched.push(uuid);
+        }
+
+        let mut saved = Vec::new();
+        for uuid in &touched {
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot save collection '{}', rolling back the restore: {}", uuid, e);
+                self.rollback_restore(&snapshots, &saved, &outcome.created);
+                return Err(e);
+            }
+            saved.push(*uuid);
+        }
+        Ok(outcome)
+    }
+
+    fn check_all_unlocked(&self) -> Result<(), TksError> {
+        match self
+            .collections
+            .iter()
+            .find(|c| c.locked || c.items.iter().any(|i| i.data.is_none()))
+        {
+            Some(c) => Err(TksError::BackupError(format!(
+                "collection '{}' is locked",
+                c.label()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn create_restored_collection(&mut self, backup: &BackupCollection) -> Result<Uuid, TksError> {
+        let uuid = self.create_collection(&backup.name, "", &backup.properties)?;
+        // the aliases already pointing to another collection stay there
+        let aliases: Vec<String> = backup
+            .aliases
+            .iter()
+            .filter(|a| self.read_alias(a).is_err())
+            .cloned()
+            .collect();
+        let collection = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == uuid)
+            .ok_or(TksError::NotFound(None))?;
+        collection.label = backup.label.clone();
+        collection.aliases = (!aliases.is_empty()).then_some(aliases);
+        // the collection is empty and the storage is unlocked, see check_all_unlocked
+        collection.locked = false;
+        Ok(uuid)
+    }
+
+    fn rollback_restore(&mut self, snapshots: &[ItemsSnapshot], saved: &[Uuid], created: &[Uuid]) {
+        self.rollback_items(snapshots, saved);
+        for uuid in created {
+            if let Err(e) = self.delete_collection(uuid) {
+                error!("Cannot delete restored collection '{}': {}", uuid, e);
+            }
+        }
+    }
+}
+
+fn derive_key(passphrase: &SecretString, salt: &[u8], iterations: u32) -> Result<Vec<u8>, TksError> {
+    let mut key = vec![0u8; 32];
+    openssl::pkcs5::pbkdf2_hmac(
+        passphrase.expose_secret().as_bytes(),
+        salt,
+        iterations as usize,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+fn encrypt(passphrase: &SecretString, data: &[u8]) -> Result<Vec<u8>, TksError> {
+    let mut salt = [0u8; SALT_LEN];
+    rand_bytes(&mut salt)?;
+    let mut iv = [0u8; IV_LEN];
+    rand_bytes(&mut iv)?;
+    let mut archive = Vec::with_capacity(HEADER_LEN + data.len() + TAG_LEN);
+    archive.extend_from_slice(MAGIC);
+    archive.extend_from_slice(&BACKUP_VERSION.to_be_bytes());
+    archive.extend_from_slice(&KDF_ITERATIONS.to_be_bytes());
+    archive.extend_from_slice(&salt);
+    archive.extend_from_slice(&iv);
+
+    let key = derive_key(passphrase, &salt, KDF_ITERATIONS)?;
+    let mut tag = [0u8; TAG_LEN];
+    let encrypted = encrypt_aead(Cipher::aes_256_gcm(), &key, Some(&iv), &archive, data, &mut tag)?;
+    archive.extend_from_slice(&encrypted);
+    archive.extend_from_slice(&tag);
+    Ok(archive)
+}
+
+fn decrypt(passphrase: &SecretString, archive: &[u8]) -> Result<Vec<u8>, TksError> {
+    let BackupHeader {
+        version,
+        iterations,
+    } = BackupHeader::read(archive)?;
+    let (header, rest) = archive.split_at(HEADER_LEN);
+    if version == 0 || version > BACKUP_VERSION {
+        return Err(TksError::BackupError(format!(
+            "backup format version {} is not supported, this tks-service only reads versions up \
+             to {}",
+            version, BACKUP_VERSION
+        )));
+    }
+    if iterations == 0 {
+        return Err(TksError::BackupError("damaged backup header".to_string()));
+    }
+    let salt = &header[MAGIC.len() + 6..MAGIC.len() + 6 + SALT_LEN];
+    let iv = &header[HEADER_LEN - IV_LEN..];
+    let (data, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    let key = derive_key(passphrase, salt, iterations)?;
+    decrypt_aead(Cipher::aes_256_gcm(), &key, Some(iv), header, data, tag)
+        .map_err(|_| TksError::BackupError("wrong passphrase or damaged backup".to_string()))
+}
@@ -0,0 +1,100 @@
+//! Importers write many items in a row; other clients writing to the same collection meanwhile
+//! could interleave their changes, e.g. creating an item the import then duplicates. An importer
+//! may take an exclusive batch lease on the collection: until it ends the batch, leaves the bus or
+//! lets the lease expire, the changes other clients make to the collection get refused. The lease
+//! belongs to the process of the client, so the importer may write through another connection,
+//! e.g. the one of its Secret Service library. The service's own writes, such as flushing the
+//! delayed changes, are never refused.
+
+use crate::audit::{self, AuditClient};
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{debug, trace};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Longest lease a client may take, so a stuck importer can't block the others for long
+pub const MAX_BATCH_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+pub(crate) struct BatchLease {
+    owner: AuditClient,
+    expires: Instant,
+}
+
+impl BatchLease {
+    /// Whether the client is the one holding the lease, or another connection of its process
+    fn held_by(&self, client: &AuditClient) -> bool {
+        (client.bus_name.is_some() && client.bus_name == self.owner.bus_name)
+            || (client.pid.is_some() && client.pid == self.owner.pid)
+    }
+}
+
+impl Storage {
+    /// Gives the collection to the client for `timeout`, at most [MAX_BATCH_TIMEOUT]. The client
+    /// holding the lease may renew it.
+    pub fn begin_batch(
+        &mut self,
+        uuid: &Uuid,
+        owner: AuditClient,
+        timeout: Duration,
+    ) -> Result<(), TksError> {
+        trace!("begin_batch {} for {:?} ({:?})", uuid, owner, timeout);
+        self.with_collection(uuid, |_| Ok(()))?;
+        if timeout.is_zero() || owner.bus_name.is_none() {
+            return Err(TksError::ParameterError);
+        }
+        if self.batch(uuid).is_some_and(|lease| !lease.held_by(&owner)) {
+            return Err(TksError::BatchInProgress);
+        }
+        let lease = BatchLease {
+            owner,
+            expires: Instant::now() + timeout.min(MAX_BATCH_TIMEOUT),
+        };
+        self.batches.insert(*uuid, lease);
+        Ok(())
+    }
+
+    /// Gives the collection back to all the clients; only the client holding the lease may end it
+    pub fn end_batch(&mut self, uuid: &Uuid, owner: &AuditClient) -> Result<(), TksError> {
+        trace!("end_batch {} for {:?}", uuid, owner);
+        match self.batch(uuid) {
+            Some(lease) if lease.held_by(owner) => {
+                self.batches.remove(uuid);
+                Ok(())
+            }
+            Some(_) => Err(TksError::PermissionDenied),
+            None => Err(TksError::NotFound(Some("No batch in progress".to_string()))),
+        }
+    }
+
+    /// Ends the batches begun by a client which left the bus
+    pub fn end_batches_of(&mut self, bus_name: &str) {
+        self.batches.retain(|uuid, lease| {
+            let left = lease.owner.bus_name.as_deref() == Some(bus_name);
+            if left {
+                debug!("{} left, ending its batch on collection {}", bus_name, uuid);
+            }
+            !left
+        });
+    }
+
+    /// The unexpired lease on the collection
+    fn batch(&self, uuid: &Uuid) -> Option<&BatchLease> {
+        self.batches.get(uuid).filter(|lease| lease.expires > Instant::now())
+    }
+
+    /// Refuses the changes to the collection of the client being handled, when another client
+    /// holds its lease
+    pub(crate) fn check_batch(&self, uuid: &Uuid) -> Result<(), TksError> {
+        let Some(lease) = self.batch(uuid) else {
+            return Ok(());
+        };
+        let caller = audit::caller();
+        if caller.bus_name.is_none() || lease.held_by(&caller) {
+            return Ok(());
+        }
+        debug!("Refusing the change of {:?} to {}, in a batch of {:?}", caller, uuid, lease.owner);
+        Err(TksError::BatchInProgress)
+    }
+}
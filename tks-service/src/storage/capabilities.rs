@@ -0,0 +1,49 @@
+//! What the storage backend can do, so that clients such as the importers can adapt, instead of
+//! finding out from a failed call. See `io.linux_tks.Service1.GetCapabilities`.
+
+use crate::storage::{Storage, StorageBackendType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// Name of the backend, as in the `storage.backend` setting
+    pub backend: &'static str,
+    /// Whether new collections may be created
+    pub create_collection: bool,
+    /// Whether there may be other collections than `default`
+    pub multiple_collections: bool,
+    /// Whether the secrets may be other bytes than text
+    pub binary_secrets: bool,
+    /// Size of the largest secret, in bytes, if the backend has a limit
+    pub max_secret_size: Option<u64>,
+}
+
+impl Capabilities {
+    /// What the backends storing the collections on their own can do
+    pub(crate) fn full(backend: &'static str) -> Capabilities {
+        Capabilities {
+            backend,
+            create_collection: true,
+            multiple_collections: true,
+            binary_secrets: true,
+            max_secret_size: None,
+        }
+    }
+}
+
+impl StorageBackendType {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            StorageBackendType::FSCrypt => "fscrypt",
+            StorageBackendType::TksGcm => "tks_gcm",
+            StorageBackendType::PasswordStore => "password-store",
+            StorageBackendType::Memory => "memory",
+        }
+    }
+}
+
+impl Storage {
+    /// What the `[storage]` backend, holding the new collections, can do
+    pub fn capabilities(&self) -> Capabilities {
+        self.mounts[0].backend.capabilities()
+    }
+}
@@ -0,0 +1,61 @@
+//! Sync and diff tools comparing collections need to know which secrets changed, without reading
+//! them. Collections having the `tks:secret-checksums` property set to `true` give each item a
+//! `tks:secret-hmac` attribute, holding the HMAC-SHA256 of its secret. The HMAC key is derived
+//! from the storage key and the collection, so the checksums can't be used to guess the secrets
+//! nor to tell whether two collections hold the same secret. The checksums get updated whenever
+//! the collection gets saved; the locked items keep theirs.
+
+use crate::hex;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{debug, trace};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use uuid::Uuid;
+
+pub const CHECKSUMS_PROPERTY: &str = "tks:secret-checksums";
+pub const CHECKSUM_ATTRIBUTE: &str = "tks:secret-hmac";
+
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, TksError> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Hex encoded HMAC-SHA256 of the secret
+pub fn checksum(key: &[u8], secret: &[u8]) -> Result<String, TksError> {
+    Ok(hex::encode(&hmac(key, secret)?))
+}
+
+impl Storage {
+    /// Sets the checksum attribute of the unlocked items of a collection asking for them
+    pub(crate) fn update_checksums(&mut self, uuid: &Uuid) -> Result<(), TksError> {
+        let mount = self.mount_of(uuid)?;
+        let collection = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        if collection.properties.get(CHECKSUMS_PROPERTY).map(String::as_str) != Some("true") {
+            return Ok(());
+        }
+        let key = match self.mounts[mount].backend.checksum_key(uuid) {
+            Ok(key) => key,
+            Err(TksError::NotSupported(what)) => {
+                debug!("Collection {} asks for checksums, but {} aren't supported", uuid, what);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        trace!("update_checksums {}", uuid);
+        for item in collection.items.iter_mut() {
+            if let Some(data) = &item.data {
+                let checksum = checksum(&key, data.secret())?;
+                item.attributes.insert(CHECKSUM_ATTRIBUTE.to_string(), checksum);
+            }
+        }
+        Ok(())
+    }
+}
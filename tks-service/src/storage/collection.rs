@@ -1,34 +1,70 @@
+use crate::storage::acl::Acl;
+use crate::storage::checksums::CHECKSUM_ATTRIBUTE;
+use crate::storage::folders::FolderIndex;
+use crate::storage::history::{HistoryEntry, HistoryOperation};
+use crate::storage::oauth;
+use crate::storage::schema;
+use crate::storage::search::SearchIndex;
+use crate::storage::secure_buffer::SecureBuffer;
+use crate::storage::trash::TrashedItem;
+use crate::storage::versions::SecretVersion;
 use crate::storage::{CollectionSecrets, DEFAULT_NAME};
 use crate::tks_dbus::session_impl::Session;
 use crate::tks_error::TksError;
 use log::{debug, error, trace};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 use futures::TryFutureExt;
 use openssl::rand::rand_bytes;
 use uuid::Uuid;
 
-/// This is the item's secret data
+/// The standard collection property holding its label
+pub const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Collection.Label";
+
+/// This is the item's secret data; it gets zeroed when dropped, see [SecureBuffer]
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ItemData {
-    uuid: Uuid,
-    data: Vec<u8>,
+    pub(crate) uuid: Uuid,
+    pub(crate) data: SecureBuffer,
     pub content_type: String,
+    /// Previous values of the secret, latest first, see [crate::storage::versions]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) versions: Vec<SecretVersion>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ItemData {
+    pub(crate) fn new(uuid: Uuid, data: Vec<u8>, content_type: String) -> ItemData {
+        ItemData {
+            uuid,
+            data: data.into(),
+            content_type,
+            versions: Vec::new(),
+        }
+    }
+    pub(crate) fn secret(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Item {
     pub label: String,
     pub created: u64,
     pub modified: u64,
     pub attributes: HashMap<String, String>,
     pub id: ItemId,
+    /// Replaces the ACL of the collection for this item, see [crate::storage::acl]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<Acl>,
 
     // when Item is locked, this is None
     #[serde(skip)]
     pub data: Option<ItemData>,
+    // items can be unlocked individually, even when their collection is still locked
+    #[serde(skip)]
+    pub locked: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -43,11 +79,34 @@ pub struct Collection {
     schema_version: u8,
     pub uuid: Uuid,
     pub default: bool,
+    /// Internal name, used to build the collection's file names
     pub name: String,
+    /// Label shown to the users; it defaults to the name for the collections created without one
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Custom `tks:*` properties given upon collection creation
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
     pub items: Vec<Item>,
     pub aliases: Option<Vec<String>>,
     pub created: u64,
     pub modified: u64,
+    /// Bumped each time the collection gets saved, so clients can cheaply detect changes
+    #[serde(default)]
+    pub sequence: u64,
+    /// Latest operations on the items, oldest first, see [crate::storage::history]
+    #[serde(default)]
+    pub history: VecDeque<HistoryEntry>,
+    /// Clients allowed to use the items, all of them when `None`, see [crate::storage::acl]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<Acl>,
+    /// Deleted items, oldest first, see [crate::storage::trash]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trash: Vec<TrashedItem>,
+    /// Saves of the collection by each machine, when its backend gets synced, see
+    /// [crate::storage::sync]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub versions: BTreeMap<String, u64>,
 
     #[serde(skip)]
     pub(crate) path: PathBuf,
@@ -55,6 +114,18 @@ pub struct Collection {
     pub(crate) items_path: PathBuf,
     #[serde(skip)]
     pub locked: bool,
+    /// Index of the backend saving the collection, see [crate::storage::Storage::open]
+    #[serde(skip)]
+    pub(crate) mount: usize,
+    /// see [crate::storage::folders]
+    #[serde(skip)]
+    pub(crate) folders: FolderIndex,
+    /// see [crate::storage::search]
+    #[serde(skip)]
+    pub(crate) search_index: SearchIndex,
+    /// Whether the backend of the collection gets synced with other machines
+    #[serde(skip)]
+    pub(crate) synced: bool,
 }
 
 impl Collection {
@@ -78,20 +149,49 @@ impl Collection {
         let collection = Collection {
             uuid: Uuid::new_v4(),
             default: DEFAULT_NAME == name,
-            schema_version: 1,
+            schema_version: schema::METADATA_VERSION,
             name: name.to_string(),
+            label: None,
+            properties: HashMap::new(),
             path: path.clone(),
             items_path: items_path.clone(),
             items: Vec::new(),
             aliases: None,
             locked: true,
+            mount: 0,
             created: ts,
             modified: ts,
+            sequence: 0,
+            history: VecDeque::new(),
+            acl: None,
+            trash: Vec::new(),
+            versions: BTreeMap::new(),
+            folders: FolderIndex::from([(String::new(), Vec::new())]),
+            search_index: SearchIndex::default(),
+            synced: false,
         };
 
         Ok(collection)
     }
 
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Takes the label and the custom `tks:*` properties; any other property is ignored, as
+    /// allowed by the spec
+    pub fn set_properties(&mut self, properties: &HashMap<String, String>) {
+        if let Some(label) = properties.get(LABEL_PROPERTY) {
+            self.label = Some(label.clone());
+        }
+        properties
+            .iter()
+            .filter(|(k, _)| k.starts_with("tks:"))
+            .for_each(|(k, v)| {
+                self.properties.insert(k.clone(), v.clone());
+            });
+    }
+
     pub fn create_item(
         &mut self,
         label: &str,
@@ -125,14 +225,18 @@ impl Collection {
             data: Some(ItemData {
                 uuid,
                 data: match secret_session.decrypt(&secret.1, &secret.2, sender) {
-                    Ok(data) => data,
+                    Ok(data) => data.into(),
+                    Err(TksError::SessionExpired) => return Err(TksError::SessionExpired),
                     Err(e) => {
                         error!("Cannot decrypt secret: {}", e);
                         return Err(TksError::CryptoError);
                     }
                 },
                 content_type: secret.3,
+                versions: Vec::new(),
             }),
+            locked: false,
+            acl: None,
             id: ItemId {
                 collection_uuid: self.uuid,
                 uuid,
@@ -140,14 +244,17 @@ impl Collection {
             attributes: properties,
         };
         let item = if let Some(index) = self.items.iter().position(|i| {
-            i.attributes == item.attributes
-                && match (&i.data, &item.data) {
-                    (Some(d1), Some(d2)) => {
-                        d1.content_type == d2.content_type && d1.data == d2.data
+            // a new OAuth2 token of the same grant replaces the previous one, see
+            // [crate::storage::oauth]
+            oauth::same_grant(&i.attributes, &item.attributes)
+                || i.has_attributes(&item.attributes)
+                    && match (&i.data, &item.data) {
+                        (Some(d1), Some(d2)) => {
+                            d1.content_type == d2.content_type && d1.data == d2.data
+                        }
+                        (None, None) => true,
+                        _ => false,
                     }
-                    (None, None) => true,
-                    _ => false,
-                }
         }) {
             if replace {
                 self.items[index] = item;
@@ -160,6 +267,44 @@ impl Collection {
             self.items.last().unwrap()
         };
         let item_id = item.id.clone();
+        let label = item.label.clone();
+        self.record_history(HistoryOperation::Created, &item_id.uuid, &label);
+        Ok(item_id)
+    }
+
+    /// Adds an item holding a secret the service already has in plain, e.g. one written through
+    /// the KWallet interface, see [crate::tks_dbus::kwallet_impl]; no duplicate check is done
+    pub fn add_item(
+        &mut self,
+        label: &str,
+        attributes: HashMap<String, String>,
+        secret: Vec<u8>,
+        content_type: String,
+    ) -> Result<ItemId, TksError> {
+        trace!("add_item");
+        if self.locked {
+            debug!("Collection is locked, aborting add_item");
+            return Err(TksError::PermissionDenied);
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let uuid = Uuid::new_v4();
+        let item_id = ItemId {
+            collection_uuid: self.uuid,
+            uuid,
+        };
+        self.items.push(Item {
+            label: label.to_string(),
+            created: ts,
+            modified: ts,
+            attributes,
+            id: item_id.clone(),
+            acl: None,
+            data: Some(ItemData::new(uuid, secret, content_type)),
+            locked: false,
+        });
+        self.record_history(HistoryOperation::Created, &uuid, label);
         Ok(item_id)
     }
 
@@ -187,23 +332,25 @@ impl Collection {
             .ok_or_else(|| TksError::NotFound(None))
             .and_then(|i| {
                 let older = self.items.swap_remove(i);
+                self.record_history(HistoryOperation::Deleted, &older.id.uuid, &older.label);
                 Ok(older)
             })
     }
 
+    /// Returns the secrets of the unlocked items, trashed ones included; individually locked
+    /// items are left out
     pub(crate) fn get_secrets(&self) -> CollectionSecrets {
         CollectionSecrets {
             items: self
-                .items
-                .iter()
-                .map(|i| i.data.as_ref().unwrap().clone())
+                .stored_items()
+                .filter_map(|i| i.data.clone())
                 .collect(),
         }
     }
 
-    pub fn unlock(&mut self, data: &Vec<u8>) -> Result<(), TksError> {
+    pub fn unlock(&mut self, data: &[u8]) -> Result<(), TksError> {
         trace!("unlock - items count = {}, data size = {}", self.items.len(), data.len());
-        if !self.locked || self.items.is_empty() {
+        if !self.locked || self.stored_items().next().is_none() {
             self.locked = false;
             return Ok(());
         }
@@ -217,48 +364,77 @@ impl Collection {
         let collection_secrets: CollectionSecrets = serde_json::from_slice(data)
             .map_err(|e| TksError::SerializationError(e.to_string()))?;
 
-        for item in self.items.iter_mut() {
-            collection_secrets
-                .items
-                .iter()
-                .find(|s| s.uuid == item.id.uuid)
-                .ok_or_else(||
-                    // looks like the items file got out of sync with this collection and this is very bad
-                    TksError::ItemNotFound
-                )
-                .and_then(|s| {
+        for item in self.stored_items_mut() {
+            match collection_secrets.items.iter().find(|s| s.uuid == item.id.uuid) {
+                Some(s) => {
                     item.data = Some(s.clone());
-                    Ok(())
-                })?;
+                    item.locked = false;
+                }
+                None => {
+                    // the item's secret got lost or corrupted; the remaining items are still
+                    // usable, so only this one stays locked
+                    error!("No secret found for item {}, leaving it locked", item.id.uuid);
+                    item.locked = true;
+                }
+            }
         }
         self.locked = false;
         Ok(())
     }
     pub fn lock(&mut self) -> Result<(), TksError> {
         self.locked = true;
-        // TODO: items should be zeroed out upon free
-        self.items.iter_mut().for_each(|item| item.data = None);
+        self.stored_items_mut().for_each(|item| item.lock());
         Ok(())
     }
 }
 
 impl Item {
+    /// The value of an attribute, borrowed from the item
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+    /// The attributes, borrowed from the item, so enumerating them doesn't clone the map
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attributes.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+    /// Whether the item has exactly the given attributes, leaving its secret checksum aside, see
+    /// [crate::storage::checksums]
+    pub fn has_attributes(&self, attributes: &HashMap<String, String>) -> bool {
+        let relevant = |(name, _): &(&String, &String)| name.as_str() != CHECKSUM_ATTRIBUTE;
+        self.attributes.iter().filter(relevant).count()
+            == attributes.iter().filter(relevant).count()
+            && attributes.iter().filter(relevant).all(|(n, v)| self.attributes.get(n) == Some(v))
+    }
+    pub fn unlock(&mut self, data: ItemData) {
+        trace!("unlock item '{}'", self.label);
+        self.data = Some(data);
+        self.locked = false;
+    }
+    pub fn lock(&mut self) {
+        self.locked = true;
+        // dropping the secret zeroes it, see SecureBuffer
+        self.data = None;
+    }
     pub fn get_secret(
         &self,
         session: &Session,
         sender: String,
-    ) -> Result<(String, Vec<u8>, Vec<u8>, String), std::io::Error> {
+    ) -> Result<(String, Vec<u8>, Vec<u8>, String), TksError> {
         trace!("get_secret called on '{}'", self.label);
         let data = self.data.as_ref().ok_or_else(|| {
             std::io::Error::new(std::io::ErrorKind::NotFound, format!("Item is locked"))
         })?;
 
-        let (iv, secret) = session.encrypt(&data.data, sender).map_err(|e| {
-            error!("Error encrypting secret: {}", e);
-            std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Data cannot be prepared"),
-            )
+        let (iv, secret) = session.encrypt(&data.data, sender).map_err(|e| match e {
+            TksError::SessionExpired => e,
+            e => {
+                error!("Error encrypting secret: {}", e);
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "Data cannot be prepared".to_string(),
+                )
+                .into()
+            }
         })?;
         Ok(("".to_string(), iv, secret, data.content_type.clone()))
     }
@@ -271,11 +447,11 @@ impl Item {
         sender: String,
     ) -> Result<(), TksError> {
         trace!("set_secret called on '{}'", self.label);
-        self.data = Some(ItemData {
-            uuid: self.id.uuid,
-            data: session.decrypt(&parameters, value, sender)?,
-            content_type,
-        });
+        let secret = session.decrypt(&parameters, value, sender)?;
+        match self.data.as_mut() {
+            Some(data) => data.replace(secret.into(), content_type),
+            None => self.data = Some(ItemData::new(self.id.uuid, secret, content_type)),
+        }
         Ok(())
     }
 }
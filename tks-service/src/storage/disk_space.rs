@@ -0,0 +1,133 @@
+//! An encrypted file cut short by a full filesystem can't be decrypted anymore. When
+//! `storage.min_free_space` is configured, saves get refused as long as the storage filesystem has
+//! less free space than that, and a background task warns about it through the
+//! `io.linux_tks.Service1.StorageSpaceLow` signal.
+
+use crate::storage::{Storage, STORAGE};
+use crate::tks_dbus::service_impl::ServiceHandle;
+use crate::tks_dbus::tks::service::IoLinuxTksService1StorageSpaceLow;
+use crate::tks_dbus::{DBusHandle, MESSAGE_SENDER};
+use crate::tks_error::TksError;
+use dbus::message::SignalArgs;
+use log::{debug, info, warn};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use uuid::Uuid;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Set while the free space is below the threshold, so the signal is only sent once
+static SPACE_LOW: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskUsage {
+    /// Bytes taken by the storage files
+    pub used: u64,
+    /// Bytes left on the storage filesystem, if it could be found
+    pub available: Option<u64>,
+}
+
+impl Storage {
+    /// Disk usage of the `[storage]` backend, holding the new collections
+    pub fn disk_usage(&self) -> Result<DiskUsage, TksError> {
+        let root = self.mounts[0]
+            .backend
+            .root_path()
+            .ok_or(TksError::NotSupported("disk usage"))?;
+        Ok(DiskUsage {
+            used: dir_size(&root)?,
+            available: available_space(&root),
+        })
+    }
+
+    /// Fails when saving the collection could fill the filesystem of its backend up
+    pub(crate) fn check_free_space(&self, uuid: &Uuid) -> Result<(), TksError> {
+        if self.min_free_space == 0 {
+            return Ok(());
+        }
+        let available = self.mounts[self.mount_of(uuid)?]
+            .backend
+            .root_path()
+            .and_then(|root| available_space(&root));
+        match available {
+            Some(available) if available < self.min_free_space => {
+                warn!("Refusing to save, only {} bytes left on the storage filesystem", available);
+                Err(TksError::StorageFull(available))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts the task watching the free space of the storage filesystem
+    pub fn start_space_monitor() {
+        let threshold = STORAGE.read().unwrap().min_free_space;
+        if threshold == 0 {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let usage = STORAGE.read().unwrap().disk_usage();
+                match usage {
+                    Ok(DiskUsage {
+                        used,
+                        available: Some(available),
+                    }) => {
+                        debug!("Storage uses {} bytes, {} bytes available", used, available);
+                        space_checked(available, threshold);
+                    }
+                    Ok(_) => debug!("Storage filesystem not found, free space unknown"),
+                    Err(e) => warn!("Cannot compute the storage disk usage: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn space_checked(available: u64, threshold: u64) {
+    let low = available < threshold;
+    if SPACE_LOW.swap(low, Ordering::Relaxed) == low {
+        return;
+    }
+    if low {
+        warn!("Storage filesystem is almost full, {} bytes left", available);
+        MESSAGE_SENDER.lock().unwrap().send_message(
+            IoLinuxTksService1StorageSpaceLow {
+                available,
+                threshold,
+            }
+            .to_emit_message(&ServiceHandle {}.path().into()),
+        );
+    } else {
+        info!("Storage filesystem has enough free space again");
+    }
+}
+
+/// Free space of the filesystem mounted the closest to `path`
+fn available_space(path: &Path) -> Option<u64> {
+    let path = path.canonicalize().ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|d| path.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+        .map(|d| d.available_space())
+}
+
+fn dir_size(path: &Path) -> Result<u64, TksError> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
@@ -0,0 +1,132 @@
+//! Importing the same secrets several times, e.g. from KWallet then from a backup, leaves
+//! duplicated items behind. Items are duplicates when their attributes are the same once
+//! normalized, optionally requiring the same secret too. The duplicates then get deleted in a
+//! single batch, which deletes either all the given items or none of them.
+
+use crate::storage::checksums::CHECKSUM_ATTRIBUTE;
+use crate::storage::collection::{Item, ItemId};
+use crate::storage::merge::ItemsSnapshot;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{error, trace};
+use openssl::sha::sha256;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Attributes telling where an item came from rather than what it is; the same secret imported
+/// by different tools gets different values
+const PROVENANCE_ATTRIBUTES: &[&str] = &["xdg:creator", "tks:kwallet-entry-type"];
+
+/// What duplicated items have in common, mostly borrowed from the item
+#[derive(PartialEq, Eq, Hash)]
+struct DuplicateKey<'a> {
+    attributes: Vec<(Cow<'a, str>, &'a str)>,
+    /// Only used for the items having no attributes at all
+    label: Option<String>,
+    secret_digest: Option<[u8; 32]>,
+}
+
+/// Lowercases the given name, only allocating when it has uppercase letters
+fn lowercase(name: &str) -> Cow<'_, str> {
+    match name.chars().any(|c| !c.is_lowercase() && c.to_lowercase().ne([c])) {
+        true => Cow::Owned(name.to_lowercase()),
+        false => Cow::Borrowed(name),
+    }
+}
+
+impl DuplicateKey<'_> {
+    fn new(item: &Item, compare_secrets: bool) -> DuplicateKey<'_> {
+        let mut attributes: Vec<(Cow<str>, &str)> = item
+            .attributes()
+            .map(|(name, value)| (lowercase(name.trim()), value.trim()))
+            .filter(|(name, value)| !value.is_empty() && !PROVENANCE_ATTRIBUTES.contains(&&**name))
+            // the secrets only get compared when asked to
+            .filter(|(name, _)| *name != CHECKSUM_ATTRIBUTE)
+            .collect();
+        attributes.sort();
+        let label = match attributes.is_empty() {
+            true => Some(item.label.trim().to_lowercase()),
+            false => None,
+        };
+        let secret_digest = match compare_secrets {
+            true => item.data.as_ref().map(|d| sha256(d.secret())),
+            false => None,
+        };
+        DuplicateKey {
+            attributes,
+            label,
+            secret_digest,
+        }
+    }
+}
+
+impl Storage {
+    /// Groups the items of a collection having the same normalized attributes, and the same
+    /// secret when `compare_secrets` is set, which requires the collection to be unlocked. Each
+    /// group lists the most recently modified item first.
+    pub fn find_duplicates(
+        &self,
+        collection: &Uuid,
+        compare_secrets: bool,
+    ) -> Result<Vec<Vec<ItemId>>, TksError> {
+        trace!("find_duplicates in {} (secrets: {})", collection, compare_secrets);
+        self.with_collection(collection, |c| {
+            if compare_secrets && (c.locked || c.items.iter().any(|i| i.data.is_none())) {
+                return Err(TksError::PermissionDenied);
+            }
+            let mut groups: HashMap<DuplicateKey, Vec<&Item>> = HashMap::new();
+            for item in c.items.iter() {
+                groups
+                    .entry(DuplicateKey::new(item, compare_secrets))
+                    .or_default()
+                    .push(item);
+            }
+            let mut groups: Vec<Vec<&Item>> =
+                groups.into_values().filter(|g| g.len() > 1).collect();
+            groups.iter_mut().for_each(|g| g.sort_by_key(|i| Reverse(i.modified)));
+            groups.sort_by(|a, b| a[0].label.cmp(&b[0].label));
+            Ok(groups
+                .into_iter()
+                .map(|g| g.into_iter().map(|i| i.id.clone()).collect())
+                .collect())
+        })
+    }
+
+    /// Deletes all the given items, moving them to the trash of their collection, or none of them
+    /// when a collection can't be saved
+    pub fn delete_items(&mut self, items: &[ItemId]) -> Result<(), TksError> {
+        trace!("delete_items {:?}", items.iter().map(|i| i.uuid).collect::<Vec<_>>());
+        let mut collections: Vec<Uuid> = items.iter().map(|i| i.collection_uuid).collect();
+        collections.sort();
+        collections.dedup();
+        let snapshots = collections
+            .iter()
+            .map(|uuid| self.with_collection(uuid, |c| Ok(ItemsSnapshot::new(c))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for item_id in items {
+            let deleted = self
+                .collections
+                .iter_mut()
+                .find(|c| c.uuid == item_id.collection_uuid)
+                .ok_or(TksError::NotFound(None))
+                .and_then(|c| c.trash_item(&item_id.uuid));
+            if let Err(e) = deleted {
+                self.rollback_items(&snapshots, &[]);
+                return Err(e);
+            }
+        }
+        let mut saved = Vec::new();
+        for uuid in &collections {
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot save collection '{}', rolling back the deletion: {}", uuid, e);
+                self.rollback_items(&snapshots, &saved);
+                return Err(e);
+            }
+            saved.push(*uuid);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Short-lived secrets, such as access tokens, may be given a `tks:expires-at` attribute holding
+//! the time, in seconds since the Unix epoch, after which they're no longer needed. A background
+//! task then deletes the expired items, emitting ItemDeleted; like the other deleted items, they
+//! go to the trash, see [crate::storage::trash]. Collections having the `tks:on-expiry` property
+//! set to `lock` get their expired items locked instead, so their secret can still be read after
+//! unlocking them again.
+//!
+//! The expired items of locked collections get removed once their collection gets unlocked. The
+//! items of read-only collections, and of the session collection when it has to lock them, are
+//! left alone.
+
+use crate::audit;
+use crate::audit::{AuditEvent, Outcome};
+use crate::storage::collection::{Collection, ItemId};
+use crate::storage::{Storage, STORAGE};
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_error::TksError;
+use log::{debug, error, info, trace};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+pub const EXPIRES_AT_ATTRIBUTE: &str = "tks:expires-at";
+pub const ON_EXPIRY_PROPERTY: &str = "tks:on-expiry";
+
+/// How often the expired items get looked for
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// What got done with the expired items
+#[derive(Debug, Default)]
+pub struct Expired {
+    pub deleted: Vec<ItemId>,
+    pub locked: Vec<ItemId>,
+}
+
+impl Collection {
+    /// Whether the expired items get locked instead of deleted
+    fn locks_expired_items(&self) -> bool {
+        self.properties.get(ON_EXPIRY_PROPERTY).map(String::as_str) == Some("lock")
+    }
+
+    /// The items expired at the given time; already locked ones are left out when they'd get
+    /// locked
+    fn expired_items(&self, now: u64) -> Vec<ItemId> {
+        let lock = self.locks_expired_items();
+        self.items
+            .iter()
+            .filter(|i| !lock || i.data.is_some())
+            .filter(|i| {
+                let Some(expires_at) = i.attribute(EXPIRES_AT_ATTRIBUTE) else {
+                    return false;
+                };
+                match expires_at.parse::<u64>() {
+                    Ok(expires_at) => expires_at <= now,
+                    Err(_) => {
+                        debug!(
+                            "Ignoring the malformed expiry '{}' of {}",
+                            expires_at, i.id.uuid
+                        );
+                        false
+                    }
+                }
+            })
+            .map(|i| i.id.clone())
+            .collect()
+    }
+}
+
+impl Storage {
+    /// Deletes, or locks, the items expired at the given time. A collection failing to save keeps
+    /// its expired items, the other ones still get theirs removed.
+    pub fn expire_items(&mut self, now: u64) -> Expired {
+        let mut expired = Expired::default();
+        let collections: Vec<(Uuid, bool, Vec<ItemId>)> = self
+            .collections
+            .iter()
+            .filter(|c| !c.locked && self.check_writable(&c.uuid).is_ok())
+            .map(|c| (c.uuid, c.locks_expired_items(), c.expired_items(now)))
+            .filter(|(_, _, items)| !items.is_empty())
+            .collect();
+        for (uuid, lock, items) in collections {
+            trace!("{} item(s) of {} expired", items.len(), uuid);
+            let removed = match lock {
+                // it could never get unlocked again
+                true if self.is_in_memory(&uuid) => continue,
+                true => self.lock_expired_items(&items, &mut expired.locked),
+                false => self
+                    .delete_items(&items)
+                    .map(|_| expired.deleted.extend(items)),
+            };
+            if let Err(e) = removed {
+                error!("Cannot remove the expired items of {}: {}", uuid, e);
+            }
+        }
+        expired
+    }
+
+    fn lock_expired_items(
+        &mut self,
+        items: &[ItemId],
+        locked: &mut Vec<ItemId>,
+    ) -> Result<(), TksError> {
+        for item in items {
+            self.lock_item(item)?;
+            locked.push(item.clone());
+        }
+        Ok(())
+    }
+
+    /// Starts the task deleting, or locking, the expired items
+    pub fn start_expiry() {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                // the items failing to get removed are retried on the next tick
+                let expired = STORAGE.write().unwrap().expire_items(now());
+                if !expired.deleted.is_empty() {
+                    info!("Deleted {} expired item(s)", expired.deleted.len());
+                    let uuids = expired.deleted.iter().map(|i| i.uuid).collect();
+                    audit::record(AuditEvent::ItemDelete, Outcome::Success, uuids);
+                    expired.deleted.iter().for_each(ItemImpl::unregister);
+                }
+                if !expired.locked.is_empty() {
+                    info!("Locked {} expired item(s)", expired.locked.len());
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,288 @@
+//! All the writes done by the storage backends go through this module. Files get written to a
+//! temporary file first, which then replaces the original, so a crash in the middle of a write
+//! leaves either the old or the new contents.
+//!
+//! A collection save writes several files, its metadata and its secrets, which only make sense
+//! together: these get written as a [Group]. Its temporary files only replace the originals once
+//! all of them got written, the journal of the group listing them meanwhile, so that [recover]
+//! completes the replacement after a crash. A crash before the journal got written leaves all the
+//! previous files instead.
+//!
+//! With the `fault-injection` feature, the test suite may simulate a power loss in the middle of a
+//! collection save, see [inject_fault].
+
+#[cfg(any(test, feature = "fault-injection"))]
+use lazy_static::lazy_static;
+#[cfg(any(test, feature = "fault-injection"))]
+use log::debug;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(any(test, feature = "fault-injection"))]
+use std::sync::Mutex;
+
+/// Suffix of the temporary files; leftovers are interrupted writes, see [recover]
+const TEMP_SUFFIX: &str = ".tks-tmp";
+/// Name of the journal of the group being committed, in the directory given to [recover]
+const JOURNAL: &str = "journal";
+
+/// Failure to simulate on the upcoming writes; writes are counted from 1, starting when the fault
+/// gets injected
+#[cfg(any(test, feature = "fault-injection"))]
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    /// The nth write only stores the first half of its data, then fails
+    PartialWrite(usize),
+    /// The nth write, and all the writes after it, fail without touching the disk, as if the
+    /// machine lost power right before it
+    PowerLoss(usize),
+    /// The next group stops once its journal got written, as if the machine lost power before its
+    /// files replaced the previous ones
+    InterruptedCommit,
+}
+
+#[cfg(any(test, feature = "fault-injection"))]
+struct FaultState {
+    fault: Fault,
+    writes: usize,
+}
+
+#[cfg(any(test, feature = "fault-injection"))]
+lazy_static! {
+    static ref FAULT: Mutex<Option<FaultState>> = Mutex::new(None);
+}
+
+#[cfg(any(test, feature = "fault-injection"))]
+pub fn inject_fault(fault: Fault) {
+    warn!("Injecting storage fault {:?}", fault);
+    *FAULT.lock().unwrap() = Some(FaultState { fault, writes: 0 });
+}
+
+#[cfg(any(test, feature = "fault-injection"))]
+pub fn clear_fault() {
+    *FAULT.lock().unwrap() = None;
+}
+
+/// Fails the write of `path` the way the injected fault says, if any
+#[cfg(any(test, feature = "fault-injection"))]
+fn simulate_fault(path: &Path, temp_path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut fault = FAULT.lock().unwrap();
+    let Some(state) = fault.as_mut() else {
+        return Ok(());
+    };
+    state.writes += 1;
+    match state.fault {
+        Fault::PartialWrite(n) if state.writes == n => {
+            debug!("Simulating partial write of {:?}", path);
+            fs::write(temp_path, &contents[..contents.len() / 2])?;
+            Err(io::Error::other("simulated partial write"))
+        }
+        Fault::PowerLoss(n) if state.writes >= n => {
+            debug!("Simulating power loss before writing {:?}", path);
+            Err(io::Error::other("simulated power loss"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(test, feature = "fault-injection")))]
+fn simulate_fault(_path: &Path, _temp_path: &Path, _contents: &[u8]) -> io::Result<()> {
+    Ok(())
+}
+
+/// Fails the commit of a group once its journal got written, when the test suite asks to
+#[cfg(any(test, feature = "fault-injection"))]
+fn simulate_interrupted_commit() -> io::Result<()> {
+    let mut fault = FAULT.lock().unwrap();
+    if let Some(FaultState {
+        fault: Fault::InterruptedCommit,
+        ..
+    }) = *fault
+    {
+        debug!("Simulating power loss before committing the group");
+        *fault = None;
+        return Err(io::Error::other("simulated power loss"));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(test, feature = "fault-injection")))]
+fn simulate_interrupted_commit() -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    write_temp(path, contents.as_ref())?;
+    replace(path)
+}
+
+/// Writes the temporary file meant to replace `path`, see [replace]
+fn write_temp(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    simulate_fault(path, &temp_path, contents)?;
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Replaces `path` with its temporary file
+fn replace(path: &Path) -> io::Result<()> {
+    fs::rename(temp_path(path), path)?;
+    // the rename itself only survives a crash once the directory got synced
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = OsString::from(path);
+    temp_path.push(TEMP_SUFFIX);
+    temp_path.into()
+}
+
+/// Whether the file is the temporary file of a write in progress, or an interrupted one
+pub(crate) fn is_temp(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(TEMP_SUFFIX)
+}
+
+/// Files replacing the previous ones together, e.g. the metadata and the secrets of a collection,
+/// once the group gets committed; the files may get removed as well
+pub(crate) struct Group {
+    /// Where the journal goes, the directory given to [recover]
+    root: PathBuf,
+    journal: Journal,
+}
+
+/// What a group does once committed, so that [recover] completes it after a crash
+#[derive(Default, Serialize, Deserialize)]
+struct Journal {
+    written: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+}
+
+impl Group {
+    pub(crate) fn new(root: &Path) -> Group {
+        Group {
+            root: root.to_path_buf(),
+            journal: Journal::default(),
+        }
+    }
+
+    /// Writes the temporary file which replaces `path` once the group gets committed
+    pub(crate) fn write<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &mut self,
+        path: P,
+        contents: C,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        write_temp(path, contents.as_ref())?;
+        if !self.journal.written.iter().any(|p| p == path) {
+            self.journal.written.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Removes the file or the directory once the group gets committed
+    pub(crate) fn remove<P: AsRef<Path>>(&mut self, path: P) {
+        self.journal.removed.push(path.as_ref().to_path_buf());
+    }
+
+    /// Replaces the files with the ones written, then removes the ones to remove
+    pub(crate) fn commit(self) -> io::Result<()> {
+        let journal = &self.journal;
+        if journal.written.is_empty() && journal.removed.is_empty() {
+            return Ok(());
+        }
+        let journal_path = self.root.join(JOURNAL);
+        write(&journal_path, serde_json::to_vec(journal)?)?;
+        simulate_interrupted_commit()?;
+        journal.apply()?;
+        fs::remove_file(journal_path)
+    }
+
+    /// Removes the files written, leaving the previous ones, e.g. after one of the writes failed
+    pub(crate) fn discard(self) {
+        for path in &self.journal.written {
+            let _ = fs::remove_file(temp_path(path));
+        }
+    }
+}
+
+impl Journal {
+    fn apply(&self) -> io::Result<()> {
+        for path in &self.written {
+            // the replacement got done before the crash, if the temporary file is gone
+            if temp_path(path).exists() {
+                replace(path)?;
+            }
+        }
+        for path in &self.removed {
+            let removed = match path.is_dir() {
+                true => fs::remove_dir_all(path),
+                false => fs::remove_file(path),
+            };
+            match removed {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Completes the group whose commit got interrupted, if any, then looks for the temporary files
+/// left by interrupted writes under `dir` and removes them. The files they were meant to replace
+/// still hold their previous contents. Returns the paths of the files whose write got
+/// interrupted.
+pub fn recover<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let journal_path = dir.as_ref().join(JOURNAL);
+    if journal_path.exists() {
+        let journal: Journal = serde_json::from_slice(&fs::read(&journal_path)?)?;
+        warn!(
+            "Completing the interrupted save of {} files",
+            journal.written.len()
+        );
+        journal.apply()?;
+        fs::remove_file(&journal_path)?;
+    }
+    remove_temp_files(dir.as_ref())
+}
+
+fn remove_temp_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut interrupted = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            interrupted.extend(remove_temp_files(&path)?);
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(original) = name.strip_suffix(TEMP_SUFFIX) {
+            let original = path.with_file_name(original);
+            warn!(
+                "The last write of {:?} got interrupted, keeping its previous contents",
+                original
+            );
+            fs::remove_file(&path)?;
+            interrupted.push(original);
+        }
+    }
+    Ok(interrupted)
+}
+
+/// Name for a copy of the file kept in another directory: the name of its directory, then its
+/// own, as the metadata and the items files of a collection have the same name
+pub(crate) fn qualified_name(path: &Path) -> String {
+    let name = |p: Option<&Path>| {
+        p.and_then(|p| p.file_name())
+            .map_or(String::new(), |n| n.to_string_lossy().to_string())
+    };
+    format!("{}.{}", name(path.parent()), name(Some(path)))
+}
@@ -0,0 +1,88 @@
+//! The Secret Service collections are flat, while users coming from pass or KWallet expect a
+//! hierarchy. Items get organized into folders through their `tks:path` attribute, e.g.
+//! `email/work`; items without it sit at the root. Each collection keeps an index of its folders,
+//! rebuilt upon loading and whenever it gets saved.
+
+use crate::storage::collection::{Collection, Item};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// The item attribute holding the folder the item belongs to
+pub const PATH_ATTRIBUTE: &str = "tks:path";
+
+/// Folders of a collection and the items directly in them, the root folder being ""
+pub type FolderIndex = BTreeMap<String, Vec<Uuid>>;
+
+/// Drops the leading, trailing and repeated slashes, so `/email//work/` is `email/work`; most paths
+/// already are, and get borrowed
+pub fn normalize(path: &str) -> Cow<'_, str> {
+    if !path.starts_with('/') && !path.ends_with('/') && !path.contains("//") {
+        return Cow::Borrowed(path);
+    }
+    Cow::Owned(
+        path.split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// The folder holding the given folder, `None` for the root folder
+pub fn parent(folder: &str) -> Option<&str> {
+    match folder {
+        "" => None,
+        folder => Some(folder.rsplit_once('/').map_or("", |(parent, _)| parent)),
+    }
+}
+
+impl Item {
+    /// The normalized folder of the item
+    pub fn folder(&self) -> Cow<'_, str> {
+        self.attribute(PATH_ATTRIBUTE)
+            .map_or(Cow::Borrowed(""), normalize)
+    }
+}
+
+impl Collection {
+    pub(crate) fn index_folders(&mut self) {
+        let mut index = FolderIndex::from([(String::new(), Vec::new())]);
+        for item in self.items.iter() {
+            let folder = item.folder();
+            // intermediate folders show up even when they hold no item of their own
+            let mut ancestor = parent(&folder);
+            while let Some(a) = ancestor {
+                if !index.contains_key(a) {
+                    index.insert(a.to_string(), Vec::new());
+                }
+                ancestor = parent(a);
+            }
+            match index.get_mut(folder.as_ref()) {
+                Some(uuids) => uuids.push(item.id.uuid),
+                None => {
+                    index.insert(folder.into_owned(), vec![item.id.uuid]);
+                }
+            }
+        }
+        self.folders = index;
+    }
+
+    /// All the folders of the collection, the root one included
+    pub fn folders(&self) -> impl Iterator<Item = &String> {
+        self.folders.keys()
+    }
+
+    /// The direct subfolders of a folder, and the items in it
+    pub fn list_folder(&self, folder: &str) -> Option<(Vec<String>, Vec<&Item>)> {
+        let folder = normalize(folder);
+        let uuids = self.folders.get(folder.as_ref())?;
+        let subfolders = self
+            .folders
+            .keys()
+            .filter(|f| parent(f) == Some(folder.as_ref()))
+            .cloned()
+            .collect();
+        let items = self.items.iter().filter(|i| uuids.contains(&i.id.uuid)).collect();
+        Some((subfolders, items))
+    }
+}
@@ -0,0 +1,305 @@
+//! Integrity check of the storage files, see `tks-cli service fsck`. The metadata of each
+//! collection should parse, and each of its items should have its secret in the items file, or in
+//! its item file, passing the AEAD tag check; otherwise the damage only shows up once unlocking
+//! the collection fails. The secrets of the locked collections can't be checked.
+//!
+//! Repairing moves the damaged files to the `quarantine` directory of the backend, then drops the
+//! items having lost their secret, writing their metadata next to the quarantined files. The
+//! collections keep their metadata, and their unlocked items their secret, in memory: saving the
+//! collection writes them again. The secrets having no item get dropped by that save too.
+
+use crate::storage::collection::{Collection, Item, ItemId};
+use crate::storage::file_ops;
+use crate::storage::secure_buffer::SecureBuffer;
+use crate::storage::{CollectionSecrets, Storage};
+use crate::tks_error::TksError;
+use log::{info, trace, warn};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Directory of the backend receiving the damaged files
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The metadata file of a collection doesn't parse
+    Metadata,
+    /// A file holding secrets fails to decrypt, or to parse once decrypted
+    Secrets,
+    /// An item has no secret stored
+    MissingSecret,
+    /// A secret is stored for no item
+    OrphanSecret,
+    /// The collection is locked, its secrets didn't get checked
+    Skipped,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Problem::Metadata => "metadata",
+            Problem::Secrets => "secrets",
+            Problem::MissingSecret => "missing-secret",
+            Problem::OrphanSecret => "orphan-secret",
+            Problem::Skipped => "skipped",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub problem: Problem,
+    /// The file, collection or item concerned
+    pub subject: String,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+impl Finding {
+    fn new(problem: Problem, subject: impl ToString, detail: impl ToString) -> Finding {
+        Finding {
+            problem,
+            subject: subject.to_string(),
+            detail: detail.to_string(),
+            repaired: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FsckReport {
+    pub findings: Vec<Finding>,
+    /// The items dropped by the repair, as their secret got lost
+    pub removed: Vec<ItemId>,
+}
+
+/// What a backend found in the files holding the secrets of a collection
+#[derive(Default)]
+pub(crate) struct StoredSecrets {
+    /// The items whose secret got decrypted
+    pub found: HashSet<Uuid>,
+    /// The files failing to decrypt, with the error
+    pub damaged: Vec<(PathBuf, String)>,
+}
+
+impl StoredSecrets {
+    /// Notes the secrets of a decrypted items file, or the file as damaged
+    pub fn add_items_file(&mut self, path: &Path, decrypted: Result<Vec<u8>, TksError>) {
+        let uuids = decrypted.and_then(|data| {
+            let data = SecureBuffer::from(data);
+            if data.is_empty() {
+                return Ok(Vec::new());
+            }
+            let secrets: CollectionSecrets = serde_json::from_slice(&data)?;
+            Ok(secrets.items.iter().map(|s| s.uuid).collect())
+        });
+        match uuids {
+            Ok(uuids) => self.found.extend(uuids),
+            Err(e) => self.damaged.push((path.to_path_buf(), e.to_string())),
+        }
+    }
+}
+
+impl Storage {
+    /// Checks the files of all the collections, `repair` quarantining the damaged ones
+    pub fn fsck(&mut self, repair: bool) -> Result<FsckReport, TksError> {
+        trace!("fsck (repair: {})", repair);
+        let mut report = FsckReport::default();
+        for mount in 0..self.mounts.len() {
+            self.check_metadata(mount, repair, &mut report)?;
+        }
+        let uuids: Vec<Uuid> = self
+            .collections
+            .iter()
+            .map(|c| c.uuid)
+            .filter(|uuid| !self.is_in_memory(uuid))
+            .collect();
+        for uuid in uuids {
+            self.check_collection(&uuid, repair, &mut report)?;
+        }
+        info!(
+            "Storage check found {} problem(s), dropped {} item(s)",
+            report.findings.len(),
+            report.removed.len()
+        );
+        Ok(report)
+    }
+
+    /// The collections got loaded when the service started, but their files may have changed
+    /// since
+    fn check_metadata(
+        &mut self,
+        mount: usize,
+        repair: bool,
+        report: &mut FsckReport,
+    ) -> Result<(), TksError> {
+        for path in self.mounts[mount].backend.get_metadata_paths()? {
+            let Err(e) = Storage::load_collection(&path) else {
+                continue;
+            };
+            let mut finding = Finding::new(Problem::Metadata, path.display(), e);
+            if repair && !self.mounts[mount].read_only {
+                finding.repaired = self.quarantine(mount, &path)?;
+                let loaded = self.collections.iter().find(|c| c.path == path);
+                if let Some(uuid) = loaded.map(|c| c.uuid).filter(|_| finding.repaired) {
+                    self.save_collection(&uuid, false)?;
+                }
+            }
+            report.findings.push(finding);
+        }
+        Ok(())
+    }
+
+    fn check_collection(
+        &mut self,
+        uuid: &Uuid,
+        repair: bool,
+        report: &mut FsckReport,
+    ) -> Result<(), TksError> {
+        // the pending changes should be on disk before comparing with it
+        self.flush_collection(uuid)?;
+        let collection = self
+            .collections
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        if collection.locked {
+            report.findings.push(Finding::new(
+                Problem::Skipped,
+                &collection.name,
+                "locked, unlock it to check its secrets",
+            ));
+            return Ok(());
+        }
+        let mount = collection.mount;
+        let aad = Storage::collection_aad(collection);
+        let stored = self.mounts[mount].backend.check_secrets(collection, &aad)?;
+
+        let mut findings: Vec<Finding> = stored
+            .damaged
+            .iter()
+            .map(|(path, e)| Finding::new(Problem::Secrets, path.display(), e))
+            .collect();
+        let mut lost = Vec::new();
+        for item in collection
+            .stored_items()
+            .filter(|i| !stored.found.contains(&i.id.uuid))
+        {
+            let detail = match item.data {
+                Some(_) => format!("'{}' is still unlocked, saving writes it again", item.label),
+                None => {
+                    lost.push(item.id.uuid);
+                    format!("'{}' lost its secret", item.label)
+                }
+            };
+            findings.push(Finding::new(Problem::MissingSecret, item.id.uuid, detail));
+        }
+        for orphan in stored
+            .found
+            .iter()
+            .filter(|uuid| !collection.stored_items().any(|i| i.id.uuid == **uuid))
+        {
+            findings.push(Finding::new(
+                Problem::OrphanSecret,
+                orphan,
+                format!("stored in collection '{}' for no item", collection.name),
+            ));
+        }
+
+        if repair && !findings.is_empty() && self.can_quarantine(uuid) {
+            for (path, _) in &stored.damaged {
+                self.quarantine(mount, path)?;
+            }
+            report.removed.extend(self.drop_lost_items(uuid, &lost)?);
+            findings.iter_mut().for_each(|f| f.repaired = true);
+        }
+        report.findings.extend(findings);
+        Ok(())
+    }
+
+    fn can_quarantine(&self, uuid: &Uuid) -> bool {
+        let Ok(mount) = self.mount_of(uuid) else {
+            return false;
+        };
+        if self.check_writable(uuid).is_err() || self.mounts[mount].backend.root_path().is_none() {
+            warn!(
+                "Cannot repair collection '{}', its backend can't quarantine files",
+                uuid
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Removes the items from the collection, keeping their metadata in the quarantine
+    /// directory, then saves the collection
+    fn drop_lost_items(&mut self, uuid: &Uuid, lost: &[Uuid]) -> Result<Vec<ItemId>, TksError> {
+        let mount = self.mount_of(uuid)?;
+        let collection = self
+            .collections
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        let items: Vec<&Item> = collection
+            .stored_items()
+            .filter(|i| lost.contains(&i.id.uuid))
+            .collect();
+        let removed: Vec<ItemId> = items.iter().map(|i| i.id.clone()).collect();
+        if !items.is_empty() {
+            let dir = self.quarantine_dir(mount)?;
+            let path = dir.join(format!("{}.items.{}", collection.name, timestamp()));
+            fs::write(&path, serde_json::to_vec_pretty(&items)?)?;
+            info!(
+                "Dropping {} item(s), their metadata went to {:?}",
+                items.len(),
+                path
+            );
+        }
+        // saving writes the secrets still in memory again, and only those of the known items
+        self.modify_collection(uuid, |c: &mut Collection| {
+            c.items.retain(|i| !lost.contains(&i.id.uuid));
+            c.trash.retain(|t| !lost.contains(&t.item.id.uuid));
+            Ok(())
+        })?;
+        self.flush_collection(uuid)?;
+        Ok(removed)
+    }
+
+    fn quarantine_dir(&self, mount: usize) -> Result<PathBuf, TksError> {
+        let dir = self.mounts[mount]
+            .backend
+            .root_path()
+            .ok_or(TksError::NotSupported("quarantining files"))?
+            .join(QUARANTINE_DIR);
+        fs::DirBuilder::new().recursive(true).create(&dir)?;
+        Ok(dir)
+    }
+
+    /// Moves a damaged file to the quarantine directory, returning false when the backend has
+    /// none
+    fn quarantine(&self, mount: usize, path: &Path) -> Result<bool, TksError> {
+        if self.mounts[mount].backend.root_path().is_none() {
+            return Ok(false);
+        }
+        let target = self.quarantine_dir(mount)?.join(format!(
+            "{}.{}",
+            file_ops::qualified_name(path),
+            timestamp()
+        ));
+        fs::rename(path, &target)?;
+        info!("Moved the damaged file {:?} to {:?}", path, target);
+        Ok(true)
+    }
+}
+
+/// Suffix keeping the quarantined files of successive repairs apart
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,70 @@
+//! Each collection keeps its latest item operations, so the users can find out what an application
+//! changed recently. The entries get saved in the collection metadata, next to the item labels;
+//! like these, they never hold secrets nor attributes. Only the last [HISTORY_LENGTH] entries are
+//! kept.
+
+use crate::audit;
+use crate::storage::collection::Collection;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// How many entries each collection keeps
+pub const HISTORY_LENGTH: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryOperation {
+    Created,
+    Modified,
+    Deleted,
+    /// Moved back from the trash, see [crate::storage::trash]
+    Restored,
+}
+
+impl fmt::Display for HistoryOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HistoryOperation::Created => "created",
+            HistoryOperation::Modified => "modified",
+            HistoryOperation::Deleted => "deleted",
+            HistoryOperation::Restored => "restored",
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub operation: HistoryOperation,
+    pub item: Uuid,
+    /// Label of the item once the operation got done
+    pub label: String,
+    /// Executable path of the client, when the operation came from a client which could be
+    /// looked up
+    #[serde(default)]
+    pub client: Option<String>,
+}
+
+impl Collection {
+    /// Notes an operation on one of the items, forgetting the oldest entry when the history is
+    /// full
+    pub(crate) fn record_history(&mut self, operation: HistoryOperation, item: &Uuid, label: &str) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if self.history.len() >= HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            time,
+            operation,
+            item: *item,
+            label: label.to_string(),
+            client: audit::caller_exe(),
+        });
+    }
+}
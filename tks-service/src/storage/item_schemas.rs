@@ -0,0 +1,209 @@
+//! Schemas tell which attributes the items of a kind have, like libsecret's SecretSchema: the
+//! items naming a schema in their `xdg:schema` attribute get their other attributes checked upon
+//! CreateItem and SetAttributes, so a typo in an attribute name, or a port which is not a number,
+//! gets refused instead of making the item unfindable. The items naming no schema, or an unknown
+//! one, keep any attributes. The `xdg:` and `tks:` attributes, set by libsecret and the service,
+//! belong to all the schemas. Values of integer attributes may be searched by comparison, see
+//! [crate::storage::search::AttributeMatch].
+//!
+//! Besides the built-in schemas, the configured ones (`[[schemas]]` in the settings) and the ones
+//! the applications register through `io.linux_tks.Service1.RegisterSchema` apply, the latter
+//! until the service stops. A schema can't be redefined under the name of another one.
+
+use crate::settings::{AttributeType, ItemSchema};
+use crate::tks_error::TksError;
+use lazy_static::lazy_static;
+use log::debug;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// The attribute naming the schema of the item
+pub const SCHEMA_ATTRIBUTE: &str = "xdg:schema";
+
+/// The prefixes of the attributes the schemas don't need to list
+const META_PREFIXES: &[&str] = &["xdg:", "tks:"];
+
+type BuiltinSchema = (&'static str, &'static [(&'static str, AttributeType)]);
+
+/// libsecret's network password and note schemas, the ones of Chromium's libsecret password
+/// store, and the one `tks-cli import browser` gives the Firefox logins
+const BUILTIN: &[BuiltinSchema] = &[
+    (
+        "org.gnome.keyring.NetworkPassword",
+        &[
+            ("user", AttributeType::String),
+            ("domain", AttributeType::String),
+            ("object", AttributeType::String),
+            ("protocol", AttributeType::String),
+            ("port", AttributeType::Integer),
+            ("server", AttributeType::String),
+            ("authtype", AttributeType::String),
+        ],
+    ),
+    ("org.gnome.keyring.Note", &[]),
+    (
+        "chrome_libsecret_password_schema",
+        &[
+            ("origin_url", AttributeType::String),
+            ("action_url", AttributeType::String),
+            ("username_element", AttributeType::String),
+            ("username_value", AttributeType::String),
+            ("password_element", AttributeType::String),
+            ("submit_element", AttributeType::String),
+            ("signon_realm", AttributeType::String),
+            ("preferred", AttributeType::Integer),
+            ("date_created", AttributeType::String),
+            ("blacklisted_by_user", AttributeType::Integer),
+            ("scheme", AttributeType::Integer),
+            ("type", AttributeType::Integer),
+            ("times_used", AttributeType::Integer),
+            ("date_synced", AttributeType::String),
+            ("display_name", AttributeType::String),
+            ("avatar_url", AttributeType::String),
+            ("federation_url", AttributeType::String),
+            ("should_skip_zero_click", AttributeType::Integer),
+            ("generation_upload_status", AttributeType::Integer),
+            ("form_data", AttributeType::String),
+            ("application", AttributeType::String),
+        ],
+    ),
+    (
+        "chrome_libsecret_os_crypt_password",
+        &[("application", AttributeType::String)],
+    ),
+    (
+        "chrome_libsecret_os_crypt_password_v2",
+        &[("application", AttributeType::String)],
+    ),
+    (
+        "io.linux_tks.FirefoxLogin",
+        &[
+            ("url", AttributeType::String),
+            ("username", AttributeType::String),
+            ("username-field", AttributeType::String),
+            ("password-field", AttributeType::String),
+            ("form-submit-url", AttributeType::String),
+            ("http-realm", AttributeType::String),
+        ],
+    ),
+];
+
+lazy_static! {
+    /// The schemas registered through DBus, by name
+    static ref REGISTERED: Mutex<HashMap<String, ItemSchema>> = Mutex::new(HashMap::new());
+}
+
+impl AttributeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeType::String => "string",
+            AttributeType::Integer => "integer",
+            AttributeType::Boolean => "boolean",
+        }
+    }
+}
+
+impl FromStr for AttributeType {
+    type Err = TksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(AttributeType::String),
+            "integer" => Ok(AttributeType::Integer),
+            "boolean" => Ok(AttributeType::Boolean),
+            _ => Err(TksError::ParameterError),
+        }
+    }
+}
+
+pub fn builtin() -> Vec<ItemSchema> {
+    BUILTIN
+        .iter()
+        .map(|(name, attributes)| ItemSchema {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(a, t)| (a.to_string(), *t))
+                .collect(),
+        })
+        .collect()
+}
+
+/// The schemas applying besides the configured ones: the built-in and the registered ones
+pub fn all(configured: &[ItemSchema]) -> Vec<ItemSchema> {
+    let mut schemas = builtin();
+    schemas.extend(configured.iter().cloned());
+    schemas.extend(REGISTERED.lock().unwrap().values().cloned());
+    schemas
+}
+
+pub fn find(configured: &[ItemSchema], name: &str) -> Option<ItemSchema> {
+    all(configured).into_iter().find(|s| s.name == name)
+}
+
+/// Registers the schema; registering it again with the same attributes does nothing, as the
+/// applications may do upon each start
+pub fn register(configured: &[ItemSchema], schema: ItemSchema) -> Result<(), TksError> {
+    if schema.name.is_empty() || schema.attributes.keys().any(|a| a.is_empty() || is_meta(a)) {
+        return Err(TksError::ParameterError);
+    }
+    let fixed = builtin().into_iter().chain(configured.iter().cloned());
+    if let Some(existing) = fixed.into_iter().find(|s| s.name == schema.name) {
+        return match existing == schema {
+            true => Ok(()),
+            false => Err(TksError::Duplicate),
+        };
+    }
+    debug!("Registering schema {}", schema.name);
+    REGISTERED
+        .lock()
+        .unwrap()
+        .insert(schema.name.clone(), schema);
+    Ok(())
+}
+
+/// Whether the attribute is one of the `xdg:` or `tks:` ones
+pub fn is_meta(attribute: &str) -> bool {
+    META_PREFIXES.iter().any(|p| attribute.starts_with(p))
+}
+
+/// Checks the attributes against the schema named by their `xdg:schema`, if known
+pub fn check(
+    configured: &[ItemSchema],
+    attributes: &HashMap<String, String>,
+) -> Result<(), TksError> {
+    let Some(name) = attributes.get(SCHEMA_ATTRIBUTE) else {
+        return Ok(());
+    };
+    match find(configured, name) {
+        Some(schema) => validate(&schema, attributes),
+        None => Ok(()),
+    }
+}
+
+pub fn validate(schema: &ItemSchema, attributes: &HashMap<String, String>) -> Result<(), TksError> {
+    for (attribute, value) in attributes.iter().filter(|(a, _)| !is_meta(a)) {
+        let Some(kind) = schema.attributes.get(attribute) else {
+            return Err(TksError::SchemaViolation(format!(
+                "schema {} has no attribute '{}'",
+                schema.name, attribute
+            )));
+        };
+        let valid = match kind {
+            AttributeType::String => true,
+            AttributeType::Integer => value.parse::<i64>().is_ok(),
+            AttributeType::Boolean => value == "true" || value == "false",
+        };
+        if !valid {
+            return Err(TksError::SchemaViolation(format!(
+                "attribute '{}' of schema {} should be {}, not '{}'",
+                attribute,
+                schema.name,
+                kind.as_str(),
+                value
+            )));
+        }
+    }
+    Ok(())
+}
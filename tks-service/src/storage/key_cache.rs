@@ -0,0 +1,61 @@
+//! The keys derived from the passwords stay cached for `prompt.cache_ttl` seconds, the way
+//! gpg-agent caches the passphrases: locking the collections drops their secrets, but unlocking
+//! them again within that time reuses the key the backend holds, without prompting. The time
+//! counts from the unlock which asked the password. `tks-cli service forget`, through
+//! `io.linux_tks.Service1.ForgetKeys`, locks all the collections and drops the keys the backends
+//! hold, zeroing them, so that the next unlock asks again.
+
+use crate::storage::collection::ItemId;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::debug;
+use std::time::Duration;
+use uuid::Uuid;
+
+impl Storage {
+    /// Unlocks the collection, with the collections sharing its key, when that key is cached;
+    /// returns whether it did
+    pub fn unlock_with_cached_key(
+        &mut self,
+        coll_uuid: &Uuid,
+        ttl: Duration,
+    ) -> Result<bool, TksError> {
+        if !self.has_cached_key(coll_uuid, ttl)? {
+            return Ok(false);
+        }
+        debug!("Unlocking collection {} with its cached key", coll_uuid);
+        self.unlock_backend_collections(coll_uuid)?;
+        Ok(true)
+    }
+
+    /// Unlocks the item alone when the key of its collection is cached; returns whether it did
+    pub fn unlock_item_with_cached_key(
+        &mut self,
+        item_id: &ItemId,
+        ttl: Duration,
+    ) -> Result<bool, TksError> {
+        if !self.has_cached_key(&item_id.collection_uuid, ttl)? {
+            return Ok(false);
+        }
+        debug!("Unlocking item {} with its cached key", item_id.uuid);
+        self.unlock_item(item_id)?;
+        Ok(true)
+    }
+
+    /// Locks all the collections, as their secrets couldn't be saved anymore, then drops the
+    /// keys, the next unlocks asking the passwords again
+    pub fn forget_keys(&mut self) -> Result<(), TksError> {
+        debug!("Forgetting the cached keys");
+        self.lock_all()?;
+        self.mounts.iter_mut().for_each(|m| m.backend.forget_keys());
+        Ok(())
+    }
+
+    fn has_cached_key(&self, coll_uuid: &Uuid, ttl: Duration) -> Result<bool, TksError> {
+        if ttl.is_zero() {
+            return Ok(false);
+        }
+        let mount = self.mount_of(coll_uuid)?;
+        Ok(self.mounts[mount].backend.has_cached_key(coll_uuid, ttl))
+    }
+}
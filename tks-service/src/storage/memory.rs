@@ -0,0 +1,150 @@
+//! The `session` collection of the Secret Service spec, at
+//! `/org/freedesktop/secrets/collection/session`: its items only live in memory, never get written
+//! anywhere, and vanish when the service stops. Applications such as NetworkManager keep the
+//! secrets there which shouldn't outlive the user's session.
+//!
+//! The collection comes from a backend of its own, mounted after the configured ones, which saves
+//! nothing. Having no password, the collection never gets locked, as its secrets would then be
+//! lost; it doesn't get backed up nor migrated either.
+
+use crate::settings::StorageMount;
+use crate::storage::capabilities::Capabilities;
+use crate::storage::collection::Collection;
+use crate::storage::{Mount, SecretsHandler, Storage, StorageBackend, StorageBackendType};
+use crate::tks_dbus::prompt_impl::{PassphraseActionParam, PromptAction};
+use crate::tks_error::TksError;
+use secrecy::SecretString;
+use std::path::PathBuf;
+use uuid::{uuid, Uuid};
+
+/// Alias of the session collection
+pub const SESSION_ALIAS: &str = "session";
+/// Uuid of the session collection, which is the same each time the service starts
+pub const SESSION_COLLECTION_UUID: Uuid = uuid!("5e55104e-7c5a-4a11-8e1d-5e55104e0000");
+/// Object path of the session collection, instead of the one made of its uuid
+pub const SESSION_COLLECTION_PATH: &str = "/org/freedesktop/secrets/collection/session";
+
+pub(crate) struct MemoryBackend;
+
+/// There's no key to derive, so unlocking with a password does nothing
+struct NoSecretsHandler;
+
+impl SecretsHandler for NoSecretsHandler {
+    fn derive_key_from_password(&mut self, _s: SecretString) -> Result<(), TksError> {
+        Ok(())
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get_kind(&self) -> StorageBackendType {
+        StorageBackendType::Memory
+    }
+
+    /// Only the session collection lives in memory
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            create_collection: false,
+            multiple_collections: false,
+            ..Capabilities::full(self.get_kind().name())
+        }
+    }
+
+    fn get_metadata_paths(&self) -> Result<Vec<PathBuf>, TksError> {
+        Ok(Vec::new())
+    }
+
+    fn new_metadata_path(&self, _name: &str) -> Result<(PathBuf, PathBuf), TksError> {
+        Err(TksError::NotSupported(
+            "collections kept in memory, besides the session one",
+        ))
+    }
+
+    /// Never used as a file, only to authenticate the secrets of the collection
+    fn collection_items_path(&self, name: &str) -> Result<PathBuf, TksError> {
+        Ok(PathBuf::from(name))
+    }
+
+    fn get_secrets_handler(&mut self) -> Result<Box<dyn SecretsHandler + '_>, TksError> {
+        Ok(Box::new(NoSecretsHandler))
+    }
+
+    fn unlock_items(&self, _items_path: &PathBuf) -> Result<String, TksError> {
+        Ok(String::new())
+    }
+
+    fn create_unlock_action(
+        &mut self,
+        _coll_uuid: &Uuid,
+        _coll_name: &str,
+        _param: PassphraseActionParam,
+    ) -> Result<PromptAction, TksError> {
+        Err(TksError::NotSupported(
+            "unlocking the session collection, which is never locked",
+        ))
+    }
+
+    fn is_locked(&self) -> Result<bool, TksError> {
+        Ok(false)
+    }
+
+    fn save_collection_metadata(
+        &self,
+        _coll_path: &PathBuf,
+        _metadata: &String,
+    ) -> Result<(), TksError> {
+        Ok(())
+    }
+
+    fn save_collection_items(
+        &self,
+        _collection: &Collection,
+        _aad: &String,
+        _items: &String,
+    ) -> Result<(), TksError> {
+        Ok(())
+    }
+
+    /// All the secrets are in the collection, which never gets locked
+    fn load_collection_items(
+        &self,
+        _collection: &Collection,
+        _aad: &String,
+    ) -> Result<Vec<u8>, TksError> {
+        Ok(Vec::new())
+    }
+}
+
+impl Mount {
+    /// The backend of the session collection
+    pub(crate) fn memory() -> Mount {
+        Mount {
+            name: StorageMount::SESSION.to_string(),
+            backend: Box::new(MemoryBackend),
+            read_only: false,
+            synced: false,
+        }
+    }
+}
+
+impl Storage {
+    /// The empty session collection, saved by the mount having the given index
+    pub(crate) fn session_collection(mount: usize) -> Result<Collection, TksError> {
+        let path = PathBuf::from(SESSION_ALIAS);
+        let mut collection = Collection::new(SESSION_ALIAS, &path, &path)?;
+        collection.uuid = SESSION_COLLECTION_UUID;
+        collection.aliases = Some(vec![SESSION_ALIAS.to_string()]);
+        collection.locked = false;
+        collection.mount = mount;
+        Ok(collection)
+    }
+
+    /// Whether the collection only lives in memory, see [crate::storage::memory]
+    pub fn is_in_memory(&self, uuid: &Uuid) -> bool {
+        self.mount_of(uuid).is_ok_and(|mount| {
+            matches!(
+                self.mounts[mount].backend.get_kind(),
+                StorageBackendType::Memory
+            )
+        })
+    }
+}
@@ -0,0 +1,257 @@
+//! Moving items from a collection into another one, e.g. after importing secrets into a temporary
+//! collection. Both collections get saved; when saving any of them fails, the items are moved
+//! back so that no item gets lost or duplicated.
+
+use crate::storage::acl::Acl;
+use crate::storage::collection::{Collection, Item, ItemId};
+use crate::storage::history::{HistoryEntry, HistoryOperation};
+use crate::storage::trash::TrashedItem;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{error, trace};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// What to do with a source item when the destination already holds an item having the same
+/// attributes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeConflict {
+    /// Leave the source item in the source collection
+    Skip,
+    /// Delete the destination item and move the source item in its place
+    Replace,
+    /// Move the source item anyway, the destination then holds both
+    KeepBoth,
+}
+
+impl FromStr for MergeConflict {
+    type Err = TksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(MergeConflict::Skip),
+            "replace" => Ok(MergeConflict::Replace),
+            "keep-both" => Ok(MergeConflict::KeepBoth),
+            _ => Err(TksError::ParameterError),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MergeOutcome {
+    /// The moved items, with their ids in the source and in the destination collections
+    pub moved: Vec<(ItemId, ItemId)>,
+    /// The destination items deleted to make room for a source item
+    pub replaced: Vec<ItemId>,
+    /// The source items left in the source collection
+    pub skipped: Vec<ItemId>,
+}
+
+/// The part of a collection that moving items, restoring a backup, or the closures given to
+/// [Storage::modify_collection] and [Storage::modify_item], are allowed to change
+pub(crate) struct ItemsSnapshot {
+    uuid: Uuid,
+    label: Option<String>,
+    properties: HashMap<String, String>,
+    items: Vec<Item>,
+    history: VecDeque<HistoryEntry>,
+    acl: Option<Acl>,
+    trash: Vec<TrashedItem>,
+    modified: u64,
+    sequence: u64,
+}
+
+impl ItemsSnapshot {
+    pub(crate) fn new(collection: &Collection) -> ItemsSnapshot {
+        ItemsSnapshot {
+            uuid: collection.uuid,
+            label: collection.label.clone(),
+            properties: collection.properties.clone(),
+            items: collection.items.clone(),
+            history: collection.history.clone(),
+            acl: collection.acl.clone(),
+            trash: collection.trash.clone(),
+            modified: collection.modified,
+            sequence: collection.sequence,
+        }
+    }
+    pub(crate) fn restore(&self, collection: &mut Collection) {
+        collection.label = self.label.clone();
+        collection.properties = self.properties.clone();
+        collection.items = self.items.clone();
+        collection.history = self.history.clone();
+        collection.acl = self.acl.clone();
+        collection.trash = self.trash.clone();
+        collection.modified = self.modified;
+        collection.sequence = self.sequence;
+        collection.index_folders();
+        collection.index_attributes();
+    }
+}
+
+impl Storage {
+    /// Moves all the items of the `source` collection into the `destination` collection. Both
+    /// collections should be unlocked. Items are in conflict when they have the same attributes,
+    /// i.e. when SearchItems would not tell them apart.
+    pub fn merge_collections(
+        &mut self,
+        source: &Uuid,
+        destination: &Uuid,
+        on_conflict: MergeConflict,
+    ) -> Result<MergeOutcome, TksError> {
+        trace!("merge_collections {} into {}", source, destination);
+        if source == destination {
+            return Err(TksError::ParameterError);
+        }
+        let mut snapshots = Vec::new();
+        for uuid in [source, destination] {
+            snapshots.push(self.with_collection(uuid, |c| {
+                if c.locked || c.items.iter().any(|i| i.data.is_none()) {
+                    return Err(TksError::PermissionDenied);
+                }
+                Ok(ItemsSnapshot::new(c))
+            })?);
+        }
+
+        let mut outcome = MergeOutcome::default();
+        let source_items = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == *source)
+            .map(|c| std::mem::take(&mut c.items))
+            .unwrap_or_default();
+        let mut kept = Vec::new();
+        let mut moved = Vec::new();
+        let dst = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == *destination)
+            .ok_or(TksError::NotFound(None))?;
+        for mut item in source_items {
+            let conflict = dst.items.iter().position(|i| i.attributes == item.attributes);
+            match (conflict, on_conflict) {
+                (Some(_), MergeConflict::Skip) => {
+                    outcome.skipped.push(item.id.clone());
+                    kept.push(item);
+                    continue;
+                }
+                (Some(index), MergeConflict::Replace) => {
+                    let replaced = dst.items.swap_remove(index);
+                    dst.record_history(
+                        HistoryOperation::Deleted,
+                        &replaced.id.uuid,
+                        &replaced.label,
+                    );
+                    outcome.replaced.push(replaced.id);
+                }
+                _ => {}
+            }
+            let source_id = item.id.clone();
+            item.id.collection_uuid = *destination;
+            dst.record_history(HistoryOperation::Created, &item.id.uuid, &item.label);
+            moved.push((item.id.uuid, item.label.clone()));
+            outcome.moved.push((source_id, item.id.clone()));
+            dst.items.push(item);
+        }
+        if let Some(src) = self.collections.iter_mut().find(|c| c.uuid == *source) {
+            src.items = kept;
+            for (uuid, label) in &moved {
+                src.record_history(HistoryOperation::Deleted, uuid, label);
+            }
+        }
+
+        // the destination goes first, so a failure can't leave the moved items nowhere on disk
+        let mut saved = Vec::new();
+        for uuid in [destination, source] {
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot save collection '{}', rolling back the merge: {}", uuid, e);
+                self.rollback_items(&snapshots, &saved);
+                return Err(e);
+            }
+            saved.push(*uuid);
+        }
+        Ok(outcome)
+    }
+
+    /// Moves an item into the `destination` collection, keeping its uuid. When `copy` is set, the
+    /// item stays in its collection and the destination gets a copy having a new uuid. Both
+    /// collections and the item should be unlocked.
+    pub fn move_item(
+        &mut self,
+        item_id: &ItemId,
+        destination: &Uuid,
+        copy: bool,
+    ) -> Result<ItemId, TksError> {
+        trace!("move_item {} into {} (copy: {})", item_id.uuid, destination, copy);
+        if item_id.collection_uuid == *destination {
+            return Err(TksError::ParameterError);
+        }
+        let mut snapshots = Vec::new();
+        for uuid in [&item_id.collection_uuid, destination] {
+            snapshots.push(self.with_collection(uuid, |c| {
+                if c.locked {
+                    return Err(TksError::PermissionDenied);
+                }
+                Ok(ItemsSnapshot::new(c))
+            })?);
+        }
+
+        let source = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == item_id.collection_uuid)
+            .ok_or(TksError::NotFound(None))?;
+        if source.get_item(&item_id.uuid)?.data.is_none() {
+            return Err(TksError::PermissionDenied);
+        }
+        let mut item = if copy {
+            let mut item = source.get_item(&item_id.uuid)?.clone();
+            item.id.uuid = Uuid::new_v4();
+            if let Some(data) = item.data.as_mut() {
+                data.uuid = item.id.uuid;
+            }
+            item
+        } else {
+            source.delete_item(&item_id.uuid)?
+        };
+        item.id.collection_uuid = *destination;
+        let new_id = item.id.clone();
+        let dst = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == *destination)
+            .ok_or(TksError::NotFound(None))?;
+        dst.record_history(HistoryOperation::Created, &item.id.uuid, &item.label);
+        dst.items.push(item);
+
+        let mut to_save = vec![*destination];
+        if !copy {
+            to_save.push(item_id.collection_uuid);
+        }
+        let mut saved = Vec::new();
+        for uuid in &to_save {
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot save collection '{}', rolling back the move: {}", uuid, e);
+                self.rollback_items(&snapshots, &saved);
+                return Err(e);
+            }
+            saved.push(*uuid);
+        }
+        Ok(new_id)
+    }
+
+    pub(crate) fn rollback_items(&mut self, snapshots: &[ItemsSnapshot], saved: &[Uuid]) {
+        for s in snapshots {
+            if let Some(c) = self.collections.iter_mut().find(|c| c.uuid == s.uuid) {
+                s.restore(c);
+            }
+        }
+        for uuid in saved {
+            // best effort, there's not much else we can do if this fails too
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot roll back collection '{}': {}", uuid, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Moving a whole collection to another of the mounted backends, e.g. from a shared store into the
+//! personal one. The target backend writes the collection with its own key, then the former
+//! backend deletes its files; the collection keeps its uuid, timestamps, items and attributes, so
+//! the clients don't notice.
+
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{debug, error, trace};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Where a collection gets saved
+#[derive(Debug, Clone)]
+struct Location {
+    mount: usize,
+    path: PathBuf,
+    items_path: PathBuf,
+}
+
+impl Storage {
+    /// Names of the backends, the `[storage]` one first
+    pub fn backend_names(&self) -> Vec<&str> {
+        self.mounts.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    /// Name of the backend saving the collection
+    pub fn backend_name(&self, uuid: &Uuid) -> Result<&str, TksError> {
+        Ok(&self.mounts[self.mount_of(uuid)?].name)
+    }
+
+    /// Moves the collection to the named backend. The collection should be unlocked, and so
+    /// should the target backend. When the target backend can't write the collection, it stays
+    /// where it was.
+    pub fn migrate_collection(&mut self, uuid: &Uuid, backend: &str) -> Result<(), TksError> {
+        trace!("migrate_collection {} to {}", uuid, backend);
+        let target = self
+            .mounts
+            .iter()
+            .position(|m| m.name == backend)
+            .ok_or_else(|| TksError::NotFound(Some(format!("Backend '{}' not found", backend))))?;
+        if self.is_in_memory(uuid) {
+            return Err(TksError::NotSupported("the session collection stays in memory"));
+        }
+        let (source, name) = self.with_collection(uuid, |c| {
+            if c.default {
+                return Err(TksError::NotSupported(
+                    "the default collection stays in the main backend",
+                ));
+            }
+            if c.locked || c.items.iter().any(|i| i.data.is_none()) {
+                return Err(TksError::PermissionDenied);
+            }
+            Ok((c.mount, c.name.clone()))
+        })?;
+        if source == target {
+            return Err(TksError::ParameterError);
+        }
+        // its files get deleted from the source backend
+        self.check_writable(uuid)?;
+        self.check_batch(uuid)?;
+        if self.mounts[target].read_only {
+            return Err(TksError::NotSupported("the backend is read-only"));
+        }
+        if self.collections.iter().any(|c| c.mount == target && c.name == name) {
+            return Err(TksError::Duplicate);
+        }
+
+        let (path, items_path) = self.mounts[target].backend.new_metadata_path(&name)?;
+        let migrated = Location {
+            mount: target,
+            path,
+            items_path,
+        };
+        let former = self.relocate(uuid, migrated.clone())?;
+        if let Err(e) = self.write_collection(uuid) {
+            error!("Cannot write collection '{}' to backend '{}': {}", uuid, backend, e);
+            // the target backend may have written some of the files
+            if let Err(e) = self.delete_files(uuid) {
+                debug!("Cannot clean the partially written collection up: {}", e);
+            }
+            self.relocate(uuid, former)?;
+            return Err(e);
+        }
+        self.relocate(uuid, former)?;
+        let deleted = self.delete_files(uuid);
+        self.relocate(uuid, migrated)?;
+        if let Err(e) = deleted {
+            error!("Collection '{}' got migrated, but its former files remain: {}", uuid, e);
+            return Err(e);
+        }
+        debug!("Migrated collection '{}' to backend '{}'", uuid, backend);
+        Ok(())
+    }
+
+    /// Makes the collection saved to another location, returning the former one
+    fn relocate(&mut self, uuid: &Uuid, location: Location) -> Result<Location, TksError> {
+        let synced = self.mounts[location.mount].synced;
+        let collection = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        let former = Location {
+            mount: collection.mount,
+            path: std::mem::replace(&mut collection.path, location.path),
+            items_path: std::mem::replace(&mut collection.items_path, location.items_path),
+        };
+        collection.mount = location.mount;
+        collection.synced = synced;
+        Ok(former)
+    }
+
+    /// Removes the files of the collection from its current location
+    fn delete_files(&mut self, uuid: &Uuid) -> Result<(), TksError> {
+        let collection = self
+            .collections
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        self.mounts[collection.mount]
+            .backend
+            .delete_collection_files(collection)
+    }
+}
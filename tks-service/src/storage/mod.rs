@@ -1,49 +1,136 @@
-use collection::{Collection, Item, ItemData};
+use collection::{Collection, Item, ItemData, ItemId};
 use dbus::arg::RefArg;
 #[cfg(feature = "fscrypt")]
 use fscrypt::FSCryptBackend;
 use lazy_static::lazy_static;
 use log::{error, info, trace};
+use secrecy::zeroize::Zeroize;
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 use uuid::Uuid;
 
-use crate::settings::SETTINGS;
+use crate::settings::{StorageMount, SETTINGS};
+use crate::storage::batch::BatchLease;
+use crate::storage::capabilities::Capabilities;
+use crate::storage::fsck::StoredSecrets;
+use crate::storage::history::HistoryOperation;
+use crate::storage::merge::ItemsSnapshot;
 use crate::storage::password_store::PasswordStoreBackend;
+use crate::storage::secure_buffer::SecureBuffer;
 use crate::storage::tks_gcm::TksGcmBackend;
-use crate::tks_dbus::prompt_impl::PromptAction;
+use crate::tks_dbus::prompt_impl::{PassphraseActionParam, PromptAction};
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::service_impl::ServiceImpl;
+use crate::tks_dbus::kwallet_impl;
 use crate::tks_error::TksError;
 
 pub(crate) mod collection;
+pub mod acl;
+pub mod aead_file;
+pub mod auto_lock;
+pub mod backup;
+pub mod batch;
+pub mod capabilities;
+pub mod checksums;
+pub mod disk_space;
+pub mod duplicates;
+pub mod expiry;
+pub mod file_ops;
+pub mod folders;
+pub mod fsck;
+pub mod history;
+pub mod item_schemas;
+pub mod key_cache;
+pub mod memory;
+pub mod oauth;
+pub mod permissions;
+pub mod schema;
+pub mod search;
+pub mod trash;
+pub mod secure_buffer;
+pub mod sync;
+pub mod versions;
 #[cfg(feature = "fscrypt")]
 mod fscrypt;
+pub mod merge;
+pub mod migrate;
 mod password_store;
+mod rekey;
 mod tks_gcm;
+mod transaction;
+mod write_back;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct CollectionSecrets {
     items: Vec<ItemData>,
 }
 
+impl CollectionSecrets {
+    /// Picks the secret of an item out of the serialized secrets of its collection
+    fn find_item(data: &[u8], item_uuid: &Uuid) -> Result<ItemData, TksError> {
+        if data.is_empty() {
+            return Err(TksError::ItemNotFound);
+        }
+        let collection_secrets: CollectionSecrets = serde_json::from_slice(data)?;
+        collection_secrets
+            .items
+            .into_iter()
+            .find(|s| s.uuid == *item_uuid)
+            .ok_or(TksError::ItemNotFound)
+    }
+}
+
 static DEFAULT_NAME: &'static str = "default";
 
+/// A storage backend, contributing its collections
+struct Mount {
+    /// see [crate::settings::StorageMount::name]
+    name: String,
+    backend: Box<dyn StorageBackend + Send + Sync>,
+    /// Refuses any change to its collections
+    read_only: bool,
+    /// see [crate::settings::StorageMount::sync]
+    synced: bool,
+}
+
 pub struct Storage {
-    backend: Box<dyn StorageBackend + Send>,
+    /// The `[storage]` backend, holding the new collections, then the `storage.mounts` ones
+    mounts: Vec<Mount>,
     pub collections: Vec<Collection>,
+    /// Collections having changes not yet written, see [write_back]; the flusher writes them while
+    /// only holding a read lock
+    dirty: Mutex<HashSet<Uuid>>,
+    /// Whether the changes are left to the flusher even without `flush_delay`, so that they get
+    /// encrypted and written outside the write lock of [STORAGE], see [write_back]
+    write_behind: bool,
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+    flush_delay: Duration,
+    /// Bytes to keep free on the storage filesystem, see [disk_space]
+    min_free_space: u64,
+    /// Collections leased to a client, see [batch]
+    batches: HashMap<Uuid, BatchLease>,
 }
 
 lazy_static! {
-    pub static ref STORAGE: Arc<Mutex<Storage>> = Arc::new(Mutex::new(Storage::new()));
+    // read-only operations, such as getting item properties or secrets, may run concurrently;
+    // only the operations changing the collections need exclusive access
+    pub static ref STORAGE: Arc<RwLock<Storage>> = Arc::new(RwLock::new(Storage::new()));
 }
 
+/// PBKDF2 iterations deriving the key of the tks_gcm backends from the password or key file
+pub const KDF_ITERATIONS: u32 = 1024;
+
 enum StorageBackendType {
     /// Use EXPERIMENTAL fscrypt to handle item encryption on disk
     /// https://github.com/google/fscrypt
@@ -51,6 +138,8 @@ enum StorageBackendType {
     FSCrypt,
     TksGcm,
     PasswordStore,
+    /// Keeps the session collection in memory, see [memory]
+    Memory,
 }
 
 trait SecretsHandler {
@@ -62,29 +151,168 @@ trait StorageBackend {
     fn new_metadata_path(&self, name: &str) -> Result<(PathBuf, PathBuf), TksError>;
     fn collection_items_path(&self, name: &str) -> Result<PathBuf, TksError>;
     fn get_secrets_handler(&mut self) -> Result<Box<dyn SecretsHandler + '_>, TksError>;
+    /// The secrets handler of the collection's key, the one of the backend unless the collection
+    /// has a key of its own
+    fn get_collection_secrets_handler(
+        &mut self,
+        _collection: &Uuid,
+    ) -> Result<Box<dyn SecretsHandler + '_>, TksError> {
+        self.get_secrets_handler()
+    }
+    /// Whether the collection has a key of its own, so that it gets unlocked on its own
+    fn has_own_key(&self, _collection: &Uuid) -> bool {
+        false
+    }
+    /// Gives the new collection a key of its own when the backend is configured to; its first
+    /// unlock then defines its password
+    fn create_collection_key(&mut self, _collection: &Uuid) -> Result<(), TksError> {
+        Ok(())
+    }
     fn unlock_items(&self, items_path: &PathBuf) -> Result<String, TksError>;
     fn create_unlock_action(
         &mut self,
         coll_uuid: &Uuid,
         coll_name: &str,
+        param: PassphraseActionParam,
     ) -> Result<PromptAction, TksError>;
     fn is_locked(&self) -> Result<bool, TksError>;
-    fn save_collection_metadata(
+    /// Unlocks the backend with the key file configured for the collection, instead of a password
+    fn unlock_with_keyfile(&mut self, _collection_name: &str) -> Result<(), TksError> {
+        Err(TksError::NotSupported("key files"))
+    }
+    /// Lets the configured key files unlock the backend, once the key is available
+    fn update_keyslots(&mut self) -> Result<(), TksError> {
+        Ok(())
+    }
+    /// Whether the key of the collection is available, having been derived less than `ttl` ago,
+    /// see [key_cache]
+    fn has_cached_key(&self, _collection: &Uuid, _ttl: Duration) -> bool {
+        false
+    }
+    /// Drops the keys, so that unlocking asks the passwords again, see [key_cache]; the
+    /// collections got locked before
+    fn forget_keys(&mut self) {}
+    /// What the backend can do, see [capabilities]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::full(self.get_kind().name())
+    }
+    /// Key of the secret checksums of the collection, see [checksums]; the backend needs to be
+    /// unlocked
+    fn checksum_key(&self, _collection: &Uuid) -> Result<Vec<u8>, TksError> {
+        Err(TksError::NotSupported("secret checksums"))
+    }
+    /// Encrypts data of the service itself, e.g. the known clients, into the named file; the
+    /// backend needs to be unlocked
+    fn write_private_file(&self, _name: &str, _data: &[u8]) -> Result<(), TksError> {
+        Err(TksError::NotSupported("private files"))
+    }
+    /// Decrypts the named file written by `write_private_file`, `None` until it gets written; the
+    /// backend needs to be unlocked
+    fn read_private_file(&self, _name: &str) -> Result<Option<Vec<u8>>, TksError> {
+        Err(TksError::NotSupported("private files"))
+    }
+    /// Encrypts the files of the collection's key with a key derived from the new password, once
+    /// the old one got checked; `collections` are the ones sharing the key, with their AAD. Any
+    /// file written gets restored when writing the next one fails.
+    fn change_password(
         &mut self,
+        _collection: &Uuid,
+        _collections: &[(&Collection, String)],
+        _old: &SecretString,
+        _new: &SecretString,
+    ) -> Result<(), TksError> {
+        Err(TksError::NotSupported("changing the password"))
+    }
+    /// Directory holding the storage files, if any
+    fn root_path(&self) -> Option<PathBuf> {
+        None
+    }
+    /// Removes the metadata and the items files of the collection
+    fn delete_collection_files(&mut self, _collection: &Collection) -> Result<(), TksError> {
+        Err(TksError::NotSupported("deleting collections"))
+    }
+    /// Starts saving a collection: the files written until [StorageBackend::end_save] only
+    /// replace the previous ones together, see [file_ops::Group]
+    fn begin_save(&self) {}
+    /// Ends the save started by `begin_save`, replacing the files if it `completed`; otherwise
+    /// the previous files stay
+    fn end_save(&self, _completed: bool) -> Result<(), TksError> {
+        Ok(())
+    }
+    fn save_collection_metadata(
+        &self,
         coll_path: &PathBuf,
         x: &String,
     ) -> Result<(), TksError>;
     fn save_collection_items(
-        &mut self,
-        coll_items_path: &PathBuf,
+        &self,
+        collection: &Collection,
         aad: &String,
         item_data: &String,
     ) -> Result<(), TksError>;
+    /// Stores the secrets of the collection's unlocked items; the locked items keep their stored
+    /// secrets
+    fn save_collection_secrets(
+        &self,
+        collection: &Collection,
+        aad: &String,
+        collection_secrets: CollectionSecrets,
+    ) -> Result<(), TksError> {
+        self.save_items_file(collection, aad, collection_secrets)
+    }
+    /// Rewrites the single file holding all the secrets of the collection
+    fn save_items_file(
+        &self,
+        collection: &Collection,
+        aad: &String,
+        mut collection_secrets: CollectionSecrets,
+    ) -> Result<(), TksError> {
+        if collection_secrets.items.len() < collection.stored_items().count() {
+            // some items are locked, so their secrets should be taken from the items file
+            let stored = SecureBuffer::from(self.load_collection_items(collection, aad)?);
+            if !stored.is_empty() {
+                let stored: CollectionSecrets = serde_json::from_slice(&stored)?;
+                collection_secrets.items.extend(stored.items.into_iter().filter(|s| {
+                    collection
+                        .stored_items()
+                        .any(|i| i.id.uuid == s.uuid && i.data.is_none())
+                }));
+            }
+        }
+        let mut items = serde_json::to_string(&collection_secrets)?;
+        let saved = self.save_collection_items(collection, aad, &items);
+        items.zeroize();
+        saved
+    }
     fn load_collection_items(
         &self,
         collection: &Collection,
         aad: &String,
     ) -> Result<Vec<u8>, TksError>;
+    /// Decrypts every file holding secrets of the collection, without writing any, see [fsck]
+    fn check_secrets(
+        &self,
+        collection: &Collection,
+        aad: &String,
+    ) -> Result<StoredSecrets, TksError> {
+        let mut stored = StoredSecrets::default();
+        stored.add_items_file(
+            &collection.items_path,
+            self.load_collection_items(collection, aad),
+        );
+        Ok(stored)
+    }
+    /// Decrypts the secret of a single item. Backends keeping all the collection's secrets in a
+    /// single file fall back to decrypting that file and picking the item from it.
+    fn load_item_data(
+        &self,
+        collection: &Collection,
+        aad: &String,
+        item_uuid: &Uuid,
+    ) -> Result<ItemData, TksError> {
+        let data = SecureBuffer::from(self.load_collection_items(collection, aad)?);
+        CollectionSecrets::find_item(&data, item_uuid)
+    }
 }
 
 impl Storage {
@@ -96,48 +324,185 @@ impl Storage {
                     format!("Error getting settings: {}", e),
                 )
             })?;
-            let backend: Box<dyn StorageBackend + Send + 'static> =
-                match settings.storage.kind.as_str() {
-                    // #[cfg(feature = "fscrypt")]
-                    // "fscrypt" => FSCryptBackend::new(OsString::from(settings.storage.path.clone()))?,
-                    "tks_gcm" => Box::new(TksGcmBackend::new(settings.storage.clone())?),
-                    "password-store" => {
-                        Box::new(PasswordStoreBackend::new(settings.storage.clone())?)
-                    }
+            Storage::open(settings.storage.clone())
+        };
 
-                    _ => panic!("Unknown storage backend kind specified in the configuration file"),
-                };
-            let collections = backend
-                .as_ref()
-                .get_metadata_paths()?
-                .into_iter()
-                .map(|p| Storage::load_collection(&p))
-                .collect::<Result<Vec<_>, _>>()?;
-            let mut storage = Storage {
-                backend,
-                collections,
-            };
-            for c in storage.collections.iter_mut() {
-                c.items_path = storage.backend.collection_items_path(&c.name)?;
+        do_create_storage().unwrap_or_else(|e: TksError| {
+            panic!("Error initializing storage: {:}", e);
+        })
+    }
+
+    /// Loads the storage described by `settings`. The service uses the [STORAGE] instance, this is
+    /// meant for tools and tests needing to (re)open a storage on their own.
+    ///
+    /// The backends of `settings.mounts` contribute their collections too, each collection being
+    /// saved by the backend it comes from. The `[storage]` backend holds the default collection
+    /// and the new ones: the default collections of the mounted backends become plain ones. The
+    /// session collection, see [memory], comes last.
+    pub fn open(settings: crate::settings::Storage) -> Result<Storage, TksError> {
+        let flush_delay = Duration::from_millis(settings.flush_delay);
+        let min_free_space = settings.min_free_space * 1024 * 1024;
+        let mut mounts = vec![Mount {
+            name: StorageMount::MAIN.to_string(),
+            backend: Storage::open_backend(settings.clone())?,
+            read_only: false,
+            synced: false,
+        }];
+        for mount in &settings.mounts {
+            mounts.push(Mount {
+                name: mount.name.clone(),
+                backend: Storage::open_backend(mount.settings(&settings))?,
+                read_only: mount.read_only,
+                synced: mount.sync,
+            });
+        }
+        let mut collections = Vec::new();
+        for (index, mount) in mounts.iter().enumerate() {
+            for path in mount.backend.get_metadata_paths()? {
+                // the sync tools' copies of the conflicting versions get merged, see [sync]
+                if mount.synced && sync::conflicted_original(&path).is_some() {
+                    continue;
+                }
+                match mount.backend.root_path() {
+                    Some(root) if !mount.read_only => schema::upgrade_metadata_file(&path, &root)?,
+                    _ => {}
+                }
+                let mut c = Storage::load_collection(&path)?;
+                c.mount = index;
+                c.synced = mount.synced;
+                c.items_path = mount.backend.collection_items_path(&c.name)?;
+                if index > 0 {
+                    c.default = false;
+                    if let Some(aliases) = c.aliases.as_mut() {
+                        aliases.retain(|a| a != DEFAULT_NAME);
+                    }
+                }
+                collections.push(c);
             }
+        }
+        mounts.push(Mount::memory());
+        collections.push(Storage::session_collection(mounts.len() - 1)?);
+        let mut storage = Storage {
+            mounts,
+            collections,
+            dirty: Mutex::new(HashSet::new()),
+            write_behind: false,
+            first_change: None,
+            last_change: None,
+            flush_delay,
+            min_free_space,
+            batches: HashMap::new(),
+        };
 
-            // look for the default collection and create it if it doesn't exist
-            let _ = storage.read_alias("default").or_else(|_| {
-                info!("Creating default collection");
-                storage
-                    .create_collection(DEFAULT_NAME, DEFAULT_NAME, &HashMap::new())
-                    .map(|_| "default".to_string())
-            })?;
+        // look for the default collection and create it if it doesn't exist
+        let _ = storage.read_alias("default").or_else(|_| {
+            info!("Creating default collection");
+            storage
+                .create_collection(DEFAULT_NAME, DEFAULT_NAME, &HashMap::new())
+                .map(|_| "default".to_string())
+        })?;
 
-            Ok(storage)
+        Ok(storage)
+    }
+
+    fn open_backend(
+        settings: crate::settings::Storage,
+    ) -> Result<Box<dyn StorageBackend + Send + Sync>, TksError> {
+        let kind = settings.kind.clone();
+        let backend: Box<dyn StorageBackend + Send + Sync + 'static> = match kind.as_str() {
+            // #[cfg(feature = "fscrypt")]
+            // "fscrypt" => FSCryptBackend::new(OsString::from(settings.path.clone()))?,
+            "tks_gcm" => Box::new(TksGcmBackend::new(settings)?),
+            "password-store" => Box::new(PasswordStoreBackend::new(settings)?),
+
+            _ => panic!("Unknown storage backend kind specified in the configuration file"),
         };
+        Ok(backend)
+    }
 
-        do_create_storage().unwrap_or_else(|e: TksError| {
-            panic!("Error initializing storage: {:}", e);
-        })
+    /// Unlocks all the collections without prompting the user; all the backends, and all the
+    /// collections having a key of their own, get the same password
+    pub fn unlock_with_password(&mut self, password: SecretString) -> Result<(), TksError> {
+        for mount in self.mounts.iter_mut() {
+            mount
+                .backend
+                .get_secrets_handler()?
+                .derive_key_from_password(password.clone())?;
+            mount.backend.update_keyslots()?;
+        }
+        for c in &self.collections {
+            let backend = &mut self.mounts[c.mount].backend;
+            if backend.has_own_key(&c.uuid) {
+                backend
+                    .get_collection_secrets_handler(&c.uuid)?
+                    .derive_key_from_password(password.clone())?;
+            }
+        }
+        self.unlock_all_collections()
+    }
+
+    /// Unlocks the collections sharing the key of the given one with a password the client sent,
+    /// instead of prompting the user; the password commissions a key having none yet
+    pub(crate) fn unlock_backend_with_password(
+        &mut self,
+        coll_uuid: &Uuid,
+        password: SecretString,
+    ) -> Result<(), TksError> {
+        let backend = self.backend_of(coll_uuid)?;
+        backend
+            .get_collection_secrets_handler(coll_uuid)?
+            .derive_key_from_password(password)?;
+        backend.update_keyslots()?;
+        self.unlock_backend_collections(coll_uuid)
+    }
+
+    /// Unlocks the collections sharing the key of the given one with the key file configured for
+    /// it, see [crate::settings::Storage::keyfiles], instead of prompting the user
+    pub fn unlock_backend_with_keyfile(&mut self, coll_uuid: &Uuid) -> Result<(), TksError> {
+        let name = self.with_collection(coll_uuid, |c| Ok(c.label().to_string()))?;
+        let backend = self.backend_of(coll_uuid)?;
+        backend.unlock_with_keyfile(&name)?;
+        backend.update_keyslots()?;
+        self.unlock_backend_collections(coll_uuid)
+    }
+
+    /// Saves data of the service itself through the `[storage]` backend, encrypted with its key
+    pub fn write_private_file(&self, name: &str, data: &[u8]) -> Result<(), TksError> {
+        self.mounts[0].backend.write_private_file(name, data)
+    }
+
+    /// Reads back data saved by [Storage::write_private_file], `None` until it gets written;
+    /// fails with [TksError::PermissionDenied] while the `[storage]` backend is locked
+    pub fn read_private_file(&self, name: &str) -> Result<Option<Vec<u8>>, TksError> {
+        self.mounts[0].backend.read_private_file(name)
+    }
+
+    /// The backend saving the collection
+    fn backend_of(
+        &mut self,
+        uuid: &Uuid,
+    ) -> Result<&mut (dyn StorageBackend + Send + Sync + 'static), TksError> {
+        let index = self.mount_of(uuid)?;
+        Ok(self.mounts[index].backend.as_mut())
+    }
+
+    fn mount_of(&self, uuid: &Uuid) -> Result<usize, TksError> {
+        self.collections
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .map(|c| c.mount)
+            .ok_or(TksError::NotFound(None))
     }
 
-    pub fn read_alias(&mut self, alias: &str) -> Result<String, TksError> {
+    /// Fails when the collection comes from a read-only backend
+    pub(crate) fn check_writable(&self, uuid: &Uuid) -> Result<(), TksError> {
+        match self.mounts[self.mount_of(uuid)?].read_only {
+            true => Err(TksError::NotSupported("the collection is read-only")),
+            false => Ok(()),
+        }
+    }
+
+    pub fn read_alias(&self, alias: &str) -> Result<String, TksError> {
         self.collections
             .iter()
             .filter(|c| c.aliases.is_some())
@@ -148,6 +513,34 @@ impl Storage {
             ))
     }
 
+    /// Points `alias` to the given collection, or removes it when `uuid` is `None`. Moving the
+    /// `default` alias also moves the default flag. Returns the uuids of the changed collections.
+    pub fn set_alias(&mut self, alias: &str, uuid: Option<Uuid>) -> Result<Vec<Uuid>, TksError> {
+        trace!("set_alias '{}' to {:?}", alias, uuid);
+        if alias == DEFAULT_NAME && uuid.is_none() {
+            return Err(TksError::NotSupported("the default alias cannot be removed"));
+        }
+        if let Some(uuid) = uuid {
+            self.with_collection(&uuid, |_| Ok(()))?;
+        }
+        self.modify_collections_metadata(|collections| {
+            for c in collections.iter_mut() {
+                let is_target = Some(c.uuid) == uuid;
+                let mut aliases = c.aliases.take().unwrap_or_default();
+                if !is_target {
+                    aliases.retain(|a| a != alias);
+                } else if !aliases.iter().any(|a| a == alias) {
+                    aliases.push(alias.to_string());
+                }
+                c.aliases = (!aliases.is_empty()).then_some(aliases);
+                if alias == DEFAULT_NAME {
+                    c.default = is_target;
+                }
+            }
+            Ok(())
+        })
+    }
+
     pub fn with_collection<F, T>(&self, uuid: &Uuid, f: F) -> Result<T, TksError>
     where
         F: FnOnce(&Collection) -> Result<T, TksError>,
@@ -165,30 +558,44 @@ impl Storage {
         f(&mut collection)
     }
 
+    /// Changes a collection, then saves it. When `f` fails, or the collection can't be saved, the
+    /// collection gets back to what it was, so it doesn't differ from the one on disk.
     pub fn modify_collection<F, T>(&mut self, uuid: &Uuid, f: F) -> Result<T, TksError>
     where
         F: FnOnce(&mut Collection) -> Result<T, TksError>,
     {
-        let result = self
+        let collection = self
             .collections
             .iter_mut()
             .find(|c| c.uuid == *uuid)
             .ok_or(TksError::NotFound(
                 format!("Collection '{}' not found", uuid).into(),
-            ))
-            .and_then(|c| f(c));
+            ))?;
+        let snapshot = ItemsSnapshot::new(collection);
+        let result = match f(collection) {
+            Ok(result) => result,
+            Err(e) => {
+                snapshot.restore(collection);
+                return Err(e);
+            }
+        };
 
         // TODO the collection name may have changed; in this case, we might need to also
         // update the collection's path on disk; but for the moment, it should still reload
         // fine as the correct collection name gets serialized on disk
-        self.save_collection(uuid, false)?;
-        result
+        if let Err(e) = self.persist_collection(uuid) {
+            error!("Cannot save collection '{}', undoing the changes: {}", uuid, e);
+            self.rollback_items(&[snapshot], &[]);
+            return Err(e);
+        }
+        ServiceImpl::emit_collection_changed(*uuid);
+        Ok(result)
     }
 
     /// This performs a read-only operation on a collection item
     /// for RW operations, use modify_item
     pub fn with_item<F, T>(
-        &mut self,
+        &self,
         collection_uuid: &Uuid,
         item_uuid: &Uuid,
         f: F,
@@ -198,7 +605,7 @@ impl Storage {
     {
         let collection = self
             .collections
-            .iter_mut()
+            .iter()
             .find(|c| c.uuid == *collection_uuid)
             .ok_or(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -208,6 +615,25 @@ impl Storage {
         f(item)
     }
 
+    /// Calls `f` with each of the items found, and its collection, in a single pass over the
+    /// storage, so that batches of items don't scan the collections once per item
+    pub fn for_each_item<F>(&self, ids: &[ItemId], mut f: F)
+    where
+        F: FnMut(&Collection, &Item),
+    {
+        let collections: HashMap<&Uuid, &Collection> =
+            self.collections.iter().map(|c| (&c.uuid, c)).collect();
+        for id in ids {
+            let Some(collection) = collections.get(&id.collection_uuid) else {
+                continue;
+            };
+            if let Some(item) = collection.indexed_item(&id.uuid) {
+                f(collection, item);
+            }
+        }
+    }
+
+    /// Changes an item, then saves its collection; see [Storage::modify_collection]
     pub fn modify_item<F, T>(
         &mut self,
         collection_uuid: &Uuid,
@@ -228,38 +654,57 @@ impl Storage {
                     "Collection not found".to_string(),
                 )
             })?;
-        let mut item = collection.get_item_mut(item_uuid)?;
-        match f(&mut item) {
-            Ok(t) => {
-                item.modified = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    .into();
-                self.save_collection(collection_uuid, false)?;
-                Ok(t)
+        let snapshot = ItemsSnapshot::new(collection);
+        let depth = collection.history_depth();
+        let item = collection.get_item_mut(item_uuid)?;
+        let result = match f(item) {
+            Ok(result) => result,
+            Err(e) => {
+                snapshot.restore(collection);
+                return Err(e);
             }
-            Err(e) => Err(e),
+        };
+        if let Some(data) = item.data.as_mut() {
+            data.trim_versions(depth);
+        }
+        item.modified = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let label = item.label.clone();
+        collection.record_history(HistoryOperation::Modified, item_uuid, &label);
+        if let Err(e) = self.persist_collection(collection_uuid) {
+            error!("Cannot save collection '{}', undoing the changes: {}", collection_uuid, e);
+            self.rollback_items(&[snapshot], &[]);
+            return Err(e);
         }
+        Ok(result)
     }
 
     /// Create a new collection
     ///
     /// # Arguments
     /// * `name` - The name of the collection
-    /// * `properties` - A HashMap of properties to set on the collection; this version ignores
-    /// these properties and this is allowed by the spec
+    /// * `properties` - A HashMap of properties to set on the collection; only the label and the
+    ///   custom `tks:*` properties are kept, see [Collection::set_properties]
     pub fn create_collection(
         &mut self,
         name: &str,
         alias: &str,
-        _properties: &HashMap<String, String>,
+        properties: &HashMap<String, String>,
     ) -> Result<Uuid, TksError> {
-        let (path, items_path) = self.backend.new_metadata_path(name)?;
+        let (path, items_path) = self.mounts[0].backend.new_metadata_path(name)?;
         let mut coll = Collection::new(name, &path, &items_path)?;
+        coll.set_properties(properties);
         if !alias.is_empty() {
             coll.aliases = Some(vec![alias.to_string()]);
         }
+        // the default collection keeps the key of the backend, which the private files share;
+        // the other ones may get their own, the new collection staying locked until its first
+        // unlock defines its password
+        if !coll.default {
+            self.mounts[0].backend.create_collection_key(&coll.uuid)?;
+        }
         let uuid = coll.uuid;
         self.collections.push(coll);
         self.save_collection(&uuid, true)?;
@@ -267,17 +712,42 @@ impl Storage {
         Ok(uuid)
     }
 
+    /// Deletes the collection and its files; the default collection cannot be deleted
+    pub fn delete_collection(&mut self, uuid: &Uuid) -> Result<Collection, TksError> {
+        let index = self
+            .collections
+            .iter()
+            .position(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(
+                format!("Collection '{}' not found", uuid).into(),
+            ))?;
+        if self.collections[index].default {
+            return Err(TksError::NotSupported("the default collection cannot be deleted"));
+        }
+        self.check_writable(uuid)?;
+        self.check_batch(uuid)?;
+        trace!("Deleting collection '{}'", uuid);
+        let collection = &self.collections[index];
+        self.mounts[collection.mount]
+            .backend
+            .delete_collection_files(collection)?;
+        Ok(self.collections.remove(index))
+    }
+
     fn save_collection(&mut self, uuid: &Uuid, is_new: bool) -> Result<(), TksError> {
+        self.check_batch(uuid)?;
+        self.touch_collection(uuid)?;
+        self.write_collection(uuid)
+    }
+
+    /// Updates the modification timestamp and sequence number of a collection about to be saved
+    fn touch_collection(&mut self, uuid: &Uuid) -> Result<(), TksError> {
+        self.update_checksums(uuid)?;
         let collection = self
             .collections
             .iter_mut()
             .find(|c| c.uuid == *uuid)
             .ok_or_else(|| TksError::NotFound(None))?;
-        trace!(
-            "Saving collection '{}' to path '{}'",
-            collection.name,
-            collection.path.display()
-        );
         let ts = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .map_err(|e| {
@@ -289,39 +759,82 @@ impl Storage {
             .as_secs()
             .into();
         collection.modified = ts;
+        collection.sequence += 1;
+        if collection.synced {
+            *collection.versions.entry(sync::machine_id()).or_default() += 1;
+        }
+        collection.index_folders();
+        collection.index_attributes();
+        Ok(())
+    }
 
-        let mut metadata = serde_json::to_string(&collection)?;
-        self.backend
-            .save_collection_metadata(&collection.path, &metadata)?;
-
-        if !collection.locked {
-            // add file paths to the authentication metadata to reduce attack surface
-            let mut aad = collection.uuid.to_string();
-            aad.push_str(collection.path.to_str().unwrap());
-            aad.push_str(collection.items_path.to_str().unwrap());
+    fn write_collection(&self, uuid: &Uuid) -> Result<(), TksError> {
+        self.check_writable(uuid)?;
+        self.check_free_space(uuid)?;
+        self.check_not_behind(uuid)?;
+        let collection = self
+            .collections
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        trace!(
+            "Saving collection '{}' to path '{}'",
+            collection.name,
+            collection.path.display()
+        );
+        let backend = &self.mounts[collection.mount].backend;
+        let metadata = serde_json::to_string(&collection)?;
+        // the metadata lists the items, so it only gets replaced together with their secrets
+        backend.begin_save();
+        let saved = (|| {
+            backend.save_collection_metadata(&collection.path, &metadata)?;
+            if !collection.locked || collection.items.iter().any(|i| !i.locked) {
+                let aad = Storage::collection_aad(collection);
+                backend.save_collection_secrets(collection, &aad, collection.get_secrets())?;
+            }
+            Ok(())
+        })();
+        let ended = backend.end_save(saved.is_ok());
+        saved.and(ended)?;
+        // whatever was pending got written
+        self.dirty.lock().unwrap().remove(uuid);
+        Ok(())
+    }
 
-            let collection_secrets = collection.get_secrets();
-            let items = serde_json::to_string(&collection_secrets)?;
-            self.backend
-                .save_collection_items(&collection.items_path, &aad, &items)?;
+    /// Builds the authentication metadata used when encrypting the collection's items
+    fn collection_aad(collection: &Collection) -> String {
+        // add file paths to the authentication metadata to reduce attack surface
+        assert!(collection.items_path.to_str().unwrap().len() > 0);
+        let mut aad = collection.uuid.to_string();
+        if collection.synced {
+            // the synced directory may be elsewhere on the other machines
+            aad.push_str(&sync::relative_path(&collection.path));
+            aad.push_str(&sync::relative_path(&collection.items_path));
+            return aad;
         }
-        Ok(())
+        aad.push_str(collection.path.to_str().unwrap());
+        aad.push_str(collection.items_path.to_str().unwrap());
+        aad
     }
 
     /// Loads collection metadata from disk.
-    /// The resulting collection is in a locked state.
+    /// The resulting collection, and each of its items, is in a locked state.
     fn load_collection(path: &PathBuf) -> Result<Collection, TksError> {
         trace!("Loading collection from path '{}'", path.display());
         let mut file = File::open(path)?;
         let mut data = String::new();
         file.read_to_string(&mut data)?;
-        let mut collection: Collection = serde_json::from_str(&data)?;
+        let mut collection: Collection = schema::parse_metadata(&data)?;
         collection.path = path.clone();
         collection.locked = true;
-        collection
-            .items
-            .iter_mut()
-            .for_each(|i: &mut Item| i.id.collection_uuid = collection.uuid);
+        let uuid = collection.uuid;
+        // the secrets aren't loaded, whatever the items were when saved
+        collection.stored_items_mut().for_each(|i: &mut Item| {
+            i.id.collection_uuid = uuid;
+            i.locked = true;
+        });
+        collection.index_folders();
+        collection.index_attributes();
         Ok(collection)
     }
 
@@ -338,14 +851,14 @@ impl Storage {
         );
 
         // prepare the authentication metadata
-        assert!(collection.items_path.to_str().unwrap().len() > 0);
-        let mut aad = collection.uuid.to_string();
-        aad.push_str(collection.path.to_str().unwrap());
-        aad.push_str(collection.items_path.to_str().unwrap());
+        let aad = Storage::collection_aad(collection);
 
         // ask backend to decrypt the items, if any
-        let decrypted_items = self.backend.load_collection_items(collection, &aad)?;
+        let backend = &self.mounts[collection.mount].backend;
+        let decrypted_items = SecureBuffer::from(backend.load_collection_items(collection, &aad)?);
         collection.unlock(&decrypted_items)?;
+        ServiceImpl::emit_collection_changed(collection.uuid);
+        Storage::emit_lock_state_changed(collection);
         Ok(())
     }
 
@@ -358,6 +871,98 @@ impl Storage {
         Ok(())
     }
 
+    /// The collections sharing the key of the given one: the ones of its backend, unless the
+    /// collection has a key of its own
+    fn collections_sharing_key(&self, coll_uuid: &Uuid) -> Result<Vec<Uuid>, TksError> {
+        let mount = self.mount_of(coll_uuid)?;
+        let backend = &self.mounts[mount].backend;
+        Ok(match backend.has_own_key(coll_uuid) {
+            true => vec![*coll_uuid],
+            false => self
+                .collections
+                .iter()
+                .filter(|c| c.mount == mount && !backend.has_own_key(&c.uuid))
+                .map(|c| c.uuid)
+                .collect(),
+        })
+    }
+
+    /// Unlocks the collections sharing the key of the given one, as it got available
+    fn unlock_backend_collections(&mut self, coll_uuid: &Uuid) -> Result<(), TksError> {
+        trace!("unlock_backend_collections of '{}'", coll_uuid);
+        for c in self.collections_sharing_key(coll_uuid)? {
+            self.unlock_collection(&c)?;
+        }
+        Ok(())
+    }
+
+    /// Decrypts a single item, leaving the other items of its collection untouched
+    fn unlock_item(&mut self, item_id: &ItemId) -> Result<(), TksError> {
+        let collection = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == item_id.collection_uuid)
+            .ok_or_else(|| TksError::NotFound(None))?;
+        trace!(
+            "unlock_item '{}' from collection '{}'",
+            item_id.uuid,
+            collection.name
+        );
+        let aad = Storage::collection_aad(collection);
+        let item_data = self.mounts[collection.mount]
+            .backend
+            .load_item_data(collection, &aad, &item_id.uuid)?;
+        collection.get_item_mut(&item_id.uuid)?.unlock(item_data);
+        ItemImpl::emit_properties_changed(item_id.clone(), &["Locked"]);
+        Ok(())
+    }
+
+    /// Locks the collection, once its pending changes got written
+    pub fn lock_collection(&mut self, uuid: &Uuid) -> Result<(), TksError> {
+        if self.is_in_memory(uuid) {
+            // nothing could unlock it again
+            return Ok(());
+        }
+        // the secrets are about to be dropped from memory
+        self.flush_collection(uuid)?;
+        let collection = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        collection.lock()?;
+        ServiceImpl::emit_collection_changed(collection.uuid);
+        Storage::emit_lock_state_changed(collection);
+        kwallet_impl::collection_locked(uuid);
+        Ok(())
+    }
+
+    pub fn lock_item(&mut self, item_id: &ItemId) -> Result<(), TksError> {
+        trace!("lock_item '{}'", item_id.uuid);
+        if self.is_in_memory(&item_id.collection_uuid) {
+            return Ok(());
+        }
+        // the secret is about to be dropped from memory
+        self.flush_collection(&item_id.collection_uuid)?;
+        self.collections
+            .iter_mut()
+            .find(|c| c.uuid == item_id.collection_uuid)
+            .ok_or(TksError::NotFound(None))?
+            .get_item_mut(&item_id.uuid)?
+            .lock();
+        ItemImpl::emit_properties_changed(item_id.clone(), &["Locked"]);
+        Ok(())
+    }
+
+    /// Lets clients know the collection, and all of its items, got locked or unlocked
+    pub(crate) fn emit_lock_state_changed(collection: &Collection) {
+        CollectionImpl::emit_properties_changed(collection.uuid, &["Locked"]);
+        collection
+            .items
+            .iter()
+            .for_each(|i| ItemImpl::emit_properties_changed(i.id.clone(), &["Locked"]));
+    }
+
     pub(crate) fn create_unlock_action(
         &mut self,
         coll_uuid: &Uuid,
@@ -367,7 +972,26 @@ impl Storage {
             .iter()
             .find(|c| c.uuid == *coll_uuid)
             .ok_or_else(|| TksError::NotFound(None))?;
-        self.backend
-            .create_unlock_action(coll_uuid, &collection.name)
+        self.mounts[collection.mount].backend.create_unlock_action(
+            coll_uuid,
+            collection.label(),
+            PassphraseActionParam::UnlockAllCollections(*coll_uuid),
+        )
+    }
+
+    pub(crate) fn create_item_unlock_action(
+        &mut self,
+        item_id: &ItemId,
+    ) -> Result<PromptAction, TksError> {
+        let collection = self
+            .collections
+            .iter()
+            .find(|c| c.uuid == item_id.collection_uuid)
+            .ok_or(TksError::NotFound(None))?;
+        self.mounts[collection.mount].backend.create_unlock_action(
+            &collection.uuid,
+            collection.label(),
+            PassphraseActionParam::UnlockItem(item_id.clone()),
+        )
     }
 }
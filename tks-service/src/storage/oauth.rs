@@ -0,0 +1,166 @@
+//! OAuth2 tokens shared by the mail clients and the command line tools, so that each of them
+//! doesn't have to walk the user through the authorization again. A token is an item having the
+//! `io.linux_tks.OAuth2Token` schema, its secret being the JSON answer of the token endpoint, with
+//! the access token and, usually, a refresh token. Its attributes tell what it grants:
+//!
+//! - `oauth2:issuer`, e.g. `https://accounts.google.com`
+//! - `oauth2:account`, the user the token was granted for
+//! - `oauth2:scopes`, the granted scopes, sorted and separated by spaces
+//! - `oauth2:expires-at`, when the access token expires, in seconds since the Unix epoch
+//! - `oauth2:refreshable`, `true` when the secret holds a refresh token
+//! - `oauth2:token-endpoint` and `oauth2:client-id`, optional, to refresh the token with
+//!
+//! The access token expiry isn't `tks:expires-at`, see [crate::storage::expiry]: the refresh token
+//! outlives it, so the item should stay. Storing a token replaces the one of the same issuer,
+//! account and scopes, whatever its other attributes, when CreateItem is told to replace. The
+//! io.linux_tks.OAuth1 interface finds the tokens still valid for a while.
+
+use crate::storage::collection::{Collection, Item};
+use crate::storage::Storage;
+use std::collections::{BTreeSet, HashMap};
+
+pub const SCHEMA: &str = "io.linux_tks.OAuth2Token";
+pub const ISSUER_ATTRIBUTE: &str = "oauth2:issuer";
+pub const ACCOUNT_ATTRIBUTE: &str = "oauth2:account";
+pub const SCOPES_ATTRIBUTE: &str = "oauth2:scopes";
+pub const EXPIRES_AT_ATTRIBUTE: &str = "oauth2:expires-at";
+pub const REFRESHABLE_ATTRIBUTE: &str = "oauth2:refreshable";
+pub const TOKEN_ENDPOINT_ATTRIBUTE: &str = "oauth2:token-endpoint";
+pub const CLIENT_ID_ATTRIBUTE: &str = "oauth2:client-id";
+
+/// What a token grants, from the attributes of its item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub issuer: String,
+    pub account: String,
+    pub scopes: BTreeSet<String>,
+    /// Seconds since the Unix epoch
+    pub expires_at: u64,
+    pub refreshable: bool,
+    pub token_endpoint: Option<String>,
+    pub client_id: Option<String>,
+}
+
+impl TokenMetadata {
+    /// The attributes of the item holding the token
+    pub fn attributes(&self) -> HashMap<String, String> {
+        let mut attributes = HashMap::from([
+            ("xdg:schema".to_string(), SCHEMA.to_string()),
+            (ISSUER_ATTRIBUTE.to_string(), self.issuer.clone()),
+            (ACCOUNT_ATTRIBUTE.to_string(), self.account.clone()),
+            (SCOPES_ATTRIBUTE.to_string(), scopes_attribute(&self.scopes)),
+            (
+                EXPIRES_AT_ATTRIBUTE.to_string(),
+                self.expires_at.to_string(),
+            ),
+            (
+                REFRESHABLE_ATTRIBUTE.to_string(),
+                self.refreshable.to_string(),
+            ),
+        ]);
+        if let Some(token_endpoint) = &self.token_endpoint {
+            attributes.insert(TOKEN_ENDPOINT_ATTRIBUTE.to_string(), token_endpoint.clone());
+        }
+        if let Some(client_id) = &self.client_id {
+            attributes.insert(CLIENT_ID_ATTRIBUTE.to_string(), client_id.clone());
+        }
+        attributes
+    }
+
+    /// The token an item holds, unless it isn't one or its attributes are malformed
+    pub fn from_attributes(attributes: &HashMap<String, String>) -> Option<TokenMetadata> {
+        if attributes.get("xdg:schema").map(String::as_str) != Some(SCHEMA) {
+            return None;
+        }
+        Some(TokenMetadata {
+            issuer: attributes.get(ISSUER_ATTRIBUTE)?.clone(),
+            account: attributes.get(ACCOUNT_ATTRIBUTE)?.clone(),
+            scopes: attributes
+                .get(SCOPES_ATTRIBUTE)?
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            expires_at: attributes.get(EXPIRES_AT_ATTRIBUTE)?.parse().ok()?,
+            refreshable: attributes.get(REFRESHABLE_ATTRIBUTE).map(String::as_str) == Some("true"),
+            token_endpoint: attributes.get(TOKEN_ENDPOINT_ATTRIBUTE).cloned(),
+            client_id: attributes.get(CLIENT_ID_ATTRIBUTE).cloned(),
+        })
+    }
+
+    pub fn label(&self) -> String {
+        format!("OAuth2 token of {} at {}", self.account, self.issuer)
+    }
+
+    /// Whether both tokens grant the same, so that the newer one replaces the other
+    pub fn same_grant(&self, other: &TokenMetadata) -> bool {
+        self.issuer == other.issuer && self.account == other.account && self.scopes == other.scopes
+    }
+}
+
+/// The scopes as the `oauth2:scopes` attribute has them, so that exact searches work too
+pub fn scopes_attribute<'a>(scopes: impl IntoIterator<Item = &'a String>) -> String {
+    let scopes: BTreeSet<&String> = scopes.into_iter().collect();
+    scopes
+        .into_iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether the items with these attributes hold the tokens of the same grant
+pub fn same_grant(a: &HashMap<String, String>, b: &HashMap<String, String>) -> bool {
+    match (
+        TokenMetadata::from_attributes(a),
+        TokenMetadata::from_attributes(b),
+    ) {
+        (Some(a), Some(b)) => a.same_grant(&b),
+        _ => false,
+    }
+}
+
+/// Which tokens [Storage::find_oauth_tokens] returns
+#[derive(Debug, Clone, Default)]
+pub struct TokenQuery {
+    /// Any issuer when empty
+    pub issuer: String,
+    /// Any account when empty
+    pub account: String,
+    /// The tokens should grant at least these
+    pub scopes: Vec<String>,
+    /// Seconds the access token should still be valid for
+    pub min_validity: u64,
+}
+
+impl TokenQuery {
+    fn matches(&self, token: &TokenMetadata, now: u64) -> bool {
+        (self.issuer.is_empty() || token.issuer == self.issuer)
+            && (self.account.is_empty() || token.account == self.account)
+            && self.scopes.iter().all(|s| token.scopes.contains(s))
+            && token.expires_at > now.saturating_add(self.min_validity)
+    }
+}
+
+impl Collection {
+    fn oauth_tokens(&self, query: &TokenQuery, now: u64) -> Vec<(&Item, u64)> {
+        self.items
+            .iter()
+            .filter_map(|i| TokenMetadata::from_attributes(&i.attributes).map(|t| (i, t)))
+            .filter(|(_, token)| query.matches(token, now))
+            .map(|(i, token)| (i, token.expires_at))
+            .collect()
+    }
+}
+
+impl Storage {
+    /// The tokens of all the collections matching the query at the given time, the ones
+    /// expiring last first
+    pub fn find_oauth_tokens(&self, query: &TokenQuery, now: u64) -> Vec<&Item> {
+        let mut tokens: Vec<(&Item, u64)> = self
+            .collections
+            .iter()
+            .flat_map(|c| c.oauth_tokens(query, now))
+            .collect();
+        tokens.sort_by_key(|(_, expires_at)| std::cmp::Reverse(*expires_at));
+        tokens.into_iter().map(|(i, _)| i).collect()
+    }
+}
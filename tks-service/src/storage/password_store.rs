@@ -1,12 +1,14 @@
 use crate::settings::{Settings, Storage};
+use crate::storage::capabilities::Capabilities;
 use crate::storage::collection::Collection;
+use crate::storage::permissions;
 use crate::storage::{SecretsHandler, StorageBackend, StorageBackendType};
-use crate::tks_dbus::prompt_impl::PromptAction;
+use crate::tks_dbus::prompt_impl::{PassphraseActionParam, PromptAction};
 use crate::tks_error::TksError;
 use homedir::my_home;
 use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 pub struct PasswordStoreBackend {
@@ -34,6 +36,7 @@ impl PasswordStoreBackend {
             PathBuf::from,
         );
         log::info!("path: {:?}", path);
+        permissions::check_private_dir(&path)?;
         let mut b = Self {
             path,
             metadata_path: None,
@@ -48,10 +51,13 @@ impl PasswordStoreBackend {
     fn create_or_update_metadata(&mut self) -> Result<(), TksError> {
         let mut metadata_path = PathBuf::new();
         let path: OsString = xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?
-            .create_data_directory("password-store")?.into();
+            .get_data_home()
+            .join("password-store")
+            .into();
         metadata_path.push(path.clone());
         metadata_path.push("metadata");
         // create if not exists ~/.local/share/io.linux-tks/password-store/metadata
+        permissions::create_private_dir(Path::new(&path))?;
         let _ = fs::DirBuilder::new()
             .recursive(true)
             .create(metadata_path.clone())?;
@@ -80,6 +86,16 @@ impl StorageBackend for PasswordStoreBackend {
         StorageBackendType::PasswordStore
     }
 
+    /// The store is a single collection of text files, managed by `pass`
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            create_collection: false,
+            multiple_collections: false,
+            binary_secrets: false,
+            ..Capabilities::full(self.get_kind().name())
+        }
+    }
+
     fn get_metadata_paths(&self) -> Result<Vec<PathBuf>, TksError> {
         // // we enumerate all the directories and return the paths to the leaf directories
         // let dirs = fs::read_dir(self.path.clone())?
@@ -97,8 +113,9 @@ impl StorageBackend for PasswordStoreBackend {
         ))
     }
 
-    fn collection_items_path(&self, name: &str) -> Result<PathBuf, TksError> {
-        todo!()
+    /// The store itself holds the secrets of its single collection
+    fn collection_items_path(&self, _name: &str) -> Result<PathBuf, TksError> {
+        Ok(self.path.clone())
     }
 
     fn get_secrets_handler(&mut self) -> Result<Box<dyn SecretsHandler + '_>, TksError> {
@@ -113,6 +130,7 @@ impl StorageBackend for PasswordStoreBackend {
         &mut self,
         coll_uuid: &Uuid,
         coll_name: &str,
+        _param: PassphraseActionParam,
     ) -> Result<PromptAction, TksError> {
         todo!()
     }
@@ -122,7 +140,7 @@ impl StorageBackend for PasswordStoreBackend {
     }
 
     fn save_collection_metadata(
-        &mut self,
+        &self,
         coll_path: &PathBuf,
         x: &String,
     ) -> Result<(), TksError> {
@@ -130,8 +148,8 @@ impl StorageBackend for PasswordStoreBackend {
     }
 
     fn save_collection_items(
-        &mut self,
-        coll_items_path: &PathBuf,
+        &self,
+        _collection: &Collection,
         x: &String,
         x0: &String,
     ) -> Result<(), TksError> {
@@ -0,0 +1,59 @@
+//! The storage directories must belong to the user running the service, and be closed to the
+//! others: with systemd user instances, each user runs a service of their own, and a storage path
+//! leaking from another user's environment, e.g. a system-wide XDG_DATA_HOME, or a directory left
+//! readable by the group, would share secrets across users. Such directories get refused with
+//! [TksError::InsecurePermissions] rather than silently fixed, as fixing them would hide the
+//! misconfiguration; the directories the service creates itself are private from the start.
+
+use crate::tks_error::TksError;
+use log::trace;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::Path;
+
+/// The permission bits of the group and the others
+const SHARED_BITS: u32 = 0o077;
+
+/// Creates the directory, and its missing parents, accessible by the current user only, then
+/// checks it like [check_private_dir]
+pub fn create_private_dir(path: &Path) -> Result<(), TksError> {
+    if !path.exists() {
+        trace!("Creating the private directory {:?}", path);
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path)?;
+    }
+    check_private_dir(path)
+}
+
+/// Checks the directory is owned by the user running the service and can't be accessed by the
+/// group nor the others
+pub fn check_private_dir(path: &Path) -> Result<(), TksError> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Err(TksError::InsecurePermissions(format!(
+            "{:?} is not a directory",
+            path
+        )));
+    }
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        return Err(TksError::InsecurePermissions(format!(
+            "{:?} is owned by uid {}, not by uid {} running the service; each user needs a \
+             storage of their own",
+            path,
+            metadata.uid(),
+            uid
+        )));
+    }
+    let mode = metadata.mode() & 0o777;
+    if mode & SHARED_BITS != 0 {
+        return Err(TksError::InsecurePermissions(format!(
+            "{:?} has mode {:o}, the group or the others may access it; run chmod 700 on it",
+            path, mode
+        )));
+    }
+    Ok(())
+}
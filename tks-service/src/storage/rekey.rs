@@ -0,0 +1,52 @@
+//! Changing the password of a key: the one of a backend, shared by its collections, or the one of
+//! a collection having its own, see `storage.per_collection_keys`. The backend checks the old
+//! password, then encrypts again every file of the key, the commissioned data coming last. When
+//! writing one of them fails, the files already written get their former contents back, so the
+//! old password keeps working.
+
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{debug, trace};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+impl Storage {
+    /// Changes the password of the key of the collection, returning the collections sharing it.
+    /// The collections don't need to be unlocked.
+    pub fn change_password(
+        &mut self,
+        uuid: &Uuid,
+        old: &SecretString,
+        new: &SecretString,
+    ) -> Result<Vec<Uuid>, TksError> {
+        trace!("change_password of the key of '{}'", uuid);
+        if new.expose_secret().is_empty() {
+            return Err(TksError::ParameterError);
+        }
+        if self.is_in_memory(uuid) {
+            return Err(TksError::NotSupported(
+                "the session collection has no password",
+            ));
+        }
+        self.check_writable(uuid)?;
+        let sharing = self.collections_sharing_key(uuid)?;
+        for c in &sharing {
+            self.check_batch(c)?;
+            // the pending changes get encrypted with the old key, along with the rest
+            self.flush_collection(c)?;
+        }
+
+        let mount = self.mount_of(uuid)?;
+        let collections: Vec<_> = self
+            .collections
+            .iter()
+            .filter(|c| sharing.contains(&c.uuid))
+            .map(|c| (c, Storage::collection_aad(c)))
+            .collect();
+        self.mounts[mount]
+            .backend
+            .change_password(uuid, &collections, old, new)?;
+        debug!("Changed the password of {} collections", sharing.len());
+        Ok(sharing)
+    }
+}
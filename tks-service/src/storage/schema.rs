@@ -0,0 +1,98 @@
+//! Versions of the on-disk formats: the collection metadata carries its `schema_version`, and the
+//! files encrypted by the tks_gcm backend start with the version of their format. Files written
+//! by a newer tks-service get refused, rather than misread then overwritten.
+//!
+//! Older metadata gets upgraded upon loading, one version at a time, by [METADATA_MIGRATIONS].
+//! When the service opens the storage, the file is copied to the [BACKUP_DIR] directory of its
+//! backend, as `<directory>.<file>.v<version>`, then rewritten; the read-only backends only get
+//! upgraded in memory. The encrypted files of an older format remain readable, the backup of the
+//! items file gets made when unlocking it, and the next save writes it in the current format.
+
+use crate::storage::file_ops;
+use crate::tks_error::TksError;
+use log::info;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// Version of the collection metadata written by this version of the service
+pub const METADATA_VERSION: u8 = 1;
+
+/// Directory of the backends receiving the files as they were before their upgrade
+pub const BACKUP_DIR: &str = "schema-backups";
+
+/// Upgrades the metadata of a collection from the previous version to `to`
+pub struct Migration {
+    pub to: u8,
+    pub upgrade: fn(&mut Map<String, Value>) -> Result<(), TksError>,
+}
+
+/// The upgrades of the collection metadata, oldest first; none yet, version 1 being the first
+pub const METADATA_MIGRATIONS: &[Migration] = &[];
+
+/// Brings the metadata to the `target` version, returning the version it had when it got upgraded
+pub fn upgrade(
+    metadata: &mut Value,
+    target: u8,
+    migrations: &[Migration],
+) -> Result<Option<u8>, TksError> {
+    let fields = metadata
+        .as_object_mut()
+        .ok_or_else(|| TksError::SerializationError("metadata is not an object".to_string()))?;
+    let version = fields
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| TksError::SerializationError("missing schema_version".to_string()))?;
+    if version > target as u64 {
+        return Err(TksError::UnsupportedVersion(format!(
+            "collection metadata version {}, this tks-service reads up to version {}",
+            version, target
+        )));
+    }
+    if version == target as u64 {
+        return Ok(None);
+    }
+    for to in version as u8 + 1..=target {
+        let migration = migrations.iter().find(|m| m.to == to).ok_or_else(|| {
+            TksError::UnsupportedVersion(format!("no upgrade to metadata version {}", to))
+        })?;
+        (migration.upgrade)(fields)?;
+        fields.insert("schema_version".to_string(), to.into());
+    }
+    Ok(Some(version as u8))
+}
+
+/// Parses the metadata of a collection, upgrading it in memory if needed
+pub(crate) fn parse_metadata<T: serde::de::DeserializeOwned>(data: &str) -> Result<T, TksError> {
+    let mut metadata: Value = serde_json::from_str(data)?;
+    upgrade(&mut metadata, METADATA_VERSION, METADATA_MIGRATIONS)?;
+    Ok(serde_json::from_value(metadata)?)
+}
+
+/// Upgrades the metadata file in place, after copying it to the backup directory under `root`
+pub(crate) fn upgrade_metadata_file(path: &Path, root: &Path) -> Result<(), TksError> {
+    let mut metadata: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let Some(from) = upgrade(&mut metadata, METADATA_VERSION, METADATA_MIGRATIONS)? else {
+        return Ok(());
+    };
+    backup_file(path, root, from)?;
+    file_ops::write(path, serde_json::to_string(&metadata)?)?;
+    info!(
+        "Upgraded {:?} from version {} to version {}",
+        path, from, METADATA_VERSION
+    );
+    Ok(())
+}
+
+/// Copies the file, as it is in the given version, to the backup directory under `root`; an
+/// upgrade interrupted then run again keeps the first copy
+pub(crate) fn backup_file(path: &Path, root: &Path, version: u8) -> Result<(), TksError> {
+    let dir = root.join(BACKUP_DIR);
+    fs::DirBuilder::new().recursive(true).create(&dir)?;
+    let backup = dir.join(format!("{}.v{}", file_ops::qualified_name(path), version));
+    if !backup.exists() {
+        fs::copy(path, &backup)?;
+        info!("Kept a copy of {:?} in {:?}", path, backup);
+    }
+    Ok(())
+}
@@ -0,0 +1,285 @@
+//! SearchItems used to compare the attributes of every item of every collection, which gets slow
+//! with thousands of items. Each collection keeps an inverted index of its item attributes
+//! instead, so searches only look at the matching items. Like the folders one, the index gets
+//! rebuilt upon loading and whenever the collection gets saved, which every item change does.
+//!
+//! Besides the exact matches of SearchItems, [AttributeQuery] matches attribute values by prefix,
+//! substring or regular expression, or just the presence of an attribute, e.g. for the browser
+//! integrations storing URLs as attributes. These go through the distinct values of the queried
+//! attribute, never through all the items. The integer attributes of the item schemas, like the
+//! port of the network passwords, may be compared too, the values not being integers never
+//! matching these comparisons.
+//!
+//! [Storage::search_text] looks for some text in the labels and in the values of all the
+//! attributes at once, for the users typing what they remember of an item, e.g. in
+//! `tks-cli search`.
+
+use crate::storage::collection::{Collection, Item};
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Size limit of the compiled client regular expressions
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// How [AttributeQuery] matches the values of an attribute
+#[derive(Debug, Clone)]
+pub enum AttributeMatch {
+    Exact(String),
+    Prefix(String),
+    Substring(String),
+    Regex(Regex),
+    /// Any value, the item only needs to have the attribute
+    Present,
+    LessThan(i64),
+    AtMost(i64),
+    GreaterThan(i64),
+    AtLeast(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct AttributeQuery {
+    pub name: String,
+    pub matcher: AttributeMatch,
+}
+
+impl AttributeQuery {
+    /// Parses a query of the Search1 interface; the value is ignored by the `present` operator,
+    /// and should be an integer for the `lt`, `le`, `gt` and `ge` ones
+    pub fn new(name: &str, operator: &str, value: &str) -> Result<AttributeQuery, TksError> {
+        let matcher = match operator {
+            "exact" => AttributeMatch::Exact(value.to_string()),
+            "prefix" => AttributeMatch::Prefix(value.to_string()),
+            "substring" => AttributeMatch::Substring(value.to_string()),
+            "regex" => AttributeMatch::Regex(
+                RegexBuilder::new(value)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(|_| TksError::ParameterError)?,
+            ),
+            "present" => AttributeMatch::Present,
+            "lt" => AttributeMatch::LessThan(parse_integer(value)?),
+            "le" => AttributeMatch::AtMost(parse_integer(value)?),
+            "gt" => AttributeMatch::GreaterThan(parse_integer(value)?),
+            "ge" => AttributeMatch::AtLeast(parse_integer(value)?),
+            _ => return Err(TksError::ParameterError),
+        };
+        Ok(AttributeQuery {
+            name: name.to_string(),
+            matcher,
+        })
+    }
+}
+
+impl AttributeMatch {
+    /// Matches the text case-insensitively, as a substring, or as a regular expression
+    pub fn text(text: &str, regex: bool) -> Result<AttributeMatch, TksError> {
+        let pattern = match regex {
+            true => Cow::Borrowed(text),
+            false => Cow::Owned(regex::escape(text)),
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map(AttributeMatch::Regex)
+            .map_err(|_| TksError::ParameterError)
+    }
+}
+
+fn parse_integer(value: &str) -> Result<i64, TksError> {
+    value.trim().parse().map_err(|_| TksError::ParameterError)
+}
+
+impl AttributeMatch {
+    fn matches(&self, value: &str) -> bool {
+        let integer = || value.trim().parse::<i64>().ok();
+        match self {
+            AttributeMatch::Exact(v) => value == v,
+            AttributeMatch::Prefix(p) => value.starts_with(p.as_str()),
+            AttributeMatch::Substring(s) => value.contains(s.as_str()),
+            AttributeMatch::Regex(r) => r.is_match(value),
+            AttributeMatch::Present => true,
+            AttributeMatch::LessThan(n) => integer().is_some_and(|v| v < *n),
+            AttributeMatch::AtMost(n) => integer().is_some_and(|v| v <= *n),
+            AttributeMatch::GreaterThan(n) => integer().is_some_and(|v| v > *n),
+            AttributeMatch::AtLeast(n) => integer().is_some_and(|v| v >= *n),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// Items by attribute name, then by attribute value
+    attributes: HashMap<String, HashMap<String, Vec<Uuid>>>,
+    /// Items by lowercase label, for the `label` queries
+    labels: HashMap<String, Vec<Uuid>>,
+    /// Position of each item in [Collection::items]
+    positions: HashMap<Uuid, usize>,
+}
+
+impl SearchIndex {
+    /// The items matching a single attribute. Users may look for `label` too, matching the
+    /// lowercase item labels, so they can find items lacking such attribute. The matches are
+    /// borrowed from the index, unless both of these matched.
+    fn lookup(&self, name: &str, value: &str) -> Cow<'_, [Uuid]> {
+        let attribute = self
+            .attributes
+            .get(name)
+            .and_then(|values| values.get(value))
+            .map_or(&[][..], Vec::as_slice);
+        let label = match name.eq_ignore_ascii_case("label") {
+            true => self.labels.get(value).map_or(&[][..], Vec::as_slice),
+            false => &[],
+        };
+        if label.is_empty() {
+            return Cow::Borrowed(attribute);
+        }
+        if attribute.is_empty() {
+            return Cow::Borrowed(label);
+        }
+        let mut uuids = attribute.to_vec();
+        uuids.extend(label.iter().filter(|uuid| !attribute.contains(uuid)));
+        Cow::Owned(uuids)
+    }
+
+    /// The items matching a single query; `label` queries match the lowercase item labels too
+    fn query(&self, query: &AttributeQuery) -> Cow<'_, [Uuid]> {
+        if let AttributeMatch::Exact(value) = &query.matcher {
+            return self.lookup(&query.name, value);
+        }
+        let mut uuids: Vec<Uuid> = self
+            .attributes
+            .get(&query.name)
+            .into_iter()
+            .flatten()
+            .filter(|(value, _)| query.matcher.matches(value))
+            .flat_map(|(_, uuids)| uuids.iter().copied())
+            .collect();
+        if query.name.eq_ignore_ascii_case("label") {
+            let mut seen: HashSet<Uuid> = uuids.iter().copied().collect();
+            for (label, label_uuids) in self.labels.iter() {
+                if query.matcher.matches(label) {
+                    uuids.extend(label_uuids.iter().filter(|u| seen.insert(**u)));
+                }
+            }
+        }
+        Cow::Owned(uuids)
+    }
+
+    /// The items whose label or any attribute value matches
+    fn text(&self, matcher: &AttributeMatch) -> Vec<Uuid> {
+        let mut seen = HashSet::new();
+        let values = self.attributes.values().flatten();
+        values
+            .chain(self.labels.iter())
+            .filter(|(value, _)| matcher.matches(value))
+            .flat_map(|(_, uuids)| uuids.iter().copied())
+            .filter(|uuid| seen.insert(*uuid))
+            .collect()
+    }
+}
+
+impl Collection {
+    pub(crate) fn index_attributes(&mut self) {
+        let mut index = SearchIndex::default();
+        for (position, item) in self.items.iter().enumerate() {
+            for (name, value) in item.attributes() {
+                // the names repeat across the items, only allocate them once
+                if !index.attributes.contains_key(name) {
+                    index.attributes.insert(name.to_string(), HashMap::new());
+                }
+                let values = index.attributes.get_mut(name).unwrap();
+                match values.get_mut(value) {
+                    Some(uuids) => uuids.push(item.id.uuid),
+                    None => {
+                        values.insert(value.to_string(), vec![item.id.uuid]);
+                    }
+                }
+            }
+            index
+                .labels
+                .entry(item.label.to_lowercase())
+                .or_default()
+                .push(item.id.uuid);
+            index.positions.insert(item.id.uuid, position);
+        }
+        self.search_index = index;
+    }
+
+    /// The items having all the given attributes; no attributes match all the items
+    pub fn search(&self, attributes: &HashMap<String, String>) -> Vec<&Item> {
+        self.intersect(
+            attributes
+                .iter()
+                .map(|(name, value)| self.search_index.lookup(name, value))
+                .collect(),
+        )
+    }
+
+    /// The items matching all the given queries; no queries match all the items
+    pub fn query(&self, queries: &[AttributeQuery]) -> Vec<&Item> {
+        self.intersect(queries.iter().map(|q| self.search_index.query(q)).collect())
+    }
+
+    /// The items whose label or any attribute value matches
+    pub fn search_text(&self, matcher: &AttributeMatch) -> Vec<&Item> {
+        let uuids = self.search_index.text(matcher);
+        uuids.iter().filter_map(|uuid| self.indexed_item(uuid)).collect()
+    }
+
+    /// The items found in all the given matches
+    fn intersect(&self, mut matches: Vec<Cow<[Uuid]>>) -> Vec<&Item> {
+        if matches.is_empty() {
+            return self.items.iter().collect();
+        }
+        // the rarest attribute has the fewest candidates to check against the others
+        matches.sort_by_key(|m| m.len());
+        let (candidates, others) = matches.split_first().unwrap();
+        let others: Vec<HashSet<&Uuid>> = others.iter().map(|m| m.iter().collect()).collect();
+        candidates
+            .iter()
+            .filter(|uuid| others.iter().all(|m| m.contains(uuid)))
+            .filter_map(|uuid| self.indexed_item(uuid))
+            .collect()
+    }
+
+    /// The item, found through the index rather than by going through all the items
+    pub(crate) fn indexed_item(&self, uuid: &Uuid) -> Option<&Item> {
+        let position = self.search_index.positions.get(uuid)?;
+        match self.items.get(*position) {
+            Some(item) if item.id.uuid == *uuid => Some(item),
+            // the items changed without the collection getting saved, e.g. upon a rollback
+            _ => self.items.iter().find(|i| i.id.uuid == *uuid),
+        }
+    }
+}
+
+impl Storage {
+    /// The items of all the collections having all the given attributes
+    pub fn search_items(&self, attributes: &HashMap<String, String>) -> Vec<&Item> {
+        self.collections
+            .iter()
+            .flat_map(|c| c.search(attributes))
+            .collect()
+    }
+
+    /// The items of all the collections matching all the given queries
+    pub fn query_items(&self, queries: &[AttributeQuery]) -> Vec<&Item> {
+        self.collections
+            .iter()
+            .flat_map(|c| c.query(queries))
+            .collect()
+    }
+
+    /// The items of all the collections whose label or any attribute value matches
+    pub fn search_text(&self, matcher: &AttributeMatch) -> Vec<&Item> {
+        self.collections
+            .iter()
+            .flat_map(|c| c.search_text(matcher))
+            .collect()
+    }
+}
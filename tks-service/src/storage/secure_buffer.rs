@@ -0,0 +1,172 @@
+//! Memory for the secrets the service holds: the decrypted item secrets, the storage key and the
+//! session keys. The buffers get their own pages, which are locked in RAM when the limits allow it
+//! so they don't get swapped out, and left out of the core dumps. The contents get zeroed before
+//! the pages are given back.
+//!
+//! The buffers never grow, so no copy of the secret gets left behind by a reallocation.
+
+use log::debug;
+use secrecy::zeroize::Zeroize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Logs the first failure to lock the memory only, as it then usually fails for all the buffers
+static MLOCK_FAILED: AtomicBool = AtomicBool::new(false);
+
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// A fixed size byte buffer, see the module documentation
+pub struct SecureBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    /// The pages holding the buffer; empty buffers have none
+    layout: Option<Layout>,
+    locked: bool,
+}
+
+// SAFETY: the buffer owns its memory, like a Vec<u8>
+unsafe impl Send for SecureBuffer {}
+unsafe impl Sync for SecureBuffer {}
+
+impl SecureBuffer {
+    /// A zeroed buffer of `len` bytes
+    pub fn new(len: usize) -> SecureBuffer {
+        if len == 0 {
+            return SecureBuffer {
+                ptr: NonNull::dangling(),
+                len,
+                layout: None,
+                locked: false,
+            };
+        }
+        let page_size = page_size();
+        let size = len.div_ceil(page_size) * page_size;
+        let layout = Layout::from_size_align(size, page_size).expect("secure buffer too large");
+        // SAFETY: the layout has a non zero size
+        let ptr = NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        // SAFETY: the pages were just allocated for this buffer only
+        let locked = unsafe { libc::mlock(ptr.as_ptr() as *const libc::c_void, size) } == 0;
+        if !locked && !MLOCK_FAILED.swap(true, Ordering::Relaxed) {
+            debug!(
+                "Cannot lock the secrets in memory, they may get swapped out: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        #[cfg(target_os = "linux")]
+        // SAFETY: as above; not being left out of the core dumps is no reason to fail
+        unsafe {
+            libc::madvise(ptr.as_ptr() as *mut libc::c_void, size, libc::MADV_DONTDUMP);
+        }
+        SecureBuffer {
+            ptr,
+            len,
+            layout: Some(layout),
+            locked,
+        }
+    }
+
+    /// Whether the buffer is locked in RAM
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Drop for SecureBuffer {
+    fn drop(&mut self) {
+        let Some(layout) = self.layout else {
+            return;
+        };
+        // SAFETY: the pages were allocated by new() with this layout and are owned by the buffer
+        unsafe {
+            std::slice::from_raw_parts_mut(self.ptr.as_ptr(), layout.size()).zeroize();
+            if self.locked {
+                libc::munlock(self.ptr.as_ptr() as *const libc::c_void, layout.size());
+            }
+            alloc::dealloc(self.ptr.as_ptr(), layout);
+        }
+    }
+}
+
+impl Deref for SecureBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr points to len initialized bytes, or is dangling with a zero len
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for SecureBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for deref
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for SecureBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<&[u8]> for SecureBuffer {
+    fn from(data: &[u8]) -> SecureBuffer {
+        let mut buffer = SecureBuffer::new(data.len());
+        buffer.copy_from_slice(data);
+        buffer
+    }
+}
+
+/// Takes the contents of the vector, which gets zeroed
+impl From<Vec<u8>> for SecureBuffer {
+    fn from(mut data: Vec<u8>) -> SecureBuffer {
+        let buffer = SecureBuffer::from(data.as_slice());
+        data.zeroize();
+        buffer
+    }
+}
+
+impl Clone for SecureBuffer {
+    fn clone(&self) -> SecureBuffer {
+        SecureBuffer::from(&**self)
+    }
+}
+
+impl PartialEq for SecureBuffer {
+    fn eq(&self, other: &SecureBuffer) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for SecureBuffer {}
+
+/// Never shows the contents
+impl fmt::Debug for SecureBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecureBuffer({} bytes)", self.len)
+    }
+}
+
+/// Serialized like a `Vec<u8>`, so the stored secrets keep their format
+impl Serialize for SecureBuffer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecureBuffer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SecureBuffer, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(SecureBuffer::from)
+    }
+}
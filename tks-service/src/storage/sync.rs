@@ -0,0 +1,542 @@
+//! Sharing the collections between the machines of a user, through a directory a sync tool, e.g.
+//! Syncthing or Nextcloud, keeps in sync: a `[[storage.mounts]]` backend having `sync = true`.
+//! Only the encrypted files get synced, the backend keeps each secret in its own item file, so
+//! the sync tool only sees the items changing. The key is the one of the backend, so each machine
+//! unlocks the collections with the same password.
+//!
+//! Each save of a synced collection bumps the counter of the machine in its version vector, the
+//! `versions` of its metadata. Comparing the vector of a file with the one of the collection in
+//! memory tells whether the other machines wrote a newer version, which then gets loaded, or
+//! whether both machines changed the collection meanwhile. Saving over a newer version gets
+//! refused with [TksError::SyncConflict], so the changes of the other machines never get lost.
+//!
+//! Both machines having changed the collection, the sync tool keeps one of the versions as the
+//! file, and the other as a conflict copy next to it, or the version in memory has changes still
+//! to be written: the user gets asked whether to merge both versions. Otherwise the version of
+//! this machine wins; the other one gets kept in the [CONFLICTS_DIR] directory of the backend
+//! either way. Merging keeps the items of both versions, the newest one of each item changed on
+//! both sides, and drops the ones deleted on either side since they got changed.
+
+use crate::storage::collection::{Collection, Item};
+use crate::storage::history::HistoryEntry;
+use crate::storage::{schema, Storage, STORAGE};
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::fdo::service::{
+    OrgFreedesktopSecretServiceCollectionCreated, OrgFreedesktopSecretServiceCollectionDeleted,
+};
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::prompter;
+use crate::tks_dbus::service_impl::{ServiceHandle, ServiceImpl};
+use crate::tks_dbus::{alias_registry, DBusHandle, MESSAGE_SENDER};
+use crate::tks_error::TksError;
+use dbus::message::SignalArgs;
+use lazy_static::lazy_static;
+use log::{debug, error, info, trace, warn};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Directory of the backends receiving the versions not kept by the conflict resolution
+pub const CONFLICTS_DIR: &str = "sync-conflicts";
+
+/// How often the synced files get compared with the collections in memory
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The item files no item knows get removed once this old, as the sync tool may deliver them
+/// before the metadata listing their item
+pub(crate) const UNKNOWN_FILE_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Whether the item file is older than [UNKNOWN_FILE_GRACE]
+pub(crate) fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > UNKNOWN_FILE_GRACE)
+}
+
+lazy_static! {
+    static ref MACHINE_ID: String = read_machine_id();
+}
+
+/// The name of this machine in the version vectors
+pub fn machine_id() -> String {
+    MACHINE_ID.clone()
+}
+
+fn read_machine_id() -> String {
+    [
+        "/etc/machine-id",
+        "/var/lib/dbus/machine-id",
+        "/proc/sys/kernel/hostname",
+    ]
+    .iter()
+    .filter_map(|path| fs::read_to_string(path).ok())
+    .map(|id| id.trim().to_string())
+    .find(|id| !id.is_empty())
+    .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// How a version vector relates to another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Causality {
+    Equal,
+    /// The other version has all the saves of this one, and more
+    Before,
+    /// This version has all the saves of the other one, and more
+    After,
+    /// Each version has saves the other one lacks
+    Concurrent,
+}
+
+pub fn compare(ours: &BTreeMap<String, u64>, theirs: &BTreeMap<String, u64>) -> Causality {
+    let count = |versions: &BTreeMap<String, u64>, machine: &String| {
+        versions.get(machine).copied().unwrap_or_default()
+    };
+    let machines: HashSet<&String> = ours.keys().chain(theirs.keys()).collect();
+    let ahead = machines.iter().any(|m| count(ours, m) > count(theirs, m));
+    let behind = machines.iter().any(|m| count(ours, m) < count(theirs, m));
+    match (ahead, behind) {
+        (false, false) => Causality::Equal,
+        (false, true) => Causality::Before,
+        (true, false) => Causality::After,
+        (true, true) => Causality::Concurrent,
+    }
+}
+
+/// The version having the saves of both
+pub fn merge_versions(
+    ours: &BTreeMap<String, u64>,
+    theirs: &BTreeMap<String, u64>,
+) -> BTreeMap<String, u64> {
+    let mut merged = ours.clone();
+    for (machine, count) in theirs {
+        let entry = merged.entry(machine.clone()).or_default();
+        *entry = (*entry).max(*count);
+    }
+    merged
+}
+
+/// The file name of the collection file a sync tool made a conflict copy of: Syncthing's
+/// `<name>.sync-conflict-<date>-<time>-<device>`, Nextcloud's `<name> (conflicted copy <date>)`
+/// or Dropbox's `<name> (<host>'s conflicted copy <date>)`
+pub fn conflicted_original(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    if let Some(index) = name.find(".sync-conflict-") {
+        return Some(name[..index].to_string());
+    }
+    let index = name.find(" (")?;
+    name[index..]
+        .contains("conflicted copy")
+        .then(|| name[..index].to_string())
+}
+
+/// The path within the backend, the same on all the machines
+pub(crate) fn relative_path(path: &Path) -> String {
+    let parts: Vec<_> = path.iter().rev().take(2).collect();
+    parts
+        .iter()
+        .rev()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Merges the items the other version has into the collection; the label, aliases and
+/// properties stay the ones of the collection
+pub fn merge_collection(ours: &mut Collection, theirs: &Collection) {
+    let deleted_after = |trash_owner: &Collection, item: &Item| {
+        trash_owner
+            .trash
+            .iter()
+            .any(|t| t.item.id.uuid == item.id.uuid && t.deleted >= item.modified)
+    };
+    ours.items.retain(|i| !deleted_after(theirs, i));
+    for item in &theirs.items {
+        if deleted_after(ours, item) {
+            continue;
+        }
+        match ours.items.iter_mut().find(|i| i.id.uuid == item.id.uuid) {
+            Some(mine) if mine.modified >= item.modified => {}
+            Some(mine) => {
+                trace!("Taking the other version of item {}", item.id.uuid);
+                *mine = item.clone();
+                mine.id.collection_uuid = ours.uuid;
+            }
+            None => {
+                trace!("Taking item {} of the other version", item.id.uuid);
+                let mut item = item.clone();
+                item.id.collection_uuid = ours.uuid;
+                ours.items.push(item);
+            }
+        }
+    }
+    for trashed in &theirs.trash {
+        let known = ours
+            .trash
+            .iter()
+            .any(|t| t.item.id.uuid == trashed.item.id.uuid);
+        let restored = ours.items.iter().any(|i| i.id.uuid == trashed.item.id.uuid);
+        if !known && !restored {
+            ours.trash.push(trashed.clone());
+        }
+    }
+    ours.trash.sort_by_key(|t| t.deleted);
+    let mut history: Vec<HistoryEntry> = ours.history.drain(..).collect();
+    for entry in &theirs.history {
+        if !history.contains(entry) {
+            history.push(entry.clone());
+        }
+    }
+    history.sort_by_key(|e| e.time);
+    let excess = history
+        .len()
+        .saturating_sub(crate::storage::history::HISTORY_LENGTH);
+    ours.history = history.into_iter().skip(excess).collect();
+    ours.versions = merge_versions(&ours.versions, &theirs.versions);
+    ours.index_folders();
+    ours.index_attributes();
+}
+
+/// A version of a synced collection written by another machine, concurrent with the one in
+/// memory
+#[derive(Debug, Clone)]
+pub struct SyncConflict {
+    pub collection: Uuid,
+    pub label: String,
+    /// The file holding the other version: the collection file, or a conflict copy of it
+    pub path: PathBuf,
+}
+
+/// What comparing the synced files with the collections in memory found
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// The collections another machine created
+    pub added: Vec<Uuid>,
+    /// The collections another machine deleted
+    pub removed: Vec<Uuid>,
+    /// The collections loaded again, as another machine wrote a newer version, with the items
+    /// gone and the items new
+    pub updated: Vec<(Uuid, Vec<Uuid>, Vec<Uuid>)>,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+/// The part of the metadata telling its version
+#[derive(Deserialize)]
+struct Versions {
+    #[serde(default)]
+    versions: BTreeMap<String, u64>,
+}
+
+impl Storage {
+    /// Refuses to save a synced collection over a version it doesn't have all the saves of
+    pub(crate) fn check_not_behind(&self, uuid: &Uuid) -> Result<(), TksError> {
+        let Some(collection) = self.collections.iter().find(|c| c.uuid == *uuid) else {
+            return Ok(());
+        };
+        if !collection.synced || !collection.path.exists() {
+            return Ok(());
+        }
+        let written: Versions = schema::parse_metadata(&fs::read_to_string(&collection.path)?)?;
+        match compare(&collection.versions, &written.versions) {
+            Causality::Equal | Causality::After => Ok(()),
+            _ => {
+                warn!(
+                    "Not saving collection '{}', another machine wrote it meanwhile",
+                    collection.name
+                );
+                Err(TksError::SyncConflict(collection.label().to_string()))
+            }
+        }
+    }
+
+    /// Compares the files of the synced backends with the collections in memory: the versions
+    /// written by other machines get loaded, unless they conflict with the one in memory
+    pub fn check_sync(&mut self) -> Result<SyncReport, TksError> {
+        let mut report = SyncReport::default();
+        for mount in 0..self.mounts.len() {
+            if self.mounts[mount].synced {
+                self.check_mount(mount, &mut report)?;
+            }
+        }
+        Ok(report)
+    }
+
+    fn check_mount(&mut self, mount: usize, report: &mut SyncReport) -> Result<(), TksError> {
+        let mut written = HashSet::new();
+        // the changes yet to be written don't get overwritten by the other machines
+        let dirty = self.dirty.lock().unwrap().clone();
+        for path in self.mounts[mount].backend.get_metadata_paths()? {
+            // the file may be half synced yet
+            let theirs = match Storage::load_collection(&path) {
+                Ok(theirs) => theirs,
+                Err(e) => {
+                    debug!("Skipping {:?} for now: {}", path, e);
+                    continue;
+                }
+            };
+            let copy = conflicted_original(&path).is_some();
+            if !copy {
+                written.insert(theirs.uuid);
+            }
+            let Some(index) = self.collections.iter().position(|c| c.uuid == theirs.uuid) else {
+                if !copy {
+                    info!(
+                        "Loading collection '{}' created on another machine",
+                        theirs.name
+                    );
+                    report.added.push(theirs.uuid);
+                    self.add_synced(mount, theirs)?;
+                }
+                continue;
+            };
+            let ours = &self.collections[index];
+            match (compare(&ours.versions, &theirs.versions), copy) {
+                (Causality::Equal | Causality::After, false) => {}
+                (Causality::Equal | Causality::After, true) => {
+                    debug!(
+                        "Setting aside {:?}, the version in memory has its changes",
+                        path
+                    );
+                    self.set_aside(mount, &path, false)?;
+                }
+                (Causality::Before, false) if !dirty.contains(&theirs.uuid) => {
+                    info!(
+                        "Loading collection '{}' written on another machine",
+                        theirs.name
+                    );
+                    report.updated.push(self.fast_forward(index, theirs)?);
+                }
+                _ => report.conflicts.push(SyncConflict {
+                    collection: ours.uuid,
+                    label: ours.label().to_string(),
+                    path,
+                }),
+            }
+        }
+        let deleted: Vec<Uuid> = self
+            .collections
+            .iter()
+            .filter(|c| c.mount == mount && !written.contains(&c.uuid))
+            .filter(|c| !c.path.exists() && !dirty.contains(&c.uuid))
+            .map(|c| c.uuid)
+            .collect();
+        for uuid in deleted {
+            info!("Collection '{}' got deleted on another machine", uuid);
+            self.collections.retain(|c| c.uuid != uuid);
+            report.removed.push(uuid);
+        }
+        Ok(())
+    }
+
+    fn add_synced(&mut self, mount: usize, mut collection: Collection) -> Result<(), TksError> {
+        collection.mount = mount;
+        collection.synced = true;
+        collection.default = false;
+        collection.items_path = self.mounts[mount]
+            .backend
+            .collection_items_path(&collection.name)?;
+        self.collections.push(collection);
+        Ok(())
+    }
+
+    /// Replaces the collection by the newer version, unlocking it again if it was
+    fn fast_forward(
+        &mut self,
+        index: usize,
+        mut theirs: Collection,
+    ) -> Result<(Uuid, Vec<Uuid>, Vec<Uuid>), TksError> {
+        let ours = &self.collections[index];
+        let uuids =
+            |c: &Collection| -> HashSet<Uuid> { c.stored_items().map(|i| i.id.uuid).collect() };
+        let (before, after) = (uuids(ours), uuids(&theirs));
+        let gone = before.difference(&after).copied().collect();
+        let new = after.difference(&before).copied().collect();
+        let unlocked = !ours.locked;
+        theirs.mount = ours.mount;
+        theirs.synced = true;
+        theirs.default = ours.default;
+        theirs.items_path = ours.items_path.clone();
+        let uuid = theirs.uuid;
+        self.collections[index] = theirs;
+        if unlocked {
+            self.unlock_collection(&uuid)?;
+        }
+        Ok((uuid, gone, new))
+    }
+
+    /// Resolves the conflict, merging the other version into the one in memory, or only keeping
+    /// the one in memory; the other one gets kept in the [CONFLICTS_DIR] directory
+    pub fn resolve_sync_conflict(
+        &mut self,
+        conflict: &SyncConflict,
+        merge: bool,
+    ) -> Result<(), TksError> {
+        debug!(
+            "Resolving the conflict of '{}' with {:?} (merge: {})",
+            conflict.label, conflict.path, merge
+        );
+        let mount = self.mount_of(&conflict.collection)?;
+        let theirs = Storage::load_collection(&conflict.path)?;
+        let collection = self
+            .collections
+            .iter_mut()
+            .find(|c| c.uuid == conflict.collection)
+            .ok_or(TksError::NotFound(None))?;
+        match merge {
+            true => merge_collection(collection, &theirs),
+            false => collection.versions = merge_versions(&collection.versions, &theirs.versions),
+        }
+        let unlocked = !collection.locked;
+        let copy = conflicted_original(&conflict.path).is_some();
+        self.set_aside(mount, &conflict.path, !copy)?;
+        self.save_collection(&conflict.collection, false)?;
+        if merge && unlocked {
+            // the secrets of the other version's items are in their item files
+            let collection = self
+                .collections
+                .iter_mut()
+                .find(|c| c.uuid == conflict.collection)
+                .ok_or(TksError::NotFound(None))?;
+            collection.locked = true;
+            self.unlock_collection(&conflict.collection)?;
+        }
+        Ok(())
+    }
+
+    /// Moves, or copies, the file of a version not kept into the [CONFLICTS_DIR] directory
+    fn set_aside(&self, mount: usize, path: &Path, copy: bool) -> Result<(), TksError> {
+        let Some(root) = self.mounts[mount].backend.root_path() else {
+            return Ok(());
+        };
+        let dir = root.join(CONFLICTS_DIR);
+        fs::DirBuilder::new().recursive(true).create(&dir)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let target = dir.join(format!("{}.{}", name, timestamp()));
+        match copy {
+            true => fs::copy(path, &target).map(|_| ())?,
+            false => fs::rename(path, &target)?,
+        }
+        info!("Kept the version {:?} in {:?}", path, target);
+        Ok(())
+    }
+
+    /// Starts comparing the synced files with the collections in memory, in the background, if
+    /// any backend gets synced
+    pub fn start_sync() {
+        if !STORAGE.read().unwrap().mounts.iter().any(|m| m.synced) {
+            return;
+        }
+        debug!("Checking the synced collections as {}", machine_id());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let report = match STORAGE.write().unwrap().check_sync() {
+                    Ok(report) => report,
+                    Err(e) => {
+                        error!("Cannot check the synced collections: {}", e);
+                        continue;
+                    }
+                };
+                announce(&report);
+                for conflict in report.conflicts {
+                    // the dialogs may take a while, the clients keep being served meanwhile
+                    let asked = tokio::task::spawn_blocking(move || ask(conflict)).await;
+                    if let Err(e) = asked {
+                        error!("Cannot ask about the synced collection: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Lets the clients know about the collections the other machines changed
+fn announce(report: &SyncReport) {
+    for uuid in &report.added {
+        let path = CollectionImpl::from(uuid).canonical_path();
+        tokio::spawn(async move {
+            debug!("Sending CollectionCreated signal");
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretServiceCollectionCreated {
+                    collection: path.clone(),
+                }
+                .to_emit_message(&path),
+            );
+        });
+    }
+    for uuid in &report.removed {
+        let Some(handle) = CollectionImpl::unregister(uuid) else {
+            continue;
+        };
+        let collection = handle.canonical_path();
+        tokio::spawn(async move {
+            debug!("Sending CollectionDeleted signal");
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretServiceCollectionDeleted { collection }
+                    .to_emit_message(&ServiceHandle {}.path().into()),
+            );
+        });
+    }
+    if !report.added.is_empty() || !report.removed.is_empty() {
+        alias_registry::update(&STORAGE.read().unwrap());
+    }
+    for (uuid, gone, new) in &report.updated {
+        let item_id = |item: &Uuid| crate::storage::collection::ItemId {
+            uuid: *item,
+            collection_uuid: *uuid,
+        };
+        gone.iter()
+            .map(item_id)
+            .for_each(|id| ItemImpl::unregister(&id));
+        new.iter()
+            .map(item_id)
+            .for_each(|id| ItemImpl::register(&id));
+        CollectionImpl::emit_properties_changed(*uuid, &["Items", "Label", "Modified"]);
+        CollectionImpl::emit_sequence_changed(*uuid);
+        ServiceImpl::emit_collection_changed(*uuid);
+    }
+}
+
+/// Asks the user whether to merge the versions, then resolves the conflict
+fn ask(conflict: SyncConflict) {
+    let message = format!(
+        "Collection '{}' got changed on this machine and on another one. Merge both versions? \
+         Otherwise the version of this machine is kept, the other one being set aside in the \
+         {} directory.",
+        conflict.label, CONFLICTS_DIR
+    );
+    let merge = match prompter::current().confirm("Merge", "Keep this version", &message) {
+        Ok(merge) => merge,
+        Err(e) => {
+            // asked again upon the next check
+            error!(
+                "Cannot ask about the conflict of '{}': {}",
+                conflict.label, e
+            );
+            return;
+        }
+    };
+    let mut storage = STORAGE.write().unwrap();
+    let resolved = storage.resolve_sync_conflict(&conflict, merge);
+    drop(storage);
+    match resolved {
+        Ok(()) => announce(&SyncReport {
+            updated: vec![(conflict.collection, Vec::new(), Vec::new())],
+            ..Default::default()
+        }),
+        Err(e) => error!("Cannot resolve the conflict of '{}': {}", conflict.label, e),
+    }
+}
+
+/// Suffix keeping the versions set aside apart
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
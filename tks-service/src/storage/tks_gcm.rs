@@ -1,27 +1,64 @@
 //!
 //! Tks specific backend using the AES/GCM item secrets encryption
 //!
-use crate::settings::{Settings, Storage};
+use crate::settings::Storage;
+use crate::storage::aead_file;
 use crate::storage::collection::Collection;
+use crate::storage::checksums;
+use crate::storage::file_ops;
+use crate::storage::permissions;
+use crate::storage::fsck::StoredSecrets;
+use crate::storage::schema;
+use crate::storage::sync;
 use crate::storage::tks_gcm::TksGcmPasswordSecretHandlerState::{
     KeyAvailable, Locked, NotCommissioned,
 };
-use crate::storage::{SecretsHandler, StorageBackend, StorageBackendType, STORAGE};
-use crate::tks_dbus::prompt_impl::{PromptAction, PromptDialog};
+use crate::storage::collection::ItemData;
+use crate::storage::secure_buffer::SecureBuffer;
+use crate::storage::{
+    CollectionSecrets, SecretsHandler, StorageBackend, StorageBackendType, KDF_ITERATIONS,
+    STORAGE,
+};
+use crate::tks_dbus::prompt_impl::{PassphraseActionParam, PromptAction, PromptDialog};
 use crate::tks_error::TksError;
-use log::{debug, trace};
-use openssl::rand::rand_bytes;
+use log::{debug, error, trace, warn};
 use openssl::sha::Sha256;
-use openssl::symm::decrypt_aead;
+use secrecy::zeroize::Zeroize;
 use secrecy::{ExposeSecret, SecretString};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use std::{cmp::PartialEq, ffi::OsString, fs, path::Path, path::PathBuf};
 use uuid::Uuid;
 use StorageBackendType::TksGcm;
 
 pub struct TksGcmBackend {
+    root_path: PathBuf,
     metadata_path: OsString,
     items_path: OsString,
+    /// Key files are given access to the key through key slots, each holding the key encrypted
+    /// with a key derived from the key file
+    keyslots_path: OsString,
+    keyfiles: HashMap<String, PathBuf>,
     secrets_handler: TksGcmPasswordSecretHandler,
+    /// Each item's secret goes to its own file under the `<collection>.d` directory, so saves
+    /// only rewrite the changed items and a damaged file only loses one item
+    per_item_files: bool,
+    /// Item files get padded to bucketed sizes, see [pad], so their size doesn't tell the length
+    /// of their secret
+    pad_item_files: bool,
+    /// Digests of the item secrets as they were last read or written, by item file path
+    item_digests: Mutex<HashMap<PathBuf, [u8; 32]>>,
+    /// The files of the collection being saved, see [StorageBackend::begin_save]
+    save: Mutex<Option<file_ops::Group>>,
+    /// New collections get a key of their own, see [TksGcmBackend::collection_keys]
+    per_collection_keys: bool,
+    /// Holds the salt and the commissioned data of the collection keys, a directory each
+    keys_path: PathBuf,
+    /// The keys of the collections having their own password, by collection; unlocking one of
+    /// them leaves the other collections locked, the way KWallet has a password per wallet. The
+    /// other collections share `secrets_handler`, which also encrypts the private files.
+    collection_keys: HashMap<Uuid, TksGcmPasswordSecretHandler>,
 }
 
 #[derive(PartialEq)]
@@ -39,18 +76,17 @@ struct TksGcmPasswordSecretHandler {
     salt: Vec<u8>,
     commissioned_data: Vec<u8>,
     commissioned_data_path: OsString,
-    key: Vec<u8>,
+    key: SecureBuffer,
     cipher: openssl::symm::Cipher,
+    /// When the key got derived and checked, see [crate::storage::key_cache]
+    derived_at: Option<Instant>,
 }
 impl TksGcmBackend {
     pub(crate) fn new(settings: Storage) -> Result<TksGcmBackend, TksError> {
-        let path = settings.path.unwrap_or({
-            xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?
-                .create_data_directory("storage")?
-                .to_string_lossy()
-                .to_string()
-        });
+        let path = settings.resolved_path()?.to_string_lossy().to_string();
         trace!("Initializing TksGcmBackend with {:?}", path);
+        // the directories below get created with the default mode, but inside this one
+        permissions::create_private_dir(Path::new(&path))?;
         let mut metadata_path = PathBuf::from(path.clone());
         metadata_path.push("metadata");
         let _ = fs::DirBuilder::new()
@@ -64,50 +100,50 @@ impl TksGcmBackend {
             .recursive(true)
             .create(items_path.clone())?;
 
-        let mut salt_file_path = PathBuf::from(path.clone());
-        salt_file_path.push("salt");
-        let salt_check = Path::new(&salt_file_path).exists();
-        let secret_state: TksGcmPasswordSecretHandlerState;
-        let salt = if !salt_check {
-            trace!("Initializing salt file {:?}", salt_file_path);
-            // upon the very first initialization, generate a random salt
-            let mut salt = vec![0u8; 256];
-            openssl::rand::rand_bytes(&mut salt)?;
-            fs::write(salt_file_path, salt.clone())?;
-            salt
-        } else {
-            trace!("Reading salt file {:?}", salt_file_path);
-            fs::read(salt_file_path.clone())?
-        };
+        let mut keyslots_path = PathBuf::from(path.clone());
+        keyslots_path.push("keyslots");
+        fs::DirBuilder::new()
+            .recursive(true)
+            .create(keyslots_path.clone())?;
 
-        let mut commissioned_data_path = PathBuf::from(path.clone());
-        commissioned_data_path.push("commissioned");
-        let commissioned_data_check = Path::new(&commissioned_data_path).exists();
+        // temporary files left by a crash would otherwise get loaded as collections
+        let interrupted = file_ops::recover(&path)?;
+        if !interrupted.is_empty() {
+            warn!("Recovered from {} interrupted writes", interrupted.len());
+        }
 
-        let commissioned_data = if !commissioned_data_check {
-            trace!("Initializing commissioned data {}", &commissioned_data_path.display());
-            let mut commissioned_data = vec![0u8; 256];
-            openssl::rand::rand_bytes(&mut commissioned_data)?;
-            // we still need to wait for the password so we are still not commissioned
-            secret_state = TksGcmPasswordSecretHandlerState::NotCommissioned;
-            commissioned_data
-        } else {
-            trace!("Reading commissioned data {}", &commissioned_data_path.display());
-            secret_state = TksGcmPasswordSecretHandlerState::Locked;
-            fs::read(commissioned_data_path.clone())?
-        };
+        let mut keys_path = PathBuf::from(path.clone());
+        keys_path.push("keys");
+        fs::DirBuilder::new().recursive(true).create(&keys_path)?;
+        let mut collection_keys = HashMap::new();
+        for entry in fs::read_dir(&keys_path)? {
+            let dir = entry?.path();
+            match dir.file_name().and_then(|n| Uuid::parse_str(&n.to_string_lossy()).ok()) {
+                Some(uuid) if dir.is_dir() => {
+                    collection_keys.insert(uuid, TksGcmPasswordSecretHandler::open(&dir)?);
+                }
+                _ => debug!("Ignoring {:?}, not the key of a collection", dir),
+            }
+        }
 
         let backend = TksGcmBackend {
+            root_path: PathBuf::from(&path),
             metadata_path: metadata_path.into(),
             items_path: items_path.into(),
-            secrets_handler: TksGcmPasswordSecretHandler {
-                state: secret_state,
-                salt,
-                commissioned_data,
-                commissioned_data_path: commissioned_data_path.into(),
-                key: vec![0u8; 32],
-                cipher: openssl::symm::Cipher::aes_256_gcm(),
-            },
+            keyslots_path: keyslots_path.into(),
+            keyfiles: settings
+                .keyfiles
+                .into_iter()
+                .map(|(name, path)| (name, PathBuf::from(path)))
+                .collect(),
+            per_item_files: settings.per_item_files,
+            pad_item_files: settings.pad_item_files,
+            item_digests: Mutex::new(HashMap::new()),
+            save: Mutex::new(None),
+            secrets_handler: TksGcmPasswordSecretHandler::open(Path::new(&path))?,
+            per_collection_keys: settings.per_collection_keys,
+            keys_path,
+            collection_keys,
         };
         Ok(backend)
     }
@@ -144,10 +180,127 @@ impl StorageBackend for TksGcmBackend {
         Ok(items_path)
     }
 
+    fn checksum_key(&self, collection: &Uuid) -> Result<Vec<u8>, TksError> {
+        self.handler_of(collection).checksum_key(collection)
+    }
+    fn root_path(&self) -> Option<PathBuf> {
+        Some(self.root_path.clone())
+    }
+    fn change_password(
+        &mut self,
+        collection: &Uuid,
+        collections: &[(&Collection, String)],
+        old: &SecretString,
+        new: &SecretString,
+    ) -> Result<(), TksError> {
+        let handler = self.handler_of(collection);
+        if handler.state == NotCommissioned {
+            return Err(TksError::NotSupported("changing a password not defined yet"));
+        }
+        let old_key = handler.derive_key(old.expose_secret().as_bytes())?;
+        let new_key = handler.derive_key(new.expose_secret().as_bytes())?;
+        let commissioned_path = PathBuf::from(&handler.commissioned_data_path);
+        let metadata = commissioned_path.to_str().unwrap();
+        let commissioned = fs::read(&commissioned_path)?;
+        let commissioned_data = handler
+            .decrypt_aead_with(&old_key, metadata, &commissioned)
+            .map_err(|_| TksError::PermissionDenied)?;
+
+        // nothing gets written until all the files got encrypted with the new key
+        let mut rewrites = Vec::new();
+        for (path, aad, private) in self.files_of_key(collection, collections)? {
+            let encrypted = fs::read(&path)?;
+            let mut plain = match handler.decrypt_aead_with(&old_key, &aad, &encrypted) {
+                Ok(plain) => plain,
+                Err(_) if private => {
+                    debug!("Leaving {:?} alone, the key didn't encrypt it", path);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let reencrypted = handler.encrypt_aead_with(&new_key, &aad, &plain);
+            plain.zeroize();
+            rewrites.push((path, encrypted, reencrypted?));
+        }
+        if !self.has_own_key(collection) {
+            for (name, keyfile) in &self.keyfiles {
+                let keyslot_path = self.keyslot_path(name);
+                if !keyslot_path.exists() {
+                    continue;
+                }
+                let Ok(keyfile_data) = fs::read(keyfile) else {
+                    warn!("Key file {:?} not found, its key slot keeps the old key", keyfile);
+                    continue;
+                };
+                let keyslot = handler.keyslot(&new_key, &keyfile_data, &keyslot_path)?;
+                rewrites.push((keyslot_path.clone(), fs::read(&keyslot_path)?, keyslot));
+            }
+        }
+        // the commissioned data tells which password is the right one, so it goes last
+        let reencrypted = handler.encrypt_aead_with(&new_key, metadata, &commissioned_data)?;
+        rewrites.push((commissioned_path, commissioned, reencrypted));
+
+        for (i, (path, _, reencrypted)) in rewrites.iter().enumerate() {
+            trace!("Encrypting {:?} with the new key", path);
+            if let Err(e) = file_ops::write(path, reencrypted) {
+                error!("Cannot write {:?}, restoring the old key: {}", path, e);
+                for (path, encrypted, _) in &rewrites[..i] {
+                    if let Err(e) = file_ops::write(path, encrypted) {
+                        error!("Cannot restore {:?}: {}", path, e);
+                    }
+                }
+                return Err(e.into());
+            }
+        }
+        let handler = self.handler_of_mut(collection);
+        if handler.state == KeyAvailable {
+            handler.key = new_key;
+        }
+        Ok(())
+    }
+    fn write_private_file(&self, name: &str, data: &[u8]) -> Result<(), TksError> {
+        let encrypted = self.secrets_handler.encrypt_private(name, data)?;
+        file_ops::write(self.root_path.join(name), encrypted)?;
+        Ok(())
+    }
+    fn read_private_file(&self, name: &str) -> Result<Option<Vec<u8>>, TksError> {
+        if self.secrets_handler.state != KeyAvailable {
+            return Err(TksError::PermissionDenied);
+        }
+        match fs::read(self.root_path.join(name)) {
+            Ok(encrypted) => Ok(Some(self.secrets_handler.decrypt_aead(name, &encrypted)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     fn get_secrets_handler(&mut self) -> Result<Box<dyn SecretsHandler + '_>, TksError> {
         Ok(Box::new(&mut self.secrets_handler))
     }
 
+    fn get_collection_secrets_handler(
+        &mut self,
+        collection: &Uuid,
+    ) -> Result<Box<dyn SecretsHandler + '_>, TksError> {
+        Ok(Box::new(self.handler_of_mut(collection)))
+    }
+
+    fn has_own_key(&self, collection: &Uuid) -> bool {
+        self.collection_keys.contains_key(collection)
+    }
+
+    fn create_collection_key(&mut self, collection: &Uuid) -> Result<(), TksError> {
+        if !self.per_collection_keys {
+            return Ok(());
+        }
+        let dir = self.keys_path.join(collection.to_string());
+        trace!("Creating the key of collection {:?}", dir);
+        fs::DirBuilder::new().recursive(true).create(&dir)?;
+        let handler = TksGcmPasswordSecretHandler::open(&dir)?;
+        self.collection_keys.insert(*collection, handler);
+        Ok(())
+    }
+
     fn unlock_items(&self, items_path: &PathBuf) -> Result<String, TksError> {
         if !items_path.starts_with(self.items_path.clone()) {
             return Err(TksError::InternalError(
@@ -158,39 +311,60 @@ impl StorageBackend for TksGcmBackend {
     }
 
     /// this actually would unlock the secrets_handler, as all the collections on this backend
-    /// type share the same password
+    /// type share the same password, unless the collection has a key of its own
     fn create_unlock_action(
         &mut self,
         coll_uuid: &Uuid,
         coll_name: &str,
+        param: PassphraseActionParam,
     ) -> Result<PromptAction, TksError> {
         trace!("create_onlock_action for {:?}", coll_uuid);
-        let description = if matches!(
-            &self.secrets_handler.state,
-            TksGcmPasswordSecretHandlerState::NotCommissioned
-        ) {
+        let own_key = self.has_own_key(coll_uuid);
+        let state = &self.handler_of(coll_uuid).state;
+        let description = if own_key && *state == NotCommissioned {
+            format!("Define the password of the new collection '{}'", coll_name)
+        } else if own_key && matches!(&param, PassphraseActionParam::UnlockItem(_)) {
+            format!(
+                "Enter the password of the collection '{}', so we can unlock one of its items",
+                coll_name
+            )
+        } else if own_key {
+            format!("Enter the password of the collection '{}'", coll_name)
+        } else if matches!(state, TksGcmPasswordSecretHandlerState::NotCommissioned) {
             format!(
                 "Define the TKS unlock password, so we can store the new collection '{}'",
                 coll_name
             )
+        } else if matches!(&param, PassphraseActionParam::UnlockItem(_)) {
+            format!(
+                "Enter the TKS unlock password, so we can unlock an item of the collection '{}'",
+                coll_name
+            )
         } else {
             format!(
                 "Enter the TKS unlock password, so we can unlock the collection '{}'",
                 coll_name
             )
         };
-        let confirmation = if matches!(
-            &self.secrets_handler.state,
-            TksGcmPasswordSecretHandlerState::NotCommissioned
-        ) {
+        // the key slots hold the key of the backend
+        let keyfile = self.keyfiles.get(coll_name).filter(|_| !own_key);
+        let (description, param) = match (keyfile, param) {
+            (Some(keyfile), PassphraseActionParam::UnlockAllCollections(uuid)) => (
+                format!(
+                    "{}\n\nLeave the password empty to use the key file {}",
+                    description,
+                    keyfile.display()
+                ),
+                PassphraseActionParam::UnlockAllCollectionsOrKeyFile(uuid, coll_name.to_string()),
+            ),
+            (_, param) => (description, param),
+        };
+        let confirmation = if matches!(state, TksGcmPasswordSecretHandlerState::NotCommissioned) {
             Some("Confirm password".to_string())
         } else {
             None
         };
-        let mismatch = if matches!(
-            &self.secrets_handler.state,
-            TksGcmPasswordSecretHandlerState::NotCommissioned
-        ) {
+        let mismatch = if matches!(state, TksGcmPasswordSecretHandlerState::NotCommissioned) {
             Some("Passwords do not match".to_string())
         } else {
             None
@@ -201,14 +375,33 @@ impl StorageBackend for TksGcmBackend {
                 "Password".to_string(),
                 confirmation,
                 mismatch,
-                |s| {
+                param,
+                |s, param| {
                     trace!("create_unlock_action: Performing unlock action");
-                    let mut storage = STORAGE.lock()?;
-                    {
-                        let mut secrets_handler = storage.backend.get_secrets_handler()?;
-                        secrets_handler.derive_key_from_password(s)?;
+                    let mut storage = STORAGE.write()?;
+                    let coll_uuid = param.collection_uuid().ok_or(TksError::ParameterError)?;
+                    if let PassphraseActionParam::UnlockAllCollectionsOrKeyFile(uuid, _) = param {
+                        if s.expose_secret().is_empty() {
+                            storage.unlock_backend_with_keyfile(uuid)?;
+                            return Ok(false);
+                        }
+                    }
+                    let backend = storage.backend_of(&coll_uuid)?;
+                    backend
+                        .get_collection_secrets_handler(&coll_uuid)?
+                        .derive_key_from_password(s)?;
+                    storage.backend_of(&coll_uuid)?.update_keyslots()?;
+                    match param {
+                        PassphraseActionParam::UnlockAllCollections(uuid)
+                        | PassphraseActionParam::UnlockAllCollectionsOrKeyFile(uuid, _) => {
+                            storage.unlock_backend_collections(uuid)?
+                        }
+                        PassphraseActionParam::UnlockItem(item_id) => {
+                            storage.unlock_item(item_id)?
+                        }
+                        // test prompts do not unlock anything
+                        PassphraseActionParam::TestPrompt => return Err(TksError::ParameterError),
                     }
-                    storage.unlock_all_collections()?;
                     Ok(false) // remember, we return the `dismissed` state and not the `success` state
                 },
             ),
@@ -219,26 +412,145 @@ impl StorageBackend for TksGcmBackend {
         Ok(self.secrets_handler.state == TksGcmPasswordSecretHandlerState::KeyAvailable)
     }
 
+    fn has_cached_key(&self, collection: &Uuid, ttl: Duration) -> bool {
+        let handler = self.handler_of(collection);
+        handler.state == KeyAvailable && handler.derived_at.is_some_and(|t| t.elapsed() < ttl)
+    }
+
+    fn forget_keys(&mut self) {
+        self.secrets_handler.forget_key();
+        self.collection_keys.values_mut().for_each(|h| h.forget_key());
+    }
+
+    fn unlock_with_keyfile(&mut self, collection_name: &str) -> Result<(), TksError> {
+        let keyfile = self.keyfiles.get(collection_name).ok_or(TksError::NotFound(Some(
+            format!("No key file configured for collection '{}'", collection_name),
+        )))?;
+        trace!("unlock_with_keyfile {:?}", keyfile);
+        let keyfile_data = fs::read(keyfile)?;
+        let keyslot_path = self.keyslot_path(collection_name);
+        self.secrets_handler
+            .unlock_with_keyfile(&keyfile_data, &keyslot_path)
+    }
+
+    fn update_keyslots(&mut self) -> Result<(), TksError> {
+        if self.secrets_handler.state != KeyAvailable {
+            return Ok(());
+        }
+        for (name, keyfile) in &self.keyfiles {
+            let keyslot_path = self.keyslot_path(name);
+            if keyslot_path.exists() {
+                continue;
+            }
+            if !keyfile.exists() {
+                debug!("Key file {:?} not found, skipping its key slot", keyfile);
+                continue;
+            }
+            trace!("Creating key slot {:?}", keyslot_path);
+            self.secrets_handler
+                .write_keyslot(&fs::read(keyfile)?, &keyslot_path)?;
+        }
+        Ok(())
+    }
+
+    fn begin_save(&self) {
+        *self.save.lock().unwrap() = Some(file_ops::Group::new(&self.root_path));
+    }
+
+    fn end_save(&self, completed: bool) -> Result<(), TksError> {
+        let Some(group) = self.save.lock().unwrap().take() else {
+            return Ok(());
+        };
+        if completed {
+            return Ok(group.commit()?);
+        }
+        group.discard();
+        // the digests of the files discarded no longer tell what the files hold
+        self.item_digests.lock().unwrap().clear();
+        Ok(())
+    }
+
     fn save_collection_metadata(
-        &mut self,
+        &self,
         coll_path: &PathBuf,
         metadata: &String,
     ) -> Result<(), TksError> {
         trace!("save_collection_metadata {:?}", coll_path);
-        fs::write(coll_path, metadata)?;
+        self.write_file(coll_path, metadata)
+    }
+
+    fn delete_collection_files(&mut self, collection: &Collection) -> Result<(), TksError> {
+        // the metadata goes first: an orphaned items file is harmless, but metadata listing items
+        // without their secrets would make the collection fail to unlock
+        fs::remove_file(&collection.path)?;
+        match fs::remove_file(&collection.items_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        match fs::remove_dir_all(Self::item_files_dir(collection)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        if self.collection_keys.remove(&collection.uuid).is_some() {
+            fs::remove_dir_all(self.keys_path.join(collection.uuid.to_string()))?;
+        }
         Ok(())
     }
 
     fn save_collection_items(
-        &mut self,
-        coll_items_path: &PathBuf,
+        &self,
+        collection: &Collection,
         aad: &String,
         item_data: &String,
     ) -> Result<(), TksError> {
-        trace!("save_collection_items {:?}", &coll_items_path);
-        let secrets_handler = &self.secrets_handler;
+        trace!("save_collection_items {:?}", collection.items_path);
+        let secrets_handler = self.handler_of(&collection.uuid);
         let items_encrypted = secrets_handler.encrypt_aead(aad, item_data.as_ref())?;
-        fs::write(&coll_items_path, items_encrypted)?;
+        self.write_file(&collection.items_path, items_encrypted)
+    }
+
+    fn save_collection_secrets(
+        &self,
+        collection: &Collection,
+        aad: &String,
+        collection_secrets: CollectionSecrets,
+    ) -> Result<(), TksError> {
+        let dir = Self::item_files_dir(collection);
+        if !self.per_item_files {
+            self.save_items_file(collection, aad, collection_secrets)?;
+            // the item files were left there by the per item mode, they're stale now
+            if dir.exists() {
+                debug!("Removing the item files of collection '{}'", collection.name);
+                self.remove_file(&dir)?;
+            }
+            return Ok(());
+        }
+        trace!("save_collection_secrets {:?}", dir);
+        self.migrate_items_file(collection, aad)?;
+        fs::DirBuilder::new().recursive(true).create(&dir)?;
+        for item_data in &collection_secrets.items {
+            self.write_item_file(collection, aad, item_data)?;
+        }
+        // the locked items keep their files, only the deleted items lose them
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if file_ops::is_temp(&path) {
+                continue;
+            }
+            let known = path
+                .file_name()
+                .and_then(|n| Uuid::parse_str(&n.to_string_lossy()).ok())
+                .is_some_and(|uuid| collection.stored_items().any(|i| i.id.uuid == uuid));
+            // another machine may have synced the file before the metadata listing its item
+            if !known && collection.synced && !sync::is_stale(&path) {
+                continue;
+            }
+            if !known {
+                trace!("Removing item file {:?}", path);
+                self.remove_file(&path)?;
+                self.item_digests.lock().unwrap().remove(&path);
+            }
+        }
         Ok(())
     }
 
@@ -252,124 +564,495 @@ impl StorageBackend for TksGcmBackend {
 
         let mut encrypted: Vec<u8> = Vec::new();
         if Path::new(&collection.items_path).exists() {
-            encrypted = fs::read(&collection.items_path)?;
-            self.secrets_handler.decrypt_aead(aad, &encrypted)
+            if self.per_item_files {
+                self.migrate_items_file(collection, aad)?;
+                return self.load_item_files(collection, aad);
+            }
+            encrypted = self.read_items_file(collection)?;
+            self.handler_of(&collection.uuid).decrypt_aead(aad, &encrypted)
+        } else if Self::item_files_dir(collection).exists() {
+            self.load_item_files(collection, aad)
         } else {
             debug!("Collection is empty");
             Ok(encrypted)
         }
     }
+
+    fn check_secrets(
+        &self,
+        collection: &Collection,
+        aad: &String,
+    ) -> Result<StoredSecrets, TksError> {
+        // loading would migrate the items file, so both kinds of files get read as they are
+        let mut stored = StoredSecrets::default();
+        if collection.items_path.exists() {
+            let decrypted = fs::read(&collection.items_path)
+                .map_err(TksError::from)
+                .and_then(|data| self.handler_of(&collection.uuid).decrypt_aead(aad, &data));
+            stored.add_items_file(&collection.items_path, decrypted);
+        }
+        let dir = Self::item_files_dir(collection);
+        if !dir.exists() {
+            return Ok(stored);
+        }
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let uuid = path
+                .file_name()
+                .and_then(|n| Uuid::parse_str(&n.to_string_lossy()).ok());
+            let Some(uuid) = uuid else {
+                continue;
+            };
+            match self.read_item_file(collection, aad, &uuid) {
+                Ok(_) => {
+                    stored.found.insert(uuid);
+                }
+                Err(e) => stored.damaged.push((path, e.to_string())),
+            }
+        }
+        Ok(stored)
+    }
+
+    fn load_item_data(
+        &self,
+        collection: &Collection,
+        aad: &String,
+        item_uuid: &Uuid,
+    ) -> Result<ItemData, TksError> {
+        if !collection.items_path.exists() && Self::item_files_dir(collection).exists() {
+            return self.read_item_file(collection, aad, item_uuid);
+        }
+        let data = self.load_collection_items(collection, aad)?;
+        CollectionSecrets::find_item(&data, item_uuid)
+    }
+}
+
+/// Smallest padded item file contents; the larger ones get padded to the next power of two
+const ITEM_FILE_BUCKETS: usize = 512;
+
+/// Pads the JSON contents of an item file with whitespace, which JSON parsers ignore, so files
+/// written with padding stay readable with and without it. The padding gets encrypted along with
+/// the contents, so it can't be told apart from them.
+fn pad(plain: &mut Vec<u8>) {
+    let size = plain.len().max(ITEM_FILE_BUCKETS).next_power_of_two();
+    // growing in place would leave a copy of the secret behind in the old allocation
+    let mut padded = Vec::with_capacity(size);
+    padded.extend_from_slice(plain);
+    padded.resize(size, b' ');
+    plain.zeroize();
+    *plain = padded;
+}
+
+fn unpad(plain: &mut Vec<u8>) {
+    let len = plain.len() - plain.iter().rev().take_while(|b| **b == b' ').count();
+    plain.truncate(len);
+}
+
+impl TksGcmBackend {
+    /// The key of the collection: its own one, if any, otherwise the one of the backend
+    fn handler_of(&self, collection: &Uuid) -> &TksGcmPasswordSecretHandler {
+        self.collection_keys
+            .get(collection)
+            .unwrap_or(&self.secrets_handler)
+    }
+
+    fn handler_of_mut(&mut self, collection: &Uuid) -> &mut TksGcmPasswordSecretHandler {
+        match self.collection_keys.get_mut(collection) {
+            Some(handler) => handler,
+            None => &mut self.secrets_handler,
+        }
+    }
+
+    /// The files encrypted with the key of the collection, with their AAD: the secrets of the
+    /// collections sharing it, and the private files when it's the key of the backend. The
+    /// private ones are only known once decrypted, any other file may sit next to them.
+    fn files_of_key(
+        &self,
+        collection: &Uuid,
+        collections: &[(&Collection, String)],
+    ) -> Result<Vec<(PathBuf, String, bool)>, TksError> {
+        let mut files = Vec::new();
+        for (c, aad) in collections {
+            if c.items_path.exists() {
+                files.push((c.items_path.clone(), aad.clone(), false));
+            }
+            let dir = Self::item_files_dir(c);
+            if !dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                let uuid = path
+                    .file_name()
+                    .and_then(|n| Uuid::parse_str(&n.to_string_lossy()).ok());
+                if let Some(uuid) = uuid {
+                    files.push((path, Self::item_aad(aad, &uuid), false));
+                }
+            }
+        }
+        if self.has_own_key(collection) {
+            return Ok(files);
+        }
+        for entry in fs::read_dir(&self.root_path)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if path.is_file() && name != "salt" && name != "commissioned" {
+                files.push((path, name, true));
+            }
+        }
+        Ok(files)
+    }
+
+    fn keyslot_path(&self, collection_name: &str) -> PathBuf {
+        let mut keyslot_path = PathBuf::from(&self.keyslots_path);
+        keyslot_path.push(collection_name);
+        keyslot_path
+    }
+
+    fn item_files_dir(collection: &Collection) -> PathBuf {
+        let mut dir = collection.items_path.clone().into_os_string();
+        dir.push(".d");
+        dir.into()
+    }
+
+    fn item_file_path(collection: &Collection, item_uuid: &Uuid) -> PathBuf {
+        let mut path = Self::item_files_dir(collection);
+        path.push(item_uuid.to_string());
+        path
+    }
+
+    /// Binds each item file to its item, so files can't get swapped
+    fn item_aad(aad: &str, item_uuid: &Uuid) -> String {
+        format!("{}{}", aad, item_uuid)
+    }
+
+    /// Writes the item's file, unless it already holds the same secret
+    fn write_item_file(
+        &self,
+        collection: &Collection,
+        aad: &str,
+        item_data: &ItemData,
+    ) -> Result<(), TksError> {
+        let path = Self::item_file_path(collection, &item_data.uuid);
+        let mut plain = serde_json::to_vec(item_data)?;
+        let digest = openssl::sha::sha256(&plain);
+        if path.exists() && self.item_digests.lock().unwrap().get(&path) == Some(&digest) {
+            plain.zeroize();
+            return Ok(());
+        }
+        if self.pad_item_files {
+            pad(&mut plain);
+        }
+        trace!("Writing item file {:?}", path);
+        let encrypted = self
+            .handler_of(&collection.uuid)
+            .encrypt_aead(&Self::item_aad(aad, &item_data.uuid), &plain);
+        plain.zeroize();
+        let encrypted = encrypted?;
+        self.write_file(&path, encrypted)?;
+        self.item_digests.lock().unwrap().insert(path, digest);
+        Ok(())
+    }
+
+    fn read_item_file(
+        &self,
+        collection: &Collection,
+        aad: &str,
+        item_uuid: &Uuid,
+    ) -> Result<ItemData, TksError> {
+        let path = Self::item_file_path(collection, item_uuid);
+        if !path.exists() {
+            return Err(TksError::ItemNotFound);
+        }
+        let mut plain = self
+            .handler_of(&collection.uuid)
+            .decrypt_aead(&Self::item_aad(aad, item_uuid), &fs::read(&path)?)?;
+        unpad(&mut plain);
+        self.item_digests
+            .lock()
+            .unwrap()
+            .insert(path, openssl::sha::sha256(&plain));
+        let item_data = serde_json::from_slice(&plain);
+        plain.zeroize();
+        Ok(item_data?)
+    }
+
+    /// Reads the encrypted items file, keeping a copy of the files of an older format, as the
+    /// next save rewrites them in the current one
+    fn read_items_file(&self, collection: &Collection) -> Result<Vec<u8>, TksError> {
+        let encrypted = fs::read(&collection.items_path)?;
+        match encrypted.first() {
+            Some(v) if *v < aead_file::FORMAT_VERSION => {
+                schema::backup_file(&collection.items_path, &self.root_path, *v)?
+            }
+            _ => {}
+        }
+        Ok(encrypted)
+    }
+
+    /// Gathers the item files into the format of the items file. Damaged or missing files are
+    /// only logged, so the other items still get unlocked.
+    fn load_item_files(&self, collection: &Collection, aad: &str) -> Result<Vec<u8>, TksError> {
+        let items = collection
+            .stored_items()
+            .filter_map(|i| match self.read_item_file(collection, aad, &i.id.uuid) {
+                Ok(item_data) => Some(item_data),
+                Err(e) => {
+                    error!("Cannot read the secret of item {}: {}", i.id.uuid, e);
+                    None
+                }
+            })
+            .collect();
+        Ok(serde_json::to_vec(&CollectionSecrets { items })?)
+    }
+
+    /// Splits the collection's items file into item files. The items file only goes away once
+    /// all the item files got written, so an interrupted migration starts over.
+    fn migrate_items_file(&self, collection: &Collection, aad: &str) -> Result<(), TksError> {
+        if !collection.items_path.exists() {
+            return Ok(());
+        }
+        debug!("Migrating collection '{}' to item files", collection.name);
+        let data = self
+            .handler_of(&collection.uuid)
+            .decrypt_aead(aad, &self.read_items_file(collection)?)?;
+        let stored: CollectionSecrets = serde_json::from_slice(&data)?;
+        fs::DirBuilder::new()
+            .recursive(true)
+            .create(Self::item_files_dir(collection))?;
+        for item_data in stored
+            .items
+            .iter()
+            .filter(|s| collection.stored_items().any(|i| i.id.uuid == s.uuid))
+        {
+            self.write_item_file(collection, aad, item_data)?;
+        }
+        self.remove_file(&collection.items_path)
+    }
+
+    /// Writes the file, only replacing the previous one once the save in progress, if any, ends
+    fn write_file<C: AsRef<[u8]>>(&self, path: &Path, contents: C) -> Result<(), TksError> {
+        match self.save.lock().unwrap().as_mut() {
+            Some(group) => group.write(path, contents)?,
+            None => file_ops::write(path, contents)?,
+        }
+        Ok(())
+    }
+
+    /// Removes the file or the directory, once the save in progress, if any, ends
+    fn remove_file(&self, path: &Path) -> Result<(), TksError> {
+        match self.save.lock().unwrap().as_mut() {
+            Some(group) => group.remove(path),
+            None if path.is_dir() => fs::remove_dir_all(path)?,
+            None => fs::remove_file(path)?,
+        }
+        Ok(())
+    }
 }
 
 impl SecretsHandler for &mut TksGcmPasswordSecretHandler {
     fn derive_key_from_password(&mut self, s: SecretString) -> Result<(), TksError> {
         trace!("derive_key_from_password");
-        let mut key = vec![0u8; 32];
+        let key = self.derive_key(s.expose_secret().as_bytes())?;
+        self.use_key(key)
+    }
+}
+
+impl TksGcmPasswordSecretHandler {
+    /// Reads the salt and the commissioned data kept in the directory, creating the salt upon
+    /// the very first initialization
+    fn open(dir: &Path) -> Result<TksGcmPasswordSecretHandler, TksError> {
+        let salt_file_path = dir.join("salt");
+        let salt_check = Path::new(&salt_file_path).exists();
+        let secret_state: TksGcmPasswordSecretHandlerState;
+        let salt = if !salt_check {
+            trace!("Initializing salt file {:?}", salt_file_path);
+            // upon the very first initialization, generate a random salt
+            let mut salt = vec![0u8; 256];
+            openssl::rand::rand_bytes(&mut salt)?;
+            file_ops::write(salt_file_path, salt.clone())?;
+            salt
+        } else {
+            trace!("Reading salt file {:?}", salt_file_path);
+            fs::read(salt_file_path.clone())?
+        };
+
+        let commissioned_data_path = dir.join("commissioned");
+        let commissioned_data_check = Path::new(&commissioned_data_path).exists();
+
+        let commissioned_data = if !commissioned_data_check {
+            trace!("Initializing commissioned data {}", commissioned_data_path.display());
+            let mut commissioned_data = vec![0u8; 256];
+            openssl::rand::rand_bytes(&mut commissioned_data)?;
+            // we still need to wait for the password so we are still not commissioned
+            secret_state = TksGcmPasswordSecretHandlerState::NotCommissioned;
+            commissioned_data
+        } else {
+            trace!("Reading commissioned data {}", commissioned_data_path.display());
+            secret_state = TksGcmPasswordSecretHandlerState::Locked;
+            fs::read(commissioned_data_path.clone())?
+        };
+
+        Ok(TksGcmPasswordSecretHandler {
+            state: secret_state,
+            salt,
+            commissioned_data,
+            commissioned_data_path: commissioned_data_path.into(),
+            key: SecureBuffer::new(32),
+            cipher: openssl::symm::Cipher::aes_256_gcm(),
+            derived_at: None,
+        })
+    }
+
+    fn derive_key(&self, secret_material: &[u8]) -> Result<SecureBuffer, TksError> {
+        let mut key = SecureBuffer::new(32);
         openssl::pkcs5::pbkdf2_hmac(
-            s.expose_secret().as_bytes(),
+            secret_material,
             &self.salt,
-            1024,
+            KDF_ITERATIONS as usize,
             openssl::hash::MessageDigest::sha512(),
             &mut key,
         )?;
-        self.key = key;
+        Ok(key)
+    }
+
+    /// Derives the key of the collection's checksums from the storage key, so it never gets
+    /// used as is
+    fn checksum_key(&self, collection: &Uuid) -> Result<Vec<u8>, TksError> {
+        if self.state != KeyAvailable {
+            return Err(TksError::PermissionDenied);
+        }
+        let info = format!("tks-secret-checksums-{}", collection);
+        checksums::hmac(&self.key, info.as_bytes())
+    }
+
+    /// Encrypts a private file of the service; its name is the AAD, so the files can't be
+    /// swapped
+    fn encrypt_private(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, TksError> {
+        if self.state != KeyAvailable {
+            return Err(TksError::PermissionDenied);
+        }
+        self.encrypt_aead(name, data)
+    }
+
+    fn unlock_with_keyfile(
+        &mut self,
+        keyfile_data: &[u8],
+        keyslot_path: &Path,
+    ) -> Result<(), TksError> {
+        let keyfile_key = self.derive_key(keyfile_data)?;
+        let key = if self.state == NotCommissioned {
+            // the key file is used instead of a password
+            keyfile_key
+        } else {
+            let keyslot = fs::read(keyslot_path)?;
+            self.decrypt_aead_with(
+                &keyfile_key,
+                keyslot_path.to_str().unwrap(),
+                &keyslot,
+            )?
+            .into()
+        };
+        self.use_key(key)?;
+        if !keyslot_path.exists() {
+            self.write_keyslot(keyfile_data, keyslot_path)?;
+        }
+        Ok(())
+    }
+
+    fn write_keyslot(&self, keyfile_data: &[u8], keyslot_path: &Path) -> Result<(), TksError> {
+        let keyslot = self.keyslot(&self.key, keyfile_data, keyslot_path)?;
+        file_ops::write(keyslot_path, keyslot)?;
+        Ok(())
+    }
+
+    /// The contents of a key slot giving the key file access to `key`
+    fn keyslot(
+        &self,
+        key: &[u8],
+        keyfile_data: &[u8],
+        keyslot_path: &Path,
+    ) -> Result<Vec<u8>, TksError> {
+        let keyfile_key = self.derive_key(keyfile_data)?;
+        self.encrypt_aead_with(&keyfile_key, keyslot_path.to_str().unwrap(), key)
+    }
+
+    /// Drops the key, which zeroes it, see [SecureBuffer]; the password has to be given again
+    fn forget_key(&mut self) {
+        if self.state == KeyAvailable {
+            self.state = Locked;
+        }
+        self.key = SecureBuffer::new(32);
+        self.derived_at = None;
+    }
+
+    /// Makes `key` the current key, after checking it against the commissioned data; the very
+    /// first key commissions the backend
+    fn use_key(&mut self, key: SecureBuffer) -> Result<(), TksError> {
+        let previous_key = std::mem::replace(&mut self.key, key);
 
         match self.state {
             NotCommissioned => {
                 trace!("Commissioning the storage backend");
                 let metadata = self.commissioned_data_path.to_str().unwrap();
                 let encrypted = self.encrypt_aead(metadata, &self.commissioned_data)?;
-                fs::write(&self.commissioned_data_path, encrypted)?;
+                file_ops::write(&self.commissioned_data_path, encrypted)?;
+                self.state = KeyAvailable;
             }
-            Locked => {
+            Locked | KeyAvailable => {
+                // the password is checked again when it's already available, as locked items or
+                // collections may get unlocked again later on
                 trace!("Checking storage backend password");
                 let data = fs::read(&self.commissioned_data_path)?;
                 let metadata = self.commissioned_data_path.to_str().unwrap();
-                let _ = self.decrypt_aead(metadata, &data)?;
+                if let Err(e) = self.decrypt_aead(metadata, &data) {
+                    // a mistyped password should not discard the key we already have
+                    self.key = previous_key;
+                    return Err(e);
+                }
                 // we've made it so far, meaning we've got the right secret material
                 self.state = KeyAvailable;
             }
-            KeyAvailable => {
-                unreachable!()
-            }
         }
+        self.derived_at = Some(Instant::now());
         Ok(())
     }
-}
 
-impl TksGcmPasswordSecretHandler {
-    const FILE_SCHEMA_VERSION: u8 = 1;
     fn encrypt_aead(&self, metadata: &str, items: &[u8]) -> Result<Vec<u8>, TksError> {
+        self.encrypt_aead_with(&self.key, metadata, items)
+    }
+
+    fn encrypt_aead_with(
+        &self,
+        key: &[u8],
+        metadata: &str,
+        items: &[u8],
+    ) -> Result<Vec<u8>, TksError> {
         let mut metadata_sha = Sha256::new();
         metadata_sha.update(metadata.as_bytes());
         debug!(
             "encrypt_aead using metadata SHA {:?}",
             metadata_sha.finish()
         );
-
-        let mut tag = vec![0u8; 16];
-        let mut iv = [0u8; 12];
-        rand_bytes(&mut iv)?;
-        let ciphertext = openssl::symm::encrypt_aead(
-            self.cipher,
-            &self.key,
-            Some(&iv),
-            metadata.as_ref(),
-            items.as_ref(),
-            &mut tag,
-        )?;
-        // here we build the structure of the items file
-        let mut encrypted: Vec<u8> = Vec::new();
-        encrypted.push(Self::FILE_SCHEMA_VERSION);
-        encrypted.extend_from_slice(&iv);
-        encrypted.extend_from_slice(&tag);
-        encrypted.extend_from_slice(&ciphertext);
-        Ok(encrypted)
+        aead_file::seal(self.cipher, key, metadata, items)
     }
 
     fn decrypt_aead(&self, aad: &str, encrypted: &[u8]) -> Result<Vec<u8>, TksError> {
-        let version: &u8 = encrypted
-            .get(0)
-            .ok_or_else(|| TksError::SerializationError("Corrupted file".to_string()))?;
-        let tag: Vec<u8>;
-        let iv: Vec<u8>;
-        let cyphertext: Vec<u8>;
-        match version {
-            1 => {
-                iv = encrypted
-                    .get(1..13)
-                    .ok_or_else(|| TksError::SerializationError("Corrupted file".to_string()))?
-                    .into();
-                tag = encrypted
-                    .get(13..29)
-                    .ok_or_else(|| TksError::SerializationError("Corrupted file".to_string()))?
-                    .into();
-                cyphertext = encrypted
-                    .get(29..)
-                    .ok_or_else(|| TksError::SerializationError("Corrupted file".to_string()))?
-                    .into();
-            }
-            _ => {
-                return Err(TksError::SerializationError(
-                    "Unknown file version".to_string(),
-                ))
-            }
-        };
+        self.decrypt_aead_with(&self.key, aad, encrypted)
+    }
 
+    fn decrypt_aead_with(
+        &self,
+        key: &[u8],
+        aad: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, TksError> {
         let mut metadata_sha = Sha256::new();
         metadata_sha.update(aad.as_bytes());
         debug!(
             "decrypt_aead using metadata SHA {:?}",
             metadata_sha.finish()
         );
-        let decrypted = decrypt_aead(
-            self.cipher,
-            &self.key,
-            Some(&iv),
-            aad.as_ref(),
-            cyphertext.as_ref(),
-            tag.as_ref(),
-        )?;
-        Ok(decrypted)
+        aead_file::open(self.cipher, key, aad, encrypted)
     }
 }
@@ -0,0 +1,89 @@
+//! Changing the default collection or an alias touches the metadata of several collections. These
+//! changes are applied all-or-nothing: when saving one of the collections fails, the in-memory
+//! metadata of all of them is rolled back and the collections already saved get written again.
+
+use crate::storage::collection::Collection;
+use crate::storage::Storage;
+use crate::tks_error::TksError;
+use log::{error, trace};
+use uuid::Uuid;
+
+/// The part of the collection metadata a transaction is allowed to change
+struct MetadataSnapshot {
+    uuid: Uuid,
+    default: bool,
+    aliases: Option<Vec<String>>,
+    modified: u64,
+    sequence: u64,
+}
+
+impl MetadataSnapshot {
+    fn new(collection: &Collection) -> MetadataSnapshot {
+        MetadataSnapshot {
+            uuid: collection.uuid,
+            default: collection.default,
+            aliases: collection.aliases.clone(),
+            modified: collection.modified,
+            sequence: collection.sequence,
+        }
+    }
+    fn is_changed(&self, collection: &Collection) -> bool {
+        self.default != collection.default || self.aliases != collection.aliases
+    }
+    fn restore(&self, collection: &mut Collection) {
+        collection.default = self.default;
+        collection.aliases = self.aliases.clone();
+        collection.modified = self.modified;
+        collection.sequence = self.sequence;
+    }
+}
+
+impl Storage {
+    /// Lets `f` change the default flag and the aliases of any collection, then saves the changed
+    /// collections. Returns the uuids of the changed collections.
+    pub(crate) fn modify_collections_metadata<F>(&mut self, f: F) -> Result<Vec<Uuid>, TksError>
+    where
+        F: FnOnce(&mut [Collection]) -> Result<(), TksError>,
+    {
+        let snapshots: Vec<MetadataSnapshot> =
+            self.collections.iter().map(MetadataSnapshot::new).collect();
+        if let Err(e) = f(&mut self.collections) {
+            self.rollback(&snapshots, &[]);
+            return Err(e);
+        }
+        let changed: Vec<Uuid> = snapshots
+            .iter()
+            .filter(|s| {
+                self.collections
+                    .iter()
+                    .any(|c| c.uuid == s.uuid && s.is_changed(c))
+            })
+            .map(|s| s.uuid)
+            .collect();
+        let mut saved = Vec::new();
+        for uuid in &changed {
+            trace!("Saving collection '{}' metadata change", uuid);
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot save collection '{}', rolling back: {}", uuid, e);
+                self.rollback(&snapshots, &saved);
+                return Err(e);
+            }
+            saved.push(*uuid);
+        }
+        Ok(changed)
+    }
+
+    fn rollback(&mut self, snapshots: &[MetadataSnapshot], saved: &[Uuid]) {
+        for s in snapshots {
+            if let Some(c) = self.collections.iter_mut().find(|c| c.uuid == s.uuid) {
+                s.restore(c);
+            }
+        }
+        for uuid in saved {
+            // best effort, there's not much else we can do if this fails too
+            if let Err(e) = self.save_collection(uuid, false) {
+                error!("Cannot roll back collection '{}': {}", uuid, e);
+            }
+        }
+    }
+}
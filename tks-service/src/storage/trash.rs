@@ -0,0 +1,158 @@
+//! The items deleted by the clients aren't destroyed right away: they go to the trash of their
+//! collection, from where the user may restore them, e.g. after a buggy client deleted them. The
+//! trashed items keep their secret, saved and encrypted along with the other items, but clients
+//! don't see them anymore.
+//!
+//! Collections keep their trashed items for [DEFAULT_RETENTION_DAYS] days, unless their
+//! `tks:trash-retention-days` property asks for another number; `0` deletes the items right away.
+//! A background task then purges the trash; the trash of locked collections gets purged once they
+//! get unlocked.
+
+use crate::storage::collection::{Collection, Item, ItemId};
+use crate::storage::expiry::now;
+use crate::storage::history::HistoryOperation;
+use crate::storage::{Storage, STORAGE};
+use crate::tks_error::TksError;
+use log::{debug, error, info, trace};
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const TRASH_RETENTION_PROPERTY: &str = "tks:trash-retention-days";
+
+/// How long the trashed items are kept, unless their collection says otherwise
+pub const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// How often the trash gets purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrashedItem {
+    /// When the item got deleted, in seconds since the Unix epoch
+    pub deleted: u64,
+    pub item: Item,
+}
+
+impl Collection {
+    /// How many days the trashed items are kept
+    pub fn trash_retention_days(&self) -> u64 {
+        let Some(days) = self.properties.get(TRASH_RETENTION_PROPERTY) else {
+            return DEFAULT_RETENTION_DAYS;
+        };
+        days.parse().unwrap_or_else(|_| {
+            debug!(
+                "Ignoring the malformed trash retention '{}' of {}",
+                days, self.uuid
+            );
+            DEFAULT_RETENTION_DAYS
+        })
+    }
+
+    /// The items having their secret saved: the collection's ones, then the trashed ones
+    pub(crate) fn stored_items(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter().chain(self.trash.iter().map(|t| &t.item))
+    }
+
+    pub(crate) fn stored_items_mut(&mut self) -> impl Iterator<Item = &mut Item> {
+        self.items
+            .iter_mut()
+            .chain(self.trash.iter_mut().map(|t| &mut t.item))
+    }
+
+    /// Deletes an item, moving it to the trash unless the collection doesn't keep its trashed
+    /// items
+    pub fn trash_item(&mut self, uuid: &Uuid) -> Result<Item, TksError> {
+        let item = self.delete_item(uuid)?;
+        if self.trash_retention_days() > 0 {
+            self.trash.push(TrashedItem {
+                deleted: now(),
+                item: item.clone(),
+            });
+        }
+        Ok(item)
+    }
+
+    /// Moves a trashed item back to the collection
+    pub fn restore_item(&mut self, uuid: &Uuid) -> Result<ItemId, TksError> {
+        if self.locked {
+            return Err(TksError::PermissionDenied);
+        }
+        let index = self
+            .trash
+            .iter()
+            .position(|t| t.item.id.uuid == *uuid)
+            .ok_or(TksError::NotFound(None))?;
+        let item = self.trash.remove(index).item;
+        self.record_history(HistoryOperation::Restored, &item.id.uuid, &item.label);
+        let item_id = item.id.clone();
+        self.items.push(item);
+        Ok(item_id)
+    }
+
+    /// Destroys the given trashed items, all of them when none is given; returns how many got
+    /// destroyed
+    pub fn purge_trash(&mut self, uuids: &[Uuid]) -> Result<usize, TksError> {
+        if self.locked {
+            return Err(TksError::PermissionDenied);
+        }
+        if let Some(unknown) = uuids
+            .iter()
+            .find(|u| !self.trash.iter().any(|t| t.item.id.uuid == **u))
+        {
+            return Err(TksError::NotFound(
+                format!("No trashed item {}", unknown).into(),
+            ));
+        }
+        let count = self.trash.len();
+        self.trash
+            .retain(|t| !uuids.is_empty() && !uuids.contains(&t.item.id.uuid));
+        Ok(count - self.trash.len())
+    }
+
+    /// The trashed items kept for longer than the collection's retention at the given time
+    fn expired_trash(&self, now: u64) -> Vec<Uuid> {
+        let retention = self.trash_retention_days() * 86400;
+        self.trash
+            .iter()
+            .filter(|t| t.deleted.saturating_add(retention) <= now)
+            .map(|t| t.item.id.uuid)
+            .collect()
+    }
+}
+
+impl Storage {
+    /// Destroys the trashed items kept for longer than the retention of their collection at the
+    /// given time; returns how many got destroyed. Locked and read-only collections keep theirs.
+    pub fn purge_expired_trash(&mut self, now: u64) -> usize {
+        let collections: Vec<(Uuid, Vec<Uuid>)> = self
+            .collections
+            .iter()
+            .filter(|c| !c.locked && self.check_writable(&c.uuid).is_ok())
+            .map(|c| (c.uuid, c.expired_trash(now)))
+            .filter(|(_, items)| !items.is_empty())
+            .collect();
+        let mut purged = 0;
+        for (uuid, items) in collections {
+            trace!("{} trashed item(s) of {} expired", items.len(), uuid);
+            match self.modify_collection(&uuid, |c| c.purge_trash(&items)) {
+                Ok(count) => purged += count,
+                Err(e) => error!("Cannot purge the trash of {}: {}", uuid, e),
+            }
+        }
+        purged
+    }
+
+    /// Starts the task purging the trash
+    pub fn start_trash_purge() {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let purged = STORAGE.write().unwrap().purge_expired_trash(now());
+                if purged > 0 {
+                    info!("Purged {} trashed item(s)", purged);
+                }
+            }
+        });
+    }
+}
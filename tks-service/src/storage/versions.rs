@@ -0,0 +1,88 @@
+//! Items keep the previous values of their secret, so users overwriting a password can get the
+//! old one back. The versions get saved along with the secret, encrypted the same way, and go
+//! away with it when the item gets locked or deleted. Collections keep [DEFAULT_DEPTH] versions
+//! per item, unless their `tks:secret-history-depth` property asks for another number; `0` keeps
+//! none.
+
+use crate::storage::collection::{Collection, Item, ItemData};
+use crate::storage::secure_buffer::SecureBuffer;
+use crate::tks_error::TksError;
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HISTORY_DEPTH_PROPERTY: &str = "tks:secret-history-depth";
+
+/// How many versions each item keeps, unless its collection says otherwise
+pub const DEFAULT_DEPTH: usize = 5;
+
+/// A previous value of an item's secret
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SecretVersion {
+    /// When it got replaced, in seconds since the Unix epoch
+    pub replaced: u64,
+    pub(crate) data: SecureBuffer,
+    pub content_type: String,
+}
+
+impl ItemData {
+    /// Previous values of the secret, latest first
+    pub fn versions(&self) -> &[SecretVersion] {
+        &self.versions
+    }
+
+    /// Changes the secret, keeping the current one as the latest version, unless it's the same
+    pub(crate) fn replace(&mut self, data: SecureBuffer, content_type: String) {
+        if self.data == data && self.content_type == content_type {
+            return;
+        }
+        let replaced = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let version = SecretVersion {
+            replaced,
+            data: mem::replace(&mut self.data, data),
+            content_type: mem::replace(&mut self.content_type, content_type),
+        };
+        self.versions.insert(0, version);
+    }
+
+    /// Forgets the oldest versions, beyond the given depth
+    pub(crate) fn trim_versions(&mut self, depth: usize) {
+        self.versions.truncate(depth);
+    }
+}
+
+impl Item {
+    /// Gets back the version having the given index, `0` being the latest one; the current
+    /// secret becomes the latest version, so rolling back can be undone
+    pub fn rollback(&mut self, index: usize) -> Result<(), TksError> {
+        let data = self.data.as_mut().ok_or(TksError::PermissionDenied)?;
+        if index >= data.versions.len() {
+            return Err(TksError::NotFound(
+                format!("Item '{}' has no version {}", self.label, index).into(),
+            ));
+        }
+        let version = data.versions.remove(index);
+        data.replace(version.data, version.content_type);
+        Ok(())
+    }
+}
+
+impl Collection {
+    /// How many versions the items keep
+    pub fn history_depth(&self) -> usize {
+        let Some(depth) = self.properties.get(HISTORY_DEPTH_PROPERTY) else {
+            return DEFAULT_DEPTH;
+        };
+        depth.parse().unwrap_or_else(|_| {
+            debug!(
+                "Ignoring the malformed history depth '{}' of {}",
+                depth, self.uuid
+            );
+            DEFAULT_DEPTH
+        })
+    }
+}
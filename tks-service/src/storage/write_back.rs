@@ -0,0 +1,104 @@
+//! Each save re-serializes and re-encrypts the whole collection. When `storage.flush_delay` is
+//! configured, item and collection changes only get written once no other change happened during
+//! that delay, so bulk imports don't rewrite the collection for every single item. Changes are
+//! also written before locking and upon shutdown.
+//!
+//! The service leaves the item and collection changes to the flusher, which encrypts and writes
+//! the collections while only holding a read lock of [STORAGE]: the clients reading secrets don't
+//! wait for the disk. Without a flush delay, each change wakes the flusher up at once. A failed
+//! write only gets logged, the collection staying dirty until the next write succeeds.
+
+use crate::storage::{Storage, STORAGE};
+use crate::tks_error::TksError;
+use lazy_static::lazy_static;
+use log::{error, trace};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// Continuous changes still get written after this many flush delays
+const MAX_DELAY_FACTOR: u32 = 10;
+
+lazy_static! {
+    /// Wakes the flusher up once a change got made, when the changes aren't delayed
+    static ref CHANGED: Notify = Notify::new();
+}
+
+impl Storage {
+    /// Saves the collection right away, or marks it for the next flush when changes are delayed
+    pub(crate) fn persist_collection(&mut self, uuid: &Uuid) -> Result<(), TksError> {
+        self.check_writable(uuid)?;
+        self.check_batch(uuid)?;
+        self.check_not_behind(uuid)?;
+        if self.flush_delay.is_zero() && !self.write_behind {
+            return self.save_collection(uuid, false);
+        }
+        self.touch_collection(uuid)?;
+        let now = Instant::now();
+        let mut dirty = self.dirty.lock().unwrap();
+        if dirty.is_empty() {
+            self.first_change = Some(now);
+        }
+        self.last_change = Some(now);
+        dirty.insert(*uuid);
+        if self.flush_delay.is_zero() {
+            CHANGED.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Writes all the pending changes
+    pub fn flush(&self) -> Result<(), TksError> {
+        let dirty: Vec<Uuid> = self.dirty.lock().unwrap().iter().copied().collect();
+        for uuid in dirty {
+            self.flush_collection(&uuid)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pending changes of a collection, if any
+    pub(crate) fn flush_collection(&self, uuid: &Uuid) -> Result<(), TksError> {
+        let dirty = self.dirty.lock().unwrap().contains(uuid);
+        if dirty {
+            trace!("Flushing collection '{}'", uuid);
+            // write_collection forgets the collection from the dirty set once written
+            self.write_collection(uuid)?;
+        }
+        Ok(())
+    }
+
+    fn is_flush_due(&self) -> bool {
+        let elapsed = |t: Option<Instant>| t.map_or(Duration::ZERO, |t| t.elapsed());
+        !self.dirty.lock().unwrap().is_empty()
+            && (elapsed(self.last_change) >= self.flush_delay
+                || elapsed(self.first_change) >= self.flush_delay * MAX_DELAY_FACTOR)
+    }
+
+    /// Starts the task writing the changes in the background, from then on
+    pub fn start_flusher() {
+        let delay = {
+            let mut storage = STORAGE.write().unwrap();
+            storage.write_behind = true;
+            storage.flush_delay
+        };
+        tokio::spawn(async move {
+            let mut interval = (!delay.is_zero()).then(|| tokio::time::interval(delay / 2));
+            loop {
+                match interval.as_mut() {
+                    Some(interval) => {
+                        interval.tick().await;
+                    }
+                    None => CHANGED.notified().await,
+                }
+                // the changes wait for the write to end, the readers don't
+                let storage = STORAGE.read().unwrap();
+                if storage.is_flush_due() {
+                    // failed collections stay dirty, so they get retried on the next tick
+                    if let Err(e) = storage.flush() {
+                        error!("Cannot flush the storage: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
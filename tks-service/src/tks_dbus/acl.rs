@@ -0,0 +1,288 @@
+//! Enforces the ACLs of the collections and items, see [crate::storage::acl], and manages them
+//! through the io.linux_tks.Acl1 interface of the collection and item objects.
+//!
+//! A client needing an access its ACL doesn't grant gets the user asked, while the call waits;
+//! the grant then gets remembered in the ACL. Lifting an ACL, or granting accesses through the
+//! interface, asks the user too, so that clients can't let themselves in.
+
+use crate::audit;
+use crate::storage::acl::{Access, Acl};
+use crate::storage::STORAGE;
+use crate::tks_dbus::client_context::TksClientProcess;
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::prompter;
+use crate::tks_dbus::tks::acl::IoLinuxTksAcl1;
+use crate::tks_error::TksError;
+use log::{debug, error, trace};
+use uuid::Uuid;
+
+fn exe_path_of(bus_name: &str) -> Result<String, TksError> {
+    let process = TksClientProcess::from_bus_name(bus_name.to_string())?;
+    Ok(process.exe_path().to_string_lossy().into_owned())
+}
+
+fn describe(access: Access, item: Option<&Uuid>, label: &str) -> String {
+    let verb = match access {
+        Access::Read => "read the secret",
+        Access::Write => "change",
+        Access::Delete => "delete",
+    };
+    match (item, access) {
+        (Some(_), Access::Read) => format!("{} of '{}'", verb, label),
+        (Some(_), _) => format!("{} '{}'", verb, label),
+        (None, Access::Read) => format!("{}s of the items of '{}'", verb, label),
+        (None, _) => format!("{} the items of '{}'", verb, label),
+    }
+}
+
+/// Checks the client being served may access the collection, or one of its items, asking the user
+/// when the ACL doesn't grant it yet. Fails with PermissionDenied when the user refused. The
+/// service's own calls, which have no client, are always let in.
+pub fn check(collection: &Uuid, item: Option<&Uuid>, access: Access) -> Result<(), TksError> {
+    let Some(sender) = audit::caller_bus_name() else {
+        return Ok(());
+    };
+    // asking the user may take a while, so nothing stays locked meanwhile
+    let (acl, own_acl, label) = STORAGE.read().unwrap().with_collection(collection, |c| {
+        let (own_acl, label) = match item {
+            Some(item) => {
+                let item = c.get_item(item)?;
+                (item.acl.as_ref().map(|_| item.id.uuid), item.label.clone())
+            }
+            None => (None, c.label().to_string()),
+        };
+        Ok((c.acl_of(item)?.cloned(), own_acl, label))
+    })?;
+    let Some(acl) = acl else {
+        return Ok(());
+    };
+    let exe_path = exe_path_of(&sender)?;
+    if acl.allows(&exe_path, access) {
+        return Ok(());
+    }
+    let allowed = prompter::current().confirm(
+        "Allow",
+        "Deny",
+        &format!(
+            "{} asks to {}. Allow it from now on?",
+            exe_path,
+            describe(access, item, &label)
+        ),
+    )?;
+    if !allowed {
+        debug!("{} may not {} {}", exe_path, access, collection);
+        return Err(TksError::PermissionDenied);
+    }
+    let granted = STORAGE
+        .write()
+        .unwrap()
+        .modify_acl(collection, own_acl.as_ref(), |acl| {
+            acl.get_or_insert_with(Acl::default)
+                .grant(&exe_path, access)
+        });
+    if let Err(e) = granted {
+        // allowed this time nevertheless, as the user just said so
+        error!("Cannot save the {} access of {}: {}", access, exe_path, e);
+    }
+    Ok(())
+}
+
+/// Asks the user whether the client being served may loosen an ACL
+fn confirm_change(what: &str) -> Result<(), TksError> {
+    let client = match audit::caller_bus_name() {
+        Some(sender) => exe_path_of(&sender)?,
+        None => return Ok(()),
+    };
+    match prompter::current().confirm(
+        "Allow",
+        "Deny",
+        &format!("{} asks to {}. Allow it?", client, what),
+    )? {
+        true => Ok(()),
+        false => Err(TksError::PermissionDenied),
+    }
+}
+
+fn label_of(collection: &Uuid, item: Option<&Uuid>) -> Result<String, TksError> {
+    STORAGE
+        .read()
+        .unwrap()
+        .with_collection(collection, |c| match item {
+            Some(item) => Ok(c.get_item(item)?.label.clone()),
+            None => Ok(c.label().to_string()),
+        })
+}
+
+fn parse_accesses(accesses: &[String]) -> Result<Vec<Access>, TksError> {
+    accesses.iter().map(|a| a.parse()).collect()
+}
+
+fn restricted(collection: &Uuid, item: Option<&Uuid>) -> Result<bool, TksError> {
+    Ok(STORAGE.read().unwrap().acl_of(collection, item)?.is_some())
+}
+
+fn inherited(collection: &Uuid, item: &Uuid) -> Result<bool, TksError> {
+    STORAGE.read().unwrap().with_collection(collection, |c| {
+        Ok(c.get_item(item)?.acl.is_none() && c.acl.is_some())
+    })
+}
+
+fn get_acl(collection: &Uuid, item: Option<&Uuid>) -> Result<Vec<(String, Vec<String>)>, TksError> {
+    let acl = STORAGE
+        .read()
+        .unwrap()
+        .acl_of(collection, item)?
+        .unwrap_or_default();
+    Ok(acl
+        .entries()
+        .into_iter()
+        .map(|(exe_path, granted)| (exe_path, granted.iter().map(Access::to_string).collect()))
+        .collect())
+}
+
+/// Restricted items get a copy of the ACL of their collection to start with
+fn set_restricted(
+    collection: &Uuid,
+    item: Option<&Uuid>,
+    restricted: bool,
+) -> Result<(), TksError> {
+    trace!("set_restricted {} {:?} {}", collection, item, restricted);
+    if !restricted {
+        let label = label_of(collection, item)?;
+        confirm_change(&format!("lift the access control list of '{}'", label))?;
+    }
+    let mut storage = STORAGE.write().unwrap();
+    let inherited = match item {
+        Some(_) => storage.acl_of(collection, None)?,
+        None => None,
+    };
+    storage.modify_acl(collection, item, |acl| match restricted {
+        true => {
+            acl.get_or_insert_with(|| inherited.unwrap_or_default());
+        }
+        false => *acl = None,
+    })
+}
+
+/// Grants go to the ACL of the object, which gets one if needed
+fn grant(
+    collection: &Uuid,
+    item: Option<&Uuid>,
+    exe: &str,
+    accesses: &[String],
+) -> Result<(), TksError> {
+    trace!(
+        "grant {:?} to {} on {} {:?}",
+        accesses,
+        exe,
+        collection,
+        item
+    );
+    let accesses = parse_accesses(accesses)?;
+    let accesses = match accesses.is_empty() {
+        true => Access::ALL.to_vec(),
+        false => accesses,
+    };
+    let label = label_of(collection, item)?;
+    for access in &accesses {
+        confirm_change(&format!("let {} {}", exe, describe(*access, item, &label)))?;
+    }
+    let mut storage = STORAGE.write().unwrap();
+    let inherited = match item {
+        Some(_) => storage.acl_of(collection, None)?,
+        None => None,
+    };
+    storage.modify_acl(collection, item, |acl| {
+        let acl = acl.get_or_insert_with(|| inherited.unwrap_or_default());
+        accesses.iter().for_each(|access| acl.grant(exe, *access));
+    })
+}
+
+fn revoke(
+    collection: &Uuid,
+    item: Option<&Uuid>,
+    exe: &str,
+    accesses: &[String],
+) -> Result<bool, TksError> {
+    trace!(
+        "revoke {:?} from {} on {} {:?}",
+        accesses,
+        exe,
+        collection,
+        item
+    );
+    let accesses = parse_accesses(accesses)?;
+    STORAGE
+        .write()
+        .unwrap()
+        .modify_acl(collection, item, |acl| match acl {
+            Some(acl) => acl.revoke(exe, &accesses),
+            None => false,
+        })
+}
+
+impl IoLinuxTksAcl1 for CollectionImpl {
+    fn restricted(&self) -> Result<bool, dbus::MethodErr> {
+        Ok(restricted(&self.uuid, None)?)
+    }
+    fn inherited(&self) -> Result<bool, dbus::MethodErr> {
+        Ok(false)
+    }
+    fn get_acl(&mut self) -> Result<Vec<(String, Vec<String>)>, dbus::MethodErr> {
+        Ok(get_acl(&self.uuid, None)?)
+    }
+    fn set_restricted(&mut self, restricted: bool) -> Result<(), dbus::MethodErr> {
+        Ok(set_restricted(&self.uuid, None, restricted)?)
+    }
+    fn grant(&mut self, exe: String, access: Vec<String>) -> Result<(), dbus::MethodErr> {
+        Ok(grant(&self.uuid, None, &exe, &access)?)
+    }
+    fn revoke(&mut self, exe: String, access: Vec<String>) -> Result<bool, dbus::MethodErr> {
+        Ok(revoke(&self.uuid, None, &exe, &access)?)
+    }
+}
+
+impl IoLinuxTksAcl1 for ItemImpl {
+    fn restricted(&self) -> Result<bool, dbus::MethodErr> {
+        Ok(restricted(
+            &self.item_id.collection_uuid,
+            Some(&self.item_id.uuid),
+        )?)
+    }
+    fn inherited(&self) -> Result<bool, dbus::MethodErr> {
+        Ok(inherited(
+            &self.item_id.collection_uuid,
+            &self.item_id.uuid,
+        )?)
+    }
+    fn get_acl(&mut self) -> Result<Vec<(String, Vec<String>)>, dbus::MethodErr> {
+        Ok(get_acl(
+            &self.item_id.collection_uuid,
+            Some(&self.item_id.uuid),
+        )?)
+    }
+    fn set_restricted(&mut self, restricted: bool) -> Result<(), dbus::MethodErr> {
+        Ok(set_restricted(
+            &self.item_id.collection_uuid,
+            Some(&self.item_id.uuid),
+            restricted,
+        )?)
+    }
+    fn grant(&mut self, exe: String, access: Vec<String>) -> Result<(), dbus::MethodErr> {
+        Ok(grant(
+            &self.item_id.collection_uuid,
+            Some(&self.item_id.uuid),
+            &exe,
+            &access,
+        )?)
+    }
+    fn revoke(&mut self, exe: String, access: Vec<String>) -> Result<bool, dbus::MethodErr> {
+        Ok(revoke(
+            &self.item_id.collection_uuid,
+            Some(&self.item_id.uuid),
+            &exe,
+            &access,
+        )?)
+    }
+}
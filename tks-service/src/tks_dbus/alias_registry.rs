@@ -0,0 +1,92 @@
+//! Besides its canonical `/org/freedesktop/secrets/collection/<uuid>` object, a collection has an
+//! object at `/org/freedesktop/secrets/aliases/<alias>` for each of its aliases, as the Secret
+//! Service spec describes; the default collection always has the `default` one. The objects follow
+//! the aliases saved in the storage: [update] moves them after anything changing the aliases, i.e.
+//! SetAlias, creating or reusing a collection, and restoring a backup. Deleting a collection drops
+//! its objects along with its aliases.
+
+use crate::storage::memory::{SESSION_COLLECTION_PATH, SESSION_COLLECTION_UUID};
+use crate::storage::Storage;
+use crate::tks_dbus::collection_impl::{self, CollectionImpl};
+use crate::tks_dbus::object_manager::{self, COLLECTION_HANDLES};
+use crate::tks_dbus::{sanitize_string, CROSSROADS};
+use log::trace;
+use uuid::Uuid;
+
+pub const DEFAULT_ALIAS: &str = "default";
+pub const DEFAULT_ALIAS_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+
+/// The path of the object of an alias; the empty alias has none
+pub fn alias_path(alias: &str) -> Option<dbus::Path<'static>> {
+    (!alias.is_empty()).then(|| {
+        dbus::Path::from(format!("/org/freedesktop/secrets/aliases/{}", sanitize_string(alias)))
+    })
+}
+
+/// The path made of the collection's uuid; the session collection has a well-known one instead
+pub fn canonical_path(uuid: &Uuid) -> dbus::Path<'static> {
+    if *uuid == SESSION_COLLECTION_UUID {
+        return dbus::Path::from(SESSION_COLLECTION_PATH);
+    }
+    dbus::Path::from(format!(
+        "/org/freedesktop/secrets/collection/{}",
+        sanitize_string(&uuid.to_string())
+    ))
+}
+
+/// The paths of a collection: those of its aliases, the default one first, then its canonical
+/// path
+pub(crate) fn collection_paths(
+    uuid: &Uuid,
+    default: bool,
+    aliases: &[String],
+) -> Vec<dbus::Path<'static>> {
+    let mut paths = Vec::new();
+    let aliases = aliases.iter().map(String::as_str);
+    for path in default.then_some(DEFAULT_ALIAS).into_iter().chain(aliases).filter_map(alias_path) {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    paths.push(canonical_path(uuid));
+    paths
+}
+
+/// Makes the alias objects of the registered collections match the aliases saved in the storage
+pub fn update(storage: &Storage) {
+    let mut handles = COLLECTION_HANDLES.lock().unwrap();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for collection in &storage.collections {
+        let aliases = collection.aliases.as_deref().unwrap_or_default();
+        let paths = collection_paths(&collection.uuid, collection.default, aliases);
+        // the others get their objects once first used
+        handles.modify(&collection.uuid, |handle| {
+            if paths == handle.paths {
+                return;
+            }
+            removed.extend(handle.paths.iter().filter(|p| !paths.contains(p)).cloned());
+            let former = std::mem::replace(&mut handle.paths, paths);
+            handle.default = collection.default;
+            for path in handle.paths.iter().filter(|p| !former.contains(p)) {
+                added.push((path.clone(), handle.clone()));
+            }
+        });
+    }
+    if removed.is_empty() && added.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut cr_lock = CROSSROADS.lock().unwrap();
+        // an alias moving to another collection gets removed first
+        for path in removed {
+            trace!("Unregistering {}", path);
+            object_manager::remove::<CollectionImpl>(&mut cr_lock, &path);
+        }
+        let itfs = object_manager::tokens(&mut cr_lock, collection_impl::INTERFACES);
+        for (path, handle) in added {
+            trace!("Registering {} for collection {}", path, handle.uuid);
+            object_manager::insert(&mut cr_lock, path, &itfs, handle);
+        }
+    });
+}
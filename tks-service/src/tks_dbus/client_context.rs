@@ -1,19 +1,25 @@
 use crate::tks_dbus::prompt_impl::{
     ConfirmationMessageActionParam, PromptAction, PromptDialog, PromptWithPinentry, TksPrompt,
 };
+use crate::hex;
+use crate::settings::SETTINGS;
+use crate::storage::{Storage, STORAGE};
+use crate::tks_dbus::sandbox::Sandbox;
+use crate::tks_dbus;
 use crate::tks_error::TksError;
 use dbus::arg::{PropMap, RefArg, Variant};
 use dbus_crossroads::Context;
 use lazy_static::lazy_static;
 use log::{debug, error, trace};
 use openssl::sha;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::hash::{Hash, Hasher};
 use std::io::Read;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use sysinfo::Pid;
 use sysinfo::ProcessRefreshKind;
 use sysinfo::RefreshKind;
@@ -22,7 +28,13 @@ use tokio::task;
 #[derive(Clone, Debug)]
 pub struct TksClientProcess {
     name: String,
+    /// Name of the process, as shown to the user
+    process_name: String,
+    /// Process executable, or the identity of a sandboxed client, see [crate::tks_dbus::sandbox]
     exe_path: OsString,
+    /// Hex encoded SHA-256 of the executable
+    exe_sha256: String,
+    sandbox: Option<Sandbox>,
 }
 
 pub enum TksClientOption {
@@ -30,11 +42,77 @@ pub enum TksClientOption {
     Client(TksClient),
 }
 
-/// Information about the TKS client process
-/// TODO hold the calling process binary SHA (and have it automatically updated upon system update?)
-/// TODO retrieve method below should check actuall caller has the same SHA as when enrolled
-#[derive(Clone, Debug)]
-pub struct TksClient {}
+/// A client the user let in, by process executable. An executable changed since, e.g. by an
+/// update, needs to be let in again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TksClient {
+    /// Name of the process, as shown to the user
+    pub name: String,
+    pub exe_path: OsString,
+    /// Hex encoded SHA-256 of the executable when the user let it in
+    pub exe_sha256: String,
+}
+
+/// Name of the private file of the storage holding the known clients
+const CLIENTS_FILE: &str = "clients";
+
+/// The clients the user let in, saved encrypted with the key of the `[storage]` backend
+#[derive(Debug, Default)]
+pub struct KnownClients {
+    clients: HashMap<OsString, TksClient>,
+}
+
+impl KnownClients {
+    /// Reads the clients back; `None` while the storage is locked. Backends without private files
+    /// don't remember the clients across restarts.
+    pub fn load(storage: &Storage) -> Result<Option<KnownClients>, TksError> {
+        let clients: Vec<TksClient> = match storage.read_private_file(CLIENTS_FILE) {
+            Ok(Some(data)) => serde_json::from_slice(&data)?,
+            Ok(None) | Err(TksError::NotSupported(_)) => Vec::new(),
+            Err(TksError::PermissionDenied) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let clients = clients
+            .into_iter()
+            .map(|c| (c.exe_path.clone(), c))
+            .collect();
+        Ok(Some(KnownClients { clients }))
+    }
+
+    pub fn save(&self, storage: &Storage) -> Result<(), TksError> {
+        match storage.write_private_file(CLIENTS_FILE, &serde_json::to_vec(&self.list())?) {
+            Err(TksError::NotSupported(what)) => {
+                debug!("The known clients won't survive a restart, {} aren't supported", what);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Whether the user let this very executable in
+    pub fn contains(&self, client: &TksClient) -> bool {
+        self.clients.get(&client.exe_path) == Some(client)
+    }
+
+    pub fn get(&self, exe_path: &OsStr) -> Option<&TksClient> {
+        self.clients.get(exe_path)
+    }
+
+    pub fn insert(&mut self, client: TksClient) {
+        self.clients.insert(client.exe_path.clone(), client);
+    }
+
+    pub fn remove(&mut self, exe_path: &OsStr) -> Option<TksClient> {
+        self.clients.remove(exe_path)
+    }
+
+    /// The clients, sorted by executable
+    pub fn list(&self) -> Vec<TksClient> {
+        let mut clients: Vec<_> = self.clients.values().cloned().collect();
+        clients.sort_by(|a, b| a.exe_path.cmp(&b.exe_path));
+        clients
+    }
+}
 
 pub struct EnrollClientPrompt {
     client_process: TksClientProcess,
@@ -61,72 +139,259 @@ impl EnrollClientPrompt {
     }
 }
 
-/// This holds the known clients
-/// TODO store contents encrypted on disk and load it upon service start
+/// Longest time a client gets turned down after being denied, unless denied permanently
+pub const MAX_DENIAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Denial {
+    /// How many times in a row the user denied the client
+    pub count: u32,
+    /// When the client may prompt again; `None` once denied permanently
+    pub until: Option<SystemTime>,
+}
+
+/// Clients the user denied access to, by process executable. They get turned down without
+/// prompting until their denial expires, so that a client retrying in a loop doesn't flood the
+/// user with confirmations.
+#[derive(Default)]
+pub struct ClientDenials {
+    denials: HashMap<OsString, Denial>,
+}
+
+impl ClientDenials {
+    pub fn new() -> ClientDenials {
+        ClientDenials::default()
+    }
+
+    /// Remembers the user denied the client. The first denial lasts `ttl`, each further one in
+    /// a row twice as long as the previous one, up to [MAX_DENIAL].
+    pub fn deny(&mut self, exe_path: &OsStr, now: SystemTime, ttl: Duration) {
+        let denial = self.denials.entry(exe_path.into()).or_insert(Denial {
+            count: 0,
+            until: Some(now),
+        });
+        denial.count = denial.count.saturating_add(1);
+        if denial.until.is_some() {
+            let backoff = 2u32.saturating_pow(denial.count - 1);
+            let duration = ttl.saturating_mul(backoff).min(MAX_DENIAL);
+            denial.until = Some(now + duration);
+        }
+    }
+
+    pub fn deny_permanently(&mut self, exe_path: &OsStr) {
+        let denial = self.denials.entry(exe_path.into()).or_insert(Denial {
+            count: 0,
+            until: None,
+        });
+        denial.until = None;
+    }
+
+    /// Lets the client prompt again, returns false if it wasn't denied
+    pub fn forget(&mut self, exe_path: &OsStr) -> bool {
+        self.denials.remove(exe_path).is_some()
+    }
+
+    pub fn is_denied(&self, exe_path: &OsStr, now: SystemTime) -> bool {
+        match self.denials.get(exe_path) {
+            Some(Denial { until: None, .. }) => true,
+            Some(Denial {
+                until: Some(until), ..
+            }) => *until > now,
+            None => false,
+        }
+    }
+
+    /// The denied clients, sorted by executable; expired denials are kept, as they make the
+    /// next denial last longer
+    pub fn list(&self) -> Vec<(OsString, Denial)> {
+        let mut denials: Vec<_> = self
+            .denials
+            .iter()
+            .map(|(exe_path, denial)| (exe_path.clone(), denial.clone()))
+            .collect();
+        denials.sort_by(|a, b| a.0.cmp(&b.0));
+        denials
+    }
+}
+
+/// This holds the known clients. They're encrypted with the storage key, so they get read back
+/// once the storage gets unlocked; until then, the clients get let in again.
 pub struct ClientRegistry {
-    known_clients: HashMap<OsString, TksClient>,
+    known_clients: KnownClients,
+    /// Whether the saved clients were read back
+    loaded: bool,
+    pub denials: ClientDenials,
 }
 
 impl ClientRegistry {
     fn new() -> ClientRegistry {
         ClientRegistry {
-            known_clients: HashMap::new(),
+            known_clients: KnownClients::default(),
+            loaded: false,
+            denials: ClientDenials::new(),
+        }
+    }
+
+    /// Reads the saved clients back, if not done yet and the storage is unlocked. The clients let
+    /// in meanwhile get saved with them.
+    fn load(&mut self) {
+        if self.loaded {
+            return;
+        }
+        let storage = STORAGE.read().unwrap();
+        match KnownClients::load(&storage) {
+            Ok(Some(mut saved)) => {
+                trace!("Loaded {} known clients", saved.clients.len());
+                let enrolled = self.known_clients.list();
+                let unsaved = !enrolled.is_empty();
+                enrolled.into_iter().for_each(|c| saved.insert(c));
+                self.known_clients = saved;
+                self.loaded = true;
+                if unsaved {
+                    self.save(&storage);
+                }
+            }
+            Ok(None) => trace!("Storage locked, the known clients can't be read yet"),
+            Err(e) => {
+                // overwritten upon the next enrollment, rather than prompting for all the clients
+                // each time
+                error!("Cannot read the known clients: {}", e);
+                self.loaded = true;
+            }
         }
     }
+
+    fn save(&self, storage: &Storage) {
+        if let Err(e) = self.known_clients.save(storage) {
+            error!("Cannot save the known clients: {}", e);
+        }
+    }
+
+    /// Lets the client in from now on
+    pub fn enroll(&mut self, client: TksClient) {
+        trace!("Registering client {}", client.exe_path.to_string_lossy());
+        self.load();
+        self.denials.forget(&client.exe_path);
+        self.known_clients.insert(client);
+        if self.loaded {
+            self.save(&STORAGE.read().unwrap());
+        }
+    }
+
+    /// Whether the user already let this client in
+    pub fn is_known(&mut self, client: &TksClient) -> bool {
+        self.load();
+        self.known_clients.contains(client)
+    }
+
+    /// The clients let in, failing while the storage is locked
+    pub fn list(&mut self) -> Result<Vec<TksClient>, TksError> {
+        self.load();
+        match self.loaded {
+            true => Ok(self.known_clients.list()),
+            false => Err(TksError::PermissionDenied),
+        }
+    }
+
+    /// Makes the client ask to be let in again; returns false if it wasn't known
+    pub fn revoke(&mut self, exe_path: &OsStr) -> Result<bool, TksError> {
+        self.load();
+        if !self.loaded {
+            return Err(TksError::PermissionDenied);
+        }
+        if self.known_clients.remove(exe_path).is_none() {
+            return Ok(false);
+        }
+        self.known_clients.save(&STORAGE.read().unwrap())?;
+        Ok(true)
+    }
+
+    /// Called when the user refused to let the client in; `clients.denial_timeout` 0 keeps
+    /// prompting each time instead
+    pub fn deny(&mut self, exe_path: &OsStr) {
+        let ttl = SETTINGS.lock().unwrap().clients.denial_timeout;
+        if ttl > 0 {
+            debug!("Client {:?} denied", exe_path);
+            self.denials
+                .deny(exe_path, SystemTime::now(), Duration::from_secs(ttl));
+        }
+    }
+
+    /// Whether the user already let the client in
+    pub fn is_enrolled(&mut self, process: &TksClientProcess) -> bool {
+        self.is_known(&process.client())
+    }
+
+    /// The process of the caller, failing with PermissionDenied unless the user already let it
+    /// in; for the calls which can't return the prompt enrolling it
+    pub fn enrolled_caller(&mut self, ctx: &mut Context) -> Result<TksClientProcess, TksError> {
+        let process = TksClientProcess::new(ctx)?;
+        if !self.is_enrolled(&process) {
+            debug!("Client {:?} is not enrolled", process.exe_path);
+            return Err(TksError::PermissionDenied);
+        }
+        Ok(process)
+    }
+
     pub fn retrieve(
         self: &mut ClientRegistry,
         ctx: &mut Context,
     ) -> Result<TksClientOption, TksError> {
+        match self.enroll_action(ctx)? {
+            (_, Some(action)) => Ok(TksClientOption::Prompt(
+                PromptWithPinentry::new(action)?.to_string(),
+            )),
+            (client, None) => Ok(TksClientOption::Client(client)),
+        }
+    }
+
+    /// The client of the call, with the action asking the user to let it in unless it's known;
+    /// for the callers running the dialogs themselves, rather than returning a prompt
+    pub fn enroll_action(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<(TksClient, Option<PromptAction>), TksError> {
         let process = TksClientProcess::new(ctx)?;
+        if self.denials.is_denied(&process.exe_path, SystemTime::now()) {
+            debug!("Client {:?} was denied, not prompting", process.exe_path);
+            return Err(TksError::PermissionDenied);
+        }
 
-        match self.known_clients.get(&process.exe_path) {
-            Some(client) => {
-                // TODO also check the client process executable's SHA to
-                // ensure no spoofing is taking place
-                Ok(TksClientOption::Client(client.clone()))
-            }
-            None => {
-                // new client process
-                let action = PromptAction {
-                    dialog: PromptDialog::ConfirmationMessage(
-                        "Yes".into(),
-                        "No".into(),
-                        format!(
-                            "An application having the process \
-                        executable {:?} wants to let Tks handle their secrets\
-                        . Should we accept this?",
-                            process.exe_path
-                        )
-                        .into(),
-                        ConfirmationMessageActionParam::ConfirmNewClient(process.exe_path),
-                        |param| {
-                            match param {
-                                ConfirmationMessageActionParam::ConfirmNewClient(exe_path) => {
-                                    trace!("Registering client {}", exe_path.to_string_lossy());
-                                    // TODO we should check if meanwhile a same path client has been added here
-                                    // and that it is the same SHA; if not, then dismiss the operation
-                                    let client = TksClient {};
-                                    CLIENT_REGISTRY
-                                        .lock()
-                                        .unwrap()
-                                        .known_clients
-                                        .insert(exe_path.clone(), client);
-                                    Ok(false) // we succeeded, but we don't dismiss this dialog
-                                }
-                                _ => {
-                                    error!("Unexpected confirmation message param: {:?}", param);
-                                    assert!(false);
-                                    Ok(true)
-                                }
-                            }
-                        },
-                    ),
-                };
-                Ok(TksClientOption::Prompt(
-                    PromptWithPinentry::new(action)?.to_string(),
-                ))
-            }
+        let client = process.client();
+        if self.is_known(&client) {
+            return Ok((client, None));
+        }
+        let changed = self.known_clients.get(&client.exe_path).is_some();
+        if changed {
+            debug!("Client {:?} executable changed since enrolled", client.exe_path);
         }
+        // new client process
+        let action = PromptAction {
+            dialog: PromptDialog::ConfirmationMessage(
+                "Yes".into(),
+                "No".into(),
+                format!(
+                    "{} wants to let Tks handle their secrets\
+                {}. Should we accept this?",
+                    process.describe(),
+                    match changed {
+                        true => ", but the executable changed since you accepted it",
+                        false => "",
+                    }
+                ),
+                ConfirmationMessageActionParam::ConfirmNewClient(client.clone()),
+                |param| {
+                    match param {
+                        ConfirmationMessageActionParam::ConfirmNewClient(client) => {
+                            CLIENT_REGISTRY.lock().unwrap().enroll(client.clone());
+                            Ok(false) // we succeeded, but we don't dismiss this dialog
+                        }
+                        _ => unreachable!("Unexpected confirmation message param: {:?}", param),
+                    }
+                },
+            ),
+        };
+        Ok((client, Some(action)))
     }
 }
 
@@ -143,7 +408,37 @@ impl TksClientProcess {
             .ok_or_else(|| TksError::ContextError("Cannot get message sender"))
             .unwrap()
             .to_string();
-        let conn = dbus::blocking::Connection::new_session()?;
+        TksClientProcess::from_bus_name(name)
+    }
+
+    pub fn exe_path(&self) -> &OsStr {
+        &self.exe_path
+    }
+
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    /// The client, as shown to the user
+    pub fn describe(&self) -> String {
+        match &self.sandbox {
+            Some(sandbox) => sandbox.describe(),
+            None => format!("An application having the process executable {:?}", self.exe_path),
+        }
+    }
+
+    /// The client to let in, as of now
+    pub fn client(&self) -> TksClient {
+        TksClient {
+            name: self.process_name.clone(),
+            exe_path: self.exe_path.clone(),
+            exe_sha256: self.exe_sha256.clone(),
+        }
+    }
+
+    /// The process behind a unique bus name
+    pub fn from_bus_name(name: String) -> Result<TksClientProcess, TksError> {
+        let conn = tks_dbus::blocking_connection()?;
         let proxy = conn.with_proxy(
             "org.freedesktop.DBus",
             "/org/freedesktop/DBus",
@@ -159,38 +454,48 @@ impl TksClientProcess {
         let s = sysinfo::System::new_with_specifics(
             RefreshKind::new().with_processes(ProcessRefreshKind::everything()),
         );
+        let pid = credentials
+            .get("ProcessID")
+            .ok_or(TksError::ContextError("No ProcessID found"))?
+            .as_i64()
+            .ok_or(TksError::ContextError("No Process ID number"))? as u32;
         let caller_process = s
-            .process(Pid::from_u32(
-                credentials
-                    .get("ProcessID")
-                    .ok_or_else(|| TksError::ContextError("No ProcessID found"))?
-                    .as_i64()
-                    .ok_or_else(|| TksError::ContextError("No Process ID number"))?
-                    as u32,
-            ))
-            .ok_or_else(|| TksError::ContextError("No Process ID number"))?;
+            .process(Pid::from_u32(pid))
+            .ok_or(TksError::ContextError("No Process ID number"))?;
         debug!("Caller process: {:?}", caller_process);
         let exe_path = caller_process
             .exe()
             .ok_or_else(|| TksError::ContextError("No EXE path"))?;
         debug!("Caller process path: {:?}", exe_path);
 
+        let sandbox = Sandbox::of(pid);
+        if let Some(sandbox) = &sandbox {
+            debug!("Caller process sandboxed as {}", sandbox.identity());
+        }
+
+        // the path may only exist inside the mount namespace of a sandboxed caller
         let mut hasher = sha::Sha256::new();
-        let mut exe_file = std::fs::File::open(exe_path)?;
+        let mut exe_file = std::fs::File::open(format!("/proc/{}/exe", pid))?;
         loop {
             let mut chunk = vec![0u8; 1024];
             let n = exe_file.read(&mut chunk)?;
             if n == 0 {
                 break;
             };
-            hasher.update(chunk.as_slice());
+            hasher.update(&chunk[..n]);
         }
-        let exe_sha = hasher.finish();
-        debug!("Call process hash: {:?}", exe_sha);
+        let exe_sha256 = hex::encode(&hasher.finish());
+        debug!("Call process hash: {}", exe_sha256);
 
         Ok(TksClientProcess {
             name,
-            exe_path: exe_path.into(),
+            process_name: caller_process.name().to_string(),
+            exe_path: match &sandbox {
+                Some(sandbox) => sandbox.identity().into(),
+                None => exe_path.into(),
+            },
+            exe_sha256,
+            sandbox,
         })
     }
 }
@@ -1,26 +1,40 @@
+use crate::audit;
+use crate::audit::{AuditEvent, Outcome};
+use crate::settings::{Quirk, SETTINGS};
+use crate::storage::acl::Access;
 use crate::storage::collection::Collection;
+use crate::storage::item_schemas;
 use crate::storage::STORAGE;
 use crate::tks_dbus::fdo::collection::register_org_freedesktop_secret_collection;
 use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollection;
 use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollectionItemCreated;
+use crate::tks_dbus::acl;
 use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::tks::acl::register_io_linux_tks_acl1;
+use crate::tks_dbus::tks::collection::{
+    register_io_linux_tks_collection1, IoLinuxTksCollection1, IoLinuxTksCollection1SequenceChanged,
+};
+use crate::tks_dbus::alias_registry;
+use crate::tks_dbus::object_manager;
+use crate::tks_dbus::object_manager::{ManagedObject, COLLECTION_HANDLES, ITEM_HANDLES};
+use crate::tks_dbus::owner_tracker;
+use crate::tks_dbus::quirks;
+use crate::tks_dbus::owner_tracker::LOCK_ON_OWNER_EXIT_PROPERTY;
+use crate::tks_dbus::service_impl::ServiceImpl;
 use crate::tks_dbus::session_impl::SESSION_MANAGER;
 use crate::tks_dbus::DBusHandle;
 use crate::tks_dbus::DBusHandlePath::MultiplePaths;
-use crate::tks_dbus::CROSSROADS;
 use crate::tks_dbus::MESSAGE_SENDER;
-use crate::tks_dbus::{DBusHandlePath, sanitize_string};
-use crate::register_object;
+use crate::tks_dbus::DBusHandlePath;
+use crate::tks_dbus::{emit_properties_changed, prop_value, property_caller};
 use arg::cast;
-use dbus::arg::RefArg;
+use dbus::arg::{PropMap, RefArg};
 use dbus::message::SignalArgs;
 use dbus::{arg, Path};
-use dbus_crossroads::Context;
-use lazy_static::lazy_static;
+use dbus_crossroads::{Context, Crossroads, IfaceToken, PropContext};
 use log::{debug, error, trace, warn};
 use std::collections::HashMap;
-use std::io::ErrorKind;
-use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use uuid::Uuid;
 use crate::tks_error::TksError;
 
@@ -31,31 +45,128 @@ pub struct CollectionImpl {
     pub paths: Vec<dbus::Path<'static>>,
 }
 
-lazy_static! {
-    pub static ref COLLECTION_HANDLES: Arc<Mutex<HashMap<Uuid, CollectionImpl>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-}
+/// The interfaces of the collection objects, at each of their paths
+pub(crate) const INTERFACES: &[fn(&mut Crossroads) -> IfaceToken<CollectionImpl>] = &[
+    register_org_freedesktop_secret_collection,
+    register_io_linux_tks_collection1,
+    register_io_linux_tks_acl1,
+];
+
+/// The properties of org.freedesktop.Secret.Collection
+const PROPERTIES: &[&str] = &["Items", "Label", "Locked", "Created", "Modified"];
 
 impl CollectionImpl {
-    fn new(uuid: &Uuid, default: bool) -> CollectionImpl {
-        let mut handle = CollectionImpl {
+    fn new(uuid: &Uuid, default: bool, aliases: &[String]) -> CollectionImpl {
+        let handle = CollectionImpl {
             uuid: uuid.clone(),
             default,
-            paths: vec![dbus::Path::from(format!(
-                "/org/freedesktop/secrets/collection/{}",
-                sanitize_string(&uuid.to_string()).as_str()
-            ))],
+            paths: alias_registry::collection_paths(uuid, default, aliases),
         };
-        default.then(|| {
-            // the default path should always be kept the first in the vector
-            handle.paths.insert(
-                0,
-                dbus::Path::from("/org/freedesktop/secrets/aliases/default"),
+        object_manager::register_managed(INTERFACES, handle.clone());
+        handle
+    }
+    /// Removes the DBus objects of a deleted collection
+    pub fn unregister(uuid: &Uuid) -> Option<CollectionImpl> {
+        let handle = COLLECTION_HANDLES.lock().unwrap().remove(uuid)?;
+        object_manager::unregister_managed(handle.clone());
+        Some(handle)
+    }
+    /// The uuid of the collection at the given path, be it its canonical path, an alias path or
+    /// the path of one of its items; unknown paths resolve to none
+    pub fn resolve(path: &dbus::Path) -> Option<Uuid> {
+        let collection = COLLECTION_HANDLES.lock().unwrap().find(path).map(|c| c.uuid);
+        collection.or_else(|| {
+            ITEM_HANDLES
+                .lock()
+                .unwrap()
+                .find(path)
+                .map(|i| i.item_id.collection_uuid)
+        })
+    }
+    /// The `/org/freedesktop/secrets/collection/<uuid>` path, without any alias
+    pub fn canonical_path(&self) -> dbus::Path<'static> {
+        // aliases are always inserted before the canonical path
+        self.paths.last().unwrap().clone()
+    }
+    /// The path listed by the Collections property: the default alias path for the default
+    /// collection, the canonical path for the others
+    pub fn listed_path(&self) -> dbus::Path<'static> {
+        match self.default {
+            true => dbus::Path::from(alias_registry::DEFAULT_ALIAS_PATH),
+            false => self.canonical_path(),
+        }
+    }
+    /// Lets the clients know the collection got saved, so they can refresh their caches
+    pub fn emit_sequence_changed(collection_uuid: Uuid) {
+        tokio::spawn(async move {
+            let sequence = STORAGE
+                .read()
+                .unwrap()
+                .with_collection(&collection_uuid, |collection| Ok(collection.sequence));
+            match sequence {
+                Ok(sequence) => {
+                    debug!("Sending SequenceChanged signal");
+                    let path = CollectionImpl::from(&collection_uuid).canonical_path();
+                    MESSAGE_SENDER.lock().unwrap().send_message(
+                        IoLinuxTksCollection1SequenceChanged { sequence }.to_emit_message(&path),
+                    );
+                }
+                Err(e) => error!("Cannot read collection {} sequence: {}", collection_uuid, e),
+            }
+        });
+        // each save also touches the modification timestamp
+        CollectionImpl::emit_properties_changed(collection_uuid, &["Modified"]);
+    }
+    /// The Label property, as the client having the given unique bus name expects it
+    fn label_for(&self, sender: Option<&str>) -> Result<String, dbus::MethodErr> {
+        let label_is_alias = quirks::applies(sender, Quirk::LabelIsAlias);
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid.clone(), |collection| {
+                let alias = collection.aliases.as_ref().and_then(|a| a.first());
+                Ok(match alias {
+                    Some(alias) if label_is_alias => alias.clone(),
+                    _ => collection.label().to_string(),
+                })
+            })
+            .map_err(|e| {
+                error!("Error retrieving collectioni {}: {}", self.uuid, e);
+                e.into()
+            })
+    }
+    /// The current values of the given Collection properties, as the client having the given
+    /// unique bus name sees them; those failing to read get left out
+    fn property_values(&self, properties: &[&str], sender: Option<&str>) -> PropMap {
+        let mut values = PropMap::new();
+        for property in properties {
+            let value = match *property {
+                "Items" => self.items().map(prop_value),
+                "Label" => self.label_for(sender).map(prop_value),
+                "Locked" => self.locked().map(prop_value),
+                "Created" => self.created().map(prop_value),
+                "Modified" => self.modified().map(prop_value),
+                _ => Err(dbus::MethodErr::no_property(property)),
+            };
+            match value {
+                Ok(value) => {
+                    values.insert(property.to_string(), value);
+                }
+                Err(e) => error!("Cannot read collection property {}: {:?}", property, e),
+            }
+        }
+        values
+    }
+    /// Sends PropertiesChanged with the current values of the given Collection properties
+    pub fn emit_properties_changed(collection_uuid: Uuid, properties: &'static [&'static str]) {
+        tokio::spawn(async move {
+            let collection = CollectionImpl::from(&collection_uuid);
+            emit_properties_changed(
+                &collection.paths,
+                CollectionImpl::INTERFACE,
+                collection.property_values(properties, None),
             );
         });
-        let handle_clone = handle.clone();
-        register_object!(register_org_freedesktop_secret_collection, handle_clone);
-        handle
     }
     // IMPORTANT: this checks if collection object has a default value, and not that if this
     // instance corresponds to the default collection!
@@ -67,37 +178,16 @@ impl CollectionImpl {
 impl From<&Collection> for CollectionImpl {
     fn from(collection: &Collection) -> CollectionImpl {
         let uuid = collection.uuid;
-        let is_new = !COLLECTION_HANDLES.lock().unwrap().contains_key(&uuid);
-        is_new.then(|| {
-            COLLECTION_HANDLES
-                .lock()
-                .unwrap()
-                .insert(uuid.clone(), CollectionImpl::new(&uuid, collection.default));
-        });
-        COLLECTION_HANDLES
-            .lock()
-            .unwrap()
-            .get(&uuid)
-            .unwrap()
-            .clone()
+        let aliases = collection.aliases.as_deref().unwrap_or_default();
+        object_manager::handle(&COLLECTION_HANDLES, uuid, || {
+            CollectionImpl::new(&uuid, collection.default, aliases)
+        })
     }
 }
 
 impl From<&Uuid> for CollectionImpl {
     fn from(uuid: &Uuid) -> CollectionImpl {
-        let is_new = !COLLECTION_HANDLES.lock().unwrap().contains_key(&uuid);
-        is_new.then(|| {
-            COLLECTION_HANDLES
-                .lock()
-                .unwrap()
-                .insert(uuid.clone(), CollectionImpl::new(uuid, false));
-        });
-        COLLECTION_HANDLES
-            .lock()
-            .unwrap()
-            .get(&uuid)
-            .unwrap()
-            .clone()
+        object_manager::handle(&COLLECTION_HANDLES, *uuid, || CollectionImpl::new(uuid, false, &[]))
     }
 }
 
@@ -106,13 +196,25 @@ impl From<&dbus::Path<'_>> for CollectionImpl {
         COLLECTION_HANDLES
             .lock()
             .unwrap()
-            .clone()
-            .into_values()
-            .find(|c| c.paths.contains(p))
+            .find(p)
+            .cloned()
             .unwrap_or_default()
     }
 }
 
+impl ManagedObject for CollectionImpl {
+    const INTERFACE: &'static str = "org.freedesktop.Secret.Collection";
+    fn managed_path(&self) -> dbus::Path<'static> {
+        self.listed_path()
+    }
+    fn collection_uuid(&self) -> Uuid {
+        self.uuid
+    }
+    fn properties(&self, sender: Option<&str>) -> PropMap {
+        self.property_values(PROPERTIES, sender)
+    }
+}
+
 impl DBusHandle for CollectionImpl {
     fn path(&self) -> DBusHandlePath {
         warn!("CollectionHandle::path() called");
@@ -131,13 +233,12 @@ impl OrgFreedesktopSecretCollection for CollectionImpl {
         attributes: ::std::collections::HashMap<String, String>,
     ) -> Result<Vec<dbus::Path<'static>>, dbus::MethodErr> {
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_collection(&self.uuid, |collection| {
                 Ok(collection
-                    .items
-                    .iter()
-                    .filter(|item| item.attributes == attributes)
+                    .search(&attributes)
+                    .into_iter()
                     .map(|item| ItemImpl::from(item).path().into())
                     .collect::<Vec<dbus::Path>>())
             })
@@ -161,6 +262,7 @@ impl OrgFreedesktopSecretCollection for CollectionImpl {
             debug!("Collection is locked, aborting create_item");
             return Err(dbus::MethodErr::failed("Collection is locked"));
         }
+        acl::check(&self.uuid, None, Access::Write)?;
         let sender = ctx
             .message()
             .sender()
@@ -203,20 +305,27 @@ impl OrgFreedesktopSecretCollection for CollectionImpl {
             .parse::<usize>()
             .map_err(|_| dbus::MethodErr::failed(&"Invalid session ID"))?;
 
-        CollectionImpl::create_item(
-            self.uuid,
-            secret,
-            replace,
-            item_label,
-            item_attributes,
-            session_id,
-            sender,
-        )
-        .map_err(|e| e.into())
+        let schemas = SETTINGS.lock().unwrap().schemas.clone();
+        item_schemas::check(&schemas, &item_attributes)
+            .and_then(|_| {
+                CollectionImpl::create_item(
+                    self.uuid,
+                    secret,
+                    replace,
+                    item_label,
+                    item_attributes,
+                    session_id,
+                    sender,
+                )
+            })
+            .map_err(|e| {
+                audit::record(AuditEvent::ItemCreate, Outcome::Failure, vec![self.uuid]);
+                e.into()
+            })
     }
     fn items(&self) -> Result<Vec<dbus::Path<'static>>, dbus::MethodErr> {
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_collection(&self.uuid.clone(), |collection| {
                 Ok(collection
@@ -233,50 +342,191 @@ impl OrgFreedesktopSecretCollection for CollectionImpl {
                 e.into()
             })
     }
-    fn label(&self) -> Result<String, dbus::MethodErr> {
-        STORAGE
-            .lock()
-            .unwrap()
-            .with_collection(&self.uuid.clone(), |collection| Ok(collection.name.clone()))
-            .map_err(|e| {
-                error!("Error retrieving collectioni {}: {}", self.uuid, e);
-                e.into()
-            })
+    fn label(&self, ctx: &mut PropContext) -> Result<String, dbus::MethodErr> {
+        let sender = property_caller(ctx);
+        self.label_for(sender.as_deref())
     }
     fn set_label(&self, value: String) -> Result<(), dbus::MethodErr> {
+        acl::check(&self.uuid, None, Access::Write)?;
         STORAGE
-            .lock()
+            .write()
             .unwrap()
             .modify_collection(&self.uuid, |collection| {
-                collection.name = value;
+                collection.label = Some(value);
                 Ok(())
             })
+            .map(|_| {
+                CollectionImpl::emit_properties_changed(self.uuid, &["Label"]);
+                CollectionImpl::emit_sequence_changed(self.uuid);
+            })
             .map_err(|e| e.into())
     }
 
     fn locked(&self) -> Result<bool, dbus::MethodErr> {
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_collection(&self.uuid, |collection| Ok(collection.locked))
             .map_err(|e| e.into())
     }
     fn created(&self) -> Result<u64, dbus::MethodErr> {
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_collection(&self.uuid.clone(), |collection| Ok(collection.created))
             .map_err(|e| e.into())
     }
     fn modified(&self) -> Result<u64, dbus::MethodErr> {
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_collection(&self.uuid.clone(), |collection| Ok(collection.modified))
             .map_err(|e| e.into())
     }
 }
 
+impl IoLinuxTksCollection1 for CollectionImpl {
+    fn sequence(&self) -> Result<u64, dbus::MethodErr> {
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid, |collection| Ok(collection.sequence))
+            .map_err(|e| e.into())
+    }
+    fn owner(&self) -> Result<String, dbus::MethodErr> {
+        Ok(owner_tracker::owner(&self.uuid).unwrap_or_default())
+    }
+    fn set_owner(&mut self, lock_on_exit: bool, ctx: &mut Context) -> Result<(), dbus::MethodErr> {
+        let sender = ctx
+            .message()
+            .sender()
+            .ok_or(dbus::MethodErr::failed("Unknown sender"))?
+            .to_string();
+        trace!("set_owner {} for {} (lock on exit: {})", sender, self.uuid, lock_on_exit);
+        STORAGE.write().unwrap().modify_collection(&self.uuid, |collection| {
+            collection
+                .properties
+                .insert(LOCK_ON_OWNER_EXIT_PROPERTY.to_string(), lock_on_exit.to_string());
+            Ok(())
+        })?;
+        owner_tracker::set_owner(self.uuid, Some(sender));
+        CollectionImpl::emit_sequence_changed(self.uuid);
+        Ok(())
+    }
+    fn release_owner(&mut self, ctx: &mut Context) -> Result<(), dbus::MethodErr> {
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        if owner_tracker::owner(&self.uuid) != sender {
+            return Err(dbus::MethodErr::failed("Only the owner may release the collection"));
+        }
+        owner_tracker::set_owner(self.uuid, None);
+        Ok(())
+    }
+    fn properties(&self) -> Result<HashMap<String, String>, dbus::MethodErr> {
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid, |collection| Ok(collection.properties.clone()))
+            .map_err(|e| e.into())
+    }
+    fn folders(&self) -> Result<Vec<String>, dbus::MethodErr> {
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid, |collection| {
+                Ok(collection.folders().cloned().collect())
+            })
+            .map_err(|e| e.into())
+    }
+    fn list_folder(
+        &mut self,
+        folder: String,
+    ) -> Result<(Vec<String>, Vec<dbus::Path<'static>>), dbus::MethodErr> {
+        trace!("list_folder '{}' of {}", folder, self.uuid);
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid, |collection| {
+                let (subfolders, items) = collection
+                    .list_folder(&folder)
+                    .ok_or(TksError::NotFound(format!("No folder '{}'", folder).into()))?;
+                let items = items.into_iter().map(|i| ItemImpl::from(i).path().into()).collect();
+                Ok((subfolders, items))
+            })
+            .map_err(|e| e.into())
+    }
+    fn get_history(&mut self) -> Result<Vec<(u64, String, String, String)>, dbus::MethodErr> {
+        trace!("get_history of {}", self.uuid);
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid, |collection| {
+                Ok(collection
+                    .history
+                    .iter()
+                    .map(|e| {
+                        let client = e.client.clone().unwrap_or_default();
+                        (e.time, e.operation.to_string(), e.label.clone(), client)
+                    })
+                    .collect())
+            })
+            .map_err(|e| e.into())
+    }
+    fn list_trash(&mut self) -> Result<Vec<(String, String, u64)>, dbus::MethodErr> {
+        trace!("list_trash of {}", self.uuid);
+        STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&self.uuid, |collection| {
+                Ok(collection
+                    .trash
+                    .iter()
+                    .map(|t| (t.item.id.uuid.to_string(), t.item.label.clone(), t.deleted))
+                    .collect())
+            })
+            .map_err(|e| e.into())
+    }
+    fn restore_item(&mut self, uuid: String) -> Result<dbus::Path<'static>, dbus::MethodErr> {
+        trace!("restore_item {} of {}", uuid, self.uuid);
+        let uuid = Uuid::parse_str(&uuid).map_err(|_| dbus::MethodErr::invalid_arg(&uuid))?;
+        acl::check(&self.uuid, None, Access::Write)?;
+        let result = STORAGE
+            .write()
+            .unwrap()
+            .modify_collection(&self.uuid, |collection| collection.restore_item(&uuid));
+        audit::record(AuditEvent::ItemCreate, (&result).into(), vec![uuid]);
+        let item_id = result?;
+        ItemImpl::register(&item_id);
+        CollectionImpl::emit_properties_changed(self.uuid, &["Items"]);
+        CollectionImpl::emit_sequence_changed(self.uuid);
+        Ok(ItemImpl::from(&item_id).path)
+    }
+    fn purge_trash(&mut self, uuids: Vec<String>) -> Result<u32, dbus::MethodErr> {
+        trace!("purge_trash {:?} of {}", uuids, self.uuid);
+        let uuids = uuids
+            .iter()
+            .map(|u| Uuid::parse_str(u).map_err(|_| dbus::MethodErr::invalid_arg(u)))
+            .collect::<Result<Vec<_>, _>>()?;
+        acl::check(&self.uuid, None, Access::Delete)?;
+        let result = STORAGE
+            .write()
+            .unwrap()
+            .modify_collection(&self.uuid, |collection| collection.purge_trash(&uuids));
+        audit::record(AuditEvent::ItemDelete, (&result).into(), uuids);
+        let purged = result?;
+        CollectionImpl::emit_sequence_changed(self.uuid);
+        Ok(purged as u32)
+    }
+    fn begin_batch(&mut self, timeout: u32) -> Result<(), dbus::MethodErr> {
+        trace!("begin_batch on {} for {}s", self.uuid, timeout);
+        let timeout = Duration::from_secs(timeout.into());
+        Ok(STORAGE.write().unwrap().begin_batch(&self.uuid, audit::caller(), timeout)?)
+    }
+    fn end_batch(&mut self) -> Result<(), dbus::MethodErr> {
+        trace!("end_batch on {}", self.uuid);
+        Ok(STORAGE.write().unwrap().end_batch(&self.uuid, &audit::caller())?)
+    }
+}
+
 impl CollectionImpl {
     fn create_item(
         collection_uuid: Uuid,
@@ -288,14 +538,11 @@ impl CollectionImpl {
         sender: String,
     ) -> Result<(dbus::Path, dbus::Path), TksError> {
         trace!("create_item");
+        let collection_changed =
+            quirks::applies(Some(&sender), Quirk::CollectionChangedOnNewItem);
         let sm = SESSION_MANAGER.lock().unwrap();
-        let session = sm.sessions.get(session_id).ok_or_else(|| {
-            std::io::Error::new(
-                ErrorKind::Other,
-                format!("Session {} not found", session_id),
-            )
-        })?;
-        let mut storage = STORAGE.lock()?;
+        let session = sm.get_session(session_id, &sender)?;
+        let mut storage = STORAGE.write()?;
         storage
             .modify_collection(&collection_uuid, |collection| {
                 collection.create_item(
@@ -308,6 +555,7 @@ impl CollectionImpl {
             })
             .and_then(|item_id| {
                 debug!("Item created: {}", item_id.uuid);
+                audit::record(AuditEvent::ItemCreate, Outcome::Success, vec![item_id.uuid]);
                 let item_path = ItemImpl::from(&item_id).path();
                 let item_path_clone = item_path.clone();
                 tokio::spawn(async move {
@@ -319,6 +567,11 @@ impl CollectionImpl {
                         .to_emit_message(&item_path_clone.into()),
                     );
                 });
+                CollectionImpl::emit_properties_changed(collection_uuid, &["Items"]);
+                CollectionImpl::emit_sequence_changed(collection_uuid);
+                if collection_changed {
+                    ServiceImpl::emit_collection_changed(collection_uuid);
+                }
                 Ok((item_path.into(), dbus::Path::from("/")))
             })
     }
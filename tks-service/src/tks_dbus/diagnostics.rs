@@ -0,0 +1,40 @@
+//! Counters telling how much the service holds on to, for hunting the leaks in the field: the
+//! DBus objects should come and go with the collections, items, sessions and prompts, so a count
+//! growing while the others don't points at handles never cleaned up.
+
+use crate::tks_dbus::object_manager::{object_count, COLLECTION_HANDLES, ITEM_HANDLES};
+use crate::tks_dbus::prompt_impl::PROMPTS;
+use crate::tks_dbus::session_impl::SESSION_MANAGER;
+use log::debug;
+use std::ops::Deref;
+use sysinfo::ProcessRefreshKind;
+
+/// The counters, by name:
+/// - `objects`: the DBus objects, the service included
+/// - `collection_handles`, `item_handles`: the collections and items having DBus objects
+/// - `sessions`: the sessions not closed yet
+/// - `prompts`: the prompts not completed yet
+/// - `resident_memory`, `virtual_memory`: the memory used by the service, in bytes; 0 when it
+///   could not be found
+pub fn counters() -> Vec<(&'static str, u64)> {
+    let (resident_memory, virtual_memory) = memory_usage().unwrap_or_default();
+    vec![
+        ("objects", object_count() as u64),
+        ("collection_handles", COLLECTION_HANDLES.lock().unwrap().len() as u64),
+        ("item_handles", ITEM_HANDLES.lock().unwrap().len() as u64),
+        ("sessions", SESSION_MANAGER.lock().unwrap().sessions.len() as u64),
+        ("prompts", PROMPTS.lock().deref().borrow().len() as u64),
+        ("resident_memory", resident_memory),
+        ("virtual_memory", virtual_memory),
+    ]
+}
+
+fn memory_usage() -> Option<(u64, u64)> {
+    let pid = sysinfo::get_current_pid()
+        .map_err(|e| debug!("Cannot get the service pid: {}", e))
+        .ok()?;
+    let mut s = sysinfo::System::new();
+    s.refresh_process_specifics(pid, ProcessRefreshKind::new().with_memory());
+    let process = s.process(pid)?;
+    Some((process.memory(), process.virtual_memory()))
+}
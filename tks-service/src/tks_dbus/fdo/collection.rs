@@ -2,7 +2,7 @@
 use dbus;
 use dbus::arg;
 use dbus_crossroads as crossroads;
-use dbus_crossroads::Context;
+use dbus_crossroads::{Context, PropContext};
 
 pub trait OrgFreedesktopSecretCollection {
     fn delete(&mut self) -> Result<dbus::Path<'static>, dbus::MethodErr>;
@@ -18,7 +18,7 @@ pub trait OrgFreedesktopSecretCollection {
         ctx: &mut Context,
     ) -> Result<(dbus::Path<'static>, dbus::Path<'static>), dbus::MethodErr>;
     fn items(&self) -> Result<Vec<dbus::Path<'static>>, dbus::MethodErr>;
-    fn label(&self) -> Result<String, dbus::MethodErr>;
+    fn label(&self, ctx: &mut PropContext) -> Result<String, dbus::MethodErr>;
     fn set_label(&self, value: String) -> Result<(), dbus::MethodErr>;
     fn locked(&self) -> Result<bool, dbus::MethodErr>;
     fn created(&self) -> Result<u64, dbus::MethodErr>;
@@ -124,7 +124,7 @@ where
         b.property::<Vec<dbus::Path<'static>>, _>("Items")
             .get(|_, t| t.items());
         b.property::<String, _>("Label")
-            .get(|_, t| t.label())
+            .get(|ctx, t| t.label(ctx))
             .set(|_, t, value| t.set_label(value).map(|_| None));
         b.property::<bool, _>("Locked").get(|_, t| t.locked());
         b.property::<u64, _>("Created").get(|_, t| t.created());
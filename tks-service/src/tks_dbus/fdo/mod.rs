@@ -1,5 +1,7 @@
 pub mod collection;
 pub mod item;
+pub mod object_manager;
+pub mod portal_secret;
 pub mod prompt;
 pub mod service;
 pub mod session;
@@ -0,0 +1,97 @@
+// This code was autogenerated with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+use dbus_crossroads::Context;
+
+pub trait OrgFreedesktopDBusObjectManager {
+    fn get_managed_objects(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<
+        ::std::collections::HashMap<
+            dbus::Path<'static>,
+            ::std::collections::HashMap<String, arg::PropMap>,
+        >,
+        dbus::MethodErr,
+    >;
+}
+
+#[derive(Debug)]
+pub struct OrgFreedesktopDBusObjectManagerInterfacesAdded {
+    pub object: dbus::Path<'static>,
+    pub interfaces: ::std::collections::HashMap<String, arg::PropMap>,
+}
+
+impl arg::AppendAll for OrgFreedesktopDBusObjectManagerInterfacesAdded {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.object, i);
+        arg::RefArg::append(&self.interfaces, i);
+    }
+}
+
+impl arg::ReadAll for OrgFreedesktopDBusObjectManagerInterfacesAdded {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgFreedesktopDBusObjectManagerInterfacesAdded {
+            object: i.read()?,
+            interfaces: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgFreedesktopDBusObjectManagerInterfacesAdded {
+    const NAME: &'static str = "InterfacesAdded";
+    const INTERFACE: &'static str = "org.freedesktop.DBus.ObjectManager";
+}
+
+#[derive(Debug)]
+pub struct OrgFreedesktopDBusObjectManagerInterfacesRemoved {
+    pub object: dbus::Path<'static>,
+    pub interfaces: Vec<String>,
+}
+
+impl arg::AppendAll for OrgFreedesktopDBusObjectManagerInterfacesRemoved {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.object, i);
+        arg::RefArg::append(&self.interfaces, i);
+    }
+}
+
+impl arg::ReadAll for OrgFreedesktopDBusObjectManagerInterfacesRemoved {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgFreedesktopDBusObjectManagerInterfacesRemoved {
+            object: i.read()?,
+            interfaces: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgFreedesktopDBusObjectManagerInterfacesRemoved {
+    const NAME: &'static str = "InterfacesRemoved";
+    const INTERFACE: &'static str = "org.freedesktop.DBus.ObjectManager";
+}
+
+pub fn register_org_freedesktop_dbus_object_manager<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: OrgFreedesktopDBusObjectManager + Send + 'static,
+{
+    cr.register("org.freedesktop.DBus.ObjectManager", |b| {
+        b.signal::<(
+            dbus::Path<'static>,
+            ::std::collections::HashMap<String, arg::PropMap>,
+        ), _>("InterfacesAdded", ("object", "interfaces"));
+        b.signal::<(dbus::Path<'static>, Vec<String>), _>(
+            "InterfacesRemoved",
+            ("object", "interfaces"),
+        );
+        b.method(
+            "GetManagedObjects",
+            (),
+            ("objects",),
+            |ctx, t: &mut T, ()| t.get_managed_objects(ctx).map(|x| (x,)),
+        );
+    })
+}
@@ -0,0 +1,57 @@
+// This code was generated from org.freedesktop.impl.portal.Secret.xml with
+// `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs, then edited: RetrieveSecret may
+// reply once the collection got unlocked.
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+use dbus_crossroads::Context;
+
+pub trait OrgFreedesktopImplPortalSecret {
+    /// `None` when the reply gets sent later on, through the message of `ctx`
+    fn retrieve_secret(
+        &mut self,
+        ctx: &mut Context,
+        handle: dbus::Path<'static>,
+        app_id: String,
+        fd: arg::OwnedFd,
+        options: arg::PropMap,
+    ) -> Result<Option<(u32, arg::PropMap)>, dbus::MethodErr>;
+    fn version(&self) -> Result<u32, dbus::MethodErr>;
+}
+
+pub fn register_org_freedesktop_impl_portal_secret<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: OrgFreedesktopImplPortalSecret + Send + 'static,
+{
+    cr.register("org.freedesktop.impl.portal.Secret", |b| {
+        b.method_with_cr_custom::<
+            (dbus::Path<'static>, String, arg::OwnedFd, arg::PropMap),
+            (u32, arg::PropMap),
+            _,
+            _,
+        >(
+            "RetrieveSecret",
+            ("handle", "app_id", "fd", "options"),
+            ("response", "results"),
+            |mut ctx, cr, (handle, app_id, fd, options)| {
+                let retrieved = ctx.check(|ctx| {
+                    let t: &mut T = cr
+                        .data_mut(ctx.path())
+                        .ok_or_else(|| dbus::MethodErr::no_path(ctx.path()))?;
+                    t.retrieve_secret(ctx, handle, app_id, fd, options)
+                });
+                match retrieved {
+                    Ok(Some(reply)) => ctx.do_reply(|msg| msg.append_all(reply)),
+                    Ok(None) => return None,
+                    Err(()) => {}
+                }
+                Some(ctx)
+            },
+        );
+        b.property::<u32, _>("version")
+            .get(|_, t: &mut T| t.version());
+    })
+}
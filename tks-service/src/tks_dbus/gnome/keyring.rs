@@ -0,0 +1,106 @@
+// This code was autogenerated with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+use dbus_crossroads::Context;
+
+pub trait OrgGnomeKeyringInternalUnsupportedGuiltRiddenInterface {
+    fn change_with_master_password(
+        &mut self,
+        ctx: &mut Context,
+        collection: dbus::Path<'static>,
+        original: (dbus::Path<'static>, Vec<u8>, Vec<u8>, String),
+        master: (dbus::Path<'static>, Vec<u8>, Vec<u8>, String),
+    ) -> Result<(), dbus::MethodErr>;
+    fn change_with_prompt(
+        &mut self,
+        ctx: &mut Context,
+        collection: dbus::Path<'static>,
+    ) -> Result<dbus::Path<'static>, dbus::MethodErr>;
+    fn create_with_master_password(
+        &mut self,
+        ctx: &mut Context,
+        attributes: arg::PropMap,
+        master: (dbus::Path<'static>, Vec<u8>, Vec<u8>, String),
+    ) -> Result<dbus::Path<'static>, dbus::MethodErr>;
+    fn unlock_with_master_password(
+        &mut self,
+        ctx: &mut Context,
+        collection: dbus::Path<'static>,
+        master: (dbus::Path<'static>, Vec<u8>, Vec<u8>, String),
+    ) -> Result<(), dbus::MethodErr>;
+}
+
+pub fn register_org_gnome_keyring_internal_unsupported_guilt_ridden_interface<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: OrgGnomeKeyringInternalUnsupportedGuiltRiddenInterface + Send + 'static,
+{
+    cr.register(
+        "org.gnome.keyring.InternalUnsupportedGuiltRiddenInterface",
+        |b| {
+            b.method(
+                "ChangeWithMasterPassword",
+                ("collection", "original", "master"),
+                (),
+                |ctx, t: &mut T, (collection, original, master)| {
+                    t.change_with_master_password(ctx, collection, original, master)
+                },
+            );
+            b.method(
+                "ChangeWithPrompt",
+                ("collection",),
+                ("prompt",),
+                |ctx, t: &mut T, (collection,)| t.change_with_prompt(ctx, collection).map(|x| (x,)),
+            );
+            b.method(
+                "CreateWithMasterPassword",
+                ("attributes", "master"),
+                ("collection",),
+                |ctx, t: &mut T, (attributes, master)| {
+                    t.create_with_master_password(ctx, attributes, master)
+                        .map(|x| (x,))
+                },
+            );
+            b.method(
+                "UnlockWithMasterPassword",
+                ("collection", "master"),
+                (),
+                |ctx, t: &mut T, (collection, master)| {
+                    t.unlock_with_master_password(ctx, collection, master)
+                },
+            );
+        },
+    )
+}
+
+pub trait OrgGnomeKeyringDaemon {
+    fn get_environment(
+        &mut self,
+    ) -> Result<::std::collections::HashMap<String, String>, dbus::MethodErr>;
+    fn get_control_directory(&mut self) -> Result<String, dbus::MethodErr>;
+}
+
+pub fn register_org_gnome_keyring_daemon<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: OrgGnomeKeyringDaemon + Send + 'static,
+{
+    cr.register("org.gnome.keyring.Daemon", |b| {
+        b.method(
+            "GetEnvironment",
+            (),
+            ("environment",),
+            |_, t: &mut T, ()| t.get_environment().map(|x| (x,)),
+        );
+        b.method(
+            "GetControlDirectory",
+            (),
+            ("directory",),
+            |_, t: &mut T, ()| t.get_control_directory().map(|x| (x,)),
+        );
+    })
+}
@@ -0,0 +1,141 @@
+//! The gnome-keyring-daemon extensions to the Secret Service some applications still call. The
+//! org.gnome.keyring.InternalUnsupportedGuiltRiddenInterface of the service object lets a client
+//! create or unlock a collection with a password it sends, e.g. Seahorse or a keyring setup tool,
+//! instead of having the user prompted. TKS has one unlock password per storage backend, not per
+//! collection: the password unlocks all the collections of the backend, and changing it isn't
+//! supported.
+//!
+//! Once `gnome_keyring.enabled` is set, the service also takes the org.gnome.keyring name, so that
+//! the session managers asking the daemon for its environment get an answer rather than starting
+//! gnome-keyring-daemon.
+
+use crate::audit;
+use crate::audit::AuditEvent;
+use crate::storage::STORAGE;
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::fdo::service::OrgFreedesktopSecretService;
+use crate::tks_dbus::gnome::keyring::*;
+use crate::tks_dbus::service_impl::ServiceImpl;
+use crate::tks_dbus::session_impl::{SessionImpl, SESSION_MANAGER};
+use crate::tks_dbus::visibility;
+use crate::tks_error::TksError;
+use dbus::arg;
+use dbus::MethodErr;
+use dbus_crossroads::Context;
+use log::{debug, trace};
+use secrecy::SecretString;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Bus name of gnome-keyring-daemon
+pub const BUS_NAME: &str = "org.gnome.keyring";
+
+/// Object path of the org.gnome.keyring.Daemon interface
+pub const DAEMON_PATH: &str = "/org/gnome/keyring/daemon";
+
+type Secret = (dbus::Path<'static>, Vec<u8>, Vec<u8>, String);
+
+/// The password a client sent, decrypted with its session
+fn master_password(ctx: &Context, secret: Secret) -> Result<SecretString, MethodErr> {
+    let session_id = SessionImpl::id_of(&secret.0)?;
+    let sender = ctx
+        .message()
+        .sender()
+        .ok_or_else(|| MethodErr::failed("Sender Unknown"))?
+        .to_string();
+    let sm = SESSION_MANAGER.lock().unwrap();
+    let session = sm.get_session(session_id, &sender)?;
+    let password = session.decrypt(&secret.1, &secret.2, sender)?;
+    let password = String::from_utf8(password).map_err(|_| MethodErr::invalid_arg("master"))?;
+    Ok(SecretString::new(password))
+}
+
+/// The collection at the path, unless it's hidden from the caller
+fn collection_of(ctx: &Context, path: &dbus::Path) -> Result<Uuid, MethodErr> {
+    let sender = ctx.message().sender().map(|s| s.to_string());
+    match CollectionImpl::resolve(path) {
+        Some(uuid) if !visibility::hidden_from(sender.as_deref()).contains(&uuid) => Ok(uuid),
+        _ => Err(TksError::NotFound(Some(format!("Collection {} not found", path))).into()),
+    }
+}
+
+/// Unlocks the collection, and those sharing its backend, unless it's unlocked already
+fn unlock_with(uuid: Uuid, password: SecretString) -> Result<(), MethodErr> {
+    let locked = STORAGE
+        .read()
+        .unwrap()
+        .with_collection(&uuid, |c| Ok(c.locked))?;
+    if !locked {
+        return Ok(());
+    }
+    let result = STORAGE
+        .write()
+        .unwrap()
+        .unlock_backend_with_password(&uuid, password);
+    audit::record(AuditEvent::CollectionUnlock, (&result).into(), vec![uuid]);
+    Ok(result?)
+}
+
+impl OrgGnomeKeyringInternalUnsupportedGuiltRiddenInterface for ServiceImpl {
+    fn change_with_master_password(
+        &mut self,
+        _ctx: &mut Context,
+        collection: dbus::Path<'static>,
+        _original: Secret,
+        _master: Secret,
+    ) -> Result<(), MethodErr> {
+        debug!("Refusing to change the password of {}", collection);
+        Err(TksError::NotSupported("changing the password of a collection").into())
+    }
+    fn change_with_prompt(
+        &mut self,
+        _ctx: &mut Context,
+        collection: dbus::Path<'static>,
+    ) -> Result<dbus::Path<'static>, MethodErr> {
+        debug!("Refusing to change the password of {}", collection);
+        Err(TksError::NotSupported("changing the password of a collection").into())
+    }
+    fn create_with_master_password(
+        &mut self,
+        ctx: &mut Context,
+        attributes: arg::PropMap,
+        master: Secret,
+    ) -> Result<dbus::Path<'static>, MethodErr> {
+        trace!("create_with_master_password");
+        let password = master_password(ctx, master)?;
+        let (collection, prompt) = self.create_collection(ctx, attributes, String::new())?;
+        if &*prompt != "/" {
+            // the prompt offering to reuse the collection stays unused, nobody can answer it
+            return Err(MethodErr::failed(
+                "A collection with this label already exists",
+            ));
+        }
+        let uuid = CollectionImpl::resolve(&collection).ok_or(TksError::NotFound(None))?;
+        unlock_with(uuid, password)?;
+        Ok(collection)
+    }
+    fn unlock_with_master_password(
+        &mut self,
+        ctx: &mut Context,
+        collection: dbus::Path<'static>,
+        master: Secret,
+    ) -> Result<(), MethodErr> {
+        trace!("unlock_with_master_password {}", collection);
+        let uuid = collection_of(ctx, &collection)?;
+        unlock_with(uuid, master_password(ctx, master)?)
+    }
+}
+
+/// The org.gnome.keyring.Daemon object
+#[derive(Default)]
+pub struct GnomeKeyringDaemon {}
+
+impl OrgGnomeKeyringDaemon for GnomeKeyringDaemon {
+    /// gnome-keyring-daemon gives its SSH agent and control socket, TKS has neither
+    fn get_environment(&mut self) -> Result<HashMap<String, String>, MethodErr> {
+        Ok(HashMap::new())
+    }
+    fn get_control_directory(&mut self) -> Result<String, MethodErr> {
+        Ok(String::new())
+    }
+}
@@ -1,56 +1,188 @@
 // Purpose: Provides an implementation of the DBus interface for a secret item.
-use crate::register_object;
+use crate::audit;
+use crate::audit::{AuditEvent, Outcome};
+use crate::settings::SETTINGS;
+use crate::storage::acl::Access;
 use crate::storage::collection::Item;
 use crate::storage::collection::ItemId;
+use crate::storage::item_schemas;
 use crate::storage::STORAGE;
+use crate::tks_dbus::acl;
+use crate::tks_dbus::alias_registry;
+use crate::tks_dbus::collection_impl::CollectionImpl;
 use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollectionItemChanged;
+use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollectionItemCreated;
 use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollectionItemDeleted;
 use crate::tks_dbus::fdo::item::register_org_freedesktop_secret_item;
 use crate::tks_dbus::fdo::item::OrgFreedesktopSecretItem;
-use crate::tks_dbus::session_impl::SESSION_MANAGER;
+use crate::tks_dbus::object_manager;
+use crate::tks_dbus::object_manager::{ManagedObject, ITEM_HANDLES};
+use crate::tks_dbus::plain_transfers;
+use crate::tks_dbus::tks::acl::register_io_linux_tks_acl1;
+use crate::tks_dbus::tks::item::{register_io_linux_tks_item1, IoLinuxTksItem1};
+use crate::tks_dbus::session_impl::{SessionImpl, SESSION_MANAGER};
 use crate::tks_dbus::DBusHandle;
 use crate::tks_dbus::DBusHandlePath::SinglePath;
-use crate::tks_dbus::CROSSROADS;
 use crate::tks_dbus::MESSAGE_SENDER;
+use crate::tks_dbus::{emit_properties_changed, prop_value};
 use crate::tks_dbus::{sanitize_string, DBusHandlePath};
+use crate::tks_error::TksError;
+use dbus::arg::PropMap;
 use dbus::message::SignalArgs;
 use dbus::{MethodErr, Path};
 use dbus_crossroads::Context;
-use lazy_static::lazy_static;
-use log::error;
 use log::{debug, trace};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Default)]
 pub struct ItemImpl {
-    item_id: ItemId,
+    pub(crate) item_id: ItemId,
     pub(crate) path: dbus::Path<'static>,
 }
 
-lazy_static! {
-    pub static ref ITEM_HANDLES: Arc<Mutex<HashMap<Uuid, ItemImpl>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-}
+/// A secret as sent over DBus: its session, parameters, value and content type
+type Secret = (dbus::Path<'static>, Vec<u8>, Vec<u8>, String);
+
+/// The properties of org.freedesktop.Secret.Item
+const PROPERTIES: &[&str] = &["Locked", "Attributes", "Label", "Type", "Created", "Modified"];
 
 impl ItemImpl {
     fn new(item_id: &ItemId) -> Self {
         assert!(!item_id.collection_uuid.is_nil());
         let handle = ItemImpl {
-            path: format!(
-                "/org/freedesktop/secrets/collection/{}/{}",
-                sanitize_string(&item_id.collection_uuid.to_string()),
-                sanitize_string(&item_id.uuid.to_string())
-            )
-            .to_string()
-            .into(),
+            path: ItemImpl::item_path(item_id),
             item_id: item_id.clone(),
         };
-        let handle_clone = handle.clone();
-        register_object!(register_org_freedesktop_secret_item, handle_clone);
+        object_manager::register_managed(
+            &[
+                register_org_freedesktop_secret_item,
+                register_io_linux_tks_acl1,
+                register_io_linux_tks_item1,
+            ],
+            handle.clone(),
+        );
         handle
     }
+    /// Fails unless the ACL of the item lets the caller in, see [acl::check]; refusals get
+    /// audited as the given event
+    fn check_acl(&self, access: Access, event: Option<AuditEvent>) -> Result<(), MethodErr> {
+        let id = &self.item_id;
+        acl::check(&id.collection_uuid, Some(&id.uuid), access).map_err(|e| {
+            if let Some(event) = event {
+                audit::record(event, Outcome::Failure, vec![id.uuid]);
+            }
+            e.into()
+        })
+    }
+    fn item_path(item_id: &ItemId) -> dbus::Path<'static> {
+        format!(
+            "{}/{}",
+            alias_registry::canonical_path(&item_id.collection_uuid),
+            sanitize_string(&item_id.uuid.to_string())
+        )
+        .into()
+    }
+    /// Removes the DBus object of an item which is no longer stored under this id, then lets the
+    /// clients know about it
+    pub(crate) fn unregister(item_id: &ItemId) {
+        let handle = ITEM_HANDLES.lock().unwrap().remove(&item_id.uuid);
+        let handle = handle.unwrap_or_else(|| ItemImpl {
+            path: ItemImpl::item_path(item_id),
+            item_id: item_id.clone(),
+        });
+        let path = handle.path.clone();
+        object_manager::unregister_managed(handle);
+        tokio::spawn(async move {
+            debug!("Sending ItemDeleted signal");
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretCollectionItemDeleted { item: path.clone() }
+                    .to_emit_message(&path),
+            );
+        });
+    }
+    /// Registers the DBus object of an item now stored under this id, then lets the clients know
+    /// about it
+    pub(crate) fn register(item_id: &ItemId) {
+        let path = ItemImpl::from(item_id).path;
+        tokio::spawn(async move {
+            debug!("Sending ItemCreated signal");
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretCollectionItemCreated { item: path.clone() }
+                    .to_emit_message(&path),
+            );
+        });
+    }
+    /// The secrets of the items, for GetSecrets. As the specification tells, the locked items
+    /// get left out, and so do the unknown ones and those the client may not read, instead of
+    /// failing the whole call. The storage gets read in one pass before asking the user about the
+    /// restricted or sensitive items, nothing staying locked meanwhile, then in another pass
+    /// encrypting the secrets.
+    pub fn get_secrets(
+        items: &[ItemImpl],
+        session: &dbus::Path<'static>,
+        sender: &str,
+    ) -> Result<HashMap<dbus::Path<'static>, Secret>, dbus::MethodErr> {
+        let session_id = SessionImpl::id_of(session)?;
+        let plain = SESSION_MANAGER.lock().unwrap().get_session(session_id, sender)?.is_plain();
+        let ids: Vec<ItemId> = items.iter().map(|i| i.item_id.clone()).collect();
+        let mut refused = Vec::new();
+        let mut candidates = Vec::new();
+        STORAGE.read().unwrap().for_each_item(&ids, |collection, item| {
+            if item.locked {
+                refused.push(item.id.uuid);
+                return;
+            }
+            let restricted = item.acl.is_some() || collection.acl.is_some();
+            let confirm = plain && plain_transfers::is_sensitive(&item.attributes);
+            candidates.push((item.id.clone(), item.label.clone(), restricted, confirm));
+        });
+
+        let mut readable = Vec::new();
+        for (id, label, restricted, confirm) in candidates {
+            let mut allowed = Ok(());
+            if restricted {
+                allowed = acl::check(&id.collection_uuid, Some(&id.uuid), Access::Read);
+            }
+            if confirm && allowed.is_ok() {
+                allowed = plain_transfers::confirm(sender, &id.uuid, &label);
+            }
+            match allowed {
+                Ok(()) => readable.push(id),
+                Err(e) => {
+                    debug!("Leaving {} out of GetSecrets: {}", id.uuid, e);
+                    refused.push(id.uuid);
+                }
+            }
+        }
+
+        let paths: HashMap<Uuid, dbus::Path<'static>> =
+            items.iter().map(|i| (i.item_id.uuid, i.path.clone())).collect();
+        let mut secrets = HashMap::new();
+        let mut read = Vec::new();
+        let sm = SESSION_MANAGER.lock().unwrap();
+        let s = sm.get_session(session_id, sender)?;
+        STORAGE.read().unwrap().for_each_item(&readable, |_, item| {
+            match item.get_secret(s, sender.to_string()) {
+                Ok((_, parameters, value, content_type)) => {
+                    let secret = (session.clone(), parameters, value, content_type);
+                    secrets.insert(paths[&item.id.uuid].clone(), secret);
+                    read.push(item.id.uuid);
+                }
+                Err(e) => {
+                    debug!("Cannot read the secret of {}: {}", item.id.uuid, e);
+                    refused.push(item.id.uuid);
+                }
+            }
+        });
+        if !read.is_empty() {
+            audit::record(AuditEvent::SecretRead, Outcome::Success, read);
+        }
+        if !refused.is_empty() {
+            audit::record(AuditEvent::SecretRead, Outcome::Failure, refused);
+        }
+        Ok(secrets)
+    }
     pub fn uuid_to_path(uuid: &Uuid) -> dbus::Path<'static> {
         ITEM_HANDLES.lock().unwrap().get(uuid).unwrap().path.clone()
     }
@@ -60,6 +192,37 @@ impl ItemImpl {
     pub fn is_not_default(&self) -> bool {
         !self.is_default()
     }
+    /// The current values of the given Item properties; those failing to read get left out
+    fn property_values(&self, properties: &[&str]) -> PropMap {
+        let mut values = PropMap::new();
+        for property in properties {
+            let value = match *property {
+                "Locked" => self.locked().map(prop_value),
+                "Attributes" => self.attributes().map(prop_value),
+                "Label" => self.label().map(prop_value),
+                "Type" => self.type_().map(prop_value),
+                "Created" => self.created().map(prop_value),
+                "Modified" => self.modified().map(prop_value),
+                _ => Err(MethodErr::no_property(property)),
+            };
+            match value {
+                Ok(value) => {
+                    values.insert(property.to_string(), value);
+                }
+                // e.g. Type cannot be read while the item is locked
+                Err(e) => debug!("Cannot read item property {}: {:?}", property, e),
+            }
+        }
+        values
+    }
+    /// Sends PropertiesChanged with the current values of the given Item properties
+    pub fn emit_properties_changed(item_id: ItemId, properties: &'static [&'static str]) {
+        tokio::spawn(async move {
+            let item = ItemImpl::from(&item_id);
+            let values = item.property_values(properties);
+            emit_properties_changed(&[item.path], ItemImpl::INTERFACE, values);
+        });
+    }
 }
 
 impl From<&Item> for ItemImpl {
@@ -70,20 +233,20 @@ impl From<&Item> for ItemImpl {
 
 impl From<&ItemId> for ItemImpl {
     fn from(item_id: &ItemId) -> Self {
-        let is_new = !ITEM_HANDLES.lock().unwrap().contains_key(&item_id.uuid);
-        is_new.then(|| {
-            let item_handle = ItemImpl::new(&item_id);
-            ITEM_HANDLES
-                .lock()
-                .unwrap()
-                .insert(item_id.uuid, item_handle);
-        });
-        ITEM_HANDLES
-            .lock()
-            .unwrap()
-            .get(&item_id.uuid)
-            .unwrap()
-            .clone()
+        object_manager::handle(&ITEM_HANDLES, item_id.uuid, || ItemImpl::new(item_id))
+    }
+}
+
+impl ManagedObject for ItemImpl {
+    const INTERFACE: &'static str = "org.freedesktop.Secret.Item";
+    fn managed_path(&self) -> dbus::Path<'static> {
+        self.path.clone()
+    }
+    fn collection_uuid(&self) -> Uuid {
+        self.item_id.collection_uuid
+    }
+    fn properties(&self, _sender: Option<&str>) -> PropMap {
+        self.property_values(PROPERTIES)
     }
 }
 
@@ -109,39 +272,30 @@ impl From<&Path<'_>> for ItemImpl {
         ITEM_HANDLES
             .lock()
             .unwrap()
-            .clone()
-            .into_values()
-            .find(|i| i.path == *p)
+            .find(p)
+            .cloned()
             .unwrap_or_default()
     }
 }
 
 impl OrgFreedesktopSecretItem for ItemImpl {
     fn delete(&mut self) -> Result<dbus::Path<'static>, dbus::MethodErr> {
-        match STORAGE
-            .lock()
+        self.check_acl(Access::Delete, Some(AuditEvent::ItemDelete))?;
+        let result = STORAGE
+            .write()
             .unwrap()
             .modify_collection(&self.item_id.collection_uuid, |collection| {
-                collection.delete_item(&self.item_id.uuid)
-            }) {
+                collection.trash_item(&self.item_id.uuid)
+            });
+        audit::record(AuditEvent::ItemDelete, (&result).into(), vec![self.item_id.uuid]);
+        match result {
             Ok(_) => {
-                let uuid: Uuid = self.item_id.uuid;
-                let path: dbus::Path = self.path().clone().into();
-                tokio::spawn(async move {
-                    trace!("Unregistering Item");
-                    ITEM_HANDLES.lock().unwrap().remove(&uuid);
-                    CROSSROADS.lock().unwrap().remove::<ItemImpl>(&path);
-                });
-                let item_path_clone = self.path().clone();
-                tokio::spawn(async move {
-                    debug!("Sending ItemDeleted signal");
-                    MESSAGE_SENDER.lock().unwrap().send_message(
-                        OrgFreedesktopSecretCollectionItemDeleted {
-                            item: item_path_clone.clone().into(),
-                        }
-                        .to_emit_message(&item_path_clone.into()),
-                    );
-                });
+                ItemImpl::unregister(&self.item_id);
+                CollectionImpl::emit_properties_changed(
+                    self.item_id.collection_uuid,
+                    &["Items"],
+                );
+                CollectionImpl::emit_sequence_changed(self.item_id.collection_uuid);
                 let prompt_path = dbus::Path::from("/");
                 Ok(prompt_path)
             }
@@ -154,48 +308,50 @@ impl OrgFreedesktopSecretItem for ItemImpl {
         ctx: &mut Context,
     ) -> Result<(dbus::Path<'static>, Vec<u8>, Vec<u8>, String), dbus::MethodErr> {
         if self.locked()? {
+            audit::record(AuditEvent::SecretRead, Outcome::Failure, vec![self.item_id.uuid]);
             return Err(dbus::MethodErr::failed(&"Item is locked"));
         }
+        self.check_acl(Access::Read, Some(AuditEvent::SecretRead))?;
         let sender = ctx
             .message()
             .sender()
             .ok_or_else(|| dbus::MethodErr::failed("Unkown sender"))?
             .to_string();
-        let session_id = session
-            .split('/')
-            .last()
-            .unwrap()
-            .parse::<usize>()
-            .map_err(|_| {
-                error!("Invalid session ID");
-                dbus::MethodErr::failed(&"Invalid session ID")
-            })?;
+        let session_id = SessionImpl::id_of(&session)?;
+        let plain = SESSION_MANAGER.lock().unwrap().get_session(session_id, &sender)?.is_plain();
+        if plain {
+            // asking the user may take a while, so nothing stays locked meanwhile
+            let (label, sensitive) = STORAGE.read().unwrap().with_item(
+                &self.item_id.collection_uuid,
+                &self.item_id.uuid,
+                |item| Ok((item.label.clone(), plain_transfers::is_sensitive(&item.attributes))),
+            )?;
+            if sensitive {
+                let uuid = self.item_id.uuid;
+                if let Err(e) = plain_transfers::confirm(&sender, &uuid, &label) {
+                    audit::record(AuditEvent::SecretRead, Outcome::Failure, vec![uuid]);
+                    return Err(e.into());
+                }
+            }
+        }
         let sm = SESSION_MANAGER.lock().unwrap();
-        let s = sm.sessions.get(session_id).ok_or_else(|| {
-            error!("Session {} not found", session_id);
-            dbus::MethodErr::failed(&"Session not found")
-        })?;
-        STORAGE
-            .lock()
+        let s = sm.get_session(session_id, &sender)?;
+        let result = STORAGE
+            .read()
             .unwrap()
             .with_item(&self.item_id.collection_uuid, &self.item_id.uuid, |item| {
                 let s = item.get_secret(s, sender)?;
                 Ok((session, s.1, s.2, s.3.clone()))
-            })
-            .map_err(|e| e.into())
+            });
+        audit::record(AuditEvent::SecretRead, (&result).into(), vec![self.item_id.uuid]);
+        result.map_err(|e| e.into())
     }
     fn set_secret(
         &mut self,
         secret: (dbus::Path<'static>, Vec<u8>, Vec<u8>, String),
         ctx: &mut Context,
     ) -> Result<(), dbus::MethodErr> {
-        let session_id = secret
-            .0
-            .split('/')
-            .last()
-            .unwrap()
-            .parse::<usize>()
-            .map_err(|_| dbus::MethodErr::failed(&"Invalid session ID"))?;
+        let session_id = SessionImpl::id_of(&secret.0)?;
         let sender = ctx
             .message()
             .sender()
@@ -203,20 +359,21 @@ impl OrgFreedesktopSecretItem for ItemImpl {
             .to_string();
 
         if self.locked()? {
+            audit::record(AuditEvent::SecretWrite, Outcome::Failure, vec![self.item_id.uuid]);
             return Err(dbus::MethodErr::failed(&"Item is locked"));
         }
+        self.check_acl(Access::Write, Some(AuditEvent::SecretWrite))?;
 
         let sm = SESSION_MANAGER.lock().unwrap();
-        let s = sm.sessions.get(session_id).ok_or_else(|| {
-            error!("Session {} not found", session_id);
-            dbus::MethodErr::failed(&"Session not found")
-        })?;
+        let s = sm.get_session(session_id, &sender)?;
 
-        match STORAGE.lock().unwrap().modify_item(
+        let result = STORAGE.write().unwrap().modify_item(
             &self.item_id.collection_uuid,
             &self.item_id.uuid,
             |item| item.set_secret(&s, secret.1, &secret.2, secret.3, sender),
-        ) {
+        );
+        audit::record(AuditEvent::SecretWrite, (&result).into(), vec![self.item_id.uuid]);
+        match result {
             Ok(_) => {
                 let item_path_clone = self.path().clone();
                 tokio::spawn(async move {
@@ -228,24 +385,26 @@ impl OrgFreedesktopSecretItem for ItemImpl {
                         .to_emit_message(&item_path_clone.into()),
                     );
                 });
+                ItemImpl::emit_properties_changed(self.item_id.clone(), &["Type", "Modified"]);
+                CollectionImpl::emit_sequence_changed(self.item_id.collection_uuid);
                 Ok(())
             }
+            Err(TksError::SessionExpired) => Err(TksError::SessionExpired.into()),
             Err(_) => Err(dbus::MethodErr::failed(&"Item not found")),
         }
     }
     fn locked(&self) -> Result<bool, dbus::MethodErr> {
-        let b = STORAGE
-            .lock()
-            .unwrap()
-            .collections
-            .iter()
-            .find(|c| c.uuid == self.item_id.collection_uuid)
-            .ok_or_else(|| dbus::MethodErr::failed("Item not found"))?
-            .locked;
-        Ok(b)
+        match STORAGE.read().unwrap().with_item(
+            &self.item_id.collection_uuid,
+            &self.item_id.uuid,
+            |item| Ok(item.locked),
+        ) {
+            Ok(locked) => Ok(locked),
+            Err(_) => Err(dbus::MethodErr::failed(&"Item not found")),
+        }
     }
     fn attributes(&self) -> Result<::std::collections::HashMap<String, String>, dbus::MethodErr> {
-        match STORAGE.lock().unwrap().with_item(
+        match STORAGE.read().unwrap().with_item(
             &self.item_id.collection_uuid,
             &self.item_id.uuid,
             |item| Ok(item.attributes.clone()),
@@ -258,8 +417,11 @@ impl OrgFreedesktopSecretItem for ItemImpl {
         &self,
         value: ::std::collections::HashMap<String, String>,
     ) -> Result<(), dbus::MethodErr> {
+        self.check_acl(Access::Write, None)?;
+        let schemas = SETTINGS.lock().unwrap().schemas.clone();
+        item_schemas::check(&schemas, &value)?;
         STORAGE
-            .lock()
+            .write()
             .unwrap()
             .modify_item(&self.item_id.collection_uuid, &self.item_id.uuid, |item| {
                 item.attributes = value;
@@ -276,13 +438,18 @@ impl OrgFreedesktopSecretItem for ItemImpl {
                         .to_emit_message(&item_path_clone.into()),
                     );
                 });
+                ItemImpl::emit_properties_changed(
+                    self.item_id.clone(),
+                    &["Attributes", "Modified"],
+                );
+                CollectionImpl::emit_sequence_changed(self.item_id.collection_uuid);
                 Ok(())
             })
             .map_err(|e| e.into())
     }
     fn label(&self) -> Result<String, dbus::MethodErr> {
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_item(&self.item_id.collection_uuid, &self.item_id.uuid, |item| {
                 Ok(item.label.clone())
@@ -291,7 +458,8 @@ impl OrgFreedesktopSecretItem for ItemImpl {
     }
 
     fn set_label(&self, value: String) -> Result<(), dbus::MethodErr> {
-        match STORAGE.lock().unwrap().modify_item(
+        self.check_acl(Access::Write, None)?;
+        match STORAGE.write().unwrap().modify_item(
             &self.item_id.collection_uuid,
             &self.item_id.uuid,
             |item| {
@@ -310,6 +478,8 @@ impl OrgFreedesktopSecretItem for ItemImpl {
                         .to_emit_message(&item_path_clone.into()),
                     );
                 });
+                ItemImpl::emit_properties_changed(self.item_id.clone(), &["Label", "Modified"]);
+                CollectionImpl::emit_sequence_changed(self.item_id.collection_uuid);
                 Ok(())
             }
             Err(_) => Err(dbus::MethodErr::failed(&"Item not found")),
@@ -322,16 +492,14 @@ impl OrgFreedesktopSecretItem for ItemImpl {
         }
 
         STORAGE
-            .lock()
+            .read()
             .unwrap()
             .with_item(&self.item_id.collection_uuid, &self.item_id.uuid, |item| {
-                Ok(item
-                    .data
-                    .clone()
-                    .ok_or_else(|| MethodErr::failed("No data"))
-                    .unwrap()
-                    .content_type
-                    .clone())
+                // the secret itself isn't needed, only its content type
+                item.data
+                    .as_ref()
+                    .map(|data| data.content_type.clone())
+                    .ok_or(TksError::PermissionDenied)
             })
             .map_err(|e| e.into())
     }
@@ -339,7 +507,7 @@ impl OrgFreedesktopSecretItem for ItemImpl {
     fn set_type(&self, value: String) -> Result<(), dbus::MethodErr> {
         match self.locked() {
             Ok(true) => Err(dbus::MethodErr::failed(&"Item is locked")),
-            Ok(false) => match STORAGE.lock().unwrap().modify_item(
+            Ok(false) => match STORAGE.write().unwrap().modify_item(
                 &self.item_id.collection_uuid,
                 &self.item_id.uuid,
                 |item| {
@@ -359,6 +527,8 @@ impl OrgFreedesktopSecretItem for ItemImpl {
                             .into(),
                         );
                     });
+                    ItemImpl::emit_properties_changed(self.item_id.clone(), &["Type", "Modified"]);
+                    CollectionImpl::emit_sequence_changed(self.item_id.collection_uuid);
                     Ok(())
                 }
                 Err(_) => Err(dbus::MethodErr::failed(&"Item not found")),
@@ -367,7 +537,7 @@ impl OrgFreedesktopSecretItem for ItemImpl {
         }
     }
     fn created(&self) -> Result<u64, dbus::MethodErr> {
-        match STORAGE.lock().unwrap().with_item(
+        match STORAGE.read().unwrap().with_item(
             &self.item_id.collection_uuid,
             &self.item_id.uuid,
             |item| Ok(item.created),
@@ -377,7 +547,7 @@ impl OrgFreedesktopSecretItem for ItemImpl {
         }
     }
     fn modified(&self) -> Result<u64, dbus::MethodErr> {
-        match STORAGE.lock().unwrap().with_item(
+        match STORAGE.read().unwrap().with_item(
             &self.item_id.collection_uuid,
             &self.item_id.uuid,
             |item| Ok(item.modified),
@@ -387,3 +557,50 @@ impl OrgFreedesktopSecretItem for ItemImpl {
         }
     }
 }
+
+impl IoLinuxTksItem1 for ItemImpl {
+    fn get_versions(&mut self) -> Result<Vec<(u64, String, u64)>, dbus::MethodErr> {
+        trace!("get_versions of {}", self.item_id.uuid);
+        self.check_acl(Access::Read, None)?;
+        STORAGE
+            .read()
+            .unwrap()
+            .with_item(&self.item_id.collection_uuid, &self.item_id.uuid, |item| {
+                let data = item.data.as_ref().ok_or(TksError::PermissionDenied)?;
+                Ok(data
+                    .versions()
+                    .iter()
+                    .map(|v| (v.replaced, v.content_type.clone(), v.data.len() as u64))
+                    .collect())
+            })
+            .map_err(|e| e.into())
+    }
+    fn rollback(&mut self, index: u32) -> Result<(), dbus::MethodErr> {
+        trace!("rollback {} to version {}", self.item_id.uuid, index);
+        if self.locked()? {
+            audit::record(AuditEvent::SecretWrite, Outcome::Failure, vec![self.item_id.uuid]);
+            return Err(dbus::MethodErr::failed(&"Item is locked"));
+        }
+        self.check_acl(Access::Write, Some(AuditEvent::SecretWrite))?;
+        let result = STORAGE.write().unwrap().modify_item(
+            &self.item_id.collection_uuid,
+            &self.item_id.uuid,
+            |item| item.rollback(index as usize),
+        );
+        audit::record(AuditEvent::SecretWrite, (&result).into(), vec![self.item_id.uuid]);
+        result?;
+        let item_path_clone = self.path().clone();
+        tokio::spawn(async move {
+            debug!("Sending ItemChanged signal");
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretCollectionItemChanged {
+                    item: item_path_clone.clone().into(),
+                }
+                .to_emit_message(&item_path_clone.into()),
+            );
+        });
+        ItemImpl::emit_properties_changed(self.item_id.clone(), &["Type", "Modified"]);
+        CollectionImpl::emit_sequence_changed(self.item_id.collection_uuid);
+        Ok(())
+    }
+}
@@ -0,0 +1,760 @@
+// This code was generated from org.kde.KWallet.xml with `dbus-codegen-rust -r`, see
+// https://github.com/diwic/dbus-rs, then edited: crossroads dispatches the methods by name, so the
+// overloaded close, isOpen and writeEntry read their arguments after the signature of the call,
+// and open may reply once its prompts got answered.
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+use dbus_crossroads::Context;
+
+pub trait OrgKdeKWallet {
+    fn is_enabled(&mut self) -> Result<bool, dbus::MethodErr>;
+    /// `None` when the reply gets sent later on, through the message of `ctx`
+    fn open(
+        &mut self,
+        ctx: &mut Context,
+        wallet: String,
+        w_id: i64,
+        appid: String,
+    ) -> Result<Option<i32>, dbus::MethodErr>;
+    fn open_async(
+        &mut self,
+        ctx: &mut Context,
+        wallet: String,
+        w_id: i64,
+        appid: String,
+        handle_session: bool,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn close_wallet(&mut self, wallet: String, force: bool) -> Result<i32, dbus::MethodErr>;
+    fn close(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        force: bool,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn sync(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        appid: String,
+    ) -> Result<(), dbus::MethodErr>;
+    fn delete_wallet(&mut self, wallet: String) -> Result<i32, dbus::MethodErr>;
+    fn is_open_wallet(&mut self, wallet: String) -> Result<bool, dbus::MethodErr>;
+    fn is_open(&mut self, ctx: &mut Context, handle: i32) -> Result<bool, dbus::MethodErr>;
+    fn users(&mut self, wallet: String) -> Result<Vec<String>, dbus::MethodErr>;
+    fn wallets(&mut self, ctx: &mut Context) -> Result<Vec<String>, dbus::MethodErr>;
+    fn folder_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        appid: String,
+    ) -> Result<Vec<String>, dbus::MethodErr>;
+    fn has_folder(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn create_folder(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn remove_folder(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn entry_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<Vec<String>, dbus::MethodErr>;
+    fn read_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<Vec<u8>, dbus::MethodErr>;
+    fn read_map(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<Vec<u8>, dbus::MethodErr>;
+    fn read_password(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<String, dbus::MethodErr>;
+    fn read_entry_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<arg::PropMap, dbus::MethodErr>;
+    fn read_map_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<arg::PropMap, dbus::MethodErr>;
+    fn read_password_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<arg::PropMap, dbus::MethodErr>;
+    fn entries_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<arg::PropMap, dbus::MethodErr>;
+    fn map_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<arg::PropMap, dbus::MethodErr>;
+    fn password_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        appid: String,
+    ) -> Result<arg::PropMap, dbus::MethodErr>;
+    fn rename_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        old_name: String,
+        new_name: String,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    #[allow(clippy::too_many_arguments)]
+    fn write_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        value: Vec<u8>,
+        entry_type: i32,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn write_map(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        value: Vec<u8>,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn write_password(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        value: String,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn has_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn entry_type(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn remove_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        appid: String,
+    ) -> Result<i32, dbus::MethodErr>;
+    fn disconnect_application(
+        &mut self,
+        wallet: String,
+        application: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn folder_does_not_exist(
+        &mut self,
+        wallet: String,
+        folder: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn key_does_not_exist(
+        &mut self,
+        wallet: String,
+        folder: String,
+        key: String,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn close_all_wallets(&mut self) -> Result<(), dbus::MethodErr>;
+    fn network_wallet(&mut self) -> Result<String, dbus::MethodErr>;
+    fn local_wallet(&mut self) -> Result<String, dbus::MethodErr>;
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletWalletListDirty {}
+
+impl arg::AppendAll for OrgKdeKWalletWalletListDirty {
+    fn append(&self, _: &mut arg::IterAppend) {}
+}
+
+impl arg::ReadAll for OrgKdeKWalletWalletListDirty {
+    fn read(_: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletWalletListDirty {})
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletWalletListDirty {
+    const NAME: &'static str = "walletListDirty";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletWalletCreated {
+    pub wallet: String,
+}
+
+impl arg::AppendAll for OrgKdeKWalletWalletCreated {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.wallet, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletWalletCreated {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletWalletCreated { wallet: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletWalletCreated {
+    const NAME: &'static str = "walletCreated";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletWalletOpened {
+    pub wallet: String,
+}
+
+impl arg::AppendAll for OrgKdeKWalletWalletOpened {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.wallet, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletWalletOpened {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletWalletOpened { wallet: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletWalletOpened {
+    const NAME: &'static str = "walletOpened";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletWalletAsyncOpened {
+    pub t_id: i32,
+    pub handle: i32,
+}
+
+impl arg::AppendAll for OrgKdeKWalletWalletAsyncOpened {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.t_id, i);
+        arg::RefArg::append(&self.handle, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletWalletAsyncOpened {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletWalletAsyncOpened {
+            t_id: i.read()?,
+            handle: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletWalletAsyncOpened {
+    const NAME: &'static str = "walletAsyncOpened";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletWalletClosed {
+    pub wallet: String,
+}
+
+impl arg::AppendAll for OrgKdeKWalletWalletClosed {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.wallet, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletWalletClosed {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletWalletClosed { wallet: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletWalletClosed {
+    const NAME: &'static str = "walletClosed";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletWalletClosedId {
+    pub handle: i32,
+}
+
+impl arg::AppendAll for OrgKdeKWalletWalletClosedId {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.handle, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletWalletClosedId {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletWalletClosedId { handle: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletWalletClosedId {
+    const NAME: &'static str = "walletClosedId";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletAllWalletsClosed {}
+
+impl arg::AppendAll for OrgKdeKWalletAllWalletsClosed {
+    fn append(&self, _: &mut arg::IterAppend) {}
+}
+
+impl arg::ReadAll for OrgKdeKWalletAllWalletsClosed {
+    fn read(_: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletAllWalletsClosed {})
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletAllWalletsClosed {
+    const NAME: &'static str = "allWalletsClosed";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletFolderListUpdated {
+    pub wallet: String,
+}
+
+impl arg::AppendAll for OrgKdeKWalletFolderListUpdated {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.wallet, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletFolderListUpdated {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletFolderListUpdated { wallet: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletFolderListUpdated {
+    const NAME: &'static str = "folderListUpdated";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletFolderUpdated {
+    pub wallet: String,
+    pub folder: String,
+}
+
+impl arg::AppendAll for OrgKdeKWalletFolderUpdated {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.wallet, i);
+        arg::RefArg::append(&self.folder, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletFolderUpdated {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletFolderUpdated {
+            wallet: i.read()?,
+            folder: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletFolderUpdated {
+    const NAME: &'static str = "folderUpdated";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+#[derive(Debug)]
+pub struct OrgKdeKWalletApplicationDisconnected {
+    pub wallet: String,
+    pub application: String,
+}
+
+impl arg::AppendAll for OrgKdeKWalletApplicationDisconnected {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.wallet, i);
+        arg::RefArg::append(&self.application, i);
+    }
+}
+
+impl arg::ReadAll for OrgKdeKWalletApplicationDisconnected {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgKdeKWalletApplicationDisconnected {
+            wallet: i.read()?,
+            application: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgKdeKWalletApplicationDisconnected {
+    const NAME: &'static str = "applicationDisconnected";
+    const INTERFACE: &'static str = "org.kde.KWallet";
+}
+
+pub fn register_org_kde_kwallet<T>(cr: &mut crossroads::Crossroads) -> crossroads::IfaceToken<T>
+where
+    T: OrgKdeKWallet + Send + 'static,
+{
+    cr.register("org.kde.KWallet", |b| {
+        b.signal::<(), _>("walletListDirty", ());
+        b.signal::<(String,), _>("walletCreated", ("wallet",));
+        b.signal::<(String,), _>("walletOpened", ("wallet",));
+        b.signal::<(i32, i32), _>("walletAsyncOpened", ("tId", "handle"));
+        b.signal::<(String,), _>("walletClosed", ("wallet",));
+        b.signal::<(i32,), _>("walletClosedId", ("handle",));
+        b.signal::<(), _>("allWalletsClosed", ());
+        b.signal::<(String,), _>("folderListUpdated", ("wallet",));
+        b.signal::<(String, String), _>("folderUpdated", ("wallet", "folder"));
+        b.signal::<(String, String), _>("applicationDisconnected", ("wallet", "application"));
+        b.method("isEnabled", (), ("enabled",), |_, t: &mut T, ()| {
+            t.is_enabled().map(|x| (x,))
+        });
+        b.method_with_cr_custom::<(String, i64, String), (i32,), _, _>(
+            "open",
+            ("wallet", "wId", "appid"),
+            ("handle",),
+            |mut ctx, cr, (wallet, w_id, appid)| {
+                let opened = ctx.check(|ctx| {
+                    let t: &mut T = cr
+                        .data_mut(ctx.path())
+                        .ok_or_else(|| dbus::MethodErr::no_path(ctx.path()))?;
+                    t.open(ctx, wallet, w_id, appid)
+                });
+                match opened {
+                    Ok(Some(handle)) => ctx.do_reply(|msg| msg.append_all((handle,))),
+                    Ok(None) => return None,
+                    Err(()) => {}
+                }
+                Some(ctx)
+            },
+        );
+        b.method(
+            "openAsync",
+            ("wallet", "wId", "appid", "handleSession"),
+            ("tId",),
+            |ctx, t: &mut T, (wallet, w_id, appid, handle_session)| {
+                t.open_async(ctx, wallet, w_id, appid, handle_session)
+                    .map(|x| (x,))
+            },
+        );
+        b.method("close", (), ("result",), |ctx, t: &mut T, ()| {
+            if ctx.message().iter_init().arg_type() == arg::ArgType::String {
+                let (wallet, force) = ctx.message().read2()?;
+                return t.close_wallet(wallet, force).map(|x| (x,));
+            }
+            let (handle, force, appid) = ctx.message().read3()?;
+            t.close(ctx, handle, force, appid).map(|x| (x,))
+        });
+        b.method(
+            "sync",
+            ("handle", "appid"),
+            (),
+            |ctx, t: &mut T, (handle, appid)| t.sync(ctx, handle, appid),
+        );
+        b.method(
+            "deleteWallet",
+            ("wallet",),
+            ("result",),
+            |_, t: &mut T, (wallet,)| t.delete_wallet(wallet).map(|x| (x,)),
+        );
+        b.method("isOpen", (), ("open",), |ctx, t: &mut T, ()| {
+            if ctx.message().iter_init().arg_type() == arg::ArgType::String {
+                let wallet = ctx.message().read1()?;
+                return t.is_open_wallet(wallet).map(|x| (x,));
+            }
+            let handle = ctx.message().read1()?;
+            t.is_open(ctx, handle).map(|x| (x,))
+        });
+        b.method(
+            "users",
+            ("wallet",),
+            ("users",),
+            |_, t: &mut T, (wallet,)| t.users(wallet).map(|x| (x,)),
+        );
+        b.method("wallets", (), ("wallets",), |ctx, t: &mut T, ()| {
+            t.wallets(ctx).map(|x| (x,))
+        });
+        b.method(
+            "folderList",
+            ("handle", "appid"),
+            ("folders",),
+            |ctx, t: &mut T, (handle, appid)| t.folder_list(ctx, handle, appid).map(|x| (x,)),
+        );
+        b.method(
+            "hasFolder",
+            ("handle", "folder", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.has_folder(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "createFolder",
+            ("handle", "folder", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.create_folder(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "removeFolder",
+            ("handle", "folder", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.remove_folder(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "entryList",
+            ("handle", "folder", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.entry_list(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "readEntry",
+            ("handle", "folder", "key", "appid"),
+            ("value",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.read_entry(ctx, handle, folder, key, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "readMap",
+            ("handle", "folder", "key", "appid"),
+            ("value",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.read_map(ctx, handle, folder, key, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "readPassword",
+            ("handle", "folder", "key", "appid"),
+            ("value",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.read_password(ctx, handle, folder, key, appid)
+                    .map(|x| (x,))
+            },
+        );
+        b.method(
+            "readEntryList",
+            ("handle", "folder", "key", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.read_entry_list(ctx, handle, folder, key, appid)
+                    .map(|x| (x,))
+            },
+        )
+        .annotate("org.qtproject.QtDBus.QtTypeName.Out0", "QVariantMap");
+        b.method(
+            "readMapList",
+            ("handle", "folder", "key", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.read_map_list(ctx, handle, folder, key, appid)
+                    .map(|x| (x,))
+            },
+        )
+        .annotate("org.qtproject.QtDBus.QtTypeName.Out0", "QVariantMap");
+        b.method(
+            "readPasswordList",
+            ("handle", "folder", "key", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.read_password_list(ctx, handle, folder, key, appid)
+                    .map(|x| (x,))
+            },
+        )
+        .annotate("org.qtproject.QtDBus.QtTypeName.Out0", "QVariantMap");
+        b.method(
+            "entriesList",
+            ("handle", "folder", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.entries_list(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        )
+        .annotate("org.qtproject.QtDBus.QtTypeName.Out0", "QVariantMap");
+        b.method(
+            "mapList",
+            ("handle", "folder", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.map_list(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        )
+        .annotate("org.qtproject.QtDBus.QtTypeName.Out0", "QVariantMap");
+        b.method(
+            "passwordList",
+            ("handle", "folder", "appid"),
+            ("entries",),
+            |ctx, t: &mut T, (handle, folder, appid)| {
+                t.password_list(ctx, handle, folder, appid).map(|x| (x,))
+            },
+        )
+        .annotate("org.qtproject.QtDBus.QtTypeName.Out0", "QVariantMap");
+        b.method(
+            "renameEntry",
+            ("handle", "folder", "oldName", "newName", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, old_name, new_name, appid)| {
+                t.rename_entry(ctx, handle, folder, old_name, new_name, appid)
+                    .map(|x| (x,))
+            },
+        );
+        b.method("writeEntry", (), ("result",), |ctx, t: &mut T, ()| {
+            let mut args = ctx.message().iter_init();
+            let (handle, folder, key, value): (i32, String, String, Vec<u8>) =
+                (args.read()?, args.read()?, args.read()?, args.read()?);
+            // the overload without the entry type writes a stream
+            let (entry_type, appid): (i32, String) = match args.arg_type() {
+                arg::ArgType::String => (2, args.read()?),
+                _ => (args.read()?, args.read()?),
+            };
+            t.write_entry(ctx, handle, folder, key, value, entry_type, appid)
+                .map(|x| (x,))
+        });
+        b.method(
+            "writeMap",
+            ("handle", "folder", "key", "value", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, key, value, appid)| {
+                t.write_map(ctx, handle, folder, key, value, appid)
+                    .map(|x| (x,))
+            },
+        );
+        b.method(
+            "writePassword",
+            ("handle", "folder", "key", "value", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, key, value, appid)| {
+                t.write_password(ctx, handle, folder, key, value, appid)
+                    .map(|x| (x,))
+            },
+        );
+        b.method(
+            "hasEntry",
+            ("handle", "folder", "key", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.has_entry(ctx, handle, folder, key, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "entryType",
+            ("handle", "folder", "key", "appid"),
+            ("type",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.entry_type(ctx, handle, folder, key, appid).map(|x| (x,))
+            },
+        );
+        b.method(
+            "removeEntry",
+            ("handle", "folder", "key", "appid"),
+            ("result",),
+            |ctx, t: &mut T, (handle, folder, key, appid)| {
+                t.remove_entry(ctx, handle, folder, key, appid)
+                    .map(|x| (x,))
+            },
+        );
+        b.method(
+            "disconnectApplication",
+            ("wallet", "application"),
+            ("result",),
+            |_, t: &mut T, (wallet, application)| {
+                t.disconnect_application(wallet, application).map(|x| (x,))
+            },
+        );
+        b.method(
+            "folderDoesNotExist",
+            ("wallet", "folder"),
+            ("result",),
+            |_, t: &mut T, (wallet, folder)| t.folder_does_not_exist(wallet, folder).map(|x| (x,)),
+        );
+        b.method(
+            "keyDoesNotExist",
+            ("wallet", "folder", "key"),
+            ("result",),
+            |_, t: &mut T, (wallet, folder, key)| {
+                t.key_does_not_exist(wallet, folder, key).map(|x| (x,))
+            },
+        );
+        b.method("closeAllWallets", (), (), |_, t: &mut T, ()| {
+            t.close_all_wallets()
+        });
+        b.method("networkWallet", (), ("wallet",), |_, t: &mut T, ()| {
+            t.network_wallet().map(|x| (x,))
+        });
+        b.method("localWallet", (), ("wallet",), |_, t: &mut T, ()| {
+            t.local_wallet().map(|x| (x,))
+        });
+    })
+}
@@ -0,0 +1,1300 @@
+//! Serves the legacy org.kde.KWallet interface of kwalletd, once `kwallet.enabled` is set, so
+//! that the KDE applications which never moved to the Secret Service keep finding their
+//! credentials after `tks-cli import kwallet`. A wallet is a collection, looked up by alias then
+//! by label, `kdewallet` being the default collection; opening a wallet which doesn't exist
+//! creates it. The folders are the `tks:kwallet-folder` attribute of the items, and the keys their
+//! label, the way the import stores them; the items lacking the attribute don't show up.
+//!
+//! Opening a wallet asks the user the same as Unlock does: the passphrase of a locked collection,
+//! then whether to let a new client in. The handles it gives belong to the calling connection, and
+//! get dropped once it leaves the bus or the collection gets locked. Closing a wallet never locks
+//! its collection, which other clients may be using through the Secret Service.
+
+use crate::audit;
+use crate::audit::{AuditEvent, Outcome};
+use crate::storage::acl::Access;
+use crate::storage::collection::{Collection, Item, ItemId, LABEL_PROPERTY};
+use crate::storage::folders::PATH_ATTRIBUTE;
+use crate::storage::{Storage, STORAGE};
+use crate::tks_dbus::acl;
+use crate::tks_dbus::alias_registry;
+use crate::tks_dbus::client_context::CLIENT_REGISTRY;
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollectionItemChanged;
+use crate::tks_dbus::fdo::service::OrgFreedesktopSecretServiceCollectionCreated;
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::kde::kwallet::*;
+use crate::tks_dbus::prompt_impl::PromptAction;
+use crate::tks_dbus::sanitize_string;
+use crate::tks_dbus::visibility;
+use crate::tks_dbus::MESSAGE_SENDER;
+use crate::tks_error::TksError;
+use dbus::arg::{AppendAll, PropMap, Variant};
+use dbus::message::SignalArgs;
+use dbus::MethodErr;
+use dbus_crossroads::Context;
+use lazy_static::lazy_static;
+use log::{debug, error, trace};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Object paths of the interface, kwalletd6 and kwalletd5 serving the same one
+pub const PATHS: [&str; 2] = ["/modules/kwalletd6", "/modules/kwalletd5"];
+
+/// Bus names the KDE applications call
+pub const BUS_NAMES: [&str; 2] = ["org.kde.kwalletd6", "org.kde.kwalletd5"];
+
+/// The wallet KDE opens by default, the default collection
+pub const DEFAULT_WALLET: &str = "kdewallet";
+
+/// The item attribute holding the folder of the entry
+pub const FOLDER_ATTRIBUTE: &str = "tks:kwallet-folder";
+
+/// The item attribute holding the type of the entry, see [EntryType::name]
+pub const ENTRY_TYPE_ATTRIBUTE: &str = "tks:kwallet-entry-type";
+
+/// Content type of the maps, stored as JSON objects
+pub const MAP_CONTENT_TYPE: &str = "application/x-kde-wallet-map";
+
+/// Prefix of the attributes naming the keys of a map, so maps can be searched by key
+pub const MAP_KEY_ATTRIBUTE: &str = "tks:kwallet-map-key:";
+
+/// Type of a wallet entry, numbered as KWallet does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    Unknown = 0,
+    Password = 1,
+    Stream = 2,
+    Map = 3,
+}
+
+impl EntryType {
+    pub fn from_i32(value: i32) -> EntryType {
+        match value {
+            1 => EntryType::Password,
+            2 => EntryType::Stream,
+            3 => EntryType::Map,
+            _ => EntryType::Unknown,
+        }
+    }
+
+    /// Value of [ENTRY_TYPE_ATTRIBUTE]
+    pub fn name(self) -> &'static str {
+        match self {
+            EntryType::Unknown => "unknown",
+            EntryType::Password => "password",
+            EntryType::Stream => "stream",
+            EntryType::Map => "map",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            EntryType::Password => "text/plain",
+            EntryType::Map => MAP_CONTENT_TYPE,
+            EntryType::Unknown | EntryType::Stream => "application/octet-stream",
+        }
+    }
+
+    /// The type of an item, after its attribute or else its content type; the items stored by
+    /// other clients are passwords when they hold text
+    pub fn of(item: &Item) -> EntryType {
+        match item.attribute(ENTRY_TYPE_ATTRIBUTE) {
+            Some("password") => return EntryType::Password,
+            Some("stream") => return EntryType::Stream,
+            Some("map") => return EntryType::Map,
+            _ => {}
+        }
+        match item.data.as_ref().map(|data| data.content_type.as_str()) {
+            Some(MAP_CONTENT_TYPE) => EntryType::Map,
+            Some(content_type) if content_type.starts_with("text/") => EntryType::Password,
+            Some(_) => EntryType::Stream,
+            None => EntryType::Unknown,
+        }
+    }
+}
+
+fn truncated() -> TksError {
+    TksError::SerializationError("Truncated QDataStream data".to_string())
+}
+
+fn read_u32(data: &mut &[u8]) -> Result<u32, TksError> {
+    let (value, rest) = data.split_first_chunk::<4>().ok_or_else(truncated)?;
+    *data = rest;
+    Ok(u32::from_be_bytes(*value))
+}
+
+fn read_qstring(data: &mut &[u8]) -> Result<String, TksError> {
+    let length = read_u32(data)?;
+    // a null string
+    if length == u32::MAX {
+        return Ok(String::new());
+    }
+    let text = data.split_off(..length as usize).ok_or_else(truncated)?;
+    let units = text.chunks_exact(2);
+    if !units.remainder().is_empty() {
+        return Err(TksError::SerializationError(
+            "Malformed QString".to_string(),
+        ));
+    }
+    String::from_utf16(
+        &units
+            .map(|u| u16::from_be_bytes([u[0], u[1]]))
+            .collect::<Vec<_>>(),
+    )
+    .map_err(|e| TksError::SerializationError(e.to_string()))
+}
+
+fn write_qstring(data: &mut Vec<u8>, text: &str) {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    data.extend_from_slice(&(units.len() as u32 * 2).to_be_bytes());
+    units
+        .iter()
+        .for_each(|u| data.extend_from_slice(&u.to_be_bytes()));
+}
+
+/// A `QString` serialized by `QDataStream`: its length in bytes, then its UTF-16 text, the way
+/// KWallet holds its passwords
+pub fn encode_qstring(text: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_qstring(&mut data, text);
+    data
+}
+
+pub fn decode_qstring(mut data: &[u8]) -> Result<String, TksError> {
+    read_qstring(&mut data)
+}
+
+/// A `QMap<QString, QString>` serialized by `QDataStream`, the way KWallet holds its maps
+pub fn encode_qmap(map: &BTreeMap<String, String>) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(map.len() as u32).to_be_bytes());
+    for (key, value) in map {
+        write_qstring(&mut data, key);
+        write_qstring(&mut data, value);
+    }
+    data
+}
+
+pub fn decode_qmap(mut data: &[u8]) -> Result<BTreeMap<String, String>, TksError> {
+    let count = read_u32(&mut data)?;
+    let mut map = BTreeMap::new();
+    for _ in 0..count {
+        let key = read_qstring(&mut data)?;
+        map.insert(key, read_qstring(&mut data)?);
+    }
+    Ok(map)
+}
+
+/// Whether the text matches a pattern where `*` stands for any text and `?` for any character,
+/// as the keys given to the `*List` methods
+pub fn wildcard_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // the last star seen, and the position in the text it got matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The secret of an entry the way readEntry gives it, serialized by `QDataStream` unless it's a
+/// stream
+fn encoded_value(entry_type: EntryType, secret: &[u8]) -> Result<Vec<u8>, TksError> {
+    match entry_type {
+        EntryType::Password => Ok(encode_qstring(&String::from_utf8_lossy(secret))),
+        EntryType::Map => Ok(encode_qmap(&serde_json::from_slice(secret)?)),
+        EntryType::Unknown | EntryType::Stream => Ok(secret.to_vec()),
+    }
+}
+
+/// An opened wallet
+#[derive(Debug, Clone)]
+struct WalletHandle {
+    collection_uuid: Uuid,
+    /// The wallet name the client opened, which the signals report
+    wallet: String,
+    /// Unique bus name of the client
+    client: String,
+    appid: String,
+}
+
+lazy_static! {
+    static ref HANDLES: Mutex<HashMap<i32, WalletHandle>> = Mutex::new(HashMap::new());
+    /// The last handle, or transaction id of openAsync, given out
+    static ref LAST_ID: Mutex<i32> = Mutex::new(0);
+    /// Folders created without any entry yet, which only last as long as the service runs
+    static ref EMPTY_FOLDERS: Mutex<HashMap<Uuid, BTreeSet<String>>> = Mutex::new(HashMap::new());
+}
+
+fn next_id() -> i32 {
+    let mut id = LAST_ID.lock().unwrap();
+    *id = id.checked_add(1).unwrap_or(1);
+    *id
+}
+
+/// Sends a signal of the interface, from each of its paths
+fn emit<S: SignalArgs + AppendAll + Send + 'static>(signal: S) {
+    tokio::spawn(async move {
+        debug!("Sending KWallet {} signal", S::NAME);
+        let sender = MESSAGE_SENDER.lock().unwrap();
+        for path in PATHS {
+            sender.send_message(signal.to_emit_message(&path.into()));
+        }
+    });
+}
+
+/// Lets the clients know an item changed, the way Item's methods do
+fn emit_item_changed(item_id: &ItemId, properties: &'static [&'static str]) {
+    let path = ItemImpl::from(item_id).path;
+    tokio::spawn(async move {
+        debug!("Sending ItemChanged signal");
+        MESSAGE_SENDER.lock().unwrap().send_message(
+            OrgFreedesktopSecretCollectionItemChanged { item: path.clone() }.to_emit_message(&path),
+        );
+    });
+    ItemImpl::emit_properties_changed(item_id.clone(), properties);
+    CollectionImpl::emit_sequence_changed(item_id.collection_uuid);
+}
+
+/// Lets the clients know the items of a collection changed
+fn emit_items_changed(collection_uuid: Uuid) {
+    CollectionImpl::emit_properties_changed(collection_uuid, &["Items"]);
+    CollectionImpl::emit_sequence_changed(collection_uuid);
+}
+
+/// KWallet reports the failure of its methods returning an int as -1
+fn status(result: Result<(), MethodErr>) -> Result<i32, MethodErr> {
+    match result {
+        Ok(()) => Ok(0),
+        Err(e) => {
+            debug!("KWallet call failed: {}", e.description());
+            Ok(-1)
+        }
+    }
+}
+
+fn sender_of(ctx: &Context) -> Result<String, MethodErr> {
+    ctx.message()
+        .sender()
+        .map(|s| s.to_string())
+        .ok_or_else(|| MethodErr::failed("Unknown sender"))
+}
+
+/// The collection of a wallet: by alias, then by label, `kdewallet` being the default one
+fn find_wallet(storage: &Storage, wallet: &str) -> Option<Uuid> {
+    if let Some(uuid) = storage
+        .read_alias(wallet)
+        .ok()
+        .and_then(|u| Uuid::parse_str(&u).ok())
+    {
+        return Some(uuid);
+    }
+    storage
+        .collections
+        .iter()
+        .find(|c| c.label() == wallet)
+        .or_else(|| {
+            let default = storage.collections.iter().find(|c| c.default);
+            default.filter(|_| wallet == DEFAULT_WALLET)
+        })
+        .map(|c| c.uuid)
+}
+
+/// The entries of a folder
+fn folder_entries<'a>(
+    collection: &'a Collection,
+    folder: &'a str,
+) -> impl Iterator<Item = &'a Item> {
+    collection
+        .items
+        .iter()
+        .filter(move |i| i.attribute(FOLDER_ATTRIBUTE) == Some(folder))
+}
+
+fn find_entry<'a>(collection: &'a Collection, folder: &'a str, key: &str) -> Option<&'a Item> {
+    folder_entries(collection, folder).find(|i| i.label == key)
+}
+
+fn folders(collection: &Collection) -> BTreeSet<String> {
+    let mut folders: BTreeSet<String> = collection
+        .items
+        .iter()
+        .filter_map(|i| i.attribute(FOLDER_ATTRIBUTE))
+        .map(String::from)
+        .collect();
+    if let Some(empty) = EMPTY_FOLDERS.lock().unwrap().get(&collection.uuid) {
+        folders.extend(empty.iter().cloned());
+    }
+    folders
+}
+
+/// The attributes of an entry, the same `tks-cli import kwallet` sets
+fn entry_attributes(
+    folder: &str,
+    entry_type: EntryType,
+    map_keys: &[String],
+) -> HashMap<String, String> {
+    let mut attributes = HashMap::from([
+        (FOLDER_ATTRIBUTE.to_string(), folder.to_string()),
+        (PATH_ATTRIBUTE.to_string(), folder.to_string()),
+        (
+            ENTRY_TYPE_ATTRIBUTE.to_string(),
+            entry_type.name().to_string(),
+        ),
+        (
+            "xdg:schema".to_string(),
+            "org.freedesktop.Secret.Generic".to_string(),
+        ),
+        ("xdg:creator".to_string(), "org.kde.KWallet".to_string()),
+    ]);
+    for key in map_keys {
+        attributes.insert(format!("{}{}", MAP_KEY_ATTRIBUTE, key), String::new());
+    }
+    attributes
+}
+
+/// How the outcome of opening a wallet gets to the client
+enum OpenReply {
+    /// The reply of open, sent once the prompts got answered
+    Reply(dbus::Message),
+    /// The walletAsyncOpened signal of openAsync, with its transaction id
+    Signal(i32),
+}
+
+impl OpenReply {
+    fn send(self, handle: i32) {
+        match self {
+            OpenReply::Reply(reply) => {
+                MESSAGE_SENDER
+                    .lock()
+                    .unwrap()
+                    .send_message(reply.append1(handle));
+            }
+            OpenReply::Signal(t_id) => emit(OrgKdeKWalletWalletAsyncOpened { t_id, handle }),
+        }
+    }
+}
+
+/// Drops the handles of a client which left the bus
+pub fn forget_client(bus_name: &str) {
+    for (_, handle) in KWalletImpl::drop_handles(|h| h.client == bus_name) {
+        emit(OrgKdeKWalletApplicationDisconnected {
+            wallet: handle.wallet,
+            application: handle.appid,
+        });
+    }
+}
+
+/// Closes the wallet of a collection which got locked
+pub fn collection_locked(collection_uuid: &Uuid) {
+    if HANDLES.lock().unwrap().is_empty() {
+        return;
+    }
+    KWalletImpl::drop_handles(|h| h.collection_uuid == *collection_uuid);
+}
+
+#[derive(Default)]
+pub struct KWalletImpl {}
+
+impl KWalletImpl {
+    pub fn new() -> KWalletImpl {
+        KWalletImpl {}
+    }
+
+    fn drop_handle(handle: i32) {
+        KWalletImpl::drop_handles_by(|id, _| id == handle);
+    }
+
+    /// Forgets the matching handles, then lets the clients know about them and about the wallets
+    /// now closed
+    fn drop_handles(filter: impl Fn(&WalletHandle) -> bool) -> Vec<(i32, WalletHandle)> {
+        KWalletImpl::drop_handles_by(|_, h| filter(h))
+    }
+
+    fn drop_handles_by(filter: impl Fn(i32, &WalletHandle) -> bool) -> Vec<(i32, WalletHandle)> {
+        let (dropped, closed) = {
+            let mut handles = HANDLES.lock().unwrap();
+            let dropped: Vec<(i32, WalletHandle)> = handles
+                .iter()
+                .filter(|(id, h)| filter(**id, h))
+                .map(|(id, h)| (*id, h.clone()))
+                .collect();
+            dropped.iter().for_each(|(id, _)| {
+                handles.remove(id);
+            });
+            let mut closed: Vec<&WalletHandle> = dropped
+                .iter()
+                .map(|(_, h)| h)
+                .filter(|h| {
+                    !handles
+                        .values()
+                        .any(|o| o.collection_uuid == h.collection_uuid)
+                })
+                .collect();
+            closed.sort_by_key(|h| h.collection_uuid);
+            closed.dedup_by_key(|h| h.collection_uuid);
+            let closed: Vec<String> = closed.into_iter().map(|h| h.wallet.clone()).collect();
+            (dropped, closed)
+        };
+        for (handle, _) in &dropped {
+            trace!("Dropped wallet handle {}", handle);
+            emit(OrgKdeKWalletWalletClosedId { handle: *handle });
+        }
+        for wallet in closed {
+            emit(OrgKdeKWalletWalletClosed { wallet });
+        }
+        dropped
+    }
+
+    fn add_handle(handle: WalletHandle) -> i32 {
+        let id = next_id();
+        debug!(
+            "{} opened wallet '{}' as {}",
+            handle.client, handle.wallet, id
+        );
+        let wallet = handle.wallet.clone();
+        HANDLES.lock().unwrap().insert(id, handle);
+        emit(OrgKdeKWalletWalletOpened { wallet });
+        id
+    }
+
+    /// The collection and wallet name behind a handle of the caller
+    fn opened(ctx: &Context, handle: i32) -> Result<(Uuid, String), MethodErr> {
+        let sender = sender_of(ctx)?;
+        match HANDLES.lock().unwrap().get(&handle) {
+            Some(h) if h.client == sender => Ok((h.collection_uuid, h.wallet.clone())),
+            _ => Err(MethodErr::failed(&format!(
+                "Invalid wallet handle {}",
+                handle
+            ))),
+        }
+    }
+
+    fn handles_of(wallet: &str) -> Vec<WalletHandle> {
+        let Some(uuid) = find_wallet(&STORAGE.read().unwrap(), wallet) else {
+            return Vec::new();
+        };
+        HANDLES
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|h| h.collection_uuid == uuid)
+            .cloned()
+            .collect()
+    }
+
+    /// Creates the collection of a wallet opened for the first time
+    fn create_wallet(wallet: &str) -> Result<Uuid, TksError> {
+        let taken = STORAGE
+            .read()
+            .unwrap()
+            .collections
+            .iter()
+            .any(|c| sanitize_string(&c.name) == sanitize_string(wallet));
+        if taken {
+            // the name makes the object path of the collection
+            debug!(
+                "Cannot create wallet '{}', a collection has this name",
+                wallet
+            );
+            return Err(TksError::Duplicate);
+        }
+        let properties = HashMap::from([(LABEL_PROPERTY.to_string(), wallet.to_string())]);
+        let created = STORAGE
+            .write()
+            .unwrap()
+            .create_collection(wallet, "", &properties);
+        let uuids = created.as_ref().map_or(Vec::new(), |uuid| vec![*uuid]);
+        audit::record(AuditEvent::CollectionCreate, (&created).into(), uuids);
+        let uuid = created?;
+        let storage = STORAGE.read().unwrap();
+        let collection = storage.with_collection(&uuid, |c| Ok(CollectionImpl::from(c)))?;
+        alias_registry::update(&storage);
+        drop(storage);
+        let path = collection.listed_path();
+        tokio::spawn(async move {
+            debug!("Sending CollectionCreated signal");
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretServiceCollectionCreated {
+                    collection: path.clone(),
+                }
+                .to_emit_message(&path),
+            );
+        });
+        emit(OrgKdeKWalletWalletCreated {
+            wallet: wallet.to_string(),
+        });
+        emit(OrgKdeKWalletWalletListDirty {});
+        Ok(uuid)
+    }
+
+    /// Finds, or creates, the collection of the wallet, and the dialogs to run before the client
+    /// may use it
+    fn prepare_open(
+        ctx: &mut Context,
+        wallet: &str,
+    ) -> Result<(Uuid, Vec<PromptAction>), TksError> {
+        if wallet.is_empty() {
+            return Err(TksError::ParameterError);
+        }
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let found = find_wallet(&STORAGE.read().unwrap(), wallet);
+        let uuid = match found {
+            Some(uuid) if visibility::hidden_from(sender.as_deref()).contains(&uuid) => {
+                debug!("Wallet '{}' is hidden from {:?}", wallet, sender);
+                return Err(TksError::PermissionDenied);
+            }
+            Some(uuid) => uuid,
+            None => KWalletImpl::create_wallet(wallet)?,
+        };
+        let mut prompts = Vec::new();
+        let locked = STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&uuid, |c| Ok(c.locked))?;
+        if locked {
+            prompts.push(STORAGE.write().unwrap().create_unlock_action(&uuid)?);
+        }
+        // the known clients only get read once the storage is unlocked, see Unlock
+        let (_, enroll) = CLIENT_REGISTRY.lock().unwrap().enroll_action(ctx)?;
+        prompts.extend(enroll);
+        let outcome = match prompts.is_empty() {
+            true => Outcome::Success,
+            false => Outcome::Prompted,
+        };
+        audit::record(AuditEvent::CollectionUnlock, outcome, vec![uuid]);
+        Ok((uuid, prompts))
+    }
+
+    /// Opens the wallet for the client, once the user answered the dialogs
+    fn open_wallet(ctx: &mut Context, wallet: String, appid: String, reply: OpenReply) {
+        let (uuid, prompts) = match KWalletImpl::prepare_open(ctx, &wallet) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                debug!("Cannot open wallet '{}': {}", wallet, e);
+                return reply.send(-1);
+            }
+        };
+        let handle = WalletHandle {
+            collection_uuid: uuid,
+            wallet,
+            client: sender_of(ctx).unwrap_or_default(),
+            appid,
+        };
+        if prompts.is_empty() {
+            return reply.send(KWalletImpl::add_handle(handle));
+        }
+        // the dialogs may take a while, the other clients keep being served meanwhile
+        tokio::task::spawn_blocking(move || {
+            for action in prompts {
+                match action.perform() {
+                    Ok(false) => continue,
+                    Ok(true) => debug!("Opening wallet '{}' got dismissed", handle.wallet),
+                    Err(e) => error!("Cannot open wallet '{}': {}", handle.wallet, e),
+                }
+                return reply.send(-1);
+            }
+            let unlocked = STORAGE
+                .read()
+                .unwrap()
+                .with_collection(&uuid, |c| Ok(!c.locked))
+                .unwrap_or(false);
+            match unlocked {
+                true => reply.send(KWalletImpl::add_handle(handle)),
+                false => reply.send(-1),
+            }
+        });
+    }
+
+    /// The keys, types and secrets of the entries of a folder the filter keeps; the client needs
+    /// the read access to each of them
+    fn read_entries(
+        ctx: &Context,
+        handle: i32,
+        folder: &str,
+        filter: impl Fn(&Item) -> bool,
+    ) -> Result<Vec<(String, EntryType, Vec<u8>)>, MethodErr> {
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        let item_uuids: Vec<Uuid> = STORAGE.read().unwrap().with_collection(&uuid, |c| {
+            Ok(folder_entries(c, folder)
+                .filter(|i| filter(i))
+                .map(|i| i.id.uuid)
+                .collect())
+        })?;
+        let mut entries = Vec::new();
+        for item_uuid in item_uuids {
+            acl::check(&uuid, Some(&item_uuid), Access::Read).map_err(|e| {
+                audit::record(AuditEvent::SecretRead, Outcome::Failure, vec![item_uuid]);
+                MethodErr::from(e)
+            })?;
+            let result = STORAGE
+                .read()
+                .unwrap()
+                .with_item(&uuid, &item_uuid, |item| {
+                    let data = item.data.as_ref().ok_or(TksError::PermissionDenied)?;
+                    Ok((
+                        item.label.clone(),
+                        EntryType::of(item),
+                        data.secret().to_vec(),
+                    ))
+                });
+            audit::record(AuditEvent::SecretRead, (&result).into(), vec![item_uuid]);
+            entries.push(result?);
+        }
+        Ok(entries)
+    }
+
+    fn read_entry_of(
+        ctx: &Context,
+        handle: i32,
+        folder: &str,
+        key: &str,
+    ) -> Result<Option<(EntryType, Vec<u8>)>, MethodErr> {
+        let entries = KWalletImpl::read_entries(ctx, handle, folder, |i| i.label == key)?;
+        Ok(entries
+            .into_iter()
+            .next()
+            .map(|(_, entry_type, secret)| (entry_type, secret)))
+    }
+
+    /// Writes an entry, replacing the secret of the one having the same key
+    fn write(
+        ctx: &Context,
+        handle: i32,
+        folder: &str,
+        key: &str,
+        entry_type: EntryType,
+        secret: Vec<u8>,
+        map_keys: &[String],
+    ) -> Result<(), MethodErr> {
+        let (uuid, wallet) = KWalletImpl::opened(ctx, handle)?;
+        let (existing, new_folder) = STORAGE.read().unwrap().with_collection(&uuid, |c| {
+            let existing = find_entry(c, folder, key).map(|i| i.id.clone());
+            Ok((existing, !folders(c).contains(folder)))
+        })?;
+        let attributes = entry_attributes(folder, entry_type, map_keys);
+        let content_type = entry_type.content_type().to_string();
+        match existing {
+            Some(item_id) => {
+                acl::check(&uuid, Some(&item_id.uuid), Access::Write).map_err(|e| {
+                    audit::record(
+                        AuditEvent::SecretWrite,
+                        Outcome::Failure,
+                        vec![item_id.uuid],
+                    );
+                    MethodErr::from(e)
+                })?;
+                let result = STORAGE
+                    .write()
+                    .unwrap()
+                    .modify_item(&uuid, &item_id.uuid, |item| {
+                        let data = item.data.as_mut().ok_or(TksError::PermissionDenied)?;
+                        data.replace(secret.into(), content_type);
+                        item.attributes
+                            .retain(|name, _| !name.starts_with(MAP_KEY_ATTRIBUTE));
+                        item.attributes.extend(attributes);
+                        Ok(())
+                    });
+                audit::record(
+                    AuditEvent::SecretWrite,
+                    (&result).into(),
+                    vec![item_id.uuid],
+                );
+                result?;
+                emit_item_changed(&item_id, &["Attributes", "Type", "Modified"]);
+            }
+            None => {
+                acl::check(&uuid, None, Access::Write).map_err(|e| {
+                    audit::record(AuditEvent::ItemCreate, Outcome::Failure, vec![uuid]);
+                    MethodErr::from(e)
+                })?;
+                let result = STORAGE.write().unwrap().modify_collection(&uuid, |c| {
+                    c.add_item(key, attributes, secret, content_type)
+                });
+                let uuids = result.as_ref().map_or(vec![uuid], |id| vec![id.uuid]);
+                audit::record(AuditEvent::ItemCreate, (&result).into(), uuids);
+                ItemImpl::register(&result?);
+                emit_items_changed(uuid);
+            }
+        }
+        if let Some(empty) = EMPTY_FOLDERS.lock().unwrap().get_mut(&uuid) {
+            empty.remove(folder);
+        }
+        emit(OrgKdeKWalletFolderUpdated {
+            wallet: wallet.clone(),
+            folder: folder.to_string(),
+        });
+        if new_folder {
+            emit(OrgKdeKWalletFolderListUpdated { wallet });
+        }
+        Ok(())
+    }
+
+    /// Moves entries to the trash; the client needs the delete access to each of them
+    fn remove(
+        ctx: &Context,
+        handle: i32,
+        folder: &str,
+        key: Option<&str>,
+    ) -> Result<bool, MethodErr> {
+        let (uuid, wallet) = KWalletImpl::opened(ctx, handle)?;
+        let item_ids: Vec<ItemId> = STORAGE.read().unwrap().with_collection(&uuid, |c| {
+            Ok(folder_entries(c, folder)
+                .filter(|i| key.is_none_or(|key| i.label == key))
+                .map(|i| i.id.clone())
+                .collect())
+        })?;
+        if item_ids.is_empty() {
+            return Ok(false);
+        }
+        for item_id in &item_ids {
+            acl::check(&uuid, Some(&item_id.uuid), Access::Delete).map_err(|e| {
+                audit::record(AuditEvent::ItemDelete, Outcome::Failure, vec![item_id.uuid]);
+                MethodErr::from(e)
+            })?;
+        }
+        let result = STORAGE.write().unwrap().delete_items(&item_ids);
+        let uuids = item_ids.iter().map(|id| id.uuid).collect();
+        audit::record(AuditEvent::ItemDelete, (&result).into(), uuids);
+        result?;
+        item_ids.iter().for_each(ItemImpl::unregister);
+        emit_items_changed(uuid);
+        emit(OrgKdeKWalletFolderUpdated {
+            wallet,
+            folder: folder.to_string(),
+        });
+        Ok(true)
+    }
+
+    /// The entries of a folder as the `*List` methods give them, by key
+    fn list(
+        ctx: &Context,
+        handle: i32,
+        folder: &str,
+        pattern: Option<&str>,
+        entry_type: Option<EntryType>,
+    ) -> Result<PropMap, MethodErr> {
+        let entries = KWalletImpl::read_entries(ctx, handle, folder, |i| {
+            pattern.is_none_or(|p| wildcard_matches(p, &i.label))
+                && entry_type.is_none_or(|t| EntryType::of(i) == t)
+        })?;
+        let mut list = PropMap::new();
+        for (key, entry_type, secret) in entries {
+            let value = match entry_type {
+                EntryType::Password => {
+                    Variant(Box::new(String::from_utf8_lossy(&secret).into_owned()) as _)
+                }
+                _ => Variant(Box::new(encoded_value(entry_type, &secret)?) as _),
+            };
+            list.insert(key, value);
+        }
+        Ok(list)
+    }
+}
+
+impl OrgKdeKWallet for KWalletImpl {
+    fn is_enabled(&mut self) -> Result<bool, MethodErr> {
+        Ok(true)
+    }
+    fn open(
+        &mut self,
+        ctx: &mut Context,
+        wallet: String,
+        _w_id: i64,
+        appid: String,
+    ) -> Result<Option<i32>, MethodErr> {
+        trace!("open '{}' for {}", wallet, appid);
+        let reply = ctx.message().method_return();
+        KWalletImpl::open_wallet(ctx, wallet, appid, OpenReply::Reply(reply));
+        Ok(None)
+    }
+    fn open_async(
+        &mut self,
+        ctx: &mut Context,
+        wallet: String,
+        _w_id: i64,
+        appid: String,
+        _handle_session: bool,
+    ) -> Result<i32, MethodErr> {
+        trace!("open_async '{}' for {}", wallet, appid);
+        let t_id = next_id();
+        KWalletImpl::open_wallet(ctx, wallet, appid, OpenReply::Signal(t_id));
+        Ok(t_id)
+    }
+    fn close_wallet(&mut self, wallet: String, force: bool) -> Result<i32, MethodErr> {
+        trace!("close_wallet '{}' (force: {})", wallet, force);
+        let handles = KWalletImpl::handles_of(&wallet);
+        if handles.is_empty() || (handles.len() > 1 && !force) {
+            return Ok(-1);
+        }
+        KWalletImpl::drop_handles(|h| {
+            handles
+                .iter()
+                .any(|o| o.collection_uuid == h.collection_uuid)
+        });
+        Ok(0)
+    }
+    fn close(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        _force: bool,
+        appid: String,
+    ) -> Result<i32, MethodErr> {
+        trace!("close {} for {}", handle, appid);
+        status(KWalletImpl::opened(ctx, handle).map(|_| {
+            KWalletImpl::drop_handle(handle);
+        }))
+    }
+    fn sync(&mut self, ctx: &mut Context, handle: i32, _appid: String) -> Result<(), MethodErr> {
+        trace!("sync {}", handle);
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        Ok(STORAGE.write().unwrap().flush_collection(&uuid)?)
+    }
+    fn delete_wallet(&mut self, wallet: String) -> Result<i32, MethodErr> {
+        debug!(
+            "Refusing to delete wallet '{}', its collection gets deleted through Delete",
+            wallet
+        );
+        Ok(-1)
+    }
+    fn is_open_wallet(&mut self, wallet: String) -> Result<bool, MethodErr> {
+        Ok(!KWalletImpl::handles_of(&wallet).is_empty())
+    }
+    fn is_open(&mut self, ctx: &mut Context, handle: i32) -> Result<bool, MethodErr> {
+        Ok(KWalletImpl::opened(ctx, handle).is_ok())
+    }
+    fn users(&mut self, wallet: String) -> Result<Vec<String>, MethodErr> {
+        let users: BTreeSet<String> = KWalletImpl::handles_of(&wallet)
+            .into_iter()
+            .map(|h| h.appid)
+            .collect();
+        Ok(users.into_iter().collect())
+    }
+    fn wallets(&mut self, ctx: &mut Context) -> Result<Vec<String>, MethodErr> {
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let hidden = visibility::hidden_from(sender.as_deref());
+        Ok(STORAGE
+            .read()
+            .unwrap()
+            .collections
+            .iter()
+            .filter(|c| !hidden.contains(&c.uuid))
+            .map(|c| c.label().to_string())
+            .collect())
+    }
+    fn folder_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        _appid: String,
+    ) -> Result<Vec<String>, MethodErr> {
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        let folders = STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&uuid, |c| Ok(folders(c)))?;
+        Ok(folders.into_iter().collect())
+    }
+    fn has_folder(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<bool, MethodErr> {
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        Ok(STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&uuid, |c| Ok(folders(c).contains(&folder)))?)
+    }
+    fn create_folder(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<bool, MethodErr> {
+        trace!("create_folder '{}' in {}", folder, handle);
+        let (uuid, wallet) = KWalletImpl::opened(ctx, handle)?;
+        let exists = STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&uuid, |c| Ok(folders(c).contains(&folder)))?;
+        if !exists {
+            EMPTY_FOLDERS
+                .lock()
+                .unwrap()
+                .entry(uuid)
+                .or_default()
+                .insert(folder);
+            emit(OrgKdeKWalletFolderListUpdated { wallet });
+        }
+        Ok(true)
+    }
+    fn remove_folder(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<bool, MethodErr> {
+        trace!("remove_folder '{}' in {}", folder, handle);
+        let (uuid, wallet) = KWalletImpl::opened(ctx, handle)?;
+        let empty = EMPTY_FOLDERS
+            .lock()
+            .unwrap()
+            .get_mut(&uuid)
+            .is_some_and(|empty| empty.remove(&folder));
+        let removed = KWalletImpl::remove(ctx, handle, &folder, None)?;
+        if removed || empty {
+            emit(OrgKdeKWalletFolderListUpdated { wallet });
+        }
+        Ok(removed || empty)
+    }
+    fn entry_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<Vec<String>, MethodErr> {
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        let keys: BTreeSet<String> = STORAGE.read().unwrap().with_collection(&uuid, |c| {
+            Ok(folder_entries(c, &folder)
+                .map(|i| i.label.clone())
+                .collect())
+        })?;
+        Ok(keys.into_iter().collect())
+    }
+    fn read_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<Vec<u8>, MethodErr> {
+        trace!("read_entry '{}/{}' of {}", folder, key, handle);
+        match KWalletImpl::read_entry_of(ctx, handle, &folder, &key)? {
+            Some((entry_type, secret)) => Ok(encoded_value(entry_type, &secret)?),
+            None => Ok(Vec::new()),
+        }
+    }
+    fn read_map(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<Vec<u8>, MethodErr> {
+        trace!("read_map '{}/{}' of {}", folder, key, handle);
+        match KWalletImpl::read_entry_of(ctx, handle, &folder, &key)? {
+            Some((EntryType::Map, secret)) => Ok(encoded_value(EntryType::Map, &secret)?),
+            _ => Ok(Vec::new()),
+        }
+    }
+    fn read_password(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<String, MethodErr> {
+        trace!("read_password '{}/{}' of {}", folder, key, handle);
+        match KWalletImpl::read_entry_of(ctx, handle, &folder, &key)? {
+            Some((EntryType::Password, secret)) => Ok(String::from_utf8_lossy(&secret).into()),
+            _ => Ok(String::new()),
+        }
+    }
+    fn read_entry_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<PropMap, MethodErr> {
+        KWalletImpl::list(ctx, handle, &folder, Some(&key), None)
+    }
+    fn read_map_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<PropMap, MethodErr> {
+        KWalletImpl::list(ctx, handle, &folder, Some(&key), Some(EntryType::Map))
+    }
+    fn read_password_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<PropMap, MethodErr> {
+        KWalletImpl::list(ctx, handle, &folder, Some(&key), Some(EntryType::Password))
+    }
+    fn entries_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<PropMap, MethodErr> {
+        KWalletImpl::list(ctx, handle, &folder, None, None)
+    }
+    fn map_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<PropMap, MethodErr> {
+        KWalletImpl::list(ctx, handle, &folder, None, Some(EntryType::Map))
+    }
+    fn password_list(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        _appid: String,
+    ) -> Result<PropMap, MethodErr> {
+        KWalletImpl::list(ctx, handle, &folder, None, Some(EntryType::Password))
+    }
+    fn rename_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        old_name: String,
+        new_name: String,
+        _appid: String,
+    ) -> Result<i32, MethodErr> {
+        trace!(
+            "rename_entry '{}/{}' to '{}' in {}",
+            folder,
+            old_name,
+            new_name,
+            handle
+        );
+        status((|| {
+            let (uuid, wallet) = KWalletImpl::opened(ctx, handle)?;
+            let item_id = STORAGE.read().unwrap().with_collection(&uuid, |c| {
+                if find_entry(c, &folder, &new_name).is_some() {
+                    return Err(TksError::Duplicate);
+                }
+                let item = find_entry(c, &folder, &old_name).ok_or(TksError::NotFound(None))?;
+                Ok(item.id.clone())
+            })?;
+            acl::check(&uuid, Some(&item_id.uuid), Access::Write)?;
+            STORAGE
+                .write()
+                .unwrap()
+                .modify_item(&uuid, &item_id.uuid, |item| {
+                    item.label = new_name;
+                    Ok(())
+                })?;
+            emit_item_changed(&item_id, &["Label", "Modified"]);
+            emit(OrgKdeKWalletFolderUpdated { wallet, folder });
+            Ok(())
+        })())
+    }
+    fn write_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        value: Vec<u8>,
+        entry_type: i32,
+        _appid: String,
+    ) -> Result<i32, MethodErr> {
+        trace!(
+            "write_entry '{}/{}' ({}) in {}",
+            folder,
+            key,
+            entry_type,
+            handle
+        );
+        match EntryType::from_i32(entry_type) {
+            EntryType::Password => {
+                let password = match decode_qstring(&value) {
+                    Ok(password) => password,
+                    Err(e) => return status(Err(e.into())),
+                };
+                self.write_password(ctx, handle, folder, key, password, _appid)
+            }
+            EntryType::Map => self.write_map(ctx, handle, folder, key, value, _appid),
+            EntryType::Unknown | EntryType::Stream => status(KWalletImpl::write(
+                ctx,
+                handle,
+                &folder,
+                &key,
+                EntryType::Stream,
+                value,
+                &[],
+            )),
+        }
+    }
+    fn write_map(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        value: Vec<u8>,
+        _appid: String,
+    ) -> Result<i32, MethodErr> {
+        trace!("write_map '{}/{}' in {}", folder, key, handle);
+        status((|| {
+            let map = decode_qmap(&value)?;
+            let keys: Vec<String> = map.keys().cloned().collect();
+            let secret = serde_json::to_vec(&map).map_err(TksError::from)?;
+            KWalletImpl::write(ctx, handle, &folder, &key, EntryType::Map, secret, &keys)
+        })())
+    }
+    fn write_password(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        value: String,
+        _appid: String,
+    ) -> Result<i32, MethodErr> {
+        trace!("write_password '{}/{}' in {}", folder, key, handle);
+        status(KWalletImpl::write(
+            ctx,
+            handle,
+            &folder,
+            &key,
+            EntryType::Password,
+            value.into_bytes(),
+            &[],
+        ))
+    }
+    fn has_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<bool, MethodErr> {
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        Ok(STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&uuid, |c| Ok(find_entry(c, &folder, &key).is_some()))?)
+    }
+    fn entry_type(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<i32, MethodErr> {
+        let (uuid, _) = KWalletImpl::opened(ctx, handle)?;
+        let entry_type = STORAGE.read().unwrap().with_collection(&uuid, |c| {
+            Ok(find_entry(c, &folder, &key).map_or(EntryType::Unknown, EntryType::of))
+        })?;
+        Ok(entry_type as i32)
+    }
+    fn remove_entry(
+        &mut self,
+        ctx: &mut Context,
+        handle: i32,
+        folder: String,
+        key: String,
+        _appid: String,
+    ) -> Result<i32, MethodErr> {
+        trace!("remove_entry '{}/{}' in {}", folder, key, handle);
+        status(KWalletImpl::remove(ctx, handle, &folder, Some(&key)).map(|_| ()))
+    }
+    fn disconnect_application(
+        &mut self,
+        wallet: String,
+        application: String,
+    ) -> Result<bool, MethodErr> {
+        trace!("disconnect_application {} from '{}'", application, wallet);
+        let Some(uuid) = find_wallet(&STORAGE.read().unwrap(), &wallet) else {
+            return Ok(false);
+        };
+        let dropped =
+            KWalletImpl::drop_handles(|h| h.collection_uuid == uuid && h.appid == application);
+        if dropped.is_empty() {
+            return Ok(false);
+        }
+        emit(OrgKdeKWalletApplicationDisconnected {
+            wallet,
+            application,
+        });
+        Ok(true)
+    }
+    fn folder_does_not_exist(&mut self, wallet: String, folder: String) -> Result<bool, MethodErr> {
+        // the attributes are readable while the collection is locked
+        let storage = STORAGE.read().unwrap();
+        let Some(uuid) = find_wallet(&storage, &wallet) else {
+            return Ok(true);
+        };
+        Ok(!storage.with_collection(&uuid, |c| Ok(folders(c).contains(&folder)))?)
+    }
+    fn key_does_not_exist(
+        &mut self,
+        wallet: String,
+        folder: String,
+        key: String,
+    ) -> Result<bool, MethodErr> {
+        let storage = STORAGE.read().unwrap();
+        let Some(uuid) = find_wallet(&storage, &wallet) else {
+            return Ok(true);
+        };
+        Ok(!storage.with_collection(&uuid, |c| Ok(find_entry(c, &folder, &key).is_some()))?)
+    }
+    fn close_all_wallets(&mut self) -> Result<(), MethodErr> {
+        trace!("close_all_wallets");
+        KWalletImpl::drop_handles(|_| true);
+        emit(OrgKdeKWalletAllWalletsClosed {});
+        Ok(())
+    }
+    fn network_wallet(&mut self) -> Result<String, MethodErr> {
+        self.local_wallet()
+    }
+    fn local_wallet(&mut self) -> Result<String, MethodErr> {
+        Ok(STORAGE
+            .read()
+            .unwrap()
+            .collections
+            .iter()
+            .find(|c| c.default)
+            .map_or(DEFAULT_WALLET.to_string(), |c| c.label().to_string()))
+    }
+}
@@ -0,0 +1,125 @@
+//! All the collections get locked when the desktop session does, as configured by
+//! `auto_lock.lock_on`: the screensaver activating (`org.freedesktop.ScreenSaver`, session bus),
+//! logind locking the session, flagging it idle, or preparing for sleep (`org.freedesktop.login1`,
+//! system bus).
+
+use crate::settings::{LockTrigger, SETTINGS};
+use crate::storage::STORAGE;
+use dbus::arg::PropMap;
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus_tokio::connection;
+use log::{debug, error, info, trace};
+use std::time::Duration;
+
+const LOGIN1_NAME: &str = "org.freedesktop.login1";
+const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+const LOGIN1_MANAGER: &str = "org.freedesktop.login1.Manager";
+const LOGIN1_SESSION: &str = "org.freedesktop.login1.Session";
+/// logind resolves it to the caller's session, or to the user's display session
+const LOGIN1_AUTO_SESSION: &str = "/org/freedesktop/login1/session/auto";
+const SCREENSAVER_INTERFACE: &str = "org.freedesktop.ScreenSaver";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Subscribes to the signals of the configured triggers; `c` is the session bus connection
+pub async fn start_watching(c: &SyncConnection) -> Result<(), dbus::Error> {
+    let triggers = SETTINGS.lock().unwrap().auto_lock.lock_on.clone();
+    if triggers.is_empty() {
+        return Ok(());
+    }
+    debug!("Locking all collections upon {:?}", triggers);
+    if triggers.contains(&LockTrigger::Screensaver) {
+        let rule = MatchRule::new_signal(SCREENSAVER_INTERFACE, "ActiveChanged");
+        c.add_match_no_cb(&rule.match_str()).await?;
+        c.start_receive(
+            rule,
+            Box::new(|msg, _conn| {
+                if let Ok(true) = msg.read1::<bool>() {
+                    lock_all(LockTrigger::Screensaver);
+                }
+                true
+            }),
+        );
+    }
+    let logind_triggers = [LockTrigger::SessionLock, LockTrigger::Idle, LockTrigger::Sleep];
+    if logind_triggers.iter().any(|t| triggers.contains(t)) {
+        watch_logind(&triggers).await?;
+    }
+    Ok(())
+}
+
+async fn watch_logind(triggers: &[LockTrigger]) -> Result<(), dbus::Error> {
+    let (resource, c) = connection::new_system_sync()?;
+    tokio::spawn(async {
+        let err = resource.await;
+        error!("Lost connection to the system bus: {}", err);
+    });
+
+    if triggers.contains(&LockTrigger::Sleep) {
+        let rule = MatchRule::new_signal(LOGIN1_MANAGER, "PrepareForSleep");
+        c.add_match_no_cb(&rule.match_str()).await?;
+        c.start_receive(
+            rule,
+            Box::new(|msg, _conn| {
+                // also sent with false upon resume
+                if let Ok(true) = msg.read1::<bool>() {
+                    lock_all(LockTrigger::Sleep);
+                }
+                true
+            }),
+        );
+    }
+
+    let session = session_path(&c).await?;
+    trace!("Watching logind session {}", session);
+    if triggers.contains(&LockTrigger::SessionLock) {
+        let rule = MatchRule::new_signal(LOGIN1_SESSION, "Lock").with_path(session.clone());
+        c.add_match_no_cb(&rule.match_str()).await?;
+        c.start_receive(
+            rule,
+            Box::new(|_msg, _conn| {
+                lock_all(LockTrigger::SessionLock);
+                true
+            }),
+        );
+    }
+    if triggers.contains(&LockTrigger::Idle) {
+        let rule =
+            MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+                .with_path(session);
+        c.add_match_no_cb(&rule.match_str()).await?;
+        c.start_receive(
+            rule,
+            Box::new(|msg, _conn| {
+                if let Ok((LOGIN1_SESSION, changed)) = msg.read2::<&str, PropMap>() {
+                    let idle = changed.get("IdleHint").and_then(|v| v.0.as_u64());
+                    if idle == Some(1) {
+                        lock_all(LockTrigger::Idle);
+                    }
+                }
+                true
+            }),
+        );
+    }
+    Ok(())
+}
+
+/// The object path of our logind session, as signals are sent from the actual path
+async fn session_path(c: &SyncConnection) -> Result<dbus::Path<'static>, dbus::Error> {
+    let auto = Proxy::new(LOGIN1_NAME, LOGIN1_AUTO_SESSION, TIMEOUT, c);
+    let id: String = auto.get(LOGIN1_SESSION, "Id").await?;
+    let manager = Proxy::new(LOGIN1_NAME, LOGIN1_PATH, TIMEOUT, c);
+    let (path,): (dbus::Path<'static>,) =
+        manager.method_call(LOGIN1_MANAGER, "GetSession", (id,)).await?;
+    Ok(path)
+}
+
+fn lock_all(trigger: LockTrigger) {
+    match STORAGE.write().unwrap().lock_all() {
+        Ok(locked) if locked.is_empty() => trace!("{:?}, nothing to lock", trigger),
+        Ok(locked) => info!("{:?}, locked {} collection(s)", trigger, locked.len()),
+        Err(e) => error!("Cannot lock the collections upon {:?}: {}", trigger, e),
+    }
+}
@@ -1,21 +1,63 @@
 pub mod fdo;
+pub mod gnome;
+pub mod kde;
+pub mod tks;
 
+pub mod acl;
+pub mod alias_registry;
 pub mod collection_impl;
+pub mod gnome_keyring_impl;
 pub mod item_impl;
+pub mod kwallet_impl;
+pub mod object_manager;
+pub mod portal_impl;
 pub mod prompt_impl;
+pub mod prompter;
+pub mod quirks;
 pub mod service_impl;
 pub mod session_impl;
 pub mod client_context;
+pub mod diagnostics;
+pub mod owner_tracker;
+pub mod lock_triggers;
+pub mod plain_transfers;
+pub mod visibility;
+pub mod sandbox;
 
+use crate::audit;
+use crate::settings::reload;
+use crate::settings::BusKind;
+use crate::settings::RunMode;
+use crate::settings::SETTINGS;
+use crate::storage::{auto_lock, Storage};
+use crate::tks_dbus::fdo::object_manager::register_org_freedesktop_dbus_object_manager;
+use crate::tks_dbus::fdo::portal_secret::register_org_freedesktop_impl_portal_secret;
 use crate::tks_dbus::fdo::service::register_org_freedesktop_secret_service;
+use crate::tks_dbus::gnome::keyring::{
+    register_org_gnome_keyring_daemon,
+    register_org_gnome_keyring_internal_unsupported_guilt_ridden_interface,
+};
+use crate::tks_dbus::gnome_keyring_impl::GnomeKeyringDaemon;
+use crate::tks_dbus::kde::kwallet::register_org_kde_kwallet;
+use crate::tks_dbus::kwallet_impl::KWalletImpl;
+use crate::tks_dbus::object_manager::insert;
+use crate::tks_dbus::portal_impl::SecretPortal;
+use crate::tks_dbus::tks::oauth::register_io_linux_tks_oauth1;
+use crate::tks_dbus::tks::search::register_io_linux_tks_search1;
+use crate::tks_dbus::tks::service::register_io_linux_tks_service1;
 use crate::tks_dbus::service_impl::ServiceImpl;
+use crate::tks_error::TksError;
+use dbus::arg::{PropMap, RefArg, Variant};
 use dbus::channel::MatchingReceiver;
 use dbus::channel::Sender;
-use dbus::message::MatchRule;
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
 use dbus::*;
+use dbus_crossroads::{Context, PropContext};
 use dbus_tokio::connection;
 use lazy_static::lazy_static;
-use log::{debug, trace, warn};
+use log::{debug, error, trace, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 
@@ -80,36 +122,74 @@ impl MessageSender {
                 c.send(msg).unwrap();
             }
             None => {
-                panic!("No connection");
+                // storage may get used without the bus, e.g. by the test suite
+                warn!("No connection, dropping message");
             }
         }
     }
 }
 
-#[macro_export]
-macro_rules! register_object {
-    ($iface:expr, $f:expr) => {
-        tokio::spawn(async move {
-            {
-                let mut cr_lock = CROSSROADS.lock().unwrap();
-                let itf = $iface(&mut cr_lock);
-                match $f.path() {
-                    DBusHandlePath::SinglePath(p) => {
-                        let p = p.to_string();
-                        trace!("Registering {}", p);
-                        cr_lock.insert(p, &[itf], $f);
-                    }
-                    DBusHandlePath::MultiplePaths(paths) => {
-                        for p in paths {
-                            let ps = p.to_string();
-                            trace!("Registering {}", ps);
-                            cr_lock.insert(p, &[itf], $f.clone());
-                        }
-                    }
-                }
+/// Runs `f`, which may show dialogs, outside of the DBus dispatch, so that the other clients keep
+/// being served meanwhile, then replies to the call being handled with what it returned; the
+/// method then returns `None`, see e.g. `TestPrompt` in [tks::service]
+pub fn reply_later<R, F>(ctx: &Context, f: F) -> Result<(), MethodErr>
+where
+    R: arg::AppendAll + Send + 'static,
+    F: FnOnce() -> Result<R, TksError> + Send + 'static,
+{
+    let mut call = ctx.message().duplicate().map_err(|e| MethodErr::failed(&e))?;
+    // the copy gets no serial, which the replies refer to
+    call.set_serial(ctx.message().get_serial().unwrap_or_default());
+    let client = call.sender().map(|s| s.to_string());
+    tokio::task::spawn_blocking(move || {
+        let reply = match prompter::with_client(client, f) {
+            Ok(result) => {
+                let mut reply = call.method_return();
+                reply.append_all(result);
+                reply
             }
-        });
-    };
+            Err(e) => MethodErr::from(e).to_message(&call),
+        };
+        MESSAGE_SENDER.lock().unwrap().send_message(reply);
+    });
+    Ok(())
+}
+
+/// Emits the standard `org.freedesktop.DBus.Properties.PropertiesChanged` signal on each of the
+/// given paths. Call it once the storage got modified, so the values are the new ones.
+pub fn emit_properties_changed(
+    paths: &[dbus::Path<'static>],
+    interface_name: &str,
+    changed_properties: PropMap,
+) {
+    if changed_properties.is_empty() {
+        return;
+    }
+    for path in paths {
+        debug!("Sending PropertiesChanged signal for {}", path);
+        MESSAGE_SENDER.lock().unwrap().send_message(
+            PropertiesPropertiesChanged {
+                interface_name: interface_name.to_string(),
+                changed_properties: changed_properties
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Variant(v.0.box_clone())))
+                    .collect(),
+                invalidated_properties: Vec::new(),
+            }
+            .to_emit_message(path),
+        );
+    }
+}
+
+/// Unique bus name of the client reading a property. Crossroads gives no message to the getters
+/// called by Properties.GetAll, the client is then the one whose call is being handled.
+pub fn property_caller(ctx: &PropContext) -> Option<String> {
+    let sender = ctx.message().and_then(|m| m.sender()).map(|s| s.to_string());
+    sender.or_else(audit::caller_bus_name)
+}
+
+pub fn prop_value<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
 }
 
 #[macro_export]
@@ -142,18 +222,39 @@ macro_rules! convert_prop_map {
     };
 }
 
-const DBUS_NAME: &'static str = "org.freedesktop.secrets";
-
 const DBUS_PATH: &'static str = "/org/freedesktop/secrets";
 
+/// Set once the service got connected to the system bus, see `bus.kind`
+static SYSTEM_BUS: AtomicBool = AtomicBool::new(false);
+
+/// Whether the service serves on the system bus rather than the session one
+pub fn on_system_bus() -> bool {
+    SYSTEM_BUS.load(Ordering::Relaxed)
+}
+
+/// A connection of its own to the bus the service is on, for the calls made outside of the
+/// dispatch, which must not wait on the service's connection
+pub fn blocking_connection() -> Result<dbus::blocking::Connection, dbus::Error> {
+    match on_system_bus() {
+        false => dbus::blocking::Connection::new_session(),
+        true => dbus::blocking::Connection::new_system(),
+    }
+}
+
 pub async fn start_server() {
-    trace!("Connecting to the D-Bus session bus");
-    let (resource, c) = connection::new_session_sync().unwrap_or_else(|_| {
+    let kind = SETTINGS.lock().unwrap().bus.kind;
+    trace!("Connecting to the D-Bus {:?} bus", kind);
+    let connected = match kind {
+        BusKind::Session => connection::new_session_sync(),
+        BusKind::System => connection::new_system_sync(),
+    };
+    let (resource, c) = connected.unwrap_or_else(|e| {
         panic!(
-            "Failed to connect to the D-Bus session bus. \
-                 Is a session bus instance of D-Bus running?"
+            "Failed to connect to the D-Bus {:?} bus, is it running? {}",
+            kind, e
         )
     });
+    SYSTEM_BUS.store(kind == BusKind::System, Ordering::Relaxed);
     let _handle = tokio::spawn(async {
         let err = resource.await;
         panic!("Connection has died: {:?}", err);
@@ -165,14 +266,63 @@ pub async fn start_server() {
         trace!("Registering org.freedesktop.Secret.Service");
         let mut crossroads = CROSSROADS.lock().unwrap();
         let itf = register_org_freedesktop_secret_service(&mut crossroads);
+        let tks_itf = register_io_linux_tks_service1(&mut crossroads);
+        let search_itf = register_io_linux_tks_search1(&mut crossroads);
+        let oauth_itf = register_io_linux_tks_oauth1(&mut crossroads);
+        let gnome_itf =
+            register_org_gnome_keyring_internal_unsupported_guilt_ridden_interface(&mut crossroads);
+        let object_manager_itf = register_org_freedesktop_dbus_object_manager(&mut crossroads);
         let service = ServiceImpl::new();
-        crossroads.insert(DBUS_PATH, &[itf], service);
+        insert(
+            &mut crossroads,
+            DBUS_PATH.into(),
+            &[itf, tks_itf, search_itf, oauth_itf, gnome_itf, object_manager_itf],
+            service,
+        );
         ServiceImpl::register_collections().unwrap();
+        if SETTINGS.lock().unwrap().kwallet.enabled {
+            trace!("Registering org.kde.KWallet");
+            let kwallet_itf = register_org_kde_kwallet(&mut crossroads);
+            for path in kwallet_impl::PATHS {
+                insert(&mut crossroads, path.into(), &[kwallet_itf], KWalletImpl::new());
+            }
+        }
+        if SETTINGS.lock().unwrap().gnome_keyring.enabled {
+            trace!("Registering org.gnome.keyring.Daemon");
+            let daemon_itf = register_org_gnome_keyring_daemon(&mut crossroads);
+            insert(
+                &mut crossroads,
+                gnome_keyring_impl::DAEMON_PATH.into(),
+                &[daemon_itf],
+                GnomeKeyringDaemon::default(),
+            );
+        }
+        if SETTINGS.lock().unwrap().portal.enabled {
+            trace!("Registering org.freedesktop.impl.portal.Secret");
+            let portal_itf = register_org_freedesktop_impl_portal_secret(&mut crossroads);
+            insert(
+                &mut crossroads,
+                portal_impl::PORTAL_PATH.into(),
+                &[portal_itf],
+                SecretPortal::default(),
+            );
+        }
+    }
+    Storage::start_flusher();
+    Storage::start_space_monitor();
+    Storage::start_auto_lock();
+    Storage::start_expiry();
+    Storage::start_sync();
+    Storage::start_trash_purge();
+    if let Err(e) = reload::start_watching() {
+        error!("Cannot watch the configuration file, ReloadConfig applies its changes: {}", e);
     }
 
-    trace!("Requesting name {}", DBUS_NAME);
+    let configured = SETTINGS.lock().unwrap().bus.name.clone();
+    let bus_name = RunMode::test_bus_name().unwrap_or(configured);
+    trace!("Requesting name {}", bus_name);
     let nr = c
-        .request_name(DBUS_NAME, false, true, true)
+        .request_name(bus_name.as_str(), false, true, true)
         .await
         .unwrap_or_else(|_| {
             panic!("Failed to acquire the service name");
@@ -182,6 +332,27 @@ pub async fn start_server() {
     if nr != PrimaryOwner {
         panic!("Failed to acquire the service name");
     }
+    if SETTINGS.lock().unwrap().kwallet.enabled {
+        for name in kwallet_impl::BUS_NAMES {
+            trace!("Requesting name {}", name);
+            match c.request_name(name, false, false, true).await {
+                Ok(PrimaryOwner) => {}
+                // the KWallet daemon may be running, it keeps its name then
+                Ok(nr) => error!("Cannot serve KWallet as {}: {:?}", name, nr),
+                Err(e) => error!("Cannot serve KWallet as {}: {}", name, e),
+            }
+        }
+    }
+    if SETTINGS.lock().unwrap().gnome_keyring.enabled {
+        let name = gnome_keyring_impl::BUS_NAME;
+        trace!("Requesting name {}", name);
+        match c.request_name(name, false, false, true).await {
+            Ok(PrimaryOwner) => {}
+            // gnome-keyring-daemon may be running, it keeps its name then
+            Ok(nr) => error!("Cannot serve as {}: {:?}", name, nr),
+            Err(e) => error!("Cannot serve as {}: {}", name, e),
+        }
+    }
 
     // let proxy = Proxy::new("org.freedesktop.DBus.Local", "/org/freedesktop/DBus/Local", Default::default(), c);
     // tokio::spawn( async move {
@@ -193,23 +364,26 @@ pub async fn start_server() {
         MatchRule::new_method_call(),
         Box::new(move |msg, conn| {
             trace!("Received message: {:?}", msg);
-            {
+            auto_lock::record_activity();
+            let sender = msg.sender().map(|s| s.to_string());
+            audit::set_caller(sender.clone());
+            prompter::with_client(sender, || {
                 CROSSROADS
                     .lock()
                     .unwrap()
                     .handle_message(msg, conn)
                     .unwrap();
-            }
+            });
+            audit::set_caller(None);
             debug!("Handled message");
             true
         }),
     );
-    trace!("Start receiving signals");
-    c.start_receive(
-        MatchRule::new_signal("org.freedesktop.DBus.local", "Disconnected"),
-        Box::new(move |msg, _conn| {
-            trace!("Received signal: {:?}", msg);
-            true
-        }),
-    );
+    owner_tracker::start_tracking(&c)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to track the bus clients: {}", e));
+    if let Err(e) = lock_triggers::start_watching(&c).await {
+        // e.g. no system bus in containers; the collections still lock by other means
+        error!("Cannot watch the session locking events: {}", e);
+    }
 }
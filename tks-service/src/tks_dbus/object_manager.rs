@@ -0,0 +1,348 @@
+//! The DBus objects of the service: the handles of the collections and items by uuid, and the
+//! paths of all the objects inserted into [CROSSROADS], which doesn't tell how many objects it
+//! has. The objects should get registered and unregistered through this module only. The
+//! handles are indexed by path too, as each method call of the Secret Service names its objects
+//! by path, e.g. GetSecrets for thousands of items at once.
+//!
+//! The service object also implements org.freedesktop.DBus.ObjectManager, so the clients can get
+//! the collections and items with their properties in one call instead of one per property. The
+//! collections are listed at the path the Collections property gives, the ones hidden from the
+//! caller by [visibility] left out; InterfacesAdded and InterfacesRemoved follow the objects of
+//! the collections visible to all.
+
+use crate::storage::STORAGE;
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollection;
+use crate::tks_dbus::fdo::object_manager::{
+    OrgFreedesktopDBusObjectManager, OrgFreedesktopDBusObjectManagerInterfacesAdded,
+    OrgFreedesktopDBusObjectManagerInterfacesRemoved,
+};
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::service_impl::ServiceImpl;
+use crate::tks_dbus::visibility;
+use crate::tks_dbus::{DBusHandle, DBusHandlePath, CROSSROADS, DBUS_PATH, MESSAGE_SENDER};
+use dbus::arg::PropMap;
+use dbus::message::SignalArgs;
+use dbus::MethodErr;
+use dbus_crossroads::{Context, Crossroads, IfaceToken};
+use lazy_static::lazy_static;
+use log::{debug, trace};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+lazy_static! {
+    /// The paths of the objects in CROSSROADS
+    static ref PATHS: Mutex<HashSet<dbus::Path<'static>>> = Mutex::new(HashSet::new());
+    pub static ref COLLECTION_HANDLES: Mutex<Handles<CollectionImpl>> =
+        Mutex::new(Handles::default());
+    pub static ref ITEM_HANDLES: Mutex<Handles<ItemImpl>> = Mutex::new(Handles::default());
+}
+
+/// The handles of the collections or of the items, by uuid and by each of their paths
+#[derive(Debug)]
+pub struct Handles<H> {
+    by_uuid: HashMap<Uuid, H>,
+    by_path: HashMap<String, Uuid>,
+}
+
+impl<H> Default for Handles<H> {
+    fn default() -> Self {
+        Handles {
+            by_uuid: HashMap::new(),
+            by_path: HashMap::new(),
+        }
+    }
+}
+
+impl<H: DBusHandle> Handles<H> {
+    pub fn get(&self, uuid: &Uuid) -> Option<&H> {
+        self.by_uuid.get(uuid)
+    }
+
+    /// The handle having the path, be it one of its alias paths
+    pub fn find(&self, path: &dbus::Path) -> Option<&H> {
+        self.by_path.get(&**path).and_then(|uuid| self.by_uuid.get(uuid))
+    }
+
+    pub fn get_or_insert_with(&mut self, uuid: Uuid, new: impl FnOnce() -> H) -> &H {
+        if !self.by_uuid.contains_key(&uuid) {
+            let handle = new();
+            for path in paths_of(&handle) {
+                self.by_path.insert(path.to_string(), uuid);
+            }
+            self.by_uuid.insert(uuid, handle);
+        }
+        &self.by_uuid[&uuid]
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) -> Option<H> {
+        let handle = self.by_uuid.remove(uuid)?;
+        self.unindex(uuid, &handle);
+        Some(handle)
+    }
+
+    /// Changes the handle, e.g. its paths, indexing it again
+    pub fn modify<R>(&mut self, uuid: &Uuid, change: impl FnOnce(&mut H) -> R) -> Option<R> {
+        let handle = self.by_uuid.get_mut(uuid)?;
+        let former = paths_of(handle);
+        let result = change(handle);
+        let paths = paths_of(handle);
+        for path in former.iter().filter(|p| !paths.contains(p)) {
+            if self.by_path.get(&**path) == Some(uuid) {
+                self.by_path.remove(&**path);
+            }
+        }
+        for path in paths {
+            self.by_path.insert(path.to_string(), *uuid);
+        }
+        Some(result)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &H> {
+        self.by_uuid.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_uuid.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_uuid.is_empty()
+    }
+
+    fn unindex(&mut self, uuid: &Uuid, handle: &H) {
+        for path in paths_of(handle) {
+            // an alias may have moved to another collection meanwhile
+            if self.by_path.get(&*path) == Some(uuid) {
+                self.by_path.remove(&*path);
+            }
+        }
+    }
+}
+
+/// The objects listed by GetManagedObjects
+pub trait ManagedObject: DBusHandle {
+    /// The Secret Service interface of the object
+    const INTERFACE: &'static str;
+    /// The path the object gets listed at
+    fn managed_path(&self) -> dbus::Path<'static>;
+    fn collection_uuid(&self) -> Uuid;
+    /// The properties of [Self::INTERFACE], as the client having the given unique bus name sees
+    /// them
+    fn properties(&self, sender: Option<&str>) -> PropMap;
+}
+
+/// Inserts an object into crossroads, replacing the one at the same path. Each object gets
+/// org.freedesktop.DBus.Properties, as some clients call GetAll on any object, and an empty
+/// object gets inserted at each of its parent paths having none, so that introspecting from the
+/// root reaches every object.
+pub fn insert<D: Any + Send + 'static>(
+    cr: &mut Crossroads,
+    path: dbus::Path<'static>,
+    itfs: &[IfaceToken<D>],
+    data: D,
+) {
+    insert_parents(cr, &path);
+    PATHS.lock().unwrap().insert(path.clone());
+    let mut itfs = itfs.to_vec();
+    itfs.push(cr.properties());
+    cr.insert(path, &itfs, data);
+}
+
+/// Removes an object from crossroads; None when there was no object of this type at the path
+pub fn remove<D: Any + Send + 'static>(
+    cr: &mut Crossroads,
+    path: &dbus::Path<'static>,
+) -> Option<D> {
+    PATHS.lock().unwrap().remove(path);
+    cr.remove(path)
+}
+
+/// How many objects crossroads has, the service included
+pub fn object_count() -> usize {
+    PATHS.lock().unwrap().len()
+}
+
+/// The maps keeping handles, see [handle]
+pub trait HandleMap<K, H> {
+    fn get_or_insert_with(&mut self, key: K, new: impl FnOnce() -> H) -> &H;
+}
+
+impl<K: Eq + Hash, H> HandleMap<K, H> for HashMap<K, H> {
+    fn get_or_insert_with(&mut self, key: K, new: impl FnOnce() -> H) -> &H {
+        self.entry(key).or_insert_with(new)
+    }
+}
+
+impl<H: DBusHandle> HandleMap<Uuid, H> for Handles<H> {
+    fn get_or_insert_with(&mut self, uuid: Uuid, new: impl FnOnce() -> H) -> &H {
+        Handles::get_or_insert_with(self, uuid, new)
+    }
+}
+
+/// The handle stored under the key, created by `new` when there is none yet
+pub fn handle<K, H: Clone, M: HandleMap<K, H>>(
+    handles: &Mutex<M>,
+    key: K,
+    new: impl FnOnce() -> H,
+) -> H {
+    handles
+        .lock()
+        .unwrap()
+        .get_or_insert_with(key, new)
+        .clone()
+}
+
+/// Inserts the object of the handle at each of its paths. This happens in the background, as the
+/// callers may run from a method call, holding CROSSROADS.
+pub fn register<D>(ifaces: &[fn(&mut Crossroads) -> IfaceToken<D>], handle: D)
+where
+    D: DBusHandle + Clone + Any + Send + 'static,
+{
+    let ifaces = ifaces.to_vec();
+    tokio::spawn(async move {
+        insert_all(&ifaces, &handle);
+    });
+}
+
+/// Registers the object like [register], then sends InterfacesAdded
+pub fn register_managed<D>(ifaces: &[fn(&mut Crossroads) -> IfaceToken<D>], handle: D)
+where
+    D: ManagedObject + Clone + Any + Send + 'static,
+{
+    let ifaces = ifaces.to_vec();
+    tokio::spawn(async move {
+        insert_all(&ifaces, &handle);
+        // the properties read the storage, which must not happen while holding CROSSROADS
+        if visibility::hidden_from(None).contains(&handle.collection_uuid()) {
+            return;
+        }
+        debug!(
+            "Sending InterfacesAdded signal for {}",
+            handle.managed_path()
+        );
+        MESSAGE_SENDER.lock().unwrap().send_message(
+            OrgFreedesktopDBusObjectManagerInterfacesAdded {
+                object: handle.managed_path(),
+                interfaces: interfaces_of(&handle, None),
+            }
+            .to_emit_message(&DBUS_PATH.into()),
+        );
+    });
+}
+
+/// Removes the objects at the given paths in the background, see [register]
+pub fn unregister<D: Any + Send + 'static>(paths: Vec<dbus::Path<'static>>) {
+    tokio::spawn(async move {
+        remove_all::<D>(&paths);
+    });
+}
+
+/// Unregisters the object of the handle like [unregister], then sends InterfacesRemoved
+pub fn unregister_managed<D: ManagedObject + Any + Send + 'static>(handle: D) {
+    tokio::spawn(async move {
+        remove_all::<D>(&paths_of(&handle));
+        // a deleted collection is no longer hidden, its objects are gone anyway
+        if visibility::hidden_from(None).contains(&handle.collection_uuid()) {
+            return;
+        }
+        debug!(
+            "Sending InterfacesRemoved signal for {}",
+            handle.managed_path()
+        );
+        MESSAGE_SENDER.lock().unwrap().send_message(
+            OrgFreedesktopDBusObjectManagerInterfacesRemoved {
+                object: handle.managed_path(),
+                interfaces: vec![D::INTERFACE.to_string()],
+            }
+            .to_emit_message(&DBUS_PATH.into()),
+        );
+    });
+}
+
+/// The interfaces registered into crossroads, in the given order
+pub fn tokens<D: Send + 'static>(
+    cr: &mut Crossroads,
+    ifaces: &[fn(&mut Crossroads) -> IfaceToken<D>],
+) -> Vec<IfaceToken<D>> {
+    ifaces.iter().map(|iface| iface(cr)).collect()
+}
+
+/// The parent nodes stay once inserted, the paths of the collections, sessions and prompts
+/// sharing a few of them
+fn insert_parents(cr: &mut Crossroads, path: &dbus::Path<'static>) {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    for depth in 0..segments.len() {
+        let parent = dbus::Path::from(format!("/{}", segments[..depth].join("/")));
+        // crossroads gives Introspectable to all its objects
+        if cr.has_interface(&parent, cr.introspectable::<()>()) {
+            continue;
+        }
+        trace!("Registering the node {}", parent);
+        let properties = cr.properties();
+        cr.insert(parent, &[properties], ());
+    }
+}
+
+fn paths_of<D: DBusHandle>(handle: &D) -> Vec<dbus::Path<'static>> {
+    match handle.path() {
+        DBusHandlePath::SinglePath(p) => vec![p],
+        DBusHandlePath::MultiplePaths(paths) => paths,
+    }
+}
+
+fn insert_all<D>(ifaces: &[fn(&mut Crossroads) -> IfaceToken<D>], handle: &D)
+where
+    D: DBusHandle + Clone + Any + Send + 'static,
+{
+    let mut cr_lock = CROSSROADS.lock().unwrap();
+    let itfs = tokens(&mut cr_lock, ifaces);
+    for path in paths_of(handle) {
+        trace!("Registering {}", path);
+        insert(&mut cr_lock, path, &itfs, handle.clone());
+    }
+}
+
+fn remove_all<D: Any + Send + 'static>(paths: &[dbus::Path<'static>]) {
+    let mut cr_lock = CROSSROADS.lock().unwrap();
+    for path in paths {
+        trace!("Unregistering {}", path);
+        remove::<D>(&mut cr_lock, path);
+    }
+}
+
+fn interfaces_of<D: ManagedObject>(handle: &D, sender: Option<&str>) -> HashMap<String, PropMap> {
+    HashMap::from([(D::INTERFACE.to_string(), handle.properties(sender))])
+}
+
+impl OrgFreedesktopDBusObjectManager for ServiceImpl {
+    fn get_managed_objects(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<HashMap<dbus::Path<'static>, HashMap<String, PropMap>>, MethodErr> {
+        trace!("get_managed_objects");
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let hidden = visibility::hidden_from(sender.as_deref());
+        let collections: Vec<CollectionImpl> = STORAGE
+            .read()
+            .unwrap()
+            .collections
+            .iter()
+            .filter(|c| !hidden.contains(&c.uuid))
+            .map(CollectionImpl::from)
+            .collect();
+        let mut objects = HashMap::new();
+        for collection in collections {
+            for path in collection.items()? {
+                let item = ItemImpl::from(&path);
+                objects.insert(path, interfaces_of(&item, sender.as_deref()));
+            }
+            let interfaces = interfaces_of(&collection, sender.as_deref());
+            objects.insert(collection.managed_path(), interfaces);
+        }
+        Ok(objects)
+    }
+}
@@ -0,0 +1,97 @@
+//! Collections may be bound to the client owning them, e.g. a password manager front-end. When
+//! such a collection has the `tks:lock-on-owner-exit` policy, it gets locked as soon as its owner
+//! disconnects from the bus. The bindings only last as long as the owner's connection. So do, for
+//! any client, the registration of a prompter, its batch leases, see [crate::storage::batch], its
+//! sessions and the prompts it never invoked.
+
+use crate::audit;
+use crate::storage::STORAGE;
+use crate::tks_dbus::kwallet_impl;
+use crate::tks_dbus::prompt_impl;
+use crate::tks_dbus::prompter;
+use crate::tks_dbus::quirks;
+use crate::tks_dbus::session_impl::SESSION_MANAGER;
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+use dbus::nonblock::SyncConnection;
+use lazy_static::lazy_static;
+use log::{debug, error, trace};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+pub const LOCK_ON_OWNER_EXIT_PROPERTY: &str = "tks:lock-on-owner-exit";
+
+lazy_static! {
+    /// Unique bus names of the collection owners
+    static ref OWNERS: Mutex<HashMap<Uuid, String>> = Mutex::new(HashMap::new());
+}
+
+pub fn owner(collection_uuid: &Uuid) -> Option<String> {
+    OWNERS.lock().unwrap().get(collection_uuid).cloned()
+}
+
+pub fn set_owner(collection_uuid: Uuid, owner: Option<String>) {
+    trace!("Collection {} owner is now {:?}", collection_uuid, owner);
+    let mut owners = OWNERS.lock().unwrap();
+    match owner {
+        Some(owner) => owners.insert(collection_uuid, owner),
+        None => owners.remove(&collection_uuid),
+    };
+}
+
+/// Listens to the bus' NameOwnerChanged signal, to learn about the owners leaving
+pub async fn start_tracking(c: &SyncConnection) -> Result<(), dbus::Error> {
+    let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+    c.add_match_no_cb(&rule.match_str()).await?;
+    c.start_receive(
+        rule,
+        Box::new(|msg, _conn| {
+            // unique names get released once, when their connection goes away
+            if let Ok((name, _old_owner, new_owner)) = msg.read3::<String, String, String>() {
+                if new_owner.is_empty() {
+                    owner_vanished(&name);
+                    STORAGE.write().unwrap().end_batches_of(&name);
+                    SESSION_MANAGER.lock().unwrap().close_sessions_of(&name);
+                    prompt_impl::forget_client(&name);
+                    prompter::unregister(&name);
+                    audit::forget_client(&name);
+                    quirks::forget_client(&name);
+                    kwallet_impl::forget_client(&name);
+                }
+            }
+            true
+        }),
+    );
+    Ok(())
+}
+
+fn owner_vanished(name: &str) {
+    let orphans: Vec<Uuid> = {
+        let mut owners = OWNERS.lock().unwrap();
+        let orphans = owners
+            .iter()
+            .filter(|(_, owner)| *owner == name)
+            .map(|(uuid, _)| *uuid)
+            .collect::<Vec<_>>();
+        orphans.iter().for_each(|uuid| {
+            owners.remove(uuid);
+        });
+        orphans
+    };
+    let mut storage = STORAGE.write().unwrap();
+    for uuid in orphans {
+        let lock_on_exit = storage
+            .with_collection(&uuid, |c| {
+                let policy = c.properties.get(LOCK_ON_OWNER_EXIT_PROPERTY);
+                Ok(policy.map(String::as_str) == Some("true"))
+            })
+            .unwrap_or(false);
+        if lock_on_exit {
+            debug!("Owner {} left, locking collection {}", name, uuid);
+            if let Err(e) = storage.lock_collection(&uuid) {
+                error!("Cannot lock collection {}: {}", uuid, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Plain sessions send the secrets over the bus as they are, where any process monitoring the bus
+//! may read them. Items holding high-value secrets may get flagged with the `tks:sensitive`
+//! attribute set to `true`: reading them through a plain session then asks the user to confirm
+//! first. The user may have the answer remembered, for the client executable and the item, in
+//! `plain_transfers.json` in the XDG state directory.
+//!
+//! GetSecret can't return a prompt, so the confirmation gets shown while the call waits; clients
+//! with a short call timeout may give up before the user answers.
+
+use crate::settings::{RunMode, Settings};
+use crate::storage::file_ops;
+use crate::tks_dbus::client_context::TksClientProcess;
+use crate::tks_dbus::prompter;
+use crate::tks_error::TksError;
+use lazy_static::lazy_static;
+use log::{debug, error};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// The item attribute flagging high-value secrets
+pub const SENSITIVE_ATTRIBUTE: &str = "tks:sensitive";
+
+const CHOICES_FILE: &str = "plain_transfers.json";
+
+/// The remembered answers: whether a client, by process executable, may read an item through a
+/// plain session
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PlainTransferChoices {
+    items: HashMap<Uuid, HashMap<String, bool>>,
+}
+
+impl PlainTransferChoices {
+    /// Reads the choices back; there are none until the file gets written
+    pub fn load(path: &Path) -> Result<PlainTransferChoices, TksError> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), TksError> {
+        Ok(file_ops::write(path, serde_json::to_vec_pretty(self)?)?)
+    }
+
+    pub fn get(&self, item: &Uuid, exe_path: &str) -> Option<bool> {
+        self.items.get(item)?.get(exe_path).copied()
+    }
+
+    /// The client executables allowed to read at least one item through plain sessions
+    pub fn allowed_clients(&self) -> BTreeSet<&str> {
+        self.items
+            .values()
+            .flat_map(|clients| clients.iter())
+            .filter(|(_, allowed)| **allowed)
+            .map(|(exe_path, _)| exe_path.as_str())
+            .collect()
+    }
+
+    pub fn remember(&mut self, item: Uuid, exe_path: &str, allowed: bool) {
+        self.items
+            .entry(item)
+            .or_default()
+            .insert(exe_path.to_string(), allowed);
+    }
+}
+
+lazy_static! {
+    /// Loaded upon the first read of a sensitive item through a plain session
+    static ref CHOICES: Mutex<Option<PlainTransferChoices>> = Mutex::new(None);
+}
+
+/// Whether the item attributes flag it sensitive
+pub fn is_sensitive(attributes: &HashMap<String, String>) -> bool {
+    attributes.get(SENSITIVE_ATTRIBUTE).map(String::as_str) == Some("true")
+}
+
+/// File holding the choices remembered by the user
+pub fn choices_path() -> Result<PathBuf, TksError> {
+    if RunMode::current() == RunMode::Test {
+        return Ok(Path::new(&RunMode::test_storage_path()).join(CHOICES_FILE));
+    }
+    Ok(xdg::BaseDirectories::with_prefix(Settings::XDG_DIR_NAME)?.place_state_file(CHOICES_FILE)?)
+}
+
+/// Lets the secret of a sensitive item go through a plain session to the client having the given
+/// unique bus name, asking the user unless they already answered for this client and item. Fails
+/// with PermissionDenied when the user refused.
+pub fn confirm(sender: &str, item: &Uuid, label: &str) -> Result<(), TksError> {
+    let process = TksClientProcess::from_bus_name(sender.to_string())?;
+    let exe_path = process.exe_path().to_string_lossy().into_owned();
+    let path = choices_path()?;
+    let mut choices = CHOICES.lock().unwrap();
+    if choices.is_none() {
+        *choices = Some(PlainTransferChoices::load(&path)?);
+    }
+    let choices = choices.as_mut().unwrap();
+    let allowed = match choices.get(item, &exe_path) {
+        Some(allowed) => {
+            debug!("{} may read {} through a plain session: {}", exe_path, item, allowed);
+            allowed
+        }
+        None => {
+            let prompter = prompter::current();
+            let allowed = prompter.confirm(
+                "Send",
+                "Don't send",
+                &format!(
+                    "{} asks for the secret of '{}' through an unencrypted session: other \
+                     applications may read it on its way. Send it anyway?",
+                    exe_path, label
+                ),
+            )?;
+            let remember = prompter.confirm(
+                "Remember",
+                "Ask again",
+                &format!(
+                    "Remember to {} the secret of '{}' to {} through unencrypted sessions?",
+                    if allowed { "send" } else { "refuse" },
+                    label,
+                    exe_path
+                ),
+            )?;
+            if remember {
+                choices.remember(*item, &exe_path, allowed);
+                if let Err(e) = choices.save(&path) {
+                    error!("Cannot save the plain session choices: {}", e);
+                }
+            }
+            allowed
+        }
+    };
+    match allowed {
+        true => Ok(()),
+        false => Err(TksError::PermissionDenied),
+    }
+}
@@ -0,0 +1,197 @@
+//! The backend of the Secret portal of xdg-desktop-portal. A sandboxed application asks the portal
+//! for its master secret, e.g. through libsecret, and keeps its own secrets encrypted with it
+//! inside its sandbox; the portal calls RetrieveSecret of its backend, which writes the secret to
+//! the file descriptor it got. The secret of each application is an item of the default
+//! collection, created with random bytes on the first call, so the same secret comes back as long
+//! as the item exists.
+//!
+//! Only xdg-desktop-portal may call the backend: any other caller, sandboxed or not, could ask for
+//! the secret of any application otherwise. The collection gets unlocked first if needed, the
+//! reply being sent once the user answered the dialog.
+
+use crate::audit;
+use crate::audit::{AuditClient, AuditEvent};
+use crate::storage::collection::ItemId;
+use crate::storage::STORAGE;
+use crate::tks_dbus::client_context::TksClientProcess;
+use crate::tks_dbus::collection_impl::CollectionImpl;
+use crate::tks_dbus::fdo::portal_secret::OrgFreedesktopImplPortalSecret;
+use crate::tks_dbus::item_impl::ItemImpl;
+use crate::tks_dbus::MESSAGE_SENDER;
+use crate::tks_error::TksError;
+use dbus::arg::{self, PropMap};
+use dbus::MethodErr;
+use dbus_crossroads::Context;
+use log::{debug, error, trace};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Object path of the portal backends, see tks.portal
+pub const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Executable file name of the only caller of the backend
+const PORTAL_EXECUTABLE: &str = "xdg-desktop-portal";
+
+/// Version of org.freedesktop.impl.portal.Secret
+const VERSION: u32 = 1;
+
+/// Attributes of the items holding the secrets, the same as gnome-keyring's
+const SCHEMA: &str = "org.freedesktop.impl.portal.Secret";
+const APP_ID_ATTRIBUTE: &str = "app_id";
+
+/// Random bytes of a new secret
+const SECRET_SIZE: usize = 64;
+
+/// Responses of the portal requests
+const RESPONSE_SUCCESS: u32 = 0;
+const RESPONSE_CANCELLED: u32 = 1;
+const RESPONSE_FAILED: u32 = 2;
+
+/// The attributes of the item holding the secret of the application
+fn attributes_of(app_id: &str) -> HashMap<String, String> {
+    HashMap::from([
+        ("xdg:schema".to_string(), SCHEMA.to_string()),
+        (APP_ID_ATTRIBUTE.to_string(), app_id.to_string()),
+    ])
+}
+
+/// Refuses the callers other than xdg-desktop-portal
+fn check_caller(ctx: &Context) -> Result<(), MethodErr> {
+    let sender = ctx
+        .message()
+        .sender()
+        .ok_or_else(|| MethodErr::failed("Unknown sender"))?
+        .to_string();
+    let process = TksClientProcess::from_bus_name(sender.clone())?;
+    let name = Path::new(process.exe_path()).file_name();
+    if process.sandbox().is_some() || name != Some(PORTAL_EXECUTABLE.as_ref()) {
+        debug!(
+            "Refusing the Secret portal to {} ({:?})",
+            sender,
+            process.exe_path()
+        );
+        return Err(TksError::PermissionDenied.into());
+    }
+    Ok(())
+}
+
+/// The secret of the application, created unless it has one already
+fn secret_of(uuid: &Uuid, app_id: &str) -> Result<Vec<u8>, TksError> {
+    let client = AuditClient {
+        application: Some(app_id.to_string()),
+        ..AuditClient::default()
+    };
+    let attributes = attributes_of(app_id);
+    let existing = STORAGE.read().unwrap().with_collection(uuid, |c| {
+        Ok(c.items
+            .iter()
+            .find(|i| i.attributes == attributes)
+            .map(|i| (i.id.clone(), i.data.as_ref().map(|d| d.secret().to_vec()))))
+    })?;
+    if let Some((item_id, secret)) = existing {
+        let result = secret.ok_or(TksError::PermissionDenied);
+        audit::record_for(
+            client,
+            AuditEvent::SecretRead,
+            (&result).into(),
+            vec![item_id.uuid],
+        );
+        return result;
+    }
+
+    let mut secret = vec![0u8; SECRET_SIZE];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let label = format!("Application key for {}", app_id);
+    let result: Result<ItemId, TksError> = STORAGE.write().unwrap().modify_collection(uuid, |c| {
+        c.add_item(
+            &label,
+            attributes,
+            secret.clone(),
+            "application/octet-stream".into(),
+        )
+    });
+    let uuids = result.as_ref().map_or(vec![*uuid], |id| vec![id.uuid]);
+    audit::record_for(client, AuditEvent::ItemCreate, (&result).into(), uuids);
+    ItemImpl::register(&result?);
+    CollectionImpl::emit_properties_changed(*uuid, &["Items"]);
+    CollectionImpl::emit_sequence_changed(*uuid);
+    Ok(secret)
+}
+
+/// Writes the secret of the application to the file descriptor the portal sent
+fn send_secret(uuid: &Uuid, app_id: &str, fd: arg::OwnedFd) -> (u32, PropMap) {
+    // SAFETY: the descriptor came along with the call, and gets closed along with the file
+    let mut file = unsafe { File::from_raw_fd(fd.into_fd()) };
+    let sent = secret_of(uuid, app_id).and_then(|secret| Ok(file.write_all(&secret)?));
+    match sent {
+        Ok(()) => (RESPONSE_SUCCESS, PropMap::new()),
+        Err(e) => {
+            error!("Cannot send the secret of {}: {}", app_id, e);
+            (RESPONSE_FAILED, PropMap::new())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SecretPortal {}
+
+impl OrgFreedesktopImplPortalSecret for SecretPortal {
+    fn retrieve_secret(
+        &mut self,
+        ctx: &mut Context,
+        handle: dbus::Path<'static>,
+        app_id: String,
+        fd: arg::OwnedFd,
+        _options: PropMap,
+    ) -> Result<Option<(u32, PropMap)>, MethodErr> {
+        trace!("retrieve_secret {} for '{}'", handle, app_id);
+        check_caller(ctx)?;
+        // the applications out of a sandbox have no ID, they use the Secret Service directly
+        if app_id.is_empty() {
+            return Err(MethodErr::invalid_arg("app_id"));
+        }
+        let uuid = {
+            let storage = STORAGE.read().unwrap();
+            Uuid::parse_str(&storage.read_alias("default")?)
+                .map_err(|_| TksError::NotFound(Some("No default collection".to_string())))?
+        };
+        let locked = STORAGE
+            .read()
+            .unwrap()
+            .with_collection(&uuid, |c| Ok(c.locked))?;
+        if !locked {
+            return Ok(Some(send_secret(&uuid, &app_id, fd)));
+        }
+
+        let action = STORAGE.write().unwrap().create_unlock_action(&uuid)?;
+        let reply = ctx.message().method_return();
+        // the dialog may take a while, the other clients keep being served meanwhile
+        tokio::task::spawn_blocking(move || {
+            let (response, results) = match action.perform() {
+                Ok(false) => send_secret(&uuid, &app_id, fd),
+                Ok(true) => {
+                    debug!("Unlocking for the secret of {} got dismissed", app_id);
+                    (RESPONSE_CANCELLED, PropMap::new())
+                }
+                Err(e) => {
+                    error!("Cannot unlock for the secret of {}: {}", app_id, e);
+                    (RESPONSE_FAILED, PropMap::new())
+                }
+            };
+            MESSAGE_SENDER
+                .lock()
+                .unwrap()
+                .send_message(reply.append2(response, results));
+        });
+        Ok(None)
+    }
+
+    fn version(&self) -> Result<u32, MethodErr> {
+        Ok(VERSION)
+    }
+}
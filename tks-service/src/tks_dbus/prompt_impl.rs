@@ -1,10 +1,19 @@
-use crate::register_object;
+use crate::audit;
+use crate::settings::SETTINGS;
+use crate::storage::collection::ItemId;
+use crate::tks_dbus::client_context::{TksClient, CLIENT_REGISTRY};
+use crate::tks_dbus::collection_impl::CollectionImpl;
 use crate::tks_dbus::fdo::prompt::register_org_freedesktop_secret_prompt;
 use crate::tks_dbus::fdo::prompt::OrgFreedesktopSecretPrompt;
 use crate::tks_dbus::fdo::prompt::OrgFreedesktopSecretPromptCompleted;
+use crate::tks_dbus::prompter;
+use crate::tks_dbus::tks::prompt::{register_io_linux_tks_prompt1, IoLinuxTksPrompt1};
+use crate::tks_dbus::tks::prompt::IoLinuxTksPrompt1PromptProgress;
+use crate::tks_dbus::prompter::PassphraseRequest;
 use crate::tks_dbus::DBusHandlePath::SinglePath;
 use crate::tks_dbus::CROSSROADS;
 use crate::tks_dbus::MESSAGE_SENDER;
+use crate::tks_dbus::object_manager;
 use crate::tks_dbus::{DBusHandle, DBusHandlePath};
 use crate::tks_error::TksError;
 use dbus;
@@ -16,11 +25,12 @@ use parking_lot::ReentrantMutex;
 use pinentry::{ConfirmationDialog, MessageDialog};
 use secrecy::SecretString;
 use std::cell::RefCell;
-use std::collections::{BTreeMap as Map, VecDeque};
-use std::ffi::OsString;
+use std::collections::{BTreeMap as Map, HashMap, VecDeque};
 use std::ops::Deref;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct PromptHandle {
@@ -30,14 +40,126 @@ pub struct PromptHandle {
 pub trait TksPrompt {
     fn prompt(&self, _window_id: String) -> Result<(bool, Option<PromptChainPaths>), TksError>;
     fn dismiss(&self) -> Result<(), TksError>;
+    /// The prompts run by this one, which get unregistered together with it
+    fn chained_prompts(&self) -> PromptChainPaths {
+        PromptChainPaths::new()
+    }
+    /// What the prompt resulted in, e.g. the collection of CreateCollection; the Completed
+    /// signal reports it unless the prompt got dismissed
+    fn result(&self) -> PromptResult {
+        PromptResult::Empty
+    }
+    fn set_result(&mut self, _result: PromptResult) {}
+}
+
+/// The result of the Completed signal, which depends on the method which returned the prompt
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum PromptResult {
+    /// An empty string, for the dismissed prompts and the ones having no result
+    #[default]
+    Empty,
+    /// The collection of CreateCollection
+    Path(dbus::Path<'static>),
+    /// The objects Unlock got asked for, all of them unlocked once the prompt completed
+    Paths(Vec<dbus::Path<'static>>),
+}
+
+impl PromptResult {
+    pub fn into_variant(self) -> arg::Variant<Box<dyn arg::RefArg>> {
+        match self {
+            PromptResult::Empty => arg::Variant(Box::new(String::new())),
+            PromptResult::Path(path) => arg::Variant(Box::new(path)),
+            PromptResult::Paths(paths) => arg::Variant(Box::new(paths)),
+        }
+    }
+}
+
+/// A DBus-registered prompt, from its creation until it completes
+pub enum RegisteredPrompt {
+    /// Yet to be invoked by the client application
+    Pending(Box<dyn TksPrompt + Send>),
+    /// Invoked, its dialogs being shown by a blocking task; dismissing it cancels them
+    Running(prompter::Cancellation),
 }
 
 lazy_static! {
-    // This is the list of the DBus-registered prompts, that are yet to be invoked
-    // by the client applications
-    pub static ref PROMPTS: Arc<ReentrantMutex<RefCell<Map<usize, Box<dyn TksPrompt + Send>>>>> =
+    // This is the list of the DBus-registered prompts, from their creation until they complete
+    pub static ref PROMPTS: Arc<ReentrantMutex<RefCell<Map<usize, RegisteredPrompt>>>> =
         Arc::new(ReentrantMutex::new(RefCell::new(Map::new())));
     pub static ref PROMPT_COUNTER: Arc<Mutex<usize>> = Arc::new(Mutex::new(0));
+    /// Unique bus names of the clients which got the prompts, by prompt id
+    static ref REQUESTERS: Mutex<HashMap<usize, String>> = Mutex::new(HashMap::new());
+}
+
+/// Takes a pending prompt out of the registry, e.g. to dismiss it; the running ones stay
+fn take_prompt(prompt_id: usize) -> Option<Box<dyn TksPrompt + Send>> {
+    let prompts = PROMPTS.lock();
+    let mut prompts = prompts.deref().borrow_mut();
+    if !matches!(prompts.get(&prompt_id), Some(RegisteredPrompt::Pending(_))) {
+        return None;
+    }
+    REQUESTERS.lock().unwrap().remove(&prompt_id);
+    match prompts.remove(&prompt_id) {
+        Some(RegisteredPrompt::Pending(prompt)) => Some(prompt),
+        _ => None,
+    }
+}
+
+/// Takes a pending prompt out to invoke it, leaving it registered as running until it completes,
+/// so that dismissing it cancels its dialogs
+fn start_prompt(
+    prompt_id: usize,
+    cancellation: prompter::Cancellation,
+) -> Option<Box<dyn TksPrompt + Send>> {
+    let prompts = PROMPTS.lock();
+    let prompt = take_prompt(prompt_id)?;
+    prompts
+        .deref()
+        .borrow_mut()
+        .insert(prompt_id, RegisteredPrompt::Running(cancellation));
+    Some(prompt)
+}
+
+/// Sets what the Completed signal of a pending prompt reports, once the prompt returned by a
+/// method is known, e.g. the one of a chain or the single prompt Unlock needed
+pub fn set_result(prompt_path: &dbus::Path, result: PromptResult) -> Result<(), TksError> {
+    let prompt_id: usize = prompt_path
+        .rsplit('/')
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or(TksError::ParameterError)?;
+    let prompts = PROMPTS.lock();
+    let mut prompts = prompts.deref().borrow_mut();
+    let Some(RegisteredPrompt::Pending(prompt)) = prompts.get_mut(&prompt_id) else {
+        return Err(TksError::NotFound(Some(format!(
+            "Prompt not registered: {}",
+            prompt_path
+        ))));
+    };
+    prompt.set_result(result);
+    Ok(())
+}
+
+/// Dismisses the prompts a client which left the bus never invoked
+pub fn forget_client(bus_name: &str) {
+    let orphans: Vec<usize> = REQUESTERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, requester)| *requester == bus_name)
+        .map(|(prompt_id, _)| *prompt_id)
+        .collect();
+    if orphans.is_empty() {
+        return;
+    }
+    let bus_name = bus_name.to_string();
+    tokio::spawn(async move {
+        for prompt_id in orphans {
+            if PromptHandle::discard(prompt_id) {
+                debug!("{} left, dismissed its prompt {}", bus_name, prompt_id);
+            }
+        }
+    });
 }
 
 pub enum DialogResult {
@@ -58,8 +180,21 @@ macro_rules! register_prompt {
             .lock()
             .deref()
             .borrow_mut()
-            .insert($prompt.prompt_id, Box::new($prompt.clone()));
-        register_object!(register_org_freedesktop_secret_prompt, handle);
+            .insert(
+                $prompt.prompt_id,
+                RegisteredPrompt::Pending(Box::new($prompt.clone())),
+            );
+        if let Some(requester) = audit::caller_bus_name() {
+            REQUESTERS.lock().unwrap().insert($prompt.prompt_id, requester);
+        }
+        object_manager::register(
+            &[
+                register_org_freedesktop_secret_prompt,
+                register_io_linux_tks_prompt1,
+            ],
+            handle,
+        );
+        PromptHandle::schedule_expiry($prompt.prompt_id);
         path
     }};
 }
@@ -74,7 +209,53 @@ macro_rules! next_prompt_id {
 
 #[derive(Clone, Debug)]
 pub enum ConfirmationMessageActionParam {
-    ConfirmNewClient(OsString)
+    ConfirmNewClient(TksClient),
+    /// CreateCollection got a name already taken: reuse that collection, with the given alias
+    /// and properties
+    ReuseCollection(Uuid, String, HashMap<String, String>),
+    TestPrompt,
+}
+
+impl ConfirmationMessageActionParam {
+    /// Called once the user answered no
+    fn denied(&self) {
+        if let ConfirmationMessageActionParam::ConfirmNewClient(client) = self {
+            CLIENT_REGISTRY.lock().unwrap().deny(&client.exe_path);
+        }
+    }
+
+    /// Whether the confirmation got obsolete while waiting its turn, e.g. the client was known
+    /// all along, but the known clients could only be read once the storage got unlocked
+    fn already_confirmed(&self) -> bool {
+        match self {
+            ConfirmationMessageActionParam::ConfirmNewClient(client) => {
+                CLIENT_REGISTRY.lock().unwrap().is_known(client)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PassphraseActionParam {
+    /// Unlocks the collections sharing the backend of the given one
+    UnlockAllCollections(Uuid),
+    /// Same as above, but an empty password means using the key file of the named collection
+    UnlockAllCollectionsOrKeyFile(Uuid, String),
+    UnlockItem(ItemId),
+    TestPrompt,
+}
+
+impl PassphraseActionParam {
+    /// The collection the passphrase unlocks, and so the backend it applies to
+    pub fn collection_uuid(&self) -> Option<Uuid> {
+        match self {
+            PassphraseActionParam::UnlockAllCollections(uuid)
+            | PassphraseActionParam::UnlockAllCollectionsOrKeyFile(uuid, _) => Some(*uuid),
+            PassphraseActionParam::UnlockItem(item_id) => Some(item_id.collection_uuid),
+            PassphraseActionParam::TestPrompt => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -85,7 +266,8 @@ pub enum PromptDialog {
         String,                                     // prompt
         Option<String>,                             // confirmation
         Option<String>,                             // mismatch message
-        fn(SecretString) -> Result<bool, TksError>, // action if user confirms dialog
+        PassphraseActionParam,
+        fn(SecretString, &PassphraseActionParam) -> Result<bool, TksError>, // action if user confirms dialog
     ),
     ConfirmationMessage(
         // ConfirmationDialog::with_ok(1).with_cancel(2).confirm(3)
@@ -107,44 +289,96 @@ impl PromptAction {
         Ok(())
     }
 
+    /// Dummy dialogs used by `tks-cli service test-prompt` to check the pinentry setup
+    pub(crate) fn test_prompt(kind: &str) -> Result<PromptAction, TksError> {
+        let dialog = match kind {
+            "message" => PromptDialog::PromptMessage(
+                "OK".into(),
+                "This is a test message from tks-service".into(),
+            ),
+            "confirmation" => PromptDialog::ConfirmationMessage(
+                "Yes".into(),
+                "No".into(),
+                "This is a test confirmation from tks-service. Do you see it?".into(),
+                ConfirmationMessageActionParam::TestPrompt,
+                |_| Ok(false),
+            ),
+            "passphrase" => PromptDialog::PassphraseInput(
+                "This is a test passphrase input from tks-service. \
+                The passphrase is not used for anything."
+                    .into(),
+                "Passphrase".into(),
+                None,
+                None,
+                PassphraseActionParam::TestPrompt,
+                |_, _| Ok(false),
+            ),
+            _ => {
+                error!("Unknown test prompt kind '{}'", kind);
+                return Err(TksError::ParameterError);
+            }
+        };
+        Ok(PromptAction { dialog })
+    }
+
+    fn result(&self) -> PromptResult {
+        match &self.dialog {
+            PromptDialog::ConfirmationMessage(
+                _,
+                _,
+                _,
+                ConfirmationMessageActionParam::ReuseCollection(uuid, _, _),
+                _,
+            ) => PromptResult::Path(CollectionImpl::from(uuid).canonical_path()),
+            _ => PromptResult::Empty,
+        }
+    }
+
     // returns true if the dialog has been dismissed, false otherwise
     pub fn perform(&self) -> Result<bool, TksError> {
+        let prompter = prompter::current();
         match &self.dialog {
             PromptDialog::PromptMessage(ok, msg) => {
-                if let Some(mut d) = MessageDialog::with_default_binary() {
-                    d.with_ok(ok).show_message(msg).unwrap();
-                    Ok(false)
-                } else {
-                    Err(TksError::NoPinentryBinaryFound)
-                }
+                prompter.show_message(ok, msg)?;
+                Ok(false)
             }
-            PromptDialog::PassphraseInput(desc, prompt, confirmation, mismatch, action) => {
-                if let Some(mut d) = pinentry::PassphraseInput::with_default_binary() {
-                    d.required("Password is required".into())
-                        .with_prompt(prompt.as_str())
-                        .with_description(desc.as_str());
-                    let mis: String;
-                    if let Some(conf) = confirmation {
-                        mis = mismatch.clone().unwrap();
-                        d.with_confirmation(conf.as_str(), mis.as_str());
+            PromptDialog::PassphraseInput(desc, prompt, confirmation, mismatch, action_param, action) => {
+                let request = PassphraseRequest {
+                    description: desc,
+                    prompt,
+                    confirmation: confirmation.as_deref().zip(mismatch.as_deref()),
+                    required: !matches!(
+                        action_param,
+                        PassphraseActionParam::UnlockAllCollectionsOrKeyFile(..)
+                    ),
+                };
+                match prompter.ask_passphrase(&request)? {
+                    // the prompt may have been dismissed while the dialog was shown
+                    Some(s) if !prompter::cancelled() => action(s, action_param),
+                    _ => {
+                        trace!("User dismissed passphrase input '{}'", prompt);
+                        Ok(true)
                     }
-                    let s = d.interact()?;
-                    action(s)
-                } else {
-                    Err(TksError::NoPinentryBinaryFound)
                 }
             }
             PromptDialog::ConfirmationMessage(yes, no, confirmation, action_param, action) => {
-                if let Some(mut input) = ConfirmationDialog::with_default_binary() {
-                    let dismissed = !input.with_ok(yes).with_cancel(no).confirm(confirmation)?;
-                    if dismissed {
-                        trace!("User dismissed confirmation '{}", confirmation);
-                        Ok(dismissed)
-                    } else {
-                        Ok(action(action_param)?)
-                    }
+                if action_param.already_confirmed() {
+                    trace!("Confirmation no longer needed '{}'", confirmation);
+                    return Ok(false);
+                }
+                let confirmed = prompter.confirm(yes, no, confirmation)?;
+                if prompter::cancelled() {
+                    // the client dismissed the prompt, the user didn't answer
+                    trace!("Confirmation cancelled '{}'", confirmation);
+                    return Ok(true);
+                }
+                let dismissed = !confirmed;
+                if dismissed {
+                    trace!("User dismissed confirmation '{}", confirmation);
+                    action_param.denied();
+                    Ok(dismissed)
                 } else {
-                    Err(TksError::NoPinentryBinaryFound)
+                    Ok(action(action_param)?)
                 }
             }
         }
@@ -155,15 +389,16 @@ impl PromptAction {
 pub struct PromptWithPinentry {
     prompt_id: usize,
     action: PromptAction,
+    result: PromptResult,
 }
 
 impl PromptWithPinentry {
     pub fn new(action: PromptAction) -> Result<dbus::Path<'static>, TksError> {
         let prompt = PromptWithPinentry {
             prompt_id: next_prompt_id!(),
+            result: action.result(),
             action: action.clone(),
         };
-        // TODO users might forget to use prompts, so attach a timer on each and self destruct after several minutes
         Ok(register_prompt!(prompt).into())
     }
 }
@@ -177,6 +412,14 @@ impl TksPrompt for PromptWithPinentry {
     fn dismiss(&self) -> Result<(), TksError> {
         self.action.dismiss()
     }
+
+    fn result(&self) -> PromptResult {
+        self.result.clone()
+    }
+
+    fn set_result(&mut self, result: PromptResult) {
+        self.result = result;
+    }
 }
 
 #[cfg(feature = "fscrypt")]
@@ -252,15 +495,75 @@ impl From<DBusHandlePath> for Result<dbus::Path<'_>, TksError> {
         Ok(Path::from(value))
     }
 }
-impl OrgFreedesktopSecretPrompt for PromptHandle {
-    fn prompt(&mut self, window_id: String) -> Result<(), dbus::MethodErr> {
-        trace!("prompt {}", window_id);
+impl PromptHandle {
+    /// Clients may never invoke nor dismiss the prompts they got, so these expire after
+    /// `prompt.timeout` seconds
+    fn schedule_expiry(prompt_id: usize) {
+        let timeout = SETTINGS.lock().unwrap().prompt.timeout;
+        if timeout == 0 {
+            return;
+        }
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(timeout)).await;
+            PromptHandle::expire(prompt_id);
+        });
+    }
+
+    /// Dismisses and unregisters a prompt the client didn't invoke in time
+    fn expire(prompt_id: usize) {
+        if PromptHandle::discard(prompt_id) {
+            debug!("Prompt {} expired", prompt_id);
+        }
+    }
 
-        let dismissed: bool = false; // errors effectively dismiss us
+    /// Dismisses and unregisters a prompt which wasn't invoked; the prompts of a chain go
+    /// together with it. Returns whether the prompt was still pending.
+    fn discard(prompt_id: usize) -> bool {
+        let path: dbus::Path<'static> = PromptHandle { prompt_id }.path().into();
+        let prompt = {
+            let prompts = PROMPTS.lock();
+            let chained = |p: &RegisteredPrompt| match p {
+                RegisteredPrompt::Pending(p) => p.chained_prompts().contains(&path),
+                RegisteredPrompt::Running(_) => false,
+            };
+            if prompts.deref().borrow().values().any(chained) {
+                return false;
+            }
+            // the running prompts get dismissed by their client only
+            let Some(prompt) = take_prompt(prompt_id) else {
+                return false;
+            };
+            prompt
+        };
+        if let Err(e) = prompt.dismiss() {
+            error!("Cannot dismiss prompt {}: {}", prompt_id, e);
+        }
+        MESSAGE_SENDER.lock().unwrap().send_message(
+            OrgFreedesktopSecretPromptCompleted {
+                dismissed: true,
+                result: PromptResult::Empty.into_variant(),
+            }
+            .to_emit_message(&path),
+        );
+        let mut crossroads = CROSSROADS.lock().unwrap();
+        object_manager::remove::<PromptHandle>(&mut crossroads, &path);
+        for chained in prompt.chained_prompts() {
+            object_manager::remove::<PromptHandle>(&mut crossroads, &chained);
+        }
+        true
+    }
+
+    fn run_prompt(
+        prompt: Box<dyn TksPrompt + Send>,
+        prompt_id: usize,
+        prompt_path: DBusHandlePath,
+        window_id: String,
+    ) {
+        let dismissed: bool = true; // errors effectively dismiss us
         let chain_paths: Option<PromptChainPaths> = None;
-        let prompt_path = self.path().clone();
-        let prompt_id = self.prompt_id;
-        let mut guard = scopeguard::guard((dismissed, chain_paths), |(dismissed, chain_paths)| {
+        let state = (dismissed, chain_paths, PromptResult::Empty);
+        let mut guard = scopeguard::guard(state, |(dismissed, chain_paths, result)| {
+            PROMPTS.lock().deref().borrow_mut().remove(&prompt_id);
             // ensure we unregister the prompt once interaction has been done, but also in any case of error
             tokio::spawn(async move {
                 trace!("sending prompt completed signal, dismissed = {}", dismissed);
@@ -268,44 +571,72 @@ impl OrgFreedesktopSecretPrompt for PromptHandle {
                 MESSAGE_SENDER.lock().unwrap().send_message(
                     OrgFreedesktopSecretPromptCompleted {
                         dismissed,
-                        result: arg::Variant(Box::new((dismissed, "".to_string()))),
+                        result: result.into_variant(),
                     }
                     .to_emit_message(&prompt_path.into()),
                 );
-                PROMPTS.lock().deref().borrow_mut().remove(&prompt_id);
-                tokio::spawn(async move {
-                    trace!("unregistering prompt {}", prompt_id);
-                    CROSSROADS
-                        .lock()
-                        .unwrap()
-                        .remove::<PromptHandle>(&prompt_path2);
-                });
-                if let Some(paths) = chain_paths {
-                    for path in paths {
-                        tokio::spawn(async move {
-                            trace!("unregistering prompt {}", prompt_id);
-                            CROSSROADS.lock().unwrap().remove::<PromptHandle>(&path);
-                        });
-                    }
-                }
+                trace!("unregistering prompt {}", prompt_id);
+                let mut paths = vec![prompt_path2];
+                paths.extend(chain_paths.into_iter().flatten());
+                object_manager::unregister::<PromptHandle>(paths);
             });
         });
 
-        if let Some(prompt) = PROMPTS.lock().deref().borrow().get(&self.prompt_id) {
-            *guard = prompt.prompt(window_id)?;
-        } else {
+        match prompt.prompt(window_id) {
+            Ok((dismissed, chain_paths)) => {
+                let result = match dismissed {
+                    true => PromptResult::Empty,
+                    false => prompt.result(),
+                };
+                *guard = (dismissed, chain_paths, result)
+            }
+            Err(e) => error!("prompt {} failed: {}", prompt_id, e),
+        }
+    }
+}
+impl IoLinuxTksPrompt1 for PromptHandle {}
+
+impl OrgFreedesktopSecretPrompt for PromptHandle {
+    fn prompt(&mut self, window_id: String) -> Result<(), dbus::MethodErr> {
+        trace!("prompt {}", window_id);
+
+        let cancellation = prompter::Cancellation::default();
+        let Some(prompt) = start_prompt(self.prompt_id, cancellation.clone()) else {
             error!("prompt not found");
             return Err(dbus::MethodErr::failed(
                 "could not create confirmation dialog",
             ));
         };
-
+        let prompt_path = self.path().clone();
+        let prompt_id = self.prompt_id;
+        // the outcome gets reported by the Completed signal, so there's no need to keep the DBus
+        // dispatch, and with it all the other clients, waiting while the user interacts with
+        // the pinentry dialogs
+        let client = prompter::client();
+        tokio::task::spawn_blocking(move || {
+            prompter::with_client(client, || {
+                prompter::with_cancellation(cancellation, || {
+                    PromptHandle::run_prompt(prompt, prompt_id, prompt_path, window_id);
+                });
+            });
+        });
         Ok(())
     }
     fn dismiss(&mut self) -> Result<(), dbus::MethodErr> {
         trace!("dismiss {}", self.prompt_id);
-        if let Some(prompt) = PROMPTS.lock().deref().borrow().get(&self.prompt_id) {
+        // removed, so that it doesn't expire later on
+        let prompt = take_prompt(self.prompt_id);
+        let running = || match PROMPTS.lock().deref().borrow().get(&self.prompt_id) {
+            Some(RegisteredPrompt::Running(cancellation)) => Some(cancellation.clone()),
+            _ => None,
+        };
+        if let Some(prompt) = prompt {
             prompt.dismiss()?
+        } else if let Some(cancellation) = running() {
+            // the prompt completes as dismissed once its dialog got closed
+            debug!("Cancelling the dialogs of prompt {}", self.prompt_id);
+            cancellation.cancel();
+            return Ok(());
         } else {
             error!("prompt not found");
             return Err(dbus::MethodErr::failed("could not dismiss unknown prompt"));
@@ -319,15 +650,15 @@ impl OrgFreedesktopSecretPrompt for PromptHandle {
             MESSAGE_SENDER.lock().unwrap().send_message(
                 OrgFreedesktopSecretPromptCompleted {
                     dismissed: true,
-                    result: arg::Variant(Box::new((false, "".to_string()))),
+                    result: PromptResult::Empty.into_variant(),
                 }
                 .to_emit_message(&prompt_path.into()),
             );
             trace!("unregistering prompt {}", prompt_id);
-            CROSSROADS
-                .lock()
-                .unwrap()
-                .remove::<PromptHandle>(&prompt_path2);
+            object_manager::remove::<PromptHandle>(
+                &mut CROSSROADS.lock().unwrap(),
+                &prompt_path2,
+            );
         });
         Ok(())
     }
@@ -355,6 +686,7 @@ type PromptChainPaths = VecDeque<dbus::Path<'static>>;
 pub struct TksPromptChain {
     prompts: PromptChainPaths,
     prompt_id: usize,
+    result: PromptResult,
 }
 
 impl TksPromptChain {
@@ -362,6 +694,7 @@ impl TksPromptChain {
         let prompt = TksPromptChain {
             prompts,
             prompt_id: next_prompt_id!(),
+            result: PromptResult::Empty,
         };
         register_prompt!(prompt).into()
     }
@@ -373,13 +706,17 @@ impl TksPromptChain {
     ) -> Result<(bool, Option<PromptChainPaths>), TksError> {
         let mut dismissed = dismissed;
         assert!(dismissed || window_id.is_some());
-        for prompt_path in &self.prompts {
+        let chain_path: dbus::Path<'static> = self.path().into();
+        let total = self.prompts.len() as u32;
+        for (current, prompt_path) in self.prompts.iter().enumerate() {
             let mut parts = prompt_path.split('/');
             match parts.clone().count() {
                 6 => {
                     let ids = parts.nth(5).unwrap();
                     let id: usize = ids.parse().unwrap();
-                    dismissed |= PROMPTS.lock().deref().borrow().get(&id).map_or_else(
+                    // take the prompt out, so the registry isn't kept locked during the dialog
+                    let prompt = take_prompt(id);
+                    dismissed |= prompt.map_or_else(
                         || {
                             Err(TksError::NotFound(Some(format!(
                                 "Prompt not registered: {}",
@@ -391,6 +728,14 @@ impl TksPromptChain {
                                 p.dismiss()?;
                                 Ok(dismissed)
                             } else {
+                                trace!("Chained prompt {} of {}", current + 1, total);
+                                MESSAGE_SENDER.lock().unwrap().send_message(
+                                    IoLinuxTksPrompt1PromptProgress {
+                                        current: current as u32 + 1,
+                                        total,
+                                    }
+                                    .to_emit_message(&chain_path),
+                                );
                                 let (dismissed, _) = p.prompt(window_id.clone().unwrap())?;
                                 Ok(dismissed)
                             }
@@ -425,4 +770,16 @@ impl TksPrompt for TksPromptChain {
         debug!("dismiss the prompt chain");
         self.invoke_prompts(None, true).map(|_| {})
     }
+
+    fn chained_prompts(&self) -> PromptChainPaths {
+        self.prompts.clone()
+    }
+
+    fn result(&self) -> PromptResult {
+        self.result.clone()
+    }
+
+    fn set_result(&mut self, result: PromptResult) {
+        self.result = result;
+    }
 }
@@ -0,0 +1,703 @@
+//! The dialogs shown by the prompts go through a prompter. Desktop environments may provide native
+//! dialogs by implementing the `io.linux_tks.Prompter1` interface, see
+//! `tks/io.linux_tks.Prompter1.xml`, and registering their object with
+//! `io.linux_tks.Service1.RegisterPrompter`; as the prompter gets the passwords typed, only an
+//! enrolled client of the user may register one, while no other client has one. Otherwise, or
+//! when `prompt.backend` is `pinentry`, the dialogs are shown with pinentry.
+//!
+//! On the system bus there is nobody to show dialogs to: PolicyKit decides instead, the
+//! `io.linux-tks.policy` actions being checked for the client the dialog would be shown for, see
+//! [with_client]. The passwords can't be asked that way, so the collections get unlocked with
+//! their key files.
+//!
+//! Without any display, e.g. on a server the admins reach over SSH, nobody would see pinentry:
+//! with `prompt.backend` `remote` the passphrase dialogs wait for `tks-cli service unlock` to
+//! answer them, see [RemotePrompter]. Anybody running as the user may answer them that way, so it
+//! only gets used when configured, never guessed from the environment.
+//!
+//! The pinentry program is the one of `prompt.pinentry.program`, or the flavor suiting the desktop
+//! environment, see [pinentry_program].
+//!
+//! The client may dismiss a prompt while its dialog is shown: the pinentry dialogs then get
+//! closed and the remote ones cancelled, see [Cancellation]. The dialogs of a registered
+//! prompter stay until answered, but the answer gets ignored.
+//!
+//! The tests answer the dialogs with the [ScriptedPrompter], so that the prompts get exercised
+//! without anybody to type the passwords.
+
+use crate::settings::{Pinentry, PromptBackend, RunMode, SETTINGS};
+use crate::tks_dbus;
+use crate::tks_error::TksError;
+use dbus::arg::{PropMap, Variant};
+use lazy_static::lazy_static;
+use log::{debug, info, trace, warn};
+use pinentry::{ConfirmationDialog, MessageDialog};
+use secrecy::{ExposeSecret, SecretString};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+const PROMPTER_INTERFACE: &str = "io.linux_tks.Prompter1";
+
+const POLKIT_NAME: &str = "org.freedesktop.PolicyKit1";
+const POLKIT_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+const POLKIT_AUTHORITY: &str = "org.freedesktop.PolicyKit1.Authority";
+/// Lets the authentication agent of the user, if any, ask for their password
+const POLKIT_ALLOW_USER_INTERACTION: u32 = 1;
+/// Letting a client in, or using the secrets some way it asked for
+pub const CONFIRM_ACTION: &str = "io.linux-tks.confirm";
+/// Unlocking the collections with their key files
+pub const UNLOCK_ACTION: &str = "io.linux-tks.unlock";
+
+/// Leaves the user enough time to answer
+const PROMPTER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The pinentry flavors suiting the desktop environments, by their XDG_CURRENT_DESKTOP names
+const PINENTRY_FLAVORS: &[(&str, &[&str])] = &[
+    ("KDE", &["pinentry-qt", "pinentry-qt5"]),
+    ("LXQt", &["pinentry-qt", "pinentry-qt5"]),
+    ("GNOME", &["pinentry-gnome3"]),
+    ("Unity", &["pinentry-gnome3"]),
+    ("Budgie", &["pinentry-gnome3"]),
+    ("Pantheon", &["pinentry-gnome3"]),
+    ("Cinnamon", &["pinentry-gnome3", "pinentry-gtk-2"]),
+    ("MATE", &["pinentry-gnome3", "pinentry-gtk-2"]),
+    ("XFCE", &["pinentry-gtk-2", "pinentry-gnome3"]),
+];
+
+/// The flavor-neutral program, which most distributions point to one of the flavors
+const PINENTRY_DEFAULT: &str = "pinentry";
+
+pub struct PassphraseRequest<'a> {
+    pub description: &'a str,
+    pub prompt: &'a str,
+    /// Asks the passphrase twice, with the confirmation prompt and the mismatch error message
+    pub confirmation: Option<(&'a str, &'a str)>,
+    /// Whether the passphrase may be left empty
+    pub required: bool,
+}
+
+pub trait Prompter {
+    fn show_message(&self, ok: &str, message: &str) -> Result<(), TksError>;
+    /// Returns `None` when the user cancelled the dialog
+    fn ask_passphrase(&self, request: &PassphraseRequest)
+        -> Result<Option<SecretString>, TksError>;
+    /// Returns whether the user confirmed
+    fn confirm(&self, ok: &str, cancel: &str, message: &str) -> Result<bool, TksError>;
+}
+
+/// A passphrase dialog waiting for its answer, see [RemotePrompter]
+struct PendingPassphrase {
+    id: u32,
+    description: String,
+    prompt: String,
+    confirmation: bool,
+    required: bool,
+    /// The thread waiting for the answer, see [Cancellation]
+    thread: libc::pid_t,
+    answer: mpsc::Sender<Option<SecretString>>,
+}
+
+lazy_static! {
+    /// The unique bus name and the object path of the registered prompter
+    static ref PROMPTER: Mutex<Option<(String, dbus::Path<'static>)>> = Mutex::new(None);
+    static ref PENDING_PASSPHRASES: Mutex<Vec<PendingPassphrase>> = Mutex::new(Vec::new());
+    /// The answers the [ScriptedPrompter] gives next
+    static ref SCRIPT: Mutex<VecDeque<ScriptedAnswer>> = Mutex::new(initial_script());
+}
+
+static NEXT_PASSPHRASE_ID: AtomicU32 = AtomicU32::new(1);
+
+thread_local! {
+    /// The unique bus name of the client the dialogs of this thread get shown for
+    static CLIENT: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Tells whether the prompt showing the dialogs of this thread got dismissed
+    static CANCELLATION: RefCell<Option<Cancellation>> = const { RefCell::new(None) };
+}
+
+/// Lets the dialogs of a prompt, shown by the thread running it, get cancelled from another one,
+/// e.g. when the client dismisses the prompt while the user still sees its dialog
+#[derive(Clone, Default)]
+pub struct Cancellation(Arc<CancellationState>);
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    /// The thread showing the dialogs, while it does, see [with_cancellation]
+    thread: Mutex<Option<libc::pid_t>>,
+}
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Closes the dialog being shown, which then answers as if the user cancelled it, and makes
+    /// the next ones answer so without showing up
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let Some(thread) = *self.0.thread.lock().unwrap() else {
+            return;
+        };
+        for pending in PENDING_PASSPHRASES.lock().unwrap().iter() {
+            if pending.thread == thread {
+                debug!("Cancelling the passphrase {}", pending.id);
+                let _ = pending.answer.send(None);
+            }
+        }
+        close_dialogs(thread);
+    }
+}
+
+/// Runs `f` in the current thread, letting `cancellation` cancel its dialogs
+pub fn with_cancellation<R>(cancellation: Cancellation, f: impl FnOnce() -> R) -> R {
+    // SAFETY: gettid has no preconditions and cannot fail
+    *cancellation.0.thread.lock().unwrap() = Some(unsafe { libc::gettid() });
+    let previous = CANCELLATION.with(|c| c.replace(Some(cancellation.clone())));
+    let result = f();
+    CANCELLATION.with(|c| *c.borrow_mut() = previous);
+    *cancellation.0.thread.lock().unwrap() = None;
+    result
+}
+
+/// Whether the prompt showing the dialogs of this thread got dismissed, see [with_cancellation]
+pub fn cancelled() -> bool {
+    CANCELLATION.with(|c| c.borrow().as_ref().is_some_and(Cancellation::is_cancelled))
+}
+
+/// Terminates the pinentry processes the thread started, which only shows dialogs
+fn close_dialogs(thread: libc::pid_t) {
+    let path = format!("/proc/self/task/{}/children", thread);
+    let children = match fs::read_to_string(&path) {
+        Ok(children) => children,
+        Err(e) => {
+            warn!("Cannot close the dialogs, reading {} failed: {}", path, e);
+            return;
+        }
+    };
+    for pid in children.split_whitespace().filter_map(|p| p.parse().ok()) {
+        debug!("Closing the dialog of process {}", pid);
+        // SAFETY: kill has no memory safety preconditions
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+    }
+}
+
+/// Runs `f` showing its dialogs for the client having the given unique bus name: the DBus
+/// dispatch does so for each call, and the prompts, running in a thread of their own, for the
+/// client which called Prompt
+pub fn with_client<R>(bus_name: Option<String>, f: impl FnOnce() -> R) -> R {
+    let previous = CLIENT.with(|c| c.replace(bus_name));
+    let result = f();
+    CLIENT.with(|c| *c.borrow_mut() = previous);
+    result
+}
+
+/// The client the dialogs of this thread get shown for, see [with_client]
+pub fn client() -> Option<String> {
+    CLIENT.with(|c| c.borrow().clone())
+}
+
+/// Registers the prompter of the client, unless another client has one: the registration lasts
+/// until its client unregisters it or leaves the bus, see [crate::tks_dbus::owner_tracker].
+/// Returns whether it got registered.
+pub fn register(bus_name: String, path: dbus::Path<'static>) -> bool {
+    let mut prompter = PROMPTER.lock().unwrap();
+    if let Some((name, _)) = prompter.as_ref().filter(|(name, _)| *name != bus_name) {
+        warn!("Refusing the prompter of {}, {} has one", bus_name, name);
+        return false;
+    }
+    debug!("Prompter {} registered by {}", path, bus_name);
+    *prompter = Some((bus_name, path));
+    true
+}
+
+/// Unregisters the prompter of the client, if any; returns whether it had one
+pub fn unregister(bus_name: &str) -> bool {
+    let mut prompter = PROMPTER.lock().unwrap();
+    match prompter.as_ref() {
+        Some((name, _)) if name == bus_name => {
+            debug!("Prompter of {} unregistered", bus_name);
+            *prompter = None;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The prompter to show the dialogs with
+pub fn current() -> Box<dyn Prompter> {
+    let backend = match RunMode::test_prompt_script() {
+        Some(_) => PromptBackend::Scripted,
+        None => SETTINGS.lock().unwrap().prompt.backend,
+    };
+    match (backend, PROMPTER.lock().unwrap().clone()) {
+        (PromptBackend::Auto, Some((bus_name, path))) => {
+            trace!("Using the prompter of {}", bus_name);
+            Box::new(DBusPrompter { bus_name, path })
+        }
+        (PromptBackend::Auto, None) if tks_dbus::on_system_bus() => {
+            Box::new(PolkitPrompter { client: client() })
+        }
+        (PromptBackend::Polkit, _) => Box::new(PolkitPrompter { client: client() }),
+        (PromptBackend::Remote, _) => Box::new(RemotePrompter {}),
+        (PromptBackend::Scripted, _) => Box::new(ScriptedPrompter {}),
+        _ => Box::new(PinentryPrompter {}),
+    }
+}
+
+/// The pinentry program to run: the configured one, looked for in the `path` directories unless
+/// given by its path, or with [Pinentry::AUTO] the first installed flavor suiting the `desktop`,
+/// e.g. pinentry-qt on KDE, falling back to `pinentry`
+pub fn pinentry_program(
+    configured: &str,
+    desktop: Option<&str>,
+    path: Option<&OsStr>,
+) -> Option<PathBuf> {
+    let find = |program: &str| -> Option<PathBuf> {
+        if program.contains('/') {
+            return is_executable(Path::new(program)).then(|| program.into());
+        }
+        env::split_paths(path?)
+            .map(|dir| dir.join(program))
+            .find(|p| is_executable(p))
+    };
+    if configured != Pinentry::AUTO {
+        return find(configured);
+    }
+    let desktops = desktop.unwrap_or_default().split(':');
+    desktops
+        .filter_map(|d| PINENTRY_FLAVORS.iter().find(|(name, _)| name.eq_ignore_ascii_case(d)))
+        .flat_map(|(_, programs)| programs.iter())
+        .chain(std::iter::once(&PINENTRY_DEFAULT))
+        .find_map(|p| find(p))
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// The passphrase dialogs waiting for `tks-cli service unlock`: their id, description, prompt and
+/// whether the passphrase should be entered twice
+pub fn pending_passphrases() -> Vec<(u32, String, String, bool)> {
+    PENDING_PASSPHRASES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| (p.id, p.description.clone(), p.prompt.clone(), p.confirmation))
+        .collect()
+}
+
+/// Answers the pending passphrase dialog; `None` cancels it
+pub fn answer_passphrase(id: u32, passphrase: Option<SecretString>) -> Result<(), TksError> {
+    let mut pending = PENDING_PASSPHRASES.lock().unwrap();
+    let index = pending
+        .iter()
+        .position(|p| p.id == id)
+        .ok_or_else(|| TksError::NotFound(Some(format!("No pending passphrase {}", id))))?;
+    let empty = passphrase.as_ref().is_some_and(|p| p.expose_secret().is_empty());
+    if pending[index].required && empty {
+        return Err(TksError::ParameterError);
+    }
+    let request = pending.remove(index);
+    debug!("Passphrase {} answered", id);
+    // the dialog may have timed out meanwhile
+    let _ = request.answer.send(passphrase);
+    Ok(())
+}
+
+/// Only the user running the service may see and answer its dialogs; on the session bus the
+/// other users can't connect anyway, but the bus may be a system one, see [tks_dbus::on_system_bus]
+pub fn check_same_user(bus_name: &str) -> Result<(), TksError> {
+    let conn = tks_dbus::blocking_connection()?;
+    let proxy = conn.with_proxy(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        Duration::from_secs(5),
+    );
+    let (uid,): (u32,) =
+        proxy.method_call("org.freedesktop.DBus", "GetConnectionUnixUser", (bus_name,))?;
+    // SAFETY: geteuid has no preconditions and cannot fail
+    match uid == unsafe { libc::geteuid() } {
+        true => Ok(()),
+        false => {
+            warn!("Refusing the passphrase dialogs to {} of uid {}", bus_name, uid);
+            Err(TksError::PermissionDenied)
+        }
+    }
+}
+
+pub struct PinentryPrompter {}
+
+impl PinentryPrompter {
+    /// The program to run, and the title of the dialogs, as configured
+    fn settings() -> Result<(PathBuf, Option<String>), TksError> {
+        let pinentry = SETTINGS.lock().unwrap().prompt.pinentry.clone();
+        let program = pinentry_program(
+            &pinentry.program,
+            env::var("XDG_CURRENT_DESKTOP").ok().as_deref(),
+            env::var_os("PATH").as_deref(),
+        )
+        .ok_or(TksError::NoPinentryBinaryFound)?;
+        trace!("Showing the dialog with {}", program.display());
+        Ok((program, pinentry.title))
+    }
+}
+
+impl Prompter for PinentryPrompter {
+    fn show_message(&self, ok: &str, message: &str) -> Result<(), TksError> {
+        if cancelled() {
+            return Ok(());
+        }
+        let (program, title) = PinentryPrompter::settings()?;
+        let mut d = MessageDialog::with_binary(program);
+        if let Some(title) = &title {
+            d.with_title(title);
+        }
+        match d.with_ok(ok).show_message(message) {
+            Err(_) if cancelled() => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    fn ask_passphrase(
+        &self,
+        request: &PassphraseRequest,
+    ) -> Result<Option<SecretString>, TksError> {
+        if cancelled() {
+            return Ok(None);
+        }
+        let (program, title) = PinentryPrompter::settings()?;
+        let mut d = pinentry::PassphraseInput::with_binary(program);
+        if let Some(title) = &title {
+            d.with_title(title);
+        }
+        if request.required {
+            d.required("Password is required");
+        }
+        d.with_prompt(request.prompt).with_description(request.description);
+        if let Some((confirmation, mismatch)) = request.confirmation {
+            d.with_confirmation(confirmation, mismatch);
+        }
+        match d.interact() {
+            // closed by the cancellation
+            Err(_) if cancelled() => Ok(None),
+            Ok(s) => Ok(Some(s)),
+            Err(pinentry::Error::Cancelled) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn confirm(&self, ok: &str, cancel: &str, message: &str) -> Result<bool, TksError> {
+        if cancelled() {
+            return Ok(false);
+        }
+        let (program, title) = PinentryPrompter::settings()?;
+        let mut d = ConfirmationDialog::with_binary(program);
+        if let Some(title) = &title {
+            d.with_title(title);
+        }
+        match d.with_ok(ok).with_cancel(cancel).confirm(message) {
+            Err(_) if cancelled() => Ok(false),
+            result => Ok(result?),
+        }
+    }
+}
+
+/// Calls the prompter registered by a desktop component. The dialogs run outside of the DBus
+/// dispatch, so a connection of its own waits for the answers.
+pub struct DBusPrompter {
+    bus_name: String,
+    path: dbus::Path<'static>,
+}
+
+impl DBusPrompter {
+    fn call<A, R>(&self, method: &str, args: A) -> Result<R, TksError>
+    where
+        A: dbus::arg::AppendAll,
+        R: dbus::arg::ReadAll,
+    {
+        let conn = tks_dbus::blocking_connection()?;
+        conn.with_proxy(self.bus_name.as_str(), &self.path, PROMPTER_TIMEOUT)
+            .method_call(PROMPTER_INTERFACE, method, args)
+            .map_err(|e| {
+                warn!("Prompter {} failed: {}", self.bus_name, e);
+                if e.name() == Some("org.freedesktop.DBus.Error.ServiceUnknown") {
+                    // the prompter went away without us noticing yet
+                    unregister(&self.bus_name);
+                }
+                e.into()
+            })
+    }
+}
+
+impl Prompter for DBusPrompter {
+    fn show_message(&self, ok: &str, message: &str) -> Result<(), TksError> {
+        self.call("ShowMessage", (ok, message))
+    }
+
+    fn ask_passphrase(
+        &self,
+        request: &PassphraseRequest,
+    ) -> Result<Option<SecretString>, TksError> {
+        let (confirmation, mismatch) = request.confirmation.unwrap_or(("", ""));
+        let (cancelled, passphrase): (bool, String) = self.call(
+            "AskPassphrase",
+            (
+                request.description,
+                request.prompt,
+                confirmation,
+                mismatch,
+                request.required,
+            ),
+        )?;
+        Ok((!cancelled).then(|| SecretString::new(passphrase)))
+    }
+
+    fn confirm(&self, ok: &str, cancel: &str, message: &str) -> Result<bool, TksError> {
+        let (confirmed,): (bool,) = self.call("Confirm", (ok, cancel, message))?;
+        Ok(confirmed)
+    }
+}
+
+/// Asks PolicyKit whether the client may do what the dialog is about, instead of showing it. The
+/// client is the subject, so the polkit rules may tell the clients apart by their user.
+pub struct PolkitPrompter {
+    client: Option<String>,
+}
+
+impl PolkitPrompter {
+    fn check_authorization(&self, action: &str, message: &str) -> Result<bool, TksError> {
+        let Some(client) = &self.client else {
+            warn!("Refusing {}, there is no client to authorize", action);
+            return Ok(false);
+        };
+        let conn = dbus::blocking::Connection::new_system()?;
+        let mut subject = PropMap::new();
+        subject.insert("name".to_string(), Variant(Box::new(client.clone())));
+        let details = HashMap::from([("polkit.message", message)]);
+        let ((authorized, challenge, _),): ((bool, bool, HashMap<String, String>),) = conn
+            .with_proxy(POLKIT_NAME, POLKIT_PATH, PROMPTER_TIMEOUT)
+            .method_call(
+                POLKIT_AUTHORITY,
+                "CheckAuthorization",
+                (
+                    ("system-bus-name", subject),
+                    action,
+                    details,
+                    POLKIT_ALLOW_USER_INTERACTION,
+                    "",
+                ),
+            )?;
+        debug!(
+            "polkit {} {} for {}{}",
+            if authorized { "authorized" } else { "refused" },
+            action,
+            client,
+            if challenge { ", no agent answered" } else { "" }
+        );
+        Ok(authorized)
+    }
+}
+
+impl Prompter for PolkitPrompter {
+    fn show_message(&self, _ok: &str, message: &str) -> Result<(), TksError> {
+        info!("{}", message);
+        Ok(())
+    }
+
+    /// Only the passphrases which may be left empty, so that the key files unlock the storage
+    fn ask_passphrase(
+        &self,
+        request: &PassphraseRequest,
+    ) -> Result<Option<SecretString>, TksError> {
+        if request.required {
+            return Err(TksError::NotSupported(
+                "asking passwords through polkit, configure the key files of the storage",
+            ));
+        }
+        let authorized = self.check_authorization(UNLOCK_ACTION, request.description)?;
+        Ok(authorized.then(|| SecretString::new(String::new())))
+    }
+
+    fn confirm(&self, _ok: &str, _cancel: &str, message: &str) -> Result<bool, TksError> {
+        self.check_authorization(CONFIRM_ACTION, message)
+    }
+}
+
+/// Makes the passphrase dialogs wait for `tks-cli service unlock`, run from a terminal of the
+/// user, e.g. over SSH, to answer them through `io.linux_tks.Service1.AnswerPassphrase`. Nobody
+/// is there to confirm anything, so the confirmations get declined.
+pub struct RemotePrompter {}
+
+impl Prompter for RemotePrompter {
+    fn show_message(&self, _ok: &str, message: &str) -> Result<(), TksError> {
+        info!("{}", message);
+        Ok(())
+    }
+
+    fn ask_passphrase(
+        &self,
+        request: &PassphraseRequest,
+    ) -> Result<Option<SecretString>, TksError> {
+        if cancelled() {
+            return Ok(None);
+        }
+        let (answer, answered) = mpsc::channel();
+        let id = NEXT_PASSPHRASE_ID.fetch_add(1, Ordering::Relaxed);
+        PENDING_PASSPHRASES.lock().unwrap().push(PendingPassphrase {
+            id,
+            description: request.description.to_string(),
+            prompt: request.prompt.to_string(),
+            confirmation: request.confirmation.is_some(),
+            required: request.required,
+            // SAFETY: gettid has no preconditions and cannot fail
+            thread: unsafe { libc::gettid() },
+            answer,
+        });
+        info!("Run `tks-cli service unlock` to answer: {}", request.description);
+        let passphrase = answered.recv_timeout(PROMPTER_TIMEOUT);
+        PENDING_PASSPHRASES.lock().unwrap().retain(|p| p.id != id);
+        match passphrase {
+            Ok(passphrase) => Ok(passphrase),
+            Err(_) => {
+                warn!("Nobody answered the passphrase {} in time", id);
+                Ok(None)
+            }
+        }
+    }
+
+    fn confirm(&self, _ok: &str, _cancel: &str, message: &str) -> Result<bool, TksError> {
+        warn!("Declined, there is no display to confirm on: {}", message);
+        Ok(false)
+    }
+}
+
+/// What the [ScriptedPrompter] answers to a dialog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptedAnswer {
+    /// Enters the passphrase, twice when it needs to be confirmed
+    Passphrase(String),
+    /// Cancels the passphrase dialog
+    Cancel,
+    /// Answers the confirmation, `true` confirming it
+    Confirm(bool),
+    /// Keeps the dialog open until the prompt gets dismissed, which then cancels it
+    Wait,
+}
+
+/// The answers as listed by `TKS_TEST_PROMPT_SCRIPT`: `passphrase:<passphrase>`, `cancel`,
+/// `confirm`, `decline` or `wait`
+impl FromStr for ScriptedAnswer {
+    type Err = TksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cancel" => Ok(ScriptedAnswer::Cancel),
+            "confirm" => Ok(ScriptedAnswer::Confirm(true)),
+            "decline" => Ok(ScriptedAnswer::Confirm(false)),
+            "wait" => Ok(ScriptedAnswer::Wait),
+            _ => match s.strip_prefix("passphrase:") {
+                Some(passphrase) => Ok(ScriptedAnswer::Passphrase(passphrase.to_string())),
+                None => Err(TksError::ParameterError),
+            },
+        }
+    }
+}
+
+fn initial_script() -> VecDeque<ScriptedAnswer> {
+    let script = RunMode::test_prompt_script().unwrap_or_default();
+    script
+        .lines()
+        .filter(|l| !l.is_empty())
+        .filter_map(|l| match l.parse() {
+            Ok(answer) => Some(answer),
+            Err(_) => {
+                warn!("Ignoring the scripted answer '{}'", l);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Makes the [ScriptedPrompter] give these answers, in order, instead of the remaining ones
+pub fn script(answers: impl IntoIterator<Item = ScriptedAnswer>) {
+    *SCRIPT.lock().unwrap() = answers.into_iter().collect();
+}
+
+/// The answers the [ScriptedPrompter] didn't give yet
+pub fn remaining_answers() -> Vec<ScriptedAnswer> {
+    SCRIPT.lock().unwrap().iter().cloned().collect()
+}
+
+/// Answers the dialogs the way the tests programmed with [script], or `TKS_TEST_PROMPT_SCRIPT`.
+/// Once the answers run out, the passphrase dialogs get cancelled and the confirmations
+/// declined, as if the user closed them; an answer not fitting the dialog fails it.
+pub struct ScriptedPrompter {}
+
+impl ScriptedPrompter {
+    fn next_answer(dialog: &str) -> Option<ScriptedAnswer> {
+        let answer = SCRIPT.lock().unwrap().pop_front();
+        match &answer {
+            Some(ScriptedAnswer::Passphrase(_)) => debug!("Scripted passphrase for: {}", dialog),
+            Some(answer) => debug!("Scripted {:?} for: {}", answer, dialog),
+            None => warn!("No scripted answer left for: {}", dialog),
+        }
+        answer
+    }
+
+    /// Waits for the prompt showing the dialog to get dismissed
+    fn wait_for_cancellation() {
+        while !cancelled() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Prompter for ScriptedPrompter {
+    fn show_message(&self, _ok: &str, message: &str) -> Result<(), TksError> {
+        debug!("Scripted message: {}", message);
+        Ok(())
+    }
+
+    fn ask_passphrase(
+        &self,
+        request: &PassphraseRequest,
+    ) -> Result<Option<SecretString>, TksError> {
+        if cancelled() {
+            return Ok(None);
+        }
+        match ScriptedPrompter::next_answer(request.description) {
+            Some(ScriptedAnswer::Passphrase(passphrase)) => Ok(Some(SecretString::new(passphrase))),
+            Some(ScriptedAnswer::Cancel) | None => Ok(None),
+            Some(ScriptedAnswer::Wait) => {
+                ScriptedPrompter::wait_for_cancellation();
+                Ok(None)
+            }
+            Some(ScriptedAnswer::Confirm(_)) => Err(TksError::InternalError(
+                "the script answers a confirmation, not a passphrase dialog",
+            )),
+        }
+    }
+
+    fn confirm(&self, _ok: &str, _cancel: &str, message: &str) -> Result<bool, TksError> {
+        match ScriptedPrompter::next_answer(message) {
+            Some(ScriptedAnswer::Confirm(confirmed)) => Ok(confirmed),
+            Some(ScriptedAnswer::Wait) => {
+                ScriptedPrompter::wait_for_cancellation();
+                Ok(false)
+            }
+            None => Ok(false),
+            Some(_) => Err(TksError::InternalError(
+                "the script answers a passphrase dialog, not a confirmation",
+            )),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Some clients expect responses differing from what TKS gives, e.g. because another Secret
+//! Service implementation behaves that way. These quirks get applied to the calls of the clients
+//! needing them, after a table keyed on the process executable of the client. The table comes
+//! with the clients known to need quirks; `clients.quirks` in the settings adds clients, or
+//! replaces the quirks of the known ones:
+//!
+//! ```toml
+//! [clients.quirks]
+//! # by executable file name, or full path; the sandboxed clients by their identity, see
+//! # crate::tks_dbus::sandbox
+//! keepassxc = []
+//! "flatpak:org.keepassxc.keepassxc" = []
+//! "/opt/app/bin/app" = ["label-is-alias", "collection-changed-on-new-item"]
+//! ```
+//!
+//! `clients.builtin_quirks = false` ignores the built-in table.
+
+use crate::settings::{Clients, Quirk, SETTINGS};
+use crate::tks_dbus::client_context::TksClientProcess;
+use lazy_static::lazy_static;
+use log::debug;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Quirks of the known clients, by process executable file name or sandboxed identity
+const BUILTIN_QUIRKS: &[(&str, &[Quirk])] = &[
+    // looks the collections up by label, expecting the alias
+    ("keepassxc", &[Quirk::LabelIsAlias]),
+    ("flatpak:org.keepassxc.keepassxc", &[Quirk::LabelIsAlias]),
+    ("snap:keepassxc", &[Quirk::LabelIsAlias]),
+];
+
+lazy_static! {
+    /// Quirks of the clients already looked up, by unique bus name
+    static ref CLIENT_QUIRKS: Mutex<HashMap<String, Vec<Quirk>>> = Mutex::new(HashMap::new());
+}
+
+/// Whether the quirk applies to the client having the given unique bus name
+pub fn applies(sender: Option<&str>, quirk: Quirk) -> bool {
+    let Some(sender) = sender else {
+        return false;
+    };
+    if let Some(quirks) = CLIENT_QUIRKS.lock().unwrap().get(sender) {
+        return quirks.contains(&quirk);
+    }
+    let quirks = {
+        let settings = SETTINGS.lock().unwrap();
+        if !settings.clients.builtin_quirks && settings.clients.quirks.is_empty() {
+            return false;
+        }
+        settings.clients.clone()
+    };
+    // looking the caller up takes a few calls to the bus, so it's only done once per client
+    let quirks = match TksClientProcess::from_bus_name(sender.to_string()) {
+        Ok(process) => quirks_of(&quirks, process.exe_path()),
+        Err(e) => {
+            debug!("Cannot look {} up, applying no quirks: {}", sender, e);
+            Vec::new()
+        }
+    };
+    debug!("Quirks of {}: {:?}", sender, quirks);
+    let applies = quirks.contains(&quirk);
+    CLIENT_QUIRKS.lock().unwrap().insert(sender.to_string(), quirks);
+    applies
+}
+
+/// The quirks of the client having the given process executable. The configured ones, by path
+/// then by file name, take precedence over the built-in ones. The settings have their keys
+/// lowercased, so these get compared regardless of case.
+pub fn quirks_of(settings: &Clients, exe_path: &OsStr) -> Vec<Quirk> {
+    let name = Path::new(exe_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let configured = settings
+        .quirks
+        .get(&exe_path.to_string_lossy().to_lowercase())
+        .or_else(|| settings.quirks.get(&name));
+    if let Some(quirks) = configured {
+        return quirks.clone();
+    }
+    if !settings.builtin_quirks {
+        return Vec::new();
+    }
+    BUILTIN_QUIRKS
+        .iter()
+        .find(|(client, _)| *client == name)
+        .map(|(_, quirks)| quirks.to_vec())
+        .unwrap_or_default()
+}
+
+/// Drops the quirks of a client which left the bus
+pub fn forget_client(sender: &str) {
+    CLIENT_QUIRKS.lock().unwrap().remove(sender);
+}
+
+/// Drops the quirks of all the clients, e.g. once the settings changed
+pub fn forget_all() {
+    CLIENT_QUIRKS.lock().unwrap().clear();
+}
@@ -0,0 +1,132 @@
+//! Identifies the sandboxed clients by their application rather than their process executable,
+//! which tells nothing about them: a Flatpak application runs an executable of its runtime, or a
+//! path only existing inside its sandbox, and a Snap one runs from a directory changing upon each
+//! refresh. Such a client is `flatpak:<app ID>` or `snap:<snap name>` wherever the other clients
+//! are their executable path: the known clients, the denials, the ACLs and the quirks, e.g.
+//! `flatpak:org.mozilla.firefox` gets granted an access with `tks-cli acl grant`.
+//!
+//! A Flatpak application is known from the `.flatpak-info` file at the root of its sandbox, which
+//! it can't write, the same way xdg-desktop-portal tells them. A Snap one is known from the cgroup
+//! snapd starts it in; any process may move itself to such a cgroup, so when AppArmor is enabled,
+//! the confinement of the process must be the one of the snap too.
+
+use log::{debug, warn};
+use std::fs;
+
+pub const FLATPAK_PREFIX: &str = "flatpak:";
+pub const SNAP_PREFIX: &str = "snap:";
+
+/// Tells whether AppArmor confines the processes
+const APPARMOR_ENABLED: &str = "/sys/module/apparmor/parameters/enabled";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Sandbox {
+    /// The application ID, e.g. `org.mozilla.firefox`
+    Flatpak(String),
+    /// The snap name, e.g. `firefox`
+    Snap(String),
+}
+
+impl Sandbox {
+    /// The sandbox the process runs in, if any
+    pub fn of(pid: u32) -> Option<Sandbox> {
+        if let Ok(info) = fs::read_to_string(format!("/proc/{}/root/.flatpak-info", pid)) {
+            let app_id = flatpak_app_id(&info);
+            if app_id.is_none() {
+                debug!(
+                    "Process {} runs in a Flatpak sandbox, but not an application",
+                    pid
+                );
+            }
+            return app_id.map(Sandbox::Flatpak);
+        }
+        let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+        let snap = snap_name(&cgroup)?;
+        match apparmor_label(pid) {
+            Some(label) if !snap_confined(&label, &snap) => {
+                warn!(
+                    "Process {} runs in the cgroup of snap {}, but is confined as '{}'",
+                    pid,
+                    snap,
+                    label.trim()
+                );
+                None
+            }
+            _ => Some(Sandbox::Snap(snap)),
+        }
+    }
+
+    /// What stands for the process executable of the client
+    pub fn identity(&self) -> String {
+        match self {
+            Sandbox::Flatpak(app_id) => format!("{}{}", FLATPAK_PREFIX, app_id),
+            Sandbox::Snap(name) => format!("{}{}", SNAP_PREFIX, name),
+        }
+    }
+
+    /// The application, as shown to the user
+    pub fn describe(&self) -> String {
+        match self {
+            Sandbox::Flatpak(app_id) => format!("The Flatpak application {}", app_id),
+            Sandbox::Snap(name) => format!("The Snap application {}", name),
+        }
+    }
+}
+
+/// The `name` of the `[Application]` group of a `.flatpak-info` file; the sandboxes running a
+/// runtime alone have none
+pub fn flatpak_app_id(info: &str) -> Option<String> {
+    let mut group = "";
+    for line in info.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            group = name;
+            continue;
+        }
+        if group != "Application" {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if key.trim() == "name" && !value.trim().is_empty() => {
+                return Some(value.trim().to_string());
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The snap of a `/proc/<pid>/cgroup` file, from its `snap.<name>.<app>...` unit, e.g.
+/// `snap.firefox.firefox-<uuid>.scope`
+pub fn snap_name(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .flat_map(|path| path.split('/'))
+        .filter_map(|unit| unit.strip_prefix("snap."))
+        .filter_map(|unit| unit.split_once('.').map(|(name, _)| name))
+        .find(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+        .map(String::from)
+}
+
+/// Whether the AppArmor label of a process is the confinement of one of the snap's applications,
+/// e.g. `snap.firefox.firefox (enforce)`
+pub fn snap_confined(label: &str, snap: &str) -> bool {
+    label.trim().starts_with(&format!("snap.{}.", snap))
+}
+
+/// The AppArmor label of the process, unless AppArmor is disabled
+fn apparmor_label(pid: u32) -> Option<String> {
+    let enabled = fs::read_to_string(APPARMOR_ENABLED).is_ok_and(|e| e.trim() == "Y");
+    if !enabled {
+        return None;
+    }
+    // the LSM specific file of the kernels stacking several LSMs, then the legacy one
+    fs::read_to_string(format!("/proc/{}/attr/apparmor/current", pid))
+        .or_else(|_| fs::read_to_string(format!("/proc/{}/attr/current", pid)))
+        .ok()
+}
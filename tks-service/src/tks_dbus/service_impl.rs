@@ -1,34 +1,60 @@
+use crate::audit;
+use crate::audit::{AuditEvent, Outcome};
+use crate::settings::reload;
+use crate::settings::{ItemSchema, Quirk, SETTINGS};
+use crate::storage::acl::Access;
+use crate::storage::backup::RestoreMode;
+use crate::storage::merge::MergeConflict;
+use crate::storage::expiry;
+use crate::storage::item_schemas;
+use crate::storage::oauth::TokenQuery;
+use crate::storage::search::{AttributeMatch, AttributeQuery};
 use crate::storage::STORAGE;
 use crate::tks_dbus::fdo::service::OrgFreedesktopSecretService;
+use crate::tks_dbus::fdo::service::OrgFreedesktopSecretServiceCollectionChanged;
 use crate::tks_dbus::fdo::service::OrgFreedesktopSecretServiceCollectionCreated;
+use crate::tks_dbus::fdo::service::OrgFreedesktopSecretServiceCollectionDeleted;
 use crate::tks_dbus::session_impl::SESSION_MANAGER;
-use crate::tks_dbus::{sanitize_string, DBusHandle};
+use crate::tks_dbus::{reply_later, sanitize_string, DBusHandle};
 use crate::tks_dbus::{DBusHandlePath, MESSAGE_SENDER};
 use dbus::message::SignalArgs;
 use log;
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
+use secrecy::SecretString;
 use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::time::{Duration, UNIX_EPOCH};
 
 extern crate pretty_env_logger;
 use crate::convert_prop_map;
-use crate::register_object;
+use crate::tks_dbus::acl;
+use crate::tks_dbus::alias_registry;
 use crate::tks_dbus::collection_impl::CollectionImpl;
-use crate::tks_dbus::fdo::collection::{
-    register_org_freedesktop_secret_collection, OrgFreedesktopSecretCollection,
-};
+use crate::tks_dbus::fdo::collection::OrgFreedesktopSecretCollection;
 use crate::tks_dbus::fdo::session::register_org_freedesktop_secret_session;
 use crate::tks_dbus::item_impl::ItemImpl;
-use crate::tks_dbus::session_impl::SessionImpl;
-use crate::tks_dbus::CROSSROADS;
+use crate::tks_dbus::property_caller;
+use crate::tks_dbus::object_manager;
 
 use crate::tks_dbus::client_context::{TksClientOption, TksClientProcess, CLIENT_REGISTRY};
 use crate::tks_dbus::fdo::item::OrgFreedesktopSecretItem;
-use crate::tks_dbus::prompt_impl::{PromptWithPinentry, TksPromptChain};
+use crate::tks_dbus::prompt_impl::{
+    self, ConfirmationMessageActionParam, PromptAction, PromptDialog, PromptResult,
+    PromptWithPinentry, TksPromptChain,
+};
+use crate::tks_dbus::diagnostics;
+use crate::tks_dbus::prompter;
+use crate::tks_dbus::quirks;
+use crate::tks_dbus::visibility;
+use crate::tks_dbus::tks::oauth::IoLinuxTksOAuth1;
+use crate::tks_dbus::tks::search::IoLinuxTksSearch1;
+use crate::tks_dbus::tks::service::IoLinuxTksService1;
+use crate::tks_dbus::tks::session::register_io_linux_tks_session1;
 use crate::tks_dbus::DBusHandlePath::SinglePath;
 use crate::tks_error::TksError;
 use dbus::arg;
 use dbus_crossroads::{Context, PropContext};
-use DBusHandlePath::MultiplePaths;
+use uuid::Uuid;
 
 pub struct ServiceHandle {}
 pub struct ServiceImpl {}
@@ -64,7 +90,13 @@ impl OrgFreedesktopSecretService for ServiceImpl {
                 let path = {
                     let dh = sm.sessions.get(sess_id).unwrap().get_dbus_handle();
                     let path = dh.path();
-                    register_object!(register_org_freedesktop_secret_session::<SessionImpl>, dh);
+                    object_manager::register(
+                        &[
+                            register_org_freedesktop_secret_session,
+                            register_io_linux_tks_session1,
+                        ],
+                        dh,
+                    );
                     path
                 };
                 Ok((output, path.into()))
@@ -74,8 +106,10 @@ impl OrgFreedesktopSecretService for ServiceImpl {
     /// Create a new collection
     /// # Arguments
     /// * `properties` - A HashMap of properties to set on the collection; this version ignores any
-    /// properties but the org.freedesktop.Secret.Collection.Label property, which is required
-    /// * `alias` - The alias to use for the collection
+    ///   properties but the org.freedesktop.Secret.Collection.Label property, which is required,
+    ///   and the custom `tks:*` properties
+    /// * `alias` - The alias to use for the collection; if a collection already has this alias,
+    ///   its properties get updated and no new collection is created
     fn create_collection(
         &mut self,
         ctx: &mut Context,
@@ -83,19 +117,29 @@ impl OrgFreedesktopSecretService for ServiceImpl {
         alias: String,
     ) -> Result<(dbus::Path<'static>, dbus::Path<'static>), dbus::MethodErr> {
         trace!("create_collection alias={}", alias);
+        let (string_props, _) = convert_prop_map!(properties);
 
-        match alias.as_str() {
-            "default" => {
-                // no CollectionCreated signal is emitted for the default collection as it is already there
-                // TODO add any new properties to the existing collection
-                return Ok((
-                    dbus::Path::from("/org/freedesktop/secrets/collection/default"),
-                    dbus::Path::from("/"),
-                ));
-            }
-            _ => {}
+        let existing = match alias.as_str() {
+            "" => None,
+            _ => STORAGE.read().unwrap().read_alias(&alias).ok(),
+        };
+        if let Some(uuid) = existing {
+            // no CollectionCreated signal is emitted as the collection is already there
+            let uuid = Uuid::parse_str(&uuid).map_err(|e| dbus::MethodErr::failed(&e))?;
+            STORAGE
+                .write()
+                .unwrap()
+                .modify_collection(&uuid, |collection| {
+                    collection.set_properties(&string_props);
+                    Ok(())
+                })?;
+            CollectionImpl::emit_properties_changed(uuid, &["Label"]);
+            CollectionImpl::emit_sequence_changed(uuid);
+            return Ok((
+                CollectionImpl::from(&uuid).listed_path(),
+                dbus::Path::from("/"),
+            ));
         }
-        let (string_props, _) = convert_prop_map!(properties);
 
         // now check if user specified the org.freedesktop.Secret.Collection.Label property
         let label = string_props
@@ -107,32 +151,62 @@ impl OrgFreedesktopSecretService for ServiceImpl {
                 ))
             })?;
 
-        STORAGE
-            .lock()
+        // the name makes the object path, so it can't be taken twice
+        let taken = STORAGE
+            .read()
+            .unwrap()
+            .collections
+            .iter()
+            .find(|c| sanitize_string(&c.name) == sanitize_string(label))
+            .map(|c| c.uuid);
+        if let Some(uuid) = taken {
+            debug!("Collection '{}' already exists, prompting to reuse it", label);
+            let action = PromptAction {
+                dialog: PromptDialog::ConfirmationMessage(
+                    "Reuse".into(),
+                    "Cancel".into(),
+                    format!(
+                        "An application wants to create the collection '{}', but there already \
+                         is one having this name. Should the existing collection, with its \
+                         items, get reused?",
+                        label
+                    ),
+                    ConfirmationMessageActionParam::ReuseCollection(uuid, alias, string_props),
+                    ServiceImpl::reuse_collection,
+                ),
+            };
+            return Ok((dbus::Path::from("/"), PromptWithPinentry::new(action)?));
+        }
+
+        let created = STORAGE
+            .write()
             .unwrap()
-            .create_collection(&label, &alias, &string_props)
+            .create_collection(label, &alias, &string_props);
+        created
             .and_then(|uuid| {
-                let coll = CollectionImpl::from(&uuid);
-                let collection_path = coll.path();
-                register_object!(
-                    register_org_freedesktop_secret_collection::<CollectionImpl>,
-                    coll
-                );
+                audit::record(AuditEvent::CollectionCreate, Outcome::Success, vec![uuid]);
+                // constructing the CollectionHandle registers its paths, those of its alias too
+                let storage = STORAGE.read().unwrap();
+                let coll = storage.with_collection(&uuid, |c| Ok(CollectionImpl::from(c)))?;
+                alias_registry::update(&storage);
+                drop(storage);
+                let collection_path = coll.listed_path();
                 let collection_path_clone = collection_path.clone();
                 tokio::spawn(async move {
                     debug!("Sending CollectionCreated signal");
                     MESSAGE_SENDER.lock().unwrap().send_message(
                         OrgFreedesktopSecretServiceCollectionCreated {
-                            collection: collection_path_clone.clone().into(),
+                            collection: collection_path_clone.clone(),
                         }
-                        .to_emit_message(&collection_path_clone.into()),
+                        .to_emit_message(&collection_path_clone),
                     );
                 });
                 let prompt_path = dbus::Path::from("/");
-                Ok((collection_path.into(), prompt_path))
+                Ok((collection_path, prompt_path))
             })
             .map_err(|e| {
                 error!("Error creating collection: {}", e);
+                audit::record(AuditEvent::CollectionCreate, Outcome::Failure, Vec::new());
                 e.into()
             })
     }
@@ -145,47 +219,18 @@ impl OrgFreedesktopSecretService for ServiceImpl {
         let mut unlocked = Vec::new();
         let mut locked = Vec::new();
 
-        macro_rules! collect_paths {
-            ($locked:ident, $vec:ident) => {
-                STORAGE
-                    .lock()
-                    .unwrap()
-                    .collections
-                    .iter()
-                    .filter(|c| c.locked == $locked)
-                    .for_each(|c| {
-                        $vec.extend(
-                            c.items
-                                .iter()
-                                .filter(|i| {
-                                    search_attributes.iter().fold(true, |b, (k, v)| {
-                                        b && ( i
-                                            .attributes
-                                            .clone()
-                                            .into_keys()
-                                            .find(|kx| kx == k)
-                                            .is_some()
-                                            && i.attributes
-                                                .clone()
-                                                .into_values()
-                                                .find(|vx| vx == v)
-                                                .is_some() ) || (
-                                            // if user specified `label`:`value` then extend the
-                                            // search to current item's label, to help finding items
-                                            match k.to_lowercase().as_str() {
-                                                "label" => i.label.to_lowercase() == *v,
-                                                _ => false
-                                            }
-                                        )
-                                    })
-                                })
-                                .map(|i| ItemImpl::from(i).into()),
-                        );
-                    })
-            };
-        }
-        collect_paths!(true, locked);
-        collect_paths!(false, unlocked);
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let hidden = visibility::hidden_from(sender.as_deref());
+        STORAGE
+            .read()
+            .unwrap()
+            .search_items(&search_attributes)
+            .into_iter()
+            .filter(|i| !hidden.contains(&i.id.collection_uuid))
+            .for_each(|i| match i.locked {
+                true => locked.push(ItemImpl::from(i).into()),
+                false => unlocked.push(ItemImpl::from(i).into()),
+            });
         debug!("search_items unlocked: {:?}", unlocked);
         debug!("search_items locked: {:?}", locked);
         Ok((unlocked, locked))
@@ -199,15 +244,45 @@ impl OrgFreedesktopSecretService for ServiceImpl {
         let mut prompts = VecDeque::new();
 
         let mut binding = CLIENT_REGISTRY.lock().unwrap();
-        let client_opt = binding.retrieve(ctx)?;
-        match client_opt {
-            TksClientOption::Prompt(prompt) => prompts.push_back(dbus::Path::from(prompt)),
-            TksClientOption::Client(_) => {}
+        // the known clients only get read once the storage is unlocked, so a new client gets
+        // confirmed after the unlock prompts, by then it may turn out to be known
+        let enroll_prompt = match binding.retrieve(ctx)? {
+            TksClientOption::Prompt(prompt) => Some(dbus::Path::from(prompt)),
+            TksClientOption::Client(_) => None,
+        };
+
+        let unlock_default = objects.is_empty();
+        // items may get unlocked individually, without unlocking their collection
+        let (item_paths, objects): (Vec<_>, Vec<_>) = objects
+            .into_iter()
+            .partition(|p| ItemImpl::from(p).is_not_default());
+        let mut unlocked = Vec::new();
+        // the objects which get unlocked by the prompts
+        let mut prompted = Vec::new();
+        let mut audited = Vec::new();
+        // the keys which unlocked the collections lately unlock them again, see key_cache
+        let cache_ttl = Duration::from_secs(SETTINGS.lock().unwrap().prompt.cache_ttl);
+        for p in item_paths {
+            let item = ItemImpl::from(&p);
+            audited.push(item.item_id.uuid);
+            let cached = || {
+                let mut storage = STORAGE.write().unwrap();
+                storage.unlock_item_with_cached_key(&item.item_id, cache_ttl)
+            };
+            if item.locked()? && !cached()? {
+                let unlock_action = STORAGE
+                    .write()
+                    .unwrap()
+                    .create_item_unlock_action(&item.item_id)?;
+                prompts.push_back(PromptWithPinentry::new(unlock_action)?);
+                prompted.push(p);
+            } else {
+                unlocked.push(p);
+            }
         }
 
-        let collection_paths: Vec<_> = if objects.is_empty() {
-            let default_collection_path =
-                dbus::Path::from("/org/freedesktop/secrets/aliases/default");
+        let collection_paths: Vec<_> = if unlock_default {
+            let default_collection_path = dbus::Path::from(alias_registry::DEFAULT_ALIAS_PATH);
             let mut collection_paths = Vec::new();
             collection_paths.push((
                 default_collection_path.clone(),
@@ -228,25 +303,44 @@ impl OrgFreedesktopSecretService for ServiceImpl {
                 .collect();
             collection_paths
         };
-        let mut unlocked = Vec::new();
         for cc in collection_paths {
             let coll = cc.2;
-            if coll.locked()? {
-                let unlock_action = STORAGE.lock().unwrap().create_unlock_action(&coll.uuid)?;
+            audited.push(coll.uuid);
+            let cached = || {
+                let mut storage = STORAGE.write().unwrap();
+                storage.unlock_with_cached_key(&coll.uuid, cache_ttl)
+            };
+            if coll.locked()? && !cached()? {
+                let unlock_action = STORAGE.write().unwrap().create_unlock_action(&coll.uuid)?;
                 let prompt = PromptWithPinentry::new(unlock_action)?;
                 prompts.push_back(dbus::Path::from(prompt));
+                prompted.push(cc.1);
             } else {
                 unlocked.push(cc.1);
             }
         }
+        prompts.extend(enroll_prompt);
+        let outcome = match prompts.is_empty() {
+            true => Outcome::Success,
+            false => Outcome::Prompted,
+        };
+        audit::record(AuditEvent::CollectionUnlock, outcome, audited);
         let mut unlocked_list = Vec::new();
         let returned_prompt = match prompts.len() {
             0 => {
                 // returned unlocked list only if no prompt was created
                 unlocked_list = unlocked;
                 dbus::Path::from("/") },
-            1 => prompts.pop_front().unwrap(),
-            _ => TksPromptChain::new(prompts),
+            n => {
+                let prompt = match n {
+                    1 => prompts.pop_front().unwrap(),
+                    _ => TksPromptChain::new(prompts),
+                };
+                // otherwise the Completed signal reports them, along with those already unlocked
+                unlocked.extend(prompted);
+                prompt_impl::set_result(&prompt, PromptResult::Paths(unlocked))?;
+                prompt
+            }
         };
         debug!("unlocked: {:?}, prompt: {:?}", unlocked_list, returned_prompt);
         Ok((unlocked_list, returned_prompt))
@@ -258,25 +352,33 @@ impl OrgFreedesktopSecretService for ServiceImpl {
         objects: Vec<dbus::Path<'static>>,
     ) -> Result<(Vec<dbus::Path<'static>>, dbus::Path<'static>), dbus::MethodErr> {
         trace!("lock {:?}", objects);
-        let collection_names = objects
-            .iter()
-            .map(|p| p.to_string())
-            .map(|p| p.split('/').map(|s| s.to_string()).collect::<Vec<String>>()[5].clone())
-            .collect::<Vec<String>>();
+        // items cannot be locked without their collection, which then gets locked as a whole
+        let mut uuids: Vec<Uuid> = Vec::new();
         let mut locked: Vec<dbus::Path> = Vec::new();
-        STORAGE
-            .lock()
-            .unwrap()
-            .collections
-            .iter_mut()
-            .filter(|c| collection_names.contains(&c.name))
-            .for_each(|c| {
-                let _ = c.lock();
-                match CollectionImpl::from(&*c).path() {
-                    SinglePath(p) => locked.push(p),
-                    MultiplePaths(mut paths) => locked.append(&mut paths),
+        let mut storage = STORAGE.write().unwrap();
+        for p in objects {
+            let Some(uuid) = CollectionImpl::resolve(&p) else {
+                warn!("lock: no collection nor item at {}", p);
+                continue;
+            };
+            if storage.is_in_memory(&uuid) {
+                debug!("lock: the session collection stays unlocked");
+                continue;
+            }
+            if !uuids.contains(&uuid) {
+                uuids.push(uuid);
+            }
+            locked.push(p);
+        }
+        for uuid in uuids.iter() {
+            storage.lock_collection(uuid)?;
+            for p in CollectionImpl::from(uuid).paths {
+                if !locked.contains(&p) {
+                    locked.push(p);
                 }
-            });
+            }
+        }
+        audit::record(AuditEvent::CollectionLock, Outcome::Success, uuids);
         Ok((locked, dbus::Path::from("/")))
     }
     fn get_secrets(
@@ -292,14 +394,13 @@ impl OrgFreedesktopSecretService for ServiceImpl {
         dbus::MethodErr,
     > {
         trace!("get_secrets {:?}", items);
-        type Secret = (dbus::Path<'static>, Vec<u8>, Vec<u8>, String);
-        let mut secrets_map: HashMap<dbus::Path, Secret> = HashMap::new();
-
-        let items: Vec<_> = items.iter().map(|p| ItemImpl::from(p)).collect();
-        for mut i in items {
-            secrets_map.insert(i.path.clone(), i.get_secret(session.clone(), ctx)?);
-        }
-        Ok(secrets_map)
+        let sender = ctx
+            .message()
+            .sender()
+            .ok_or_else(|| dbus::MethodErr::failed("Unknown sender"))?
+            .to_string();
+        let items: Vec<_> = items.iter().map(ItemImpl::from).collect();
+        ItemImpl::get_secrets(&items, &session, &sender)
     }
 
     fn read_alias(
@@ -308,50 +409,711 @@ impl OrgFreedesktopSecretService for ServiceImpl {
         name: String,
     ) -> Result<dbus::Path<'static>, dbus::MethodErr> {
         trace!("read_alias {}", name);
-        Ok(STORAGE.lock().unwrap().read_alias(&name).map_or_else(
-            |_| dbus::Path::from("/"),
-            |name| {
-                dbus::Path::from(format!(
-                    "/org/freedesktop/secrets/collection/{}",
-                    sanitize_string(&name)
-                ))
-            },
-        ))
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let Ok(uuid) = STORAGE.read().unwrap().read_alias(&name) else {
+            return Ok(dbus::Path::from("/"));
+        };
+        let uuid = Uuid::parse_str(&uuid).map_err(|e| dbus::MethodErr::failed(&e))?;
+        match alias_registry::alias_path(&name) {
+            Some(path) if quirks::applies(sender.as_deref(), Quirk::AliasPath) => Ok(path),
+            _ => Ok(CollectionImpl::from(&uuid).canonical_path()),
+        }
     }
     fn set_alias(
         &mut self,
         ctx: &mut Context,
-        _name: String,
-        _collection: dbus::Path<'static>,
+        name: String,
+        collection: dbus::Path<'static>,
     ) -> Result<(), dbus::MethodErr> {
-        trace!("Hello from set_alias");
-        return Err(dbus::MethodErr::failed(&format!(
-            "Error setting alias: {}",
-            "Not implemented"
-        )));
+        trace!("set_alias {} {}", name, collection);
+        let uuid = if &*collection == "/" {
+            None
+        } else {
+            let coll = CollectionImpl::from(&collection);
+            if coll.uuid.is_nil() {
+                return Err(dbus::MethodErr::failed(&"Collection not found"));
+            }
+            Some(coll.uuid)
+        };
+        let changed = STORAGE
+            .write()
+            .unwrap()
+            .set_alias(&name, uuid)
+            .map_err(|e| {
+                error!("Error setting alias: {}", e);
+                dbus::MethodErr::from(e)
+            })?;
+        alias_registry::update(&STORAGE.read().unwrap());
+        changed
+            .into_iter()
+            .for_each(ServiceImpl::emit_collection_changed);
+        Ok(())
     }
     fn collections(
         &self,
         ctx: &mut PropContext,
     ) -> Result<Vec<dbus::Path<'static>>, dbus::MethodErr> {
         trace!("collections");
+        let sender = property_caller(ctx);
+        let hidden = visibility::hidden_from(sender.as_deref());
         let cols = CollectionImpl::collections()?
             .iter()
-            .map(|c| c.path().into())
+            .filter(|c| !hidden.contains(&c.uuid))
+            .map(|c| c.listed_path())
             .collect::<Vec<dbus::Path<'static>>>();
         Ok(cols)
     }
 }
 
+impl IoLinuxTksService1 for ServiceImpl {
+    fn test_prompt(
+        &mut self,
+        kind: String,
+        ctx: &mut Context,
+    ) -> Result<Option<bool>, dbus::MethodErr> {
+        trace!("test_prompt {}", kind);
+        // any other client could show dialogs looking like the ones of tks-service
+        let client = TksClientProcess::new(ctx)?.client();
+        if !CLIENT_REGISTRY.lock().unwrap().is_known(&client) {
+            debug!("Client {:?} is not enrolled, no test prompt", client.exe_path);
+            return Err(TksError::PermissionDenied.into());
+        }
+        let action = PromptAction::test_prompt(&kind)?;
+        reply_later(ctx, move || match action.perform() {
+            Err(TksError::PinentryError(pinentry::Error::Cancelled)) => Ok((true,)),
+            result => result.map(|dismissed| (dismissed,)),
+        })?;
+        Ok(None)
+    }
+    fn register_prompter(
+        &mut self,
+        prompter: dbus::Path<'static>,
+        ctx: &mut Context,
+    ) -> Result<(), dbus::MethodErr> {
+        let sender = ctx
+            .message()
+            .sender()
+            .ok_or(dbus::MethodErr::failed("Unknown sender"))?
+            .to_string();
+        trace!("register_prompter {} of {}", prompter, sender);
+        // the prompter gets the passwords typed, and answers the confirmations, the ones letting
+        // the clients in too
+        prompter::check_same_user(&sender)?;
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        match prompter::register(sender, prompter) {
+            true => Ok(()),
+            false => Err(dbus::MethodErr::failed("Another prompter is registered")),
+        }
+    }
+    fn unregister_prompter(&mut self, ctx: &mut Context) -> Result<(), dbus::MethodErr> {
+        let sender = ctx.message().sender().map(|s| s.to_string()).unwrap_or_default();
+        trace!("unregister_prompter of {}", sender);
+        match prompter::unregister(&sender) {
+            true => Ok(()),
+            false => Err(dbus::MethodErr::failed("Only the owner may unregister the prompter")),
+        }
+    }
+    fn merge_collections(
+        &mut self,
+        source: dbus::Path<'static>,
+        destination: dbus::Path<'static>,
+        on_conflict: String,
+        delete_source: bool,
+    ) -> Result<(u32, u32, bool), dbus::MethodErr> {
+        trace!("merge_collections {} into {} ({})", source, destination, on_conflict);
+        let on_conflict = on_conflict.parse::<MergeConflict>()?;
+        let source = CollectionImpl::from(&source);
+        let destination = CollectionImpl::from(&destination);
+        if !source.is_not_default() || !destination.is_not_default() {
+            return Err(dbus::MethodErr::failed(&"Collection not found"));
+        }
+        // the items move out of the source collection
+        acl::check(&source.uuid, None, Access::Read)?;
+        acl::check(&source.uuid, None, Access::Delete)?;
+        acl::check(&destination.uuid, None, Access::Write)?;
+        let mut storage = STORAGE.write().unwrap();
+        let outcome = storage.merge_collections(&source.uuid, &destination.uuid, on_conflict)?;
+        // skipped items would get lost together with the source collection
+        let deleted = delete_source && outcome.skipped.is_empty();
+        if deleted {
+            storage.delete_collection(&source.uuid)?;
+        }
+        drop(storage);
+
+        outcome.replaced.iter().for_each(ItemImpl::unregister);
+        for (source_id, destination_id) in &outcome.moved {
+            ItemImpl::unregister(source_id);
+            ItemImpl::register(destination_id);
+        }
+        CollectionImpl::emit_properties_changed(destination.uuid, &["Items"]);
+        CollectionImpl::emit_sequence_changed(destination.uuid);
+        if deleted {
+            CollectionImpl::unregister(&source.uuid);
+            let collection = source.canonical_path();
+            tokio::spawn(async move {
+                debug!("Sending CollectionDeleted signal");
+                MESSAGE_SENDER.lock().unwrap().send_message(
+                    OrgFreedesktopSecretServiceCollectionDeleted { collection }
+                        .to_emit_message(&ServiceHandle {}.path().into()),
+                );
+            });
+        } else {
+            CollectionImpl::emit_properties_changed(source.uuid, &["Items"]);
+            CollectionImpl::emit_sequence_changed(source.uuid);
+        }
+        Ok((
+            outcome.moved.len() as u32,
+            outcome.skipped.len() as u32,
+            deleted,
+        ))
+    }
+    fn move_item(
+        &mut self,
+        item: dbus::Path<'static>,
+        destination: dbus::Path<'static>,
+        copy: bool,
+        ctx: &mut Context,
+    ) -> Result<dbus::Path<'static>, dbus::MethodErr> {
+        trace!("move_item {} into {} (copy: {})", item, destination, copy);
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let item = ItemImpl::from(&item);
+        let destination = CollectionImpl::from(&destination);
+        if item.is_default() {
+            return Err(dbus::MethodErr::failed(&"Item not found"));
+        }
+        if !destination.is_not_default() {
+            return Err(dbus::MethodErr::failed(&"Collection not found"));
+        }
+        // the secret gets read out of the item, which goes away unless copied
+        let source = &item.item_id;
+        acl::check(&source.collection_uuid, Some(&source.uuid), Access::Read)?;
+        if !copy {
+            acl::check(&source.collection_uuid, Some(&source.uuid), Access::Delete)?;
+        }
+        acl::check(&destination.uuid, None, Access::Write)?;
+        let new_id = STORAGE
+            .write()
+            .unwrap()
+            .move_item(&item.item_id, &destination.uuid, copy)?;
+
+        if !copy {
+            ItemImpl::unregister(&item.item_id);
+            CollectionImpl::emit_properties_changed(item.item_id.collection_uuid, &["Items"]);
+            CollectionImpl::emit_sequence_changed(item.item_id.collection_uuid);
+        }
+        ItemImpl::register(&new_id);
+        CollectionImpl::emit_properties_changed(destination.uuid, &["Items"]);
+        CollectionImpl::emit_sequence_changed(destination.uuid);
+        Ok(ItemImpl::from(&new_id).path)
+    }
+    fn migrate_collection(
+        &mut self,
+        collection: dbus::Path<'static>,
+        backend: String,
+        ctx: &mut Context,
+    ) -> Result<(), dbus::MethodErr> {
+        trace!("migrate_collection {} to {}", collection, backend);
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let collection = CollectionImpl::from(&collection);
+        if !collection.is_not_default() {
+            return Err(dbus::MethodErr::failed(&"Collection not found"));
+        }
+        acl::check(&collection.uuid, None, Access::Write)?;
+        STORAGE
+            .write()
+            .unwrap()
+            .migrate_collection(&collection.uuid, &backend)?;
+        Ok(())
+    }
+    fn find_duplicates(
+        &mut self,
+        collection: dbus::Path<'static>,
+        compare_secrets: bool,
+        ctx: &mut Context,
+    ) -> Result<Vec<Vec<dbus::Path<'static>>>, dbus::MethodErr> {
+        trace!("find_duplicates in {} (secrets: {})", collection, compare_secrets);
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let collection = CollectionImpl::from(&collection);
+        if !collection.is_not_default() {
+            return Err(dbus::MethodErr::failed(&"Collection not found"));
+        }
+        // the groups then tell which items have the same secret
+        if compare_secrets {
+            acl::check(&collection.uuid, None, Access::Read)?;
+        }
+        let groups = STORAGE
+            .read()
+            .unwrap()
+            .find_duplicates(&collection.uuid, compare_secrets)?;
+        Ok(groups
+            .iter()
+            .map(|g| g.iter().map(|id| ItemImpl::from(id).path).collect())
+            .collect())
+    }
+    fn delete_items(
+        &mut self,
+        items: Vec<dbus::Path<'static>>,
+        ctx: &mut Context,
+    ) -> Result<(), dbus::MethodErr> {
+        trace!("delete_items {:?}", items);
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let items: Vec<ItemImpl> = items.iter().map(ItemImpl::from).collect();
+        if items.iter().any(|i| i.is_default()) {
+            return Err(dbus::MethodErr::failed(&"Item not found"));
+        }
+        let item_ids: Vec<_> = items.iter().map(|i| i.item_id.clone()).collect();
+        // none of the items gets deleted unless all of them may be
+        for id in &item_ids {
+            acl::check(&id.collection_uuid, Some(&id.uuid), Access::Delete).inspect_err(|_| {
+                audit::record(AuditEvent::ItemDelete, Outcome::Failure, vec![id.uuid]);
+            })?;
+        }
+        let result = STORAGE.write().unwrap().delete_items(&item_ids);
+        let uuids = item_ids.iter().map(|id| id.uuid).collect();
+        audit::record(AuditEvent::ItemDelete, (&result).into(), uuids);
+        result?;
+
+        item_ids.iter().for_each(ItemImpl::unregister);
+        let mut changed: Vec<Uuid> = item_ids.iter().map(|id| id.collection_uuid).collect();
+        changed.sort();
+        changed.dedup();
+        for uuid in changed {
+            CollectionImpl::emit_properties_changed(uuid, &["Items"]);
+            CollectionImpl::emit_sequence_changed(uuid);
+        }
+        Ok(())
+    }
+    fn disk_usage(&mut self) -> Result<(u64, u64), dbus::MethodErr> {
+        trace!("disk_usage");
+        let usage = STORAGE.read().unwrap().disk_usage()?;
+        Ok((usage.used, usage.available.unwrap_or(0)))
+    }
+    fn get_capabilities(&mut self) -> Result<(String, bool, bool, bool, u64), dbus::MethodErr> {
+        trace!("get_capabilities");
+        let c = STORAGE.read().unwrap().capabilities();
+        Ok((
+            c.backend.to_string(),
+            c.create_collection,
+            c.multiple_collections,
+            c.binary_secrets,
+            c.max_secret_size.unwrap_or(0),
+        ))
+    }
+    fn create_backup(
+        &mut self,
+        passphrase: String,
+        ctx: &mut Context,
+    ) -> Result<Option<Vec<u8>>, dbus::MethodErr> {
+        trace!("create_backup");
+        // the archive holds the secrets of all the unlocked collections, whatever their ACLs, so
+        // the user confirms each export
+        let process = CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let client = audit::caller();
+        let passphrase = SecretString::new(passphrase);
+        reply_later(ctx, move || {
+            let allowed = prompter::current().confirm(
+                "Export",
+                "Cancel",
+                &format!(
+                    "{} asks to export the secrets of all the unlocked collections to a \
+                    backup. Allow it?",
+                    process.describe()
+                ),
+            )?;
+            let storage = STORAGE.read().unwrap();
+            let result = match allowed {
+                true => storage.create_backup(&passphrase),
+                false => Err(TksError::PermissionDenied),
+            };
+            let uuids = storage.collections.iter().map(|c| c.uuid).collect();
+            audit::record_for(client, AuditEvent::BackupCreate, (&result).into(), uuids);
+            Ok((result?,))
+        })?;
+        Ok(None)
+    }
+    fn restore_backup(
+        &mut self,
+        archive: Vec<u8>,
+        passphrase: String,
+        mode: String,
+        ctx: &mut Context,
+    ) -> Result<(u32, u32, u32, u32), dbus::MethodErr> {
+        trace!("restore_backup ({})", mode);
+        // in replace mode, the archive overwrites the items
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let mode = mode.parse::<RestoreMode>()?;
+        let passphrase = SecretString::new(passphrase);
+        let result = STORAGE
+            .write()
+            .unwrap()
+            .restore_backup(&archive, &passphrase, mode);
+        let uuids = match &result {
+            Ok(outcome) => outcome.created.clone(),
+            Err(_) => Vec::new(),
+        };
+        audit::record(AuditEvent::BackupRestore, (&result).into(), uuids);
+        let outcome = result?;
+
+        for uuid in &outcome.created {
+            let path = CollectionImpl::from(uuid).canonical_path();
+            tokio::spawn(async move {
+                debug!("Sending CollectionCreated signal");
+                MESSAGE_SENDER.lock().unwrap().send_message(
+                    OrgFreedesktopSecretServiceCollectionCreated {
+                        collection: path.clone(),
+                    }
+                    .to_emit_message(&path),
+                );
+            });
+        }
+        alias_registry::update(&STORAGE.read().unwrap());
+        outcome.removed.iter().for_each(ItemImpl::unregister);
+        outcome.added.iter().for_each(ItemImpl::register);
+        let mut changed: Vec<Uuid> = outcome
+            .added
+            .iter()
+            .chain(outcome.removed.iter())
+            .map(|id| id.collection_uuid)
+            .collect();
+        changed.sort();
+        changed.dedup();
+        for uuid in changed {
+            CollectionImpl::emit_properties_changed(uuid, &["Items"]);
+            CollectionImpl::emit_sequence_changed(uuid);
+        }
+        Ok((
+            outcome.created.len() as u32,
+            outcome.added.len() as u32,
+            outcome.removed.len() as u32,
+            outcome.skipped as u32,
+        ))
+    }
+    fn change_password(
+        &mut self,
+        collection: dbus::Path<'static>,
+        old_password: String,
+        new_password: String,
+    ) -> Result<Vec<dbus::Path<'static>>, dbus::MethodErr> {
+        trace!("change_password of {}", collection);
+        let collection = CollectionImpl::from(&collection);
+        if !collection.is_not_default() {
+            return Err(dbus::MethodErr::failed(&"Collection not found"));
+        }
+        let old_password = SecretString::new(old_password);
+        let new_password = SecretString::new(new_password);
+        let result = STORAGE.write().unwrap().change_password(
+            &collection.uuid,
+            &old_password,
+            &new_password,
+        );
+        let uuids = match &result {
+            Ok(sharing) => sharing.clone(),
+            Err(_) => vec![collection.uuid],
+        };
+        audit::record(AuditEvent::PasswordChange, (&result).into(), uuids);
+        Ok(result?
+            .iter()
+            .map(|uuid| CollectionImpl::from(uuid).canonical_path())
+            .collect())
+    }
+    fn check_storage(
+        &mut self,
+        repair: bool,
+        ctx: &mut Context,
+    ) -> Result<Vec<(String, String, String, bool)>, dbus::MethodErr> {
+        trace!("check_storage (repair: {})", repair);
+        // repairing moves files away and drops items
+        if repair {
+            CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        }
+        let report = STORAGE.write().unwrap().fsck(repair)?;
+        if !report.removed.is_empty() {
+            let uuids = report.removed.iter().map(|id| id.uuid).collect();
+            audit::record(AuditEvent::ItemDelete, Outcome::Success, uuids);
+            report.removed.iter().for_each(ItemImpl::unregister);
+            let mut changed: Vec<Uuid> =
+                report.removed.iter().map(|id| id.collection_uuid).collect();
+            changed.sort();
+            changed.dedup();
+            for uuid in changed {
+                CollectionImpl::emit_properties_changed(uuid, &["Items"]);
+                CollectionImpl::emit_sequence_changed(uuid);
+            }
+        }
+        Ok(report
+            .findings
+            .into_iter()
+            .map(|f| (f.problem.to_string(), f.subject, f.detail, f.repaired))
+            .collect())
+    }
+    fn export_audit_log(&mut self, since: u64) -> Result<Vec<String>, dbus::MethodErr> {
+        trace!("export_audit_log since {}", since);
+        let path = SETTINGS.lock().unwrap().audit_path()?;
+        Ok(audit::read_since(std::path::Path::new(&path), since)?)
+    }
+    fn list_denied_clients(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<Vec<(String, u32, u64)>, dbus::MethodErr> {
+        trace!("list_denied_clients");
+        let mut registry = CLIENT_REGISTRY.lock().unwrap();
+        registry.enrolled_caller(ctx)?;
+        let denials = registry.denials.list();
+        drop(registry);
+        Ok(denials
+            .into_iter()
+            .map(|(exe_path, denial)| {
+                let until = denial.until.map_or(0, |until| {
+                    until.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+                });
+                (exe_path.to_string_lossy().into(), denial.count, until)
+            })
+            .collect())
+    }
+    fn deny_client(&mut self, exe: String, ctx: &mut Context) -> Result<(), dbus::MethodErr> {
+        trace!("deny_client {}", exe);
+        let mut registry = CLIENT_REGISTRY.lock().unwrap();
+        registry.enrolled_caller(ctx)?;
+        registry.denials.deny_permanently(OsStr::new(&exe));
+        Ok(())
+    }
+    fn forget_denied_client(
+        &mut self,
+        exe: String,
+        ctx: &mut Context,
+    ) -> Result<bool, dbus::MethodErr> {
+        trace!("forget_denied_client {}", exe);
+        let mut registry = CLIENT_REGISTRY.lock().unwrap();
+        registry.enrolled_caller(ctx)?;
+        Ok(registry.denials.forget(OsStr::new(&exe)))
+    }
+    fn list_clients(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<Vec<(String, String, String)>, dbus::MethodErr> {
+        trace!("list_clients");
+        CLIENT_REGISTRY.lock().unwrap().enrolled_caller(ctx)?;
+        let clients = CLIENT_REGISTRY.lock().unwrap().list()?;
+        Ok(clients
+            .into_iter()
+            .map(|c| (c.exe_path.to_string_lossy().into(), c.name, c.exe_sha256))
+            .collect())
+    }
+    fn revoke_client(&mut self, exe: String, ctx: &mut Context) -> Result<bool, dbus::MethodErr> {
+        trace!("revoke_client {}", exe);
+        let mut registry = CLIENT_REGISTRY.lock().unwrap();
+        registry.enrolled_caller(ctx)?;
+        Ok(registry.revoke(OsStr::new(&exe))?)
+    }
+    fn get_diagnostics(&mut self) -> Result<HashMap<String, u64>, dbus::MethodErr> {
+        trace!("get_diagnostics");
+        Ok(diagnostics::counters()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect())
+    }
+    fn reload_config(&mut self) -> Result<Vec<String>, dbus::MethodErr> {
+        trace!("reload_config");
+        Ok(reload::reload()?.into_iter().map(String::from).collect())
+    }
+    fn forget_keys(&mut self) -> Result<(), dbus::MethodErr> {
+        trace!("forget_keys");
+        STORAGE.write().unwrap().forget_keys()?;
+        Ok(())
+    }
+    fn register_schema(
+        &mut self,
+        name: String,
+        attributes: HashMap<String, String>,
+    ) -> Result<(), dbus::MethodErr> {
+        trace!("register_schema {} {:?}", name, attributes);
+        let attributes = attributes
+            .into_iter()
+            .map(|(a, t)| Ok((a, t.parse()?)))
+            .collect::<Result<HashMap<_, _>, TksError>>()?;
+        let configured = SETTINGS.lock().unwrap().schemas.clone();
+        Ok(item_schemas::register(&configured, ItemSchema { name, attributes })?)
+    }
+    fn list_schemas(
+        &mut self,
+    ) -> Result<HashMap<String, HashMap<String, String>>, dbus::MethodErr> {
+        trace!("list_schemas");
+        let configured = SETTINGS.lock().unwrap().schemas.clone();
+        Ok(item_schemas::all(&configured)
+            .into_iter()
+            .map(|s| {
+                let attributes = s.attributes.iter();
+                let attributes = attributes.map(|(a, t)| (a.clone(), t.as_str().to_string()));
+                (s.name, attributes.collect())
+            })
+            .collect())
+    }
+    fn pending_passphrases(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<Vec<(u32, String, String, bool)>, dbus::MethodErr> {
+        let sender = ctx.message().sender().map(|s| s.to_string()).unwrap_or_default();
+        trace!("pending_passphrases of {}", sender);
+        prompter::check_same_user(&sender)?;
+        Ok(prompter::pending_passphrases())
+    }
+    fn answer_passphrase(
+        &mut self,
+        id: u32,
+        cancelled: bool,
+        passphrase: String,
+        ctx: &mut Context,
+    ) -> Result<(), dbus::MethodErr> {
+        let sender = ctx.message().sender().map(|s| s.to_string()).unwrap_or_default();
+        trace!("answer_passphrase {} of {}", id, sender);
+        prompter::check_same_user(&sender)?;
+        let passphrase = (!cancelled).then(|| SecretString::new(passphrase));
+        Ok(prompter::answer_passphrase(id, passphrase)?)
+    }
+}
+
+impl IoLinuxTksSearch1 for ServiceImpl {
+    fn search_items(
+        &mut self,
+        ctx: &mut Context,
+        queries: Vec<(String, String, String)>,
+    ) -> Result<(Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>), dbus::MethodErr> {
+        trace!("search_items {:?}", queries);
+        let queries = queries
+            .iter()
+            .map(|(name, operator, value)| AttributeQuery::new(name, operator, value))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut unlocked = Vec::new();
+        let mut locked = Vec::new();
+
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let hidden = visibility::hidden_from(sender.as_deref());
+        STORAGE
+            .read()
+            .unwrap()
+            .query_items(&queries)
+            .into_iter()
+            .filter(|i| !hidden.contains(&i.id.collection_uuid))
+            .for_each(|i| match i.locked {
+                true => locked.push(ItemImpl::from(i).into()),
+                false => unlocked.push(ItemImpl::from(i).into()),
+            });
+        debug!("search_items unlocked: {:?}", unlocked);
+        debug!("search_items locked: {:?}", locked);
+        Ok((unlocked, locked))
+    }
+    fn search_text(
+        &mut self,
+        ctx: &mut Context,
+        text: String,
+        regex: bool,
+    ) -> Result<(Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>), dbus::MethodErr> {
+        trace!("search_text {:?} {}", text, regex);
+        let matcher = AttributeMatch::text(&text, regex)?;
+        let mut unlocked = Vec::new();
+        let mut locked = Vec::new();
+
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let hidden = visibility::hidden_from(sender.as_deref());
+        STORAGE
+            .read()
+            .unwrap()
+            .search_text(&matcher)
+            .into_iter()
+            .filter(|i| !hidden.contains(&i.id.collection_uuid))
+            .for_each(|i| match i.locked {
+                true => locked.push(ItemImpl::from(i).into()),
+                false => unlocked.push(ItemImpl::from(i).into()),
+            });
+        debug!("search_text unlocked: {:?}", unlocked);
+        debug!("search_text locked: {:?}", locked);
+        Ok((unlocked, locked))
+    }
+}
+
+impl IoLinuxTksOAuth1 for ServiceImpl {
+    fn find_tokens(
+        &mut self,
+        ctx: &mut Context,
+        issuer: String,
+        account: String,
+        scopes: Vec<String>,
+        min_validity: u64,
+    ) -> Result<(Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>), dbus::MethodErr> {
+        trace!("find_tokens {} {} {:?} {}", issuer, account, scopes, min_validity);
+        let query = TokenQuery {
+            issuer,
+            account,
+            scopes,
+            min_validity,
+        };
+        let mut unlocked = Vec::new();
+        let mut locked = Vec::new();
+
+        let sender = ctx.message().sender().map(|s| s.to_string());
+        let hidden = visibility::hidden_from(sender.as_deref());
+        STORAGE
+            .read()
+            .unwrap()
+            .find_oauth_tokens(&query, expiry::now())
+            .into_iter()
+            .filter(|i| !hidden.contains(&i.id.collection_uuid))
+            .for_each(|i| match i.locked {
+                true => locked.push(ItemImpl::from(i).into()),
+                false => unlocked.push(ItemImpl::from(i).into()),
+            });
+        debug!("find_tokens unlocked: {:?}", unlocked);
+        debug!("find_tokens locked: {:?}", locked);
+        Ok((unlocked, locked))
+    }
+}
+
 impl ServiceImpl {
     pub fn new() -> ServiceImpl {
         ServiceImpl {}
     }
+    /// Notifies the clients about label, alias or lock state changes of a collection
+    pub fn emit_collection_changed(collection_uuid: Uuid) {
+        tokio::spawn(async move {
+            debug!("Sending CollectionChanged signal");
+            // same path as the one listed by the Collections property
+            let collection = CollectionImpl::from(&collection_uuid).listed_path();
+            MESSAGE_SENDER.lock().unwrap().send_message(
+                OrgFreedesktopSecretServiceCollectionChanged { collection }
+                .to_emit_message(&ServiceHandle {}.path().into()),
+            );
+        });
+    }
+    /// Action of the prompt returned by CreateCollection for a name already taken
+    fn reuse_collection(param: &ConfirmationMessageActionParam) -> Result<bool, TksError> {
+        let ConfirmationMessageActionParam::ReuseCollection(uuid, alias, properties) = param else {
+            error!("Unexpected confirmation message param: {:?}", param);
+            return Ok(true);
+        };
+        let mut storage = STORAGE.write()?;
+        storage.modify_collection(uuid, |collection| {
+            collection.set_properties(properties);
+            Ok(())
+        })?;
+        let mut changed = vec![*uuid];
+        if !alias.is_empty() {
+            changed.extend(storage.set_alias(alias, Some(*uuid))?);
+            alias_registry::update(&storage);
+        }
+        CollectionImpl::emit_properties_changed(*uuid, &["Label"]);
+        CollectionImpl::emit_sequence_changed(*uuid);
+        changed.sort();
+        changed.dedup();
+        changed
+            .into_iter()
+            .for_each(ServiceImpl::emit_collection_changed);
+        Ok(false)
+    }
     pub fn get_dbus_handle(&self) -> ServiceHandle {
         ServiceHandle {}
     }
     pub fn register_collections() -> Result<(), TksError> {
-        let collections = &STORAGE.lock()?.collections;
+        let collections = &STORAGE.read()?.collections;
         collections.iter().for_each(|c| {
             // constructing the CollectionHandle will register the collection
             let _ = CollectionImpl::from(c);
@@ -1,6 +1,10 @@
+use crate::settings::SETTINGS;
+use crate::storage::secure_buffer::SecureBuffer;
 use crate::tks_dbus::fdo::session::OrgFreedesktopSecretSession;
+use crate::tks_dbus::tks::session::IoLinuxTksSession1;
 use crate::tks_dbus::DBusHandlePath::SinglePath;
 use crate::tks_dbus::CROSSROADS;
+use crate::tks_dbus::object_manager;
 use crate::tks_dbus::{DBusHandle, DBusHandlePath};
 use crate::tks_error::TksError;
 use dbus::strings::BusName;
@@ -13,15 +17,24 @@ use openssl::md::Md;
 use openssl::pkey::Id;
 use openssl::pkey_ctx::{HkdfMode, PkeyCtx};
 use openssl::symm::{decrypt, encrypt, Cipher};
+use secrecy::zeroize::Zeroize;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use vec_map::VecMap;
 
 pub struct Session {
     pub id: usize,
     sender: String,
     algorithm: String,
-    aes_key_bytes: Option<Vec<u8>>,
+    aes_key_bytes: Option<SecureBuffer>,
+    created: Instant,
+    /// When the session got opened, for the Created property
+    opened: SystemTime,
+    uses: AtomicU64,
+    max_age: Option<Duration>,
+    max_uses: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -45,14 +58,49 @@ impl OrgFreedesktopSecretSession for SessionImpl {
             .lock()
             .unwrap()
             .close_session(self.id, sender)?;
-        CROSSROADS
-            .lock()
-            .unwrap()
-            .remove::<SessionImpl>(&self.path().into());
+        object_manager::remove::<SessionImpl>(&mut CROSSROADS.lock().unwrap(), &self.path().into());
         Ok(())
     }
 }
 
+impl SessionImpl {
+    /// The id of the session at the path a client passed along with a secret
+    pub fn id_of(path: &dbus::Path) -> Result<usize, dbus::MethodErr> {
+        path.split('/').next_back().unwrap().parse::<usize>().map_err(|_| {
+            error!("Invalid session ID");
+            dbus::MethodErr::failed(&"Invalid session ID")
+        })
+    }
+    fn with_session<F, T>(&self, f: F) -> Result<T, dbus::MethodErr>
+    where
+        F: FnOnce(&Session) -> T,
+    {
+        let sm = SESSION_MANAGER.lock().unwrap();
+        let session = sm
+            .sessions
+            .get(self.id)
+            .ok_or_else(|| dbus::MethodErr::failed("Session closed"))?;
+        Ok(f(session))
+    }
+}
+
+impl IoLinuxTksSession1 for SessionImpl {
+    fn algorithm(&self) -> Result<String, dbus::MethodErr> {
+        self.with_session(|s| s.algorithm.clone())
+    }
+    fn created(&self) -> Result<u64, dbus::MethodErr> {
+        self.with_session(|s| {
+            s.opened
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs()
+        })
+    }
+    fn peer_name(&self) -> Result<String, dbus::MethodErr> {
+        self.with_session(|s| s.sender.clone())
+    }
+}
+
 impl DBusHandle for SessionImpl {
     fn path(&self) -> DBusHandlePath {
         SinglePath(format!("/org/freedesktop/secrets/session/{}", self.id).into())
@@ -100,6 +148,9 @@ impl SessionManager {
             self.next_session_id += 1;
             let mut session =
                 Session::new(session_num, algorithm.clone(), sender.unwrap().to_string());
+            let limits = SETTINGS.lock().unwrap().session.clone();
+            session.max_age = limits.max_age.map(Duration::from_secs);
+            session.max_uses = limits.max_uses;
             output = session.get_shared_secret(input)?;
             self.sessions.insert(session_num, session);
             debug!("Created session {}", session_num);
@@ -107,6 +158,39 @@ impl SessionManager {
         };
         Ok((sess_id, output))
     }
+    /// The session, provided it belongs to the client; sessions can't be used by the others
+    pub fn get_session(&self, id: usize, sender: &str) -> Result<&Session, TksError> {
+        let session = self.sessions.get(id).ok_or_else(|| {
+            error!("Session {} not found", id);
+            TksError::NotFound(Some("Session not found".to_string()))
+        })?;
+        if session.sender != sender {
+            error!("Sender {} attempted to use session {} of {}", sender, id, session.sender);
+            return Err(TksError::PermissionDenied);
+        }
+        Ok(session)
+    }
+    /// Closes the sessions of a client which left the bus, along with their objects
+    pub fn close_sessions_of(&mut self, bus_name: &str) {
+        let orphans: Vec<usize> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.sender == bus_name)
+            .map(|(id, _)| id)
+            .collect();
+        if orphans.is_empty() {
+            return;
+        }
+        let paths: Vec<dbus::Path<'static>> = orphans
+            .iter()
+            .map(|id| {
+                debug!("{} left, closing its session {}", bus_name, id);
+                self.sessions.remove(*id);
+                SessionImpl { id: *id }.path().into()
+            })
+            .collect();
+        object_manager::unregister::<SessionImpl>(paths);
+    }
     fn close_session(&mut self, id: usize, sender: String) -> Result<(), TksError> {
         trace!("close_session {} from sender {}", id, sender);
         let session = self
@@ -143,8 +227,33 @@ impl Session {
             sender,
             algorithm,
             aes_key_bytes: None,
+            created: Instant::now(),
+            opened: SystemTime::now(),
+            uses: AtomicU64::new(0),
+            max_age: None,
+            max_uses: None,
         }
     }
+    /// Whether the secrets go through unencrypted
+    pub fn is_plain(&self) -> bool {
+        self.algorithm == PLAIN
+    }
+    /// Counts one more use of the transport key, failing once the session got too old or too
+    /// much used; clients should then negotiate a new key by opening a new session
+    fn use_key(&self) -> Result<(), TksError> {
+        if self.aes_key_bytes.is_none() {
+            // nothing to protect for plain sessions
+            return Ok(());
+        }
+        let uses = self.uses.fetch_add(1, Ordering::Relaxed) + 1;
+        let too_old = self.max_age.is_some_and(|a| self.created.elapsed() > a);
+        let too_used = self.max_uses.is_some_and(|m| uses > m);
+        if too_old || too_used {
+            debug!("Session {} expired after {} uses", self.id, uses - 1);
+            return Err(TksError::SessionExpired);
+        }
+        Ok(())
+    }
     pub fn get_shared_secret(
         &mut self,
         input: Option<&Vec<u8>>,
@@ -168,7 +277,7 @@ impl Session {
                     let pub_key = priv_key.public_key();
 
                     let client_pub_key = BigNum::from_slice(input.as_slice())?;
-                    let shared_secret = priv_key.compute_key(&client_pub_key)?;
+                    let mut shared_secret = priv_key.compute_key(&client_pub_key)?;
 
                     let mut derive_key = PkeyCtx::new_id(Id::HKDF)?;
                     derive_key.derive_init()?;
@@ -177,9 +286,10 @@ impl Session {
                     derive_key.set_hkdf_salt(&salt)?;
                     derive_key.set_hkdf_md(Md::sha256())?;
                     derive_key.set_hkdf_key(shared_secret.as_slice())?;
-                    let mut aes_bytes = vec![0u8; 128];
-                    derive_key.derive(Some(aes_bytes.as_mut_slice()))?;
-                    self.aes_key_bytes = Some(aes_bytes[..16].to_owned());
+                    let mut aes_bytes = SecureBuffer::new(128);
+                    derive_key.derive(Some(&mut aes_bytes))?;
+                    shared_secret.zeroize();
+                    self.aes_key_bytes = Some(SecureBuffer::from(&aes_bytes[..16]));
 
                     Ok(Some(pub_key.to_vec()))
                 } else {
@@ -226,6 +336,7 @@ impl Session {
         if self.sender != sender {
             return Err(TksError::PermissionDenied);
         }
+        self.use_key()?;
         match self.algorithm.as_str() {
             PLAIN => Ok(input.clone()),
             DH_AES => self
@@ -235,28 +346,34 @@ impl Session {
                     error!("Cannot decrypt: No key");
                     TksError::CryptoError
                 })
-                .map(|key| {
-                    decrypt(Cipher::aes_128_cbc(), key, Some(iv), input).map_err(|e| {
+                .and_then(|key| {
+                    let cipher = Cipher::aes_128_cbc();
+                    // openssl asserts the length of the IV, the clients choose it
+                    if cipher.iv_len() != Some(iv.len()) {
+                        error!("Cannot decrypt: the IV has {} bytes", iv.len());
+                        return Err(TksError::CryptoError);
+                    }
+                    decrypt(cipher, key, Some(iv), input).map_err(|e| {
                         error!("openssl error: {:?}", e);
                         TksError::CryptoError
                     })
-                })?,
+                }),
             _ => {
                 error!("Unsupported algorithm: {}", self.algorithm);
                 Err(TksError::ParameterError)
             }
         }
     }
-    pub fn encrypt(&self, input: &Vec<u8>, sender: String) -> Result<(Vec<u8>, Vec<u8>), TksError> {
+    pub fn encrypt(&self, input: &[u8], sender: String) -> Result<(Vec<u8>, Vec<u8>), TksError> {
         trace!("Encrypting secret for session {}", self.id);
         if self.sender != sender {
             return Err(TksError::PermissionDenied);
         }
+        self.use_key()?;
         match self.algorithm.as_str() {
-            PLAIN => Ok(([].to_vec(), input.clone())),
+            PLAIN => Ok(([].to_vec(), input.to_vec())),
             DH_AES => {
                 let iv = rand::random::<[u8; 16]>().to_vec();
-                let input = input.clone();
 
                 Ok((
                     iv.clone(),
@@ -264,7 +381,7 @@ impl Session {
                         Cipher::aes_128_cbc(),
                         &self.aes_key_bytes.as_ref().unwrap(),
                         Some(&iv),
-                        &input,
+                        input,
                     )?,
                 ))
             }
@@ -0,0 +1,42 @@
+// This code was generated from io.linux_tks.Acl1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksAcl1 {
+    fn restricted(&self) -> Result<bool, dbus::MethodErr>;
+    fn inherited(&self) -> Result<bool, dbus::MethodErr>;
+    fn get_acl(&mut self) -> Result<Vec<(String, Vec<String>)>, dbus::MethodErr>;
+    fn set_restricted(&mut self, restricted: bool) -> Result<(), dbus::MethodErr>;
+    fn grant(&mut self, exe: String, access: Vec<String>) -> Result<(), dbus::MethodErr>;
+    fn revoke(&mut self, exe: String, access: Vec<String>) -> Result<bool, dbus::MethodErr>;
+}
+
+pub fn register_io_linux_tks_acl1<T>(cr: &mut crossroads::Crossroads) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksAcl1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Acl1", |b| {
+        b.property::<bool, _>("Restricted").get(|_, t: &mut T| t.restricted());
+        b.property::<bool, _>("Inherited").get(|_, t| t.inherited());
+        b.method("GetAcl", (), ("entries",), |_, t: &mut T, ()| {
+            t.get_acl().map(|x| (x,))
+        });
+        b.method(
+            "SetRestricted",
+            ("restricted",),
+            (),
+            |_, t: &mut T, (restricted,)| t.set_restricted(restricted),
+        );
+        b.method("Grant", ("exe", "access"), (), |_, t: &mut T, (exe, access)| {
+            t.grant(exe, access)
+        });
+        b.method(
+            "Revoke",
+            ("exe", "access"),
+            ("revoked",),
+            |_, t: &mut T, (exe, access)| t.revoke(exe, access).map(|x| (x,)),
+        );
+    })
+}
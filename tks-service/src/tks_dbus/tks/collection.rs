@@ -0,0 +1,104 @@
+// This code was generated from io.linux_tks.Collection1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksCollection1 {
+    fn sequence(&self) -> Result<u64, dbus::MethodErr>;
+    fn properties(
+        &self,
+    ) -> Result<::std::collections::HashMap<String, String>, dbus::MethodErr>;
+    fn owner(&self) -> Result<String, dbus::MethodErr>;
+    fn folders(&self) -> Result<Vec<String>, dbus::MethodErr>;
+    fn list_folder(
+        &mut self,
+        folder: String,
+    ) -> Result<(Vec<String>, Vec<dbus::Path<'static>>), dbus::MethodErr>;
+    fn set_owner(
+        &mut self,
+        lock_on_exit: bool,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(), dbus::MethodErr>;
+    fn release_owner(&mut self, ctx: &mut crossroads::Context) -> Result<(), dbus::MethodErr>;
+    fn get_history(&mut self) -> Result<Vec<(u64, String, String, String)>, dbus::MethodErr>;
+    fn list_trash(&mut self) -> Result<Vec<(String, String, u64)>, dbus::MethodErr>;
+    fn restore_item(&mut self, uuid: String) -> Result<dbus::Path<'static>, dbus::MethodErr>;
+    fn purge_trash(&mut self, uuids: Vec<String>) -> Result<u32, dbus::MethodErr>;
+    fn begin_batch(&mut self, timeout: u32) -> Result<(), dbus::MethodErr>;
+    fn end_batch(&mut self) -> Result<(), dbus::MethodErr>;
+}
+
+#[derive(Debug)]
+pub struct IoLinuxTksCollection1SequenceChanged {
+    pub sequence: u64,
+}
+
+impl arg::AppendAll for IoLinuxTksCollection1SequenceChanged {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.sequence, i);
+    }
+}
+
+impl arg::ReadAll for IoLinuxTksCollection1SequenceChanged {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(IoLinuxTksCollection1SequenceChanged {
+            sequence: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for IoLinuxTksCollection1SequenceChanged {
+    const NAME: &'static str = "SequenceChanged";
+    const INTERFACE: &'static str = "io.linux_tks.Collection1";
+}
+
+pub fn register_io_linux_tks_collection1<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksCollection1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Collection1", |b| {
+        b.signal::<(u64,), _>("SequenceChanged", ("sequence",));
+        b.property::<u64, _>("Sequence").get(|_, t: &mut T| t.sequence());
+        b.property::<::std::collections::HashMap<String, String>, _>("Properties")
+            .get(|_, t| t.properties());
+        b.property::<String, _>("Owner").get(|_, t| t.owner());
+        b.property::<Vec<String>, _>("Folders").get(|_, t| t.folders());
+        b.method(
+            "ListFolder",
+            ("folder",),
+            ("subfolders", "items"),
+            |_, t: &mut T, (folder,)| t.list_folder(folder),
+        );
+        b.method(
+            "SetOwner",
+            ("lock_on_exit",),
+            (),
+            |ctx, t: &mut T, (lock_on_exit,)| t.set_owner(lock_on_exit, ctx),
+        );
+        b.method("ReleaseOwner", (), (), |ctx, t: &mut T, ()| {
+            t.release_owner(ctx)
+        });
+        b.method("GetHistory", (), ("entries",), |_, t: &mut T, ()| {
+            t.get_history().map(|x| (x,))
+        });
+        b.method("ListTrash", (), ("items",), |_, t: &mut T, ()| {
+            t.list_trash().map(|x| (x,))
+        });
+        b.method("RestoreItem", ("uuid",), ("item",), |_, t: &mut T, (uuid,)| {
+            t.restore_item(uuid).map(|x| (x,))
+        });
+        b.method("PurgeTrash", ("uuids",), ("purged",), |_, t: &mut T, (uuids,)| {
+            t.purge_trash(uuids).map(|x| (x,))
+        });
+        b.method(
+            "BeginBatch",
+            ("timeout",),
+            (),
+            |_, t: &mut T, (timeout,)| t.begin_batch(timeout),
+        );
+        b.method("EndBatch", (), (), |_, t: &mut T, ()| t.end_batch());
+    })
+}
@@ -0,0 +1,24 @@
+// This code was generated from io.linux_tks.Item1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksItem1 {
+    fn get_versions(&mut self) -> Result<Vec<(u64, String, u64)>, dbus::MethodErr>;
+    fn rollback(&mut self, index: u32) -> Result<(), dbus::MethodErr>;
+}
+
+pub fn register_io_linux_tks_item1<T>(cr: &mut crossroads::Crossroads) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksItem1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Item1", |b| {
+        b.method("GetVersions", (), ("versions",), |_, t: &mut T, ()| {
+            t.get_versions().map(|x| (x,))
+        });
+        b.method("Rollback", ("index",), (), |_, t: &mut T, (index,)| {
+            t.rollback(index)
+        });
+    })
+}
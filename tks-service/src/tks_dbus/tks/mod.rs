@@ -0,0 +1,8 @@
+pub mod acl;
+pub mod collection;
+pub mod item;
+pub mod oauth;
+pub mod prompt;
+pub mod search;
+pub mod service;
+pub mod session;
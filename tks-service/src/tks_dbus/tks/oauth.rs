@@ -0,0 +1,32 @@
+// This code was generated from io.linux_tks.OAuth1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksOAuth1 {
+    fn find_tokens(
+        &mut self,
+        ctx: &mut crossroads::Context,
+        issuer: String,
+        account: String,
+        scopes: Vec<String>,
+        min_validity: u64,
+    ) -> Result<(Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>), dbus::MethodErr>;
+}
+
+pub fn register_io_linux_tks_oauth1<T>(cr: &mut crossroads::Crossroads) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksOAuth1 + Send + 'static,
+{
+    cr.register("io.linux_tks.OAuth1", |b| {
+        b.method(
+            "FindTokens",
+            ("issuer", "account", "scopes", "min_validity"),
+            ("unlocked", "locked"),
+            |ctx, t: &mut T, (issuer, account, scopes, min_validity)| {
+                t.find_tokens(ctx, issuer, account, scopes, min_validity)
+            },
+        );
+    })
+}
@@ -0,0 +1,45 @@
+// This code was generated from io.linux_tks.Prompt1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksPrompt1 {}
+
+#[derive(Debug)]
+pub struct IoLinuxTksPrompt1PromptProgress {
+    pub current: u32,
+    pub total: u32,
+}
+
+impl arg::AppendAll for IoLinuxTksPrompt1PromptProgress {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.current, i);
+        arg::RefArg::append(&self.total, i);
+    }
+}
+
+impl arg::ReadAll for IoLinuxTksPrompt1PromptProgress {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(IoLinuxTksPrompt1PromptProgress {
+            current: i.read()?,
+            total: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for IoLinuxTksPrompt1PromptProgress {
+    const NAME: &'static str = "PromptProgress";
+    const INTERFACE: &'static str = "io.linux_tks.Prompt1";
+}
+
+pub fn register_io_linux_tks_prompt1<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksPrompt1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Prompt1", |b| {
+        b.signal::<(u32, u32), _>("PromptProgress", ("current", "total"));
+    })
+}
@@ -0,0 +1,41 @@
+// This code was generated from io.linux_tks.Search1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksSearch1 {
+    fn search_items(
+        &mut self,
+        ctx: &mut crossroads::Context,
+        queries: Vec<(String, String, String)>,
+    ) -> Result<(Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>), dbus::MethodErr>;
+    fn search_text(
+        &mut self,
+        ctx: &mut crossroads::Context,
+        text: String,
+        regex: bool,
+    ) -> Result<(Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>), dbus::MethodErr>;
+}
+
+pub fn register_io_linux_tks_search1<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksSearch1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Search1", |b| {
+        b.method(
+            "SearchItems",
+            ("queries",),
+            ("unlocked", "locked"),
+            |ctx, t: &mut T, (queries,)| t.search_items(ctx, queries),
+        );
+        b.method(
+            "SearchText",
+            ("text", "regex"),
+            ("unlocked", "locked"),
+            |ctx, t: &mut T, (text, regex)| t.search_text(ctx, text, regex),
+        );
+    })
+}
@@ -0,0 +1,344 @@
+// This code was generated from io.linux_tks.Service1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs,
+// then edited: TestPrompt and CreateBackup may reply once the user answered their dialog.
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksService1 {
+    /// `None` when the reply gets sent later on, see [crate::tks_dbus::reply_later]
+    fn test_prompt(
+        &mut self,
+        kind: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Option<bool>, dbus::MethodErr>;
+    fn register_prompter(
+        &mut self,
+        prompter: dbus::Path<'static>,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(), dbus::MethodErr>;
+    fn unregister_prompter(&mut self, ctx: &mut crossroads::Context)
+        -> Result<(), dbus::MethodErr>;
+    fn merge_collections(
+        &mut self,
+        source: dbus::Path<'static>,
+        destination: dbus::Path<'static>,
+        on_conflict: String,
+        delete_source: bool,
+    ) -> Result<(u32, u32, bool), dbus::MethodErr>;
+    fn move_item(
+        &mut self,
+        item: dbus::Path<'static>,
+        destination: dbus::Path<'static>,
+        copy: bool,
+        ctx: &mut crossroads::Context,
+    ) -> Result<dbus::Path<'static>, dbus::MethodErr>;
+    fn migrate_collection(
+        &mut self,
+        collection: dbus::Path<'static>,
+        backend: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(), dbus::MethodErr>;
+    fn find_duplicates(
+        &mut self,
+        collection: dbus::Path<'static>,
+        compare_secrets: bool,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Vec<Vec<dbus::Path<'static>>>, dbus::MethodErr>;
+    fn delete_items(
+        &mut self,
+        items: Vec<dbus::Path<'static>>,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(), dbus::MethodErr>;
+    fn disk_usage(&mut self) -> Result<(u64, u64), dbus::MethodErr>;
+    fn get_capabilities(&mut self) -> Result<(String, bool, bool, bool, u64), dbus::MethodErr>;
+    /// `None` when the reply gets sent later on, see [crate::tks_dbus::reply_later]
+    fn create_backup(
+        &mut self,
+        passphrase: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Option<Vec<u8>>, dbus::MethodErr>;
+    fn restore_backup(
+        &mut self,
+        archive: Vec<u8>,
+        passphrase: String,
+        mode: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(u32, u32, u32, u32), dbus::MethodErr>;
+    fn change_password(
+        &mut self,
+        collection: dbus::Path<'static>,
+        old_password: String,
+        new_password: String,
+    ) -> Result<Vec<dbus::Path<'static>>, dbus::MethodErr>;
+    fn check_storage(
+        &mut self,
+        repair: bool,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Vec<(String, String, String, bool)>, dbus::MethodErr>;
+    fn export_audit_log(&mut self, since: u64) -> Result<Vec<String>, dbus::MethodErr>;
+    fn list_denied_clients(
+        &mut self,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Vec<(String, u32, u64)>, dbus::MethodErr>;
+    fn deny_client(
+        &mut self,
+        exe: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(), dbus::MethodErr>;
+    fn forget_denied_client(
+        &mut self,
+        exe: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn list_clients(
+        &mut self,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Vec<(String, String, String)>, dbus::MethodErr>;
+    fn revoke_client(
+        &mut self,
+        exe: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<bool, dbus::MethodErr>;
+    fn get_diagnostics(
+        &mut self,
+    ) -> Result<::std::collections::HashMap<String, u64>, dbus::MethodErr>;
+    fn reload_config(&mut self) -> Result<Vec<String>, dbus::MethodErr>;
+    fn forget_keys(&mut self) -> Result<(), dbus::MethodErr>;
+    fn pending_passphrases(
+        &mut self,
+        ctx: &mut crossroads::Context,
+    ) -> Result<Vec<(u32, String, String, bool)>, dbus::MethodErr>;
+    fn register_schema(
+        &mut self,
+        name: String,
+        attributes: ::std::collections::HashMap<String, String>,
+    ) -> Result<(), dbus::MethodErr>;
+    fn list_schemas(
+        &mut self,
+    ) -> Result<
+        ::std::collections::HashMap<String, ::std::collections::HashMap<String, String>>,
+        dbus::MethodErr,
+    >;
+    fn answer_passphrase(
+        &mut self,
+        id: u32,
+        cancelled: bool,
+        passphrase: String,
+        ctx: &mut crossroads::Context,
+    ) -> Result<(), dbus::MethodErr>;
+}
+
+#[derive(Debug)]
+pub struct IoLinuxTksService1StorageSpaceLow {
+    pub available: u64,
+    pub threshold: u64,
+}
+
+impl arg::AppendAll for IoLinuxTksService1StorageSpaceLow {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.available, i);
+        arg::RefArg::append(&self.threshold, i);
+    }
+}
+
+impl arg::ReadAll for IoLinuxTksService1StorageSpaceLow {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(IoLinuxTksService1StorageSpaceLow {
+            available: i.read()?,
+            threshold: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for IoLinuxTksService1StorageSpaceLow {
+    const NAME: &'static str = "StorageSpaceLow";
+    const INTERFACE: &'static str = "io.linux_tks.Service1";
+}
+
+pub fn register_io_linux_tks_service1<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksService1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Service1", |b| {
+        b.signal::<(u64, u64), _>("StorageSpaceLow", ("available", "threshold"));
+        b.method_with_cr_custom::<(String,), (bool,), _, _>(
+            "TestPrompt",
+            ("kind",),
+            ("dismissed",),
+            |mut ctx, cr, (kind,)| {
+                let tested = ctx.check(|ctx| {
+                    let t: &mut T = cr
+                        .data_mut(ctx.path())
+                        .ok_or_else(|| dbus::MethodErr::no_path(ctx.path()))?;
+                    t.test_prompt(kind, ctx)
+                });
+                match tested {
+                    Ok(Some(dismissed)) => ctx.do_reply(|msg| msg.append_all((dismissed,))),
+                    Ok(None) => return None,
+                    Err(()) => {}
+                }
+                Some(ctx)
+            },
+        );
+        b.method(
+            "RegisterPrompter",
+            ("prompter",),
+            (),
+            |ctx, t: &mut T, (prompter,)| t.register_prompter(prompter, ctx),
+        );
+        b.method("UnregisterPrompter", (), (), |ctx, t: &mut T, ()| {
+            t.unregister_prompter(ctx)
+        });
+        b.method(
+            "MergeCollections",
+            ("source", "destination", "on_conflict", "delete_source"),
+            ("moved", "skipped", "deleted"),
+            |_, t: &mut T, (source, destination, on_conflict, delete_source)| {
+                t.merge_collections(source, destination, on_conflict, delete_source)
+            },
+        );
+        b.method(
+            "MoveItem",
+            ("item", "destination", "copy"),
+            ("result",),
+            |ctx, t: &mut T, (item, destination, copy)| {
+                t.move_item(item, destination, copy, ctx).map(|x| (x,))
+            },
+        );
+        b.method(
+            "MigrateCollection",
+            ("collection", "backend"),
+            (),
+            |ctx, t: &mut T, (collection, backend)| {
+                t.migrate_collection(collection, backend, ctx)
+            },
+        );
+        b.method(
+            "FindDuplicates",
+            ("collection", "compare_secrets"),
+            ("groups",),
+            |ctx, t: &mut T, (collection, compare_secrets)| {
+                t.find_duplicates(collection, compare_secrets, ctx).map(|x| (x,))
+            },
+        );
+        b.method("DeleteItems", ("items",), (), |ctx, t: &mut T, (items,)| {
+            t.delete_items(items, ctx)
+        });
+        b.method(
+            "DiskUsage",
+            (),
+            ("used", "available"),
+            |_, t: &mut T, ()| t.disk_usage(),
+        );
+        b.method(
+            "GetCapabilities",
+            (),
+            (
+                "backend",
+                "supports_create_collection",
+                "supports_multiple_collections",
+                "supports_item_binary",
+                "max_secret_size",
+            ),
+            |_, t: &mut T, ()| t.get_capabilities(),
+        );
+        b.method_with_cr_custom::<(String,), (Vec<u8>,), _, _>(
+            "CreateBackup",
+            ("passphrase",),
+            ("archive",),
+            |mut ctx, cr, (passphrase,)| {
+                let created = ctx.check(|ctx| {
+                    let t: &mut T = cr
+                        .data_mut(ctx.path())
+                        .ok_or_else(|| dbus::MethodErr::no_path(ctx.path()))?;
+                    t.create_backup(passphrase, ctx)
+                });
+                match created {
+                    Ok(Some(archive)) => ctx.do_reply(|msg| msg.append_all((archive,))),
+                    Ok(None) => return None,
+                    Err(()) => {}
+                }
+                Some(ctx)
+            },
+        );
+        b.method(
+            "RestoreBackup",
+            ("archive", "passphrase", "mode"),
+            ("created", "added", "removed", "skipped"),
+            |ctx, t: &mut T, (archive, passphrase, mode)| {
+                t.restore_backup(archive, passphrase, mode, ctx)
+            },
+        );
+        b.method(
+            "ChangePassword",
+            ("collection", "old_password", "new_password"),
+            ("collections",),
+            |_, t: &mut T, (collection, old_password, new_password)| {
+                t.change_password(collection, old_password, new_password).map(|x| (x,))
+            },
+        );
+        b.method(
+            "CheckStorage",
+            ("repair",),
+            ("findings",),
+            |ctx, t: &mut T, (repair,)| t.check_storage(repair, ctx).map(|x| (x,)),
+        );
+        b.method(
+            "ExportAuditLog",
+            ("since",),
+            ("records",),
+            |_, t: &mut T, (since,)| t.export_audit_log(since).map(|x| (x,)),
+        );
+        b.method("ListDeniedClients", (), ("clients",), |ctx, t: &mut T, ()| {
+            t.list_denied_clients(ctx).map(|x| (x,))
+        });
+        b.method("DenyClient", ("exe",), (), |ctx, t: &mut T, (exe,)| {
+            t.deny_client(exe, ctx)
+        });
+        b.method(
+            "ForgetDeniedClient",
+            ("exe",),
+            ("forgotten",),
+            |ctx, t: &mut T, (exe,)| t.forget_denied_client(exe, ctx).map(|x| (x,)),
+        );
+        b.method("ListClients", (), ("clients",), |ctx, t: &mut T, ()| {
+            t.list_clients(ctx).map(|x| (x,))
+        });
+        b.method(
+            "RevokeClient",
+            ("exe",),
+            ("revoked",),
+            |ctx, t: &mut T, (exe,)| t.revoke_client(exe, ctx).map(|x| (x,)),
+        );
+        b.method("GetDiagnostics", (), ("counters",), |_, t: &mut T, ()| {
+            t.get_diagnostics().map(|x| (x,))
+        });
+        b.method("ReloadConfig", (), ("restart_needed",), |_, t: &mut T, ()| {
+            t.reload_config().map(|x| (x,))
+        });
+        b.method("ForgetKeys", (), (), |_, t: &mut T, ()| t.forget_keys());
+        b.method("PendingPassphrases", (), ("requests",), |ctx, t: &mut T, ()| {
+            t.pending_passphrases(ctx).map(|x| (x,))
+        });
+        b.method(
+            "RegisterSchema",
+            ("name", "attributes"),
+            (),
+            |_, t: &mut T, (name, attributes)| t.register_schema(name, attributes),
+        );
+        b.method("ListSchemas", (), ("schemas",), |_, t: &mut T, ()| {
+            t.list_schemas().map(|x| (x,))
+        });
+        b.method(
+            "AnswerPassphrase",
+            ("id", "cancelled", "passphrase"),
+            (),
+            |ctx, t: &mut T, (id, cancelled, passphrase)| {
+                t.answer_passphrase(id, cancelled, passphrase, ctx)
+            },
+        );
+    })
+}
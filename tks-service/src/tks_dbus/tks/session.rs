@@ -0,0 +1,24 @@
+// This code was generated from io.linux_tks.Session1.xml with `dbus-codegen-rust -r`, see https://github.com/diwic/dbus-rs
+use dbus;
+#[allow(unused_imports)]
+use dbus::arg;
+use dbus_crossroads as crossroads;
+
+pub trait IoLinuxTksSession1 {
+    fn algorithm(&self) -> Result<String, dbus::MethodErr>;
+    fn created(&self) -> Result<u64, dbus::MethodErr>;
+    fn peer_name(&self) -> Result<String, dbus::MethodErr>;
+}
+
+pub fn register_io_linux_tks_session1<T>(
+    cr: &mut crossroads::Crossroads,
+) -> crossroads::IfaceToken<T>
+where
+    T: IoLinuxTksSession1 + Send + 'static,
+{
+    cr.register("io.linux_tks.Session1", |b| {
+        b.property::<String, _>("Algorithm").get(|_, t: &mut T| t.algorithm());
+        b.property::<u64, _>("Created").get(|_, t| t.created());
+        b.property::<String, _>("PeerName").get(|_, t| t.peer_name());
+    })
+}
@@ -0,0 +1,52 @@
+//! Collections having the `tks:visibility` property set to `enrolled` only get listed to the
+//! clients the user let in, see [crate::tks_dbus::client_context], and to their owner: the
+//! Collections property and SearchItems leave them out for any other caller. This lets users hide
+//! collections, e.g. the banking ones, from the applications enumerating the secrets. The hidden
+//! collections stay reachable through their object path.
+
+use crate::storage::STORAGE;
+use crate::tks_dbus::client_context::{TksClientProcess, CLIENT_REGISTRY};
+use crate::tks_dbus::owner_tracker;
+use log::debug;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+pub const VISIBILITY_PROPERTY: &str = "tks:visibility";
+
+/// Value of [VISIBILITY_PROPERTY] restricting the collection to the enrolled clients
+pub const VISIBILITY_ENROLLED: &str = "enrolled";
+
+/// The collections not to list to the caller having the given unique bus name
+pub fn hidden_from(sender: Option<&str>) -> HashSet<Uuid> {
+    let restricted: HashSet<Uuid> = STORAGE
+        .read()
+        .unwrap()
+        .collections
+        .iter()
+        .filter(|c| {
+            c.properties.get(VISIBILITY_PROPERTY).map(String::as_str) == Some(VISIBILITY_ENROLLED)
+        })
+        .map(|c| c.uuid)
+        .collect();
+    // looking the caller up takes a few calls to the bus, only do it when needed
+    if restricted.is_empty() {
+        return restricted;
+    }
+    let Some(sender) = sender else {
+        return restricted;
+    };
+    let enrolled = match TksClientProcess::from_bus_name(sender.to_string()) {
+        Ok(process) => CLIENT_REGISTRY.lock().unwrap().is_enrolled(&process),
+        Err(e) => {
+            debug!("Cannot look {} up, hiding the restricted collections: {}", sender, e);
+            false
+        }
+    };
+    if enrolled {
+        return HashSet::new();
+    }
+    restricted
+        .into_iter()
+        .filter(|uuid| owner_tracker::owner(uuid).as_deref() != Some(sender))
+        .collect()
+}
@@ -1,6 +1,6 @@
 use log::error;
 use config::ConfigError;
-use std::sync::{MutexGuard, PoisonError};
+use std::sync::{PoisonError, RwLockReadGuard, RwLockWriteGuard};
 use dbus::MethodErr;
 use openssl::error::ErrorStack;
 use pinentry::Error;
@@ -28,6 +28,18 @@ pub enum TksError {
     ContextError(&'static str),
     GetHomeError(GetHomeError),
     NotSupported(&'static str),
+    SessionExpired,
+    StorageFull(u64),
+    BackupError(String),
+    BatchInProgress,
+    /// A file got written by a newer version, or in a format this one can't upgrade
+    UnsupportedVersion(String),
+    /// A storage directory belongs to another user, or the group or the others may access it
+    InsecurePermissions(String),
+    /// Another machine wrote the synced collection since it got loaded, see [crate::storage::sync]
+    SyncConflict(String),
+    /// The attributes don't follow the schema the item names, see [crate::storage::item_schemas]
+    SchemaViolation(String),
 }
 
 impl std::fmt::Display for TksError {
@@ -51,6 +63,20 @@ impl std::fmt::Display for TksError {
             TksError::ContextError(x) => { write!(f, "ContextError: {}", x)},
             TksError::GetHomeError(x) => { write!(f, "GetHomeError: {}", x)},
             TksError::NotSupported(x) => { write!(f, "Not supported: {}", x)},
+            TksError::SessionExpired => { write!(f, "Session expired, please open a new one")},
+            TksError::StorageFull(x) => {
+                write!(f, "Not enough free space on the storage filesystem, {} bytes left", x)
+            }
+            TksError::BackupError(x) => { write!(f, "Backup error: {}", x)},
+            TksError::BatchInProgress => {
+                write!(f, "Another client is writing a batch to the collection, try again later")
+            }
+            TksError::UnsupportedVersion(x) => { write!(f, "Unsupported version: {}", x)},
+            TksError::InsecurePermissions(x) => { write!(f, "Insecure permissions: {}", x)},
+            TksError::SyncConflict(x) => {
+                write!(f, "Collection '{}' changed on another machine, try again once merged", x)
+            }
+            TksError::SchemaViolation(x) => { write!(f, "Invalid attributes: {}", x)},
         }
     }
 }
@@ -76,8 +102,15 @@ impl From<serde_json::Error> for TksError {
     }
 }
 
-impl From<PoisonError<std::sync::MutexGuard<'_, storage::Storage>>> for TksError {
-    fn from(e: PoisonError<MutexGuard<'_, Storage>>) -> Self {
+impl From<PoisonError<RwLockReadGuard<'_, storage::Storage>>> for TksError {
+    fn from(e: PoisonError<RwLockReadGuard<'_, Storage>>) -> Self {
+        error!("Unexpected locking condition: {}", e);
+        TksError::LockingError
+    }
+}
+
+impl From<PoisonError<RwLockWriteGuard<'_, storage::Storage>>> for TksError {
+    fn from(e: PoisonError<RwLockWriteGuard<'_, Storage>>) -> Self {
         error!("Unexpected locking condition: {}", e);
         TksError::LockingError
     }
@@ -85,7 +118,19 @@ impl From<PoisonError<std::sync::MutexGuard<'_, storage::Storage>>> for TksError
 
 impl From<TksError> for MethodErr {
     fn from(e: TksError) -> Self {
-        dbus::MethodErr::failed(&e.to_string())
+        match e {
+            // clients are expected to open a new session upon this spec defined error
+            TksError::SessionExpired => {
+                ("org.freedesktop.Secret.Error.NoSession", e.to_string()).into()
+            }
+            TksError::PermissionDenied => {
+                ("org.freedesktop.DBus.Error.AccessDenied", e.to_string()).into()
+            }
+            TksError::SchemaViolation(_) => {
+                ("org.freedesktop.DBus.Error.InvalidArgs", e.to_string()).into()
+            }
+            _ => dbus::MethodErr::failed(&e.to_string()),
+        }
     }
 }
 
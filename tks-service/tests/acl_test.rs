@@ -0,0 +1,84 @@
+mod common;
+
+// These tests restrict collections and items of a storage opened in a temporary directory, then
+// check the accesses granted to made up client executables. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::storage::acl::{Access, Acl};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    const SEAHORSE: &str = "/usr/bin/seahorse";
+    const BROWSER: &str = "/usr/bin/firefox";
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    HashMap::from([("label".to_string(), label.to_string())]),
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    ":1.42".to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    #[test]
+    fn grants_are_per_client_and_access() {
+        let mut acl = Acl::default();
+        acl.grant(SEAHORSE, Access::Read);
+        acl.grant(SEAHORSE, Access::Write);
+        assert!(acl.allows(SEAHORSE, Access::Read));
+        assert!(!acl.allows(SEAHORSE, Access::Delete));
+        assert!(!acl.allows(BROWSER, Access::Read));
+
+        assert!(acl.revoke(SEAHORSE, &[Access::Write, Access::Delete]));
+        assert!(!acl.revoke(SEAHORSE, &[Access::Write]));
+        assert_eq!(acl.entries(), vec![(SEAHORSE.to_string(), vec![Access::Read])]);
+        assert!(acl.revoke(SEAHORSE, &[]));
+        assert!(acl.entries().is_empty());
+        assert_eq!("delete".parse::<Access>().unwrap(), Access::Delete);
+        assert!("execute".parse::<Access>().is_err());
+    }
+
+    #[tokio::test]
+    async fn items_use_the_acl_of_their_collection_unless_they_have_theirs() {
+        let settings = common::storage_settings("acl", "items");
+        let mut storage = open_unlocked(&settings);
+        let collection = storage.create_collection("web", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let shared = add_item(&mut storage, &collection, "shared");
+        let private = add_item(&mut storage, &collection, "private");
+        assert_eq!(storage.acl_of(&collection, Some(&shared)).unwrap(), None);
+
+        storage
+            .modify_acl(&collection, None, |acl| {
+                acl.get_or_insert_with(Acl::default).grant(BROWSER, Access::Read)
+            })
+            .unwrap();
+        storage
+            .modify_acl(&collection, Some(&private), |acl| {
+                acl.get_or_insert_with(Acl::default).grant(SEAHORSE, Access::Read)
+            })
+            .unwrap();
+        drop(storage);
+
+        // the ACLs get saved with the metadata, so they're known while locked
+        let storage = Storage::open(settings).unwrap();
+        let acl = storage.acl_of(&collection, Some(&shared)).unwrap().unwrap();
+        assert!(acl.allows(BROWSER, Access::Read));
+        let acl = storage.acl_of(&collection, Some(&private)).unwrap().unwrap();
+        assert!(acl.allows(SEAHORSE, Access::Read));
+        assert!(!acl.allows(BROWSER, Access::Read));
+        let acl = storage.acl_of(&collection, None).unwrap();
+        assert_eq!(acl, storage.acl_of(&collection, Some(&shared)).unwrap());
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+// These tests move the aliases of the collections of a storage opened in a temporary directory,
+// then check which collection the alias paths resolve to. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked};
+    use std::collections::HashMap;
+    use tks_service::tks_dbus::alias_registry;
+    use tks_service::tks_dbus::collection_impl::CollectionImpl;
+    use uuid::Uuid;
+
+    fn resolve(path: &str) -> Option<Uuid> {
+        CollectionImpl::resolve(&dbus::Path::from(path))
+    }
+
+    #[tokio::test]
+    async fn alias_paths_follow_the_aliases() {
+        let settings = common::storage_settings("alias-registry", "follow");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let work = storage.create_collection("work", "login", &HashMap::new()).unwrap();
+        let home = storage.create_collection("home", "", &HashMap::new()).unwrap();
+        storage.collections.iter().for_each(|c| {
+            let _ = CollectionImpl::from(c);
+        });
+
+        assert_eq!(resolve(alias_registry::DEFAULT_ALIAS_PATH), Some(default));
+        assert_eq!(resolve("/org/freedesktop/secrets/aliases/login"), Some(work));
+        let canonical = alias_registry::canonical_path(&work);
+        assert_eq!(CollectionImpl::from(&work).canonical_path(), canonical);
+        assert_eq!(CollectionImpl::from(&work).listed_path(), canonical);
+
+        storage.set_alias("login", Some(home)).unwrap();
+        storage.set_alias("my-alias", Some(work)).unwrap();
+        alias_registry::update(&storage);
+        assert_eq!(resolve("/org/freedesktop/secrets/aliases/login"), Some(home));
+        assert_eq!(resolve("/org/freedesktop/secrets/aliases/my_alias"), Some(work));
+        assert_eq!(CollectionImpl::from(&work).paths.len(), 2);
+
+        storage.set_alias("default", Some(work)).unwrap();
+        alias_registry::update(&storage);
+        assert_eq!(resolve(alias_registry::DEFAULT_ALIAS_PATH), Some(work));
+        assert!(CollectionImpl::from(&work).default);
+        assert!(!CollectionImpl::from(&default).default);
+        assert_eq!(CollectionImpl::from(&default).paths.len(), 1);
+        let listed = CollectionImpl::from(&work).listed_path();
+        assert_eq!(&*listed, alias_registry::DEFAULT_ALIAS_PATH);
+
+        storage.set_alias("login", None).unwrap();
+        alias_registry::update(&storage);
+        assert_eq!(resolve("/org/freedesktop/secrets/aliases/login"), None);
+        assert_eq!(CollectionImpl::from(&home).paths, vec![alias_registry::canonical_path(&home)]);
+    }
+}
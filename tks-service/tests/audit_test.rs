@@ -0,0 +1,79 @@
+// These tests write audit logs into a temporary directory, and check the format of their records
+// stays the one SIEMs get configured for. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tks_service::audit::{self, AuditClient, AuditEvent, AuditRecord, Outcome};
+    use uuid::Uuid;
+
+    fn log_path(test_name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("tks-audit-{}-{}.jsonl", std::process::id(), test_name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn record_at(time: u64, event: AuditEvent) -> AuditRecord {
+        AuditRecord::new(
+            UNIX_EPOCH + Duration::from_secs(time),
+            event,
+            Outcome::Success,
+            AuditClient {
+                bus_name: Some(":1.42".to_string()),
+                pid: Some(4242),
+                exe: Some("/usr/bin/secret-tool".to_string()),
+                application: None,
+            },
+            vec![Uuid::nil()],
+        )
+    }
+
+    #[test]
+    fn stable_field_names() {
+        let path = log_path("fields");
+        audit::append(&path, &record_at(1720949400, AuditEvent::SecretRead)).unwrap();
+        let lines = audit::read_since(&path, 0).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "timestamp": "2024-07-14T09:30:00Z",
+                "time": 1720949400,
+                "event": "secret.read",
+                "outcome": "success",
+                "client": {
+                    "bus_name": ":1.42",
+                    "pid": 4242,
+                    "exe": "/usr/bin/secret-tool"
+                },
+                "objects": ["00000000-0000-0000-0000-000000000000"]
+            })
+        );
+    }
+
+    #[test]
+    fn read_since() {
+        let path = log_path("since");
+        audit::append(&path, &record_at(1000, AuditEvent::ItemCreate)).unwrap();
+        audit::append(&path, &record_at(2000, AuditEvent::SecretWrite)).unwrap();
+        let mut damaged = fs::read_to_string(&path).unwrap();
+        damaged.push_str("{\"damaged\n");
+        fs::write(&path, damaged).unwrap();
+        audit::append(&path, &record_at(3000, AuditEvent::ItemDelete)).unwrap();
+
+        let lines = audit::read_since(&path, 2000).unwrap();
+        assert_eq!(lines.len(), 2);
+        let events: Vec<AuditEvent> = lines
+            .iter()
+            .map(|l| serde_json::from_str::<AuditRecord>(l).unwrap().event)
+            .collect();
+        assert_eq!(events, vec![AuditEvent::SecretWrite, AuditEvent::ItemDelete]);
+
+        assert!(audit::read_since(&log_path("missing"), 0).unwrap().is_empty());
+    }
+}
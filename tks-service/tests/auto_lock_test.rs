@@ -0,0 +1,44 @@
+mod common;
+
+// These tests lock the collections of a storage opened in a temporary directory, as the automatic
+// locking does after some inactivity. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn lock_all_drops_the_secrets() {
+        let settings = common::storage_settings("auto-lock", "lock-all");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let session = plain_session();
+        storage
+            .modify_collection(&default, |c| {
+                c.create_item(
+                    "mail",
+                    HashMap::new(),
+                    (&session, vec![], b"secret".to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+
+        assert_eq!(storage.lock_all().unwrap(), vec![default]);
+        let collection = storage.collections.iter().find(|c| c.uuid == default).unwrap();
+        assert!(collection.locked);
+        assert!(collection.items.iter().all(|i| i.locked && i.data.is_none()));
+
+        // nothing left to lock
+        assert!(storage.lock_all().unwrap().is_empty());
+
+        // the locked secrets were saved beforehand
+        let storage = open_unlocked(&settings);
+        let collection = storage.collections.iter().find(|c| c.uuid == default).unwrap();
+        let secret = collection.items[0].get_secret(&session, SENDER.to_string()).unwrap().2;
+        assert_eq!(secret, b"secret".to_vec());
+    }
+}
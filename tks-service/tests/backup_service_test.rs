@@ -0,0 +1,79 @@
+mod common;
+mod harness;
+
+// These tests back up the storage of the service, then restore it, over DBus, the scripted
+// prompter confirming the exports, see the harness module; they only need dbus-daemon to be
+// installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::{Connection, Proxy};
+    use std::env;
+    use tks_service::tks_dbus::prompter::{self, ScriptedAnswer};
+
+    const PASSPHRASE: &str = "backup passphrase";
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn create_backup(conn: &Connection) -> Result<Vec<u8>, dbus::Error> {
+        service_proxy(conn)
+            .method_call("io.linux_tks.Service1", "CreateBackup", (PASSPHRASE,))
+            .map(|(archive,)| archive)
+    }
+
+    fn restore_backup(conn: &Connection, archive: &[u8]) -> Result<(), dbus::Error> {
+        service_proxy(conn)
+            .method_call::<(u32, u32, u32, u32), _, _, _>(
+                "io.linux_tks.Service1",
+                "RestoreBackup",
+                (archive, PASSPHRASE, "merge"),
+            )
+            .map(|_| ())
+    }
+
+    fn assert_denied(result: Result<impl std::fmt::Debug, dbus::Error>) {
+        let err = result.unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+    }
+
+    // a single test, as it revokes the test binary, which the other tests would need
+    #[test]
+    fn only_enrolled_clients_back_up_once_the_user_confirmed() {
+        harness::start();
+        harness::unlock_all();
+        let conn = Connection::new_session().unwrap();
+        prompter::script([ScriptedAnswer::Confirm(true)]);
+        let archive = create_backup(&conn).unwrap();
+        assert!(!archive.is_empty());
+        assert!(prompter::remaining_answers().is_empty());
+        restore_backup(&conn, &archive).unwrap();
+
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        assert_denied(create_backup(&conn));
+        assert!(prompter::remaining_answers().is_empty());
+
+        let exe = env::current_exe().unwrap();
+        let (revoked,): (bool,) = service_proxy(&conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "RevokeClient",
+                (exe.to_string_lossy().as_ref(),),
+            )
+            .unwrap();
+        assert!(revoked);
+        prompter::script([ScriptedAnswer::Confirm(true)]);
+        assert_denied(create_backup(&conn));
+        assert_eq!(prompter::remaining_answers().len(), 1);
+        assert_denied(restore_backup(&conn, &archive));
+
+        harness::enroll();
+        prompter::script([]);
+    }
+}
@@ -0,0 +1,129 @@
+mod common;
+
+// These tests back up a storage opened in a temporary directory, then restore the backup into
+// another one. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::storage::backup::{BackupHeader, RestoreMode, BACKUP_VERSION, KDF_ITERATIONS};
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    fn passphrase() -> SecretString {
+        SecretString::new("backup passphrase".into())
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) {
+        let session = plain_session();
+        let attributes = HashMap::from([("label".to_string(), label.to_string())]);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    /// Labels and secrets of the items of the named collection
+    fn contents(storage: &Storage, name: &str) -> Vec<(String, Vec<u8>)> {
+        let session = plain_session();
+        let collection = storage.collections.iter().find(|c| c.name == name).unwrap();
+        let mut contents: Vec<_> = collection
+            .items
+            .iter()
+            .map(|i| {
+                let secret = i.get_secret(&session, SENDER.to_string()).unwrap().2;
+                (i.label.clone(), secret)
+            })
+            .collect();
+        contents.sort();
+        contents
+    }
+
+    /// A storage holding an extra collection, with an item in it and another in the default one
+    fn prepare(settings: &settings::Storage) -> Storage {
+        let mut storage = open_unlocked(settings);
+        let work = storage.create_collection("work", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        add_item(&mut storage, &work, "vpn");
+        add_item(&mut storage, &default, "mail");
+        storage
+    }
+
+    #[tokio::test]
+    async fn restore_into_new_storage() {
+        let archive = prepare(&common::storage_settings("backup", "source"))
+            .create_backup(&passphrase())
+            .unwrap();
+
+        let settings = common::storage_settings("backup", "new");
+        let mut storage = open_unlocked(&settings);
+        let outcome = storage
+            .restore_backup(&archive, &passphrase(), RestoreMode::Merge)
+            .unwrap();
+        assert_eq!(outcome.created.len(), 1);
+        assert_eq!(outcome.added.len(), 2);
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(contents(&storage, "work"), vec![("vpn".to_string(), b"vpn".to_vec())]);
+        assert_eq!(contents(&storage, "default"), vec![("mail".to_string(), b"mail".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_and_replace_drops_newer_items() {
+        let settings = common::storage_settings("backup", "modes");
+        let mut storage = prepare(&settings);
+        let archive = storage.create_backup(&passphrase()).unwrap();
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        add_item(&mut storage, &default, "newer");
+
+        let outcome = storage
+            .restore_backup(&archive, &passphrase(), RestoreMode::Merge)
+            .unwrap();
+        assert_eq!(outcome.skipped, 2);
+        assert_eq!(contents(&storage, "default").len(), 2);
+
+        let outcome = storage
+            .restore_backup(&archive, &passphrase(), RestoreMode::Replace)
+            .unwrap();
+        assert_eq!(outcome.removed.len(), 1);
+        let storage = open_unlocked(&settings);
+        assert_eq!(contents(&storage, "default"), vec![("mail".to_string(), b"mail".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn refuse_bad_archives() {
+        let settings = common::storage_settings("backup", "refuse");
+        let mut storage = prepare(&settings);
+        let mut archive = storage.create_backup(&passphrase()).unwrap();
+
+        // the header gets read without the passphrase
+        let header = BackupHeader::read(&archive).unwrap();
+        assert_eq!(header.version, BACKUP_VERSION);
+        assert_eq!(header.iterations, KDF_ITERATIONS);
+        assert!(BackupHeader::read(b"TKS-BACKUP").is_err());
+
+        let wrong = SecretString::new("wrong".into());
+        let result = storage.restore_backup(&archive, &wrong, RestoreMode::Merge);
+        assert!(matches!(result, Err(TksError::BackupError(_))));
+
+        // the version follows the `TKS-BACKUP` magic
+        archive[10] = 0xff;
+        let result = storage.restore_backup(&archive, &passphrase(), RestoreMode::Merge);
+        match result {
+            Err(TksError::BackupError(e)) => assert!(e.contains("not supported")),
+            _ => panic!("newer backups should be refused"),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+mod common;
+
+// These tests lease a collection of a storage opened in a temporary directory, then change it as
+// the client holding the lease and as another one. The clients are made up unique bus names, which
+// don't need to be on the bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, PASSWORD};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tks_service::audit::{self, AuditClient};
+    use tks_service::storage::Storage;
+    use tks_service::tks_dbus::session_impl::Session;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    const IMPORTER: &str = ":1.42";
+    const OTHER: &str = ":1.43";
+
+    fn client(bus_name: &str) -> AuditClient {
+        AuditClient {
+            bus_name: Some(bus_name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Creates an item as the given client
+    fn add_item(
+        storage: &mut Storage,
+        collection: &Uuid,
+        sender: &str,
+        label: &str,
+    ) -> Result<(), TksError> {
+        audit::set_caller(Some(sender.to_string()));
+        let session = Session::new(0, "plain".to_string(), sender.to_string());
+        let result = storage.modify_collection(collection, |c| {
+            c.create_item(
+                label,
+                HashMap::from([("label".to_string(), label.to_string())]),
+                (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                false,
+                sender.to_string(),
+            )
+        });
+        audit::set_caller(None);
+        result.map(|_| ())
+    }
+
+    fn item_count(storage: &Storage, collection: &Uuid) -> usize {
+        storage.with_collection(collection, |c| Ok(c.items.len())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn batches_keep_the_other_clients_out() {
+        let settings = common::storage_settings("batch", "lease");
+        let mut storage = open_unlocked(&settings);
+        let collection = storage.create_collection("import", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let timeout = Duration::from_secs(60);
+
+        storage.begin_batch(&collection, client(IMPORTER), timeout).unwrap();
+        let result = storage.begin_batch(&collection, client(OTHER), timeout);
+        assert!(matches!(result, Err(TksError::BatchInProgress)));
+        add_item(&mut storage, &collection, IMPORTER, "imported").unwrap();
+        let result = add_item(&mut storage, &collection, OTHER, "interleaved");
+        assert!(matches!(result, Err(TksError::BatchInProgress)));
+        assert_eq!(item_count(&storage, &collection), 1);
+
+        // only the importer ends its batch
+        let result = storage.end_batch(&collection, &client(OTHER));
+        assert!(matches!(result, Err(TksError::PermissionDenied)));
+        storage.end_batch(&collection, &client(IMPORTER)).unwrap();
+        add_item(&mut storage, &collection, OTHER, "after").unwrap();
+
+        // nor do the batches outlive their client
+        storage.begin_batch(&collection, client(IMPORTER), timeout).unwrap();
+        storage.end_batches_of(IMPORTER);
+        add_item(&mut storage, &collection, OTHER, "after exit").unwrap();
+        drop(storage);
+
+        let storage = Storage::open(settings).unwrap();
+        assert_eq!(item_count(&storage, &collection), 3);
+    }
+}
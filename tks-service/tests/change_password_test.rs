@@ -0,0 +1,144 @@
+mod common;
+
+// These tests change the password of a storage opened in a temporary directory, then reopen it
+// the way the service would do after a restart. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, plain_session, PASSWORD, SENDER};
+    use lazy_static::lazy_static;
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tks_service::settings;
+    use tks_service::storage::file_ops::{clear_fault, inject_fault, Fault};
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    const NEW_PASSWORD: &str = "change-password-test-new";
+
+    lazy_static! {
+        // faults are injected globally, so tests should not run concurrently
+        static ref SERIAL: Mutex<()> = Mutex::new(());
+    }
+
+    fn storage_settings(test_name: &str, per_item_files: bool) -> settings::Storage {
+        settings::Storage {
+            per_item_files,
+            ..common::storage_settings("change-password", test_name)
+        }
+    }
+
+    fn password(password: &str) -> SecretString {
+        SecretString::new(password.into())
+    }
+
+    fn open(settings: &settings::Storage, with: &str) -> Result<Storage, TksError> {
+        let mut storage = Storage::open(settings.clone()).expect("storage should open");
+        storage.unlock_with_password(password(with))?;
+        Ok(storage)
+    }
+
+    /// Opens a new storage having the default collection and another one, each with an item
+    fn prepare(settings: &settings::Storage) -> (Storage, Uuid, Uuid) {
+        let mut storage = open(settings, PASSWORD).unwrap();
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let work = storage
+            .create_collection("work", "", &HashMap::new())
+            .unwrap();
+        storage.unlock_with_password(password(PASSWORD)).unwrap();
+        add_item(&mut storage, &default, "mail");
+        add_item(&mut storage, &work, "vpn");
+        storage.write_private_file("clients", b"[]").unwrap();
+        (storage, default, work)
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, service: &str) {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    service,
+                    HashMap::from([("service".to_string(), service.to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        service.as_bytes().to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    fn secret_of(storage: &Storage, collection: &Uuid) -> Vec<u8> {
+        let session = plain_session();
+        storage
+            .with_collection(collection, |c| {
+                Ok(c.items[0].get_secret(&session, SENDER.to_string())?.2)
+            })
+            .unwrap()
+    }
+
+    fn check_password(settings: &settings::Storage, with: &str, default: &Uuid, work: &Uuid) {
+        let storage = open(settings, with).expect("the password should unlock the storage");
+        assert_eq!(secret_of(&storage, default), b"mail");
+        assert_eq!(secret_of(&storage, work), b"vpn");
+        assert_eq!(
+            storage.read_private_file("clients").unwrap(),
+            Some(b"[]".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn new_password_unlocks() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        for per_item_files in [false, true] {
+            let settings = storage_settings(&format!("new-{}", per_item_files), per_item_files);
+            let (mut storage, default, work) = prepare(&settings);
+            let sharing = storage
+                .change_password(&work, &password(PASSWORD), &password(NEW_PASSWORD))
+                .unwrap();
+            assert_eq!(sharing.len(), 2);
+            // the storage keeps working with the new key
+            add_item(&mut storage, &default, "chat");
+            drop(storage);
+
+            check_password(&settings, NEW_PASSWORD, &default, &work);
+            assert!(open(&settings, PASSWORD).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn wrong_password_changes_nothing() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = storage_settings("wrong", false);
+        let (mut storage, default, work) = prepare(&settings);
+        let changed =
+            storage.change_password(&default, &password("wrong"), &password(NEW_PASSWORD));
+        assert!(matches!(changed, Err(TksError::PermissionDenied)));
+        drop(storage);
+
+        check_password(&settings, PASSWORD, &default, &work);
+    }
+
+    #[tokio::test]
+    async fn failed_write_rolls_back() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = storage_settings("rollback", false);
+        let (mut storage, default, work) = prepare(&settings);
+        // the files of both collections got written, the private file fails
+        inject_fault(Fault::PartialWrite(3));
+        let changed =
+            storage.change_password(&default, &password(PASSWORD), &password(NEW_PASSWORD));
+        clear_fault();
+        assert!(changed.is_err());
+        drop(storage);
+
+        check_password(&settings, PASSWORD, &default, &work);
+        assert!(open(&settings, NEW_PASSWORD).is_err());
+    }
+}
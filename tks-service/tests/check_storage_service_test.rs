@@ -0,0 +1,53 @@
+mod common;
+mod harness;
+
+// These tests check the storage of the service over DBus, repairing it too while the test binary
+// is let in, see the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::{Connection, Proxy};
+    use std::env;
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn check_storage(
+        conn: &Connection,
+        repair: bool,
+    ) -> Result<Vec<(String, String, String, bool)>, dbus::Error> {
+        service_proxy(conn)
+            .method_call("io.linux_tks.Service1", "CheckStorage", (repair,))
+            .map(|(findings,)| findings)
+    }
+
+    // a single test, as it revokes the test binary, which the other tests would need
+    #[test]
+    fn only_enrolled_clients_repair_the_storage() {
+        harness::start();
+        harness::unlock_all();
+        let conn = Connection::new_session().unwrap();
+        check_storage(&conn, true).unwrap();
+
+        let exe = env::current_exe().unwrap();
+        let (revoked,): (bool,) = service_proxy(&conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "RevokeClient",
+                (exe.to_string_lossy().as_ref(),),
+            )
+            .unwrap();
+        assert!(revoked);
+        check_storage(&conn, false).unwrap();
+        let err = check_storage(&conn, true).unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+
+        harness::enroll();
+    }
+}
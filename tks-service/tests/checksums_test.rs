@@ -0,0 +1,98 @@
+mod common;
+
+// These tests change the secrets of collections saved in a temporary directory, with and without
+// the secret checksums, then compare the checksum attributes. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::storage::checksums::{CHECKSUMS_PROPERTY, CHECKSUM_ATTRIBUTE};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn create_collection(storage: &mut Storage, name: &str, checksums: bool) -> Uuid {
+        let properties = match checksums {
+            true => HashMap::from([(CHECKSUMS_PROPERTY.to_string(), "true".to_string())]),
+            false => HashMap::new(),
+        };
+        let uuid = storage.create_collection(name, "", &properties).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        uuid
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, secret: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    "token",
+                    HashMap::from([("service".to_string(), "vpn".to_string())]),
+                    (&session, vec![], secret.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn set_secret(storage: &mut Storage, collection: &Uuid, item: &Uuid, secret: &str) {
+        let session = plain_session();
+        storage
+            .modify_item(collection, item, |i| {
+                let secret = secret.as_bytes().to_vec();
+                i.set_secret(&session, vec![], &secret, "text/plain".to_string(), SENDER.into())
+            })
+            .unwrap();
+    }
+
+    fn checksum(storage: &Storage, collection: &Uuid, item: &Uuid) -> Option<String> {
+        storage
+            .with_item(collection, item, |i| Ok(i.attribute(CHECKSUM_ATTRIBUTE).map(String::from)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn checksums_follow_the_secrets() {
+        let settings = common::storage_settings("checksums", "follow");
+        let mut storage = open_unlocked(&settings);
+        let collection = create_collection(&mut storage, "synced", true);
+        let item = add_item(&mut storage, &collection, "first");
+        let other = add_item(&mut storage, &collection, "second");
+        let first = checksum(&storage, &collection, &item).expect("item should have a checksum");
+        assert_eq!(first.len(), 64);
+        assert_ne!(checksum(&storage, &collection, &other).unwrap(), first);
+
+        set_secret(&mut storage, &collection, &item, "second");
+        let second = checksum(&storage, &collection, &item).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(checksum(&storage, &collection, &other).unwrap(), second);
+        set_secret(&mut storage, &collection, &item, "first");
+        assert_eq!(checksum(&storage, &collection, &item).unwrap(), first);
+        drop(storage);
+
+        // the checksums get saved with the metadata, so they're known while locked
+        let storage = Storage::open(settings).unwrap();
+        assert_eq!(checksum(&storage, &collection, &item).unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn checksums_are_opt_in_and_per_collection() {
+        let settings = common::storage_settings("checksums", "opt-in");
+        let mut storage = open_unlocked(&settings);
+        let plain = create_collection(&mut storage, "plain", false);
+        let item = add_item(&mut storage, &plain, "secret");
+        assert_eq!(checksum(&storage, &plain, &item), None);
+
+        let first = create_collection(&mut storage, "first", true);
+        let second = create_collection(&mut storage, "second", true);
+        let in_first = add_item(&mut storage, &first, "secret");
+        let in_second = add_item(&mut storage, &second, "secret");
+        assert_ne!(
+            checksum(&storage, &first, &in_first).unwrap(),
+            checksum(&storage, &second, &in_second).unwrap()
+        );
+    }
+}
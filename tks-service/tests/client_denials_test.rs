@@ -0,0 +1,53 @@
+// These tests check how long the clients denied by the user get turned down. They don't need a
+// DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::time::{Duration, SystemTime};
+    use tks_service::tks_dbus::client_context::{ClientDenials, MAX_DENIAL};
+
+    const EXE: &str = "/usr/bin/secret-tool";
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn denials_back_off() {
+        let exe = OsStr::new(EXE);
+        let now = SystemTime::now();
+        let mut denials = ClientDenials::new();
+        assert!(!denials.is_denied(exe, now));
+
+        denials.deny(exe, now, TTL);
+        assert!(denials.is_denied(exe, now + TTL - Duration::from_secs(1)));
+        assert!(!denials.is_denied(exe, now + TTL));
+        assert!(!denials.is_denied(OsStr::new("/usr/bin/other"), now));
+
+        let later = now + TTL;
+        denials.deny(exe, later, TTL);
+        assert!(denials.is_denied(exe, later + TTL));
+        assert!(!denials.is_denied(exe, later + TTL * 2));
+        assert_eq!(denials.list()[0].1.count, 2);
+
+        for _ in 0..40 {
+            denials.deny(exe, later, TTL);
+        }
+        assert!(!denials.is_denied(exe, later + MAX_DENIAL));
+
+        assert!(denials.forget(exe));
+        assert!(!denials.is_denied(exe, later));
+        assert!(!denials.forget(exe));
+    }
+
+    #[test]
+    fn permanent_denials_never_expire() {
+        let exe = OsStr::new(EXE);
+        let now = SystemTime::now();
+        let mut denials = ClientDenials::new();
+        denials.deny(exe, now, TTL);
+        denials.deny_permanently(exe);
+        denials.deny(exe, now, TTL);
+        assert!(denials.is_denied(exe, now + MAX_DENIAL * 10));
+        assert_eq!(denials.list()[0].1.until, None);
+        assert_eq!(denials.list()[0].1.count, 2);
+    }
+}
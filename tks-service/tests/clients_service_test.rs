@@ -0,0 +1,84 @@
+mod common;
+mod harness;
+
+// These tests manage the clients the user let in, or denied, through the service, the test
+// binary being one of them until it revokes itself, see the harness module; they only need
+// dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::{Connection, Proxy};
+    use std::env;
+
+    const DENIED: &str = "/usr/bin/denied";
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn list_clients(conn: &Connection) -> Result<Vec<(String, String, String)>, dbus::Error> {
+        service_proxy(conn)
+            .method_call("io.linux_tks.Service1", "ListClients", ())
+            .map(|(clients,)| clients)
+    }
+
+    fn revoke_client(conn: &Connection, exe: &str) -> Result<bool, dbus::Error> {
+        service_proxy(conn)
+            .method_call("io.linux_tks.Service1", "RevokeClient", (exe,))
+            .map(|(revoked,)| revoked)
+    }
+
+    fn denied_clients(conn: &Connection) -> Result<Vec<(String, u32, u64)>, dbus::Error> {
+        service_proxy(conn)
+            .method_call("io.linux_tks.Service1", "ListDeniedClients", ())
+            .map(|(clients,)| clients)
+    }
+
+    fn deny_client(conn: &Connection, exe: &str) -> Result<(), dbus::Error> {
+        service_proxy(conn).method_call("io.linux_tks.Service1", "DenyClient", (exe,))
+    }
+
+    fn forget_denied_client(conn: &Connection, exe: &str) -> Result<bool, dbus::Error> {
+        service_proxy(conn)
+            .method_call("io.linux_tks.Service1", "ForgetDeniedClient", (exe,))
+            .map(|(forgotten,)| forgotten)
+    }
+
+    fn assert_denied<T: std::fmt::Debug>(result: Result<T, dbus::Error>) {
+        let err = result.unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+    }
+
+    // a single test, as it revokes the test binary, which the other tests would need
+    #[test]
+    fn only_enrolled_clients_manage_the_clients() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        let exe = env::current_exe().unwrap().to_string_lossy().into_owned();
+        let clients = list_clients(&conn).unwrap();
+        assert!(clients.iter().any(|(exe_path, _, _)| *exe_path == exe));
+        assert!(!revoke_client(&conn, "/usr/bin/not-let-in").unwrap());
+        deny_client(&conn, DENIED).unwrap();
+        assert_eq!(
+            denied_clients(&conn).unwrap(),
+            vec![(DENIED.to_string(), 0, 0)]
+        );
+
+        assert!(revoke_client(&conn, &exe).unwrap());
+        assert_denied(list_clients(&conn));
+        assert_denied(revoke_client(&conn, &exe));
+        assert_denied(denied_clients(&conn));
+        assert_denied(forget_denied_client(&conn, DENIED));
+        assert_denied(deny_client(&conn, "/usr/bin/seahorse"));
+
+        harness::enroll();
+        assert!(!list_clients(&conn).unwrap().is_empty());
+        assert!(forget_denied_client(&conn, DENIED).unwrap());
+        assert!(denied_clients(&conn).unwrap().is_empty());
+    }
+}
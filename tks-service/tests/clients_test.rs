@@ -0,0 +1,55 @@
+mod common;
+
+// These tests save the known clients in a storage opened in a temporary directory, then read them
+// back after reopening it. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked};
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::PathBuf;
+    use tks_service::storage::Storage;
+    use tks_service::tks_dbus::client_context::{KnownClients, TksClient};
+
+    fn client(exe_path: &str, exe_sha256: &str) -> TksClient {
+        TksClient {
+            name: "seahorse".to_string(),
+            exe_path: exe_path.into(),
+            exe_sha256: exe_sha256.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn known_clients_survive_a_restart() {
+        let settings = common::storage_settings("clients", "restart");
+        let storage = open_unlocked(&settings);
+        let mut clients = KnownClients::load(&storage).unwrap().expect("storage is unlocked");
+        assert!(clients.list().is_empty());
+        clients.insert(client("/usr/bin/seahorse", "00ff"));
+        clients.insert(client("/usr/bin/secret-tool", "ff00"));
+        clients.save(&storage).unwrap();
+        drop(storage);
+
+        // they're encrypted, so they can't be read while locked
+        let path = PathBuf::from(settings.path.as_ref().unwrap()).join("clients");
+        assert!(!String::from_utf8_lossy(&fs::read(path).unwrap()).contains("seahorse"));
+        let storage = Storage::open(settings.clone()).unwrap();
+        assert!(KnownClients::load(&storage).unwrap().is_none());
+        drop(storage);
+
+        let storage = open_unlocked(&settings);
+        let mut clients = KnownClients::load(&storage).unwrap().unwrap();
+        assert_eq!(clients.list().len(), 2);
+        assert!(clients.contains(&client("/usr/bin/seahorse", "00ff")));
+        // a changed executable isn't known
+        assert!(!clients.contains(&client("/usr/bin/seahorse", "0000")));
+
+        clients.remove(OsStr::new("/usr/bin/seahorse")).unwrap();
+        clients.save(&storage).unwrap();
+        drop(storage);
+        let storage = open_unlocked(&settings);
+        let clients = KnownClients::load(&storage).unwrap().unwrap();
+        assert_eq!(clients.list(), vec![client("/usr/bin/secret-tool", "ff00")]);
+    }
+}
@@ -0,0 +1,126 @@
+mod common;
+
+// These tests give the collections of a tks_gcm storage, opened in a temporary directory, their
+// own key, and check their secrets stay readable across restarts. They don't need a DBus session
+// bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use tks_service::settings;
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, per_collection_keys: bool) -> settings::Storage {
+        settings::Storage {
+            per_collection_keys,
+            ..common::storage_settings("collection-keys", test_name)
+        }
+    }
+
+    fn key_dir(settings: &settings::Storage, uuid: &Uuid) -> PathBuf {
+        PathBuf::from(settings.path.as_ref().unwrap())
+            .join("keys")
+            .join(uuid.to_string())
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid) {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    "VPN",
+                    HashMap::from([("service".to_string(), "vpn".to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        b"secret".to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    fn secret_of(storage: &Storage, collection: &Uuid) -> Vec<u8> {
+        let session = plain_session();
+        storage
+            .with_collection(collection, |c| {
+                Ok(c.items[0].get_secret(&session, SENDER.to_string())?.2)
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn new_collections_get_their_own_key() {
+        let settings = storage_settings("own", true);
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        assert!(!key_dir(&settings, &default).exists());
+
+        let work = storage
+            .create_collection("work", "", &HashMap::new())
+            .unwrap();
+        assert!(key_dir(&settings, &work).join("salt").exists());
+        // its password isn't defined yet
+        assert!(!key_dir(&settings, &work).join("commissioned").exists());
+
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert!(key_dir(&settings, &work).join("commissioned").exists());
+        add_item(&mut storage, &work);
+        drop(storage);
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(secret_of(&storage, &work), b"secret");
+    }
+
+    #[tokio::test]
+    async fn collection_key_has_its_own_salt() {
+        let settings = storage_settings("salt", true);
+        let mut storage = open_unlocked(&settings);
+        let work = storage
+            .create_collection("work", "", &HashMap::new())
+            .unwrap();
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        drop(storage);
+
+        // the password derives another key with the salt of the backend
+        let root = PathBuf::from(settings.path.as_ref().unwrap());
+        fs::copy(root.join("salt"), key_dir(&settings, &work).join("salt")).unwrap();
+        let mut storage = Storage::open(settings.clone()).unwrap();
+        assert!(storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn deleting_removes_the_key() {
+        let settings = storage_settings("delete", true);
+        let mut storage = open_unlocked(&settings);
+        let work = storage
+            .create_collection("work", "", &HashMap::new())
+            .unwrap();
+        storage.delete_collection(&work).unwrap();
+        assert!(!key_dir(&settings, &work).exists());
+    }
+
+    #[tokio::test]
+    async fn collections_share_the_key_by_default() {
+        let settings = storage_settings("shared", false);
+        let mut storage = open_unlocked(&settings);
+        let work = storage
+            .create_collection("work", "", &HashMap::new())
+            .unwrap();
+        assert!(!key_dir(&settings, &work).exists());
+    }
+}
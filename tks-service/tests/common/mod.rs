@@ -0,0 +1,54 @@
+//! Fixtures shared by the tests opening a storage of their own, in a temporary directory, and by
+//! the harness running the service.
+
+// each test binary uses some of the helpers
+#![allow(dead_code)]
+
+use secrecy::SecretString;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use tks_service::settings;
+use tks_service::storage::Storage;
+use tks_service::tks_dbus::session_impl::Session;
+
+/// The password of the test storages
+pub const PASSWORD: &str = "tks-test";
+/// The DBus name of the client of the test storages
+pub const SENDER: &str = ":1.42";
+
+/// An empty directory named after the test binary, given as `prefix`, and the test
+pub fn temp_dir(prefix: &str, test_name: &str) -> PathBuf {
+    let mut path = env::temp_dir();
+    path.push(format!(
+        "tks-{}-{}-{}",
+        prefix,
+        std::process::id(),
+        test_name
+    ));
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+/// The settings of an empty tks_gcm storage in the [temp_dir] of the test; the other settings are
+/// the default ones, which the tests override with `..storage_settings(prefix, test_name)`
+pub fn storage_settings(prefix: &str, test_name: &str) -> settings::Storage {
+    settings::Storage {
+        path: Some(temp_dir(prefix, test_name).to_string_lossy().into()),
+        ..Default::default()
+    }
+}
+
+/// Opens the storage, creating it with [PASSWORD] if needed, and unlocks it
+pub fn open_unlocked(settings: &settings::Storage) -> Storage {
+    let mut storage = Storage::open(settings.clone()).expect("storage should open");
+    storage
+        .unlock_with_password(SecretString::new(PASSWORD.into()))
+        .expect("storage should unlock");
+    storage
+}
+
+/// A session of [SENDER] passing the secrets unencrypted
+pub fn plain_session() -> Session {
+    Session::new(0, "plain".to_string(), SENDER.to_string())
+}
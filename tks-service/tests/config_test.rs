@@ -0,0 +1,169 @@
+// These tests check configuration files written into a temporary directory. They don't need a DBus
+// session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use tks_service::settings::reload::compare;
+    use tks_service::settings::{BusKind, LockTrigger, PromptBackend, RunMode, Settings};
+
+    fn write_config(test_name: &str, contents: &str) -> String {
+        let mut path = env::temp_dir();
+        path.push(format!("tks-config-{}-{}.toml", std::process::id(), test_name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into()
+    }
+
+    #[test]
+    fn valid_config() {
+        let path = write_config("valid", "[storage]\nkind = \"tks_gcm\"\n");
+        let settings = Settings::check(&path).expect("configuration should be valid");
+        assert_eq!(settings.storage.kind, "tks_gcm");
+        assert_eq!(settings.prompt.timeout, 300);
+        assert_eq!(settings.prompt.backend, PromptBackend::Auto);
+        assert_eq!(settings.prompt.pinentry.program, "auto");
+        assert_eq!(settings.prompt.cache_ttl, 0);
+        assert_eq!(settings.bus.kind, BusKind::Session);
+        assert_eq!(settings.bus.name, "org.freedesktop.secrets");
+    }
+
+    #[test]
+    fn system_bus() {
+        let path = write_config(
+            "system-bus",
+            "[storage]\nkind = \"tks_gcm\"\n[prompt]\nbackend = \"polkit\"\n\
+             [auto_lock]\nlock_on = [\"sleep\"]\n\
+             [bus]\nkind = \"system\"\nname = \"io.linux_tks.Secrets\"\n",
+        );
+        let settings = Settings::check(&path).expect("configuration should be valid");
+        assert_eq!(settings.bus.kind, BusKind::System);
+        assert_eq!(settings.bus.name, "io.linux_tks.Secrets");
+        assert_eq!(settings.prompt.backend, PromptBackend::Polkit);
+
+        // the desktop session is on the session bus, and polkit only knows the system one
+        let path = write_config(
+            "system-bus-problems",
+            "[storage]\nkind = \"tks_gcm\"\n[auto_lock]\nlock_on = [\"screensaver\"]\n\
+             [kwallet]\nenabled = true\n[bus]\nkind = \"system\"\nname = \"not a name\"\n",
+        );
+        let problems = Settings::check(&path).expect_err("configuration should be invalid");
+        let settings: Vec<_> = problems.iter().map(|p| p.setting.as_str()).collect();
+        assert_eq!(settings, vec!["bus.name", "kwallet.enabled", "auto_lock.lock_on"]);
+        let path = write_config(
+            "polkit-session-bus",
+            "[storage]\nkind = \"tks_gcm\"\n[prompt]\nbackend = \"polkit\"\n",
+        );
+        let problems = Settings::check(&path).expect_err("configuration should be invalid");
+        assert_eq!(problems[0].setting, "prompt.backend");
+    }
+
+    #[test]
+    fn all_problems_get_reported() {
+        let path = write_config(
+            "problems",
+            "[storage]\nkind = \"tks_xyz\"\nflush_delay = 99999999\n\
+             [storage.keyfiles]\nusb = \"keys/usb.key\"\n\
+             [session]\nmax_uses = 0\n",
+        );
+        let problems = Settings::check(&path).expect_err("configuration should be invalid");
+        let mut settings: Vec<_> = problems.iter().map(|p| p.setting.as_str()).collect();
+        settings.sort();
+        assert_eq!(
+            settings,
+            vec![
+                "session.max_uses",
+                "storage.flush_delay",
+                "storage.keyfiles.usb",
+                "storage.kind"
+            ]
+        );
+    }
+
+    #[test]
+    fn wrong_types_get_reported() {
+        let path = write_config("types", "[storage]\nkind = \"tks_gcm\"\nflush_delay = \"abc\"\n");
+        let problems = Settings::check(&path).expect_err("configuration should be invalid");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].setting, "storage.flush_delay");
+    }
+
+    #[test]
+    fn pinentry_settings() {
+        let path = write_config(
+            "pinentry",
+            "[storage]\nkind = \"tks_gcm\"\n[prompt]\ncache_ttl = 600\n\
+             [prompt.pinentry]\nprogram = \"/bin/sh\"\ntitle = \"Secrets\"\n",
+        );
+        let settings = Settings::check(&path).expect("configuration should be valid");
+        assert_eq!(settings.prompt.cache_ttl, 600);
+        assert_eq!(settings.prompt.pinentry.program, "/bin/sh");
+        assert_eq!(settings.prompt.pinentry.title.as_deref(), Some("Secrets"));
+
+        let path = write_config(
+            "pinentry-missing",
+            "[storage]\nkind = \"tks_gcm\"\n\
+             [prompt.pinentry]\nprogram = \"pinentry-no-such-flavor\"\n",
+        );
+        let problems = Settings::check(&path).expect_err("configuration should be invalid");
+        assert_eq!(problems[0].setting, "prompt.pinentry.program");
+    }
+
+    #[test]
+    fn lock_triggers() {
+        let path = write_config(
+            "lock-on",
+            "[storage]\nkind = \"tks_gcm\"\n[auto_lock]\nlock_on = [\"session-lock\", \"sleep\"]\n",
+        );
+        let settings = Settings::check(&path).unwrap();
+        assert_eq!(
+            settings.auto_lock.lock_on,
+            vec![LockTrigger::SessionLock, LockTrigger::Sleep]
+        );
+    }
+
+    #[test]
+    fn reloaded_settings() {
+        let path = write_config("reload", "[storage]\nkind = \"tks_gcm\"\n");
+        let current = Settings::check(&path).unwrap();
+
+        let mut new = current.clone();
+        new.prompt.backend = PromptBackend::Pinentry;
+        new.auto_lock.lock_after_idle_minutes = Some(5);
+        let (problems, restart_needed) = compare(&current, &new);
+        assert!(problems.is_empty());
+        assert!(restart_needed.is_empty());
+
+        new.auto_lock.lock_on = vec![LockTrigger::Sleep];
+        let (problems, restart_needed) = compare(&current, &new);
+        assert!(problems.is_empty());
+        assert_eq!(restart_needed, vec!["auto_lock.lock_on"]);
+
+        // the storage is already open
+        new.storage.kind = "password-store".to_string();
+        new.storage.flush_delay = 1000;
+        let (problems, _) = compare(&current, &new);
+        let settings: Vec<_> = problems.iter().map(|p| p.setting.as_str()).collect();
+        assert_eq!(settings, vec!["storage.kind", "storage.flush_delay"]);
+
+        // the service and its prompts are connected to the bus already
+        let mut new = current.clone();
+        new.bus.kind = BusKind::System;
+        new.bus.name = "io.linux_tks.Secrets".to_string();
+        let (problems, restart_needed) = compare(&current, &new);
+        assert_eq!(problems[0].setting, "bus.kind");
+        assert_eq!(restart_needed, vec!["bus.name"]);
+    }
+
+    #[test]
+    fn test_mode_keeps_away_from_the_configured_storage() {
+        let path = write_config(
+            "run-mode",
+            "[storage]\nkind = \"tks_gcm\"\npath = \"/var/lib/real-storage\"\n",
+        );
+        env::set_var("TKS_RUN_MODE", "test");
+        env::set_var(RunMode::TEST_STORAGE_PATH, "/tmp/tks-config-test-storage");
+        let settings = Settings::check(&path).unwrap();
+        assert_eq!(settings.storage.path.as_deref(), Some("/tmp/tks-config-test-storage"));
+    }
+}
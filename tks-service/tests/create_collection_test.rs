@@ -0,0 +1,251 @@
+mod common;
+mod harness;
+
+// These tests create collections through the service, with properties, then check those the
+// collection objects expose, also when the name is taken and the scripted prompter gets asked
+// about reusing the collection, see the harness module; they only need dbus-daemon to be
+// installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked};
+    use crate::harness;
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+    use dbus::Message;
+    use std::collections::HashMap;
+    use std::sync::{mpsc, Mutex, MutexGuard};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tks_service::storage::Storage;
+    use tks_service::tks_dbus::prompter::{self, ScriptedAnswer};
+
+    const LABEL: &str = "org.freedesktop.Secret.Collection.Label";
+
+    /// The scripted prompter answers the dialogs of all the tests, so the prompting ones take turns
+    static PROMPTING: Mutex<()> = Mutex::new(());
+
+    fn properties(entries: &[(&str, &str)]) -> PropMap {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), Variant(Box::new(v.to_string()) as _)))
+            .collect()
+    }
+
+    fn create_collection(
+        conn: &Connection,
+        properties: PropMap,
+        alias: &str,
+    ) -> (dbus::Path<'static>, dbus::Path<'static>) {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+        .method_call(
+            "org.freedesktop.Secret.Service",
+            "CreateCollection",
+            (properties, alias),
+        )
+        .unwrap()
+    }
+
+    /// The label and the custom properties of the collection, once the service answers at its path
+    fn exposed(conn: &Connection, collection: &dbus::Path) -> (String, HashMap<String, String>) {
+        let proxy = conn.with_proxy(harness::SERVICE_NAME, collection, harness::TIMEOUT);
+        // the object gets registered in the background
+        let deadline = Instant::now() + harness::TIMEOUT;
+        let label = loop {
+            match proxy.get("org.freedesktop.Secret.Collection", "Label") {
+                Ok(label) => break label,
+                Err(e) => assert!(
+                    Instant::now() < deadline,
+                    "{} should answer: {}",
+                    collection,
+                    e
+                ),
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let custom = proxy.get("io.linux_tks.Collection1", "Properties").unwrap();
+        (label, custom)
+    }
+
+    #[test]
+    fn created_collections_expose_their_properties() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        let (collection, prompt) = create_collection(
+            &conn,
+            properties(&[
+                (LABEL, "Work mail"),
+                ("tks:trash-retention-days", "7"),
+                ("org.example.Ignored", "ignored"),
+            ]),
+            "",
+        );
+        assert_eq!(&*prompt, "/");
+        let (label, custom) = exposed(&conn, &collection);
+        assert_eq!(label, "Work mail");
+        assert_eq!(
+            custom,
+            HashMap::from([("tks:trash-retention-days".to_string(), "7".to_string())])
+        );
+    }
+
+    #[test]
+    fn creating_an_aliased_collection_again_updates_it() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        let (collection, _) = create_collection(
+            &conn,
+            properties(&[(LABEL, "Browser"), ("tks:secret-checksums", "true")]),
+            "create-again",
+        );
+        exposed(&conn, &collection);
+
+        let (again, prompt) = create_collection(
+            &conn,
+            properties(&[
+                (LABEL, "Browser passwords"),
+                ("tks:secret-history-depth", "2"),
+            ]),
+            "create-again",
+        );
+        assert_eq!(&*prompt, "/");
+        assert_eq!(again, collection);
+        let (label, custom) = exposed(&conn, &collection);
+        assert_eq!(label, "Browser passwords");
+        // the properties not given again are kept
+        assert_eq!(
+            custom,
+            HashMap::from([
+                ("tks:secret-checksums".to_string(), "true".to_string()),
+                ("tks:secret-history-depth".to_string(), "2".to_string()),
+            ])
+        );
+    }
+
+    fn take_turn() -> MutexGuard<'static, ()> {
+        let turn = PROMPTING.lock().unwrap_or_else(|e| e.into_inner());
+        harness::start();
+        turn
+    }
+
+    fn read_alias(conn: &Connection, alias: &str) -> dbus::Path<'static> {
+        let (collection,): (dbus::Path<'static>,) = conn
+            .with_proxy(
+                harness::SERVICE_NAME,
+                harness::SERVICE_PATH,
+                harness::TIMEOUT,
+            )
+            .method_call("org.freedesktop.Secret.Service", "ReadAlias", (alias,))
+            .unwrap();
+        collection
+    }
+
+    /// Watches the CollectionCreated signals, those of the collections of the other tests too
+    fn watch_created(conn: &Connection) -> mpsc::Receiver<Message> {
+        let rule = MatchRule::new_signal("org.freedesktop.Secret.Service", "CollectionCreated");
+        harness::watch(conn, rule)
+    }
+
+    fn created(signals: &mpsc::Receiver<Message>, collection: &dbus::Path) -> usize {
+        signals
+            .try_iter()
+            .filter(|s| s.read1::<dbus::Path>().is_ok_and(|c| c == *collection))
+            .count()
+    }
+
+    /// Invokes the prompt, returning whether it got dismissed and the path it completed with, if
+    /// any
+    fn complete(conn: &Connection, prompt: &dbus::Path<'static>) -> (bool, String) {
+        let rule = MatchRule::new_signal("org.freedesktop.Secret.Prompt", "Completed")
+            .with_path(prompt.clone());
+        let completed = harness::watch(conn, rule);
+        harness::start_prompt(conn, prompt);
+        let (dismissed, result): (bool, Variant<Box<dyn RefArg>>) =
+            harness::next_signal(conn, &completed).read2().unwrap();
+        (dismissed, result.0.as_str().unwrap_or_default().to_string())
+    }
+
+    #[test]
+    fn taken_names_prompt_before_the_reuse() {
+        let _turn = take_turn();
+        let conn = Connection::new_session().unwrap();
+        let signals = watch_created(&conn);
+        let (collection, _) = create_collection(&conn, properties(&[(LABEL, "Reused")]), "");
+        exposed(&conn, &collection);
+        // only the first call creates the collection
+        loop {
+            let created: dbus::Path = harness::next_signal(&conn, &signals).read1().unwrap();
+            if created == collection {
+                break;
+            }
+        }
+
+        let (path, prompt) = create_collection(
+            &conn,
+            properties(&[(LABEL, "Reused"), ("tks:trash-retention-days", "0")]),
+            "reused",
+        );
+        assert_eq!(&*path, "/");
+        assert_ne!(&*prompt, "/");
+        prompter::script([ScriptedAnswer::Confirm(true)]);
+        let (dismissed, result) = complete(&conn, &prompt);
+        assert!(!dismissed);
+        assert_eq!(result, collection.to_string());
+        assert_eq!(read_alias(&conn, "reused"), collection);
+        let (_, custom) = exposed(&conn, &collection);
+        assert_eq!(custom["tks:trash-retention-days"], "0");
+        assert_eq!(created(&signals, &collection), 0);
+    }
+
+    #[test]
+    fn declined_reuses_leave_the_collection() {
+        let _turn = take_turn();
+        let conn = Connection::new_session().unwrap();
+        let (collection, _) = create_collection(&conn, properties(&[(LABEL, "Not reused")]), "");
+        exposed(&conn, &collection);
+
+        let (_, prompt) = create_collection(
+            &conn,
+            properties(&[(LABEL, "Not reused"), ("tks:secret-checksums", "true")]),
+            "not-reused",
+        );
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        let (dismissed, result) = complete(&conn, &prompt);
+        assert!(dismissed);
+        assert!(result.is_empty());
+        let (_, custom) = exposed(&conn, &collection);
+        assert!(custom.is_empty());
+        assert_eq!(&*read_alias(&conn, "not-reused"), "/");
+    }
+
+    #[tokio::test]
+    async fn properties_get_saved() {
+        let settings = common::storage_settings("create-collection", "saved");
+        let mut storage = open_unlocked(&settings);
+        let given = HashMap::from([
+            (LABEL.to_string(), "Work mail".to_string()),
+            ("tks:visibility".to_string(), "enrolled".to_string()),
+        ]);
+        let uuid = storage.create_collection("work", "", &given).unwrap();
+        drop(storage);
+
+        let storage = Storage::open(settings.clone()).unwrap();
+        let (name, label, custom) = storage
+            .with_collection(&uuid, |c| {
+                Ok((c.name.clone(), c.label().to_string(), c.properties.clone()))
+            })
+            .unwrap();
+        assert_eq!(name, "work");
+        assert_eq!(label, "Work mail");
+        assert_eq!(
+            custom,
+            HashMap::from([("tks:visibility".to_string(), "enrolled".to_string())])
+        );
+    }
+}
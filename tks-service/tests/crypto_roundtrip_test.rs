@@ -0,0 +1,146 @@
+// These property tests encrypt arbitrary secrets through the sessions, as the clients get them,
+// and into the files of the tks_gcm backend, then damage what got encrypted. They don't need a
+// DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use openssl::bn::BigNum;
+    use openssl::dh::Dh;
+    use openssl::md::Md;
+    use openssl::pkey::Id;
+    use openssl::pkey_ctx::{HkdfMode, PkeyCtx};
+    use openssl::symm::{decrypt, encrypt, Cipher};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use tks_service::storage::aead_file;
+    use tks_service::tks_dbus::session_impl::Session;
+    use tks_service::tks_error::TksError;
+
+    const SENDER: &str = ":1.42";
+    const KEY: [u8; 32] = [7; 32];
+
+    fn secret() -> impl Strategy<Value = Vec<u8>> {
+        vec(any::<u8>(), 0..2048)
+    }
+
+    /// Negotiates a dh-ietf1024-sha256-aes128-cbc-pkcs7 session the way libsecret does, returning
+    /// it with the AES key of the client
+    fn dh_aes_session() -> (Session, Vec<u8>) {
+        let mut session = Session::new(
+            0,
+            "dh-ietf1024-sha256-aes128-cbc-pkcs7".to_string(),
+            SENDER.to_string(),
+        );
+        let p = BigNum::get_rfc2409_prime_1024().unwrap();
+        let dh = Dh::from_pqg(p, None, BigNum::from_u32(2).unwrap()).unwrap();
+        let client = dh.generate_key().unwrap();
+        let service_key = session
+            .get_shared_secret(Some(&client.public_key().to_vec()))
+            .unwrap()
+            .unwrap();
+        let shared = client
+            .compute_key(&BigNum::from_slice(&service_key).unwrap())
+            .unwrap();
+        let mut hkdf = PkeyCtx::new_id(Id::HKDF).unwrap();
+        hkdf.derive_init().unwrap();
+        hkdf.set_hkdf_mode(HkdfMode::EXTRACT_THEN_EXPAND).unwrap();
+        hkdf.set_hkdf_salt(&[0; 32]).unwrap();
+        hkdf.set_hkdf_md(Md::sha256()).unwrap();
+        hkdf.set_hkdf_key(&shared).unwrap();
+        let mut key = vec![0; 16];
+        hkdf.derive(Some(&mut key)).unwrap();
+        (session, key)
+    }
+
+    fn sealed(aad: &str, data: &[u8]) -> Vec<u8> {
+        aead_file::seal(Cipher::aes_256_gcm(), &KEY, aad, data).unwrap()
+    }
+
+    fn opened(aad: &str, encrypted: &[u8]) -> Result<Vec<u8>, TksError> {
+        aead_file::open(Cipher::aes_256_gcm(), &KEY, aad, encrypted)
+    }
+
+    proptest! {
+        #[test]
+        fn plain_sessions_pass_the_secrets_unchanged(secret in secret()) {
+            let session = Session::new(0, "plain".to_string(), SENDER.to_string());
+            let (iv, encrypted) = session.encrypt(&secret, SENDER.into()).unwrap();
+            prop_assert!(iv.is_empty());
+            prop_assert_eq!(&encrypted, &secret);
+            prop_assert_eq!(session.decrypt(&iv, &encrypted, SENDER.into()).unwrap(), secret);
+        }
+
+        #[test]
+        fn dh_aes_sessions_round_trip(secret in secret(), iv in any::<[u8; 16]>()) {
+            let (session, key) = dh_aes_session();
+            let cipher = Cipher::aes_128_cbc();
+            // what the service sends, the client reads
+            let (sent_iv, sent) = session.encrypt(&secret, SENDER.into()).unwrap();
+            prop_assert_eq!(sent.len(), (secret.len() / 16 + 1) * 16);
+            prop_assert_eq!(&decrypt(cipher, &key, Some(&sent_iv), &sent).unwrap(), &secret);
+            // and the other way round
+            let received = encrypt(cipher, &key, Some(&iv), &secret).unwrap();
+            let decrypted = session.decrypt(&iv.to_vec(), &received, SENDER.into()).unwrap();
+            prop_assert_eq!(decrypted, secret);
+        }
+
+        #[test]
+        fn damaged_session_secrets_fail_cleanly(
+            secret in secret(),
+            iv_len in 0usize..32,
+            cut in 1usize..16,
+            position in any::<prop::sample::Index>(),
+            mask in 1u8..,
+        ) {
+            let (session, key) = dh_aes_session();
+            let iv = vec![3u8; 16];
+            let mut encrypted = encrypt(Cipher::aes_128_cbc(), &key, Some(&iv), &secret).unwrap();
+            let decrypt = |iv: &Vec<u8>, encrypted: &Vec<u8>| {
+                session.decrypt(iv, encrypted, SENDER.into())
+            };
+
+            // CBC doesn't authenticate: the damaged secrets may decrypt, but never as the secret
+            let mut damaged = encrypted.clone();
+            damaged[position.index(encrypted.len())] ^= mask;
+            prop_assert_ne!(decrypt(&iv, &damaged).ok(), Some(secret.clone()));
+            let mut damaged_iv = iv.clone();
+            damaged_iv[position.index(iv.len())] ^= mask;
+            prop_assert_ne!(decrypt(&damaged_iv, &encrypted).ok(), Some(secret.clone()));
+
+            if iv_len != 16 {
+                let result = decrypt(&vec![3u8; iv_len], &encrypted);
+                prop_assert!(matches!(result, Err(TksError::CryptoError)));
+            }
+            encrypted.truncate(encrypted.len() - cut);
+            let result = decrypt(&iv, &encrypted);
+            prop_assert!(matches!(result, Err(TksError::CryptoError)));
+        }
+
+        #[test]
+        fn sealed_files_open_unchanged(data in secret(), aad in ".{0,64}") {
+            let encrypted = sealed(&aad, &data);
+            prop_assert_eq!(encrypted[0], aead_file::FORMAT_VERSION);
+            prop_assert_eq!(encrypted.len(), 1 + 12 + 16 + data.len());
+            prop_assert_eq!(opened(&aad, &encrypted).unwrap(), data);
+        }
+
+        #[test]
+        fn damaged_files_fail_cleanly(
+            data in secret(),
+            position in any::<prop::sample::Index>(),
+            mask in 1u8..,
+            length in any::<prop::sample::Index>(),
+        ) {
+            let encrypted = sealed("items", &data);
+            // the version, IV, tag or ciphertext byte got flipped
+            let mut damaged = encrypted.clone();
+            damaged[position.index(encrypted.len())] ^= mask;
+            prop_assert!(opened("items", &damaged).is_err());
+            // the file got cut short
+            let truncated = &encrypted[..length.index(encrypted.len())];
+            prop_assert!(opened("items", truncated).is_err());
+            // the file got swapped with the one of another collection
+            prop_assert!(matches!(opened("other", &encrypted), Err(TksError::CryptoError)));
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// These tests check the diagnostics counters, and that the objects inserted into crossroads get
+// counted. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use dbus_crossroads::Crossroads;
+    use std::collections::HashMap;
+use std::sync::Mutex;
+    use tks_service::tks_dbus::diagnostics::counters;
+    use tks_service::tks_dbus::object_manager::{handle, insert, object_count, remove};
+
+    #[test]
+    fn diagnostics_counters() {
+        let counters: HashMap<_, _> = counters().into_iter().collect();
+        for name in [
+            "objects",
+            "collection_handles",
+            "item_handles",
+            "sessions",
+            "prompts",
+            "virtual_memory",
+        ] {
+            assert!(counters.contains_key(name), "{} should be counted", name);
+        }
+        assert!(counters["resident_memory"] > 0);
+    }
+
+    #[test]
+    fn objects_get_counted() {
+        let mut cr = Crossroads::new();
+        let itf = cr.register::<u32, _, _>("io.linux_tks.Test", |_| {});
+        let count = object_count();
+        let path = dbus::Path::from("/io/linux_tks/test/object");
+        insert(&mut cr, path.clone(), &[itf], 1u32);
+        // the same path gets counted once
+        insert(&mut cr, path.clone(), &[itf], 2u32);
+        assert_eq!(object_count(), count + 1);
+        assert_eq!(remove::<u32>(&mut cr, &path), Some(2));
+        assert_eq!(object_count(), count);
+        assert_eq!(remove::<u32>(&mut cr, &path), None);
+        assert_eq!(object_count(), count);
+    }
+
+    #[test]
+    fn handles_get_created_once() {
+        let handles = Mutex::new(HashMap::new());
+        assert_eq!(handle(&handles, 1, || "first"), "first");
+        assert_eq!(handle(&handles, 1, || "second"), "first");
+        assert_eq!(handle(&handles, 2, || "second"), "second");
+        assert_eq!(handles.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,99 @@
+mod common;
+mod harness;
+
+// These tests open sessions and get prompts for a client having a connection of its own to the
+// bus, then close it, see the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn open_session(conn: &Connection) -> dbus::Path<'static> {
+        let (_, session): (Variant<Box<dyn RefArg>>, dbus::Path<'static>) = service_proxy(conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "OpenSession",
+                ("plain", Variant(String::new())),
+            )
+            .unwrap();
+        session
+    }
+
+    /// Whether the service still has an object implementing the interface at the path
+    fn exists(conn: &Connection, path: &dbus::Path, interface: &str) -> bool {
+        let result: Result<PropMap, _> = conn
+            .with_proxy(harness::SERVICE_NAME, path, harness::TIMEOUT)
+            .get_all(interface);
+        match result {
+            Ok(_) => true,
+            Err(e) if e.name() == Some("org.freedesktop.DBus.Error.UnknownObject") => false,
+            Err(e) => panic!("{} should answer: {}", path, e),
+        }
+    }
+
+    /// Waits for the object to get registered, which happens in the background, or to be gone
+    fn wait_until(conn: &Connection, path: &dbus::Path, interface: &str, registered: bool) {
+        let deadline = Instant::now() + harness::TIMEOUT;
+        while exists(conn, path, interface) != registered {
+            assert!(
+                Instant::now() < deadline,
+                "{} should be registered: {}",
+                path,
+                registered
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn sessions_go_away_with_their_client() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        let kept = open_session(&conn);
+        let client = Connection::new_session().unwrap();
+        let sessions = [open_session(&client), open_session(&client)];
+        for session in &sessions {
+            wait_until(&conn, session, "org.freedesktop.Secret.Session", true);
+        }
+
+        drop(client);
+        for session in &sessions {
+            wait_until(&conn, session, "org.freedesktop.Secret.Session", false);
+        }
+        wait_until(&conn, &kept, "org.freedesktop.Secret.Session", true);
+    }
+
+    #[test]
+    fn prompts_go_away_with_their_client() {
+        let collection = harness::unlocked_collection("prompted for a leaving client");
+        let conn = Connection::new_session().unwrap();
+        let objects = vec![collection.clone()];
+        let (_, _): (Vec<dbus::Path>, dbus::Path) = service_proxy(&conn)
+            .method_call("org.freedesktop.Secret.Service", "Lock", (objects.clone(),))
+            .unwrap();
+        let client = Connection::new_session().unwrap();
+        let (_, prompt): (Vec<dbus::Path>, dbus::Path<'static>) = service_proxy(&client)
+            .method_call("org.freedesktop.Secret.Service", "Unlock", (objects,))
+            .unwrap();
+        assert_ne!(&*prompt, "/");
+        wait_until(&conn, &prompt, "org.freedesktop.Secret.Prompt", true);
+
+        // the client leaves without invoking it
+        drop(client);
+        wait_until(&conn, &prompt, "org.freedesktop.Secret.Prompt", false);
+        harness::unlock_all();
+    }
+}
@@ -0,0 +1,44 @@
+mod common;
+
+// These tests check the free space guard of a storage opened in a temporary directory. They don't
+// need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked};
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::tks_error::TksError;
+
+    #[tokio::test]
+    async fn saves_fail_when_space_is_low() {
+        let settings = common::storage_settings("disk-space", "low");
+        let mut storage = open_unlocked(&settings);
+        let uuid = storage.create_collection("full", "", &HashMap::new()).unwrap();
+        let usage = storage.disk_usage().unwrap();
+        assert!(usage.used > 0);
+        if usage.available.is_none() {
+            // the filesystem of the temporary directory isn't known, e.g. in some containers
+            return;
+        }
+
+        // more megabytes than any filesystem has
+        let guarded = settings::Storage {
+            min_free_space: u64::MAX >> 20,
+            per_collection_keys: false,
+            ..settings.clone()
+        };
+        let mut storage = open_unlocked(&guarded);
+        let result = storage.modify_collection(&uuid, |c| {
+            c.label = Some("changed".to_string());
+            Ok(())
+        });
+        assert!(matches!(result, Err(TksError::StorageFull(_))));
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(
+            storage.with_collection(&uuid, |c| Ok(c.label().to_string())).unwrap(),
+            "full"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+mod common;
+
+// These tests look for duplicated items in a storage opened in a temporary directory, then delete
+// them. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn add_item(
+        storage: &mut Storage,
+        collection: &Uuid,
+        attributes: &[(&str, &str)],
+        secret: &str,
+    ) -> Uuid {
+        let session = plain_session();
+        let attributes = attributes
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    "item",
+                    attributes,
+                    (&session, vec![], secret.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn duplicates(storage: &Storage, collection: &Uuid, compare_secrets: bool) -> Vec<Vec<Uuid>> {
+        let mut groups: Vec<Vec<Uuid>> = storage
+            .find_duplicates(collection, compare_secrets)
+            .unwrap()
+            .into_iter()
+            .map(|g| {
+                let mut g: Vec<Uuid> = g.into_iter().map(|i| i.uuid).collect();
+                g.sort();
+                g
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    /// Deletes the items having the given uuids, plus the extra `missing` one when set
+    fn delete(storage: &mut Storage, collection: &Uuid, uuids: &[Uuid], missing: bool) -> bool {
+        let mut items: Vec<_> = storage
+            .collections
+            .iter()
+            .find(|c| c.uuid == *collection)
+            .unwrap()
+            .items
+            .iter()
+            .filter(|i| uuids.contains(&i.id.uuid))
+            .map(|i| i.id.clone())
+            .collect();
+        if missing {
+            let mut id = items[0].clone();
+            id.uuid = Uuid::new_v4();
+            items.push(id);
+        }
+        storage.delete_items(&items).is_ok()
+    }
+
+    #[tokio::test]
+    async fn find_and_delete_duplicates() {
+        let settings = common::storage_settings("duplicates", "find");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let a = add_item(
+            &mut storage,
+            &default,
+            &[("user", "joe"), ("xdg:creator", "kwallet")],
+            "1",
+        );
+        let b = add_item(&mut storage, &default, &[("User", " joe ")], "1");
+        let c = add_item(&mut storage, &default, &[("user", "joe")], "2");
+        add_item(&mut storage, &default, &[("user", "jane")], "1");
+
+        let mut expected = vec![a, b, c];
+        expected.sort();
+        assert_eq!(duplicates(&storage, &default, false), vec![expected]);
+
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(duplicates(&storage, &default, true), vec![expected]);
+
+        assert!(delete(&mut storage, &default, &[a, c], false));
+        let storage = open_unlocked(&settings);
+        assert!(duplicates(&storage, &default, false).is_empty());
+        let collection = storage.collections.iter().find(|c| c.uuid == default).unwrap();
+        assert_eq!(collection.items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn delete_all_or_nothing() {
+        let settings = common::storage_settings("duplicates", "batch");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let a = add_item(&mut storage, &default, &[("user", "joe")], "1");
+        assert!(!delete(&mut storage, &default, &[a], true));
+        let collection = storage.collections.iter().find(|c| c.uuid == default).unwrap();
+        assert!(collection.items.iter().any(|i| i.id.uuid == a));
+    }
+}
@@ -0,0 +1,116 @@
+mod common;
+
+// These tests let the items of collections saved in a temporary directory expire, then check
+// which ones got deleted or locked. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::storage::expiry::{EXPIRES_AT_ATTRIBUTE, ON_EXPIRY_PROPERTY};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str, expires_at: &str) -> Uuid {
+        let session = plain_session();
+        let attributes = HashMap::from([
+            ("token".to_string(), label.to_string()),
+            (EXPIRES_AT_ATTRIBUTE.to_string(), expires_at.to_string()),
+        ]);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (
+                        &session,
+                        vec![],
+                        b"secret".to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn labels(storage: &Storage, collection: &Uuid) -> Vec<String> {
+        let mut labels = storage
+            .with_collection(collection, |c| {
+                Ok(c.items.iter().map(|i| i.label.clone()).collect::<Vec<_>>())
+            })
+            .unwrap();
+        labels.sort();
+        labels
+    }
+
+    #[tokio::test]
+    async fn expired_items_get_deleted() {
+        let settings = common::storage_settings("expiry", "delete");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        add_item(&mut storage, &default, "expired", &(NOW - 1).to_string());
+        add_item(&mut storage, &default, "now", &NOW.to_string());
+        add_item(&mut storage, &default, "later", &(NOW + 60).to_string());
+        add_item(&mut storage, &default, "malformed", "tomorrow");
+
+        let expired = storage.expire_items(NOW);
+        assert_eq!(expired.deleted.len(), 2);
+        assert!(expired.locked.is_empty());
+        assert_eq!(labels(&storage, &default), vec!["later", "malformed"]);
+        assert!(storage.expire_items(NOW).deleted.is_empty());
+        drop(storage);
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(labels(&storage, &default), vec!["later", "malformed"]);
+    }
+
+    #[tokio::test]
+    async fn expired_items_may_get_locked_instead() {
+        let settings = common::storage_settings("expiry", "lock");
+        let mut storage = open_unlocked(&settings);
+        let properties = HashMap::from([(ON_EXPIRY_PROPERTY.to_string(), "lock".to_string())]);
+        let tokens = storage
+            .create_collection("tokens", "", &properties)
+            .unwrap();
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        let expired = add_item(&mut storage, &tokens, "expired", &(NOW - 1).to_string());
+
+        let outcome = storage.expire_items(NOW);
+        assert!(outcome.deleted.is_empty());
+        assert_eq!(
+            outcome.locked.iter().map(|i| i.uuid).collect::<Vec<_>>(),
+            vec![expired]
+        );
+        assert_eq!(labels(&storage, &tokens), vec!["expired"]);
+        let locked = storage
+            .with_item(&tokens, &expired, |i| Ok(i.data.is_none()))
+            .unwrap();
+        assert!(locked);
+        assert!(storage.expire_items(NOW).locked.is_empty());
+    }
+
+    #[tokio::test]
+    async fn locked_collections_keep_their_expired_items_until_unlocked() {
+        let settings = common::storage_settings("expiry", "locked");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        add_item(&mut storage, &default, "expired", &(NOW - 1).to_string());
+        storage.lock_collection(&default).unwrap();
+        assert!(storage.expire_items(NOW).deleted.is_empty());
+        assert_eq!(labels(&storage, &default), vec!["expired"]);
+
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert_eq!(storage.expire_items(NOW).deleted.len(), 1);
+        assert!(labels(&storage, &default).is_empty());
+    }
+}
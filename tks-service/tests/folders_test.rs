@@ -0,0 +1,83 @@
+mod common;
+
+// These tests organize the items of a storage opened in a temporary directory into folders. They
+// don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use tks_service::storage::folders::{normalize, PATH_ATTRIBUTE};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str, path: Option<&str>) {
+        let session = plain_session();
+        let attributes = path
+            .map(|p| HashMap::from([(PATH_ATTRIBUTE.to_string(), p.to_string())]))
+            .unwrap_or_default();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    fn labels(storage: &Storage, collection: &Uuid, folder: &str) -> (Vec<String>, Vec<String>) {
+        storage
+            .with_collection(collection, |c| {
+                let (subfolders, items) = c.list_folder(folder).unwrap();
+                let mut labels: Vec<_> = items.iter().map(|i| i.label.clone()).collect();
+                labels.sort();
+                Ok((subfolders, labels))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize("/email//work/"), "email/work");
+        assert_eq!(normalize("/"), "");
+        // normalized paths don't get copied
+        assert!(matches!(normalize("email/work"), Cow::Borrowed("email/work")));
+        assert!(matches!(normalize(""), Cow::Borrowed("")));
+    }
+
+    #[tokio::test]
+    async fn folders_get_indexed() {
+        let settings = common::storage_settings("folders", "index");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        add_item(&mut storage, &default, "root", None);
+        add_item(&mut storage, &default, "gmail", Some("email/personal"));
+        add_item(&mut storage, &default, "office", Some("/email/work/"));
+        add_item(&mut storage, &default, "vpn", Some("work"));
+
+        let expected_root = (
+            vec!["email".to_string(), "work".to_string()],
+            vec!["root".to_string()],
+        );
+        assert_eq!(labels(&storage, &default, ""), expected_root);
+        let expected_email = (
+            vec!["email/personal".to_string(), "email/work".to_string()],
+            vec![],
+        );
+        assert_eq!(labels(&storage, &default, "email"), expected_email);
+        assert_eq!(
+            labels(&storage, &default, "email/work"),
+            (vec![], vec!["office".to_string()])
+        );
+
+        // the index gets rebuilt upon loading
+        let storage = open_unlocked(&settings);
+        assert_eq!(labels(&storage, &default, ""), expected_root);
+        assert_eq!(labels(&storage, &default, "email"), expected_email);
+    }
+}
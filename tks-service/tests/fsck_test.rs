@@ -0,0 +1,160 @@
+mod common;
+
+// These tests damage the files of a storage opened in a temporary directory, then check and
+// repair it. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use tks_service::settings;
+    use tks_service::storage::fsck::{Problem, QUARANTINE_DIR};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, per_item_files: bool) -> settings::Storage {
+        settings::Storage {
+            per_item_files,
+            ..common::storage_settings("fsck", test_name)
+        }
+    }
+
+    fn open(settings: &settings::Storage) -> (Storage, Uuid) {
+        let mut storage = Storage::open(settings.clone()).expect("storage should open");
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        (storage, default)
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, service: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    service,
+                    HashMap::from([("service".to_string(), service.to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        service.as_bytes().to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    /// Flips a byte of the ciphertext, so the AEAD tag check fails
+    fn damage(path: &PathBuf) {
+        let mut data = fs::read(path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(path, data).unwrap();
+    }
+
+    fn root(settings: &settings::Storage) -> PathBuf {
+        PathBuf::from(settings.path.as_ref().unwrap())
+    }
+
+    #[tokio::test]
+    async fn clean_storage_has_no_findings() {
+        let settings = storage_settings("clean", false);
+        let (mut storage, default) = open(&settings);
+        add_item(&mut storage, &default, "mail");
+        let report = storage.fsck(false).unwrap();
+        assert!(report.findings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn damaged_items_file_gets_written_again() {
+        let settings = storage_settings("items-file", false);
+        let (mut storage, default) = open(&settings);
+        add_item(&mut storage, &default, "mail");
+        damage(&root(&settings).join("items").join("default"));
+
+        let report = storage.fsck(false).unwrap();
+        let problems: Vec<Problem> = report.findings.iter().map(|f| f.problem).collect();
+        assert_eq!(problems, vec![Problem::Secrets, Problem::MissingSecret]);
+        assert!(report.findings.iter().all(|f| !f.repaired));
+
+        // the item is still unlocked, so its secret survives
+        let report = storage.fsck(true).unwrap();
+        assert!(report.findings.iter().all(|f| f.repaired));
+        assert!(report.removed.is_empty());
+        assert_eq!(
+            fs::read_dir(root(&settings).join(QUARANTINE_DIR))
+                .unwrap()
+                .count(),
+            1
+        );
+        assert!(storage.fsck(false).unwrap().findings.is_empty());
+        drop(storage);
+
+        let (storage, default) = open(&settings);
+        let session = plain_session();
+        let secret = storage
+            .with_collection(&default, |c| {
+                Ok(c.items[0].get_secret(&session, SENDER.to_string())?.2)
+            })
+            .unwrap();
+        assert_eq!(secret, b"mail");
+    }
+
+    #[tokio::test]
+    async fn lost_secret_drops_the_item() {
+        let settings = storage_settings("item-file", true);
+        let (mut storage, default) = open(&settings);
+        add_item(&mut storage, &default, "mail");
+        let lost = add_item(&mut storage, &default, "vpn");
+        drop(storage);
+        damage(
+            &root(&settings)
+                .join("items")
+                .join("default.d")
+                .join(lost.to_string()),
+        );
+
+        // the other items still unlock, the damaged one stays without its secret
+        let (mut storage, default) = open(&settings);
+        let report = storage.fsck(true).unwrap();
+        let problems: Vec<Problem> = report.findings.iter().map(|f| f.problem).collect();
+        assert_eq!(problems, vec![Problem::Secrets, Problem::MissingSecret]);
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.removed[0].uuid, lost);
+        // the damaged file and the metadata of the dropped item
+        assert_eq!(
+            fs::read_dir(root(&settings).join(QUARANTINE_DIR))
+                .unwrap()
+                .count(),
+            2
+        );
+        drop(storage);
+
+        let (storage, default_again) = open(&settings);
+        assert_eq!(default, default_again);
+        let labels: Vec<String> = storage
+            .with_collection(&default, |c| {
+                Ok(c.items.iter().map(|i| i.label.clone()).collect())
+            })
+            .unwrap();
+        assert_eq!(labels, vec!["mail".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn locked_collections_get_skipped() {
+        let settings = storage_settings("locked", false);
+        let mut storage = Storage::open(settings.clone()).expect("storage should open");
+        let report = storage.fsck(true).unwrap();
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].problem, Problem::Skipped);
+        assert!(!report.findings[0].repaired);
+    }
+}
@@ -0,0 +1,152 @@
+mod common;
+mod harness;
+
+// These tests call the gnome-keyring-daemon extensions of the service, which also takes the
+// org.gnome.keyring name, see config/test-gnome-keyring.toml and the harness module; they only
+// need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common;
+    use crate::harness;
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use std::collections::HashMap;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const GUILT_RIDDEN: &str = "org.gnome.keyring.InternalUnsupportedGuiltRiddenInterface";
+
+    type Secret = (dbus::Path<'static>, Vec<u8>, Vec<u8>, String);
+
+    fn start() -> Connection {
+        harness::start_with_config("test-gnome-keyring.toml");
+        Connection::new_session().unwrap()
+    }
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    /// The password, as sent through a plain session
+    fn master(conn: &Connection, password: &str) -> Secret {
+        let (_, session): (Variant<Box<dyn RefArg>>, dbus::Path<'static>) = service_proxy(conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "OpenSession",
+                ("plain", Variant(String::new())),
+            )
+            .unwrap();
+        (
+            session,
+            Vec::new(),
+            password.as_bytes().to_vec(),
+            "text/plain".to_string(),
+        )
+    }
+
+    fn locked(conn: &Connection, collection: &dbus::Path) -> bool {
+        let deadline = Instant::now() + harness::TIMEOUT;
+        let proxy = conn.with_proxy(harness::SERVICE_NAME, collection, harness::TIMEOUT);
+        // the collections get registered in the background
+        loop {
+            match proxy.get("org.freedesktop.Secret.Collection", "Locked") {
+                Ok(locked) => return locked,
+                Err(e) => assert!(
+                    Instant::now() < deadline,
+                    "{} should answer: {}",
+                    collection,
+                    e
+                ),
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn lock(conn: &Connection, collection: &dbus::Path<'static>) {
+        let (_, _): (Vec<dbus::Path>, dbus::Path) = service_proxy(conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "Lock",
+                (vec![collection.clone()],),
+            )
+            .unwrap();
+        assert!(locked(conn, collection));
+    }
+
+    fn unlock_with_master_password(
+        conn: &Connection,
+        collection: &dbus::Path<'static>,
+        password: &str,
+    ) -> Result<(), dbus::Error> {
+        service_proxy(conn).method_call(
+            GUILT_RIDDEN,
+            "UnlockWithMasterPassword",
+            (collection.clone(), master(conn, password)),
+        )
+    }
+
+    #[test]
+    fn collections_get_created_with_the_password() {
+        let conn = start();
+        let mut attributes = PropMap::new();
+        attributes.insert(
+            "org.freedesktop.Secret.Collection.Label".to_string(),
+            Variant(Box::new("created by seahorse".to_string())),
+        );
+        let (collection,): (dbus::Path<'static>,) = service_proxy(&conn)
+            .method_call(
+                GUILT_RIDDEN,
+                "CreateWithMasterPassword",
+                (attributes, master(&conn, common::PASSWORD)),
+            )
+            .unwrap();
+        assert!(!locked(&conn, &collection));
+    }
+
+    #[test]
+    fn collections_get_unlocked_with_the_password() {
+        let conn = start();
+        let collection = harness::unlocked_collection("unlocked by seahorse");
+        lock(&conn, &collection);
+        assert!(unlock_with_master_password(&conn, &collection, "mistyped").is_err());
+        assert!(locked(&conn, &collection));
+        unlock_with_master_password(&conn, &collection, common::PASSWORD).unwrap();
+        assert!(!locked(&conn, &collection));
+
+        // the password stays the same
+        let result: Result<(), _> = service_proxy(&conn).method_call(
+            GUILT_RIDDEN,
+            "ChangeWithMasterPassword",
+            (
+                collection.clone(),
+                master(&conn, common::PASSWORD),
+                master(&conn, "changed"),
+            ),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn the_daemon_has_no_environment() {
+        let conn = start();
+        let daemon = conn.with_proxy(
+            "org.gnome.keyring",
+            "/org/gnome/keyring/daemon",
+            harness::TIMEOUT,
+        );
+        let (environment,): (HashMap<String, String>,) = daemon
+            .method_call("org.gnome.keyring.Daemon", "GetEnvironment", ())
+            .unwrap();
+        assert!(environment.is_empty());
+        let (directory,): (String,) = daemon
+            .method_call("org.gnome.keyring.Daemon", "GetControlDirectory", ())
+            .unwrap();
+        assert!(directory.is_empty());
+    }
+}
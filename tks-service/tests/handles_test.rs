@@ -0,0 +1,68 @@
+// These tests look the collection handles up by uuid and by each of their paths, as their aliases
+// change. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use tks_service::tks_dbus::alias_registry;
+    use tks_service::tks_dbus::collection_impl::CollectionImpl;
+    use tks_service::tks_dbus::object_manager::Handles;
+    use uuid::Uuid;
+
+    /// The handle of a collection having the given aliases, at their paths then at its own
+    fn collection(uuid: Uuid, aliases: &[&str]) -> CollectionImpl {
+        let mut paths: Vec<_> = aliases
+            .iter()
+            .filter_map(|a| alias_registry::alias_path(a))
+            .collect();
+        paths.push(alias_registry::canonical_path(&uuid));
+        CollectionImpl {
+            uuid,
+            default: false,
+            paths,
+        }
+    }
+
+    fn found(handles: &Handles<CollectionImpl>, path: &str) -> Option<Uuid> {
+        handles.find(&dbus::Path::from(path)).map(|c| c.uuid)
+    }
+
+    #[test]
+    fn handles_get_found_by_path() {
+        let mut handles = Handles::default();
+        let work = Uuid::new_v4();
+        let home = Uuid::new_v4();
+        handles.get_or_insert_with(work, || collection(work, &["login"]));
+        handles.get_or_insert_with(home, || collection(home, &[]));
+        // the handle in place stays
+        handles.get_or_insert_with(work, || collection(work, &[]));
+        assert_eq!(handles.len(), 2);
+
+        let login = "/org/freedesktop/secrets/aliases/login";
+        assert_eq!(found(&handles, login), Some(work));
+        assert_eq!(
+            found(&handles, &alias_registry::canonical_path(&home)),
+            Some(home)
+        );
+        assert_eq!(
+            found(&handles, "/org/freedesktop/secrets/aliases/none"),
+            None
+        );
+
+        // the alias moves to the other collection
+        handles.modify(&home, |c| *c = collection(home, &["login"]));
+        handles.modify(&work, |c| *c = collection(work, &[]));
+        assert_eq!(found(&handles, login), Some(home));
+
+        handles.remove(&home).unwrap();
+        assert_eq!(found(&handles, login), None);
+        assert_eq!(
+            found(&handles, &alias_registry::canonical_path(&home)),
+            None
+        );
+        assert_eq!(
+            found(&handles, &alias_registry::canonical_path(&work)),
+            Some(work)
+        );
+        assert!(handles.remove(&home).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+//! Runs tks-service for the tests of the binary including this module, on a private dbus-daemon,
+//! so that neither a session bus nor the absence of another Secret Service is needed, and with its
+//! storage in a temporary directory, unlocked with [common::PASSWORD]; the binary declares the
+//! common module as well. The service runs in a thread of its own, with a runtime outliving the
+//! ones of the `#[tokio::test]` functions, which then may run in parallel; each of them connects
+//! with [connect].
+//!
+//! Nobody types the passwords: the scripted prompter answers the dialogs of the prompts, once the
+//! test programmed it with `tks_service::tks_dbus::prompter::script`, see [prompt].
+
+// each test binary uses some of the helpers
+#![allow(dead_code)]
+
+use crate::common::{self, PASSWORD};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::message::MatchRule;
+use dbus::nonblock::{self, SyncConnection};
+use dbus::Message;
+use dbus_tokio::connection;
+use lazy_static::lazy_static;
+use secrecy::SecretString;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tks_service::settings::RunMode;
+use tks_service::storage::STORAGE;
+use tks_service::tks_dbus::prompter::{self, ScriptedAnswer};
+use tks_service::tks_dbus::start_server;
+
+pub const SERVICE_NAME: &str = "org.freedesktop.secrets";
+pub const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type ServiceProxy = nonblock::Proxy<'static, Arc<SyncConnection>>;
+
+pub struct Harness {
+    /// The runtime of the service
+    pub runtime: tokio::runtime::Handle,
+    /// The configuration file of the service, in the config directory
+    pub config: &'static str,
+}
+
+lazy_static! {
+    static ref HARNESS: Harness = Harness::start(*CONFIG.lock().unwrap());
+}
+
+/// The configuration file the service starts with, see [start_with_config]
+static CONFIG: Mutex<&str> = Mutex::new("test.toml");
+
+/// Starts the bus and the service, once for the whole test binary
+pub fn start() -> &'static Harness {
+    &HARNESS
+}
+
+/// Starts the bus and the service as [start] does, with another file of the config directory than
+/// test.toml, e.g. enabling an optional part of the service; all the tests of the binary then call
+/// it first, with the same file
+pub fn start_with_config(config: &'static str) -> &'static Harness {
+    *CONFIG.lock().unwrap() = config;
+    let harness = start();
+    assert_eq!(harness.config, config, "the service already started");
+    harness
+}
+
+impl Harness {
+    fn start(config: &'static str) -> Harness {
+        let dir = common::temp_dir("harness", "service");
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("config")
+            .join(config);
+        env::set_var("TKS_RUN_MODE", "test");
+        env::set_var("TKS_TEST_CONFIG_PATH", config_path);
+        env::set_var("TKS_TEST_STORAGE_PATH", dir.join("storage"));
+        env::set_var(RunMode::TEST_PROMPT_SCRIPT, "");
+        let _ = pretty_env_logger::try_init();
+
+        let (started, running) = mpsc::channel();
+        // the bus goes away with the thread which started it, so the thread never ends
+        thread::spawn(move || {
+            let (_daemon, address) = Harness::start_bus(&dir);
+            env::set_var("DBUS_SESSION_BUS_ADDRESS", &address);
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async move {
+                start_server().await;
+                unlock_storage();
+                started.send(tokio::runtime::Handle::current()).unwrap();
+                std::future::pending::<()>().await
+            })
+        });
+        let runtime = running
+            .recv_timeout(Duration::from_secs(30))
+            .expect("the service should start");
+        Harness::enroll();
+        Harness { runtime, config }
+    }
+
+    /// Lets the test binary in, as the user would upon its first call, so that the prompts don't
+    /// ask for it
+    fn enroll() {
+        let conn = blocking::Connection::new_session().unwrap();
+        let (_, prompt): (Vec<dbus::Path>, dbus::Path) = conn
+            .with_proxy(SERVICE_NAME, SERVICE_PATH, TIMEOUT)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "Unlock",
+                (Vec::<dbus::Path>::new(),),
+            )
+            .expect("the default collection should unlock");
+        if &*prompt != "/" {
+            prompter::script([ScriptedAnswer::Confirm(true)]);
+            assert!(!invoke(&conn, &prompt), "the test binary should get in");
+        }
+    }
+
+    /// Spawns dbus-daemon, which gets terminated once the calling thread ends, listening in `dir`;
+    /// returns it with its address
+    fn start_bus(dir: &Path) -> (Child, String) {
+        let mut daemon = Command::new("dbus-daemon");
+        daemon
+            .args(["--session", "--nofork", "--print-address"])
+            .arg(format!("--address=unix:path={}", dir.join("bus").display()))
+            .stdout(Stdio::piped());
+        // SAFETY: prctl is async-signal-safe, as pre_exec requires
+        unsafe {
+            daemon.pre_exec(|| {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+                Ok(())
+            });
+        }
+        let mut daemon = daemon
+            .spawn()
+            .expect("dbus-daemon is needed to run the service tests");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        (daemon, address.trim().to_string())
+    }
+}
+
+/// Lets the test binary in again, e.g. after a test revoked it
+pub fn enroll() {
+    start();
+    Harness::enroll();
+}
+
+/// Connects to the private bus from the runtime of the calling test
+pub fn connect() -> Arc<SyncConnection> {
+    start();
+    let (resource, conn) = connection::new_session_sync().unwrap();
+    tokio::spawn(async {
+        let err = resource.await;
+        panic!("Lost connection to D-Bus: {}", err);
+    });
+    conn
+}
+
+pub fn service_proxy(conn: Arc<SyncConnection>) -> ServiceProxy {
+    nonblock::Proxy::new(SERVICE_NAME, SERVICE_PATH, TIMEOUT, conn)
+}
+
+/// Unlocks all the collections, as the unlock prompt would
+pub fn unlock_all() {
+    let _service = start().runtime.enter();
+    unlock_storage();
+}
+
+fn unlock_storage() {
+    STORAGE
+        .write()
+        .unwrap()
+        .unlock_with_password(SecretString::new(PASSWORD.into()))
+        .expect("the test storage should unlock");
+}
+
+/// Creates a collection and unlocks it, returning its path once the service answers at it
+pub fn unlocked_collection(label: &str) -> dbus::Path<'static> {
+    start();
+    let conn = blocking::Connection::new_session().unwrap();
+    let mut properties = PropMap::new();
+    properties.insert(
+        "org.freedesktop.Secret.Collection.Label".to_string(),
+        Variant(Box::new(label.to_string())),
+    );
+    let (collection, prompt): (dbus::Path<'static>, dbus::Path<'static>) = conn
+        .with_proxy(SERVICE_NAME, SERVICE_PATH, TIMEOUT)
+        .method_call(
+            "org.freedesktop.Secret.Service",
+            "CreateCollection",
+            (properties, ""),
+        )
+        .expect("the collection should get created");
+    assert_eq!(&*prompt, "/", "creating {} should not prompt", label);
+    // the object gets registered in the background
+    let deadline = Instant::now() + TIMEOUT;
+    let proxy = conn.with_proxy(SERVICE_NAME, &collection, TIMEOUT);
+    while proxy
+        .get::<bool>("org.freedesktop.Secret.Collection", "Locked")
+        .is_err()
+    {
+        assert!(
+            Instant::now() < deadline,
+            "{} should get registered",
+            collection
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    unlock_all();
+    collection
+}
+
+/// Stores the secret in the collection, through a plain session, with the label as its `service`
+/// attribute too; returns the path of the item once the service answers at it
+pub fn created_item(collection: &dbus::Path, label: &str, secret: &[u8]) -> dbus::Path<'static> {
+    start();
+    let conn = blocking::Connection::new_session().unwrap();
+    let (_, session): (Variant<Box<dyn RefArg>>, dbus::Path<'static>) = conn
+        .with_proxy(SERVICE_NAME, SERVICE_PATH, TIMEOUT)
+        .method_call(
+            "org.freedesktop.Secret.Service",
+            "OpenSession",
+            ("plain", Variant(String::new())),
+        )
+        .expect("the session should open");
+    let mut properties = PropMap::new();
+    properties.insert(
+        "org.freedesktop.Secret.Item.Label".to_string(),
+        Variant(Box::new(label.to_string())),
+    );
+    let attributes = HashMap::from([("service".to_string(), label.to_string())]);
+    properties.insert(
+        "org.freedesktop.Secret.Item.Attributes".to_string(),
+        Variant(Box::new(attributes)),
+    );
+    let secret = (session, Vec::<u8>::new(), secret.to_vec(), "text/plain");
+    let (item, prompt): (dbus::Path<'static>, dbus::Path<'static>) = conn
+        .with_proxy(SERVICE_NAME, collection, TIMEOUT)
+        .method_call(
+            "org.freedesktop.Secret.Collection",
+            "CreateItem",
+            (properties, secret, false),
+        )
+        .expect("the item should get created");
+    assert_eq!(&*prompt, "/", "creating {} should not prompt", label);
+    // the object gets registered in the background
+    let deadline = Instant::now() + TIMEOUT;
+    let proxy = conn.with_proxy(SERVICE_NAME, &item, TIMEOUT);
+    while proxy
+        .get::<bool>("org.freedesktop.Secret.Item", "Locked")
+        .is_err()
+    {
+        assert!(Instant::now() < deadline, "{} should get registered", item);
+        thread::sleep(Duration::from_millis(10));
+    }
+    item
+}
+
+/// Invokes the prompt, returning whether it completed dismissed
+pub fn prompt(prompt: &dbus::Path) -> bool {
+    start();
+    invoke(&blocking::Connection::new_session().unwrap(), prompt)
+}
+
+fn invoke(conn: &blocking::Connection, prompt: &dbus::Path) -> bool {
+    let (completed, dismissed) = mpsc::channel();
+    let rule = MatchRule::new_signal("org.freedesktop.Secret.Prompt", "Completed")
+        .with_path(prompt.clone().into_static());
+    conn.add_match(
+        rule,
+        move |(dismissed, _): (bool, Variant<Box<dyn RefArg>>), _, _| {
+            let _ = completed.send(dismissed);
+            false
+        },
+    )
+    .unwrap();
+    start_prompt(conn, prompt);
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        conn.process(Duration::from_millis(10)).unwrap();
+        if let Ok(dismissed) = dismissed.try_recv() {
+            return dismissed;
+        }
+        assert!(Instant::now() < deadline, "{} should complete", prompt);
+    }
+}
+
+/// Invokes the prompt, without waiting for it to complete
+pub fn start_prompt(conn: &blocking::Connection, prompt: &dbus::Path) {
+    let deadline = Instant::now() + TIMEOUT;
+    let proxy = conn.with_proxy(SERVICE_NAME, prompt, TIMEOUT);
+    // the object gets registered in the background
+    while let Err(e) =
+        proxy.method_call::<(), _, _, _>("org.freedesktop.Secret.Prompt", "Prompt", ("",))
+    {
+        let unknown = e.name() == Some("org.freedesktop.DBus.Error.UnknownObject");
+        assert!(
+            unknown && Instant::now() < deadline,
+            "the prompt should get invoked: {}",
+            e
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Subscribes the connection to the signals matching the rule, see [next_signal]
+pub fn watch(conn: &blocking::Connection, rule: MatchRule<'static>) -> mpsc::Receiver<Message> {
+    let (signals, received) = mpsc::channel();
+    conn.add_match(rule, move |_: (), _, message| {
+        let _ = signals.send(message.duplicate().unwrap());
+        true
+    })
+    .unwrap();
+    received
+}
+
+/// Processes the incoming messages of the connection until one of the watched signals arrives
+pub fn next_signal(conn: &blocking::Connection, signals: &mpsc::Receiver<Message>) -> Message {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        if let Ok(signal) = signals.try_recv() {
+            return signal;
+        }
+        assert!(Instant::now() < deadline, "the signal should arrive");
+        conn.process(Duration::from_millis(10)).unwrap();
+    }
+}
@@ -0,0 +1,13 @@
+// These tests check the hex encoding of the digests, tokens and checksums. They don't need a DBus
+// session bus.
+//
+#[cfg(test)]
+mod tests {
+    use tks_service::hex;
+
+    #[test]
+    fn bytes_get_two_lowercase_digits() {
+        assert_eq!(hex::encode(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+        assert_eq!(hex::encode(&[]), "");
+    }
+}
@@ -0,0 +1,113 @@
+mod common;
+
+// These tests change the items of a collection saved in a temporary directory, then reopen it to
+// check the history it kept. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use tks_service::storage::history::{HistoryOperation, HISTORY_LENGTH};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    const SECRET: &str = "history-test-secret";
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    HashMap::from([("label".to_string(), label.to_string())]),
+                    (&session, vec![], SECRET.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn set_label(storage: &mut Storage, collection: &Uuid, item: &Uuid, label: &str) {
+        storage
+            .modify_item(collection, item, |i| {
+                i.label = label.to_string();
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    fn history(storage: &Storage, collection: &Uuid) -> Vec<(HistoryOperation, Uuid, String)> {
+        storage
+            .with_collection(collection, |c| {
+                Ok(c.history
+                    .iter()
+                    .map(|e| (e.operation, e.item, e.label.clone()))
+                    .collect())
+            })
+            .unwrap()
+    }
+
+    /// Contents of the files under the directory
+    fn files(dir: &Path) -> Vec<Vec<u8>> {
+        let mut contents = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => contents.extend(files(&path)),
+                false => contents.push(fs::read(&path).unwrap()),
+            }
+        }
+        contents
+    }
+
+    #[tokio::test]
+    async fn item_operations_get_saved() {
+        let settings = common::storage_settings("history", "saved");
+        let mut storage = open_unlocked(&settings);
+        let collection = storage.create_collection("apps", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let vpn = add_item(&mut storage, &collection, "VPN");
+        let mail = add_item(&mut storage, &collection, "Mail");
+        set_label(&mut storage, &collection, &vpn, "Work VPN");
+        storage.modify_collection(&collection, |c| c.delete_item(&mail)).unwrap();
+        drop(storage);
+
+        let storage = Storage::open(settings.clone()).unwrap();
+        assert_eq!(
+            history(&storage, &collection),
+            vec![
+                (HistoryOperation::Created, vpn, "VPN".to_string()),
+                (HistoryOperation::Created, mail, "Mail".to_string()),
+                (HistoryOperation::Modified, vpn, "Work VPN".to_string()),
+                (HistoryOperation::Deleted, mail, "Mail".to_string()),
+            ]
+        );
+        // the history lives in the metadata, which never holds the secrets
+        let files = files(Path::new(&settings.path.unwrap()));
+        assert!(files.iter().any(|f| f.windows(8).any(|w| w == b"Work VPN")));
+        assert!(!files.iter().any(|f| f.windows(SECRET.len()).any(|w| w == SECRET.as_bytes())));
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_operations_get_kept() {
+        let settings = common::storage_settings("history", "latest");
+        let mut storage = open_unlocked(&settings);
+        let collection = storage.create_collection("apps", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let item = add_item(&mut storage, &collection, "token");
+        for i in 0..HISTORY_LENGTH {
+            set_label(&mut storage, &collection, &item, &format!("token {}", i));
+        }
+
+        let history = history(&storage, &collection);
+        assert_eq!(history.len(), HISTORY_LENGTH);
+        assert!(history.iter().all(|(op, _, _)| *op == HistoryOperation::Modified));
+        let last = format!("token {}", HISTORY_LENGTH - 1);
+        assert_eq!(history.last().unwrap().2, last);
+    }
+}
@@ -0,0 +1,60 @@
+// These tests introspect the objects inserted into crossroads the way the service inserts them,
+// calling them without a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use dbus::arg::PropMap;
+    use dbus::Message;
+    use dbus_crossroads::Crossroads;
+    use std::cell::RefCell;
+    use tks_service::tks_dbus::object_manager::insert;
+
+    const OBJECT: &str = "/io/linux_tks/test/collection/object";
+
+    fn dispatch(cr: &mut Crossroads, mut msg: Message) -> Message {
+        msg.set_serial(1);
+        let replies = RefCell::new(Vec::new());
+        cr.handle_message(msg, &replies).unwrap();
+        let mut reply = replies.into_inner().pop().unwrap();
+        reply.as_result().unwrap();
+        reply
+    }
+
+    fn introspect(cr: &mut Crossroads, path: &str) -> String {
+        let interface = "org.freedesktop.DBus.Introspectable";
+        let msg = Message::new_method_call("io.linux_tks.Test", path, interface, "Introspect");
+        dispatch(cr, msg.unwrap()).read1().unwrap()
+    }
+
+    fn get_all(cr: &mut Crossroads, path: &str) -> PropMap {
+        let interface = "org.freedesktop.DBus.Properties";
+        let msg = Message::new_method_call("io.linux_tks.Test", path, interface, "GetAll");
+        dispatch(cr, msg.unwrap().append1("")).read1().unwrap()
+    }
+
+    fn crossroads() -> Crossroads {
+        let mut cr = Crossroads::new();
+        let itf = cr.register::<u32, _, _>("io.linux_tks.Test", |_| {});
+        insert(&mut cr, OBJECT.into(), &[itf], 1u32);
+        cr
+    }
+
+    #[test]
+    fn parents_can_be_introspected() {
+        let mut cr = crossroads();
+        assert!(introspect(&mut cr, "/").contains(r#"<node name="io"/>"#));
+        let parent = introspect(&mut cr, "/io/linux_tks/test/collection");
+        assert!(parent.contains(r#"<node name="object"/>"#));
+        let object = introspect(&mut cr, OBJECT);
+        assert!(object.contains(r#"<interface name="io.linux_tks.Test">"#));
+        assert!(object.contains(r#"<interface name="org.freedesktop.DBus.Properties">"#));
+    }
+
+    #[test]
+    fn objects_without_properties_have_get_all() {
+        let mut cr = crossroads();
+        for path in [OBJECT, "/io/linux_tks"] {
+            assert!(get_all(&mut cr, path).is_empty());
+        }
+    }
+}
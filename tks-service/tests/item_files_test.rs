@@ -0,0 +1,185 @@
+mod common;
+
+// These tests store the item secrets in their own files, in a storage opened in a temporary
+// directory, then reopen it to check what got saved. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use tks_service::settings;
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, per_item_files: bool) -> settings::Storage {
+        settings::Storage {
+            per_item_files,
+            ..common::storage_settings("item-files", test_name)
+        }
+    }
+
+    fn items_path(settings: &settings::Storage, name: &str) -> PathBuf {
+        let mut path = PathBuf::from(settings.path.as_ref().unwrap());
+        path.push("items");
+        path.push(name);
+        path
+    }
+
+    fn item_file(settings: &settings::Storage, name: &str, item: &Uuid) -> PathBuf {
+        let mut path = items_path(settings, name).into_os_string();
+        path.push(".d");
+        let mut path = PathBuf::from(path);
+        path.push(item.to_string());
+        path
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) -> Uuid {
+        let session = plain_session();
+        let attributes = HashMap::from([("label".to_string(), label.to_string())]);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    /// The secret of an unlocked item, or None when the item is locked
+    fn secret(storage: &Storage, collection: &Uuid, item: &Uuid) -> Option<Vec<u8>> {
+        let session = plain_session();
+        storage
+            .with_collection(collection, |c| {
+                let item = c.get_item(item)?;
+                if item.locked {
+                    return Ok(None);
+                }
+                Ok(Some(item.get_secret(&session, SENDER.to_string())?.2))
+            })
+            .unwrap()
+    }
+
+    fn prepare(settings: &settings::Storage) -> (Storage, Uuid, Uuid, Uuid) {
+        let mut storage = open_unlocked(settings);
+        let collection = storage.create_collection("files", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let first = add_item(&mut storage, &collection, "first");
+        let second = add_item(&mut storage, &collection, "second");
+        (storage, collection, first, second)
+    }
+
+    #[tokio::test]
+    async fn items_get_their_own_files() {
+        let settings = storage_settings("own-files", true);
+        let (mut storage, collection, first, second) = prepare(&settings);
+        assert!(!items_path(&settings, "files").exists());
+        assert!(item_file(&settings, "files", &first).exists());
+
+        storage
+            .modify_collection(&collection, |c| c.delete_item(&second).map(|_| ()))
+            .unwrap();
+        assert!(!item_file(&settings, "files", &second).exists());
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(secret(&storage, &collection, &first), Some(b"first".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn damaged_item_file_only_locks_its_item() {
+        let settings = storage_settings("damaged", true);
+        let (_, collection, first, second) = prepare(&settings);
+
+        let path = item_file(&settings, "files", &second);
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(secret(&storage, &collection, &first), Some(b"first".to_vec()));
+        assert_eq!(secret(&storage, &collection, &second), None);
+    }
+
+    #[tokio::test]
+    async fn items_file_gets_migrated() {
+        let settings = storage_settings("migrate", false);
+        let (_, collection, first, second) = prepare(&settings);
+        assert!(items_path(&settings, "files").exists());
+
+        let settings = settings::Storage {
+            per_item_files: true,
+            ..settings
+        };
+        let storage = open_unlocked(&settings);
+        assert!(!items_path(&settings, "files").exists());
+        assert!(item_file(&settings, "files", &second).exists());
+        assert_eq!(secret(&storage, &collection, &first), Some(b"first".to_vec()));
+        assert_eq!(secret(&storage, &collection, &second), Some(b"second".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn padded_item_files_hide_the_secret_length() {
+        let settings = settings::Storage {
+            pad_item_files: true,
+            ..storage_settings("padded", true)
+        };
+        let (mut storage, collection, first, _) = prepare(&settings);
+        let long = add_item(&mut storage, &collection, &"long".repeat(25));
+        let size = |item| fs::metadata(item_file(&settings, "files", item)).unwrap().len();
+        assert_eq!(size(&first), size(&long));
+
+        // padded files get read back whether padding is still on or not
+        let settings = settings::Storage {
+            pad_item_files: false,
+            ..settings
+        };
+        let storage = open_unlocked(&settings);
+        assert_eq!(secret(&storage, &collection, &first), Some(b"first".to_vec()));
+        assert_eq!(secret(&storage, &collection, &long), Some("long".repeat(25).into_bytes()));
+    }
+
+    #[tokio::test]
+    async fn binary_secrets_round_trip() {
+        // every byte value, ending with the spaces the padding is made of
+        let mut payload: Vec<u8> = (0..=255u8).collect();
+        payload.extend_from_slice(b"\0  ");
+        let content_type = "application/octet-stream";
+        let session = plain_session();
+        for (name, per_item_files) in [("binary-files", true), ("binary-items", false)] {
+            let settings = settings::Storage {
+                pad_item_files: per_item_files,
+                ..storage_settings(name, per_item_files)
+            };
+            let (mut storage, collection, _, _) = prepare(&settings);
+            let item = storage
+                .modify_collection(&collection, |c| {
+                    c.create_item(
+                        "ssh key",
+                        HashMap::new(),
+                        (&session, vec![], payload.clone(), content_type.to_string()),
+                        false,
+                        SENDER.to_string(),
+                    )
+                })
+                .unwrap()
+                .uuid;
+
+            let storage = open_unlocked(&settings);
+            let (_, _, secret, stored_type) = storage
+                .with_collection(&collection, |c| {
+                    c.get_item(&item)?.get_secret(&session, SENDER.to_string())
+                })
+                .unwrap();
+            assert_eq!(secret, payload);
+            assert_eq!(stored_type, content_type);
+        }
+    }
+}
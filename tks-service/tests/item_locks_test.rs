@@ -0,0 +1,102 @@
+mod common;
+
+// These tests lock items of a storage opened in a temporary directory, on their own or along with
+// their collection, then reopen it to check what got saved. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn attributes() -> HashMap<String, String> {
+        HashMap::from([("service".to_string(), "imap".to_string())])
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, secret: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    secret,
+                    attributes(),
+                    (
+                        &session,
+                        vec![],
+                        secret.as_bytes().to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn secret(storage: &Storage, collection: &Uuid, item: &Uuid) -> String {
+        let session = plain_session();
+        let secret = storage
+            .with_item(collection, item, |i| i.get_secret(&session, SENDER.into()))
+            .unwrap();
+        String::from_utf8(secret.2).unwrap()
+    }
+
+    fn locked(storage: &Storage, collection: &Uuid, item: &Uuid) -> bool {
+        storage
+            .with_item(collection, item, |i| Ok(i.locked))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn loaded_items_are_locked() {
+        let settings = common::storage_settings("item-locks", "loaded");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let item = add_item(&mut storage, &default, "imap");
+        assert!(!locked(&storage, &default, &item));
+        drop(storage);
+
+        let mut storage = Storage::open(settings.clone()).unwrap();
+        assert!(storage.with_collection(&default, |c| Ok(c.locked)).unwrap());
+        assert!(locked(&storage, &default, &item));
+        let found = storage.search_items(&attributes());
+        assert_eq!(found.len(), 1);
+        assert!(found.iter().all(|i| i.locked));
+
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert!(!locked(&storage, &default, &item));
+        assert!(storage
+            .search_items(&attributes())
+            .iter()
+            .all(|i| !i.locked));
+        assert_eq!(secret(&storage, &default, &item), "imap");
+    }
+
+    #[tokio::test]
+    async fn locked_items_keep_their_secrets() {
+        let settings = common::storage_settings("item-locks", "kept");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let first = add_item(&mut storage, &default, "first");
+        let id = storage
+            .with_item(&default, &first, |i| Ok(i.id.clone()))
+            .unwrap();
+        storage.lock_item(&id).unwrap();
+        assert!(locked(&storage, &default, &first));
+        assert!(storage
+            .with_collection(&default, |c| Ok(!c.locked))
+            .unwrap());
+
+        // saving the collection writes the secret of the locked item back as it was
+        let second = add_item(&mut storage, &default, "second");
+        drop(storage);
+        let storage = open_unlocked(&settings);
+        assert_eq!(secret(&storage, &default, &first), "first");
+        assert_eq!(secret(&storage, &default, &second), "second");
+    }
+}
@@ -0,0 +1,140 @@
+// These tests check the item attributes against the built-in, configured and registered schemas.
+// They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use tks_service::settings::{AttributeType, ItemSchema, Settings};
+    use tks_service::storage::item_schemas;
+    use tks_service::tks_error::TksError;
+
+    fn attributes(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(a, v)| (a.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn schema(name: &str, attributes: &[(&str, AttributeType)]) -> ItemSchema {
+        ItemSchema {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(a, t)| (a.to_string(), *t))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn builtin_schemas_get_checked() {
+        let network = |entries: &[(&str, &str)]| {
+            let mut entries = entries.to_vec();
+            entries.push(("xdg:schema", "org.gnome.keyring.NetworkPassword"));
+            item_schemas::check(&[], &attributes(&entries))
+        };
+        network(&[
+            ("server", "imap.example.com"),
+            ("port", "993"),
+            ("tks:path", "Mail"),
+        ])
+        .unwrap();
+        let result = network(&[("server", "imap.example.com"), ("port", "imaps")]);
+        assert!(matches!(result, Err(TksError::SchemaViolation(_))));
+        let result = network(&[("sever", "imap.example.com")]);
+        assert!(matches!(result, Err(TksError::SchemaViolation(_))));
+
+        // the items naming no schema, or an unknown one, keep any attributes
+        item_schemas::check(&[], &attributes(&[("anything", "goes")])).unwrap();
+        let unknown = attributes(&[("xdg:schema", "org.example.Unknown"), ("port", "imaps")]);
+        item_schemas::check(&[], &unknown).unwrap();
+    }
+
+    #[test]
+    fn configured_schemas_get_checked() {
+        let configured = vec![schema(
+            "org.example.Database",
+            &[
+                ("host", AttributeType::String),
+                ("read-only", AttributeType::Boolean),
+            ],
+        )];
+        let database = |read_only: &str| {
+            attributes(&[
+                ("xdg:schema", "org.example.Database"),
+                ("read-only", read_only),
+            ])
+        };
+        item_schemas::check(&configured, &database("true")).unwrap();
+        let result = item_schemas::check(&configured, &database("yes"));
+        assert!(matches!(result, Err(TksError::SchemaViolation(_))));
+        item_schemas::check(&[], &database("yes")).unwrap();
+    }
+
+    #[test]
+    fn schemas_get_registered() {
+        let configured = vec![schema("org.example.Configured", &[])];
+        let vault = schema("org.example.Vault", &[("token-id", AttributeType::Integer)]);
+        item_schemas::register(&configured, vault.clone()).unwrap();
+        // the applications register their schema upon each start
+        item_schemas::register(&configured, vault.clone()).unwrap();
+        assert_eq!(
+            item_schemas::find(&configured, "org.example.Vault"),
+            Some(vault)
+        );
+        let token = attributes(&[("xdg:schema", "org.example.Vault"), ("token-id", "x")]);
+        let result = item_schemas::check(&configured, &token);
+        assert!(matches!(result, Err(TksError::SchemaViolation(_))));
+
+        let note = schema("org.gnome.keyring.Note", &[("body", AttributeType::String)]);
+        let result = item_schemas::register(&configured, note);
+        assert!(matches!(result, Err(TksError::Duplicate)));
+        let result = item_schemas::register(&configured, schema("org.example.Configured", &[]));
+        assert!(result.is_ok());
+        let meta = schema(
+            "org.example.Meta",
+            &[("xdg:creator", AttributeType::String)],
+        );
+        let result = item_schemas::register(&configured, meta);
+        assert!(matches!(result, Err(TksError::ParameterError)));
+    }
+
+    #[test]
+    fn configured_schemas_get_validated() {
+        let mut path = env::temp_dir();
+        path.push(format!("tks-item-schemas-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[storage]\nkind = \"tks_gcm\"\n\
+             [[schemas]]\nname = \"org.example.Database\"\n\
+             attributes = { host = \"string\", port = \"integer\" }\n\
+             [[schemas]]\nname = \"org.gnome.keyring.Note\"\n\
+             [[schemas]]\nname = \"org.example.Other\"\n\
+             attributes = { \"xdg:creator\" = \"string\" }\n",
+        )
+        .unwrap();
+        let problems = Settings::check(&path.to_string_lossy()).expect_err("should be invalid");
+        let settings: Vec<_> = problems.iter().map(|p| p.setting.as_str()).collect();
+        assert_eq!(settings, vec!["schemas[1].name", "schemas[2].attributes"]);
+
+        fs::write(
+            &path,
+            "[storage]\nkind = \"tks_gcm\"\n\
+             [[schemas]]\nname = \"org.example.Database\"\n\
+             attributes = { host = \"string\", port = \"integer\" }\n",
+        )
+        .unwrap();
+        let settings = Settings::check(&path.to_string_lossy()).expect("should be valid");
+        assert_eq!(
+            settings.schemas,
+            vec![schema(
+                "org.example.Database",
+                &[
+                    ("host", AttributeType::String),
+                    ("port", AttributeType::Integer)
+                ]
+            )]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+mod common;
+
+// These tests unlock the collections of a storage opened in a temporary directory again with
+// their cached key, without prompting. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    const TTL: Duration = Duration::from_secs(600);
+
+    fn open_unlocked(test_name: &str) -> Storage {
+        common::open_unlocked(&common::storage_settings("key-cache", test_name))
+    }
+
+    fn locked(storage: &Storage, uuid: &Uuid) -> bool {
+        storage
+            .collections
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .unwrap()
+            .locked
+    }
+
+    #[tokio::test]
+    async fn cached_keys_unlock_again() {
+        let mut storage = open_unlocked("unlock");
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let session = plain_session();
+        let item = storage
+            .modify_collection(&default, |c| {
+                c.create_item(
+                    "mail",
+                    HashMap::new(),
+                    (
+                        &session,
+                        vec![],
+                        b"secret".to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+
+        storage.lock_collection(&default).unwrap();
+        // the cache is disabled
+        assert!(!storage
+            .unlock_with_cached_key(&default, Duration::ZERO)
+            .unwrap());
+        assert!(locked(&storage, &default));
+        assert!(storage.unlock_item_with_cached_key(&item, TTL).unwrap());
+        assert!(locked(&storage, &default));
+        assert!(storage.unlock_with_cached_key(&default, TTL).unwrap());
+        assert!(!locked(&storage, &default));
+        let collection = storage
+            .collections
+            .iter()
+            .find(|c| c.uuid == default)
+            .unwrap();
+        let secret = collection.items[0]
+            .get_secret(&session, SENDER.to_string())
+            .unwrap()
+            .2;
+        assert_eq!(secret, b"secret".to_vec());
+
+        storage.lock_collection(&default).unwrap();
+        storage.forget_keys().unwrap();
+        assert!(!storage.unlock_with_cached_key(&default, TTL).unwrap());
+        assert!(!storage.unlock_item_with_cached_key(&item, TTL).unwrap());
+        assert!(locked(&storage, &default));
+
+        // the password unlocking the storage caches its key again
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        storage.lock_collection(&default).unwrap();
+        assert!(storage.unlock_with_cached_key(&default, TTL).unwrap());
+    }
+
+    #[tokio::test]
+    async fn forgetting_drops_the_keys() {
+        let mut storage = open_unlocked("forget");
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        storage.write_private_file("forget", b"private").unwrap();
+
+        // the unlocked collections get locked, as nothing could save them anymore
+        storage.forget_keys().unwrap();
+        assert!(locked(&storage, &default));
+        assert!(!storage
+            .unlock_with_cached_key(&default, Duration::MAX)
+            .unwrap());
+        assert!(matches!(
+            storage.read_private_file("forget"),
+            Err(TksError::PermissionDenied)
+        ));
+        assert!(matches!(
+            storage.write_private_file("forget", b"other"),
+            Err(TksError::PermissionDenied)
+        ));
+
+        // a wrong password doesn't get the key back
+        assert!(storage
+            .unlock_with_password(SecretString::new("wrong".into()))
+            .is_err());
+        assert!(storage.read_private_file("forget").is_err());
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert!(!locked(&storage, &default));
+        assert_eq!(
+            storage.read_private_file("forget").unwrap(),
+            Some(b"private".to_vec())
+        );
+    }
+}
@@ -0,0 +1,154 @@
+mod common;
+
+// These tests unlock a storage opened in a temporary directory with the key file configured for
+// its default collection, instead of the password. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use tks_service::settings;
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    const KEYFILE: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    /// The settings giving the default collection a key file, next to the storage directory
+    fn keyfile_settings(test_name: &str) -> (settings::Storage, PathBuf) {
+        let settings = common::storage_settings("keyfile", test_name);
+        let keyfile = PathBuf::from(format!("{}.key", settings.path.as_ref().unwrap()));
+        fs::write(&keyfile, KEYFILE).unwrap();
+        let keyfiles = HashMap::from([("default".to_string(), keyfile.to_string_lossy().into())]);
+        (
+            settings::Storage {
+                keyfiles,
+                ..settings
+            },
+            keyfile,
+        )
+    }
+
+    fn keyslot(settings: &settings::Storage) -> PathBuf {
+        PathBuf::from(settings.path.as_ref().unwrap()).join("keyslots/default")
+    }
+
+    fn default_collection(storage: &Storage) -> Uuid {
+        Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap()
+    }
+
+    fn locked(storage: &Storage, collection: &Uuid) -> bool {
+        storage
+            .with_collection(collection, |c| Ok(c.locked))
+            .unwrap()
+    }
+
+    /// Stores a secret in the default collection, unlocked with the password
+    fn prepare(settings: &settings::Storage) -> (Uuid, Uuid) {
+        let mut storage = open_unlocked(settings);
+        let collection = default_collection(&storage);
+        assert_eq!(
+            storage
+                .with_collection(&collection, |c| Ok(c.label().to_string()))
+                .unwrap(),
+            "default"
+        );
+        let session = plain_session();
+        let item = storage
+            .modify_collection(&collection, |c| {
+                c.create_item(
+                    "mail",
+                    HashMap::from([("service".to_string(), "imap".to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        b"hunter2".to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid;
+        (collection, item)
+    }
+
+    #[tokio::test]
+    async fn unlocking_enrolls_the_keyfile() {
+        let (settings, _) = keyfile_settings("enrolled");
+        assert!(!keyslot(&settings).exists());
+        prepare(&settings);
+        assert!(keyslot(&settings).exists());
+    }
+
+    #[tokio::test]
+    async fn keyfiles_unlock_the_storage() {
+        let (settings, _) = keyfile_settings("unlock");
+        let (collection, item) = prepare(&settings);
+
+        let mut storage = Storage::open(settings.clone()).unwrap();
+        assert!(locked(&storage, &collection));
+        storage.unlock_backend_with_keyfile(&collection).unwrap();
+        assert!(!locked(&storage, &collection));
+        let session = plain_session();
+        let secret = storage
+            .with_item(&collection, &item, |i| {
+                i.get_secret(&session, SENDER.into())
+            })
+            .unwrap();
+        assert_eq!(secret.2, b"hunter2");
+    }
+
+    #[tokio::test]
+    async fn missing_keyfiles_leave_the_password() {
+        let (settings, keyfile) = keyfile_settings("missing");
+        fs::remove_file(&keyfile).unwrap();
+        let (collection, _) = prepare(&settings);
+        // the key slot only gets enrolled once the key file shows up
+        assert!(!keyslot(&settings).exists());
+
+        let mut storage = Storage::open(settings.clone()).unwrap();
+        assert!(storage.unlock_backend_with_keyfile(&collection).is_err());
+        assert!(locked(&storage, &collection));
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert!(!locked(&storage, &collection));
+
+        // nor does a key file gone after its enrollment keep the password from unlocking
+        fs::write(&keyfile, KEYFILE).unwrap();
+        drop(open_unlocked(&settings));
+        assert!(keyslot(&settings).exists());
+        fs::remove_file(&keyfile).unwrap();
+        let mut storage = Storage::open(settings.clone()).unwrap();
+        assert!(storage.unlock_backend_with_keyfile(&collection).is_err());
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert!(!locked(&storage, &collection));
+    }
+
+    #[tokio::test]
+    async fn wrong_keyfiles_get_rejected() {
+        let (settings, keyfile) = keyfile_settings("wrong");
+        let (collection, _) = prepare(&settings);
+
+        let truncated = &KEYFILE[..KEYFILE.len() / 2];
+        let other = b"fedcba9876543210fedcba9876543210".as_slice();
+        for contents in [truncated, other, b"".as_slice()] {
+            fs::write(&keyfile, contents).unwrap();
+            let mut storage = Storage::open(settings.clone()).unwrap();
+            assert!(storage.unlock_backend_with_keyfile(&collection).is_err());
+            assert!(locked(&storage, &collection));
+        }
+
+        // the key slot still lets the right key file in
+        fs::write(&keyfile, KEYFILE).unwrap();
+        let mut storage = Storage::open(settings.clone()).unwrap();
+        storage.unlock_backend_with_keyfile(&collection).unwrap();
+        assert!(!locked(&storage, &collection));
+    }
+}
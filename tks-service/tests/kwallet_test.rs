@@ -0,0 +1,52 @@
+// These tests check the QDataStream encoding the KWallet interface uses for its passwords and
+// maps, and the wildcard patterns of its *List methods. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use tks_service::tks_dbus::kwallet_impl::*;
+
+    #[test]
+    fn test_qstring() {
+        assert_eq!(encode_qstring("ab"), vec![0, 0, 0, 4, 0, b'a', 0, b'b']);
+        assert_eq!(decode_qstring(&encode_qstring("pässwörd 🔑")).unwrap(), "pässwörd 🔑");
+        assert_eq!(decode_qstring(&encode_qstring("")).unwrap(), "");
+        // a null QString
+        assert_eq!(decode_qstring(&[0xff, 0xff, 0xff, 0xff]).unwrap(), "");
+        assert!(decode_qstring(&[0, 0, 0, 4, 0, b'a']).is_err());
+        assert!(decode_qstring(&[0, 0, 0, 3, 0, b'a', 0]).is_err());
+    }
+
+    #[test]
+    fn test_qmap() {
+        let map = BTreeMap::from([
+            ("login".to_string(), "jane".to_string()),
+            ("password".to_string(), "s3cr3t".to_string()),
+        ]);
+        let encoded = encode_qmap(&map);
+        assert_eq!(&encoded[..4], &[0, 0, 0, 2]);
+        assert_eq!(decode_qmap(&encoded).unwrap(), map);
+        assert_eq!(decode_qmap(&encode_qmap(&BTreeMap::new())).unwrap(), BTreeMap::new());
+        assert!(decode_qmap(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_entry_type() {
+        assert_eq!(EntryType::from_i32(3), EntryType::Map);
+        assert_eq!(EntryType::from_i32(7), EntryType::Unknown);
+        assert_eq!(EntryType::Map.content_type(), MAP_CONTENT_TYPE);
+        assert_eq!(EntryType::Password.name(), "password");
+    }
+
+    #[test]
+    fn test_wildcard_matches() {
+        assert!(wildcard_matches("*", ""));
+        assert!(wildcard_matches("*", "anything"));
+        assert!(wildcard_matches("https://*.example.com", "https://mail.example.com"));
+        assert!(wildcard_matches("key?", "key1"));
+        assert!(wildcard_matches("a*b*c", "aXXbYbZc"));
+        assert!(!wildcard_matches("key?", "key"));
+        assert!(!wildcard_matches("a*b", "aXXbY"));
+        assert!(!wildcard_matches("exact", "exactly"));
+    }
+}
@@ -0,0 +1,51 @@
+mod common;
+
+// These tests resolve the object paths given to Lock into the collections to lock, from the
+// handles of a storage opened in a temporary directory. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::tks_dbus::collection_impl::CollectionImpl;
+    use tks_service::tks_dbus::item_impl::ItemImpl;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn all_path_shapes_resolve() {
+        let settings = common::storage_settings("lock-paths", "shapes");
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let work = storage.create_collection("work", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        let session = plain_session();
+        storage
+            .modify_collection(&work, |c| {
+                c.create_item(
+                    "VPN",
+                    HashMap::from([("service".to_string(), "vpn".to_string())]),
+                    (&session, vec![], b"secret".to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+        storage.collections.iter().for_each(|c| {
+            let _ = CollectionImpl::from(c);
+        });
+        let item_path: dbus::Path = ItemImpl::from(storage.search_items(&HashMap::new())[0]).into();
+
+        let resolve = |path: &str| CollectionImpl::resolve(&dbus::Path::from(path));
+        let work_path = CollectionImpl::from(&work).canonical_path();
+        assert_eq!(CollectionImpl::resolve(&work_path), Some(work));
+        assert_eq!(resolve("/org/freedesktop/secrets/aliases/default"), Some(default));
+        assert_eq!(CollectionImpl::resolve(&item_path), Some(work));
+
+        // paths used to get split at their 5th component
+        assert_eq!(resolve("/"), None);
+        assert_eq!(resolve("/org/freedesktop/secrets"), None);
+        assert_eq!(resolve("/org/freedesktop/secrets/collection/work"), None);
+        assert_eq!(resolve("/org/freedesktop/secrets/collection/nope/item"), None);
+    }
+}
@@ -0,0 +1,112 @@
+mod common;
+
+// These tests merge collections of a storage opened in a temporary directory, then reopen it to
+// check what got saved. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::storage::merge::MergeConflict;
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str, user: &str) {
+        let session = plain_session();
+        let attributes = HashMap::from([("user".to_string(), user.to_string())]);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    fn labels(storage: &Storage, collection: &Uuid) -> Vec<String> {
+        let mut labels = storage
+            .with_collection(collection, |c| {
+                Ok(c.items.iter().map(|i| i.label.clone()).collect::<Vec<_>>())
+            })
+            .unwrap();
+        labels.sort();
+        labels
+    }
+
+    fn prepare(settings: &settings::Storage) -> (Storage, Uuid, Uuid) {
+        let mut storage = open_unlocked(settings);
+        let source = storage.create_collection("imported", "", &HashMap::new()).unwrap();
+        let destination = storage.create_collection("main", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        add_item(&mut storage, &source, "alice imported", "alice");
+        add_item(&mut storage, &source, "bob imported", "bob");
+        add_item(&mut storage, &destination, "alice", "alice");
+        (storage, source, destination)
+    }
+
+    #[tokio::test]
+    async fn merge_skips_conflicting_items() {
+        let settings = common::storage_settings("merge", "skip");
+        let (mut storage, source, destination) = prepare(&settings);
+
+        let outcome = storage
+            .merge_collections(&source, &destination, MergeConflict::Skip)
+            .unwrap();
+        assert_eq!(outcome.moved.len(), 1);
+        assert_eq!(outcome.skipped.len(), 1);
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(labels(&storage, &source), vec!["alice imported"]);
+        assert_eq!(labels(&storage, &destination), vec!["alice", "bob imported"]);
+    }
+
+    #[tokio::test]
+    async fn merge_replaces_then_deletes_source() {
+        let settings = common::storage_settings("merge", "replace");
+        let (mut storage, source, destination) = prepare(&settings);
+
+        let outcome = storage
+            .merge_collections(&source, &destination, MergeConflict::Replace)
+            .unwrap();
+        assert_eq!(outcome.moved.len(), 2);
+        assert_eq!(outcome.replaced.len(), 1);
+        storage.delete_collection(&source).unwrap();
+
+        let storage = open_unlocked(&settings);
+        assert!(storage.with_collection(&source, |_| Ok(())).is_err());
+        assert_eq!(
+            labels(&storage, &destination),
+            vec!["alice imported", "bob imported"]
+        );
+    }
+
+    #[tokio::test]
+    async fn move_and_copy_items() {
+        let settings = common::storage_settings("merge", "move");
+        let (mut storage, source, destination) = prepare(&settings);
+        let item_id = |storage: &Storage, collection: &Uuid, label: &str| {
+            storage
+                .with_collection(collection, |c| {
+                    Ok(c.items.iter().find(|i| i.label == label).unwrap().id.clone())
+                })
+                .unwrap()
+        };
+
+        let bob = item_id(&storage, &source, "bob imported");
+        let moved = storage.move_item(&bob, &destination, false).unwrap();
+        assert_eq!(moved.uuid, bob.uuid);
+        let alice = item_id(&storage, &destination, "alice");
+        let copied = storage.move_item(&alice, &source, true).unwrap();
+        assert_ne!(copied.uuid, alice.uuid);
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(labels(&storage, &source), vec!["alice", "alice imported"]);
+        assert_eq!(labels(&storage, &destination), vec!["alice", "bob imported"]);
+    }
+}
@@ -0,0 +1,204 @@
+mod common;
+
+// These tests open a storage mounting a second backend, both in temporary directories, then
+// reopen the backends on their own to check which one saved what, also after migrating a
+// collection between them. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use tks_service::settings::{self, Settings};
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, backend: &str) -> settings::Storage {
+        common::storage_settings("mounts", &format!("{}-{}", test_name, backend))
+    }
+
+    fn mount(settings: &settings::Storage, read_only: bool) -> settings::StorageMount {
+        settings::StorageMount {
+            name: "shared".to_string(),
+            kind: settings.kind.clone(),
+            path: settings.path.clone(),
+            read_only,
+            keyfiles: HashMap::new(),
+            per_item_files: false,
+            pad_item_files: false,
+            sync: false,
+        }
+    }
+
+    fn label(storage: &Storage, uuid: &Uuid) -> String {
+        storage.with_collection(uuid, |c| Ok(c.label().to_string())).unwrap()
+    }
+
+    fn set_label(storage: &mut Storage, uuid: &Uuid, label: &str) -> Result<(), TksError> {
+        storage.modify_collection(uuid, |c| {
+            c.label = Some(label.to_string());
+            Ok(())
+        })
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) {
+        let session = plain_session();
+        let attributes = HashMap::from([("label".to_string(), label.to_string())]);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    /// Labels, attributes and secrets of the items of the collection
+    fn contents(storage: &Storage, uuid: &Uuid) -> Vec<(String, HashMap<String, String>, Vec<u8>)> {
+        let session = plain_session();
+        storage
+            .with_collection(uuid, |c| {
+                Ok(c.items
+                    .iter()
+                    .map(|i| {
+                        let secret = i.get_secret(&session, SENDER.to_string()).unwrap().2;
+                        (i.label.clone(), i.attributes.clone(), secret)
+                    })
+                    .collect())
+            })
+            .unwrap()
+    }
+
+    /// Opens the personal storage mounting the shared one, which holds the `team` collection
+    fn prepare(
+        test_name: &str,
+        read_only: bool,
+    ) -> (settings::Storage, settings::Storage, Storage, Uuid) {
+        let shared = storage_settings(test_name, "shared");
+        let team = open_unlocked(&shared)
+            .create_collection("team", "", &HashMap::new())
+            .unwrap();
+        let personal = storage_settings(test_name, "personal");
+        let mounting = settings::Storage {
+            mounts: vec![mount(&shared, read_only)],
+            ..personal.clone()
+        };
+        let storage = open_unlocked(&mounting);
+        (personal, shared, storage, team)
+    }
+
+    #[tokio::test]
+    async fn collections_get_saved_by_their_backend() {
+        let (personal, shared, mut storage, team) = prepare("saved", false);
+        // both backends have a default collection, only the personal one stays the default; the
+        // session collection comes on top
+        assert_eq!(storage.collections.len(), 4);
+        assert_eq!(storage.collections.iter().filter(|c| c.default).count(), 1);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+
+        set_label(&mut storage, &team, "Team").unwrap();
+        let work = storage.create_collection("work", "", &HashMap::new()).unwrap();
+        drop(storage);
+
+        let storage = open_unlocked(&shared);
+        assert_eq!(label(&storage, &team), "Team");
+        assert!(storage.with_collection(&work, |_| Ok(())).is_err());
+        let storage = open_unlocked(&personal);
+        assert_eq!(storage.read_alias("default").unwrap(), default.to_string());
+        assert_eq!(label(&storage, &work), "work");
+        assert!(storage.with_collection(&team, |_| Ok(())).is_err());
+    }
+
+    #[tokio::test]
+    async fn read_only_backends_refuse_changes() {
+        let (_, shared, mut storage, team) = prepare("read-only", true);
+        let result = set_label(&mut storage, &team, "Team");
+        assert!(matches!(result, Err(TksError::NotSupported(_))));
+        assert_eq!(label(&storage, &team), "team");
+        let result = storage.delete_collection(&team);
+        assert!(matches!(result, Err(TksError::NotSupported(_))));
+        let result = storage.set_alias("team", Some(team));
+        assert!(matches!(result, Err(TksError::NotSupported(_))));
+        drop(storage);
+
+        let storage = open_unlocked(&shared);
+        assert_eq!(label(&storage, &team), "team");
+    }
+
+    #[tokio::test]
+    async fn migrated_collections_keep_their_contents() {
+        let (personal, shared, mut storage, team) = prepare("migrate", false);
+        add_item(&mut storage, &team, "VPN");
+        let before = contents(&storage, &team);
+        let (created, modified) = storage
+            .with_collection(&team, |c| Ok((c.created, c.modified)))
+            .unwrap();
+        assert_eq!(storage.backend_name(&team).unwrap(), "shared");
+        storage.migrate_collection(&team, "main").unwrap();
+        assert_eq!(storage.backend_name(&team).unwrap(), "main");
+
+        let result = storage.migrate_collection(&team, "nowhere");
+        assert!(matches!(result, Err(TksError::NotFound(_))));
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let result = storage.migrate_collection(&default, "shared");
+        assert!(matches!(result, Err(TksError::NotSupported(_))));
+        drop(storage);
+
+        let storage = open_unlocked(&shared);
+        assert!(storage.with_collection(&team, |_| Ok(())).is_err());
+        let storage = open_unlocked(&personal);
+        assert_eq!(contents(&storage, &team), before);
+        assert_eq!(
+            storage.with_collection(&team, |c| Ok((c.created, c.modified))).unwrap(),
+            (created, modified)
+        );
+    }
+
+    #[test]
+    fn mounts_settings() {
+        let mut path = env::temp_dir();
+        path.push(format!("tks-mounts-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[storage]\nkind = \"tks_gcm\"\npath = \"/tmp/tks-personal\"\n\
+             [[storage.mounts]]\nname = \"pass\"\nkind = \"password-store\"\n\
+             path = \"/tmp/tks-pass\"\nread_only = true\n",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let settings = Settings::check(&path).expect("configuration should be valid");
+        assert_eq!(settings.storage.mounts.len(), 1);
+        assert!(settings.storage.mounts[0].read_only);
+        let mounted = settings.storage.mounts[0].settings(&settings.storage);
+        assert_eq!(mounted.kind, "password-store");
+        assert_eq!(mounted.path.as_deref(), Some("/tmp/tks-pass"));
+
+        fs::write(
+            &path,
+            "[storage]\nkind = \"password-store\"\npath = \"/tmp/tks-pass\"\n\
+             [[storage.mounts]]\nname = \"pass\"\nkind = \"password-store\"\n\
+             path = \"/tmp/tks-pass\"\n\
+             [[storage.mounts]]\nname = \"main\"\nkind = \"tks_gcm\"\n",
+        )
+        .unwrap();
+        let problems = Settings::check(&path).expect_err("configuration should be invalid");
+        let mut settings: Vec<_> = problems.iter().map(|p| p.setting.as_str()).collect();
+        settings.sort();
+        assert_eq!(
+            settings,
+            vec![
+                "storage.mounts",
+                "storage.mounts[0].path",
+                "storage.mounts[1].name",
+                "storage.mounts[1].path"
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
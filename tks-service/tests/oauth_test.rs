@@ -0,0 +1,143 @@
+mod common;
+
+// These tests store OAuth2 tokens in a storage opened in a temporary directory, and look for the
+// ones still valid. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use std::collections::BTreeSet;
+    use tks_service::settings;
+    use tks_service::storage::oauth::{TokenMetadata, TokenQuery};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    const NOW: u64 = 1_700_000_000;
+    const ISSUER: &str = "https://accounts.example.com";
+
+    fn open_default(settings: &settings::Storage) -> (Storage, Uuid) {
+        let storage = open_unlocked(settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        (storage, default)
+    }
+
+    fn token(account: &str, scopes: &[&str], expires_at: u64) -> TokenMetadata {
+        TokenMetadata {
+            issuer: ISSUER.to_string(),
+            account: account.to_string(),
+            scopes: scopes
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<BTreeSet<_>>(),
+            expires_at,
+            refreshable: true,
+            token_endpoint: Some(format!("{}/token", ISSUER)),
+            client_id: None,
+        }
+    }
+
+    fn store(storage: &mut Storage, collection: &Uuid, token: &TokenMetadata) -> Uuid {
+        let session = plain_session();
+        let secret = format!(r#"{{"access_token":"{}"}}"#, token.expires_at);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    &token.label(),
+                    token.attributes(),
+                    (
+                        &session,
+                        vec![],
+                        secret.into_bytes(),
+                        "application/json".to_string(),
+                    ),
+                    true,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn find(storage: &Storage, query: &TokenQuery) -> Vec<u64> {
+        storage
+            .find_oauth_tokens(query, NOW)
+            .iter()
+            .map(|i| {
+                TokenMetadata::from_attributes(&i.attributes)
+                    .unwrap()
+                    .expires_at
+            })
+            .collect()
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        let token = token("me@example.com", &["mail", "calendar"], NOW);
+        let attributes = token.attributes();
+        assert_eq!(attributes["oauth2:scopes"], "calendar mail");
+        assert_eq!(TokenMetadata::from_attributes(&attributes), Some(token));
+
+        let mut other = attributes.clone();
+        other.insert(
+            "xdg:schema".to_string(),
+            "org.freedesktop.Secret.Generic".to_string(),
+        );
+        assert_eq!(TokenMetadata::from_attributes(&other), None);
+    }
+
+    #[tokio::test]
+    async fn only_unexpired_tokens_get_found() {
+        let settings = common::storage_settings("oauth", "unexpired");
+        let (mut storage, default) = open_default(&settings);
+        store(&mut storage, &default, &token("me", &["mail"], NOW + 3600));
+        store(
+            &mut storage,
+            &default,
+            &token("me", &["mail", "calendar"], NOW + 60),
+        );
+        store(&mut storage, &default, &token("boss", &["mail"], NOW - 60));
+
+        let query = TokenQuery {
+            scopes: vec!["mail".to_string()],
+            ..TokenQuery::default()
+        };
+        assert_eq!(find(&storage, &query), vec![NOW + 3600, NOW + 60]);
+        let query = TokenQuery {
+            min_validity: 300,
+            ..query
+        };
+        assert_eq!(find(&storage, &query), vec![NOW + 3600]);
+        let query = TokenQuery {
+            scopes: vec!["calendar".to_string()],
+            ..TokenQuery::default()
+        };
+        assert_eq!(find(&storage, &query), vec![NOW + 60]);
+        let query = TokenQuery {
+            account: "boss".to_string(),
+            ..TokenQuery::default()
+        };
+        assert!(find(&storage, &query).is_empty());
+        let query = TokenQuery {
+            issuer: "https://other.example.com".to_string(),
+            ..TokenQuery::default()
+        };
+        assert!(find(&storage, &query).is_empty());
+    }
+
+    #[tokio::test]
+    async fn new_token_replaces_the_same_grant() {
+        let settings = common::storage_settings("oauth", "replace");
+        let (mut storage, default) = open_default(&settings);
+        let first = store(&mut storage, &default, &token("me", &["mail"], NOW + 60));
+        let second = store(&mut storage, &default, &token("me", &["mail"], NOW + 3600));
+        assert_ne!(first, second);
+        store(
+            &mut storage,
+            &default,
+            &token("me", &["calendar"], NOW + 60),
+        );
+
+        let query = TokenQuery::default();
+        assert_eq!(find(&storage, &query), vec![NOW + 3600, NOW + 60]);
+    }
+}
@@ -0,0 +1,111 @@
+mod common;
+mod harness;
+
+// These tests give collections an owner, a connection of their own to the bus, then close it, see
+// the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Connection;
+    use std::collections::HashMap;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn collection_proxy<'a>(
+        conn: &'a Connection,
+        collection: &'a dbus::Path<'static>,
+    ) -> dbus::blocking::Proxy<'a, &'a Connection> {
+        conn.with_proxy(harness::SERVICE_NAME, collection, harness::TIMEOUT)
+    }
+
+    fn set_owner(conn: &Connection, collection: &dbus::Path<'static>, lock_on_exit: bool) {
+        let () = collection_proxy(conn, collection)
+            .method_call("io.linux_tks.Collection1", "SetOwner", (lock_on_exit,))
+            .unwrap();
+    }
+
+    fn owner(conn: &Connection, collection: &dbus::Path<'static>) -> String {
+        collection_proxy(conn, collection)
+            .get("io.linux_tks.Collection1", "Owner")
+            .unwrap()
+    }
+
+    fn locked(conn: &Connection, collection: &dbus::Path<'static>) -> bool {
+        collection_proxy(conn, collection)
+            .get("org.freedesktop.Secret.Collection", "Locked")
+            .unwrap()
+    }
+
+    /// Waits for the service to learn that the owner of the collection left the bus
+    fn wait_for_no_owner(conn: &Connection, collection: &dbus::Path<'static>) {
+        let deadline = Instant::now() + harness::TIMEOUT;
+        while !owner(conn, collection).is_empty() {
+            assert!(Instant::now() < deadline, "the owner should be gone");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn collections_lock_when_their_owner_leaves() {
+        let collection = harness::unlocked_collection("owned");
+        let conn = Connection::new_session().unwrap();
+        let front_end = Connection::new_session().unwrap();
+        set_owner(&front_end, &collection, true);
+        assert_eq!(
+            owner(&conn, &collection),
+            front_end.unique_name().to_string()
+        );
+        assert!(!locked(&conn, &collection));
+        let properties: HashMap<String, String> = collection_proxy(&conn, &collection)
+            .get("io.linux_tks.Collection1", "Properties")
+            .unwrap();
+        assert_eq!(properties["tks:lock-on-owner-exit"], "true");
+
+        drop(front_end);
+        wait_for_no_owner(&conn, &collection);
+        assert!(locked(&conn, &collection));
+        harness::unlock_all();
+    }
+
+    #[test]
+    fn collections_stay_unlocked_without_the_policy() {
+        let collection = harness::unlocked_collection("owned, staying unlocked");
+        let conn = Connection::new_session().unwrap();
+        let front_end = Connection::new_session().unwrap();
+        set_owner(&front_end, &collection, false);
+        assert_eq!(
+            owner(&conn, &collection),
+            front_end.unique_name().to_string()
+        );
+
+        drop(front_end);
+        wait_for_no_owner(&conn, &collection);
+        assert!(!locked(&conn, &collection));
+    }
+
+    #[test]
+    fn only_owners_release_their_collection() {
+        let collection = harness::unlocked_collection("released");
+        let conn = Connection::new_session().unwrap();
+        let front_end = Connection::new_session().unwrap();
+        set_owner(&front_end, &collection, true);
+
+        let result: Result<(), _> = collection_proxy(&conn, &collection).method_call(
+            "io.linux_tks.Collection1",
+            "ReleaseOwner",
+            (),
+        );
+        assert!(result.is_err());
+        let () = collection_proxy(&front_end, &collection)
+            .method_call("io.linux_tks.Collection1", "ReleaseOwner", ())
+            .unwrap();
+        assert!(owner(&conn, &collection).is_empty());
+
+        // the released collection no longer locks upon its former owner's exit
+        drop(front_end);
+        thread::sleep(Duration::from_millis(100));
+        assert!(!locked(&conn, &collection));
+    }
+}
@@ -0,0 +1,52 @@
+mod common;
+
+// These tests open storages in temporary directories having various permissions. They don't need
+// a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use tks_service::settings;
+    use tks_service::storage::permissions::check_private_dir;
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+
+    fn root(settings: &settings::Storage) -> PathBuf {
+        PathBuf::from(settings.path.as_ref().unwrap())
+    }
+
+    #[tokio::test]
+    async fn created_storage_is_private() {
+        let settings = common::storage_settings("permissions", "created");
+        Storage::open(settings.clone()).expect("storage should open");
+        let mode = fs::metadata(root(&settings)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        check_private_dir(&root(&settings)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_storage_is_refused() {
+        let settings = common::storage_settings("permissions", "shared");
+        fs::create_dir_all(root(&settings)).unwrap();
+        for mode in [0o750, 0o705] {
+            fs::set_permissions(root(&settings), fs::Permissions::from_mode(mode)).unwrap();
+            let opened = Storage::open(settings.clone());
+            assert!(matches!(opened, Err(TksError::InsecurePermissions(_))));
+        }
+
+        fs::set_permissions(root(&settings), fs::Permissions::from_mode(0o700)).unwrap();
+        Storage::open(settings.clone()).expect("storage should open once private");
+    }
+
+    #[test]
+    fn files_are_not_storage_directories() {
+        let settings = common::storage_settings("permissions", "file");
+        fs::write(root(&settings), b"").unwrap();
+        let checked = check_private_dir(&root(&settings));
+        assert!(matches!(checked, Err(TksError::InsecurePermissions(_))));
+        fs::remove_file(root(&settings)).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+// These tests pick the pinentry program among fake ones installed into a temporary directory.
+// They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use tks_service::tks_dbus::prompter::pinentry_program;
+
+    /// A directory holding the given programs, to be looked for as in PATH
+    fn install(test_name: &str, programs: &[&str]) -> PathBuf {
+        let mut dir = env::temp_dir();
+        dir.push(format!("tks-pinentry-{}-{}", std::process::id(), test_name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for program in programs {
+            let path = dir.join(program);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn desktops_get_their_flavor() {
+        let dir = install(
+            "flavors",
+            &[
+                "pinentry",
+                "pinentry-qt",
+                "pinentry-gtk-2",
+                "pinentry-curses",
+            ],
+        );
+        let auto = |desktop: Option<&str>| pinentry_program("auto", desktop, Some(dir.as_os_str()));
+        assert_eq!(auto(Some("KDE")), Some(dir.join("pinentry-qt")));
+        // pinentry-gnome3 isn't installed
+        assert_eq!(auto(Some("ubuntu:GNOME")), Some(dir.join("pinentry")));
+        assert_eq!(
+            auto(Some("X-Cinnamon:Cinnamon")),
+            Some(dir.join("pinentry-gtk-2"))
+        );
+        assert_eq!(auto(Some("sway")), Some(dir.join("pinentry")));
+        assert_eq!(auto(None), Some(dir.join("pinentry")));
+
+        let empty = install("empty", &[]);
+        assert_eq!(
+            pinentry_program("auto", Some("KDE"), Some(empty.as_os_str())),
+            None
+        );
+    }
+
+    #[test]
+    fn configured_programs_get_found() {
+        let dir = install("configured", &["pinentry-curses"]);
+        fs::write(dir.join("pinentry-tty"), "not executable").unwrap();
+        let path = Some(dir.as_os_str());
+        assert_eq!(
+            pinentry_program("pinentry-curses", Some("KDE"), path),
+            Some(dir.join("pinentry-curses"))
+        );
+        assert_eq!(pinentry_program("pinentry-tty", None, path), None);
+        assert_eq!(pinentry_program("pinentry-qt", None, path), None);
+        let by_path = dir.join("pinentry-curses").to_string_lossy().to_string();
+        assert_eq!(pinentry_program(&by_path, None, None), Some(by_path.into()));
+    }
+}
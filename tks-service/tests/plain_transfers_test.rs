@@ -0,0 +1,42 @@
+// These tests check the remembered answers about sending sensitive secrets through plain
+// sessions. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use tks_service::tks_dbus::plain_transfers::{is_sensitive, PlainTransferChoices};
+    use uuid::Uuid;
+
+    #[test]
+    fn sensitive_items() {
+        let mut attributes = HashMap::from([("user".to_string(), "alice".to_string())]);
+        assert!(!is_sensitive(&attributes));
+        attributes.insert("tks:sensitive".to_string(), "false".to_string());
+        assert!(!is_sensitive(&attributes));
+        attributes.insert("tks:sensitive".to_string(), "true".to_string());
+        assert!(is_sensitive(&attributes));
+    }
+
+    #[test]
+    fn choices_get_remembered() {
+        let mut path = env::temp_dir();
+        path.push(format!("tks-plain-transfers-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut choices = PlainTransferChoices::load(&path).unwrap();
+        let (item, other) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(choices.get(&item, "/usr/bin/app"), None);
+
+        choices.remember(item, "/usr/bin/app", true);
+        choices.remember(item, "/usr/bin/other", false);
+        choices.save(&path).unwrap();
+
+        // the choices are per client and item
+        let choices = PlainTransferChoices::load(&path).unwrap();
+        assert_eq!(choices.get(&item, "/usr/bin/app"), Some(true));
+        assert_eq!(choices.get(&item, "/usr/bin/other"), Some(false));
+        assert_eq!(choices.get(&other, "/usr/bin/app"), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+mod common;
+mod harness;
+
+// These tests call the Secret portal backend of the service, see config/test-portal.toml and the
+// harness module; they only need dbus-daemon to be installed. As the backend only answers
+// xdg-desktop-portal, the calls it should answer come from a copy of the test binary named after
+// it, running the retrieve_secret_as_the_portal test.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common;
+    use crate::harness;
+    use dbus::arg::{OwnedFd, PropMap};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::process::Command;
+
+    const PORTAL_NAME: &str = "org.freedesktop.impl.portal.Secret";
+    const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+
+    /// The application whose secret the copy of the binary asks for
+    const APP_ID: &str = "TKS_TEST_PORTAL_APP_ID";
+
+    fn start() -> Connection {
+        harness::start_with_config("test-portal.toml");
+        Connection::new_session().unwrap()
+    }
+
+    fn portal_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(harness::SERVICE_NAME, PORTAL_PATH, harness::TIMEOUT)
+    }
+
+    /// Calls RetrieveSecret, returning the response and the bytes written to the descriptor
+    fn retrieve_secret(conn: &Connection, app_id: &str) -> Result<(u32, Vec<u8>), dbus::Error> {
+        let (mut received, sent) = UnixStream::pair().unwrap();
+        // SAFETY: the descriptor was just created, and gets closed along with the message
+        let fd = unsafe { OwnedFd::new(sent.into_raw_fd()) };
+        let handle = dbus::Path::from("/org/freedesktop/portal/desktop/request/1_42/tks");
+        let (response, _): (u32, PropMap) = portal_proxy(conn).method_call(
+            PORTAL_NAME,
+            "RetrieveSecret",
+            (handle, app_id, fd, PropMap::new()),
+        )?;
+        let mut secret = Vec::new();
+        received.read_to_end(&mut secret).unwrap();
+        Ok((response, secret))
+    }
+
+    /// The secret the service sends xdg-desktop-portal for the application
+    fn portal_secret(app_id: &str) -> Vec<u8> {
+        start();
+        let dir = common::temp_dir("portal", app_id);
+        fs::create_dir_all(&dir).unwrap();
+        let portal = dir.join("xdg-desktop-portal");
+        fs::copy(env::current_exe().unwrap(), &portal).unwrap();
+        let output = Command::new(&portal)
+            .args([
+                "tests::retrieve_secret_as_the_portal",
+                "--exact",
+                "--nocapture",
+            ])
+            .env(APP_ID, app_id)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let secret = stdout
+            .lines()
+            // the line of the test result goes on with the printed one
+            .find_map(|line| line.split_once("secret: ").map(|(_, secret)| secret))
+            .unwrap_or_else(|| panic!("the portal should print the secret: {}", stdout));
+        (0..secret.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&secret[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// The items of the service having the attributes of the secret of the application
+    fn application_keys(conn: &Connection, app_id: &str) -> Vec<dbus::Path<'static>> {
+        let attributes = HashMap::from([("xdg:schema", PORTAL_NAME), ("app_id", app_id)]);
+        let (unlocked, locked): (Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>) = conn
+            .with_proxy(
+                harness::SERVICE_NAME,
+                harness::SERVICE_PATH,
+                harness::TIMEOUT,
+            )
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "SearchItems",
+                (attributes,),
+            )
+            .unwrap();
+        unlocked.into_iter().chain(locked).collect()
+    }
+
+    /// Not a test on its own: run by [portal_secret] as xdg-desktop-portal, it prints the secret
+    /// of the application the variable names, in hexadecimal
+    #[test]
+    fn retrieve_secret_as_the_portal() {
+        let Ok(app_id) = env::var(APP_ID) else {
+            return;
+        };
+        let conn = Connection::new_session().unwrap();
+        let (response, secret) = retrieve_secret(&conn, &app_id).unwrap();
+        assert_eq!(response, 0);
+        let hex: String = secret.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("secret: {}", hex);
+    }
+
+    #[test]
+    fn the_backend_has_its_version() {
+        let conn = start();
+        let version: u32 = portal_proxy(&conn).get(PORTAL_NAME, "version").unwrap();
+        assert_eq!(version, 1);
+    }
+
+    #[test]
+    fn applications_keep_their_secret() {
+        let conn = start();
+        harness::unlock_all();
+        let secret = portal_secret("org.example.Keeping");
+        assert_eq!(secret.len(), 64);
+        assert_eq!(application_keys(&conn, "org.example.Keeping").len(), 1);
+        assert_eq!(portal_secret("org.example.Keeping"), secret);
+        assert_eq!(application_keys(&conn, "org.example.Keeping").len(), 1);
+
+        let other = portal_secret("org.example.Other");
+        assert_eq!(other.len(), 64);
+        assert_ne!(other, secret);
+    }
+
+    #[test]
+    fn only_the_portal_gets_secrets() {
+        let conn = start();
+        harness::unlock_all();
+        let err = retrieve_secret(&conn, "org.example.Spied").unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+        assert!(application_keys(&conn, "org.example.Spied").is_empty());
+    }
+}
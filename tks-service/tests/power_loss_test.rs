@@ -0,0 +1,234 @@
+mod common;
+
+// These tests simulate power losses while the storage saves a collection, then reopen the storage
+// the way the service would do after a reboot. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD};
+    use lazy_static::lazy_static;
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tks_service::settings;
+    use tks_service::storage::file_ops::{clear_fault, inject_fault, recover, Fault};
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    lazy_static! {
+        // faults are injected globally, so tests should not run concurrently
+        static ref SERIAL: Mutex<()> = Mutex::new(());
+    }
+
+    /// Creates an unlocked collection that already went through a successful save
+    fn prepare(settings: &settings::Storage) -> (Storage, Uuid) {
+        let mut storage = open_unlocked(settings);
+        let uuid = storage
+            .create_collection("power-loss", "", &HashMap::new())
+            .unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        storage.modify_collection(&uuid, |_| Ok(())).unwrap();
+        (storage, uuid)
+    }
+
+    fn sequence(storage: &Storage, uuid: &Uuid) -> u64 {
+        storage.with_collection(uuid, |c| Ok(c.sequence)).unwrap()
+    }
+
+    fn add_item(storage: &mut Storage, uuid: &Uuid, label: &str) -> Result<Uuid, TksError> {
+        let session = plain_session();
+        let attributes = HashMap::from([("label".to_string(), label.to_string())]);
+        storage
+            .modify_collection(uuid, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    ":1.42".to_string(),
+                )
+            })
+            .map(|id| id.uuid)
+    }
+
+    fn labels(storage: &Storage, uuid: &Uuid) -> Vec<String> {
+        storage
+            .with_collection(uuid, |c| Ok(c.items.iter().map(|i| i.label.clone()).collect()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn power_loss_before_save() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "before-save");
+        let (mut storage, uuid) = prepare(&settings);
+        let saved_sequence = sequence(&storage, &uuid);
+
+        inject_fault(Fault::PowerLoss(1));
+        assert!(storage.modify_collection(&uuid, |_| Ok(())).is_err());
+        clear_fault();
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(sequence(&storage, &uuid), saved_sequence);
+    }
+
+    fn secret(storage: &Storage, uuid: &Uuid, label: &str) -> String {
+        let session = plain_session();
+        let search = HashMap::from([("label".to_string(), label.to_string())]);
+        let item = storage.search_items(&search)[0].id.uuid;
+        let secret = storage
+            .with_item(uuid, &item, |i| i.get_secret(&session, ":1.42".to_string()))
+            .unwrap();
+        String::from_utf8(secret.2).unwrap()
+    }
+
+    #[tokio::test]
+    async fn power_loss_between_metadata_and_items() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "between-metadata-and-items");
+        let (mut storage, uuid) = prepare(&settings);
+        add_item(&mut storage, &uuid, "kept").unwrap();
+        let saved_sequence = sequence(&storage, &uuid);
+
+        // the metadata gets written first, then the items
+        inject_fault(Fault::PowerLoss(2));
+        assert!(add_item(&mut storage, &uuid, "lost").is_err());
+        clear_fault();
+
+        // the metadata listing the new item didn't replace the previous one either
+        let storage = open_unlocked(&settings);
+        assert!(!storage.with_collection(&uuid, |c| Ok(c.locked)).unwrap());
+        assert_eq!(sequence(&storage, &uuid), saved_sequence);
+        assert_eq!(labels(&storage, &uuid), vec!["kept"]);
+        assert_eq!(secret(&storage, &uuid, "kept"), "kept");
+    }
+
+    async fn interrupted_commit(test_name: &str, per_item_files: bool) {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = settings::Storage {
+            per_item_files,
+            ..common::storage_settings("power-loss", test_name)
+        };
+        let (mut storage, uuid) = prepare(&settings);
+        add_item(&mut storage, &uuid, "kept").unwrap();
+
+        inject_fault(Fault::InterruptedCommit);
+        assert!(add_item(&mut storage, &uuid, "completed").is_err());
+        clear_fault();
+
+        // the files were all written, so reopening the storage completes the save
+        let storage = open_unlocked(&settings);
+        let mut stored = labels(&storage, &uuid);
+        stored.sort();
+        assert_eq!(stored, vec!["completed", "kept"]);
+        assert_eq!(secret(&storage, &uuid, "completed"), "completed");
+        assert_eq!(secret(&storage, &uuid, "kept"), "kept");
+        assert!(recover(settings.path.as_ref().unwrap()).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn interrupted_commits_get_completed() {
+        interrupted_commit("interrupted-commit", false).await;
+    }
+
+    #[tokio::test]
+    async fn interrupted_commits_of_item_files_get_completed() {
+        interrupted_commit("interrupted-commit-item-files", true).await;
+    }
+
+    #[tokio::test]
+    async fn partial_metadata_write() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "partial-metadata");
+        let (mut storage, uuid) = prepare(&settings);
+        let saved_sequence = sequence(&storage, &uuid);
+
+        inject_fault(Fault::PartialWrite(1));
+        assert!(storage.modify_collection(&uuid, |_| Ok(())).is_err());
+        clear_fault();
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(sequence(&storage, &uuid), saved_sequence);
+    }
+
+    #[tokio::test]
+    async fn partial_items_write() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "partial-items");
+        let (mut storage, uuid) = prepare(&settings);
+
+        inject_fault(Fault::PartialWrite(2));
+        assert!(storage.modify_collection(&uuid, |_| Ok(())).is_err());
+        clear_fault();
+
+        let storage = open_unlocked(&settings);
+        assert!(!storage.with_collection(&uuid, |c| Ok(c.locked)).unwrap());
+    }
+
+    #[tokio::test]
+    async fn interrupted_writes_get_reported() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "recover");
+        let (mut storage, uuid) = prepare(&settings);
+
+        inject_fault(Fault::PartialWrite(1));
+        assert!(storage.modify_collection(&uuid, |_| Ok(())).is_err());
+        clear_fault();
+
+        let path = settings.path.as_ref().unwrap();
+        let interrupted = recover(path).unwrap();
+        assert_eq!(interrupted.len(), 1);
+        assert!(interrupted[0].ends_with("metadata/power-loss"));
+        assert!(recover(path).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_saves_get_undone_in_memory() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "undone");
+        let (mut storage, uuid) = prepare(&settings);
+        let item = add_item(&mut storage, &uuid, "kept").unwrap();
+        let saved_sequence = sequence(&storage, &uuid);
+
+        inject_fault(Fault::PowerLoss(1));
+        assert!(add_item(&mut storage, &uuid, "lost").is_err());
+        let renamed = storage.modify_item(&uuid, &item, |i| {
+            i.label = "renamed".to_string();
+            Ok(())
+        });
+        assert!(renamed.is_err());
+        clear_fault();
+
+        // the storage still serves what is on disk
+        assert_eq!(labels(&storage, &uuid), vec!["kept"]);
+        assert_eq!(sequence(&storage, &uuid), saved_sequence);
+        let search = HashMap::from([("label".to_string(), "lost".to_string())]);
+        assert!(storage.search_items(&search).is_empty());
+
+        // later changes save the collection as it was, without the undone ones
+        add_item(&mut storage, &uuid, "added").unwrap();
+        let storage = open_unlocked(&settings);
+        let mut stored = labels(&storage, &uuid);
+        stored.sort();
+        assert_eq!(stored, vec!["added", "kept"]);
+    }
+
+    #[tokio::test]
+    async fn failed_changes_get_undone() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let settings = common::storage_settings("power-loss", "failed-change");
+        let (mut storage, uuid) = prepare(&settings);
+        let saved_sequence = sequence(&storage, &uuid);
+
+        let result: Result<(), TksError> = storage.modify_collection(&uuid, |c| {
+            c.label = Some("halfway".to_string());
+            Err(TksError::ParameterError)
+        });
+        assert!(result.is_err());
+        let label = storage.with_collection(&uuid, |c| Ok(c.label().to_string())).unwrap();
+        assert_eq!(label, "power-loss");
+        // nothing got saved either
+        assert_eq!(sequence(&storage, &uuid), saved_sequence);
+    }
+}
@@ -0,0 +1,49 @@
+// These tests check the results the Completed signal of the prompts carries. They don't need a
+// DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use dbus::arg::RefArg;
+    use tks_service::tks_dbus::prompt_impl::{self, PromptResult};
+    use tks_service::tks_error::TksError;
+
+    #[test]
+    fn results_have_the_spec_signatures() {
+        let empty = PromptResult::Empty.into_variant();
+        assert_eq!(empty.0.signature().to_string(), "s");
+        assert_eq!(empty.0.as_str(), Some(""));
+
+        let collection = dbus::Path::from("/org/freedesktop/secrets/collection/work");
+        let created = PromptResult::Path(collection.clone()).into_variant();
+        assert_eq!(created.0.signature().to_string(), "o");
+        assert_eq!(created.0.as_str(), Some(&*collection));
+
+        let item = dbus::Path::from("/org/freedesktop/secrets/collection/work/1");
+        let unlocked = PromptResult::Paths(vec![collection, item]).into_variant();
+        assert_eq!(unlocked.0.signature().to_string(), "ao");
+        let paths: Vec<_> = unlocked
+            .0
+            .as_iter()
+            .unwrap()
+            .filter_map(|p| p.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/org/freedesktop/secrets/collection/work",
+                "/org/freedesktop/secrets/collection/work/1"
+            ]
+        );
+    }
+
+    #[test]
+    fn results_of_unknown_prompts_are_refused() {
+        let result = prompt_impl::set_result(
+            &dbus::Path::from("/org/freedesktop/secrets/prompt/4242"),
+            PromptResult::Empty,
+        );
+        assert!(matches!(result, Err(TksError::NotFound(_))));
+        let result = prompt_impl::set_result(&dbus::Path::from("/"), PromptResult::Empty);
+        assert!(matches!(result, Err(TksError::ParameterError)));
+    }
+}
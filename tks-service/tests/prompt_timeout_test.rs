@@ -0,0 +1,88 @@
+mod common;
+mod harness;
+
+// These tests get prompts they never invoke, with a short `prompt.timeout`, see the harness
+// module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use dbus::message::MatchRule;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tks_service::settings::SETTINGS;
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    /// Whether the service still has a prompt at the path
+    fn registered(conn: &Connection, prompt: &dbus::Path) -> bool {
+        let result: Result<PropMap, _> = conn
+            .with_proxy(harness::SERVICE_NAME, prompt, harness::TIMEOUT)
+            .get_all("org.freedesktop.Secret.Prompt");
+        match result {
+            Ok(_) => true,
+            Err(e) if e.name() == Some("org.freedesktop.DBus.Error.UnknownObject") => false,
+            Err(e) => panic!("{} should answer: {}", prompt, e),
+        }
+    }
+
+    /// The prompt unlocking a collection just locked, once the service answers at it
+    fn unlock_prompt(conn: &Connection, label: &str) -> dbus::Path<'static> {
+        let objects = vec![harness::unlocked_collection(label)];
+        let (_, _): (Vec<dbus::Path>, dbus::Path) = service_proxy(conn)
+            .method_call("org.freedesktop.Secret.Service", "Lock", (objects.clone(),))
+            .unwrap();
+        let (_, prompt): (Vec<dbus::Path>, dbus::Path<'static>) = service_proxy(conn)
+            .method_call("org.freedesktop.Secret.Service", "Unlock", (objects,))
+            .unwrap();
+        assert_ne!(&*prompt, "/");
+        // the prompts get registered in the background
+        let deadline = Instant::now() + harness::TIMEOUT;
+        while !registered(conn, &prompt) {
+            assert!(
+                Instant::now() < deadline,
+                "{} should get registered",
+                prompt
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        prompt
+    }
+
+    #[test]
+    fn forgotten_prompts_expire() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        SETTINGS.lock().unwrap().prompt.timeout = 1;
+        let prompt = unlock_prompt(&conn, "unlocked too late");
+        let rule = MatchRule::new_signal("org.freedesktop.Secret.Prompt", "Completed")
+            .with_path(prompt.clone());
+        let signals = harness::watch(&conn, rule);
+
+        let completed = harness::next_signal(&conn, &signals);
+        let (dismissed, _): (bool, Variant<Box<dyn RefArg>>) = completed.read2().unwrap();
+        assert!(dismissed);
+        assert!(!registered(&conn, &prompt));
+
+        // no timeout keeps the prompts until their client invokes them
+        SETTINGS.lock().unwrap().prompt.timeout = 0;
+        let prompt = unlock_prompt(&conn, "unlocked whenever");
+        let rule = MatchRule::new_signal("org.freedesktop.Secret.Prompt", "Completed")
+            .with_path(prompt.clone());
+        let signals = harness::watch(&conn, rule);
+        thread::sleep(Duration::from_secs(2));
+        assert!(registered(&conn, &prompt));
+        conn.process(Duration::ZERO).unwrap();
+        assert!(signals.try_recv().is_err());
+        harness::unlock_all();
+    }
+}
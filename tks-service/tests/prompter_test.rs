@@ -0,0 +1,78 @@
+mod common;
+mod harness;
+
+// These tests register prompters from several connections of the test binary, revoked then let
+// in again, see the harness module; they only need dbus-daemon to be installed. The dialogs of
+// the tests still go through the scripted prompter.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::{Connection, Proxy};
+    use std::env;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const PROMPTER_PATH: &str = "/io/linux_tks/test/Prompter";
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn register(conn: &Connection) -> Result<(), dbus::Error> {
+        service_proxy(conn).method_call(
+            "io.linux_tks.Service1",
+            "RegisterPrompter",
+            (dbus::Path::from(PROMPTER_PATH),),
+        )
+    }
+
+    fn unregister(conn: &Connection) -> Result<(), dbus::Error> {
+        service_proxy(conn).method_call("io.linux_tks.Service1", "UnregisterPrompter", ())
+    }
+
+    fn revoke(conn: &Connection) {
+        let exe = env::current_exe().unwrap();
+        let (revoked,): (bool,) = service_proxy(conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "RevokeClient",
+                (exe.to_string_lossy().as_ref(),),
+            )
+            .unwrap();
+        assert!(revoked);
+    }
+
+    // a single test, as it revokes the test binary, which the other tests would need
+    #[test]
+    fn prompters_get_registered_by_a_single_enrolled_client() {
+        harness::start();
+        let owner = Connection::new_session().unwrap();
+        let other = Connection::new_session().unwrap();
+        register(&owner).unwrap();
+        // registering again keeps it
+        register(&owner).unwrap();
+        assert!(register(&other).is_err());
+        assert!(unregister(&other).is_err());
+
+        // the registration goes away with its owner, which the service learns in the background
+        drop(owner);
+        let deadline = Instant::now() + harness::TIMEOUT;
+        while register(&other).is_err() {
+            assert!(Instant::now() < deadline, "the prompter should go away");
+            thread::sleep(Duration::from_millis(10));
+        }
+        unregister(&other).unwrap();
+
+        revoke(&other);
+        let err = register(&other).unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+        harness::enroll();
+        register(&other).unwrap();
+        unregister(&other).unwrap();
+    }
+}
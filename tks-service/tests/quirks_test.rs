@@ -0,0 +1,94 @@
+// These tests check which quirks apply to the clients, after the built-in table and the
+// settings. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::ffi::OsStr;
+    use std::fs;
+    use tks_service::settings::{Clients, Quirk, Settings};
+    use tks_service::tks_dbus::quirks::quirks_of;
+
+    fn clients(builtin_quirks: bool, quirks: &[(&str, &[Quirk])]) -> Clients {
+        Clients {
+            builtin_quirks,
+            quirks: quirks
+                .iter()
+                .map(|(client, quirks)| (client.to_string(), quirks.to_vec()))
+                .collect(),
+            ..Clients::default()
+        }
+    }
+
+    #[test]
+    fn builtin_quirks() {
+        let settings = clients(true, &[]);
+        assert_eq!(
+            quirks_of(&settings, OsStr::new("/usr/bin/keepassxc")),
+            vec![Quirk::LabelIsAlias]
+        );
+        assert!(quirks_of(&settings, OsStr::new("/usr/bin/secret-tool")).is_empty());
+        // the sandboxed clients, by their application
+        assert_eq!(
+            quirks_of(&settings, OsStr::new("flatpak:org.keepassxc.KeePassXC")),
+            vec![Quirk::LabelIsAlias]
+        );
+
+        let settings = clients(false, &[]);
+        assert!(quirks_of(&settings, OsStr::new("/usr/bin/keepassxc")).is_empty());
+    }
+
+    #[test]
+    fn configured_quirks_replace_builtin_ones() {
+        let settings = clients(
+            true,
+            &[
+                ("keepassxc", &[]),
+                ("app", &[Quirk::AliasPath]),
+                ("/opt/app/bin/app", &[Quirk::CollectionChangedOnNewItem]),
+            ],
+        );
+        assert!(quirks_of(&settings, OsStr::new("/usr/bin/keepassxc")).is_empty());
+        assert_eq!(quirks_of(&settings, OsStr::new("/usr/bin/app")), vec![Quirk::AliasPath]);
+        // the full path takes precedence over the file name
+        assert_eq!(
+            quirks_of(&settings, OsStr::new("/opt/app/bin/app")),
+            vec![Quirk::CollectionChangedOnNewItem]
+        );
+    }
+
+    #[test]
+    fn quirks_settings() {
+        let mut path = env::temp_dir();
+        path.push(format!("tks-quirks-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[storage]\nkind = \"tks_gcm\"\n\
+             [clients]\nbuiltin_quirks = false\n\
+             [clients.quirks]\nApp = [\"label-is-alias\", \"alias-path\"]\n\"/opt/my.app\" = []\n",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let settings = Settings::check(&path).expect("configuration should be valid");
+        assert!(!settings.clients.builtin_quirks);
+        // the keys get lowercased, and may have dots
+        assert_eq!(
+            settings.clients.quirks,
+            HashMap::from([
+                ("app".to_string(), vec![Quirk::LabelIsAlias, Quirk::AliasPath]),
+                ("/opt/my.app".to_string(), vec![]),
+            ])
+        );
+        assert_eq!(
+            quirks_of(&settings.clients, OsStr::new("/usr/bin/App")),
+            vec![Quirk::LabelIsAlias, Quirk::AliasPath]
+        );
+
+        fs::write(&path, "[storage]\nkind = \"tks_gcm\"\n[clients.quirks]\napp = [\"unknown\"]\n")
+            .unwrap();
+        let problems = Settings::check(&path).expect_err("quirk should be unknown");
+        assert_eq!(problems.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+// These tests ask passphrases through the remote prompter, answering them the way
+// `tks-cli service unlock` does through io.linux_tks.Service1. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use secrecy::{ExposeSecret, SecretString};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tks_service::tks_dbus::prompter::{
+        self, Cancellation, PassphraseRequest, Prompter, RemotePrompter,
+    };
+    use tks_service::tks_error::TksError;
+
+    fn request(description: &str, required: bool) -> PassphraseRequest<'_> {
+        PassphraseRequest {
+            description,
+            prompt: "Password",
+            confirmation: None,
+            required,
+        }
+    }
+
+    /// Asks the passphrase in the background, as the prompts do, returning the id of its dialog
+    fn ask(
+        description: &'static str,
+        required: bool,
+        cancellation: Cancellation,
+    ) -> (u32, thread::JoinHandle<Option<String>>) {
+        let asking = thread::spawn(move || {
+            prompter::with_cancellation(cancellation, || {
+                let answer = RemotePrompter {}.ask_passphrase(&request(description, required));
+                answer.unwrap().map(|p| p.expose_secret().to_string())
+            })
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let pending = prompter::pending_passphrases();
+            if let Some((id, ..)) = pending.iter().find(|p| p.1 == description) {
+                return (*id, asking);
+            }
+            assert!(Instant::now() < deadline, "the dialog should be pending");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn passphrases_get_answered() {
+        let (id, asking) = ask(
+            "Unlock the default collection",
+            true,
+            Cancellation::default(),
+        );
+        let result = prompter::answer_passphrase(id, Some(SecretString::new(String::new())));
+        assert!(matches!(result, Err(TksError::ParameterError)));
+        prompter::answer_passphrase(id, Some(SecretString::new("secret".into()))).unwrap();
+        assert_eq!(asking.join().unwrap().as_deref(), Some("secret"));
+
+        let result = prompter::answer_passphrase(id, None);
+        assert!(matches!(result, Err(TksError::NotFound(_))));
+        assert!(prompter::pending_passphrases().iter().all(|p| p.0 != id));
+    }
+
+    #[test]
+    fn dialogs_get_cancelled() {
+        let (id, asking) = ask("Unlock with the key file", false, Cancellation::default());
+        prompter::answer_passphrase(id, None).unwrap();
+        assert_eq!(asking.join().unwrap(), None);
+    }
+
+    #[test]
+    fn dismissed_prompts_cancel_their_dialogs() {
+        let cancellation = Cancellation::default();
+        let (id, asking) = ask("Unlock the work collection", true, cancellation.clone());
+        cancellation.cancel();
+        assert_eq!(asking.join().unwrap(), None);
+        assert!(prompter::pending_passphrases().iter().all(|p| p.0 != id));
+
+        // the next dialogs of the prompt don't show up
+        let answer = prompter::with_cancellation(cancellation, || {
+            assert!(prompter::cancelled());
+            RemotePrompter {}.ask_passphrase(&request("Unlock the home collection", true))
+        });
+        assert!(answer.unwrap().is_none());
+        assert!(!prompter::cancelled());
+    }
+
+    #[test]
+    fn confirmations_get_declined() {
+        let confirmed = RemotePrompter {}.confirm("Allow", "Deny", "Let the client in?");
+        assert!(!confirmed.unwrap());
+    }
+}
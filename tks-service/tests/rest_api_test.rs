@@ -0,0 +1,49 @@
+// These tests check the routes of the REST API and its bearer tokens. They don't need a DBus
+// session bus. Run them with `cargo test --features rest-api`.
+//
+#[cfg(all(test, feature = "rest-api"))]
+mod tests {
+    use tks_service::rest_api::tokens::ApiTokens;
+    use tks_service::rest_api::Route;
+
+    #[test]
+    fn test_routes() {
+        assert_eq!(Route::parse("POST", "/v1/tokens"), Some(Route::MintToken));
+        assert_eq!(Route::parse("GET", "/v1/collections/"), Some(Route::Collections));
+        assert_eq!(
+            Route::parse("POST", "/v1/collections/default/items"),
+            Some(Route::CreateItem("default".to_string()))
+        );
+        assert_eq!(
+            Route::parse("PUT", "/v1/collections/default/items/42/secret"),
+            Some(Route::SetSecret("default".to_string(), "42".to_string()))
+        );
+        assert_eq!(Route::parse("GET", "/v1/search"), None);
+        assert_eq!(Route::parse("GET", "/v1/collections//items"), None);
+        assert_eq!(Route::parse("GET", "/v2/collections"), None);
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut tokens = ApiTokens::default();
+        let first = tokens.mint("electron-app");
+        let other = tokens.mint("backup-script");
+        assert_eq!(first.len(), 64);
+        assert_eq!(tokens.verify(&first), Some("electron-app"));
+        assert_eq!(tokens.verify(&other), Some("backup-script"));
+        assert_eq!(tokens.verify("not a token"), None);
+        // only the hashes get kept
+        assert!(tokens.list().iter().all(|t| t.token_sha256 != first));
+
+        // a new token replaces the previous one of the application
+        let second = tokens.mint("electron-app");
+        assert_ne!(first, second);
+        assert_eq!(tokens.verify(&first), None);
+        assert_eq!(tokens.verify(&second), Some("electron-app"));
+
+        assert!(tokens.revoke(&second));
+        assert!(!tokens.revoke(&second));
+        assert_eq!(tokens.verify(&second), None);
+        assert_eq!(tokens.list().len(), 1);
+    }
+}
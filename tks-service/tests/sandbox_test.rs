@@ -0,0 +1,49 @@
+// These tests check how the sandboxed clients get identified, from the sandbox metadata of their
+// processes. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use tks_service::tks_dbus::sandbox::{flatpak_app_id, snap_confined, snap_name, Sandbox};
+
+    #[test]
+    fn flatpak_applications() {
+        let info = "[Application]\n\
+            name=org.mozilla.firefox\n\
+            runtime=runtime/org.freedesktop.Platform/x86_64/23.08\n\
+            \n\
+            [Instance]\n\
+            instance-id=1234567890\n";
+        assert_eq!(
+            flatpak_app_id(info),
+            Some("org.mozilla.firefox".to_string())
+        );
+        // a runtime run alone isn't an application
+        let info = "[Runtime]\nname=org.freedesktop.Platform\n";
+        assert_eq!(flatpak_app_id(info), None);
+        assert_eq!(
+            Sandbox::Flatpak("org.mozilla.firefox".to_string()).identity(),
+            "flatpak:org.mozilla.firefox"
+        );
+    }
+
+    #[test]
+    fn snap_applications() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+            snap.firefox.firefox-0b2ab3c4-1234-4d5e-8f90-123456789abc.scope\n";
+        assert_eq!(snap_name(cgroup), Some("firefox".to_string()));
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+            app-org.gnome.Terminal.slice/vte-spawn-1234.scope\n";
+        assert_eq!(snap_name(cgroup), None);
+        assert_eq!(
+            Sandbox::Snap("firefox".to_string()).identity(),
+            "snap:firefox"
+        );
+
+        assert!(snap_confined("snap.firefox.firefox (enforce)\n", "firefox"));
+        assert!(!snap_confined(
+            "snap.firefoxy.firefoxy (enforce)\n",
+            "firefox"
+        ));
+        assert!(!snap_confined("unconfined\n", "firefox"));
+    }
+}
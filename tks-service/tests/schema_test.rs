@@ -0,0 +1,126 @@
+mod common;
+
+// These tests open storages whose files have other versions than the current ones, in a
+// temporary directory. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use serde_json::{json, Map, Value};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use tks_service::settings;
+    use tks_service::storage::schema::{upgrade, Migration, BACKUP_DIR, METADATA_VERSION};
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    fn root(settings: &settings::Storage) -> PathBuf {
+        PathBuf::from(settings.path.as_ref().unwrap())
+    }
+
+    /// Creates the storage, with an item in the default collection
+    fn prepare(settings: &settings::Storage) {
+        let mut storage = Storage::open(settings.clone()).expect("storage should open");
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let session = plain_session();
+        storage
+            .modify_collection(&default, |c| {
+                c.create_item(
+                    "mail",
+                    HashMap::from([("service".to_string(), "mail".to_string())]),
+                    (&session, vec![], b"mail".to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    fn add_second(metadata: &mut Map<String, Value>) -> Result<(), TksError> {
+        metadata.insert("second".to_string(), true.into());
+        Ok(())
+    }
+
+    fn add_third(metadata: &mut Map<String, Value>) -> Result<(), TksError> {
+        metadata.insert("third".to_string(), true.into());
+        Ok(())
+    }
+
+    #[test]
+    fn upgrade_runs_each_step() {
+        let migrations = [
+            Migration {
+                to: 2,
+                upgrade: add_second,
+            },
+            Migration {
+                to: 3,
+                upgrade: add_third,
+            },
+        ];
+        let mut metadata = json!({"schema_version": 1});
+        assert_eq!(upgrade(&mut metadata, 3, &migrations).unwrap(), Some(1));
+        assert_eq!(
+            metadata,
+            json!({"schema_version": 3, "second": true, "third": true})
+        );
+        assert_eq!(upgrade(&mut metadata, 3, &migrations).unwrap(), None);
+
+        // a step missing can't be skipped
+        let mut metadata = json!({"schema_version": 1});
+        let upgraded = upgrade(&mut metadata, 3, &migrations[1..]);
+        assert!(matches!(upgraded, Err(TksError::UnsupportedVersion(_))));
+    }
+
+    #[tokio::test]
+    async fn newer_metadata_is_refused() {
+        let mut metadata = json!({"schema_version": METADATA_VERSION + 1});
+        let upgraded = upgrade(&mut metadata, METADATA_VERSION, &[]);
+        assert!(matches!(upgraded, Err(TksError::UnsupportedVersion(_))));
+
+        let settings = common::storage_settings("schema", "newer-metadata");
+        prepare(&settings);
+        let path = root(&settings).join("metadata").join("default");
+        let mut metadata: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        metadata["schema_version"] = (METADATA_VERSION + 1).into();
+        let written = serde_json::to_string(&metadata).unwrap();
+        fs::write(&path, &written).unwrap();
+
+        let opened = Storage::open(settings.clone());
+        assert!(matches!(opened, Err(TksError::UnsupportedVersion(_))));
+        // the file is left as the newer version wrote it
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+    }
+
+    #[tokio::test]
+    async fn newer_items_file_is_refused() {
+        let settings = common::storage_settings("schema", "newer-items");
+        prepare(&settings);
+        let path = root(&settings).join("items").join("default");
+        let mut data = fs::read(&path).unwrap();
+        data[0] += 1;
+        fs::write(&path, &data).unwrap();
+
+        let mut storage = Storage::open(settings.clone()).expect("storage should open");
+        let unlocked = storage.unlock_with_password(SecretString::new(PASSWORD.into()));
+        assert!(matches!(unlocked, Err(TksError::UnsupportedVersion(_))));
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn current_files_are_not_backed_up() {
+        let settings = common::storage_settings("schema", "current");
+        prepare(&settings);
+        let mut storage = Storage::open(settings.clone()).expect("storage should open");
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        assert!(!root(&settings).join(BACKUP_DIR).exists());
+    }
+}
@@ -0,0 +1,200 @@
+mod common;
+
+// These tests search the items of a storage opened in a temporary directory through its attribute
+// index. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::storage::search::{AttributeMatch, AttributeQuery};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn attributes(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn add_item(
+        storage: &mut Storage,
+        collection: &Uuid,
+        label: &str,
+        pairs: &[(&str, &str)],
+    ) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes(pairs),
+                    (&session, vec![], label.as_bytes().to_vec(), "text/plain".to_string()),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn search(storage: &Storage, pairs: &[(&str, &str)]) -> Vec<String> {
+        let mut labels: Vec<_> = storage
+            .search_items(&attributes(pairs))
+            .iter()
+            .map(|i| i.label.clone())
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    /// The default collection, holding mail accounts, and another one holding a VPN account
+    fn prepare(settings: &settings::Storage) -> (Storage, Uuid) {
+        let mut storage = open_unlocked(settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let work = storage.create_collection("work", "", &HashMap::new()).unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+        add_item(&mut storage, &default, "Personal", &[("service", "mail"), ("user", "me")]);
+        add_item(&mut storage, &default, "Office", &[("service", "mail"), ("user", "boss")]);
+        add_item(&mut storage, &work, "VPN", &[("service", "vpn"), ("user", "me")]);
+        (storage, default)
+    }
+
+    #[tokio::test]
+    async fn items_having_all_attributes_match() {
+        let settings = common::storage_settings("search", "match");
+        let (storage, _) = prepare(&settings);
+        assert_eq!(search(&storage, &[("service", "mail")]), vec!["Office", "Personal"]);
+        assert_eq!(search(&storage, &[("user", "me")]), vec!["Personal", "VPN"]);
+        assert_eq!(search(&storage, &[("service", "mail"), ("user", "me")]), vec!["Personal"]);
+        assert!(search(&storage, &[("service", "mail"), ("user", "nobody")]).is_empty());
+        assert!(search(&storage, &[("mail", "service")]).is_empty());
+        assert_eq!(search(&storage, &[("label", "vpn")]), vec!["VPN"]);
+        assert_eq!(search(&storage, &[]).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn index_follows_the_changes() {
+        let settings = common::storage_settings("search", "changes");
+        let (mut storage, default) = prepare(&settings);
+        let other = add_item(&mut storage, &default, "Other", &[("service", "mail")]);
+        assert_eq!(search(&storage, &[("service", "mail")]).len(), 3);
+
+        storage
+            .modify_item(&default, &other, |i| {
+                i.attributes.insert("service".to_string(), "chat".to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(search(&storage, &[("service", "chat")]), vec!["Other"]);
+        assert_eq!(search(&storage, &[("service", "mail")]).len(), 2);
+
+        storage
+            .modify_collection(&default, |c| c.delete_item(&other).map(|_| ()))
+            .unwrap();
+        assert!(search(&storage, &[("service", "chat")]).is_empty());
+
+        // the index gets rebuilt upon loading
+        let storage = open_unlocked(&settings);
+        assert_eq!(search(&storage, &[("service", "mail")]), vec!["Office", "Personal"]);
+    }
+
+    fn query(storage: &Storage, queries: &[(&str, &str, &str)]) -> Vec<String> {
+        let queries: Vec<_> = queries
+            .iter()
+            .map(|(name, operator, value)| AttributeQuery::new(name, operator, value).unwrap())
+            .collect();
+        let mut labels: Vec<_> = storage
+            .query_items(&queries)
+            .iter()
+            .map(|i| i.label.clone())
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    #[tokio::test]
+    async fn query_operators() {
+        let settings = common::storage_settings("search", "operators");
+        let (mut storage, default) = prepare(&settings);
+        add_item(&mut storage, &default, "Forge", &[("url", "https://git.example.com/login")]);
+        add_item(&mut storage, &default, "Wiki", &[("url", "https://wiki.example.org/")]);
+
+        assert_eq!(query(&storage, &[("url", "prefix", "https://git.")]), vec!["Forge"]);
+        assert_eq!(query(&storage, &[("url", "substring", "example")]), vec!["Forge", "Wiki"]);
+        assert_eq!(query(&storage, &[("url", "regex", r"\.org/$")]), vec!["Wiki"]);
+        assert_eq!(query(&storage, &[("url", "present", "")]), vec!["Forge", "Wiki"]);
+        assert_eq!(query(&storage, &[("user", "exact", "me")]), vec!["Personal", "VPN"]);
+        assert_eq!(
+            query(&storage, &[("service", "present", ""), ("user", "prefix", "b")]),
+            vec!["Office"]
+        );
+        assert_eq!(query(&storage, &[("label", "prefix", "pe")]), vec!["Personal"]);
+        assert!(query(&storage, &[("url", "prefix", "ftp://")]).is_empty());
+
+        add_item(&mut storage, &default, "Proxy", &[("port", "3128")]);
+        add_item(&mut storage, &default, "Web", &[("port", "80")]);
+        add_item(&mut storage, &default, "Any", &[("port", "any")]);
+        assert_eq!(query(&storage, &[("port", "lt", "1024")]), vec!["Web"]);
+        assert_eq!(query(&storage, &[("port", "ge", "80")]), vec!["Proxy", "Web"]);
+        assert_eq!(query(&storage, &[("port", "gt", "3128")]), Vec::<String>::new());
+        assert_eq!(query(&storage, &[("port", "le", "3128")]), vec!["Proxy", "Web"]);
+
+        assert!(AttributeQuery::new("url", "glob", "*").is_err());
+        assert!(AttributeQuery::new("port", "lt", "many").is_err());
+        assert!(AttributeQuery::new("url", "regex", "(").is_err());
+    }
+
+    fn search_text(storage: &Storage, text: &str, regex: bool) -> Vec<String> {
+        let matcher = AttributeMatch::text(text, regex).unwrap();
+        let mut labels: Vec<_> = storage
+            .search_text(&matcher)
+            .iter()
+            .map(|i| i.label.clone())
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    #[tokio::test]
+    async fn labels_and_attribute_values_match_the_text() {
+        let settings = common::storage_settings("search", "text");
+        let (mut storage, default) = prepare(&settings);
+        add_item(&mut storage, &default, "Mailing list", &[("service", "lists")]);
+
+        let mail = vec!["Mailing list", "Office", "Personal"];
+        assert_eq!(search_text(&storage, "MAIL", false), mail);
+        assert_eq!(search_text(&storage, "vpn", false), vec!["VPN"]);
+        assert_eq!(search_text(&storage, "boss", false), vec!["Office"]);
+        assert_eq!(search_text(&storage, "^(me|vpn)$", true), vec!["Personal", "VPN"]);
+        // the text is no regular expression unless told so
+        assert!(search_text(&storage, "^me$", false).is_empty());
+        assert!(AttributeMatch::text("(", true).is_err());
+    }
+
+    #[tokio::test]
+    async fn batches_of_items_get_found() {
+        let settings = common::storage_settings("search", "batch");
+        let (mut storage, default) = prepare(&settings);
+        let deleted = add_item(&mut storage, &default, "Deleted", &[]);
+        let ids: Vec<_> = storage
+            .collections
+            .iter()
+            .flat_map(|c| c.items.iter().map(|i| i.id.clone()))
+            .collect();
+        storage
+            .modify_collection(&default, |c| c.delete_item(&deleted).map(|_| ()))
+            .unwrap();
+
+        let mut labels = Vec::new();
+        storage.for_each_item(&ids, |collection, item| {
+            assert_eq!(collection.uuid, item.id.collection_uuid);
+            labels.push(item.label.clone());
+        });
+        labels.sort();
+        assert_eq!(labels, vec!["Office", "Personal", "VPN"]);
+    }
+}
@@ -0,0 +1,40 @@
+// These tests check the buffers holding the secrets in memory keep, copy and serialize them like
+// plain vectors do, without showing them. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use tks_service::storage::secure_buffer::SecureBuffer;
+
+    #[test]
+    fn buffers_hold_their_contents() {
+        let secret = b"correct horse battery staple".to_vec();
+        let buffer = SecureBuffer::from(secret.clone());
+        assert_eq!(&*buffer, secret.as_slice());
+        assert_eq!(buffer.clone(), buffer);
+        assert_ne!(SecureBuffer::from(&b"other"[..]), buffer);
+
+        let mut zeroed = SecureBuffer::new(5000);
+        assert!(zeroed.iter().all(|b| *b == 0));
+        zeroed[4999] = 1;
+        assert_eq!(zeroed.len(), 5000);
+        assert!(SecureBuffer::new(0).is_empty());
+    }
+
+    #[test]
+    fn buffers_serialize_like_vectors() {
+        let secret = b"secret".to_vec();
+        let buffer = SecureBuffer::from(secret.clone());
+        let json = serde_json::to_string(&buffer).unwrap();
+        assert_eq!(json, serde_json::to_string(&secret).unwrap());
+        let read: SecureBuffer = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, buffer);
+    }
+
+    #[test]
+    fn buffers_never_show_their_contents() {
+        let buffer = SecureBuffer::from(&b"secret"[..]);
+        let shown = format!("{:?}", buffer);
+        assert!(!shown.contains("115"), "{}", shown);
+        assert!(shown.contains("6 bytes"));
+    }
+}
@@ -0,0 +1,68 @@
+mod common;
+
+// These tests change the collections of a storage opened in a temporary directory, then reopen it
+// to check the sequence numbers saved along with them. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, PASSWORD};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    fn sequence(storage: &Storage, uuid: &Uuid) -> u64 {
+        storage.with_collection(uuid, |c| Ok(c.sequence)).unwrap()
+    }
+
+    fn relabel(storage: &mut Storage, uuid: &Uuid, label: &str) {
+        storage
+            .modify_collection(uuid, |c| {
+                c.label = Some(label.to_string());
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn saves_bump_the_sequence() {
+        let settings = common::storage_settings("sequence", "bumped");
+        let mut storage = open_unlocked(&settings);
+        let uuid = storage
+            .create_collection("counted", "", &HashMap::new())
+            .unwrap();
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+        let created = sequence(&storage, &uuid);
+
+        relabel(&mut storage, &uuid, "first");
+        relabel(&mut storage, &uuid, "second");
+        assert_eq!(sequence(&storage, &uuid), created + 2);
+        drop(storage);
+
+        let storage = Storage::open(settings.clone()).unwrap();
+        assert_eq!(sequence(&storage, &uuid), created + 2);
+    }
+
+    #[tokio::test]
+    async fn failed_changes_keep_the_sequence() {
+        let settings = common::storage_settings("sequence", "failed");
+        let mut storage = open_unlocked(&settings);
+        let uuid = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        relabel(&mut storage, &uuid, "saved");
+        let saved = sequence(&storage, &uuid);
+
+        let failed: Result<(), TksError> = storage.modify_collection(&uuid, |c| {
+            c.label = Some("not saved".to_string());
+            Err(TksError::PermissionDenied)
+        });
+        assert!(failed.is_err());
+        assert_eq!(sequence(&storage, &uuid), saved);
+        drop(storage);
+
+        let storage = Storage::open(settings.clone()).unwrap();
+        assert_eq!(sequence(&storage, &uuid), saved);
+    }
+}
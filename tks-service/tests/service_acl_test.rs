@@ -0,0 +1,167 @@
+mod common;
+mod harness;
+
+// These tests restrict collections and items through the service, then check the methods of
+// io.linux_tks.Service1 handling several of them at once ask the scripted prompter before
+// accessing them, see the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use std::sync::{Mutex, MutexGuard};
+    use tks_service::tks_dbus::prompter::{self, ScriptedAnswer};
+
+    /// The scripted prompter answers the dialogs of all the tests, so they take turns
+    static PROMPTING: Mutex<()> = Mutex::new(());
+
+    fn take_turn() -> MutexGuard<'static, ()> {
+        let turn = PROMPTING.lock().unwrap_or_else(|e| e.into_inner());
+        harness::start();
+        turn
+    }
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn restrict(conn: &Connection, object: &dbus::Path) {
+        conn.with_proxy(harness::SERVICE_NAME, object, harness::TIMEOUT)
+            .method_call::<(), _, _, _>("io.linux_tks.Acl1", "SetRestricted", (true,))
+            .unwrap();
+    }
+
+    fn items(conn: &Connection, collection: &dbus::Path) -> Vec<dbus::Path<'static>> {
+        conn.with_proxy(harness::SERVICE_NAME, collection, harness::TIMEOUT)
+            .get("org.freedesktop.Secret.Collection", "Items")
+            .unwrap()
+    }
+
+    fn assert_denied<T: std::fmt::Debug>(result: Result<T, dbus::Error>) {
+        let err = result.unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+    }
+
+    fn delete_items(conn: &Connection, items: &[&dbus::Path]) -> Result<(), dbus::Error> {
+        service_proxy(conn).method_call("io.linux_tks.Service1", "DeleteItems", (items.to_vec(),))
+    }
+
+    fn find_duplicates(
+        conn: &Connection,
+        collection: &dbus::Path,
+        compare_secrets: bool,
+    ) -> Result<Vec<Vec<dbus::Path<'static>>>, dbus::Error> {
+        service_proxy(conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "FindDuplicates",
+                (collection, compare_secrets),
+            )
+            .map(|(groups,)| groups)
+    }
+
+    fn move_item(
+        conn: &Connection,
+        item: &dbus::Path,
+        destination: &dbus::Path,
+        copy: bool,
+    ) -> Result<dbus::Path<'static>, dbus::Error> {
+        service_proxy(conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "MoveItem",
+                (item, destination, copy),
+            )
+            .map(|(item,)| item)
+    }
+
+    #[test]
+    fn moving_items_asks_for_both_sides() {
+        let _turn = take_turn();
+        let conn = Connection::new_session().unwrap();
+        let source = harness::unlocked_collection("acl move source");
+        let destination = harness::unlocked_collection("acl move destination");
+        let item = harness::created_item(&source, "moved", b"moved");
+        restrict(&conn, &destination);
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        assert_denied(move_item(&conn, &item, &destination, false));
+        assert_eq!(items(&conn, &source).len(), 1);
+
+        // copying only reads the item
+        restrict(&conn, &item);
+        prompter::script([ScriptedAnswer::Confirm(true), ScriptedAnswer::Confirm(true)]);
+        move_item(&conn, &item, &destination, true).unwrap();
+        assert!(prompter::remaining_answers().is_empty());
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        assert_denied(move_item(&conn, &item, &destination, false));
+        prompter::script([ScriptedAnswer::Confirm(true)]);
+        move_item(&conn, &item, &destination, false).unwrap();
+        assert!(prompter::remaining_answers().is_empty());
+        assert!(items(&conn, &source).is_empty());
+        assert_eq!(items(&conn, &destination).len(), 2);
+    }
+
+    #[test]
+    fn migrating_collections_asks_for_restricted_ones() {
+        let _turn = take_turn();
+        let conn = Connection::new_session().unwrap();
+        let collection = harness::unlocked_collection("acl migrate");
+        restrict(&conn, &collection);
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        assert_denied(service_proxy(&conn).method_call::<(), _, _, _>(
+            "io.linux_tks.Service1",
+            "MigrateCollection",
+            (&collection, "main"),
+        ));
+        assert!(prompter::remaining_answers().is_empty());
+    }
+
+    #[test]
+    fn deleting_items_asks_for_the_restricted_ones() {
+        let _turn = take_turn();
+        let conn = Connection::new_session().unwrap();
+        let collection = harness::unlocked_collection("acl delete items");
+        let open = harness::created_item(&collection, "open", b"open");
+        let restricted = harness::created_item(&collection, "restricted", b"restricted");
+        restrict(&conn, &restricted);
+
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        assert_denied(delete_items(&conn, &[&open, &restricted]));
+        assert!(prompter::remaining_answers().is_empty());
+        // none of them got deleted
+        assert_eq!(items(&conn, &collection).len(), 2);
+
+        prompter::script([ScriptedAnswer::Confirm(true)]);
+        delete_items(&conn, &[&open, &restricted]).unwrap();
+        assert!(prompter::remaining_answers().is_empty());
+        assert!(items(&conn, &collection).is_empty());
+    }
+
+    #[test]
+    fn comparing_the_secrets_of_duplicates_asks_for_restricted_collections() {
+        let _turn = take_turn();
+        let conn = Connection::new_session().unwrap();
+        let collection = harness::unlocked_collection("acl find duplicates");
+        // the attribute values get trimmed
+        harness::created_item(&collection, "twice", b"secret");
+        harness::created_item(&collection, "twice ", b"secret");
+        restrict(&conn, &collection);
+
+        // the attributes aren't secret
+        prompter::script([]);
+        assert_eq!(find_duplicates(&conn, &collection, false).unwrap().len(), 1);
+
+        prompter::script([ScriptedAnswer::Confirm(false)]);
+        assert_denied(find_duplicates(&conn, &collection, true));
+        prompter::script([ScriptedAnswer::Confirm(true)]);
+        assert_eq!(find_duplicates(&conn, &collection, true).unwrap().len(), 1);
+        // the grant got remembered
+        assert_eq!(find_duplicates(&conn, &collection, true).unwrap().len(), 1);
+        assert!(prompter::remaining_answers().is_empty());
+    }
+}
@@ -1,98 +1,26 @@
+mod common;
 mod fdo;
+mod harness;
 
-// Running these tests require the presence of an active DBus session bus.
-// Also, no other service on the DBus should offer org.freeedesktop.secrets.
-// Using a DBus session mock object would enable running these tests without tinkering with the
-// SUT's DBus configuration.
+// These tests run the service on a private DBus daemon, with its storage in a temporary directory,
+// see the harness module; they only need dbus-daemon to be installed.
 //
 #[cfg(test)]
 mod tests {
     use crate::fdo::service_client::OrgFreedesktopSecretService;
     use crate::fdo::service_client::OrgFreedesktopSecretServiceCollectionCreated;
+    use crate::harness;
     use dbus::arg;
     use dbus::arg::Variant;
-    use dbus::nonblock;
-    use dbus_tokio::connection;
+    use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+    use log::debug;
     use regex::Regex;
-    use std::env;
-    use std::path::{Path, PathBuf};
-    use tks_service::tks_dbus::start_server;
+    use tokio::time::sleep;
     use tokio::time::Duration;
-    use tokio::time::{interval, sleep};
-    extern crate log;
-    extern crate pretty_env_logger;
-    use futures::executor::block_on;
-    use lazy_static::lazy_static;
-    use log::{debug, error, info, trace};
-    use std::sync::mpsc::{Receiver, Sender};
-    use std::sync::Arc;
-    use std::sync::Mutex;
-    use std::thread;
-    use tks_service::settings::SETTINGS;
-
-    type ServiceProxy = nonblock::Proxy<'static, Arc<nonblock::SyncConnection>>;
-    struct TestFixtureData {
-        conn: Arc<nonblock::SyncConnection>,
-        service_proxy: ServiceProxy,
-        stable: bool,
-    }
-
-    impl TestFixtureData {
-        fn new() -> Self {
-            env::set_var("TKS_RUN_MODE", "test");
-            env::set_var("RUST_LOG", "trace");
-
-            // take settings directly from the test.toml file located in the source tree
-            let mut config_path = PathBuf::from(env::current_dir().unwrap());
-            config_path.push("config");
-            config_path.push("test.toml");
-            env::set_var("TKS_SERVICE_CONFIG_PATH", config_path);
-
-            pretty_env_logger::init();
-
-            let (resource, conn) = connection::new_session_sync().unwrap();
-            let _handle = tokio::spawn(async {
-                let err = resource.await;
-                panic!("Lost connection to D-Bus: {}", err);
-            });
-
-            let service_proxy: ServiceProxy = nonblock::Proxy::new(
-                "org.freedesktop.secrets",
-                "/org/freedesktop/secrets",
-                Duration::from_secs(5),
-                conn.clone(),
-            );
-            TestFixtureData {
-                conn,
-                service_proxy,
-                stable: false,
-            }
-        }
-        async fn stable(&mut self) -> &Self {
-            match self.stable {
-                true => {}
-                false => {
-                    start_server().await;
-                    self.stable = true;
-                }
-            }
-            self
-        }
-    }
-
-    lazy_static! {
-        static ref TEST_FIXTURE_DATA: Arc<Mutex<TestFixtureData>> =
-            Arc::new(Mutex::new(TestFixtureData::new()));
-    }
 
     macro_rules! service_proxy {
         () => {
-            TEST_FIXTURE_DATA
-                .lock()
-                .unwrap()
-                .stable()
-                .await
-                .service_proxy
+            harness::service_proxy(harness::connect())
         };
     }
 
@@ -165,4 +93,24 @@ mod tests {
     }
     // TODO test_create_collection_with_prompt - this should be a case where the collection already
     // exists
+
+    #[tokio::test]
+    async fn test_unlocked_collection_fixture() {
+        let coll_path = harness::unlocked_collection("fixture");
+        let s = service_proxy!();
+        let collections = s.collections().await.unwrap();
+        assert!(collections.contains(&coll_path));
+
+        let collection = dbus::nonblock::Proxy::new(
+            harness::SERVICE_NAME,
+            coll_path,
+            harness::TIMEOUT,
+            s.connection.clone(),
+        );
+        let locked: bool = collection
+            .get("org.freedesktop.Secret.Collection", "Locked")
+            .await
+            .unwrap();
+        assert!(!locked);
+    }
 }
@@ -0,0 +1,120 @@
+mod common;
+
+// These tests use the session collection of a storage opened in a temporary directory, then check
+// nothing of it got written there. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use tks_service::storage::backup::RestoreMode;
+    use tks_service::storage::memory::{
+        SESSION_ALIAS, SESSION_COLLECTION_PATH, SESSION_COLLECTION_UUID,
+    };
+    use tks_service::storage::Storage;
+    use tks_service::tks_dbus::collection_impl::CollectionImpl;
+    use uuid::Uuid;
+
+    const LABEL: &str = "wifi-of-the-session-test";
+
+    fn add_item(storage: &mut Storage) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(&SESSION_COLLECTION_UUID, |c| {
+                c.create_item(
+                    LABEL,
+                    HashMap::from([("service".to_string(), "wifi".to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        b"secret".to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    /// Whether a file of the directory mentions the label of the test item
+    fn written(dir: &Path) -> bool {
+        fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .any(|entry| match entry.path().is_dir() {
+                true => written(&entry.path()),
+                false => fs::read(entry.path())
+                    .is_ok_and(|data| String::from_utf8_lossy(&data).contains(LABEL)),
+            })
+    }
+
+    #[tokio::test]
+    async fn session_items_stay_in_memory() {
+        let settings = common::storage_settings("session-collection", "memory");
+        let mut storage = open_unlocked(&settings);
+        assert_eq!(
+            storage.read_alias(SESSION_ALIAS).unwrap(),
+            SESSION_COLLECTION_UUID.to_string()
+        );
+        let path = CollectionImpl::from(&SESSION_COLLECTION_UUID).canonical_path();
+        assert_eq!(&*path, SESSION_COLLECTION_PATH);
+        assert_eq!(
+            CollectionImpl::resolve(&path),
+            Some(SESSION_COLLECTION_UUID)
+        );
+
+        let item = add_item(&mut storage);
+        storage.flush().unwrap();
+        assert!(!written(Path::new(settings.path.as_ref().unwrap())));
+
+        // it has no password, so locking it would lose its secrets
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        assert_eq!(storage.lock_all().unwrap(), vec![default]);
+        storage.lock_collection(&SESSION_COLLECTION_UUID).unwrap();
+        let secret = storage
+            .with_item(&SESSION_COLLECTION_UUID, &item, |i| Ok(i.data.is_some()))
+            .unwrap();
+        assert!(secret);
+        drop(storage);
+
+        let storage = open_unlocked(&settings);
+        let items = storage.with_collection(&SESSION_COLLECTION_UUID, |c| Ok(c.items.len()));
+        assert_eq!(items.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn session_collection_stays_out_of_backups_and_migrations() {
+        let settings = common::storage_settings("session-collection", "backups");
+        let mut storage = open_unlocked(&settings);
+        add_item(&mut storage);
+        let passphrase = SecretString::new("backup passphrase".into());
+        let archive = storage.create_backup(&passphrase).unwrap();
+
+        let mut restored = open_unlocked(&common::storage_settings(
+            "session-collection",
+            "restored",
+        ));
+        restored
+            .restore_backup(&archive, &passphrase, RestoreMode::Merge)
+            .unwrap();
+        let items = restored.with_collection(&SESSION_COLLECTION_UUID, |c| Ok(c.items.len()));
+        assert_eq!(items.unwrap(), 0);
+        assert_eq!(
+            restored
+                .collections
+                .iter()
+                .filter(|c| c.name == SESSION_ALIAS)
+                .count(),
+            1
+        );
+
+        assert!(storage
+            .migrate_collection(&SESSION_COLLECTION_UUID, "main")
+            .is_err());
+    }
+}
@@ -0,0 +1,121 @@
+mod common;
+mod harness;
+
+// These tests limit the age and the use count of the encrypted sessions, then read a secret
+// through the service until the session expires, see the harness module; they only need
+// dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::arg::{RefArg, Variant};
+    use dbus::blocking::Connection;
+    use openssl::bn::BigNum;
+    use openssl::dh::Dh;
+    use std::sync::{Mutex, MutexGuard};
+    use std::thread;
+    use std::time::Duration;
+    use tks_service::settings::{self, SETTINGS};
+
+    const NO_SESSION: &str = "org.freedesktop.Secret.Error.NoSession";
+
+    type Secret = (dbus::Path<'static>, Vec<u8>, Vec<u8>, String);
+
+    /// The limits apply to all the sessions opened afterwards, so the tests take turns
+    static LIMITING: Mutex<()> = Mutex::new(());
+
+    fn limit(max_age: Option<u64>, max_uses: Option<u64>) -> MutexGuard<'static, ()> {
+        let turn = LIMITING.lock().unwrap_or_else(|e| e.into_inner());
+        harness::start();
+        SETTINGS.lock().unwrap().session = settings::Session { max_age, max_uses };
+        turn
+    }
+
+    fn open_session(conn: &Connection, algorithm: &str, input: Vec<u8>) -> dbus::Path<'static> {
+        let input: Box<dyn RefArg> = match input.is_empty() {
+            true => Box::new(String::new()),
+            false => Box::new(input),
+        };
+        let (_, session): (Variant<Box<dyn RefArg>>, dbus::Path<'static>) = conn
+            .with_proxy(
+                harness::SERVICE_NAME,
+                harness::SERVICE_PATH,
+                harness::TIMEOUT,
+            )
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "OpenSession",
+                (algorithm, Variant(input)),
+            )
+            .unwrap();
+        session
+    }
+
+    /// Negotiates an encrypted session; the secrets don't get decrypted, so the client keeps no
+    /// key
+    fn encrypted_session(conn: &Connection) -> dbus::Path<'static> {
+        let p = BigNum::get_rfc2409_prime_1024().unwrap();
+        let dh = Dh::from_pqg(p, None, BigNum::from_u32(2).unwrap()).unwrap();
+        let client = dh.generate_key().unwrap();
+        open_session(
+            conn,
+            "dh-ietf1024-sha256-aes128-cbc-pkcs7",
+            client.public_key().to_vec(),
+        )
+    }
+
+    fn item(label: &str) -> dbus::Path<'static> {
+        harness::created_item(&harness::unlocked_collection(label), label, b"secret")
+    }
+
+    fn get_secret(
+        conn: &Connection,
+        item: &dbus::Path,
+        session: &dbus::Path<'static>,
+    ) -> Result<Secret, dbus::Error> {
+        conn.with_proxy(harness::SERVICE_NAME, item, harness::TIMEOUT)
+            .method_call("org.freedesktop.Secret.Item", "GetSecret", (session,))
+            .map(|(secret,): (Secret,)| secret)
+    }
+
+    #[test]
+    fn sessions_expire_after_their_uses() {
+        let _turn = limit(None, Some(2));
+        let conn = Connection::new_session().unwrap();
+        let item = item("used up");
+        let session = encrypted_session(&conn);
+        for _ in 0..2 {
+            get_secret(&conn, &item, &session).unwrap();
+        }
+        let e = get_secret(&conn, &item, &session).unwrap_err();
+        assert_eq!(e.name(), Some(NO_SESSION));
+
+        // a new session negotiates a new key
+        let session = encrypted_session(&conn);
+        get_secret(&conn, &item, &session).unwrap();
+    }
+
+    #[test]
+    fn sessions_expire_with_age() {
+        let _turn = limit(Some(1), None);
+        let conn = Connection::new_session().unwrap();
+        let item = item("aged");
+        let session = encrypted_session(&conn);
+        get_secret(&conn, &item, &session).unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        let e = get_secret(&conn, &item, &session).unwrap_err();
+        assert_eq!(e.name(), Some(NO_SESSION));
+    }
+
+    #[test]
+    fn plain_sessions_do_not_expire() {
+        let _turn = limit(None, Some(1));
+        let conn = Connection::new_session().unwrap();
+        let item = item("plain");
+        let session = open_session(&conn, "plain", Vec::new());
+        for _ in 0..3 {
+            let (_, _, value, _) = get_secret(&conn, &item, &session).unwrap();
+            assert_eq!(value, b"secret");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+// These tests open sessions for made up unique bus names, then use them as their owner and as
+// another client. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use tks_service::tks_dbus::session_impl::{Session, SessionManager};
+    use tks_service::tks_error::TksError;
+
+    const OWNER: &str = ":1.42";
+    const OTHER: &str = ":1.43";
+
+    fn open_session(sm: &mut SessionManager, sender: &str) -> usize {
+        let id = sm.sessions.len();
+        sm.sessions.insert(id, Session::new(id, "plain".to_string(), sender.to_string()));
+        id
+    }
+
+    #[tokio::test]
+    async fn sessions_belong_to_their_client() {
+        let mut sm = SessionManager::new();
+        let id = open_session(&mut sm, OWNER);
+
+        assert!(sm.get_session(id, OWNER).is_ok());
+        let result = sm.get_session(id, OTHER);
+        assert!(matches!(result, Err(TksError::PermissionDenied)));
+        let result = sm.get_session(id + 1, OWNER);
+        assert!(matches!(result, Err(TksError::NotFound(_))));
+        let result = sm.get_session(id, OWNER).unwrap().encrypt(b"secret", OTHER.into());
+        assert!(matches!(result, Err(TksError::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn sessions_get_closed_when_their_client_leaves() {
+        let mut sm = SessionManager::new();
+        let first = open_session(&mut sm, OWNER);
+        let second = open_session(&mut sm, OWNER);
+        let other = open_session(&mut sm, OTHER);
+
+        sm.close_sessions_of(OWNER);
+        assert!(sm.get_session(first, OWNER).is_err());
+        assert!(sm.get_session(second, OWNER).is_err());
+        assert!(sm.get_session(other, OTHER).is_ok());
+        assert_eq!(sm.sessions.len(), 1);
+    }
+}
@@ -0,0 +1,215 @@
+mod common;
+mod harness;
+
+// These tests change the collections and the items through the service, then check the signals
+// it sends about them, see the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::arg::{prop_cast, PropMap};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+    use dbus::Message;
+    use std::sync::mpsc;
+
+    fn collection_proxy<'a>(
+        conn: &'a Connection,
+        collection: &'a dbus::Path<'static>,
+    ) -> dbus::blocking::Proxy<'a, &'a Connection> {
+        conn.with_proxy(harness::SERVICE_NAME, collection, harness::TIMEOUT)
+    }
+
+    fn sequence(conn: &Connection, collection: &dbus::Path<'static>) -> u64 {
+        collection_proxy(conn, collection)
+            .get("io.linux_tks.Collection1", "Sequence")
+            .unwrap()
+    }
+
+    fn set_label(conn: &Connection, collection: &dbus::Path<'static>, label: &str) {
+        collection_proxy(conn, collection)
+            .set(
+                "org.freedesktop.Secret.Collection",
+                "Label",
+                label.to_string(),
+            )
+            .unwrap();
+    }
+
+    fn service_proxy(conn: &Connection) -> dbus::blocking::Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    /// Watches the CollectionChanged signals of the service
+    fn watch_collections(conn: &Connection) -> mpsc::Receiver<Message> {
+        let rule = MatchRule::new_signal("org.freedesktop.Secret.Service", "CollectionChanged")
+            .with_path(harness::SERVICE_PATH);
+        harness::watch(conn, rule)
+    }
+
+    /// Waits for the CollectionChanged signal of the collection; the other tests change theirs
+    fn collection_changed(
+        conn: &Connection,
+        signals: &mpsc::Receiver<Message>,
+        collection: &dbus::Path<'static>,
+    ) {
+        loop {
+            let changed: dbus::Path = harness::next_signal(conn, signals).read1().unwrap();
+            if changed == *collection {
+                return;
+            }
+        }
+    }
+
+    /// Watches the PropertiesChanged signals of the object
+    fn watch_properties(conn: &Connection, path: &dbus::Path<'static>) -> mpsc::Receiver<Message> {
+        let rule = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+            .with_path(path.clone());
+        harness::watch(conn, rule)
+    }
+
+    /// Waits for the PropertiesChanged signal giving the property, returning the changed ones
+    fn properties_changed(
+        conn: &Connection,
+        signals: &mpsc::Receiver<Message>,
+        interface: &str,
+        property: &str,
+    ) -> PropMap {
+        loop {
+            let (changed_interface, changed, _): (String, PropMap, Vec<String>) =
+                harness::next_signal(conn, signals).read3().unwrap();
+            if changed_interface == interface && changed.contains_key(property) {
+                return changed;
+            }
+        }
+    }
+
+    #[test]
+    fn saves_send_the_new_sequence() {
+        let collection = harness::unlocked_collection("sequenced");
+        let conn = Connection::new_session().unwrap();
+        let before = sequence(&conn, &collection);
+        let rule = MatchRule::new_signal("io.linux_tks.Collection1", "SequenceChanged")
+            .with_path(collection.clone());
+        let signals = harness::watch(&conn, rule);
+
+        set_label(&conn, &collection, "sequenced again");
+        let signal: u64 = harness::next_signal(&conn, &signals).read1().unwrap();
+        assert!(signal > before);
+        assert_eq!(signal, sequence(&conn, &collection));
+    }
+
+    #[test]
+    fn relabeled_collections_change() {
+        let collection = harness::unlocked_collection("relabeled");
+        let conn = Connection::new_session().unwrap();
+        let signals = watch_collections(&conn);
+        set_label(&conn, &collection, "relabeled again");
+        collection_changed(&conn, &signals, &collection);
+    }
+
+    #[test]
+    fn aliased_collections_change() {
+        let collection = harness::unlocked_collection("aliased");
+        let conn = Connection::new_session().unwrap();
+        let signals = watch_collections(&conn);
+        let () = service_proxy(&conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "SetAlias",
+                ("signals-alias", collection.clone()),
+            )
+            .unwrap();
+        collection_changed(&conn, &signals, &collection);
+    }
+
+    #[test]
+    fn locked_collections_change() {
+        let collection = harness::unlocked_collection("locked");
+        let conn = Connection::new_session().unwrap();
+        let signals = watch_collections(&conn);
+        let (locked, _): (Vec<dbus::Path>, dbus::Path) = service_proxy(&conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "Lock",
+                (vec![collection.clone()],),
+            )
+            .unwrap();
+        assert_eq!(locked, vec![collection.clone()]);
+        collection_changed(&conn, &signals, &collection);
+
+        // and so do the unlocked ones
+        harness::unlock_all();
+        collection_changed(&conn, &signals, &collection);
+    }
+
+    #[test]
+    fn relabeled_collections_send_their_label() {
+        let collection = harness::unlocked_collection("relabeled property");
+        let conn = Connection::new_session().unwrap();
+        let signals = watch_properties(&conn, &collection);
+        set_label(&conn, &collection, "new label");
+        let changed = properties_changed(
+            &conn,
+            &signals,
+            "org.freedesktop.Secret.Collection",
+            "Label",
+        );
+        assert_eq!(prop_cast::<String>(&changed, "Label").unwrap(), "new label");
+    }
+
+    #[test]
+    fn items_send_their_changes() {
+        let collection = harness::unlocked_collection("changed items");
+        let conn = Connection::new_session().unwrap();
+        let collection_signals = watch_properties(&conn, &collection);
+        let item = harness::created_item(&collection, "item", b"secret");
+        let changed = properties_changed(
+            &conn,
+            &collection_signals,
+            "org.freedesktop.Secret.Collection",
+            "Items",
+        );
+        assert!(prop_cast::<Vec<dbus::Path>>(&changed, "Items")
+            .unwrap()
+            .contains(&item));
+
+        let item_signals = watch_properties(&conn, &item);
+        let () = conn
+            .with_proxy(harness::SERVICE_NAME, &item, harness::TIMEOUT)
+            .set(
+                "org.freedesktop.Secret.Item",
+                "Label",
+                "relabeled item".to_string(),
+            )
+            .unwrap();
+        let changed =
+            properties_changed(&conn, &item_signals, "org.freedesktop.Secret.Item", "Label");
+        assert_eq!(
+            prop_cast::<String>(&changed, "Label").unwrap(),
+            "relabeled item"
+        );
+
+        // locking the collection locks its items
+        let (_, _): (Vec<dbus::Path>, dbus::Path) = service_proxy(&conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "Lock",
+                (vec![collection.clone()],),
+            )
+            .unwrap();
+        for (signals, interface) in [
+            (&collection_signals, "org.freedesktop.Secret.Collection"),
+            (&item_signals, "org.freedesktop.Secret.Item"),
+        ] {
+            let changed = properties_changed(&conn, signals, interface, "Locked");
+            assert_eq!(prop_cast::<bool>(&changed, "Locked"), Some(&true));
+        }
+        harness::unlock_all();
+    }
+}
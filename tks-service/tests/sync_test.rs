@@ -0,0 +1,238 @@
+mod common;
+
+// These tests open two storages mounting the same synced backend, as two machines of the user
+// would, all in temporary directories; the other machine's concurrent changes get simulated by
+// conflict copies of the collection file. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, SENDER};
+    use std::collections::{BTreeMap, HashMap};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tks_service::settings;
+    use tks_service::storage::sync::{self, Causality};
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, backend: &str) -> settings::Storage {
+        settings::Storage {
+            per_item_files: true,
+            ..common::storage_settings("sync", &format!("{}-{}", test_name, backend))
+        }
+    }
+
+    /// Opens the storage of a machine, mounting the synced backend
+    fn machine(test_name: &str, name: &str, synced: &settings::Storage) -> Storage {
+        let mount = settings::StorageMount {
+            name: "synced".to_string(),
+            kind: synced.kind.clone(),
+            path: synced.path.clone(),
+            read_only: false,
+            keyfiles: HashMap::new(),
+            per_item_files: true,
+            pad_item_files: false,
+            sync: true,
+        };
+        open_unlocked(&settings::Storage {
+            mounts: vec![mount],
+            ..storage_settings(test_name, name)
+        })
+    }
+
+    /// Creates the synced backend holding the `team` collection
+    fn prepare(test_name: &str) -> (settings::Storage, Uuid) {
+        let synced = storage_settings(test_name, "synced");
+        let team = open_unlocked(&synced)
+            .create_collection("team", "", &HashMap::new())
+            .unwrap();
+        (synced, team)
+    }
+
+    fn label(storage: &Storage, uuid: &Uuid) -> String {
+        storage
+            .with_collection(uuid, |c| Ok(c.label().to_string()))
+            .unwrap()
+    }
+
+    fn set_label(storage: &mut Storage, uuid: &Uuid, label: &str) -> Result<(), TksError> {
+        storage.modify_collection(uuid, |c| {
+            c.label = Some(label.to_string());
+            Ok(())
+        })
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) {
+        let session = plain_session();
+        let attributes = HashMap::from([("label".to_string(), label.to_string())]);
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    attributes,
+                    (
+                        &session,
+                        vec![],
+                        label.as_bytes().to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap();
+    }
+
+    /// Labels and secrets of the items of the collection, sorted by label
+    fn contents(storage: &Storage, uuid: &Uuid) -> Vec<(String, Vec<u8>)> {
+        let session = plain_session();
+        let mut contents: Vec<_> = storage
+            .with_collection(uuid, |c| {
+                Ok(c.items
+                    .iter()
+                    .map(|i| {
+                        let secret = i.get_secret(&session, SENDER.to_string()).unwrap().2;
+                        (i.label.clone(), secret)
+                    })
+                    .collect())
+            })
+            .unwrap();
+        contents.sort();
+        contents
+    }
+
+    /// The file of the collection in the synced backend
+    fn collection_path(synced: &settings::Storage, uuid: &Uuid) -> PathBuf {
+        let metadata = PathBuf::from(synced.path.as_ref().unwrap()).join("metadata");
+        fs::read_dir(metadata)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                fs::read_to_string(path)
+                    .unwrap()
+                    .contains(&uuid.to_string())
+            })
+            .unwrap()
+    }
+
+    fn versions(entries: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        entries.iter().map(|(m, c)| (m.to_string(), *c)).collect()
+    }
+
+    /// Turns the collection file into a conflict copy written by the `laptop` machine, putting
+    /// the given content back as the collection file
+    fn make_conflict_copy(path: &Path, previous: &str) -> PathBuf {
+        let mut metadata: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        metadata["versions"] = serde_json::json!({ "laptop": 1 });
+        let mut copy = path.as_os_str().to_owned();
+        copy.push(".sync-conflict-20261016-120000-LAPTOP");
+        fs::write(&copy, metadata.to_string()).unwrap();
+        fs::write(path, previous).unwrap();
+        copy.into()
+    }
+
+    #[test]
+    fn versions_get_compared() {
+        let ours = versions(&[("desktop", 2), ("laptop", 1)]);
+        assert_eq!(sync::compare(&ours, &ours), Causality::Equal);
+        assert_eq!(
+            sync::compare(&ours, &versions(&[("desktop", 2)])),
+            Causality::After
+        );
+        let theirs = versions(&[("desktop", 2), ("laptop", 2)]);
+        assert_eq!(sync::compare(&ours, &theirs), Causality::Before);
+        let theirs = versions(&[("desktop", 1), ("laptop", 2)]);
+        assert_eq!(sync::compare(&ours, &theirs), Causality::Concurrent);
+        assert_eq!(
+            sync::merge_versions(&ours, &theirs),
+            versions(&[("desktop", 2), ("laptop", 2)])
+        );
+    }
+
+    #[test]
+    fn conflict_copies_are_recognized() {
+        let original = |name: &str| sync::conflicted_original(Path::new(name));
+        assert_eq!(
+            original("/sync/metadata/team.sync-conflict-20261016-120000-ABCDEFG").as_deref(),
+            Some("team")
+        );
+        assert_eq!(
+            original("/sync/metadata/team (conflicted copy 2026-10-16)").as_deref(),
+            Some("team")
+        );
+        assert_eq!(
+            original("/sync/metadata/team (laptop's conflicted copy 2026-10-16)").as_deref(),
+            Some("team")
+        );
+        assert_eq!(original("/sync/metadata/team"), None);
+        assert_eq!(original("/sync/metadata/team (2)"), None);
+    }
+
+    #[tokio::test]
+    async fn newer_versions_get_loaded() {
+        let (synced, team) = prepare("newer");
+        let mut laptop = machine("newer", "laptop", &synced);
+        let mut desktop = machine("newer", "desktop", &synced);
+
+        set_label(&mut laptop, &team, "Team").unwrap();
+        let report = desktop.check_sync().unwrap();
+        assert_eq!(report.updated.len(), 1);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(label(&desktop, &team), "Team");
+
+        // saving over a version not loaded yet would lose it
+        set_label(&mut laptop, &team, "Team 2").unwrap();
+        let result = set_label(&mut desktop, &team, "Ops");
+        assert!(matches!(result, Err(TksError::SyncConflict(_))));
+        desktop.check_sync().unwrap();
+        assert_eq!(label(&desktop, &team), "Team 2");
+        set_label(&mut desktop, &team, "Ops").unwrap();
+        laptop.check_sync().unwrap();
+        assert_eq!(label(&laptop, &team), "Ops");
+    }
+
+    #[tokio::test]
+    async fn concurrent_versions_get_merged() {
+        let (synced, team) = prepare("merge");
+        let mut desktop = machine("merge", "desktop", &synced);
+        let path = collection_path(&synced, &team);
+        let previous = fs::read_to_string(&path).unwrap();
+        let mut laptop = machine("merge", "laptop", &synced);
+        add_item(&mut laptop, &team, "VPN");
+        let copy = make_conflict_copy(&path, &previous);
+
+        // the item file of the other version stays, its item being unknown yet
+        add_item(&mut desktop, &team, "Mail");
+        let report = desktop.check_sync().unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].path, copy);
+        desktop
+            .resolve_sync_conflict(&report.conflicts[0], true)
+            .unwrap();
+
+        let merged = vec![
+            ("Mail".to_string(), b"Mail".to_vec()),
+            ("VPN".to_string(), b"VPN".to_vec()),
+        ];
+        assert_eq!(contents(&desktop, &team), merged);
+        assert!(!copy.exists());
+        let root = PathBuf::from(synced.path.as_ref().unwrap());
+        assert_eq!(
+            fs::read_dir(root.join(sync::CONFLICTS_DIR))
+                .unwrap()
+                .count(),
+            1
+        );
+        assert!(desktop.check_sync().unwrap().conflicts.is_empty());
+        drop(desktop);
+
+        let desktop = machine("merge", "desktop-again", &synced);
+        assert_eq!(contents(&desktop, &team), merged);
+        let merged_versions = desktop
+            .with_collection(&team, |c| Ok(c.versions.clone()))
+            .unwrap();
+        assert_eq!(merged_versions.get("laptop"), Some(&1));
+    }
+}
@@ -0,0 +1,63 @@
+mod common;
+mod harness;
+
+// These tests show the dummy dialogs of `tks-cli service test-prompt`, the scripted prompter
+// answering them, see the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::blocking::Connection;
+    use std::env;
+    use tks_service::tks_dbus::prompter::{self, ScriptedAnswer};
+
+    fn test_prompt(conn: &Connection, kind: &str) -> Result<bool, dbus::Error> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+        .method_call("io.linux_tks.Service1", "TestPrompt", (kind,))
+        .map(|(dismissed,)| dismissed)
+    }
+
+    // a single test, as it revokes the test binary, which the other tests would need
+    #[test]
+    fn only_enrolled_clients_get_test_prompts() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        prompter::script([
+            ScriptedAnswer::Passphrase("not used".to_string()),
+            ScriptedAnswer::Confirm(true),
+            ScriptedAnswer::Confirm(false),
+        ]);
+        assert!(!test_prompt(&conn, "message").unwrap());
+        assert!(!test_prompt(&conn, "passphrase").unwrap());
+        assert!(!test_prompt(&conn, "confirmation").unwrap());
+        assert!(test_prompt(&conn, "confirmation").unwrap());
+        assert!(prompter::remaining_answers().is_empty());
+        assert!(test_prompt(&conn, "unknown").is_err());
+
+        let exe = env::current_exe().unwrap();
+        let (revoked,): (bool,) = conn
+            .with_proxy(
+                harness::SERVICE_NAME,
+                harness::SERVICE_PATH,
+                harness::TIMEOUT,
+            )
+            .method_call(
+                "io.linux_tks.Service1",
+                "RevokeClient",
+                (exe.to_string_lossy().as_ref(),),
+            )
+            .unwrap();
+        assert!(revoked);
+        prompter::script([ScriptedAnswer::Passphrase("not used".to_string())]);
+        assert!(test_prompt(&conn, "passphrase").is_err());
+        assert_eq!(prompter::remaining_answers().len(), 1);
+
+        harness::enroll();
+        prompter::script([ScriptedAnswer::Passphrase("not used".to_string())]);
+        assert!(!test_prompt(&conn, "passphrase").unwrap());
+    }
+}
@@ -0,0 +1,124 @@
+mod common;
+
+// These tests move the aliases and the default flag between the collections of a storage opened in
+// a temporary directory, then reopen it to check what got saved, also when saving one of the
+// collections fails. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked};
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::storage::Storage;
+    use tks_service::tks_error::TksError;
+    use uuid::Uuid;
+
+    fn default_collection(storage: &Storage) -> Uuid {
+        Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap()
+    }
+
+    fn is_default(storage: &Storage, uuid: &Uuid) -> bool {
+        storage.with_collection(uuid, |c| Ok(c.default)).unwrap()
+    }
+
+    fn aliases(storage: &Storage, uuid: &Uuid) -> Vec<String> {
+        storage
+            .with_collection(uuid, |c| Ok(c.aliases.clone().unwrap_or_default()))
+            .unwrap()
+    }
+
+    fn sequence(storage: &Storage, uuid: &Uuid) -> u64 {
+        storage.with_collection(uuid, |c| Ok(c.sequence)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn moving_the_default_alias_moves_the_flag() {
+        let settings = common::storage_settings("transaction", "default");
+        let mut storage = open_unlocked(&settings);
+        let previous = default_collection(&storage);
+        let work = storage
+            .create_collection("work", "", &HashMap::new())
+            .unwrap();
+
+        let mut changed = storage.set_alias("default", Some(work)).unwrap();
+        changed.sort();
+        let mut expected = vec![previous, work];
+        expected.sort();
+        assert_eq!(changed, expected);
+        drop(storage);
+
+        let storage = open_unlocked(&settings);
+        assert_eq!(default_collection(&storage), work);
+        assert!(is_default(&storage, &work));
+        assert!(!is_default(&storage, &previous));
+        assert!(!aliases(&storage, &previous).contains(&"default".to_string()));
+    }
+
+    #[tokio::test]
+    async fn aliases_move_between_collections() {
+        let settings = common::storage_settings("transaction", "aliases");
+        let mut storage = open_unlocked(&settings);
+        let first = storage
+            .create_collection("first", "", &HashMap::new())
+            .unwrap();
+        let second = storage
+            .create_collection("second", "", &HashMap::new())
+            .unwrap();
+        storage.set_alias("shared", Some(first)).unwrap();
+
+        let changed = storage.set_alias("shared", Some(second)).unwrap();
+        assert_eq!(changed.len(), 2);
+        drop(storage);
+
+        let mut storage = open_unlocked(&settings);
+        assert_eq!(storage.read_alias("shared").unwrap(), second.to_string());
+        assert!(aliases(&storage, &first).is_empty());
+
+        // removing the alias only changes the collection having it
+        assert_eq!(storage.set_alias("shared", None).unwrap(), vec![second]);
+        assert!(storage.read_alias("shared").is_err());
+        assert!(storage.set_alias("default", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_saves_roll_back_every_collection() {
+        let shared = common::storage_settings("transaction", "rollback-shared");
+        let team = open_unlocked(&shared)
+            .create_collection("team", "", &HashMap::new())
+            .unwrap();
+        // the collections of the personal backend, there before the mounted ones, get saved first
+        let personal = common::storage_settings("transaction", "rollback");
+        drop(open_unlocked(&personal));
+        let settings = settings::Storage {
+            mounts: vec![settings::StorageMount {
+                name: "shared".to_string(),
+                kind: shared.kind.clone(),
+                path: shared.path.clone(),
+                read_only: true,
+                keyfiles: HashMap::new(),
+                per_item_files: false,
+                pad_item_files: false,
+                sync: false,
+            }],
+            ..personal
+        };
+        let mut storage = open_unlocked(&settings);
+        let previous = default_collection(&storage);
+        let saved = sequence(&storage, &previous);
+
+        let result = storage.set_alias("default", Some(team));
+        assert!(matches!(result, Err(TksError::NotSupported(_))));
+        // saved twice, with the change then without it
+        assert_eq!(sequence(&storage, &previous), saved + 1);
+        assert_eq!(default_collection(&storage), previous);
+        assert!(is_default(&storage, &previous));
+        assert!(!is_default(&storage, &team));
+        drop(storage);
+
+        // the default collection got saved again as it was
+        let storage = open_unlocked(&settings);
+        assert_eq!(default_collection(&storage), previous);
+        assert!(is_default(&storage, &previous));
+        assert!(aliases(&storage, &team).is_empty());
+    }
+}
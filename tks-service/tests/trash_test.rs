@@ -0,0 +1,167 @@
+mod common;
+
+// These tests delete items of collections saved in a temporary directory, then restore or purge
+// them from the trash. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::storage::history::HistoryOperation;
+    use tks_service::storage::trash::{DEFAULT_RETENTION_DAYS, TRASH_RETENTION_PROPERTY};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    const DAY: u64 = 86400;
+
+    fn storage_settings(test_name: &str, per_item_files: bool) -> settings::Storage {
+        settings::Storage {
+            per_item_files,
+            ..common::storage_settings("trash", test_name)
+        }
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, label: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    label,
+                    HashMap::from([("service".to_string(), label.to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        label.as_bytes().to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn trash_item(storage: &mut Storage, collection: &Uuid, item: &Uuid) {
+        storage
+            .modify_collection(collection, |c| c.trash_item(item))
+            .unwrap();
+    }
+
+    fn trashed(storage: &Storage, collection: &Uuid) -> Vec<(Uuid, u64)> {
+        storage
+            .with_collection(collection, |c| {
+                Ok(c.trash
+                    .iter()
+                    .map(|t| (t.item.id.uuid, t.deleted))
+                    .collect())
+            })
+            .unwrap()
+    }
+
+    fn secret(storage: &Storage, collection: &Uuid, item: &Uuid) -> Vec<u8> {
+        let session = plain_session();
+        storage
+            .with_item(collection, item, |i| i.get_secret(&session, SENDER.into()))
+            .unwrap()
+            .2
+    }
+
+    #[tokio::test]
+    async fn deleted_items_can_be_restored() {
+        for per_item_files in [false, true] {
+            let settings = storage_settings(&format!("restore-{}", per_item_files), per_item_files);
+            let mut storage = open_unlocked(&settings);
+            let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+            let mail = add_item(&mut storage, &default, "mail");
+            let vpn = add_item(&mut storage, &default, "vpn");
+            trash_item(&mut storage, &default, &mail);
+            let vpn_id = storage
+                .with_item(&default, &vpn, |i| Ok(i.id.clone()))
+                .unwrap();
+            storage.delete_items(&[vpn_id]).unwrap();
+            assert!(storage.with_item(&default, &mail, |_| Ok(())).is_err());
+            assert_eq!(trashed(&storage, &default).len(), 2);
+            drop(storage);
+
+            // the trashed items keep their secret, also while locked
+            let mut storage = Storage::open(settings.clone()).unwrap();
+            assert_eq!(trashed(&storage, &default).len(), 2);
+            storage
+                .unlock_with_password(SecretString::new(PASSWORD.into()))
+                .unwrap();
+            storage
+                .modify_collection(&default, |c| c.restore_item(&mail))
+                .unwrap();
+            assert_eq!(secret(&storage, &default, &mail), b"mail".to_vec());
+            assert_eq!(trashed(&storage, &default).len(), 1);
+            let restored = storage
+                .with_collection(&default, |c| Ok(c.history.back().unwrap().operation))
+                .unwrap();
+            assert_eq!(restored, HistoryOperation::Restored);
+            assert!(storage
+                .modify_collection(&default, |c| c.restore_item(&mail))
+                .is_err());
+            drop(storage);
+
+            let storage = open_unlocked(&settings);
+            assert_eq!(secret(&storage, &default, &mail), b"mail".to_vec());
+            assert_eq!(trashed(&storage, &default).len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn trashed_items_get_purged() {
+        let settings = storage_settings("purge", false);
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let properties = HashMap::from([(TRASH_RETENTION_PROPERTY.to_string(), "1".to_string())]);
+        let short = storage.create_collection("short", "", &properties).unwrap();
+        let properties = HashMap::from([(TRASH_RETENTION_PROPERTY.to_string(), "0".to_string())]);
+        let none = storage.create_collection("none", "", &properties).unwrap();
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+
+        let gone = add_item(&mut storage, &none, "gone");
+        trash_item(&mut storage, &none, &gone);
+        assert!(trashed(&storage, &none).is_empty());
+
+        let old = add_item(&mut storage, &short, "old");
+        trash_item(&mut storage, &short, &old);
+        let kept = add_item(&mut storage, &default, "kept");
+        trash_item(&mut storage, &default, &kept);
+        let deleted = trashed(&storage, &default)[0].1;
+        assert_eq!(storage.purge_expired_trash(deleted + DAY - 1), 0);
+        assert_eq!(storage.purge_expired_trash(deleted + DAY), 1);
+        assert!(trashed(&storage, &short).is_empty());
+        assert_eq!(trashed(&storage, &default).len(), 1);
+        assert_eq!(
+            storage.purge_expired_trash(deleted + DEFAULT_RETENTION_DAYS * DAY),
+            1
+        );
+
+        let first = add_item(&mut storage, &default, "first");
+        let second = add_item(&mut storage, &default, "second");
+        trash_item(&mut storage, &default, &first);
+        trash_item(&mut storage, &default, &second);
+        let unknown = Uuid::new_v4();
+        let purged = storage.modify_collection(&default, |c| c.purge_trash(&[first, unknown]));
+        assert!(purged.is_err());
+        assert_eq!(trashed(&storage, &default).len(), 2);
+        let purged = storage.modify_collection(&default, |c| c.purge_trash(&[first]));
+        assert_eq!(purged.unwrap(), 1);
+        assert_eq!(
+            storage
+                .modify_collection(&default, |c| c.purge_trash(&[]))
+                .unwrap(),
+            1
+        );
+        drop(storage);
+
+        let storage = open_unlocked(&settings);
+        assert!(trashed(&storage, &default).is_empty());
+    }
+}
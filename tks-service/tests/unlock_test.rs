@@ -0,0 +1,208 @@
+mod common;
+mod harness;
+
+// These tests unlock the collections through their prompts, the dialogs getting answered by the
+// scripted prompter, see the harness module; they only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common;
+    use crate::harness;
+    use dbus::arg::{RefArg, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+    use std::sync::{mpsc, Mutex, MutexGuard};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tks_service::tks_dbus::prompter::{self, ScriptedAnswer};
+
+    /// Unlocking a collection unlocks the others sharing its password, so the tests take turns
+    static UNLOCKING: Mutex<()> = Mutex::new(());
+
+    fn take_turn() -> MutexGuard<'static, ()> {
+        UNLOCKING.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn password(passphrase: &str) -> ScriptedAnswer {
+        ScriptedAnswer::Passphrase(passphrase.to_string())
+    }
+
+    /// Locks the collection, returning the prompt which unlocks it
+    fn unlock_prompt(conn: &Connection, collection: &dbus::Path<'static>) -> dbus::Path<'static> {
+        let service = conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        );
+        let objects = vec![collection.clone()];
+        let (_, _): (Vec<dbus::Path>, dbus::Path) = service
+            .method_call("org.freedesktop.Secret.Service", "Lock", (objects.clone(),))
+            .unwrap();
+        assert!(locked(conn, collection));
+        let (unlocked, prompt): (Vec<dbus::Path<'static>>, dbus::Path<'static>) = service
+            .method_call("org.freedesktop.Secret.Service", "Unlock", (objects,))
+            .unwrap();
+        assert!(unlocked.is_empty());
+        assert_ne!(&*prompt, "/");
+        prompt
+    }
+
+    fn locked(conn: &Connection, collection: &dbus::Path) -> bool {
+        conn.with_proxy(harness::SERVICE_NAME, collection, harness::TIMEOUT)
+            .get("org.freedesktop.Secret.Collection", "Locked")
+            .unwrap()
+    }
+
+    #[test]
+    fn prompts_unlock_with_the_password() {
+        let _turn = take_turn();
+        let collection = harness::unlocked_collection("unlocked by its prompt");
+        let conn = Connection::new_session().unwrap();
+        let prompt = unlock_prompt(&conn, &collection);
+        prompter::script([password(common::PASSWORD)]);
+        assert!(!harness::prompt(&prompt));
+        assert!(!locked(&conn, &collection));
+        assert!(prompter::remaining_answers().is_empty());
+    }
+
+    #[test]
+    fn mistyped_passwords_keep_the_collection_locked() {
+        let _turn = take_turn();
+        let collection = harness::unlocked_collection("mistyped");
+        let conn = Connection::new_session().unwrap();
+        let prompt = unlock_prompt(&conn, &collection);
+        prompter::script([password("mistyped")]);
+        assert!(harness::prompt(&prompt));
+        assert!(locked(&conn, &collection));
+
+        // the next attempt goes through
+        let prompt = unlock_prompt(&conn, &collection);
+        prompter::script([password(common::PASSWORD)]);
+        assert!(!harness::prompt(&prompt));
+        assert!(!locked(&conn, &collection));
+    }
+
+    #[test]
+    fn dismissed_prompts_keep_the_collection_locked() {
+        let _turn = take_turn();
+        let collection = harness::unlocked_collection("dismissed");
+        let conn = Connection::new_session().unwrap();
+        let prompt = unlock_prompt(&conn, &collection);
+        prompter::script([ScriptedAnswer::Cancel]);
+        assert!(harness::prompt(&prompt));
+        assert!(locked(&conn, &collection));
+
+        // nor does running out of answers unlock it
+        let prompt = unlock_prompt(&conn, &collection);
+        prompter::script([]);
+        assert!(harness::prompt(&prompt));
+        assert!(locked(&conn, &collection));
+        harness::unlock_all();
+    }
+
+    #[test]
+    fn running_prompts_get_dismissed() {
+        let _turn = take_turn();
+        let collection = harness::unlocked_collection("dismissed while running");
+        let conn = Connection::new_session().unwrap();
+        let prompt = unlock_prompt(&conn, &collection);
+        let (completed, dismissed) = mpsc::channel();
+        let rule = MatchRule::new_signal("org.freedesktop.Secret.Prompt", "Completed")
+            .with_path(prompt.clone());
+        conn.add_match(
+            rule,
+            move |(dismissed, _): (bool, Variant<Box<dyn RefArg>>), _, _| {
+                let _ = completed.send(dismissed);
+                false
+            },
+        )
+        .unwrap();
+
+        // the dialog stays open until the prompt gets dismissed
+        prompter::script([ScriptedAnswer::Wait]);
+        harness::start_prompt(&conn, &prompt);
+        let deadline = Instant::now() + harness::TIMEOUT;
+        while !prompter::remaining_answers().is_empty() {
+            assert!(Instant::now() < deadline, "the dialog should show up");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let proxy = conn.with_proxy(harness::SERVICE_NAME, &prompt, harness::TIMEOUT);
+        let () = proxy
+            .method_call("org.freedesktop.Secret.Prompt", "Dismiss", ())
+            .expect("the running prompt should be found");
+        loop {
+            conn.process(Duration::from_millis(10)).unwrap();
+            if let Ok(dismissed) = dismissed.try_recv() {
+                assert!(dismissed);
+                break;
+            }
+            assert!(Instant::now() < deadline, "the prompt should complete");
+        }
+        assert!(locked(&conn, &collection));
+        harness::unlock_all();
+    }
+
+    #[test]
+    fn chained_prompts_report_their_progress() {
+        let _turn = take_turn();
+        let collections = vec![
+            harness::unlocked_collection("first of the chain"),
+            harness::unlocked_collection("second of the chain"),
+        ];
+        let conn = Connection::new_session().unwrap();
+        let service = conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        );
+        let (_, _): (Vec<dbus::Path>, dbus::Path) = service
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "Lock",
+                (collections.clone(),),
+            )
+            .unwrap();
+        let (_, chain): (Vec<dbus::Path>, dbus::Path<'static>) = service
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "Unlock",
+                (collections.clone(),),
+            )
+            .unwrap();
+        assert_ne!(&*chain, "/");
+        let rule = MatchRule::new_signal("io.linux_tks.Prompt1", "PromptProgress")
+            .with_path(chain.clone());
+        let progress = harness::watch(&conn, rule);
+
+        prompter::script([password(common::PASSWORD), password(common::PASSWORD)]);
+        assert!(!harness::prompt(&chain));
+        for current in 1..=2 {
+            let (reported, total): (u32, u32) =
+                harness::next_signal(&conn, &progress).read2().unwrap();
+            assert_eq!((reported, total), (current, 2));
+        }
+        assert!(collections.iter().all(|c| !locked(&conn, c)));
+        prompter::script([]);
+    }
+
+    #[test]
+    fn scripted_answers_get_parsed() {
+        let answers: Vec<ScriptedAnswer> =
+            ["passphrase:a:b", "cancel", "confirm", "decline", "wait"]
+                .iter()
+                .map(|a| a.parse().unwrap())
+                .collect();
+        assert_eq!(
+            answers,
+            vec![
+                password("a:b"),
+                ScriptedAnswer::Cancel,
+                ScriptedAnswer::Confirm(true),
+                ScriptedAnswer::Confirm(false),
+                ScriptedAnswer::Wait
+            ]
+        );
+        assert!("yes".parse::<ScriptedAnswer>().is_err());
+    }
+}
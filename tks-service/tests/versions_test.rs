@@ -0,0 +1,141 @@
+mod common;
+
+// These tests overwrite the secrets of collections saved in a temporary directory, then check the
+// previous ones can be got back. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, plain_session, PASSWORD, SENDER};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use tks_service::storage::versions::{DEFAULT_DEPTH, HISTORY_DEPTH_PROPERTY};
+    use tks_service::storage::Storage;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, per_item_files: bool) -> settings::Storage {
+        settings::Storage {
+            per_item_files,
+            ..common::storage_settings("versions", test_name)
+        }
+    }
+
+    fn add_item(storage: &mut Storage, collection: &Uuid, secret: &str) -> Uuid {
+        let session = plain_session();
+        storage
+            .modify_collection(collection, |c| {
+                c.create_item(
+                    "mail",
+                    HashMap::from([("service".to_string(), "imap".to_string())]),
+                    (
+                        &session,
+                        vec![],
+                        secret.as_bytes().to_vec(),
+                        "text/plain".to_string(),
+                    ),
+                    false,
+                    SENDER.to_string(),
+                )
+            })
+            .unwrap()
+            .uuid
+    }
+
+    fn set_secret(storage: &mut Storage, collection: &Uuid, item: &Uuid, secret: &str) {
+        let session = plain_session();
+        storage
+            .modify_item(collection, item, |i| {
+                let secret = secret.as_bytes().to_vec();
+                i.set_secret(
+                    &session,
+                    vec![],
+                    &secret,
+                    "text/plain".to_string(),
+                    SENDER.into(),
+                )
+            })
+            .unwrap();
+    }
+
+    fn secret(storage: &Storage, collection: &Uuid, item: &Uuid) -> String {
+        let session = plain_session();
+        let secret = storage
+            .with_item(collection, item, |i| i.get_secret(&session, SENDER.into()))
+            .unwrap();
+        String::from_utf8(secret.2).unwrap()
+    }
+
+    fn versions(storage: &Storage, collection: &Uuid, item: &Uuid) -> usize {
+        storage
+            .with_item(collection, item, |i| {
+                Ok(i.data.as_ref().unwrap().versions().len())
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn previous_secrets_can_be_got_back() {
+        let settings = storage_settings("rollback", true);
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let item = add_item(&mut storage, &default, "first");
+        assert_eq!(versions(&storage, &default, &item), 0);
+        set_secret(&mut storage, &default, &item, "second");
+        set_secret(&mut storage, &default, &item, "second");
+        set_secret(&mut storage, &default, &item, "third");
+        assert_eq!(versions(&storage, &default, &item), 2);
+        drop(storage);
+
+        // the versions get saved along with the secret
+        let mut storage = open_unlocked(&settings);
+        assert_eq!(versions(&storage, &default, &item), 2);
+        storage
+            .modify_item(&default, &item, |i| i.rollback(1))
+            .unwrap();
+        assert_eq!(secret(&storage, &default, &item), "first");
+        assert_eq!(versions(&storage, &default, &item), 2);
+        storage
+            .modify_item(&default, &item, |i| i.rollback(0))
+            .unwrap();
+        assert_eq!(secret(&storage, &default, &item), "third");
+        assert!(storage
+            .modify_item(&default, &item, |i| i.rollback(2))
+            .is_err());
+        assert_eq!(secret(&storage, &default, &item), "third");
+
+        storage.lock_collection(&default).unwrap();
+        assert!(storage
+            .modify_item(&default, &item, |i| i.rollback(0))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn collections_choose_how_many_versions_are_kept() {
+        let settings = storage_settings("depth", false);
+        let mut storage = open_unlocked(&settings);
+        let default = Uuid::parse_str(&storage.read_alias("default").unwrap()).unwrap();
+        let properties = HashMap::from([(HISTORY_DEPTH_PROPERTY.to_string(), "2".to_string())]);
+        let short = storage.create_collection("short", "", &properties).unwrap();
+        let properties = HashMap::from([(HISTORY_DEPTH_PROPERTY.to_string(), "0".to_string())]);
+        let none = storage.create_collection("none", "", &properties).unwrap();
+        storage
+            .unlock_with_password(SecretString::new(PASSWORD.into()))
+            .unwrap();
+
+        let last = DEFAULT_DEPTH + 2;
+        for (collection, depth) in [(default, DEFAULT_DEPTH), (short, 2), (none, 0)] {
+            let item = add_item(&mut storage, &collection, "0");
+            for n in 1..=last {
+                set_secret(&mut storage, &collection, &item, &n.to_string());
+            }
+            assert_eq!(versions(&storage, &collection, &item), depth);
+            let rolled_back = storage.modify_item(&collection, &item, |i| i.rollback(0));
+            let expected = match depth {
+                0 => last,
+                _ => last - 1,
+            };
+            assert_eq!(rolled_back.is_ok(), depth > 0);
+            assert_eq!(secret(&storage, &collection, &item), expected.to_string());
+        }
+    }
+}
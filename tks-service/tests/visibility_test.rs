@@ -0,0 +1,137 @@
+mod common;
+mod harness;
+
+// These tests hide a collection from the clients the user didn't let in, then list the collections
+// and search the items as the test binary, revoked then let in again, see the harness module; they
+// only need dbus-daemon to be installed.
+//
+#[cfg(test)]
+mod tests {
+    use crate::harness;
+    use dbus::arg::{PropMap, Variant};
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::{Connection, Proxy};
+    use std::collections::HashMap;
+    use std::env;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn service_proxy(conn: &Connection) -> Proxy<'_, &Connection> {
+        conn.with_proxy(
+            harness::SERVICE_NAME,
+            harness::SERVICE_PATH,
+            harness::TIMEOUT,
+        )
+    }
+
+    fn collections(conn: &Connection) -> Vec<dbus::Path<'static>> {
+        service_proxy(conn)
+            .get("org.freedesktop.Secret.Service", "Collections")
+            .unwrap()
+    }
+
+    /// The unlocked items found having the `service` attribute
+    fn search(conn: &Connection, service: &str) -> Vec<dbus::Path<'static>> {
+        let attributes = HashMap::from([("service".to_string(), service.to_string())]);
+        let (unlocked, _): (Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>) =
+            service_proxy(conn)
+                .method_call(
+                    "org.freedesktop.Secret.Service",
+                    "SearchItems",
+                    (attributes,),
+                )
+                .unwrap();
+        unlocked
+    }
+
+    /// Creates a collection only listed to the enrolled clients
+    fn hidden_collection(conn: &Connection) -> dbus::Path<'static> {
+        let mut properties = PropMap::new();
+        properties.insert(
+            "org.freedesktop.Secret.Collection.Label".to_string(),
+            Variant(Box::new("banking".to_string())),
+        );
+        properties.insert(
+            "tks:visibility".to_string(),
+            Variant(Box::new("enrolled".to_string())),
+        );
+        let (collection, _): (dbus::Path<'static>, dbus::Path) = service_proxy(conn)
+            .method_call(
+                "org.freedesktop.Secret.Service",
+                "CreateCollection",
+                (properties, ""),
+            )
+            .unwrap();
+        // the object gets registered in the background
+        let deadline = Instant::now() + harness::TIMEOUT;
+        let proxy = conn.with_proxy(harness::SERVICE_NAME, &collection, harness::TIMEOUT);
+        while proxy
+            .get::<bool>("org.freedesktop.Secret.Collection", "Locked")
+            .is_err()
+        {
+            assert!(
+                Instant::now() < deadline,
+                "{} should get registered",
+                collection
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        harness::unlock_all();
+        collection
+    }
+
+    fn revoke(conn: &Connection) {
+        let exe = env::current_exe().unwrap();
+        let (revoked,): (bool,) = service_proxy(conn)
+            .method_call(
+                "io.linux_tks.Service1",
+                "RevokeClient",
+                (exe.to_string_lossy().as_ref(),),
+            )
+            .unwrap();
+        assert!(revoked);
+    }
+
+    // a single test, as it revokes the test binary, which the other tests would need
+    #[test]
+    fn hidden_collections_only_get_listed_to_enrolled_clients() {
+        harness::start();
+        let conn = Connection::new_session().unwrap();
+        let visible = harness::unlocked_collection("visible");
+        let hidden = hidden_collection(&conn);
+        let visible_item = harness::created_item(&visible, "visibility", b"shown");
+        let hidden_item = harness::created_item(&hidden, "visibility", b"hidden");
+        assert!(collections(&conn).contains(&hidden));
+        let found = search(&conn, "visibility");
+        assert!(found.contains(&visible_item) && found.contains(&hidden_item));
+
+        revoke(&conn);
+        let listed = collections(&conn);
+        assert!(listed.contains(&visible));
+        assert!(!listed.contains(&hidden));
+        assert_eq!(search(&conn, "visibility"), vec![visible_item.clone()]);
+        // the hidden collection stays reachable through its path
+        let label: String = conn
+            .with_proxy(harness::SERVICE_NAME, &hidden, harness::TIMEOUT)
+            .get("org.freedesktop.Secret.Collection", "Label")
+            .unwrap();
+        assert_eq!(label, "banking");
+
+        // nor does it get hidden from its owner
+        let () = conn
+            .with_proxy(harness::SERVICE_NAME, &hidden, harness::TIMEOUT)
+            .method_call("io.linux_tks.Collection1", "SetOwner", (false,))
+            .unwrap();
+        assert!(collections(&conn).contains(&hidden));
+        assert_eq!(search(&conn, "visibility").len(), 2);
+        let () = conn
+            .with_proxy(harness::SERVICE_NAME, &hidden, harness::TIMEOUT)
+            .method_call("io.linux_tks.Collection1", "ReleaseOwner", ())
+            .unwrap();
+        assert!(!collections(&conn).contains(&hidden));
+
+        harness::enroll();
+        assert!(collections(&conn).contains(&hidden));
+        assert_eq!(search(&conn, "visibility").len(), 2);
+    }
+}
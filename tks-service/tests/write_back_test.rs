@@ -0,0 +1,66 @@
+mod common;
+
+// These tests delay the collection writes of a storage opened in a temporary directory, then
+// reopen it to check what got saved. They don't need a DBus session bus.
+//
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, open_unlocked, PASSWORD};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use tks_service::settings;
+    use uuid::Uuid;
+
+    fn storage_settings(test_name: &str, flush_delay: u64) -> settings::Storage {
+        settings::Storage {
+            flush_delay,
+            ..common::storage_settings("write-back", test_name)
+        }
+    }
+
+    fn saved_label(settings: &settings::Storage, uuid: &Uuid) -> String {
+        let storage = open_unlocked(settings);
+        storage.with_collection(uuid, |c| Ok(c.label().to_string())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn changes_wait_for_flush() {
+        let settings = storage_settings("flush", 60_000);
+        let mut storage = open_unlocked(&settings);
+        let uuid = storage
+            .create_collection("delayed", "", &HashMap::new())
+            .unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+
+        for i in 0..10 {
+            storage
+                .modify_collection(&uuid, |c| {
+                    c.label = Some(format!("label {}", i));
+                    Ok(())
+                })
+                .unwrap();
+        }
+        assert_eq!(saved_label(&settings, &uuid), "delayed");
+
+        storage.flush().unwrap();
+        assert_eq!(saved_label(&settings, &uuid), "label 9");
+    }
+
+    #[tokio::test]
+    async fn no_delay_writes_right_away() {
+        let settings = storage_settings("no-delay", 0);
+        let mut storage = open_unlocked(&settings);
+        let uuid = storage
+            .create_collection("immediate", "", &HashMap::new())
+            .unwrap();
+        storage.unlock_with_password(SecretString::new(PASSWORD.into())).unwrap();
+
+        storage
+            .modify_collection(&uuid, |c| {
+                c.label = Some("changed".to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(saved_label(&settings, &uuid), "changed");
+    }
+}